@@ -3,6 +3,26 @@ use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+#[path = "src/deflate.rs"]
+#[allow(dead_code)]
+mod deflate;
+
+// Static assets served by the firmware, pre-compressed into OUT_DIR/<name>.gz
+const STATIC_ASSETS: &[&str] = &["style.css", "live.js", "logview.html"];
+
+// Dependencies reported by /api/version: (package, env var)
+const VERSIONED_DEPS: &[(&str, &str)] = &[
+    ("embassy-rp", "VERSION_EMBASSY_RP"),
+    ("embassy-net", "VERSION_EMBASSY_NET"),
+    ("cyw43", "VERSION_CYW43"),
+];
+
+// Blobs handed to the WiFi chip: (file, env var prefix)
+const CYW43_BLOBS: &[(&str, &str)] = &[
+    ("cyw43-firmware/43439A0.bin", "CYW43_FIRMWARE"),
+    ("cyw43-firmware/43439A0_clm.bin", "CYW43_CLM"),
+];
+
 fn main() {
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
@@ -12,8 +32,91 @@ fn main() {
     println!("cargo:rustc-link-search={}", out.display());
 
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=src/deflate.rs");
+
+    for name in STATIC_ASSETS {
+        let path = PathBuf::from("static").join(name);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let raw = std::fs::read(&path).unwrap();
+        File::create(out.join(format!("{}.gz", name)))
+            .unwrap()
+            .write_all(&gzip(&raw))
+            .unwrap();
+    }
+
+    version_env();
+    blob_env();
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
+
+fn gzip(raw: &[u8]) -> Vec<u8> {
+    let mut deflater = Box::new(deflate::Deflater::new());
+    let mut out = deflate::GZIP_HEADER.to_vec();
+    for chunk in raw.chunks(deflate::WINDOW_SIZE) {
+        deflater.compress(chunk, |b| out.push(b));
+    }
+    deflater.finish(|b| out.push(b));
+    out
+}
+
+// Build identity for src/version.rs: git hash, profile, dependency versions
+fn version_env() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let git = std::process::Command::new("git")
+        .args(["rev-parse", "--short=7", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string());
+    let dirty = std::process::Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()
+        .is_ok_and(|o| o.status.success() && !o.stdout.is_empty());
+    let hash = match git {
+        Some(hash) if dirty => format!("{}-dirty", hash),
+        Some(hash) => hash,
+        None => "unknown".to_string(),
+    };
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+    println!("cargo:rustc-env=BUILD_PROFILE={}", env::var("PROFILE").unwrap_or_default());
+
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for (package, var) in VERSIONED_DEPS {
+        println!("cargo:rustc-env={}={}", var, locked_version(&lock, package));
+    }
+}
+
+// Length and CRC-32 of each blob as it was built in, for src/blob_check.rs
+fn blob_env() {
+    for (path, var) in CYW43_BLOBS {
+        println!("cargo:rerun-if-changed={}", path);
+        let raw = std::fs::read(path).unwrap();
+        println!("cargo:rustc-env={}_LEN={}", var, raw.len());
+        println!("cargo:rustc-env={}_CRC={:08x}", var, deflate::crc32_update(0, &raw));
+    }
+}
+
+// "0.8.0 (286d887)" for a git dependency, "0.8.0" from crates.io
+fn locked_version(lock: &str, package: &str) -> String {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines().skip_while(|line| *line != name).skip(1);
+    let Some(version) = lines.next().and_then(|line| line.strip_prefix("version = ")) else {
+        return "unknown".to_string();
+    };
+    let version = version.trim_matches('"');
+    let rev = lines
+        .next()
+        .and_then(|line| line.strip_prefix("source = \"git+"))
+        .and_then(|source| source.rsplit_once('#'))
+        .map(|(_, rev)| rev.trim_end_matches('"'));
+    match rev {
+        Some(rev) => format!("{} ({})", version, &rev[..rev.len().min(7)]),
+        None => version.to_string(),
+    }
+}
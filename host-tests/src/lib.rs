@@ -0,0 +1,87 @@
+// 固件模块的主机测试
+//
+// Each module below is the firmware's own source file, included by path.
+// Only modules that need nothing from the board (no embassy-rp, embassy-net
+// or defmt) can be listed here. They are private, as in the firmware's
+// binary crate, so lints see the same API; what only the firmware calls
+// is unused here.
+
+#![allow(dead_code)]
+
+#[path = "../../src/at.rs"]
+mod at;
+#[path = "../../src/at_help.rs"]
+mod at_help;
+#[path = "../../src/at_response.rs"]
+mod at_response;
+#[path = "../../src/at_rtt.rs"]
+mod at_rtt;
+#[path = "../../src/capture.rs"]
+mod capture;
+#[path = "../../tools/capture_decode.rs"]
+mod capture_decode;
+#[path = "../../src/conn_close.rs"]
+mod conn_close;
+#[path = "../../src/deflate.rs"]
+mod deflate;
+#[path = "../../src/dns_cache.rs"]
+mod dns_cache;
+#[path = "../../src/fetch.rs"]
+mod fetch;
+#[path = "../../src/fetch_target.rs"]
+mod fetch_target;
+#[cfg(feature = "gnss")]
+#[path = "../../src/geofence.rs"]
+mod geofence;
+#[path = "../../src/http.rs"]
+mod http;
+#[path = "../../src/json.rs"]
+mod json;
+#[path = "../../src/limits.rs"]
+mod limits;
+#[path = "../../src/lock_stats.rs"]
+mod lock_stats;
+#[path = "../../src/log_text.rs"]
+mod log_text;
+#[path = "../../src/modem.rs"]
+mod modem;
+#[path = "../../src/modem_log.rs"]
+mod modem_log;
+#[path = "../../src/modem_sim.rs"]
+mod modem_sim;
+#[path = "../../src/page_budget.rs"]
+mod page_budget;
+#[path = "../../src/page_cache.rs"]
+mod page_cache;
+#[path = "../../src/rate_limit.rs"]
+mod rate_limit;
+#[path = "../../src/registration.rs"]
+mod registration;
+#[path = "../../src/rings.rs"]
+mod rings;
+#[path = "../../src/routes.rs"]
+mod routes;
+#[path = "../../src/rx_audit.rs"]
+mod rx_audit;
+#[path = "../../src/schedule.rs"]
+mod schedule;
+#[cfg(feature = "proxy")]
+#[path = "../../src/shaper.rs"]
+mod shaper;
+#[path = "../../src/sim.rs"]
+mod sim;
+#[path = "../../src/sparkline.rs"]
+mod sparkline;
+#[path = "../../src/status_json.rs"]
+mod status_json;
+#[path = "../../src/template.rs"]
+mod template;
+#[path = "../../src/urc.rs"]
+mod urc;
+#[path = "../../src/utf8.rs"]
+mod utf8;
+
+// routes.rs 只用到版本号; version.rs 的其余部分要 build.rs 设置的环境变量
+mod version {
+    pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+}
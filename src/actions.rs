@@ -0,0 +1,158 @@
+// 动作分发: 每个来源的动作请求都走这里, 并留下记录
+//
+// Every action asked for by the web pages or a GPIO input becomes a
+// record. It holds the source, the action, a digest of the parameters,
+// the time and the HTTP request id when there is one. The source must be
+// allowed the action in config: actions.web and actions.input are sums of
+// action bits. An allowed action goes to the subsystem that carries it
+// out, which is the modem queue, the webhook queue or the LED. Its record
+// then follows it from pending through running to done or failed. A
+// refused action is recorded as failed and goes nowhere. The last
+// CAPACITY records are kept for /api/actions.
+
+pub const CAPACITY: usize = 50;
+// the reason given for an action the source may not run
+pub const NOT_ALLOWED: &str = "not allowed for this source";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Web,
+    // a GPIO input trigger (see trigger)
+    Input,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Web => "web",
+            Source::Input => "input",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Fetch,
+    Sms,
+    Webhook,
+    Identify,
+}
+
+pub const ALL_ACTIONS: u32 = 0b1111;
+
+impl Action {
+    pub const ALL: [Action; 4] = [Action::Fetch, Action::Sms, Action::Webhook, Action::Identify];
+
+    // config actions.<source> bit
+    pub const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Fetch => "fetch",
+            Action::Sms => "sms",
+            Action::Webhook => "webhook",
+            Action::Identify => "identify",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    // waiting in the owning subsystem's queue
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Pending => "pending",
+            Status::Running => "running",
+            Status::Done => "done",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Record {
+    pub id: u32,
+    pub at_ms: u64,
+    pub source: Source,
+    pub action: Action,
+    // FNV-1a over the parameters, so two records can be told apart
+    // without keeping phone numbers and texts
+    pub digest: u32,
+    pub request_id: Option<u32>,
+    pub status: Status,
+    // why it failed, or a note on how it ended
+    pub detail: heapless::String<48>,
+}
+
+pub struct Log {
+    // newest first
+    records: heapless::Deque<Record, CAPACITY>,
+    next_id: u32,
+}
+
+impl Log {
+    pub const fn new() -> Self {
+        Self {
+            records: heapless::Deque::new(),
+            next_id: 1,
+        }
+    }
+
+    // A new pending record; returns its id
+    pub fn open(&mut self, source: Source, action: Action, digest: u32, request_id: Option<u32>, now_ms: u64) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        if self.records.is_full() {
+            self.records.pop_back();
+        }
+        let _ = self.records.push_front(Record {
+            id,
+            at_ms: now_ms,
+            source,
+            action,
+            digest,
+            request_id,
+            status: Status::Pending,
+            detail: heapless::String::new(),
+        });
+        id
+    }
+
+    // False when the record has already been pushed out
+    pub fn set(&mut self, id: u32, status: Status, detail: &str) -> bool {
+        let Some(record) = self.records.iter_mut().find(|r| r.id == id) else {
+            return false;
+        };
+        record.status = status;
+        record.detail.clear();
+        crate::utf8::push_truncated(&mut record.detail, detail);
+        true
+    }
+
+    // Newest first
+    pub fn iter(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter()
+    }
+}
+
+// FNV-1a over the parts, with a separator so ("ab", "c") and ("a", "bc")
+// differ
+pub fn digest(parts: &[&[u8]]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for part in parts {
+        for &b in part.iter().chain(&[0u8]) {
+            hash ^= b as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+    }
+    hash
+}
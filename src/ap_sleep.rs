@@ -0,0 +1,98 @@
+// 电池供电时无人使用就关掉 AP
+//
+// Beaconing costs more power than anything else the board does while
+// nobody uses it. When power.ap_idle_min is not 0 and no client has had
+// a connection open for that many minutes, the AP stops. The modem and
+// the background scheduler carry on. The cyw43 driver does not report
+// associated stations, so a client counts as present while it has a TCP
+// connection open on any listener. A station that is joined but silent
+// does not keep the AP up. The AP comes back when the wake button
+// (power.wake_gpio, pulled up, pressed = low) is pressed, when the wake
+// window power.wake_start..power.wake_end opens, or when the policy is
+// turned off. Inside the window the AP never sleeps, and the idle time
+// counts from the window's end. Recovery mode ignores the policy.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    Button,
+    Window,
+    // power.ap_idle_min set to 0
+    Disabled,
+}
+
+impl Wake {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Wake::Button => "button",
+            Wake::Window => "wake window",
+            Wake::Disabled => "policy off",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Sleep,
+    Wake(Wake),
+}
+
+pub struct ApSleep {
+    sleeping_since_ms: Option<u64>,
+    last_activity_ms: u64,
+    // since boot
+    sleeps: u32,
+}
+
+impl ApSleep {
+    pub const fn new() -> Self {
+        Self {
+            sleeping_since_ms: None,
+            last_activity_ms: 0,
+            sleeps: 0,
+        }
+    }
+
+    // A client connected, or still has a connection open
+    pub fn activity(&mut self, now_ms: u64) {
+        self.last_activity_ms = self.last_activity_ms.max(now_ms);
+    }
+
+    pub fn sleeping_since_ms(&self) -> Option<u64> {
+        self.sleeping_since_ms
+    }
+
+    pub fn sleeps(&self) -> u32 {
+        self.sleeps
+    }
+
+    // What the AP should do now; the caller carries it out
+    pub fn step(&mut self, idle_min: u32, in_window: bool, button: bool, now_ms: u64) -> Option<Change> {
+        let wake = if idle_min == 0 {
+            Some(Wake::Disabled)
+        } else if button {
+            Some(Wake::Button)
+        } else if in_window {
+            Some(Wake::Window)
+        } else {
+            None
+        };
+
+        if self.sleeping_since_ms.is_some() {
+            let wake = wake?;
+            self.sleeping_since_ms = None;
+            self.last_activity_ms = now_ms;
+            return Some(Change::Wake(wake));
+        }
+        // 醒着时按钮和时段都算有人在用
+        if wake.is_some() {
+            self.activity(now_ms);
+            return None;
+        }
+        if now_ms.saturating_sub(self.last_activity_ms) < idle_min as u64 * 60_000 {
+            return None;
+        }
+        self.sleeping_since_ms = Some(now_ms);
+        self.sleeps += 1;
+        Some(Change::Sleep)
+    }
+}
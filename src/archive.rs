@@ -0,0 +1,156 @@
+// 最近几次获取的响应存档 (/api/response/<n>)
+//
+// The last LEN fetches shown in the results area keep their body here, so
+// a response can still be compared with the next one after it has gone
+// from the page. Entries are numbered from 1 at boot. The fetch history
+// links to that number, and the link stops working once the entry is
+// evicted. The bodies are packed oldest first into one ARENA-byte arena.
+// When a new body does not fit, the oldest entries are dropped until it
+// does. A body longer than SLOT_MAX is cut, TRUNCATED_MARK is appended,
+// and `body_bytes` keeps the size the server sent. The archive lives in a
+// static, so it outlasts modem restarts; a reboot clears it.
+
+use crate::rings::{Ring, Usage};
+use crate::{response, utf8};
+
+pub const LEN: usize = 4;
+pub const ARENA: usize = 8192;
+pub const SLOT_MAX: usize = response::BODY_MAX;
+pub const TRUNCATED_MARK: &[u8] = b"\r\n[truncated]\r\n";
+
+pub struct Entry {
+    pub number: u32,
+    pub at_ms: u64,
+    pub url: heapless::String<128>,
+    pub status: Option<u16>,
+    pub content_type: heapless::String<96>,
+    // as sent by the server, before any cut
+    pub body_bytes: u32,
+    pub truncated: bool,
+    start: usize,
+    len: usize,
+}
+
+impl Entry {
+    // Stored bytes, the marker included
+    pub fn stored(&self) -> usize {
+        self.len
+    }
+}
+
+pub struct Archive {
+    arena: [u8; ARENA],
+    used: usize,
+    entries: heapless::Deque<Entry, LEN>,
+    last_number: u32,
+    // stored bytes, markers included
+    written: u32,
+    // body bytes the server sent that were not stored
+    cut: u32,
+    evicted: u32,
+}
+
+impl Archive {
+    pub const fn new() -> Self {
+        Self {
+            arena: [0; ARENA],
+            used: 0,
+            entries: heapless::Deque::new(),
+            last_number: 0,
+            written: 0,
+            cut: 0,
+            evicted: 0,
+        }
+    }
+
+    // Returns the new entry's number
+    pub fn push(&mut self, url: &str, response: &response::Response, at_ms: u64) -> u32 {
+        let body = response.body();
+        let truncated = response.truncated() || body.len() > SLOT_MAX;
+        let keep = if truncated {
+            body.len().min(SLOT_MAX - TRUNCATED_MARK.len())
+        } else {
+            body.len()
+        };
+        let len = keep + if truncated { TRUNCATED_MARK.len() } else { 0 };
+
+        while self.entries.is_full() || self.used + len > ARENA {
+            self.evict_oldest();
+        }
+        let start = self.used;
+        self.arena[start..start + keep].copy_from_slice(&body[..keep]);
+        if truncated {
+            self.arena[start + keep..start + len].copy_from_slice(TRUNCATED_MARK);
+        }
+        self.used += len;
+        self.written = self.written.saturating_add(len as u32);
+        self.cut = self.cut.saturating_add(response.body_bytes().saturating_sub(keep as u32));
+
+        self.last_number += 1;
+        let mut entry = Entry {
+            number: self.last_number,
+            at_ms,
+            url: heapless::String::new(),
+            status: response.status(),
+            content_type: heapless::String::new(),
+            body_bytes: response.body_bytes(),
+            truncated,
+            start,
+            len,
+        };
+        utf8::push_truncated(&mut entry.url, url);
+        utf8::push_truncated(&mut entry.content_type, response.content_type());
+        let _ = self.entries.push_back(entry);
+        self.last_number
+    }
+
+    // 去掉最旧的一条, 后面的正文前移
+    fn evict_oldest(&mut self) {
+        let Some(oldest) = self.entries.pop_front() else {
+            return;
+        };
+        self.arena.copy_within(oldest.len..self.used, 0);
+        self.used -= oldest.len;
+        self.evicted = self.evicted.saturating_add(1);
+        for entry in self.entries.iter_mut() {
+            entry.start -= oldest.len;
+        }
+    }
+
+    pub fn get(&self, number: u32) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.number == number)
+    }
+
+    // Copies stored bytes of entry `number` from `offset`; 0 once it is gone
+    pub fn read_at(&self, number: u32, offset: usize, out: &mut [u8]) -> usize {
+        let Some(entry) = self.get(number) else {
+            return 0;
+        };
+        let stored = &self.arena[entry.start..entry.start + entry.len];
+        let stored = stored.get(offset..).unwrap_or(&[]);
+        let n = stored.len().min(out.len());
+        out[..n].copy_from_slice(&stored[..n]);
+        n
+    }
+}
+
+impl Ring for Archive {
+    fn usage(&self) -> Usage {
+        Usage {
+            capacity: ARENA,
+            used: self.used,
+            written: self.written,
+            dropped: self.cut,
+            wraps: self.evicted,
+        }
+    }
+
+    // Numbers go on from the last one, so an old link never finds a new body
+    fn reset(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+        self.written = 0;
+        self.cut = 0;
+        self.evicted = 0;
+    }
+}
@@ -0,0 +1,407 @@
+// AP 客户端的 ARP 表 (/api/arp) 和静态 ARP 绑定
+//
+// embassy-net does not expose smoltcp's neighbor cache, so the WiFi driver
+// is wrapped in a Tap that sees every frame. Each received ARP packet or
+// IPv4 packet from the AP subnet updates our own table of IP and MAC with
+// when the client was first and last heard. smoltcp drops a neighbor a
+// minute after learning it (FRESH_MS), so an entry older than that is
+// shown as stale: the next packet to it starts with an ARP request. The
+// table forgets a client after FORGET_MS of silence. There is no DHCP
+// server on the AP, so traffic is the only source.
+//
+// arp.pins lists up to MAX_PINS "ip=mac" pairs separated by spaces, for
+// clients that stop answering ARP. When the stack is about to ask who has
+// a pinned address, the Tap keeps the request off the air and hands the
+// stack the reply itself, so such a client is never asked at all.
+
+use core::task::{Context, Waker};
+
+use embassy_net::driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+
+pub const CAPACITY: usize = 16;
+pub const MAX_PINS: usize = 4;
+// smoltcp's neighbor cache lifetime
+pub const FRESH_MS: u64 = 60_000;
+pub const FORGET_MS: u64 = 10 * 60_000;
+// Ethernet header and an IPv4 ARP packet
+pub const ARP_FRAME_LEN: usize = 42;
+
+pub type Mac = [u8; 6];
+pub type Ip = [u8; 4];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    pub ip: Ip,
+    pub mac: Mac,
+}
+
+// "192.168.4.20=aa:bb:cc:dd:ee:ff 192.168.4.21=...", empty for none
+pub fn parse_pins(text: &str) -> Option<heapless::Vec<Pin, MAX_PINS>> {
+    let mut pins = heapless::Vec::new();
+    for pair in text.split_ascii_whitespace() {
+        let (ip, mac) = pair.split_once('=')?;
+        let pin = Pin {
+            ip: parse_ip(ip)?,
+            mac: parse_mac(mac)?,
+        };
+        if pins.iter().any(|p: &Pin| p.ip == pin.ip) {
+            return None;
+        }
+        pins.push(pin).ok()?;
+    }
+    Some(pins)
+}
+
+fn parse_ip(text: &str) -> Option<Ip> {
+    let mut ip = [0; 4];
+    let mut parts = text.split('.');
+    for octet in &mut ip {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(ip)
+}
+
+// aa:bb:cc:dd:ee:ff or with '-'
+fn parse_mac(text: &str) -> Option<Mac> {
+    let mut mac = [0; 6];
+    let mut parts = text.split([':', '-']);
+    for byte in &mut mac {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
+
+pub fn write_ip<W: core::fmt::Write>(out: &mut W, ip: Ip) {
+    let _ = core::write!(out, "{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3]);
+}
+
+pub fn write_mac<W: core::fmt::Write>(out: &mut W, mac: Mac) {
+    let _ = core::write!(
+        out,
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5]
+    );
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    // heard within FRESH_MS
+    Reachable,
+    Stale,
+    // from arp.pins; never asked
+    Pinned,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Reachable => "reachable",
+            State::Stale => "stale",
+            State::Pinned => "pinned",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Neighbor {
+    pub ip: Ip,
+    pub mac: Mac,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+}
+
+impl Neighbor {
+    pub fn state(&self, now_ms: u64) -> State {
+        if now_ms.saturating_sub(self.last_seen_ms) < FRESH_MS {
+            State::Reachable
+        } else {
+            State::Stale
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Event {
+    Added(Neighbor),
+    // the address moved to another MAC
+    Moved { neighbor: Neighbor, old_mac: Mac },
+}
+
+pub struct Neighbors {
+    entries: heapless::Vec<Neighbor, CAPACITY>,
+}
+
+impl Neighbors {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    pub fn observe(&mut self, ip: Ip, mac: Mac, now_ms: u64) -> Option<Event> {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.ip == ip) {
+            entry.last_seen_ms = now_ms;
+            if entry.mac == mac {
+                return None;
+            }
+            let old_mac = entry.mac;
+            entry.mac = mac;
+            entry.first_seen_ms = now_ms;
+            return Some(Event::Moved {
+                neighbor: *entry,
+                old_mac,
+            });
+        }
+        // 满了: 挤掉最久没消息的
+        if self.entries.is_full()
+            && let Some((oldest, _)) = self.entries.iter().enumerate().min_by_key(|(_, e)| e.last_seen_ms)
+        {
+            self.entries.swap_remove(oldest);
+        }
+        let neighbor = Neighbor {
+            ip,
+            mac,
+            first_seen_ms: now_ms,
+            last_seen_ms: now_ms,
+        };
+        let _ = self.entries.push(neighbor);
+        Some(Event::Added(neighbor))
+    }
+
+    // Forgets clients silent for FORGET_MS, passing each to `forgotten`
+    pub fn expire(&mut self, now_ms: u64, mut forgotten: impl FnMut(&Neighbor)) {
+        self.entries.retain(|e| {
+            let keep = now_ms.saturating_sub(e.last_seen_ms) < FORGET_MS;
+            if !keep {
+                forgotten(e);
+            }
+            keep
+        });
+    }
+
+    // The table with the pins merged in, sorted by address
+    pub fn rows(&self, pins: &[Pin], now_ms: u64) -> heapless::Vec<Row, { CAPACITY + MAX_PINS }> {
+        let mut rows = heapless::Vec::<Row, { CAPACITY + MAX_PINS }>::new();
+        for pin in pins {
+            let _ = rows.push(Row {
+                ip: pin.ip,
+                mac: pin.mac,
+                state: State::Pinned,
+                heard: self.entries.iter().find(|e| e.ip == pin.ip).copied(),
+            });
+        }
+        for entry in self.entries.iter().filter(|e| !pins.iter().any(|p| p.ip == e.ip)) {
+            let _ = rows.push(Row {
+                ip: entry.ip,
+                mac: entry.mac,
+                state: entry.state(now_ms),
+                heard: Some(*entry),
+            });
+        }
+        rows.sort_unstable_by_key(|row| row.ip);
+        rows
+    }
+}
+
+pub struct Row {
+    pub ip: Ip,
+    // the pinned MAC for a pin
+    pub mac: Mac,
+    pub state: State,
+    // None for a pin not heard from since boot
+    pub heard: Option<Neighbor>,
+}
+
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+const ETHERTYPE_ARP: [u8; 2] = [0x08, 0x06];
+// htype Ethernet, ptype IPv4, hlen 6, plen 4
+const ARP_IPV4: [u8; 6] = [0x00, 0x01, 0x08, 0x00, 6, 4];
+const ARP_REQUEST: [u8; 2] = [0, 1];
+const ARP_REPLY: [u8; 2] = [0, 2];
+
+fn is_arp(frame: &[u8]) -> bool {
+    frame.len() >= ARP_FRAME_LEN && frame[12..14] == ETHERTYPE_ARP && frame[14..20] == ARP_IPV4
+}
+
+// The IP and MAC a received frame tells us about: the sender of an ARP
+// packet, or the source of an IPv4 packet
+pub fn sender(frame: &[u8]) -> Option<(Ip, Mac)> {
+    let (ip, mac) = if is_arp(frame) {
+        (&frame[28..32], &frame[22..28])
+    } else if frame.len() >= 34 && frame[12..14] == ETHERTYPE_IPV4 {
+        (&frame[26..30], &frame[6..12])
+    } else {
+        return None;
+    };
+    let (ip, mac): (Ip, Mac) = (ip.try_into().ok()?, mac.try_into().ok()?);
+    // ARP 探测的发送方是 0.0.0.0; 组播 MAC 不是客户端
+    (ip != [0; 4] && mac[0] & 1 == 0).then_some((ip, mac))
+}
+
+// For an ARP request the stack is sending about a pinned address, the
+// reply the client would have given
+pub fn pinned_reply(frame: &[u8], pins: &[Pin]) -> Option<[u8; ARP_FRAME_LEN]> {
+    if !is_arp(frame) || frame[20..22] != ARP_REQUEST {
+        return None;
+    }
+    let pin = pins.iter().find(|p| p.ip[..] == frame[38..42])?;
+    let (our_mac, our_ip) = (&frame[22..28], &frame[28..32]);
+
+    let mut reply = [0; ARP_FRAME_LEN];
+    reply[0..6].copy_from_slice(our_mac);
+    reply[6..12].copy_from_slice(&pin.mac);
+    reply[12..14].copy_from_slice(&ETHERTYPE_ARP);
+    reply[14..20].copy_from_slice(&ARP_IPV4);
+    reply[20..22].copy_from_slice(&ARP_REPLY);
+    reply[22..28].copy_from_slice(&pin.mac);
+    reply[28..32].copy_from_slice(&pin.ip);
+    reply[32..38].copy_from_slice(our_mac);
+    reply[38..42].copy_from_slice(our_ip);
+    Some(reply)
+}
+
+// What the Tap calls; plain functions, so the Tap holds no state of its own
+#[derive(Clone, Copy)]
+pub struct Hooks {
+    // every frame the stack receives
+    pub received: fn(&[u8]),
+    // an ARP-sized frame the stack is about to send: Some to answer it
+    // in place of sending it
+    pub answer: fn(&[u8]) -> Option<[u8; ARP_FRAME_LEN]>,
+}
+
+// The WiFi driver with the hooks on its frames
+pub struct Tap<D> {
+    inner: D,
+    hooks: Hooks,
+    // an answer waiting for the stack's next receive
+    answered: Option<[u8; ARP_FRAME_LEN]>,
+}
+
+impl<D> Tap<D> {
+    pub fn new(inner: D, hooks: Hooks) -> Self {
+        Self {
+            inner,
+            hooks,
+            answered: None,
+        }
+    }
+}
+
+pub enum TapRx<T> {
+    Frame(T, fn(&[u8])),
+    Answer([u8; ARP_FRAME_LEN]),
+}
+
+impl<T: RxToken> RxToken for TapRx<T> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        match self {
+            TapRx::Frame(token, received) => token.consume(|frame| {
+                received(frame);
+                f(frame)
+            }),
+            TapRx::Answer(mut frame) => f(&mut frame),
+        }
+    }
+}
+
+pub struct TapTx<'a, T> {
+    inner: T,
+    answer: fn(&[u8]) -> Option<[u8; ARP_FRAME_LEN]>,
+    answered: &'a mut Option<[u8; ARP_FRAME_LEN]>,
+    waker: Waker,
+}
+
+impl<T: TxToken> TxToken for TapTx<'_, T> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        if len != ARP_FRAME_LEN {
+            return self.inner.consume(len, f);
+        }
+        // ARP 大小的帧先写到这里, 看过再决定发不发
+        let mut frame = [0; ARP_FRAME_LEN];
+        let result = f(&mut frame);
+        match (self.answer)(&frame) {
+            Some(reply) => {
+                *self.answered = Some(reply);
+                self.waker.wake_by_ref();
+            }
+            None => self.inner.consume(len, |buf| buf.copy_from_slice(&frame)),
+        }
+        result
+    }
+}
+
+impl<D: Driver> Driver for Tap<D> {
+    type RxToken<'a>
+        = TapRx<D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TapTx<'a, D::TxToken<'a>>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let waker = cx.waker().clone();
+        if let Some(reply) = self.answered.take() {
+            // 先交付应答; 发送令牌没有时下次再来
+            let Some(tx) = self.inner.transmit(cx) else {
+                self.answered = Some(reply);
+                return None;
+            };
+            let tx = TapTx {
+                inner: tx,
+                answer: self.hooks.answer,
+                answered: &mut self.answered,
+                waker,
+            };
+            return Some((TapRx::Answer(reply), tx));
+        }
+        let (rx, tx) = self.inner.receive(cx)?;
+        let tx = TapTx {
+            inner: tx,
+            answer: self.hooks.answer,
+            answered: &mut self.answered,
+            waker,
+        };
+        Some((TapRx::Frame(rx, self.hooks.received), tx))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        let waker = cx.waker().clone();
+        let tx = self.inner.transmit(cx)?;
+        Some(TapTx {
+            inner: tx,
+            answer: self.hooks.answer,
+            answered: &mut self.answered,
+            waker,
+        })
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
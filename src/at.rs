@@ -0,0 +1,158 @@
+// AT 指令参数校验
+//
+// Hostnames, SMS numbers, the APN and SIM codes end up inside quoted AT
+// arguments. A quote, CR or LF in one would end the argument or the whole
+// command and hand the rest to the modem as a command of its own, so every
+// kind of value has a character class. Forms and settings check values with
+// `arg` when they are entered and name the field in the error; the command
+// builders check again and send an empty argument (the module answers
+// ERROR) rather than a value that fails. The /at console and macros send
+// commands as typed on purpose and are not covered.
+
+use core::fmt::Write as _;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    // letters, digits, '-' and '.', as in a URL
+    Host,
+    // an IP address from a DNS reply: hex digits, '.' and ':'
+    Address,
+    // digits, optionally international with a leading '+'
+    Phone,
+    // printable ASCII except '"'
+    Apn,
+    // SIM PIN or PUK digits
+    Pin,
+}
+
+impl Kind {
+    fn max_len(self) -> usize {
+        match self {
+            Kind::Host => 253,
+            Kind::Address => 45,
+            Kind::Phone => 15,
+            Kind::Apn => 62,
+            Kind::Pin => 8,
+        }
+    }
+
+    fn allows(self, b: u8) -> bool {
+        match self {
+            Kind::Host => b.is_ascii_alphanumeric() || b == b'-' || b == b'.',
+            Kind::Address => b.is_ascii_hexdigit() || b == b'.' || b == b':',
+            Kind::Phone | Kind::Pin => b.is_ascii_digit(),
+            Kind::Apn => (b' '..=b'~').contains(&b) && b != b'"',
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArgError {
+    Empty,
+    TooLong { max: usize },
+    // a phone number shorter than this many digits
+    TooShort { min: usize },
+    Forbidden(char),
+}
+
+impl ArgError {
+    pub fn describe<const N: usize>(&self, out: &mut heapless::String<N>) {
+        let _ = match self {
+            ArgError::Empty => out.push_str("must not be empty"),
+            ArgError::TooLong { max } => core::write!(out, "at most {} characters", max).map_err(|_| ()),
+            ArgError::TooShort { min } => core::write!(out, "at least {} digits", min).map_err(|_| ()),
+            ArgError::Forbidden(c) => core::write!(out, "{:?} not allowed", c).map_err(|_| ()),
+        };
+    }
+}
+
+// `value` when it is safe inside a quoted argument of this kind
+pub fn arg(value: &str, kind: Kind) -> Result<&str, ArgError> {
+    if value.is_empty() {
+        return Err(ArgError::Empty);
+    }
+    let body = match kind {
+        Kind::Phone => value.strip_prefix('+').unwrap_or(value),
+        _ => value,
+    };
+    if body.len() > kind.max_len() {
+        return Err(ArgError::TooLong { max: kind.max_len() });
+    }
+    if let Some(c) = body.chars().find(|&c| !c.is_ascii() || !kind.allows(c as u8)) {
+        return Err(ArgError::Forbidden(c));
+    }
+    if kind == Kind::Phone && body.len() < 3 {
+        return Err(ArgError::TooShort { min: 3 });
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [Kind; 5] = [Kind::Host, Kind::Address, Kind::Phone, Kind::Apn, Kind::Pin];
+
+    // A value of each kind that passes
+    fn valid(kind: Kind) -> &'static str {
+        match kind {
+            Kind::Host => "httpbin.org",
+            Kind::Address => "3.223.36.72",
+            Kind::Phone => "+8613800138000",
+            Kind::Apn => "cmnet",
+            Kind::Pin => "1234",
+        }
+    }
+
+    #[test]
+    fn valid_values_pass() {
+        for kind in KINDS {
+            assert_eq!(arg(valid(kind), kind), Ok(valid(kind)), "{kind:?}");
+        }
+        assert_eq!(arg("2001:db8::1", Kind::Address), Ok("2001:db8::1"));
+        assert_eq!(arg("internet.v6 apn", Kind::Apn), Ok("internet.v6 apn"));
+    }
+
+    #[test]
+    fn line_breaks_and_quotes_are_refused_everywhere() {
+        for kind in KINDS {
+            for bad in ['\r', '\n', '"'] {
+                let value = format!("12{bad}34");
+                assert_eq!(arg(&value, kind), Err(ArgError::Forbidden(bad)), "{kind:?} {bad:?}");
+                let value = format!("{}{bad}", valid(kind));
+                assert!(arg(&value, kind).is_err(), "{kind:?} {value:?}");
+            }
+            let injected = format!("{}\"\r\nAT+CFUN=0\r\n", valid(kind));
+            assert!(arg(&injected, kind).is_err(), "{kind:?}");
+            assert_eq!(arg("1\u{0}2", kind), Err(ArgError::Forbidden('\u{0}')));
+            assert_eq!(arg("12é34", kind), Err(ArgError::Forbidden('é')));
+        }
+    }
+
+    // '<' cannot end a quoted AT argument; it is kept out of everything but
+    // the APN, which pages escape like any other text
+    #[test]
+    fn markup() {
+        for kind in [Kind::Host, Kind::Address, Kind::Phone, Kind::Pin] {
+            assert_eq!(arg("12<34", kind), Err(ArgError::Forbidden('<')), "{kind:?}");
+            assert_eq!(arg("12>34", kind), Err(ArgError::Forbidden('>')), "{kind:?}");
+        }
+        assert_eq!(arg("a<b>", Kind::Apn), Ok("a<b>"));
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(arg("", Kind::Host), Err(ArgError::Empty));
+        assert_eq!(arg(&"a".repeat(253), Kind::Host).map(str::len), Ok(253));
+        assert_eq!(arg(&"a".repeat(254), Kind::Host), Err(ArgError::TooLong { max: 253 }));
+        assert_eq!(arg("12345678", Kind::Pin), Ok("12345678"));
+        assert_eq!(arg("123456789", Kind::Pin), Err(ArgError::TooLong { max: 8 }));
+        // the '+' of an international number does not count
+        assert_eq!(arg("+123456789012345", Kind::Phone), Ok("+123456789012345"));
+        assert_eq!(arg("1234567890123456", Kind::Phone), Err(ArgError::TooLong { max: 15 }));
+        assert_eq!(arg("+123", Kind::Phone), Ok("+123"));
+        assert_eq!(arg("+12", Kind::Phone), Err(ArgError::TooShort { min: 3 }));
+        assert_eq!(arg("+", Kind::Phone), Err(ArgError::TooShort { min: 3 }));
+        assert_eq!(arg("++123", Kind::Phone), Err(ArgError::Forbidden('+')));
+    }
+}
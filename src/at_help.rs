@@ -0,0 +1,223 @@
+// AT 命令说明: 控制台在命令下面加一行解释, 并解读认得的应答
+//
+// The /at console is hard to use without the Quectel manual at hand. A
+// static table gives each known command a short note on what it does and
+// what a good answer looks like. Unsolicited lines get a short note on
+// what the modem is reporting. The console shows the note under the
+// command it sent. It also decodes the answers it knows: CSQ to dBm, the
+// CREG/CGREG/CEREG stat codes to words, and QIACT to the context state.
+// Unknown commands and lines pass through without a note.
+
+use core::fmt::Write;
+
+use crate::registration;
+
+pub struct Help {
+    // the command name, without `=`, `?` or the digit of a basic command
+    pub name: &'static str,
+    pub about: &'static str,
+    // the shape of a good answer
+    pub answer: &'static str,
+}
+
+const fn help(name: &'static str, about: &'static str, answer: &'static str) -> Help {
+    Help { name, about, answer }
+}
+
+const COMMANDS: &[Help] = &[
+    help("AT", "Is the modem listening", "OK"),
+    help("ATI", "Product name and firmware revision", "Quectel, model, Revision: ..."),
+    help("ATE", "Command echo: 0 off, 1 on", "OK"),
+    help("AT+CSQ", "Signal quality", "+CSQ: <rssi 0-31, 99 unknown>,<ber>"),
+    help("AT+CREG", "Circuit-switched network registration", "+CREG: <n>,<stat>"),
+    help("AT+CGREG", "GPRS network registration", "+CGREG: <n>,<stat>"),
+    help("AT+CEREG", "LTE network registration", "+CEREG: <n>,<stat>"),
+    help("AT+CGATT", "Packet data attach", "+CGATT: 1 attached, 0 detached"),
+    help("AT+CPIN", "SIM state", "+CPIN: READY"),
+    help("AT+QPINC", "PIN and PUK attempts left", "+QPINC: \"SC\",<pin>,<puk>"),
+    help("AT+COPS", "Operator selection", "+COPS: <mode>,<format>,\"<operator>\",<act>"),
+    help("AT+CFUN", "Radio: 0 minimum, 1 full, 4 flight mode", "+CFUN: <fun>"),
+    help("AT+CCLK", "Network time", "+CCLK: \"yy/MM/dd,hh:mm:ss+zz\""),
+    help("AT+CGDCONT", "PDP context APN", "+CGDCONT: <cid>,\"IP\",\"<apn>\",..."),
+    help("AT+QICSGP", "TCP/IP context APN and login", "+QICSGP: <type>,\"<apn>\",..."),
+    help("AT+QIACT", "Bring up a PDP context, or list the active ones", "+QIACT: <ctx>,<state>,<type>,\"<ip>\""),
+    help("AT+QIDEACT", "Take a PDP context down", "OK"),
+    help("AT+QIDNSGIP", "DNS lookup", "OK, then +QIURC: \"dnsgip\",..."),
+    help("AT+QIOPEN", "Open a socket", "OK, then +QIOPEN: <id>,0"),
+    help("AT+QISEND", "Send on a socket", "> prompt, then SEND OK"),
+    help("AT+QIRD", "Read what a socket received", "+QIRD: <len>, then the data"),
+    help("AT+QICLOSE", "Close a socket", "OK"),
+    help("AT+QPING", "Ping a host", "OK, then +QPING: lines"),
+    help("AT+QGPS", "GNSS on (1) or off", "OK"),
+    help("AT+QGPSLOC", "GNSS position", "+QGPSLOC: <time>,<lat>,<lon>,..."),
+    help("AT+QGPSEND", "GNSS off", "OK"),
+    help("AT+CMGF", "SMS format: 0 PDU, 1 text", "OK"),
+    help("AT+CMGS", "Send an SMS", "> prompt, then +CMGS: <ref>"),
+    help("AT+QPOWD", "Power the module down", "POWERED DOWN"),
+    help("AT+IPR", "UART baud rate", "OK"),
+];
+
+// (line prefix, what the modem is reporting)
+const UNSOLICITED: &[(&str, &str)] = &[
+    ("RDY", "the module has started"),
+    ("POWERED DOWN", "the module is switching off"),
+    ("+QIURC: \"closed\"", "the peer closed a socket"),
+    ("+QIURC: \"recv\"", "a socket has data to read"),
+    ("+QIURC: \"pdpdeact\"", "the network took the PDP context down"),
+    ("+QIURC: \"dnsgip\"", "a DNS answer"),
+    ("+QIOPEN:", "socket open result, 0 is success"),
+    ("+CMTI:", "an SMS arrived"),
+    ("+CPIN:", "SIM state"),
+    ("+CMGS:", "the SMS was sent"),
+    ("NO CARRIER", "the connection dropped"),
+];
+
+// The command name as the table keys it: `AT+CREG=2` and `AT+CREG?` are
+// both AT+CREG, `ATE0` is ATE
+fn name(command: &str) -> &str {
+    let command = command.trim();
+    let end = command.find(['=', '?']).unwrap_or(command.len());
+    let name = &command[..end];
+    if name.contains('+') {
+        name
+    } else {
+        name.trim_end_matches(|c: char| c.is_ascii_digit())
+    }
+}
+
+pub fn command(command: &str) -> Option<&'static Help> {
+    let name = name(command);
+    COMMANDS.iter().find(|h| h.name.eq_ignore_ascii_case(name))
+}
+
+// One line on an answer line the table knows; false when there is nothing
+// to say
+pub fn decode(line: &str, out: &mut impl Write) -> bool {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix("+CSQ:") {
+        let rssi = rest.split(',').next().and_then(|r| r.trim().parse::<i32>().ok());
+        let _ = match rssi {
+            Some(rssi @ 0..=31) => write!(out, "signal {} dBm", -113 + 2 * rssi),
+            Some(_) => out.write_str("signal not known yet"),
+            None => return false,
+        };
+        return true;
+    }
+    if let Some(state) = registration::parse(line) {
+        let _ = write!(out, "registration: {}", state.as_str());
+        return true;
+    }
+    if let Some(rest) = line.strip_prefix("+QIACT:") {
+        let mut fields = rest.split(',').map(str::trim);
+        let (Some(context), Some(state)) = (fields.next(), fields.next()) else {
+            return false;
+        };
+        let kind = match fields.next() {
+            Some("1") => "IPv4",
+            Some("2") => "IPv6",
+            Some("3") => "IPv4v6",
+            _ => "",
+        };
+        let state = if state == "1" { "active" } else { "inactive" };
+        let _ = write!(out, "context {} {}", context, state);
+        if !kind.is_empty() {
+            let _ = write!(out, " {}", kind);
+        }
+        if let Some(ip) = fields.next() {
+            let _ = write!(out, " {}", ip.trim_matches('"'));
+        }
+        return true;
+    }
+    if let Some(rest) = line.strip_prefix("+CGATT:") {
+        let _ = out.write_str(if rest.trim() == "1" { "attached" } else { "detached" });
+        return true;
+    }
+    match UNSOLICITED.iter().find(|(prefix, _)| line.starts_with(prefix)) {
+        Some((_, about)) => {
+            let _ = out.write_str(about);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modem::{CellularModem, Quectel};
+
+    fn entries(command: &str) -> usize {
+        COMMANDS.iter().filter(|h| h.name.eq_ignore_ascii_case(name(command))).count()
+    }
+
+    #[test]
+    fn every_entry_is_found_by_its_own_name() {
+        for help in COMMANDS {
+            assert_eq!(name(help.name), help.name);
+            assert_eq!(entries(help.name), 1, "{}", help.name);
+        }
+    }
+
+    // Every command the firmware sends to the module has a note
+    #[test]
+    fn every_command_sent_has_one_entry() {
+        let m = Quectel;
+        let mut sent = vec![
+            m.register(),
+            m.register_eps(),
+            m.deactivate_pdp(),
+            m.pdp_state(),
+            m.resolve_dns("httpbin.org"),
+            m.tcp_connect(0, "1.2.3.4", 80),
+            m.tcp_connect_direct(0, "1.2.3.4", 80),
+            m.tcp_send(0, 10),
+            m.tcp_recv(0, 10),
+            m.tcp_close(0),
+            m.signal_quality(),
+            m.restart(),
+            m.ping("httpbin.org", 4),
+            m.pin_counter(),
+            m.power_down(),
+            m.sms_text_mode(),
+            m.sms_send("+441234567890"),
+        ];
+        sent.extend(m.init());
+        sent.extend(m.activate_pdp("internet"));
+        #[cfg(feature = "gnss")]
+        sent.extend([m.gnss_power(true), m.gnss_power(false), m.gnss_location()].into_iter().flatten());
+        for command in sent.iter().map(|c| c.as_str()).chain(["AT", "ATI", "ATE0", "AT+CPIN=\"1234\""]) {
+            assert_eq!(entries(command), 1, "{command:?}");
+        }
+    }
+
+    #[test]
+    fn names_drop_arguments_and_basic_digits() {
+        assert_eq!(name("AT+CREG=2\r\n"), "AT+CREG");
+        assert_eq!(name("AT+CREG?"), "AT+CREG");
+        assert_eq!(name("ATE0"), "ATE");
+        assert_eq!(command("at+csq").map(|h| h.name), Some("AT+CSQ"));
+        assert!(command("AT+QFOO=1").is_none());
+    }
+
+    fn decoded(line: &str) -> Option<String> {
+        let mut out = String::new();
+        decode(line, &mut out).then_some(out)
+    }
+
+    #[test]
+    fn known_answers_are_decoded() {
+        assert_eq!(decoded("+CSQ: 23,99").as_deref(), Some("signal -67 dBm"));
+        assert_eq!(decoded("+CSQ: 99,99").as_deref(), Some("signal not known yet"));
+        assert_eq!(decoded("+CREG: 0,5").as_deref(), Some("registration: roaming"));
+        assert_eq!(decoded("+CEREG: 1").as_deref(), Some("registration: home"));
+        assert_eq!(
+            decoded("+QIACT: 1,1,1,\"10.0.0.2\"").as_deref(),
+            Some("context 1 active IPv4 10.0.0.2")
+        );
+        assert_eq!(decoded("+CGATT: 0").as_deref(), Some("detached"));
+        assert_eq!(decoded("+QIURC: \"closed\",0").as_deref(), Some("the peer closed a socket"));
+        // unknown lines pass through
+        assert_eq!(decoded("OK"), None);
+        assert_eq!(decoded("+CSQ: x"), None);
+    }
+}
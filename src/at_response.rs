@@ -0,0 +1,239 @@
+// AT 应答分类
+//
+// Replies are taken apart line by line. `next_line` returns the next
+// complete line of what has arrived so far and how many bytes it used, so a
+// line split across reads stays in the buffer until its terminator comes.
+// Each line is classified on its own, never by searching a whole chunk,
+// so a payload that happens to contain "OK" or "ERROR" does not end a
+// command. The '>' send prompt has no terminator, so it is only looked for
+// while a command is known to be waiting for it (`next_awaited`).
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    // OK
+    Success,
+    // ERROR, +CME ERROR: <n>, +CMS ERROR: <n>, NO CARRIER
+    Failure,
+    // the '>' data prompt or CONNECT: more to send or receive
+    Intermediate,
+    // an unsolicited result code (URC_PREFIXES)
+    Urc,
+    // everything else: information lines, echoes and payload
+    Data,
+}
+
+impl Kind {
+    // The line ends the reply to a command
+    pub fn is_final(self) -> bool {
+        matches!(self, Kind::Success | Kind::Failure)
+    }
+}
+
+// Lines the module sends on its own, never as the reply to a command.
+// Prefixes that are also replies (+CPIN:, +CREG: ...) are left out.
+const URC_PREFIXES: [&str; 15] = [
+    "RDY",
+    "+QIURC:",
+    "+QIND:",
+    "+QUSIM:",
+    "+QGPSURC:",
+    "+CMTI:",
+    "+CDS:",
+    "+CRING:",
+    "RING",
+    "+CTZV:",
+    "+CTZE:",
+    "+PDP: DEACT",
+    "SMS Ready",
+    "Call Ready",
+    "NORMAL POWER DOWN",
+];
+
+pub fn classify(line: &str) -> Kind {
+    let line = line.trim();
+    match line {
+        "OK" => Kind::Success,
+        "ERROR" | "NO CARRIER" => Kind::Failure,
+        ">" | "CONNECT" => Kind::Intermediate,
+        _ if line.starts_with("+CME ERROR:") || line.starts_with("+CMS ERROR:") => Kind::Failure,
+        _ if line.starts_with("CONNECT ") => Kind::Intermediate,
+        _ if URC_PREFIXES.iter().any(|prefix| line.starts_with(prefix)) => Kind::Urc,
+        _ => Kind::Data,
+    }
+}
+
+// The next line of `buf` (terminator removed, not trimmed) and the bytes it
+// used; None until a whole line has arrived. Bytes that are not UTF-8 come
+// back as an empty line.
+pub fn next_line(buf: &[u8]) -> Option<(&str, usize)> {
+    let pos = buf.iter().position(|&b| b == b'\n')?;
+    let text = &buf[..pos];
+    let text = text.strip_suffix(b"\r").unwrap_or(text);
+    Some((core::str::from_utf8(text).unwrap_or(""), pos + 1))
+}
+
+// What arrived while a command waits for the data prompt
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Awaited<'a> {
+    Prompt,
+    // a non-blank line before the prompt: echo, URC or the command's failure
+    Line(&'a str),
+}
+
+// The prompt or the next line of `buf`, and the bytes used; None until one
+// is complete. The prompt is "> " at the start of a line, whether or not a
+// CR LF came before it, so a line that merely starts with '>' is not taken
+// for it. A lone CR also ends a line here: the echo of a command sent with
+// CR only (AT+CMGS) is followed by the prompt without a LF.
+pub fn next_awaited(buf: &[u8]) -> Option<(Awaited<'_>, usize)> {
+    let start = buf.iter().position(|&b| b != b'\r' && b != b'\n')?;
+    let rest = &buf[start..];
+    if rest.starts_with(b"> ") {
+        return Some((Awaited::Prompt, start + 2));
+    }
+    let end = rest.iter().position(|&b| b == b'\r' || b == b'\n')?;
+    let text = core::str::from_utf8(&rest[..end]).unwrap_or("");
+    Some((Awaited::Line(text), start + end + 1))
+}
+
+// Every complete, non-blank line of `buf` in order with its kind; returns
+// the bytes used, the rest being the start of a line still arriving
+pub fn split(buf: &[u8], mut each: impl FnMut(&str, Kind)) -> usize {
+    let mut used = 0;
+    while let Some((line, consumed)) = next_line(&buf[used..]) {
+        used += consumed;
+        let line = line.trim();
+        if !line.is_empty() {
+            each(line, classify(line));
+        }
+    }
+    used
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `reads` into one buffer the way the UART task does and returns
+    // every line as it completes
+    fn lines(reads: &[&[u8]]) -> Vec<(String, Kind)> {
+        let mut buf = Vec::new();
+        let mut out = Vec::new();
+        for read in reads {
+            buf.extend_from_slice(read);
+            let used = split(&buf, |line, kind| out.push((line.to_string(), kind)));
+            buf.drain(..used);
+        }
+        out
+    }
+
+    #[test]
+    fn line_split_across_reads_waits_for_its_terminator() {
+        assert_eq!(next_line(b"+CSQ: 2"), None);
+        assert_eq!(next_line(b"+CSQ: 23,99\r"), None);
+        assert_eq!(next_line(b"+CSQ: 23,99\r\nO"), Some(("+CSQ: 23,99", 13)));
+
+        let got = lines(&[b"\r\n+CSQ: 2", b"3,99\r", b"\n\r\nO", b"K\r\n"]);
+        assert_eq!(got, [("+CSQ: 23,99".into(), Kind::Data), ("OK".into(), Kind::Success)]);
+    }
+
+    #[test]
+    fn urc_between_command_and_final_result() {
+        let got = lines(&[b"AT+QIOPEN=1,0,\"TCP\"\r\r\n+QIURC: \"pdpdeact\",1\r\n", b"\r\nERROR\r\n"]);
+        assert_eq!(
+            got,
+            [
+                ("AT+QIOPEN=1,0,\"TCP\"".into(), Kind::Data),
+                ("+QIURC: \"pdpdeact\",1".into(), Kind::Urc),
+                ("ERROR".into(), Kind::Failure),
+            ]
+        );
+
+        let got = lines(&[b"+QIRD: 5\r\nhello\r\n+QIURC: \"re", b"cv\",0\r\nOK\r\n"]);
+        assert_eq!(got[2], ("+QIURC: \"recv\",0".into(), Kind::Urc));
+        assert_eq!(got[3], ("OK".into(), Kind::Success));
+    }
+
+    #[test]
+    fn payload_that_looks_like_a_result_is_data() {
+        for line in ["OK then", "HTTP/1.1 200 OK", "ERRORS: 0", "{\"ok\":\"ERROR\"}", "NO CARRIERS", "> quoted"] {
+            assert_eq!(classify(line), Kind::Data, "{line}");
+        }
+        let got = lines(&[b"body says OK and ERROR\r\nOK\r\n"]);
+        assert_eq!(got, [("body says OK and ERROR".into(), Kind::Data), ("OK".into(), Kind::Success)]);
+    }
+
+    #[test]
+    fn final_results() {
+        assert_eq!(classify("OK"), Kind::Success);
+        assert_eq!(classify(" ERROR \r"), Kind::Failure);
+        assert_eq!(classify("+CME ERROR: 10"), Kind::Failure);
+        assert_eq!(classify("+CMS ERROR: 500"), Kind::Failure);
+        assert_eq!(classify("NO CARRIER"), Kind::Failure);
+        assert_eq!(classify("CONNECT 115200"), Kind::Intermediate);
+        assert!(!classify("RDY").is_final());
+    }
+
+    #[test]
+    fn non_utf8_line_comes_back_empty() {
+        assert_eq!(next_line(b"\xff\xfe\r\nOK\r\n"), Some(("", 4)));
+    }
+
+    #[test]
+    fn awaited_prompt_and_lines() {
+        assert_eq!(next_awaited(b"\r\n> "), Some((Awaited::Prompt, 4)));
+        assert_eq!(next_awaited(b"\r\n>"), None);
+        assert_eq!(next_awaited(b"AT+CMGS=\"1\"\r> "), Some((Awaited::Line("AT+CMGS=\"1\""), 12)));
+        assert_eq!(next_awaited(b">x\r\n"), Some((Awaited::Line(">x"), 3)));
+    }
+
+    // What the wait for the prompt sees when `reads` arrive one by one
+    fn awaited(reads: &[&[u8]]) -> (Vec<Option<String>>, usize) {
+        let mut buf = Vec::new();
+        let mut out = Vec::new();
+        for read in reads {
+            buf.extend_from_slice(read);
+            while let Some((got, used)) = next_awaited(&buf) {
+                out.push(match got {
+                    Awaited::Prompt => None,
+                    Awaited::Line(line) => Some(line.to_string()),
+                });
+                buf.drain(..used);
+            }
+        }
+        (out, buf.len())
+    }
+
+    #[test]
+    fn prompt_split_across_reads() {
+        const ANSWER: &[u8] = b"AT+QISEND=0,5\r\r\n> ";
+        for cut in 1..ANSWER.len() {
+            let (got, left) = awaited(&[&ANSWER[..cut], &ANSWER[cut..]]);
+            assert_eq!(got, [Some("AT+QISEND=0,5".into()), None], "cut at {cut}");
+            assert_eq!(left, 0, "cut at {cut}");
+        }
+        // one byte at a time, the URC before it delivered as a line
+        let reads: Vec<&[u8]> = b"\r\n+QIURC: \"recv\",1\r\n\r\n> ".chunks(1).collect();
+        assert_eq!(awaited(&reads).0, [Some("+QIURC: \"recv\",1".into()), None]);
+        // the echo of a CR-only command runs straight into the prompt
+        assert_eq!(awaited(&[b"AT+CMGS=\"1\"\r", b">", b" "]).0, [Some("AT+CMGS=\"1\"".into()), None]);
+    }
+
+    #[test]
+    fn gt_in_payload_is_not_a_prompt() {
+        // a '>' that is not "> " at the start of a line
+        for line in ["<html>", "a > b", ">>", ">", ">\t", "-> x", "<p>> </p>"] {
+            let answer = format!("{line}\r\n");
+            assert_eq!(awaited(&[answer.as_bytes()]).0, [Some(line.to_string())], "{line:?}");
+        }
+        // a lone '>' at the end of a read waits for the byte after it
+        assert_eq!(awaited(&[b"\r\n>"]).0, []);
+        assert_eq!(awaited(&[b"\r\n>", b"x\r\n"]).0, [Some(">x".into())]);
+
+        // once the prompt is taken, the data read as lines keeps its '>'
+        let got = lines(&[b"+QIRD: 12\r\n> quoted\r\n", b"<a>\r\nOK\r\n"]);
+        assert_eq!(got[1], ("> quoted".into(), Kind::Data));
+        assert_eq!(got[2], ("<a>".into(), Kind::Data));
+        assert_eq!(got[3], ("OK".into(), Kind::Success));
+    }
+}
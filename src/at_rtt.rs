@@ -0,0 +1,155 @@
+// AT 指令往返时间
+//
+// Each periodic ping starts with a bare "AT", timed from the write to the
+// final OK. A failing module often answers more and more slowly before it
+// stops answering. The average is an EWMA giving the new sample a weight
+// of 1/8, as TCP does for its smoothed RTT. It is kept in 1/8 ms, so a
+// change of a few ms is not rounded away. The max covers the last hour in
+// SLOTS slots of SLOT_MS each: a spike ages out at most one slot late.
+
+pub const SLOT_MS: u64 = 10 * 60_000;
+const SLOTS: usize = 6;
+// weight of a new sample: 1 / (1 << SHIFT)
+const SHIFT: u32 = 3;
+const HALF: u32 = 1 << (SHIFT - 1);
+
+pub struct Rtt {
+    // EWMA << SHIFT; None before the first sample
+    scaled: Option<u32>,
+    last_ms: u32,
+    count: u32,
+    // (slot number since boot, max in it)
+    slots: [(u64, u32); SLOTS],
+}
+
+impl Rtt {
+    pub const fn new() -> Self {
+        Self {
+            scaled: None,
+            last_ms: 0,
+            count: 0,
+            slots: [(0, 0); SLOTS],
+        }
+    }
+
+    pub fn record(&mut self, ms: u32, now_ms: u64) {
+        // avg += (ms - avg) / 8, in units of 1/8 ms; the decay is rounded,
+        // or a steady input would settle up to 7/8 ms above itself
+        self.scaled = Some(match self.scaled {
+            None => ms.saturating_mul(1 << SHIFT),
+            Some(scaled) => (scaled - (scaled.saturating_add(HALF) >> SHIFT)).saturating_add(ms),
+        });
+        self.last_ms = ms;
+        self.count += 1;
+
+        let slot = now_ms / SLOT_MS;
+        let entry = &mut self.slots[(slot % SLOTS as u64) as usize];
+        if entry.0 == slot {
+            entry.1 = entry.1.max(ms);
+        } else {
+            *entry = (slot, ms);
+        }
+    }
+
+    // Rounded to the nearest ms
+    pub fn average_ms(&self) -> Option<u32> {
+        self.scaled.map(|scaled| scaled.saturating_add(HALF) >> SHIFT)
+    }
+
+    pub fn last_ms(&self) -> u32 {
+        self.last_ms
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    // Over the last hour; 0 when nothing was measured in it
+    pub fn max_ms(&self, now_ms: u64) -> u32 {
+        let slot = now_ms / SLOT_MS;
+        self.slots
+            .iter()
+            .filter(|(s, _)| slot - s < SLOTS as u64)
+            .map(|&(_, max)| max)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steady(rtt: &mut Rtt, ms: u32, times: usize) {
+        for _ in 0..times {
+            rtt.record(ms, 0);
+        }
+    }
+
+    #[test]
+    fn first_sample_is_the_average() {
+        let mut rtt = Rtt::new();
+        assert_eq!(rtt.average_ms(), None);
+        rtt.record(137, 0);
+        assert_eq!(rtt.average_ms(), Some(137));
+        assert_eq!((rtt.last_ms(), rtt.count()), (137, 1));
+    }
+
+    #[test]
+    fn new_sample_weighs_one_eighth() {
+        let mut rtt = Rtt::new();
+        rtt.record(100, 0);
+        rtt.record(180, 0);
+        assert_eq!(rtt.average_ms(), Some(110));
+        rtt.record(30, 0);
+        // 110 - 110 / 8 + 30 / 8 = 100
+        assert_eq!(rtt.average_ms(), Some(100));
+    }
+
+    // Both ways the average ends on the input itself, not 7/8 ms off it
+    #[test]
+    fn steady_input_settles_on_itself() {
+        for (from, to) in [(0, 100), (100, 0), (40, 41), (41, 40), (5, 2000)] {
+            let mut rtt = Rtt::new();
+            steady(&mut rtt, from, 1);
+            steady(&mut rtt, to, 200);
+            assert_eq!(rtt.average_ms(), Some(to), "{from} -> {to}");
+        }
+    }
+
+    #[test]
+    fn small_changes_are_not_rounded_away() {
+        // 10 then 14: 10 + 4/8 rounds up to 11 when kept in 1/8 ms
+        let mut rtt = Rtt::new();
+        rtt.record(10, 0);
+        rtt.record(14, 0);
+        assert_eq!(rtt.average_ms(), Some(11));
+        // 10 then 13: 10 + 3/8 stays at 10
+        let mut rtt = Rtt::new();
+        rtt.record(10, 0);
+        rtt.record(13, 0);
+        assert_eq!(rtt.average_ms(), Some(10));
+        // but the 3/8 is kept, so the next 13 moves it
+        rtt.record(13, 0);
+        assert_eq!(rtt.average_ms(), Some(11));
+    }
+
+    #[test]
+    fn huge_samples_saturate() {
+        let mut rtt = Rtt::new();
+        steady(&mut rtt, u32::MAX, 3);
+        assert!(rtt.average_ms().unwrap() >= u32::MAX >> SHIFT);
+    }
+
+    #[test]
+    fn max_covers_the_last_hour() {
+        let mut rtt = Rtt::new();
+        rtt.record(900, 0);
+        rtt.record(50, SLOT_MS);
+        assert_eq!(rtt.max_ms(SLOT_MS), 900);
+        // the 900 ms spike was in slot 0, gone once slot 6 begins
+        assert_eq!(rtt.max_ms(SLOTS as u64 * SLOT_MS - 1), 900);
+        assert_eq!(rtt.max_ms(SLOTS as u64 * SLOT_MS), 50);
+        assert_eq!(rtt.max_ms((SLOTS as u64 + 1) * SLOT_MS), 0);
+    }
+}
@@ -0,0 +1,44 @@
+// cyw43 固件校验: 启动时核对内嵌的两个 blob
+//
+// build.rs records the length and CRC-32 of 43439A0.bin and
+// 43439A0_clm.bin as they were built in. At boot the copies in flash are
+// checked against those numbers before the chip gets them. A bad blob
+// hangs cyw43::new or leaves the radio flaky, so on a mismatch the WiFi
+// bring-up is skipped. The modem and the debug UART keep running, so the
+// unit can still be reached and reflashed. /api/version lists both blobs.
+
+use crate::deflate;
+
+#[derive(Clone, Copy)]
+pub struct Blob {
+    pub name: &'static str,
+    pub len: usize,
+    pub crc: u32,
+    pub expected_len: usize,
+    pub expected_crc: u32,
+}
+
+impl Blob {
+    pub fn firmware(data: &[u8]) -> Self {
+        Self::check("43439A0.bin", data, env!("CYW43_FIRMWARE_LEN"), env!("CYW43_FIRMWARE_CRC"))
+    }
+
+    pub fn clm(data: &[u8]) -> Self {
+        Self::check("43439A0_clm.bin", data, env!("CYW43_CLM_LEN"), env!("CYW43_CLM_CRC"))
+    }
+
+    // `expected_len` decimal, `expected_crc` hex, as build.rs writes them
+    fn check(name: &'static str, data: &[u8], expected_len: &str, expected_crc: &str) -> Self {
+        Self {
+            name,
+            len: data.len(),
+            crc: deflate::crc32_update(0, data),
+            expected_len: expected_len.parse().unwrap_or(0),
+            expected_crc: u32::from_str_radix(expected_crc, 16).unwrap_or(0),
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.len == self.expected_len && self.crc == self.expected_crc
+    }
+}
@@ -0,0 +1,321 @@
+// 板级接线: 每个 GPIO 接的是什么
+//
+// Every pin the firmware drives or keeps for itself comes from the active
+// BoardConfig. The default profile is a Pico 2 W wired to an EC800K
+// breakout by hand. The `board-carrier` feature selects the carrier board,
+// which routes the modem UART to GP0/GP1 with RTS/CTS and wires the
+// module's control lines. The radio pins are fixed by the Pico 2 W module
+// and are the same in both profiles. The HAL needs typed pins, so the
+// macros below take them out of `embassy_rp::Peripherals`. Each macro sits
+// next to the profile it mirrors, with the GPIO numbers it names listed
+// beside it; a const check fails the build when those and the active
+// profile disagree. Another const check rejects either profile when two
+// functions claim the same GPIO. RESET, DTR, RI, the status LED
+// and I2C are only kept away from triggers and the wake button; the
+// firmware does not drive them.
+
+pub struct BoardConfig {
+    pub name: &'static str,
+    // UART0 to the modem: our TX goes to the module's RX
+    pub modem_tx: u8,
+    pub modem_rx: u8,
+    pub modem_rts: Option<u8>,
+    pub modem_cts: Option<u8>,
+    // module control lines, None when not wired; PWRKEY is also the
+    // default for modem.pwrkey_gpio
+    pub pwrkey: Option<u8>,
+    pub reset: Option<u8>,
+    pub dtr: Option<u8>,
+    pub ri: Option<u8>,
+    pub status_led: Option<u8>,
+    // (SDA, SCL)
+    pub i2c: Option<(u8, u8)>,
+    // UART1, the sniffing port
+    pub debug_tx: u8,
+    pub debug_rx: u8,
+    // held low at power-up: recovery mode
+    pub recovery: u8,
+    // CYW43439: power, chip select, data, clock
+    pub radio: [u8; 4],
+}
+
+// PWRKEY default when the profile does not wire it
+const DEFAULT_PWRKEY_GPIO: u8 = 14;
+// GP0-GP29 on the RP2350A
+const GPIO_COUNT: u8 = 30;
+const RADIO: [u8; 4] = [23, 25, 24, 29];
+
+pub const PICO2W: BoardConfig = BoardConfig {
+    name: "pico2w",
+    modem_tx: 12,
+    modem_rx: 13,
+    modem_rts: None,
+    modem_cts: None,
+    pwrkey: None,
+    reset: None,
+    dtr: None,
+    ri: None,
+    status_led: None,
+    i2c: None,
+    debug_tx: 4,
+    debug_rx: 5,
+    recovery: 22,
+    radio: RADIO,
+};
+
+pub const CARRIER: BoardConfig = BoardConfig {
+    name: "carrier",
+    modem_tx: 0,
+    modem_rx: 1,
+    modem_rts: Some(3),
+    modem_cts: Some(2),
+    pwrkey: Some(6),
+    reset: Some(7),
+    dtr: Some(8),
+    ri: Some(9),
+    status_led: Some(15),
+    i2c: Some((16, 17)),
+    debug_tx: 4,
+    debug_rx: 5,
+    recovery: 22,
+    radio: RADIO,
+};
+
+#[cfg(not(feature = "board-carrier"))]
+pub const ACTIVE: BoardConfig = PICO2W;
+#[cfg(feature = "board-carrier")]
+pub const ACTIVE: BoardConfig = CARRIER;
+
+// 两个配置都检查, 不只是选中的那个
+const _: () = assert!(PICO2W.distinct(), "two functions claim the same GPIO in the pico2w profile");
+const _: () = assert!(CARRIER.distinct(), "two functions claim the same GPIO in the carrier profile");
+
+impl BoardConfig {
+    // Every claimed pin, None for lines that are not wired
+    const fn claims(&self) -> [Option<u8>; 18] {
+        let (sda, scl) = match self.i2c {
+            Some((sda, scl)) => (Some(sda), Some(scl)),
+            None => (None, None),
+        };
+        [
+            Some(self.modem_tx),
+            Some(self.modem_rx),
+            self.modem_rts,
+            self.modem_cts,
+            self.pwrkey,
+            self.reset,
+            self.dtr,
+            self.ri,
+            self.status_led,
+            sda,
+            scl,
+            Some(self.debug_tx),
+            Some(self.debug_rx),
+            Some(self.recovery),
+            Some(self.radio[0]),
+            Some(self.radio[1]),
+            Some(self.radio[2]),
+            Some(self.radio[3]),
+        ]
+    }
+
+    const fn distinct(&self) -> bool {
+        let claims = self.claims();
+        let mut i = 0;
+        while i < claims.len() {
+            if let Some(a) = claims[i] {
+                if a >= GPIO_COUNT {
+                    return false;
+                }
+                let mut j = i + 1;
+                while j < claims.len() {
+                    if let Some(b) = claims[j]
+                        && a == b
+                    {
+                        return false;
+                    }
+                    j += 1;
+                }
+            }
+            i += 1;
+        }
+        true
+    }
+
+    // A pin triggers and the wake button may use
+    pub const fn is_free(&self, gpio: u8) -> bool {
+        if gpio >= GPIO_COUNT {
+            return false;
+        }
+        let claims = self.claims();
+        let mut i = 0;
+        while i < claims.len() {
+            if let Some(claimed) = claims[i]
+                && claimed == gpio
+            {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    // The profile's PWRKEY, or any free pin
+    pub const fn pwrkey_allowed(&self, gpio: u8) -> bool {
+        matches!(self.pwrkey, Some(pwrkey) if pwrkey == gpio) || self.is_free(gpio)
+    }
+
+    pub const fn default_pwrkey_gpio(&self) -> u8 {
+        match self.pwrkey {
+            Some(gpio) => gpio,
+            None => DEFAULT_PWRKEY_GPIO,
+        }
+    }
+
+    // The pins typed drivers take, in the order of TYPED_PINS
+    const fn typed(&self) -> [Option<u8>; 11] {
+        [
+            Some(self.modem_tx),
+            Some(self.modem_rx),
+            self.modem_rts,
+            self.modem_cts,
+            Some(self.debug_tx),
+            Some(self.debug_rx),
+            Some(self.recovery),
+            Some(self.radio[0]),
+            Some(self.radio[1]),
+            Some(self.radio[2]),
+            Some(self.radio[3]),
+        ]
+    }
+}
+
+const fn same_pins(a: &[Option<u8>; 11], b: &[Option<u8>; 11]) -> bool {
+    let mut i = 0;
+    while i < a.len() {
+        match (a[i], b[i]) {
+            (Some(x), Some(y)) if x == y => {}
+            (None, None) => {}
+            _ => return false,
+        }
+        i += 1;
+    }
+    true
+}
+
+// gpio_pins! gives out every GP0-GP28 except the typed ones
+const fn gpio_pins_leave_out_typed(left_out: &[u8], typed: &[Option<u8>; 11]) -> bool {
+    let mut gpio = 0;
+    while gpio < 29 {
+        let mut is_left_out = false;
+        let mut i = 0;
+        while i < left_out.len() {
+            is_left_out |= left_out[i] == gpio;
+            i += 1;
+        }
+        let mut is_typed = false;
+        let mut i = 0;
+        while i < typed.len() {
+            is_typed |= matches!(typed[i], Some(t) if t == gpio);
+            i += 1;
+        }
+        if is_left_out != is_typed {
+            return false;
+        }
+        gpio += 1;
+    }
+    true
+}
+
+// The GPIO numbers the macros below name: modem TX, RX, RTS, CTS (modem_uart!),
+// debug TX, RX (debug_uart_pins!), recovery (recovery_pin!), radio power,
+// chip select, data, clock (radio_pins!); and the slots gpio_pins! leaves None
+#[cfg(not(feature = "board-carrier"))]
+const TYPED_PINS: [Option<u8>; 11] =
+    [Some(12), Some(13), None, None, Some(4), Some(5), Some(22), Some(23), Some(25), Some(24), Some(29)];
+#[cfg(not(feature = "board-carrier"))]
+const GPIO_PINS_LEFT_OUT: &[u8] = &[4, 5, 12, 13, 22, 23, 24, 25];
+#[cfg(feature = "board-carrier")]
+const TYPED_PINS: [Option<u8>; 11] =
+    [Some(0), Some(1), Some(3), Some(2), Some(4), Some(5), Some(22), Some(23), Some(25), Some(24), Some(29)];
+#[cfg(feature = "board-carrier")]
+const GPIO_PINS_LEFT_OUT: &[u8] = &[0, 1, 2, 3, 4, 5, 22, 23, 24, 25];
+
+// 改了 ACTIVE 的接线而没有改下面的宏: 编译失败
+const _: () = assert!(same_pins(&TYPED_PINS, &ACTIVE.typed()), "the pin macros disagree with board::ACTIVE");
+const _: () = assert!(
+    gpio_pins_leave_out_typed(GPIO_PINS_LEFT_OUT, &TYPED_PINS),
+    "gpio_pins! must leave out exactly the typed pins"
+);
+
+// UART0 to the modem, hardware flow control when the profile wires it;
+// not taken with sim-modem
+#[cfg(not(feature = "board-carrier"))]
+#[cfg_attr(feature = "sim-modem", allow(unused_macros))]
+macro_rules! modem_uart {
+    ($p:ident, $irqs:expr, $tx_buf:expr, $rx_buf:expr, $config:expr) => {
+        BufferedUart::new($p.UART0, $p.PIN_12, $p.PIN_13, $irqs, $tx_buf, $rx_buf, $config)
+    };
+}
+
+#[cfg(feature = "board-carrier")]
+#[cfg_attr(feature = "sim-modem", allow(unused_macros))]
+macro_rules! modem_uart {
+    ($p:ident, $irqs:expr, $tx_buf:expr, $rx_buf:expr, $config:expr) => {
+        BufferedUart::new_with_rtscts($p.UART0, $p.PIN_0, $p.PIN_1, $p.PIN_3, $p.PIN_2, $irqs, $tx_buf, $rx_buf, $config)
+    };
+}
+
+// GP0-GP28 by number, None where a typed driver takes the pin (the UARTs,
+// the recovery input, the radio)
+#[cfg(not(feature = "board-carrier"))]
+macro_rules! gpio_pins {
+    ($p:ident) => {
+        [
+            Some($p.PIN_0.into()), Some($p.PIN_1.into()), Some($p.PIN_2.into()), Some($p.PIN_3.into()),
+            None, None, Some($p.PIN_6.into()), Some($p.PIN_7.into()),
+            Some($p.PIN_8.into()), Some($p.PIN_9.into()), Some($p.PIN_10.into()), Some($p.PIN_11.into()),
+            None, None, Some($p.PIN_14.into()), Some($p.PIN_15.into()),
+            Some($p.PIN_16.into()), Some($p.PIN_17.into()), Some($p.PIN_18.into()), Some($p.PIN_19.into()),
+            Some($p.PIN_20.into()), Some($p.PIN_21.into()), None, None,
+            None, None, Some($p.PIN_26.into()), Some($p.PIN_27.into()),
+            Some($p.PIN_28.into()),
+        ]
+    };
+}
+
+#[cfg(feature = "board-carrier")]
+macro_rules! gpio_pins {
+    ($p:ident) => {
+        [
+            None, None, None, None,
+            None, None, Some($p.PIN_6.into()), Some($p.PIN_7.into()),
+            Some($p.PIN_8.into()), Some($p.PIN_9.into()), Some($p.PIN_10.into()), Some($p.PIN_11.into()),
+            Some($p.PIN_12.into()), Some($p.PIN_13.into()), Some($p.PIN_14.into()), Some($p.PIN_15.into()),
+            Some($p.PIN_16.into()), Some($p.PIN_17.into()), Some($p.PIN_18.into()), Some($p.PIN_19.into()),
+            Some($p.PIN_20.into()), Some($p.PIN_21.into()), None, None,
+            None, None, Some($p.PIN_26.into()), Some($p.PIN_27.into()),
+            Some($p.PIN_28.into()),
+        ]
+    };
+}
+
+// UART1 (TX, RX); the same in both profiles
+macro_rules! debug_uart_pins {
+    ($p:ident) => {
+        ($p.PIN_4, $p.PIN_5)
+    };
+}
+
+macro_rules! recovery_pin {
+    ($p:ident) => {
+        $p.PIN_22
+    };
+}
+
+// (power, chip select, data, clock), fixed by the Pico 2 W module
+macro_rules! radio_pins {
+    ($p:ident) => {
+        ($p.PIN_23, $p.PIN_25, $p.PIN_24, $p.PIN_29)
+    };
+}
@@ -0,0 +1,132 @@
+// 启动阶段记录
+//
+// Each boot step is recorded with its start time and duration so the status
+// page can show how far startup got and which step failed or is hanging.
+// Times are milliseconds since boot.
+//
+// Each task main spawns is recorded as well, one entry per task name with
+// how many instances started and how many did not. A task that does not
+// start is left out and the rest keep running. When it is one of the
+// critical ones (the modem UART, the radio and the network stack),
+// /healthz reports it.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Running,
+    Done,
+    Failed(&'static str),
+}
+
+#[derive(Clone, Copy)]
+pub struct Stage {
+    pub name: &'static str,
+    pub started_ms: u32,
+    pub duration_ms: u32,
+    pub outcome: Outcome,
+}
+
+pub struct BootLog<const N: usize> {
+    stages: heapless::Vec<Stage, N>,
+}
+
+impl<const N: usize> BootLog<N> {
+    pub const fn new() -> Self {
+        Self {
+            stages: heapless::Vec::new(),
+        }
+    }
+
+    // Returns the stage index, None if the log is full
+    pub fn begin(&mut self, name: &'static str, now_ms: u32) -> Option<usize> {
+        self.stages
+            .push(Stage {
+                name,
+                started_ms: now_ms,
+                duration_ms: 0,
+                outcome: Outcome::Running,
+            })
+            .ok()?;
+        Some(self.stages.len() - 1)
+    }
+
+    pub fn end(&mut self, index: usize, outcome: Outcome, now_ms: u32) -> Option<Stage> {
+        let stage = self.stages.get_mut(index)?;
+        stage.duration_ms = now_ms.saturating_sub(stage.started_ms);
+        stage.outcome = outcome;
+        Some(*stage)
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    pub fn running(&self) -> Option<&Stage> {
+        self.stages.iter().find(|s| s.outcome == Outcome::Running)
+    }
+
+    pub fn failed(&self) -> Option<&Stage> {
+        self.stages.iter().find(|s| matches!(s.outcome, Outcome::Failed(_)))
+    }
+}
+
+// Why a task did not start
+pub const POOL_FULL: &str = "task pool full";
+
+#[derive(Clone, Copy)]
+pub struct Task {
+    pub name: &'static str,
+    pub critical: bool,
+    pub started: u8,
+    pub failed: u8,
+    // the last failure
+    pub error: Option<&'static str>,
+}
+
+pub struct Tasks<const N: usize> {
+    tasks: heapless::Vec<Task, N>,
+}
+
+impl<const N: usize> Tasks<N> {
+    pub const fn new() -> Self {
+        Self { tasks: heapless::Vec::new() }
+    }
+
+    pub fn record(&mut self, name: &'static str, critical: bool, result: Result<(), &'static str>) {
+        let index = match self.tasks.iter().position(|t| t.name == name) {
+            Some(index) => index,
+            None => {
+                let task = Task {
+                    name,
+                    critical,
+                    started: 0,
+                    failed: 0,
+                    error: None,
+                };
+                if self.tasks.push(task).is_err() {
+                    return;
+                }
+                self.tasks.len() - 1
+            }
+        };
+        let task = &mut self.tasks[index];
+        match result {
+            Ok(()) => task.started = task.started.saturating_add(1),
+            Err(reason) => {
+                task.failed = task.failed.saturating_add(1);
+                task.error = Some(reason);
+            }
+        }
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    pub fn failed(&self) -> usize {
+        self.tasks.iter().filter(|t| t.failed > 0).count()
+    }
+
+    pub fn critical_failed(&self) -> Option<&Task> {
+        self.tasks.iter().find(|t| t.critical && t.failed > 0)
+    }
+}
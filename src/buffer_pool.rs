@@ -0,0 +1,75 @@
+// 套接字收发缓冲区池
+//
+// A static arena of N rx/tx buffer pairs. Every listener leases a pair per
+// socket, so total networking RAM is N * (RX + TX) no matter how many
+// accept loops run. Occupancy is a bitmask, hence N <= 32.
+
+use core::cell::UnsafeCell;
+use portable_atomic::{AtomicU32, Ordering};
+
+pub struct BufferPool<const N: usize, const RX: usize, const TX: usize> {
+    slots: [UnsafeCell<([u8; RX], [u8; TX])>; N],
+    taken: AtomicU32,
+}
+
+// 每个槽位同一时间只会被一个 Lease 持有
+unsafe impl<const N: usize, const RX: usize, const TX: usize> Sync for BufferPool<N, RX, TX> {}
+
+impl<const N: usize, const RX: usize, const TX: usize> BufferPool<N, RX, TX> {
+    pub const fn new() -> Self {
+        assert!(N <= 32);
+        Self {
+            slots: [const { UnsafeCell::new(([0; RX], [0; TX])) }; N],
+            taken: AtomicU32::new(0),
+        }
+    }
+
+    pub fn take(&'static self) -> Option<Lease<RX, TX>> {
+        let mut taken = self.taken.load(Ordering::Acquire);
+        loop {
+            let index = (!taken).trailing_zeros() as usize;
+            if index >= N {
+                return None;
+            }
+            let bit = 1u32 << index;
+            match self
+                .taken
+                .compare_exchange_weak(taken, taken | bit, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    // SAFETY: the bit we just set gives exclusive access to this slot
+                    let (rx, tx) = unsafe { &mut *self.slots[index].get() };
+                    return Some(Lease {
+                        rx,
+                        tx,
+                        taken: &self.taken,
+                        bit,
+                    });
+                }
+                Err(current) => taken = current,
+            }
+        }
+    }
+
+    pub fn in_use(&self) -> u32 {
+        self.taken.load(Ordering::Relaxed).count_ones()
+    }
+
+    pub const fn capacity(&self) -> u32 {
+        N as u32
+    }
+}
+
+// Buffers go back to the pool when the lease is dropped
+pub struct Lease<const RX: usize, const TX: usize> {
+    pub rx: &'static mut [u8; RX],
+    pub tx: &'static mut [u8; TX],
+    taken: &'static AtomicU32,
+    bit: u32,
+}
+
+impl<const RX: usize, const TX: usize> Drop for Lease<RX, TX> {
+    fn drop(&mut self) {
+        self.taken.fetch_and(!self.bit, Ordering::Release);
+    }
+}
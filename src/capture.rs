@@ -0,0 +1,231 @@
+// 串口二进制抓包 (/capture.bin)
+//
+// While active, every UART chunk is appended to a fixed RAM buffer together
+// with its direction and a timestamp, so timing and non-text bytes survive.
+// Once a chunk does not fit, it and everything after it is only counted,
+// and a single Dropped record marks the spot; the UART never waits on the
+// capture.
+//
+// capture.bin, integers little-endian (tools/capture_decode.rs reads it):
+//
+//   header  "MCAP", version u16 = 1, reserved u16, started_ms u32,
+//           dropped_chunks u32, dropped_bytes u32              (20 bytes)
+//   record  kind u8 (0 tx, 1 rx, 2 dropped), len u16, time_ms u32,
+//           payload[len]                                       (7 + len)
+//
+// started_ms counts from boot, time_ms from the start of the capture.
+
+use crate::modem_log::Direction;
+use crate::rings::{Ring, Usage};
+
+pub const HEADER_LEN: usize = 20;
+pub const RECORD_HEADER_LEN: usize = 7;
+const MAGIC: &[u8; 4] = b"MCAP";
+const VERSION: u16 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Tx = 0,
+    Rx = 1,
+    Dropped = 2,
+}
+
+pub struct Capture<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    active: bool,
+    started_ms: u32,
+    dropped_chunks: u32,
+    dropped_bytes: u32,
+}
+
+impl<const N: usize> Capture<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            active: false,
+            started_ms: 0,
+            dropped_chunks: 0,
+            dropped_bytes: 0,
+        }
+    }
+
+    // Discards the previous capture
+    pub fn start(&mut self, now_ms: u32) {
+        self.len = 0;
+        self.started_ms = now_ms;
+        self.dropped_chunks = 0;
+        self.dropped_bytes = 0;
+        self.active = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.active = false;
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    // Bytes of records, without the file header
+    pub fn recorded(&self) -> usize {
+        self.len
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn dropped_chunks(&self) -> u32 {
+        self.dropped_chunks
+    }
+
+    pub fn record(&mut self, direction: Direction, data: &[u8], now_ms: u32) {
+        if !self.active || data.is_empty() {
+            return;
+        }
+        let time_ms = now_ms.wrapping_sub(self.started_ms);
+        let kind = match direction {
+            Direction::Tx => Kind::Tx,
+            Direction::Rx => Kind::Rx,
+        };
+
+        // 总是给丢弃标记留出一个记录头的位置
+        let fits = self.len + 2 * RECORD_HEADER_LEN + data.len() <= N;
+        if self.dropped_chunks == 0 && fits && data.len() <= u16::MAX as usize {
+            self.append(kind, data, time_ms);
+            return;
+        }
+        if self.dropped_chunks == 0 {
+            self.append(Kind::Dropped, &[], time_ms);
+        }
+        self.dropped_chunks += 1;
+        self.dropped_bytes = self.dropped_bytes.saturating_add(data.len() as u32);
+    }
+
+    fn append(&mut self, kind: Kind, data: &[u8], time_ms: u32) {
+        let start = self.len;
+        self.buf[start] = kind as u8;
+        self.buf[start + 1..start + 3].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        self.buf[start + 3..start + 7].copy_from_slice(&time_ms.to_le_bytes());
+        self.buf[start + RECORD_HEADER_LEN..start + RECORD_HEADER_LEN + data.len()].copy_from_slice(data);
+        self.len += RECORD_HEADER_LEN + data.len();
+    }
+
+    pub fn header(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[8..12].copy_from_slice(&self.started_ms.to_le_bytes());
+        header[12..16].copy_from_slice(&self.dropped_chunks.to_le_bytes());
+        header[16..20].copy_from_slice(&self.dropped_bytes.to_le_bytes());
+        header
+    }
+
+    // Copies record bytes starting at `offset`, returns how many were copied
+    pub fn read_at(&self, offset: usize, out: &mut [u8]) -> usize {
+        let available = &self.buf[offset.min(self.len)..self.len];
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        n
+    }
+}
+
+impl<const N: usize> Ring for Capture<N> {
+    // A capture stops taking chunks when full instead of wrapping
+    fn usage(&self) -> Usage {
+        Usage {
+            capacity: N,
+            used: self.len,
+            written: self.len as u32,
+            dropped: self.dropped_bytes,
+            wraps: 0,
+        }
+    }
+
+    // A running capture goes on from an empty buffer
+    fn reset(&mut self) {
+        self.len = 0;
+        self.dropped_chunks = 0;
+        self.dropped_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture_decode::{self as decode, parse_header, parse_records};
+
+    // capture.bin as /capture.bin sends it
+    fn capture_file<const N: usize>(capture: &Capture<N>) -> Vec<u8> {
+        let mut out = capture.header().to_vec();
+        let mut records = vec![0u8; capture.recorded()];
+        assert_eq!(capture.read_at(0, &mut records), records.len());
+        out.extend(records);
+        out
+    }
+
+    #[test]
+    fn decoder_reads_back_what_was_captured() {
+        let mut capture = Capture::<256>::new();
+        capture.start(5000);
+        capture.record(Direction::Tx, b"AT+CSQ\r\n", 5001);
+        capture.record(Direction::Rx, b"\r\n+CSQ: 23,99\r\n\r\nOK\r\n", 5012);
+        capture.record(Direction::Rx, &[0x00, 0xff, 0x1a], 5013);
+        // empty chunks are not recorded
+        capture.record(Direction::Rx, b"", 5014);
+
+        let file = capture_file(&capture);
+        let header = parse_header(&file).unwrap();
+        assert_eq!((header.version, header.started_ms), (1, 5000));
+        assert_eq!((header.dropped_chunks, header.dropped_bytes), (0, 0));
+        let (records, error) = parse_records(&file[HEADER_LEN..]);
+        assert!(error.is_none());
+        let got: Vec<_> = records.iter().map(|r| (r.kind, r.time_ms, r.payload)).collect();
+        assert_eq!(
+            got,
+            [
+                (decode::Kind::Tx, 1, &b"AT+CSQ\r\n"[..]),
+                (decode::Kind::Rx, 12, &b"\r\n+CSQ: 23,99\r\n\r\nOK\r\n"[..]),
+                (decode::Kind::Rx, 13, &[0x00, 0xff, 0x1a][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn full_buffer_leaves_one_dropped_marker() {
+        let mut capture = Capture::<40>::new();
+        capture.start(0);
+        capture.record(Direction::Tx, &[b'a'; 20], 1);
+        // 27 + 7 + 7 > 40: dropped, and so is everything after it
+        capture.record(Direction::Rx, b"1234567", 2);
+        capture.record(Direction::Rx, b"x", 3);
+        assert_eq!(capture.recorded(), 27 + RECORD_HEADER_LEN);
+        assert_eq!(capture.dropped_chunks(), 2);
+
+        let file = capture_file(&capture);
+        let header = parse_header(&file).unwrap();
+        assert_eq!((header.dropped_chunks, header.dropped_bytes), (2, 8));
+        let (records, error) = parse_records(&file[HEADER_LEN..]);
+        assert!(error.is_none());
+        let kinds: Vec<_> = records.iter().map(|r| (r.kind, r.time_ms, r.payload.len())).collect();
+        assert_eq!(kinds, [(decode::Kind::Tx, 1, 20), (decode::Kind::Dropped, 2, 0)]);
+
+        // a new capture starts empty
+        capture.start(100);
+        capture.record(Direction::Rx, b"x", 150);
+        let file = capture_file(&capture);
+        assert_eq!(parse_header(&file).unwrap().dropped_chunks, 0);
+        assert_eq!(parse_records(&file[HEADER_LEN..]).0[0].time_ms, 50);
+    }
+
+    #[test]
+    fn time_wraps_from_the_start() {
+        let mut capture = Capture::<64>::new();
+        capture.start(u32::MAX - 9);
+        capture.record(Direction::Rx, b"x", 10);
+        let file = capture_file(&capture);
+        assert_eq!(parse_records(&file[HEADER_LEN..]).0[0].time_ms, 20);
+    }
+}
@@ -0,0 +1,1169 @@
+// 运行时配置 (编译时默认值, 可从闪存覆盖)
+//
+// Every setting is a u32 or a short string addressed by a dotted path
+// such as "tcp.timeout_ms"; the same paths are the keys of the exported
+// JSON document and of the copy kept in flash.
+
+use core::fmt::Write as _;
+
+use crate::actions;
+use crate::arp;
+use crate::at;
+use crate::board;
+#[cfg(feature = "proxy")]
+use crate::forward;
+#[cfg(feature = "gnss")]
+use crate::geofence;
+use crate::health;
+use crate::http;
+use crate::identity;
+use crate::json;
+use crate::listener;
+use crate::log_level;
+use crate::rate_limit;
+use crate::schedule;
+use crate::sim;
+use crate::socket_budget;
+use crate::trigger;
+use crate::webhook;
+
+#[derive(Clone, Copy)]
+pub struct TcpSettings {
+    // smoltcp keepalive probe interval on accepted sockets
+    pub keepalive_ms: u32,
+    // no ACK from the peer for this long aborts the socket
+    pub timeout_ms: u32,
+    // after the response, how long the peer gets to close its side
+    // before the connection is treated as half-open and reaped
+    pub idle_close_ms: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct HttpSettings {
+    // where the pages are served
+    pub port: u32,
+    // second listener, 0 = none
+    pub port2: u32,
+    pub role2: listener::Role,
+    // an async lock held longer is logged (lock_stats)
+    pub lock_budget_ms: u32,
+}
+
+#[derive(Clone)]
+pub struct WebhookSettings {
+    // http:// only; empty turns notifications off
+    pub url: heapless::String<96>,
+    // webhook::Event bits
+    pub events: u32,
+}
+
+#[derive(Clone)]
+pub struct DeviceSettings {
+    // empty = identity::default_name
+    pub name: identity::Name,
+}
+
+#[derive(Clone)]
+pub struct SimSettings {
+    // submitted once at boot when the SIM asks for it; empty = never
+    pub pin: heapless::String<8>,
+}
+
+#[derive(Clone, Copy)]
+pub struct FetchSettings {
+    // cancel an interactive fetch nobody looked at for this long, 0 = never
+    pub abandon_s: u32,
+    // fetch::ReceiveMode::DirectPush instead of Buffered, for debugging
+    pub direct_push: bool,
+}
+
+#[derive(Clone)]
+pub struct KeepWarmSettings {
+    // minutes between keep-alive lookups, 0 = off
+    pub interval_min: u32,
+    // name resolved each time; empty = keep_warm::DEFAULT_HOST
+    pub host: heapless::String<64>,
+}
+
+#[derive(Clone, Copy)]
+pub struct RoamingSettings {
+    // refuse cellular data while registered as roaming
+    pub block_data: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct HealthSettings {
+    // health::Check bits that /healthz requires
+    pub checks: u32,
+    // for the recent_activity check
+    pub max_idle_min: u32,
+    // for the free_sockets check
+    pub min_free_sockets: u32,
+    // log a warning when a bare AT takes longer, 0 = never
+    pub at_rtt_warn_ms: u32,
+}
+
+// AP 无人使用时休眠 (ap_sleep)
+#[derive(Clone)]
+pub struct PowerSettings {
+    // minutes without a client before the AP stops, 0 = never
+    pub ap_idle_min: u32,
+    // a button to ground on wake_gpio brings the AP back
+    pub wake_button: bool,
+    pub wake_gpio: u8,
+    // "HH:MM", both empty = no wake window
+    pub wake_start: heapless::String<5>,
+    pub wake_end: heapless::String<5>,
+}
+
+impl PowerSettings {
+    // (start, end) minutes of the day
+    pub fn wake_window(&self) -> Option<(u32, u32)> {
+        let window = (schedule::parse_hhmm(&self.wake_start)?, schedule::parse_hhmm(&self.wake_end)?);
+        (window.0 != window.1).then_some(window)
+    }
+}
+
+// 调制解调器上电 (PWRKEY)
+#[derive(Clone, Copy)]
+pub struct ModemSettings {
+    // pulse PWRKEY at boot when the module does not answer
+    pub pwrkey: bool,
+    // high = key pressed, through the usual NPN that pulls PWRKEY low
+    pub pwrkey_gpio: u8,
+}
+
+// 每个来源允许的动作 (actions::Action bits)
+#[derive(Clone, Copy)]
+pub struct ActionSettings {
+    pub web: u32,
+    pub input: u32,
+}
+
+// AP 客户端的 ARP 表和静态绑定 (arp)
+#[derive(Clone)]
+pub struct ArpSettings {
+    // "ip=mac" pairs separated by spaces
+    pub pins: heapless::String<128>,
+    // log clients added, moved and forgotten at debug level
+    pub events: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct ServicesSettings {
+    // TCP echo (7) and discard (9) for connectivity checks
+    pub enabled: bool,
+}
+
+#[cfg(feature = "proxy")]
+#[derive(Clone, Copy)]
+pub struct ShaperSettings {
+    // bytes per second of forwarded payload each way, 0 = shaper::AUTO_PERCENT of the UART
+    pub rate: u32,
+}
+
+#[cfg(feature = "proxy")]
+#[derive(Clone, Copy)]
+pub struct PacSettings {
+    // forward rule (1-4) to an upstream HTTP proxy for /proxy.pac, 0 = none
+    pub forward: u32,
+}
+
+#[cfg(feature = "gnss")]
+#[derive(Clone, Copy)]
+pub struct GnssSettings {
+    // power the GNSS engine and poll its position (modules with GPS only)
+    pub enabled: bool,
+    pub interval_s: u32,
+}
+
+#[cfg(feature = "gnss")]
+#[derive(Clone)]
+pub struct GeofenceSettings {
+    // centre in decimal degrees, as typed
+    pub lat: heapless::String<12>,
+    pub lon: heapless::String<12>,
+    // 0 = no fence
+    pub radius_m: u32,
+    // SMS on leaving and coming back; empty = no SMS
+    pub sms: heapless::String<20>,
+}
+
+#[cfg(feature = "gnss")]
+impl GeofenceSettings {
+    // None when the fence is off
+    pub fn fence(&self) -> Option<geofence::Fence> {
+        if self.radius_m == 0 {
+            return None;
+        }
+        Some(geofence::Fence {
+            latitude: geofence::parse_degrees(&self.lat, 90.0)?,
+            longitude: geofence::parse_degrees(&self.lon, 180.0)?,
+            radius_m: self.radius_m,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ScheduleSettings {
+    // before the network time is known: run gated features anyway (true)
+    // or hold them until the clock is set
+    pub unsynced_open: bool,
+    pub windows: [schedule::Window; schedule::MAX_WINDOWS],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+impl Parity {
+    pub fn parse(text: &str) -> Option<Parity> {
+        match text {
+            "none" => Some(Parity::None),
+            "even" => Some(Parity::Even),
+            "odd" => Some(Parity::Odd),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Parity::None => "none",
+            Parity::Even => "even",
+            Parity::Odd => "odd",
+        }
+    }
+}
+
+// 串口参数只在启动时生效
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct UartSettings {
+    // 5-8
+    pub data_bits: u32,
+    pub parity: Parity,
+    // 1 or 2
+    pub stop_bits: u32,
+    // UART1 (board::ACTIVE.debug_tx/debug_rx) as a second, sniffing port (always 8N1)
+    pub debug_port: bool,
+    pub debug_baud: u32,
+    // expert mode: also drive UART1 TX and accept writes
+    pub debug_writes: bool,
+}
+
+impl UartSettings {
+    // 8N1, no debug port
+    pub const DEFAULT: UartSettings = UartSettings {
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+        debug_port: false,
+        debug_baud: 115_200,
+        debug_writes: false,
+    };
+
+    // The usual short form, "8N1"
+    pub fn write_framing<W: core::fmt::Write>(&self, out: &mut W) {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        let _ = core::write!(out, "{}{}{}", self.data_bits, parity, self.stop_bits);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LogSettings {
+    pub level: log_level::Level,
+    // minutes between log checkpoints to flash, 0 = off
+    pub persist_min: u32,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub rate_limit: rate_limit::Limits,
+    pub deadlines: http::Deadlines,
+    pub tcp: TcpSettings,
+    pub http: HttpSettings,
+    pub webhook: WebhookSettings,
+    pub device: DeviceSettings,
+    pub sim: SimSettings,
+    pub log: LogSettings,
+    pub fetch: FetchSettings,
+    pub uart: UartSettings,
+    pub keep_warm: KeepWarmSettings,
+    pub roaming: RoamingSettings,
+    pub services: ServicesSettings,
+    pub health: HealthSettings,
+    pub power: PowerSettings,
+    pub arp: ArpSettings,
+    pub modem: ModemSettings,
+    pub actions: ActionSettings,
+    #[cfg(feature = "proxy")]
+    pub forwards: [forward::Rule; forward::MAX_FORWARDS],
+    #[cfg(feature = "proxy")]
+    pub shaper: ShaperSettings,
+    #[cfg(feature = "proxy")]
+    pub pac: PacSettings,
+    pub schedule: ScheduleSettings,
+    #[cfg(feature = "gnss")]
+    pub gnss: GnssSettings,
+    #[cfg(feature = "gnss")]
+    pub geofence: GeofenceSettings,
+    pub triggers: [trigger::Binding; trigger::MAX_TRIGGERS],
+}
+
+impl Config {
+    pub const DEFAULT: Config = Config {
+        // 每个 IP 10 秒内最多 10 个请求, 最多 2 个并发连接
+        rate_limit: rate_limit::Limits {
+            burst: 10,
+            refill_ms: 1000,
+            max_connections: 2,
+            idle_expiry_ms: 60_000,
+        },
+        // 请求头 3 秒, 整个请求 8 秒, 响应写入每 5 秒至少要有进展
+        deadlines: http::Deadlines {
+            header_ms: 3_000,
+            request_ms: 8_000,
+            write_progress_ms: 5_000,
+        },
+        tcp: TcpSettings {
+            keepalive_ms: 10_000,
+            timeout_ms: 10_000,
+            idle_close_ms: 5_000,
+        },
+        http: HttpSettings {
+            port: 80,
+            port2: 0,
+            role2: listener::Role::Mirror,
+            lock_budget_ms: 5,
+        },
+        webhook: WebhookSettings {
+            url: heapless::String::new(),
+            events: webhook::ALL_EVENTS,
+        },
+        device: DeviceSettings {
+            name: heapless::String::new(),
+        },
+        sim: SimSettings {
+            pin: heapless::String::new(),
+        },
+        log: LogSettings {
+            level: log_level::Level::Info,
+            persist_min: 0,
+        },
+        fetch: FetchSettings {
+            abandon_s: 120,
+            direct_push: false,
+        },
+        uart: UartSettings::DEFAULT,
+        keep_warm: KeepWarmSettings {
+            interval_min: 0,
+            host: heapless::String::new(),
+        },
+        roaming: RoamingSettings { block_data: true },
+        services: ServicesSettings { enabled: true },
+        health: HealthSettings {
+            checks: health::DEFAULT_CHECKS,
+            max_idle_min: 60,
+            min_free_sockets: 1,
+            at_rtt_warn_ms: 500,
+        },
+        power: PowerSettings {
+            ap_idle_min: 0,
+            wake_button: false,
+            wake_gpio: 10,
+            wake_start: heapless::String::new(),
+            wake_end: heapless::String::new(),
+        },
+        arp: ArpSettings {
+            pins: heapless::String::new(),
+            events: false,
+        },
+        modem: ModemSettings {
+            pwrkey: board::ACTIVE.pwrkey.is_some(),
+            pwrkey_gpio: board::ACTIVE.default_pwrkey_gpio(),
+        },
+        actions: ActionSettings {
+            web: actions::Action::Fetch.bit() | actions::Action::Identify.bit(),
+            input: actions::ALL_ACTIONS,
+        },
+        #[cfg(feature = "proxy")]
+        forwards: [forward::Rule::DISABLED; forward::MAX_FORWARDS],
+        #[cfg(feature = "proxy")]
+        shaper: ShaperSettings { rate: 0 },
+        #[cfg(feature = "proxy")]
+        pac: PacSettings { forward: 0 },
+        schedule: ScheduleSettings {
+            unsynced_open: true,
+            windows: [schedule::Window::OFF; schedule::MAX_WINDOWS],
+        },
+        #[cfg(feature = "gnss")]
+        gnss: GnssSettings {
+            enabled: false,
+            interval_s: 30,
+        },
+        #[cfg(feature = "gnss")]
+        geofence: GeofenceSettings {
+            lat: heapless::String::new(),
+            lon: heapless::String::new(),
+            radius_m: 0,
+            sms: heapless::String::new(),
+        },
+        triggers: [
+            trigger::Binding::off(6),
+            trigger::Binding::off(7),
+            trigger::Binding::off(8),
+            trigger::Binding::off(9),
+        ],
+    };
+}
+
+// 可导入的字段和取值范围
+pub struct Field {
+    pub path: &'static str,
+    pub min: u32,
+    pub max: u32,
+}
+
+pub const FIELDS: &[Field] = &[
+    Field { path: "rate_limit.burst", min: 1, max: 1_000 },
+    Field { path: "rate_limit.refill_ms", min: 1, max: 60_000 },
+    Field { path: "rate_limit.max_connections", min: 1, max: 16 },
+    Field { path: "rate_limit.idle_expiry_ms", min: 1_000, max: 3_600_000 },
+    Field { path: "deadlines.header_ms", min: 100, max: 60_000 },
+    Field { path: "deadlines.request_ms", min: 100, max: 300_000 },
+    Field { path: "deadlines.write_progress_ms", min: 100, max: 60_000 },
+    Field { path: "tcp.keepalive_ms", min: 1_000, max: 600_000 },
+    Field { path: "tcp.timeout_ms", min: 1_000, max: 600_000 },
+    Field { path: "tcp.idle_close_ms", min: 100, max: 60_000 },
+    Field { path: "http.port", min: 1, max: 65_535 },
+    Field { path: "http.port2", min: 0, max: 65_535 },
+    Field { path: "http.lock_budget_ms", min: 1, max: 10_000 },
+    Field { path: "webhook.events", min: 0, max: webhook::ALL_EVENTS },
+    Field { path: "log.persist_min", min: 0, max: 24 * 60 },
+    Field { path: "fetch.abandon_s", min: 0, max: 3600 },
+    Field { path: "fetch.direct_push", min: 0, max: 1 },
+    Field { path: "uart.data_bits", min: 5, max: 8 },
+    Field { path: "uart.stop_bits", min: 1, max: 2 },
+    Field { path: "uart.debug_port", min: 0, max: 1 },
+    Field { path: "uart.debug_baud", min: 1_200, max: 3_000_000 },
+    Field { path: "uart.debug_writes", min: 0, max: 1 },
+    Field { path: "keep_warm.interval_min", min: 0, max: 24 * 60 },
+    Field { path: "roaming.block_data", min: 0, max: 1 },
+    Field { path: "services.enabled", min: 0, max: 1 },
+    Field { path: "health.checks", min: 0, max: health::ALL_CHECKS },
+    Field { path: "health.max_idle_min", min: 1, max: 24 * 60 },
+    Field { path: "health.min_free_sockets", min: 0, max: socket_budget::STACK_SOCKETS as u32 },
+    Field { path: "health.at_rtt_warn_ms", min: 0, max: 10_000 },
+    Field { path: "power.ap_idle_min", min: 0, max: 24 * 60 },
+    Field { path: "power.wake_button", min: 0, max: 1 },
+    Field { path: "power.wake_gpio", min: 0, max: 28 },
+    Field { path: "arp.events", min: 0, max: 1 },
+    Field { path: "modem.pwrkey", min: 0, max: 1 },
+    Field { path: "modem.pwrkey_gpio", min: 0, max: 28 },
+    Field { path: "actions.web", min: 0, max: actions::ALL_ACTIONS },
+    Field { path: "actions.input", min: 0, max: actions::ALL_ACTIONS },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward1.enabled", min: 0, max: 1 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward1.listen_port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward1.port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward2.enabled", min: 0, max: 1 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward2.listen_port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward2.port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward3.enabled", min: 0, max: 1 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward3.listen_port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward3.port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward4.enabled", min: 0, max: 1 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward4.listen_port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "forward4.port", min: 0, max: 65_535 },
+    #[cfg(feature = "proxy")]
+    Field { path: "shaper.rate", min: 0, max: 1_000_000 },
+    #[cfg(feature = "proxy")]
+    Field { path: "pac.forward", min: 0, max: forward::MAX_FORWARDS as u32 },
+    Field { path: "schedule.unsynced_open", min: 0, max: 1 },
+    Field { path: "schedule1.features", min: 0, max: schedule::ALL_FEATURES },
+    Field { path: "schedule2.features", min: 0, max: schedule::ALL_FEATURES },
+    #[cfg(feature = "gnss")]
+    Field { path: "gnss.enabled", min: 0, max: 1 },
+    #[cfg(feature = "gnss")]
+    Field { path: "gnss.interval_s", min: 5, max: 3_600 },
+    #[cfg(feature = "gnss")]
+    Field { path: "geofence.radius_m", min: 0, max: 1_000_000 },
+    Field { path: "trigger1.gpio", min: 0, max: 28 },
+    Field { path: "trigger1.debounce_ms", min: 0, max: 10_000 },
+    Field { path: "trigger1.min_interval_s", min: 0, max: 86_400 },
+    Field { path: "trigger2.gpio", min: 0, max: 28 },
+    Field { path: "trigger2.debounce_ms", min: 0, max: 10_000 },
+    Field { path: "trigger2.min_interval_s", min: 0, max: 86_400 },
+    Field { path: "trigger3.gpio", min: 0, max: 28 },
+    Field { path: "trigger3.debounce_ms", min: 0, max: 10_000 },
+    Field { path: "trigger3.min_interval_s", min: 0, max: 86_400 },
+    Field { path: "trigger4.gpio", min: 0, max: 28 },
+    Field { path: "trigger4.debounce_ms", min: 0, max: 10_000 },
+    Field { path: "trigger4.min_interval_s", min: 0, max: 86_400 },
+];
+
+// 字符串字段: (路径, 最大长度)
+pub const TEXT_FIELDS: &[(&str, usize)] = &[
+    ("device.name", identity::MAX_NAME),
+    ("webhook.url", 96),
+    ("sim.pin", 8),
+    ("log.level", 5),
+    ("http.role2", 11),
+    ("uart.parity", 4),
+    ("keep_warm.host", 64),
+    ("power.wake_start", 5),
+    ("power.wake_end", 5),
+    ("arp.pins", 128),
+    #[cfg(feature = "proxy")]
+    ("forward1.host", 64),
+    #[cfg(feature = "proxy")]
+    ("forward2.host", 64),
+    #[cfg(feature = "proxy")]
+    ("forward3.host", 64),
+    #[cfg(feature = "proxy")]
+    ("forward4.host", 64),
+    ("schedule1.start", 5),
+    ("schedule1.end", 5),
+    ("schedule2.start", 5),
+    ("schedule2.end", 5),
+    #[cfg(feature = "gnss")]
+    ("geofence.lat", 12),
+    #[cfg(feature = "gnss")]
+    ("geofence.lon", 12),
+    #[cfg(feature = "gnss")]
+    ("geofence.sms", 20),
+    ("trigger1.edge", 7),
+    ("trigger1.action", 7),
+    ("trigger1.sms", 20),
+    ("trigger2.edge", 7),
+    ("trigger2.action", 7),
+    ("trigger2.sms", 20),
+    ("trigger3.edge", 7),
+    ("trigger3.action", 7),
+    ("trigger3.sms", 20),
+    ("trigger4.edge", 7),
+    ("trigger4.action", 7),
+    ("trigger4.sms", 20),
+];
+
+// JSON 文档里各组的顺序
+const GROUPS: &[&str] = &[
+    "device",
+    "rate_limit",
+    "deadlines",
+    "tcp",
+    "http",
+    "webhook",
+    "sim",
+    "log",
+    "fetch",
+    "uart",
+    "keep_warm",
+    "roaming",
+    "services",
+    "health",
+    "power",
+    "arp",
+    "modem",
+    "actions",
+    #[cfg(feature = "proxy")]
+    "forward1",
+    #[cfg(feature = "proxy")]
+    "forward2",
+    #[cfg(feature = "proxy")]
+    "forward3",
+    #[cfg(feature = "proxy")]
+    "forward4",
+    #[cfg(feature = "proxy")]
+    "shaper",
+    #[cfg(feature = "proxy")]
+    "pac",
+    "schedule",
+    "schedule1",
+    "schedule2",
+    #[cfg(feature = "gnss")]
+    "gnss",
+    #[cfg(feature = "gnss")]
+    "geofence",
+    "trigger1",
+    "trigger2",
+    "trigger3",
+    "trigger4",
+];
+
+// 本固件未编译进来的功能的设置组
+// An export from a build with more features loads here with these ignored
+const OMITTED_GROUPS: &[&str] = &[
+    #[cfg(not(feature = "proxy"))]
+    "forward1",
+    #[cfg(not(feature = "proxy"))]
+    "forward2",
+    #[cfg(not(feature = "proxy"))]
+    "forward3",
+    #[cfg(not(feature = "proxy"))]
+    "forward4",
+    #[cfg(not(feature = "proxy"))]
+    "shaper",
+    #[cfg(not(feature = "proxy"))]
+    "pac",
+    #[cfg(not(feature = "gnss"))]
+    "gnss",
+    #[cfg(not(feature = "gnss"))]
+    "geofence",
+];
+
+pub fn omitted(path: &str) -> bool {
+    path.split_once('.').is_some_and(|(group, _)| OMITTED_GROUPS.contains(&group))
+}
+
+// The FIELDS entry of a forward setting, for error messages
+#[cfg(feature = "proxy")]
+fn forward_path(index: usize, key: &str) -> &'static str {
+    FIELDS
+        .iter()
+        .map(|f| f.path)
+        .find(|path| forward::parse_path(path) == Some((index, key)))
+        .unwrap_or("")
+}
+
+// The FIELDS entry of a window setting, for error messages
+fn schedule_path(index: usize, key: &str) -> &'static str {
+    FIELDS
+        .iter()
+        .map(|f| f.path)
+        .find(|path| schedule::parse_path(path) == Some((index, key)))
+        .unwrap_or("")
+}
+
+// The FIELDS or TEXT_FIELDS entry of a trigger setting, for error messages
+fn trigger_path(index: usize, key: &str) -> &'static str {
+    FIELDS
+        .iter()
+        .map(|f| f.path)
+        .chain(TEXT_FIELDS.iter().map(|f| f.0))
+        .find(|path| trigger::parse_path(path) == Some((index, key)))
+        .unwrap_or("")
+}
+
+// How secret fields (the SIM PIN) are written out
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Secrets {
+    // exports: asterisks, which an import reads as "keep the current value"
+    Mask,
+    // the copy in flash: recoverable, but not readable at a glance
+    Obfuscate,
+}
+
+pub const PIN_MASK: &str = "********";
+const OBFUSCATED: &str = "obf:";
+const OBFUSCATION_KEY: &[u8] = b"pico2w-sim-pin";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FieldError {
+    Unknown,
+    OutOfRange { min: u32, max: u32 },
+    TooLong { max: usize },
+    Invalid,
+    // a value that goes into an AT command
+    Arg(at::ArgError),
+}
+
+// Empty, or safe as an AT argument of this kind
+fn check_arg(value: &str, kind: at::Kind) -> Result<(), FieldError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    at::arg(value, kind).map(|_| ()).map_err(FieldError::Arg)
+}
+
+fn obfuscate<const N: usize>(out: &mut heapless::String<N>, value: &str) {
+    let _ = out.push_str(OBFUSCATED);
+    for (b, k) in value.bytes().zip(OBFUSCATION_KEY.iter().cycle()) {
+        let _ = core::write!(out, "{:02x}", b ^ k);
+    }
+}
+
+fn deobfuscate(hex: &str) -> Option<heapless::String<8>> {
+    let mut out = heapless::String::new();
+    let bytes = hex.as_bytes();
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    for (pair, k) in bytes.chunks(2).zip(OBFUSCATION_KEY.iter().cycle()) {
+        let byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+        out.push((byte ^ k) as char).ok()?;
+    }
+    Some(out)
+}
+
+impl Config {
+    pub fn get(&self, path: &str) -> Option<u32> {
+        #[cfg(feature = "proxy")]
+        if let Some((index, key)) = forward::parse_path(path) {
+            let rule = &self.forwards[index];
+            return match key {
+                "enabled" => Some(rule.enabled as u32),
+                "listen_port" => Some(rule.listen_port as u32),
+                "port" => Some(rule.port as u32),
+                _ => None,
+            };
+        }
+        if let Some((index, "features")) = schedule::parse_path(path) {
+            return Some(self.schedule.windows[index].features);
+        }
+        if let Some((index, key)) = trigger::parse_path(path) {
+            let binding = &self.triggers[index];
+            return match key {
+                "gpio" => Some(binding.gpio as u32),
+                "debounce_ms" => Some(binding.debounce_ms),
+                "min_interval_s" => Some(binding.min_interval_s),
+                _ => None,
+            };
+        }
+        Some(match path {
+            "rate_limit.burst" => self.rate_limit.burst,
+            "rate_limit.refill_ms" => self.rate_limit.refill_ms,
+            "rate_limit.max_connections" => self.rate_limit.max_connections as u32,
+            "rate_limit.idle_expiry_ms" => self.rate_limit.idle_expiry_ms,
+            "deadlines.header_ms" => self.deadlines.header_ms,
+            "deadlines.request_ms" => self.deadlines.request_ms,
+            "deadlines.write_progress_ms" => self.deadlines.write_progress_ms,
+            "tcp.keepalive_ms" => self.tcp.keepalive_ms,
+            "tcp.timeout_ms" => self.tcp.timeout_ms,
+            "tcp.idle_close_ms" => self.tcp.idle_close_ms,
+            "http.port" => self.http.port,
+            "http.port2" => self.http.port2,
+            "http.lock_budget_ms" => self.http.lock_budget_ms,
+            "webhook.events" => self.webhook.events,
+            "log.persist_min" => self.log.persist_min,
+            "fetch.abandon_s" => self.fetch.abandon_s,
+            "fetch.direct_push" => self.fetch.direct_push as u32,
+            "uart.data_bits" => self.uart.data_bits,
+            "uart.stop_bits" => self.uart.stop_bits,
+            "uart.debug_port" => self.uart.debug_port as u32,
+            "uart.debug_baud" => self.uart.debug_baud,
+            "uart.debug_writes" => self.uart.debug_writes as u32,
+            "keep_warm.interval_min" => self.keep_warm.interval_min,
+            "roaming.block_data" => self.roaming.block_data as u32,
+            "services.enabled" => self.services.enabled as u32,
+            "health.checks" => self.health.checks,
+            "health.max_idle_min" => self.health.max_idle_min,
+            "health.min_free_sockets" => self.health.min_free_sockets,
+            "health.at_rtt_warn_ms" => self.health.at_rtt_warn_ms,
+            "power.ap_idle_min" => self.power.ap_idle_min,
+            "power.wake_button" => self.power.wake_button as u32,
+            "power.wake_gpio" => self.power.wake_gpio as u32,
+            "arp.events" => self.arp.events as u32,
+            "modem.pwrkey" => self.modem.pwrkey as u32,
+            "modem.pwrkey_gpio" => self.modem.pwrkey_gpio as u32,
+            "actions.web" => self.actions.web,
+            "actions.input" => self.actions.input,
+            #[cfg(feature = "proxy")]
+            "shaper.rate" => self.shaper.rate,
+            #[cfg(feature = "proxy")]
+            "pac.forward" => self.pac.forward,
+            "schedule.unsynced_open" => self.schedule.unsynced_open as u32,
+            #[cfg(feature = "gnss")]
+            "gnss.enabled" => self.gnss.enabled as u32,
+            #[cfg(feature = "gnss")]
+            "gnss.interval_s" => self.gnss.interval_s,
+            #[cfg(feature = "gnss")]
+            "geofence.radius_m" => self.geofence.radius_m,
+            _ => return None,
+        })
+    }
+
+    pub fn set(&mut self, path: &str, value: u32) -> Result<(), FieldError> {
+        let field = FIELDS.iter().find(|f| f.path == path).ok_or(FieldError::Unknown)?;
+        if !(field.min..=field.max).contains(&value) {
+            return Err(FieldError::OutOfRange {
+                min: field.min,
+                max: field.max,
+            });
+        }
+        #[cfg(feature = "proxy")]
+        if let Some((index, key)) = forward::parse_path(path) {
+            let rule = &mut self.forwards[index];
+            match key {
+                "enabled" => rule.enabled = value == 1,
+                "listen_port" => rule.listen_port = value as u16,
+                "port" => rule.port = value as u16,
+                _ => return Err(FieldError::Unknown),
+            }
+            return Ok(());
+        }
+        if let Some((index, "features")) = schedule::parse_path(path) {
+            self.schedule.windows[index].features = value;
+            return Ok(());
+        }
+        if let Some((index, key)) = trigger::parse_path(path) {
+            let binding = &mut self.triggers[index];
+            match key {
+                "gpio" => binding.gpio = value as u8,
+                "debounce_ms" => binding.debounce_ms = value,
+                "min_interval_s" => binding.min_interval_s = value,
+                _ => return Err(FieldError::Unknown),
+            }
+            return Ok(());
+        }
+        match path {
+            "rate_limit.burst" => self.rate_limit.burst = value,
+            "rate_limit.refill_ms" => self.rate_limit.refill_ms = value,
+            "rate_limit.max_connections" => self.rate_limit.max_connections = value as u8,
+            "rate_limit.idle_expiry_ms" => self.rate_limit.idle_expiry_ms = value,
+            "deadlines.header_ms" => self.deadlines.header_ms = value,
+            "deadlines.request_ms" => self.deadlines.request_ms = value,
+            "deadlines.write_progress_ms" => self.deadlines.write_progress_ms = value,
+            "tcp.keepalive_ms" => self.tcp.keepalive_ms = value,
+            "tcp.timeout_ms" => self.tcp.timeout_ms = value,
+            "tcp.idle_close_ms" => self.tcp.idle_close_ms = value,
+            "http.port" => self.http.port = value,
+            "http.port2" => self.http.port2 = value,
+            "http.lock_budget_ms" => self.http.lock_budget_ms = value,
+            "webhook.events" => self.webhook.events = value,
+            "log.persist_min" => self.log.persist_min = value,
+            "fetch.abandon_s" => self.fetch.abandon_s = value,
+            "fetch.direct_push" => self.fetch.direct_push = value == 1,
+            "uart.data_bits" => self.uart.data_bits = value,
+            "uart.stop_bits" => self.uart.stop_bits = value,
+            "uart.debug_port" => self.uart.debug_port = value == 1,
+            "uart.debug_baud" => self.uart.debug_baud = value,
+            "uart.debug_writes" => self.uart.debug_writes = value == 1,
+            "keep_warm.interval_min" => self.keep_warm.interval_min = value,
+            "roaming.block_data" => self.roaming.block_data = value == 1,
+            "services.enabled" => self.services.enabled = value == 1,
+            "health.checks" => self.health.checks = value,
+            "health.max_idle_min" => self.health.max_idle_min = value,
+            "health.min_free_sockets" => self.health.min_free_sockets = value,
+            "health.at_rtt_warn_ms" => self.health.at_rtt_warn_ms = value,
+            "power.ap_idle_min" => self.power.ap_idle_min = value,
+            "power.wake_button" => self.power.wake_button = value == 1,
+            "power.wake_gpio" => self.power.wake_gpio = value as u8,
+            "arp.events" => self.arp.events = value == 1,
+            "modem.pwrkey" => self.modem.pwrkey = value == 1,
+            "modem.pwrkey_gpio" => self.modem.pwrkey_gpio = value as u8,
+            "actions.web" => self.actions.web = value,
+            "actions.input" => self.actions.input = value,
+            #[cfg(feature = "proxy")]
+            "shaper.rate" => self.shaper.rate = value,
+            #[cfg(feature = "proxy")]
+            "pac.forward" => self.pac.forward = value,
+            "schedule.unsynced_open" => self.schedule.unsynced_open = value == 1,
+            #[cfg(feature = "gnss")]
+            "gnss.enabled" => self.gnss.enabled = value == 1,
+            #[cfg(feature = "gnss")]
+            "gnss.interval_s" => self.gnss.interval_s = value,
+            #[cfg(feature = "gnss")]
+            "geofence.radius_m" => self.geofence.radius_m = value,
+            _ => return Err(FieldError::Unknown),
+        }
+        Ok(())
+    }
+
+    pub fn get_text(&self, path: &str) -> Option<&str> {
+        #[cfg(feature = "proxy")]
+        if let Some((index, "host")) = forward::parse_path(path) {
+            return Some(&self.forwards[index].host);
+        }
+        match schedule::parse_path(path) {
+            Some((index, "start")) => return Some(&self.schedule.windows[index].start),
+            Some((index, "end")) => return Some(&self.schedule.windows[index].end),
+            _ => {}
+        }
+        match trigger::parse_path(path) {
+            Some((index, "edge")) => return Some(self.triggers[index].edge.as_str()),
+            Some((index, "action")) => return Some(self.triggers[index].action.as_str()),
+            Some((index, "sms")) => return Some(&self.triggers[index].sms),
+            _ => {}
+        }
+        match path {
+            "device.name" => Some(&self.device.name),
+            "webhook.url" => Some(&self.webhook.url),
+            "sim.pin" => Some(&self.sim.pin),
+            "log.level" => Some(self.log.level.as_str()),
+            "http.role2" => Some(self.http.role2.as_str()),
+            "uart.parity" => Some(self.uart.parity.as_str()),
+            "keep_warm.host" => Some(&self.keep_warm.host),
+            "power.wake_start" => Some(&self.power.wake_start),
+            "power.wake_end" => Some(&self.power.wake_end),
+            "arp.pins" => Some(&self.arp.pins),
+            #[cfg(feature = "gnss")]
+            "geofence.lat" => Some(&self.geofence.lat),
+            #[cfg(feature = "gnss")]
+            "geofence.lon" => Some(&self.geofence.lon),
+            #[cfg(feature = "gnss")]
+            "geofence.sms" => Some(&self.geofence.sms),
+            _ => None,
+        }
+    }
+
+    pub fn set_text(&mut self, path: &str, value: &str) -> Result<(), FieldError> {
+        let &(_, max) = TEXT_FIELDS.iter().find(|f| f.0 == path).ok_or(FieldError::Unknown)?;
+        if path == "sim.pin" {
+            return self.set_pin(value);
+        }
+        if value.len() > max {
+            return Err(FieldError::TooLong { max });
+        }
+        #[cfg(feature = "proxy")]
+        if let Some((index, "host")) = forward::parse_path(path) {
+            check_arg(value, at::Kind::Host)?;
+            self.forwards[index].host.clear();
+            let _ = self.forwards[index].host.push_str(value);
+            return Ok(());
+        }
+        if let Some((index, key @ ("start" | "end"))) = schedule::parse_path(path) {
+            if !value.is_empty() && schedule::parse_hhmm(value).is_none() {
+                return Err(FieldError::Invalid);
+            }
+            let window = &mut self.schedule.windows[index];
+            let time = if key == "start" { &mut window.start } else { &mut window.end };
+            time.clear();
+            let _ = time.push_str(value);
+            return Ok(());
+        }
+        if let Some((index, key)) = trigger::parse_path(path) {
+            let binding = &mut self.triggers[index];
+            match key {
+                "edge" => binding.edge = trigger::Edge::parse(value).ok_or(FieldError::Invalid)?,
+                "action" => binding.action = trigger::Action::parse(value).ok_or(FieldError::Invalid)?,
+                "sms" => {
+                    check_arg(value, at::Kind::Phone)?;
+                    binding.sms.clear();
+                    let _ = binding.sms.push_str(value);
+                }
+                _ => return Err(FieldError::Unknown),
+            }
+            return Ok(());
+        }
+        match path {
+            "device.name" => {
+                if !value.is_empty() && !identity::valid_name(value) {
+                    return Err(FieldError::Invalid);
+                }
+                self.device.name.clear();
+                let _ = self.device.name.push_str(value);
+            }
+            "webhook.url" => {
+                if !value.is_empty() && webhook::parse_url(value).is_none() {
+                    return Err(FieldError::Invalid);
+                }
+                self.webhook.url.clear();
+                let _ = self.webhook.url.push_str(value);
+            }
+            "log.level" => self.log.level = log_level::Level::parse(value).ok_or(FieldError::Invalid)?,
+            "http.role2" => self.http.role2 = listener::Role::parse(value).ok_or(FieldError::Invalid)?,
+            "uart.parity" => self.uart.parity = Parity::parse(value).ok_or(FieldError::Invalid)?,
+            "keep_warm.host" => {
+                check_arg(value, at::Kind::Host)?;
+                self.keep_warm.host.clear();
+                let _ = self.keep_warm.host.push_str(value);
+            }
+            "power.wake_start" | "power.wake_end" => {
+                if !value.is_empty() && schedule::parse_hhmm(value).is_none() {
+                    return Err(FieldError::Invalid);
+                }
+                let time = if path == "power.wake_start" {
+                    &mut self.power.wake_start
+                } else {
+                    &mut self.power.wake_end
+                };
+                time.clear();
+                let _ = time.push_str(value);
+            }
+            "arp.pins" => {
+                if arp::parse_pins(value).is_none() {
+                    return Err(FieldError::Invalid);
+                }
+                self.arp.pins.clear();
+                let _ = self.arp.pins.push_str(value);
+            }
+            #[cfg(feature = "gnss")]
+            "geofence.lat" | "geofence.lon" => {
+                let (limit, target) = if path == "geofence.lat" {
+                    (90.0, &mut self.geofence.lat)
+                } else {
+                    (180.0, &mut self.geofence.lon)
+                };
+                if !value.is_empty() && geofence::parse_degrees(value, limit).is_none() {
+                    return Err(FieldError::Invalid);
+                }
+                target.clear();
+                let _ = target.push_str(value.trim());
+            }
+            #[cfg(feature = "gnss")]
+            "geofence.sms" => {
+                check_arg(value, at::Kind::Phone)?;
+                self.geofence.sms.clear();
+                let _ = self.geofence.sms.push_str(value);
+            }
+            _ => return Err(FieldError::Unknown),
+        }
+        Ok(())
+    }
+
+    // Plain digits, the obfuscated flash form, or the export mask (no change)
+    fn set_pin(&mut self, value: &str) -> Result<(), FieldError> {
+        if value == PIN_MASK {
+            return Ok(());
+        }
+        let pin = match value.strip_prefix(OBFUSCATED) {
+            Some(hex) => deobfuscate(hex).ok_or(FieldError::Invalid)?,
+            None => heapless::String::try_from(value).map_err(|_| FieldError::TooLong { max: 8 })?,
+        };
+        if !pin.is_empty() && !sim::valid_pin(&pin) {
+            return Err(FieldError::Invalid);
+        }
+        self.sim.pin = pin;
+        Ok(())
+    }
+
+    // Rules that involve more than one field: (path, problem)
+    pub fn check(&self) -> Option<(&'static str, &'static str)> {
+        if self.deadlines.header_ms > self.deadlines.request_ms {
+            return Some(("deadlines.header_ms", "must not exceed deadlines.request_ms"));
+        }
+        if self.uart.debug_writes && !self.uart.debug_port {
+            return Some(("uart.debug_writes", "needs uart.debug_port"));
+        }
+        // 7 and 9: test services
+        if matches!(self.http.port, 7 | 9) {
+            return Some(("http.port", "is used by another service"));
+        }
+        if self.http.port2 == self.http.port || matches!(self.http.port2, 7 | 9) {
+            return Some(("http.port2", "is used by another service"));
+        }
+        #[cfg(feature = "proxy")]
+        for (index, rule) in self.forwards.iter().enumerate() {
+            if !rule.enabled {
+                continue;
+            }
+            if !rule.active() {
+                return Some((forward_path(index, "enabled"), "needs a listen port, host and port"));
+            }
+            // http.port and http.port2: web pages, 7 and 9: test services
+            let port = rule.listen_port as u32;
+            if port == self.http.port || port == self.http.port2 || matches!(port, 7 | 9) {
+                return Some((forward_path(index, "listen_port"), "is used by another service"));
+            }
+            let taken = self.forwards[..index]
+                .iter()
+                .any(|other| other.enabled && other.listen_port == rule.listen_port);
+            if taken {
+                return Some((forward_path(index, "listen_port"), "is used by another forward"));
+            }
+        }
+        #[cfg(feature = "proxy")]
+        if self.pac.forward > 0 && !self.forwards[self.pac.forward as usize - 1].enabled {
+            return Some(("pac.forward", "needs that forward enabled"));
+        }
+        for (index, window) in self.schedule.windows.iter().enumerate() {
+            if window.features != 0 && !window.complete() {
+                return Some((schedule_path(index, "features"), "needs a start and an end time that differ"));
+            }
+        }
+        let power = &self.power;
+        if (!power.wake_start.is_empty() || !power.wake_end.is_empty()) && power.wake_window().is_none() {
+            return Some(("power.wake_end", "needs power.wake_start, and the two must differ"));
+        }
+        if power.ap_idle_min > 0 && !power.wake_button && power.wake_window().is_none() {
+            return Some(("power.ap_idle_min", "needs power.wake_button or a wake window"));
+        }
+        if power.wake_button {
+            if !board::ACTIVE.is_free(power.wake_gpio) {
+                return Some(("power.wake_gpio", "is used by the board"));
+            }
+            if self.triggers.iter().any(|t| t.active() && t.gpio == power.wake_gpio) {
+                return Some(("power.wake_gpio", "is used by a trigger"));
+            }
+        }
+        let modem = &self.modem;
+        if modem.pwrkey {
+            if !board::ACTIVE.pwrkey_allowed(modem.pwrkey_gpio) {
+                return Some(("modem.pwrkey_gpio", "is used by the board"));
+            }
+            if self.triggers.iter().any(|t| t.active() && t.gpio == modem.pwrkey_gpio) {
+                return Some(("modem.pwrkey_gpio", "is used by a trigger"));
+            }
+            if power.wake_button && power.wake_gpio == modem.pwrkey_gpio {
+                return Some(("modem.pwrkey_gpio", "is the AP wake button"));
+            }
+        }
+        #[cfg(feature = "gnss")]
+        if self.geofence.radius_m > 0 && self.geofence.fence().is_none() {
+            return Some(("geofence.radius_m", "needs geofence.lat and geofence.lon"));
+        }
+        for (index, binding) in self.triggers.iter().enumerate() {
+            if !binding.active() {
+                continue;
+            }
+            if !board::ACTIVE.is_free(binding.gpio) {
+                return Some((trigger_path(index, "gpio"), "is used by the board"));
+            }
+            let taken = self.triggers[..index]
+                .iter()
+                .any(|other| other.active() && other.gpio == binding.gpio);
+            if taken {
+                return Some((trigger_path(index, "gpio"), "is used by another trigger"));
+            }
+            if binding.action == trigger::Action::Sms && binding.sms.is_empty() {
+                return Some((trigger_path(index, "sms"), "needs a number for the sms action"));
+            }
+        }
+        None
+    }
+
+    // {"rate_limit":{...},"deadlines":{...},...} members, without braces
+    pub fn write_json_members<const N: usize>(&self, out: &mut heapless::String<N>, secrets: Secrets) {
+        let group_of = |path: &'static str| path.split_once('.').unwrap_or(("", path));
+        for (index, group) in GROUPS.iter().enumerate() {
+            if index > 0 {
+                let _ = out.push(',');
+            }
+            let _ = core::write!(out, "\"{}\":{{", group);
+            let mut first = true;
+            let mut separator = |out: &mut heapless::String<N>| {
+                if !core::mem::take(&mut first) {
+                    let _ = out.push(',');
+                }
+            };
+            for field in FIELDS.iter().filter(|f| group_of(f.path).0 == *group) {
+                separator(out);
+                let _ = core::write!(out, "\"{}\":{}", group_of(field.path).1, self.get(field.path).unwrap_or(0));
+            }
+            for (path, _) in TEXT_FIELDS.iter().filter(|f| group_of(f.0).0 == *group) {
+                separator(out);
+                let _ = core::write!(out, "\"{}\":", group_of(path).1);
+                let value = self.get_text(path).unwrap_or("");
+                match (*path, secrets) {
+                    ("sim.pin", _) if value.is_empty() => json::push_str_value(out, ""),
+                    ("sim.pin", Secrets::Mask) => json::push_str_value(out, PIN_MASK),
+                    ("sim.pin", Secrets::Obfuscate) => {
+                        let mut hidden = heapless::String::<24>::new();
+                        obfuscate(&mut hidden, value);
+                        json::push_str_value(out, &hidden);
+                    }
+                    _ => json::push_str_value(out, value),
+                }
+            }
+            let _ = out.push('}');
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// HTTP 连接怎样结束: FIN 正常关闭, 或者 RST 复位
+//
+// An answered connection is closed with a FIN, and the peer then gets
+// tcp.idle_close_ms to close its side. Every other way out is an abort,
+// which sends a RST. A client sees a RST as an error even when the whole
+// response arrived, so an abort is kept for when the response is broken
+// or the peer is not going away. A request whose headers run out of time
+// is answered with a 408 and closed with a FIN, but still counted under
+// its own kind. Each connection ends with exactly one Close. It is
+// counted for /metrics as http_closes_total and logged at debug level
+// with the request id.
+
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::Read;
+use portable_atomic::{AtomicU32, Ordering};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Close {
+    // FIN sent, the peer closed within the idle window
+    Graceful,
+    // FIN sent, the peer stayed open past the idle window
+    Reaped,
+    // FIN sent, the peer answered with a RST
+    PeerReset,
+    // the response stopped making progress (deadlines.write_progress_ms)
+    WriteStall,
+    // over the per-IP connection limit
+    Throttled,
+    // the headers missed deadlines.header_ms (408)
+    HeaderTimeout,
+    RequestTimeout,
+    // the request body could not be read off the socket
+    BodyNotDrained,
+    // the headers promised a body that could not be sent in full
+    Truncated,
+}
+
+pub const KINDS: usize = 9;
+
+impl Close {
+    pub const ALL: [Close; KINDS] = [
+        Close::Graceful,
+        Close::Reaped,
+        Close::PeerReset,
+        Close::WriteStall,
+        Close::Throttled,
+        Close::HeaderTimeout,
+        Close::RequestTimeout,
+        Close::BodyNotDrained,
+        Close::Truncated,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Close::Graceful => "graceful",
+            Close::Reaped => "reaped",
+            Close::PeerReset => "peer_reset",
+            Close::WriteStall => "write_stall",
+            Close::Throttled => "throttled",
+            Close::HeaderTimeout => "header_timeout",
+            Close::RequestTimeout => "request_timeout",
+            Close::BodyNotDrained => "body_not_drained",
+            Close::Truncated => "truncated",
+        }
+    }
+}
+
+pub struct Counters {
+    counts: [AtomicU32; KINDS],
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            counts: [const { AtomicU32::new(0) }; KINDS],
+        }
+    }
+
+    pub fn record(&self, close: Close) {
+        self.counts[close as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, close: Close) -> u32 {
+        self.counts[close as usize].load(Ordering::Relaxed)
+    }
+}
+
+// The two ways to end a TCP connection (embassy-net's TcpSocket)
+pub trait Socket: Read {
+    // FIN: nothing more from us, the peer may still send
+    fn close(&mut self);
+    // RST
+    fn abort(&mut self);
+}
+
+// 发送 FIN 并等待对方关闭; 空闲窗口内没有关闭的半开连接直接回收
+pub async fn close_gracefully<S: Socket>(socket: &mut S, idle_close_ms: u32) -> Close {
+    socket.close();
+    let idle = Duration::from_millis(idle_close_ms as u64);
+    match with_timeout(idle, wait_peer_close(socket)).await {
+        Ok(true) => Close::Graceful,
+        Ok(false) => Close::PeerReset,
+        Err(_) => {
+            socket.abort();
+            Close::Reaped
+        }
+    }
+}
+
+// True once the peer's FIN arrives, false on a RST; whatever the peer
+// still sends is read and dropped
+pub async fn wait_peer_close<R: Read>(socket: &mut R) -> bool {
+    let mut scratch = [0u8; 64];
+    loop {
+        match socket.read(&mut scratch).await {
+            Ok(0) => return true,
+            Ok(_) => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_io_async::{ErrorKind, ErrorType};
+
+    // What the peer does after our FIN
+    #[derive(Clone, Copy)]
+    enum Peer {
+        // sends these bytes, then closes its side
+        Fin(&'static [u8]),
+        // sends these bytes, then resets
+        Reset(&'static [u8]),
+        // never closes
+        Silent,
+    }
+
+    struct Mock {
+        peer: Peer,
+        sent: bool,
+        // close / abort / read, in order
+        calls: Vec<&'static str>,
+    }
+
+    impl ErrorType for Mock {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Mock {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            self.calls.push("read");
+            let (Peer::Fin(data) | Peer::Reset(data)) = self.peer else {
+                core::future::pending::<()>().await;
+                unreachable!()
+            };
+            if !self.sent && !data.is_empty() {
+                self.sent = true;
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                return Ok(n);
+            }
+            match self.peer {
+                Peer::Reset(_) => Err(ErrorKind::ConnectionReset),
+                _ => Ok(0),
+            }
+        }
+    }
+
+    impl Socket for Mock {
+        fn close(&mut self) {
+            self.calls.push("close");
+        }
+
+        fn abort(&mut self) {
+            self.calls.push("abort");
+        }
+    }
+
+    fn end(peer: Peer) -> (Close, Vec<&'static str>) {
+        let mut socket = Mock {
+            peer,
+            sent: false,
+            calls: Vec::new(),
+        };
+        let close = embassy_futures::block_on(close_gracefully(&mut socket, 100));
+        (close, socket.calls)
+    }
+
+    #[test]
+    fn fin_from_the_peer_is_graceful() {
+        assert_eq!(end(Peer::Fin(b"")), (Close::Graceful, vec!["close", "read"]));
+        // a pipelined request after the response is read and dropped
+        assert_eq!(end(Peer::Fin(b"GET / HTTP/1.1\r\n\r\n")), (Close::Graceful, vec!["close", "read", "read"]));
+    }
+
+    #[test]
+    fn reset_from_the_peer_is_not_answered_with_another() {
+        assert_eq!(end(Peer::Reset(b"")), (Close::PeerReset, vec!["close", "read"]));
+        assert_eq!(end(Peer::Reset(b"x")), (Close::PeerReset, vec!["close", "read", "read"]));
+    }
+
+    #[test]
+    fn silent_peer_is_aborted_after_the_idle_window() {
+        let started = embassy_time::Instant::now();
+        let (close, calls) = end(Peer::Silent);
+        assert_eq!(close, Close::Reaped);
+        assert_eq!(calls, ["close", "read", "abort"]);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+}
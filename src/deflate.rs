@@ -0,0 +1,342 @@
+// 固定霍夫曼表的 deflate 压缩器 (gzip 封装)
+//
+// Only uses core so build.rs can include it as well to pre-compress the
+// static assets. Working memory is the Deflater struct itself (~6 KB), no
+// allocation: greedy LZ77 over a sliding window with a single-entry hash
+// table, every call to `compress` emits one fixed-Huffman block.
+
+pub const WINDOW_SIZE: usize = 2048;
+const HASH_SIZE: usize = 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+pub const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0x00, 0, 0, 0, 0, 0x00, 0xff];
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const CRC_TABLE: [u32; 16] = [
+    0x00000000, 0x1db71064, 0x3b6e20c8, 0x26d930ac, 0x76dc4190, 0x6b6b51f4, 0x4db26158,
+    0x5005713c, 0xedb88320, 0xf00f9344, 0xd6d6a3e8, 0xcb61b38c, 0x9b64c2b0, 0x86d3d2d4,
+    0xa00ae278, 0xbdbdf21c,
+];
+
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    crc = !crc;
+    for &b in data {
+        crc = CRC_TABLE[((crc ^ b as u32) & 0x0f) as usize] ^ (crc >> 4);
+        crc = CRC_TABLE[((crc ^ (b as u32 >> 4)) & 0x0f) as usize] ^ (crc >> 4);
+    }
+    !crc
+}
+
+// Most bytes one `compress` call emits: up to 7 bits held over from the
+// call before, the 3-bit block header and 9 bits per input byte (a match
+// never costs more per byte than a 9-bit literal); the 7-bit end code
+// rounds that up to whole bytes and the rest is held for the next call
+pub const fn max_compressed(input: usize) -> usize {
+    (7 + 3 + 9 * input).div_ceil(8)
+}
+
+// Most bytes `finish` emits: the final block, padding and the trailer
+pub const FINISH_MAX: usize = 3 + 8;
+
+pub struct Deflater {
+    window: [u8; WINDOW_SIZE * 2],
+    len: usize,
+    // position + 1 of the last occurrence of each 3-byte hash, 0 = empty
+    head: [u16; HASH_SIZE],
+    bit_buf: u32,
+    bit_count: u8,
+    crc: u32,
+    total_in: u32,
+}
+
+impl Deflater {
+    pub const fn new() -> Self {
+        Self {
+            window: [0; WINDOW_SIZE * 2],
+            len: 0,
+            head: [0; HASH_SIZE],
+            bit_buf: 0,
+            bit_count: 0,
+            crc: 0,
+            total_in: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.head = [0; HASH_SIZE];
+        self.bit_buf = 0;
+        self.bit_count = 0;
+        self.crc = 0;
+        self.total_in = 0;
+    }
+
+    // Compress `input` (at most WINDOW_SIZE bytes) as one non-final block.
+    // Emits at most `max_compressed(input.len())` bytes.
+    pub fn compress(&mut self, input: &[u8], mut emit: impl FnMut(u8)) {
+        let input = &input[..input.len().min(WINDOW_SIZE)];
+        self.crc = crc32_update(self.crc, input);
+        self.total_in = self.total_in.wrapping_add(input.len() as u32);
+
+        if self.len + input.len() > self.window.len() {
+            self.slide();
+        }
+        let start = self.len;
+        self.window[start..start + input.len()].copy_from_slice(input);
+        self.len += input.len();
+
+        // BFINAL = 0, BTYPE = 01 (fixed Huffman)
+        self.write_bits(0, 1, &mut emit);
+        self.write_bits(1, 2, &mut emit);
+
+        let end = self.len;
+        let mut pos = start;
+        while pos < end {
+            let (match_len, distance) = self.find_match(pos, end);
+            if match_len >= MIN_MATCH {
+                self.write_length(match_len, &mut emit);
+                self.write_distance(distance, &mut emit);
+                let stop = (pos + match_len).min(end.saturating_sub(MIN_MATCH - 1));
+                for p in pos + 1..stop {
+                    self.insert_hash(p);
+                }
+                pos += match_len;
+            } else {
+                self.write_literal(self.window[pos] as u16, &mut emit);
+                pos += 1;
+            }
+        }
+
+        // end of block
+        self.write_literal(256, &mut emit);
+    }
+
+    // Final empty block, byte alignment and the gzip trailer
+    pub fn finish(&mut self, mut emit: impl FnMut(u8)) {
+        self.write_bits(1, 1, &mut emit);
+        self.write_bits(1, 2, &mut emit);
+        self.write_literal(256, &mut emit);
+        if self.bit_count > 0 {
+            emit(self.bit_buf as u8);
+        }
+        self.bit_buf = 0;
+        self.bit_count = 0;
+
+        for b in self.crc.to_le_bytes() {
+            emit(b);
+        }
+        for b in self.total_in.to_le_bytes() {
+            emit(b);
+        }
+    }
+
+    fn slide(&mut self) {
+        self.window.copy_within(WINDOW_SIZE..self.len, 0);
+        self.len -= WINDOW_SIZE;
+        for entry in self.head.iter_mut() {
+            *entry = if *entry as usize > WINDOW_SIZE {
+                *entry - WINDOW_SIZE as u16
+            } else {
+                0
+            };
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let w = &self.window;
+        let v = (w[pos] as usize) << 10 ^ (w[pos + 1] as usize) << 5 ^ w[pos + 2] as usize;
+        (v ^ (v >> 7)) & (HASH_SIZE - 1)
+    }
+
+    fn insert_hash(&mut self, pos: usize) -> usize {
+        let h = self.hash(pos);
+        let previous = self.head[h] as usize;
+        self.head[h] = (pos + 1) as u16;
+        previous
+    }
+
+    fn find_match(&mut self, pos: usize, end: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > end {
+            return (0, 0);
+        }
+        let candidate = self.insert_hash(pos);
+        if candidate == 0 {
+            return (0, 0);
+        }
+
+        let candidate = candidate - 1;
+        let distance = pos - candidate;
+        if distance == 0 || distance > WINDOW_SIZE {
+            return (0, 0);
+        }
+
+        let max = (end - pos).min(MAX_MATCH);
+        let mut len = 0;
+        while len < max && self.window[candidate + len] == self.window[pos + len] {
+            len += 1;
+        }
+        (len, distance)
+    }
+
+    fn write_bits(&mut self, value: u32, count: u8, emit: &mut impl FnMut(u8)) {
+        self.bit_buf |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            emit(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    // Huffman codes go out most significant bit first
+    fn write_code(&mut self, code: u32, count: u8, emit: &mut impl FnMut(u8)) {
+        let reversed = code.reverse_bits() >> (32 - count as u32);
+        self.write_bits(reversed, count, emit);
+    }
+
+    fn write_literal(&mut self, symbol: u16, emit: &mut impl FnMut(u8)) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8, emit),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9, emit),
+            256..=279 => self.write_code(symbol - 256, 7, emit),
+            _ => self.write_code(0xc0 + symbol - 280, 8, emit),
+        }
+    }
+
+    fn write_length(&mut self, len: usize, emit: &mut impl FnMut(u8)) {
+        let index = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap_or(0);
+        self.write_literal(257 + index as u16, emit);
+        let extra = LENGTH_EXTRA[index];
+        if extra > 0 {
+            self.write_bits((len - LENGTH_BASE[index] as usize) as u32, extra, emit);
+        }
+    }
+
+    fn write_distance(&mut self, distance: usize, emit: &mut impl FnMut(u8)) {
+        let index = DIST_BASE.iter().rposition(|&b| b as usize <= distance).unwrap_or(0);
+        self.write_code(index as u32, 5, emit);
+        let extra = DIST_EXTRA[index];
+        if extra > 0 {
+            self.write_bits((distance - DIST_BASE[index] as usize) as u32, extra, emit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_known_answers() {
+        assert_eq!(crc32_update(0, b""), 0);
+        assert_eq!(crc32_update(0, b"a"), 0xe8b7be43);
+        assert_eq!(crc32_update(0, b"123456789"), 0xcbf43926);
+        assert_eq!(crc32_update(0, b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+        // 分段计算和一次计算相同
+        let crc = crc32_update(crc32_update(0, b"12345"), b"6789");
+        assert_eq!(crc, 0xcbf43926);
+    }
+
+    // The whole gzip stream, fed `piece` bytes at a time as the log
+    // download does; every call stays within its bound
+    fn gzip(data: &[u8], piece: usize) -> Vec<u8> {
+        let mut deflater = Box::new(Deflater::new());
+        let mut out = GZIP_HEADER.to_vec();
+        for chunk in data.chunks(piece) {
+            let before = out.len();
+            deflater.compress(chunk, |b| out.push(b));
+            assert!(out.len() - before <= max_compressed(chunk.len()));
+        }
+        let before = out.len();
+        deflater.finish(|b| out.push(b));
+        assert!(out.len() - before <= FINISH_MAX);
+        out
+    }
+
+    fn gunzip(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[..10], GZIP_HEADER);
+        let (body, trailer) = stream[10..].split_at(stream.len() - 18);
+        let data = miniz_oxide::inflate::decompress_to_vec(body).expect("valid deflate stream");
+        assert_eq!(trailer[..4], crc32_update(0, &data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        data
+    }
+
+    // 伪随机字节, 限定在 lo..=hi
+    fn noise(len: usize, lo: u8, hi: u8) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                lo + ((state >> 16) % ((hi - lo) as u32 + 1)) as u8
+            })
+            .collect()
+    }
+
+    fn inputs() -> Vec<Vec<u8>> {
+        let log = "12:00:01 AT+CSQ\r\n+CSQ: 23,99\r\nOK\r\n12:00:02 AT+CREG?\r\n+CREG: 0,1\r\nOK\r\n";
+        vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"AT\r\n".to_vec(),
+            vec![0; 5000],
+            b"abc".repeat(3000),
+            log.repeat(100).into_bytes(),
+            noise(5000, 0, 255),
+            // 144..=255 的字面量是 9 bit, 最坏情况
+            noise(5000, 144, 255),
+        ]
+    }
+
+    #[test]
+    fn round_trip_through_inflate() {
+        for data in inputs() {
+            for piece in [1, 2, 3, 7, 511, 512, 513, WINDOW_SIZE] {
+                assert_eq!(gunzip(&gzip(&data, piece)), data, "{} bytes in {piece}-byte pieces", data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn matches_reach_into_earlier_pieces() {
+        // 每段都和前一段相同, 只有引用前一段才能压缩
+        let block = noise(512, 0, 255);
+        let data = block.repeat(8);
+        let stream = gzip(&data, 512);
+        assert_eq!(gunzip(&stream), data);
+        assert!(stream.len() < 700, "{} bytes", stream.len());
+
+        // 跨过窗口滑动
+        let data = noise(WINDOW_SIZE + 300, 0, 255).repeat(3);
+        assert_eq!(gunzip(&gzip(&data, 512)), data);
+    }
+
+    #[test]
+    fn reset_starts_a_new_stream() {
+        let mut deflater = Box::new(Deflater::new());
+        let mut first = Vec::new();
+        deflater.compress(b"first download", |b| first.push(b));
+        deflater.reset();
+        let mut out = GZIP_HEADER.to_vec();
+        deflater.compress(b"second", |b| out.push(b));
+        deflater.finish(|b| out.push(b));
+        assert_eq!(gunzip(&out), b"second");
+    }
+}
@@ -0,0 +1,246 @@
+// 主机名解析缓存
+//
+// A cellular DNS lookup costs seconds, so fetches look the host up here
+// first. Entries keep up to two addresses for the TTL the module reported
+// (SIMCom reports none, DEFAULT_TTL_S applies); a failed lookup is kept for
+// NEGATIVE_TTL_S so a mistyped hostname is not resolved again on every
+// retry. Expired entries stay until they are looked up or evicted.
+
+use crate::json;
+
+pub const CAPACITY: usize = 8;
+pub const MAX_ADDRESSES: usize = 2;
+const DEFAULT_TTL_S: u32 = 300;
+// 模块可能报很长的 TTL, 最多缓存一小时
+const MAX_TTL_S: u32 = 3600;
+const NEGATIVE_TTL_S: u32 = 60;
+
+pub type Host = heapless::String<64>;
+pub type Address = heapless::String<16>;
+
+pub struct Entry {
+    pub host: Host,
+    // empty for a cached failure
+    pub addresses: heapless::Vec<Address, MAX_ADDRESSES>,
+    pub expires_ms: u64,
+}
+
+pub enum Lookup {
+    Hit(Address),
+    // resolution failed recently
+    Failed,
+    Miss,
+}
+
+pub struct Cache {
+    entries: heapless::Vec<Entry, CAPACITY>,
+    pub hits: u32,
+    pub misses: u32,
+}
+
+impl Cache {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn lookup(&mut self, host: &str, now_ms: u64) -> Lookup {
+        let entry = self
+            .entries
+            .iter()
+            .find(|e| e.host.eq_ignore_ascii_case(host) && e.expires_ms > now_ms);
+        let Some(entry) = entry else {
+            self.misses += 1;
+            return Lookup::Miss;
+        };
+        self.hits += 1;
+        match entry.addresses.first() {
+            Some(address) => Lookup::Hit(address.clone()),
+            None => Lookup::Failed,
+        }
+    }
+
+    // Addresses from a successful lookup; an empty list records a failure
+    pub fn insert(&mut self, host: &str, addresses: &[Address], ttl_s: Option<u32>, now_ms: u64) {
+        let Ok(host) = Host::try_from(host) else {
+            return;
+        };
+        let ttl_s = if addresses.is_empty() {
+            NEGATIVE_TTL_S
+        } else {
+            ttl_s.unwrap_or(DEFAULT_TTL_S).min(MAX_TTL_S)
+        };
+        let entry = Entry {
+            host,
+            addresses: addresses.iter().take(MAX_ADDRESSES).cloned().collect(),
+            expires_ms: now_ms + ttl_s as u64 * 1000,
+        };
+
+        // 同名条目直接替换, 否则挤掉最早过期的
+        let slot = self
+            .entries
+            .iter()
+            .position(|e| e.host.eq_ignore_ascii_case(&entry.host))
+            .or_else(|| self.entries.is_full().then(|| self.soonest_expiring()).flatten());
+        match slot {
+            Some(index) => self.entries[index] = entry,
+            None => {
+                let _ = self.entries.push(entry);
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> usize {
+        let flushed = self.entries.len();
+        self.entries.clear();
+        flushed
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    fn soonest_expiring(&self) -> Option<usize> {
+        (0..self.entries.len()).min_by_key(|&i| self.entries[i].expires_ms)
+    }
+}
+
+pub fn write_json<const N: usize>(out: &mut heapless::String<N>, cache: &Cache, now_ms: u64) {
+    let mut list = heapless::String::<1536>::new();
+    let _ = list.push('[');
+    for (i, entry) in cache.entries().enumerate() {
+        if i > 0 {
+            let _ = list.push(',');
+        }
+        let mut addresses = heapless::String::<48>::new();
+        let _ = addresses.push('[');
+        for (j, address) in entry.addresses.iter().enumerate() {
+            if j > 0 {
+                let _ = addresses.push(',');
+            }
+            json::push_str_value(&mut addresses, address);
+        }
+        let _ = addresses.push(']');
+
+        let mut obj = json::Object::new(&mut list);
+        obj.str("host", &entry.host)
+            .raw("addresses", &addresses)
+            .bool("negative", entry.addresses.is_empty())
+            .bool("expired", entry.expires_ms <= now_ms)
+            .u32("ttl_s", (entry.expires_ms.saturating_sub(now_ms) / 1000) as u32);
+        obj.finish();
+    }
+    let _ = list.push(']');
+
+    let mut obj = json::Object::new(out);
+    obj.u32("capacity", CAPACITY as u32)
+        .u32("hits", cache.hits)
+        .u32("misses", cache.misses)
+        .raw("entries", &list);
+    obj.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(list: &[&str]) -> std::vec::Vec<Address> {
+        list.iter().map(|a| Address::try_from(*a).unwrap()).collect()
+    }
+
+    fn hit(cache: &mut Cache, host: &str, now_ms: u64) -> Option<std::string::String> {
+        match cache.lookup(host, now_ms) {
+            Lookup::Hit(address) => Some(address.as_str().into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn hit_until_the_ttl_runs_out() {
+        let mut cache = Cache::new();
+        assert!(matches!(cache.lookup("example.com", 0), Lookup::Miss));
+        cache.insert("example.com", &addresses(&["93.184.216.34", "93.184.216.35"]), Some(10), 1_000);
+        assert_eq!(hit(&mut cache, "example.com", 1_000).as_deref(), Some("93.184.216.34"));
+        // 主机名不分大小写
+        assert_eq!(hit(&mut cache, "EXAMPLE.com", 10_999).as_deref(), Some("93.184.216.34"));
+        assert!(matches!(cache.lookup("example.com", 11_000), Lookup::Miss));
+        assert_eq!((cache.hits, cache.misses), (2, 2));
+    }
+
+    #[test]
+    fn ttl_defaults_and_caps() {
+        let mut cache = Cache::new();
+        cache.insert("a.test", &addresses(&["10.0.0.1"]), None, 0);
+        cache.insert("b.test", &addresses(&["10.0.0.2"]), Some(86_400), 0);
+        let expiries: std::vec::Vec<u64> = cache.entries().map(|e| e.expires_ms).collect();
+        assert_eq!(expiries, [DEFAULT_TTL_S as u64 * 1000, MAX_TTL_S as u64 * 1000]);
+    }
+
+    #[test]
+    fn failures_are_kept_briefly() {
+        let mut cache = Cache::new();
+        // the module's TTL does not apply to a failure
+        cache.insert("typo.test", &[], Some(3_600), 0);
+        assert!(matches!(cache.lookup("typo.test", 59_999), Lookup::Failed));
+        assert!(matches!(cache.lookup("typo.test", 60_000), Lookup::Miss));
+        // a later success replaces the failure
+        cache.insert("typo.test", &addresses(&["10.0.0.9"]), None, 60_000);
+        assert_eq!(hit(&mut cache, "Typo.Test", 60_000).as_deref(), Some("10.0.0.9"));
+        assert_eq!(cache.entries().count(), 1);
+    }
+
+    #[test]
+    fn at_most_two_addresses_and_no_long_names() {
+        let mut cache = Cache::new();
+        cache.insert("many.test", &addresses(&["10.0.0.1", "10.0.0.2", "10.0.0.3"]), None, 0);
+        assert_eq!(cache.entries().next().unwrap().addresses.len(), MAX_ADDRESSES);
+        // 超过 64 字节的主机名放不下, 不缓存
+        let long = "a".repeat(65);
+        cache.insert(&long, &addresses(&["10.0.0.4"]), None, 0);
+        assert!(matches!(cache.lookup(&long, 0), Lookup::Miss));
+        assert_eq!(cache.entries().count(), 1);
+    }
+
+    #[test]
+    fn full_cache_evicts_the_soonest_expiring() {
+        let mut cache = Cache::new();
+        for i in 0..CAPACITY {
+            let host = std::format!("h{i}.test");
+            // h3 expires first
+            let ttl = if i == 3 { 5 } else { 100 + i as u32 };
+            cache.insert(&host, &addresses(&["10.0.0.1"]), Some(ttl), 0);
+        }
+        cache.insert("new.test", &addresses(&["10.0.0.2"]), None, 0);
+        assert_eq!(cache.entries().count(), CAPACITY);
+        assert!(matches!(cache.lookup("h3.test", 0), Lookup::Miss));
+        assert!(matches!(cache.lookup("h4.test", 0), Lookup::Hit(_)));
+        assert_eq!(hit(&mut cache, "new.test", 0).as_deref(), Some("10.0.0.2"));
+        assert_eq!(cache.flush(), CAPACITY);
+        assert_eq!(cache.entries().count(), 0);
+    }
+
+    #[test]
+    fn json_lists_entries() {
+        let mut cache = Cache::new();
+        cache.insert("example.com", &addresses(&["93.184.216.34", "93.184.216.35"]), Some(10), 0);
+        cache.insert("typo.test", &[], None, 0);
+        cache.lookup("example.com", 1_500);
+        let mut out = heapless::String::<512>::new();
+        write_json(&mut out, &cache, 1_500);
+        assert_eq!(
+            out.as_str(),
+            concat!(
+                r#"{"capacity":8,"hits":1,"misses":0,"entries":["#,
+                r#"{"host":"example.com","addresses":["93.184.216.34","93.184.216.35"],"#,
+                r#""negative":false,"expired":false,"ttl_s":8},"#,
+                r#"{"host":"typo.test","addresses":[],"negative":true,"expired":false,"ttl_s":58}]}"#
+            )
+        );
+        let mut out = heapless::String::<512>::new();
+        write_json(&mut out, &cache, 10_000);
+        assert!(out.contains(r#""expired":true,"ttl_s":0"#));
+    }
+}
@@ -0,0 +1,113 @@
+// 连续获取失败时逐级升级的恢复步骤
+//
+// Each failed fetch moves the ladder up: at 3 failures in a row the cached
+// addresses are dropped and the next fetch resolves the host afresh, at 5
+// the data context is torn down and activated again, at 8 the module is
+// restarted. Each step is followed by one retry. If fetches still fail
+// after the restart, a retry runs once an hour until one succeeds. A
+// single success resets everything.
+
+pub const BACKOFF_MS: u64 = 60 * 60 * 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    ReResolve,
+    ResetPdp,
+    RestartModem,
+    // hourly retries after a full modem restart did not help
+    Backoff,
+}
+
+impl Step {
+    pub const ALL: [Step; 4] = [Step::ReResolve, Step::ResetPdp, Step::RestartModem, Step::Backoff];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Step::ReResolve => "re_resolve",
+            Step::ResetPdp => "reset_pdp",
+            Step::RestartModem => "restart_modem",
+            Step::Backoff => "backoff",
+        }
+    }
+
+    // Consecutive failures that trigger this step
+    pub fn threshold(self) -> u32 {
+        match self {
+            Step::ReResolve => 3,
+            Step::ResetPdp => 5,
+            Step::RestartModem => 8,
+            Step::Backoff => 9,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Attempt {
+    pub step: Step,
+    pub at_ms: u64,
+    // None while the step is still running
+    pub ok: Option<bool>,
+}
+
+pub struct Ladder {
+    pub failures: u32,
+    // times each step ran, in Step::ALL order
+    pub counts: [u32; 4],
+    pub last: Option<Attempt>,
+    next_retry_ms: Option<u64>,
+}
+
+impl Ladder {
+    pub const fn new() -> Self {
+        Self {
+            failures: 0,
+            counts: [0; 4],
+            last: None,
+            next_retry_ms: None,
+        }
+    }
+
+    // A fetch failed; the step to take now, if any. In backoff the next
+    // hourly retry is scheduled instead.
+    pub fn failed(&mut self, now_ms: u64) -> Option<Step> {
+        self.failures += 1;
+        if self.failures > Step::Backoff.threshold() {
+            self.next_retry_ms = Some(now_ms + BACKOFF_MS);
+            return None;
+        }
+        let step = Step::ALL.into_iter().find(|s| s.threshold() == self.failures)?;
+        self.counts[step as usize] += 1;
+        self.last = Some(Attempt { step, at_ms: now_ms, ok: None });
+        if step == Step::Backoff {
+            self.next_retry_ms = Some(now_ms + BACKOFF_MS);
+        }
+        Some(step)
+    }
+
+    // True when the ladder had started climbing
+    pub fn succeeded(&mut self) -> bool {
+        let escalated = self.failures >= Step::ReResolve.threshold();
+        self.failures = 0;
+        self.next_retry_ms = None;
+        escalated
+    }
+
+    pub fn finished(&mut self, step: Step, ok: bool) {
+        if let Some(last) = self.last.as_mut().filter(|l| l.step == step) {
+            last.ok = Some(ok);
+        }
+    }
+
+    pub fn in_backoff(&self) -> bool {
+        self.failures >= Step::Backoff.threshold()
+    }
+
+    pub fn retry_due_ms(&self) -> Option<u64> {
+        self.next_retry_ms
+    }
+
+    // The hourly retry was queued
+    pub fn retry_started(&mut self) {
+        self.next_retry_ms = None;
+    }
+}
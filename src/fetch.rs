@@ -0,0 +1,1140 @@
+// 通过蜂窝模块的 TCP 指令获取一次 HTTP 响应
+//
+// Only the transition logic lives here; commands and replies come from the
+// `CellularModem` backend and the caller owns the UART. For each
+// phase the driver writes `command()`, calls `sent()`, then feeds every
+// received line to `on_line()` until the phase changes or `timeout_ms()`
+// runs out (`on_timeout()`). `Step::ReadData(n)` means the next n raw bytes
+// are response payload, to be passed to `on_data()`; line feeding resumes
+// after them.
+//
+// A complete HTTP/1.1 response without `Connection: close` leaves the
+// socket open (`kept()`), and the next fetch to the same host and port can
+// start at the send (`reuse_connection()`). If that socket turns out to be
+// dead before anything was received, it is closed and a fresh one opened.
+//
+// The payload waits in the module (buffer access mode) until read with
+// `tcp_recv`, at most READ_CHUNK at a time, so nothing is lost while the
+// firmware is busy elsewhere. After the send, and after a read that found
+// nothing, the fetch waits for the module's "data arrived" notice instead
+// of polling. `ReceiveMode::DirectPush` has the module push the payload as
+// it arrives; it is there for debugging only.
+
+use core::fmt::Write as _;
+
+use crate::at_response::{self, Kind};
+use crate::modem::{CellularModem, DnsReply, RecvNotice};
+
+pub const CONNECT_ID: u8 = 0;
+pub const READ_CHUNK: usize = 500;
+// A kept connection unused this long is closed
+pub const KEEP_IDLE_MS: u64 = 30_000;
+pub const HISTORY_LEN: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    Resolve,
+    Open,
+    AwaitOpenUrc,
+    SendLen,
+    AwaitPrompt,
+    SendBody,
+    // waiting for the module to report data
+    AwaitData,
+    Receive,
+    Close,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Resolve => "resolve",
+            Phase::Open => "open",
+            Phase::AwaitOpenUrc => "await_open",
+            Phase::SendLen => "send_len",
+            Phase::AwaitPrompt => "await_prompt",
+            Phase::SendBody => "send_body",
+            Phase::AwaitData => "await_data",
+            Phase::Receive => "receive",
+            Phase::Close => "close",
+        }
+    }
+
+    pub fn timeout_ms(self) -> u32 {
+        match self {
+            Phase::Resolve => 20_000,
+            Phase::Open => 5_000,
+            Phase::AwaitOpenUrc => 15_000,
+            Phase::SendLen => 1_000,
+            Phase::AwaitPrompt => 5_000,
+            Phase::SendBody => 10_000,
+            Phase::AwaitData => 10_000,
+            Phase::Receive => 5_000,
+            Phase::Close => 5_000,
+        }
+    }
+}
+
+// 谁发起的获取
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Origin {
+    // the button on the status page (GET /http_get)
+    Web,
+    // a fetch held back by a locked SIM, resumed after unlocking
+    SimUnlock,
+    Webhook,
+    // the retry after a recovery step (see escalation)
+    Recovery,
+    // a GPIO input trigger (see trigger)
+    Input,
+    // GET /relay, streamed on to a WiFi client (see relay)
+    Relay,
+}
+
+impl Origin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Origin::Web => "web",
+            Origin::SimUnlock => "sim_unlock",
+            Origin::Webhook => "webhook",
+            Origin::Recovery => "recovery",
+            Origin::Input => "input",
+            Origin::Relay => "relay",
+        }
+    }
+
+    // Someone is watching the page for the result, so it may be cancelled
+    pub fn interactive(self) -> bool {
+        matches!(self, Origin::Web | Origin::SimUnlock)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cancel {
+    // POST /api/fetch/cancel
+    Requested,
+    // nobody looked at the result for fetch.abandon_s
+    Abandoned,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    ResolveFailed,
+    OpenRejected,
+    // error code reported by the module (Quectel +QIOPEN, SIMCom CONNECT FAIL = 1)
+    OpenFailed(u16),
+    SendRejected,
+    SendFailed,
+    // peer closed before sending anything
+    ClosedEarly,
+    Timeout(Phase),
+    Uart,
+    // the TX buffer did not drain in time
+    UartStalled,
+    Cancelled(Cancel),
+}
+
+impl Error {
+    pub fn describe<const N: usize>(&self, out: &mut heapless::String<N>) {
+        let _ = match self {
+            Error::ResolveFailed => out.push_str("DNS lookup failed"),
+            Error::OpenRejected => out.push_str("TCP open rejected"),
+            Error::OpenFailed(code) => core::write!(out, "TCP connection failed (error {})", code).map_err(|_| ()),
+            Error::SendRejected => out.push_str("send command rejected"),
+            Error::SendFailed => out.push_str("SEND FAIL"),
+            Error::ClosedEarly => out.push_str("connection closed before any data"),
+            Error::Timeout(Phase::AwaitPrompt) => out.push_str("No '>' prompt received"),
+            Error::Timeout(phase) => core::write!(out, "timeout in {}", phase.as_str()).map_err(|_| ()),
+            Error::Uart => out.push_str("UART write error"),
+            Error::UartStalled => out.push_str("UART TX stalled"),
+            Error::Cancelled(Cancel::Requested) => out.push_str("cancelled"),
+            Error::Cancelled(Cancel::Abandoned) => out.push_str("cancelled, nobody was viewing the result"),
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Step {
+    // keep feeding lines to the current phase
+    Wait,
+    // a new phase started: write its command
+    Enter,
+    ReadData(usize),
+    Done,
+    Failed(Error),
+}
+
+// 响应数据怎么从模块读出来
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReceiveMode {
+    // held by the module until read
+    Buffered,
+    // pushed by the module as it arrives; a busy UART task loses data
+    DirectPush,
+}
+
+impl ReceiveMode {
+    // `direct_push`: config fetch.direct_push; modules without that mode
+    // stay buffered
+    pub fn select(modem: &dyn CellularModem, direct_push: bool) -> Self {
+        if direct_push && modem.supports_direct_push() {
+            ReceiveMode::DirectPush
+        } else {
+            ReceiveMode::Buffered
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReceiveMode::Buffered => "buffered",
+            ReceiveMode::DirectPush => "direct_push",
+        }
+    }
+}
+
+// 这次获取用的连接
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Connection {
+    New,
+    Reused,
+    // the kept connection was dead, a new one was opened
+    Reopened,
+}
+
+impl Connection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Connection::New => "new",
+            Connection::Reused => "reused",
+            Connection::Reopened => "reopened",
+        }
+    }
+}
+
+// What the response head says about where the body ends and whether the
+// server keeps the connection
+struct ResponseHead {
+    line: heapless::Vec<u8, 128>,
+    status_seen: bool,
+    done: bool,
+    http11: bool,
+    close: bool,
+    // None: chunked or unknown, the body ends when the server closes
+    content_length: Option<u32>,
+    body: u32,
+}
+
+impl ResponseHead {
+    fn new() -> Self {
+        Self {
+            line: heapless::Vec::new(),
+            status_seen: false,
+            done: false,
+            http11: false,
+            close: false,
+            content_length: None,
+            body: 0,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        for &b in data {
+            if self.done {
+                self.body += 1;
+            } else if b == b'\n' {
+                self.end_line();
+                self.line.clear();
+            } else if b != b'\r' {
+                // over-long header lines are cut; nothing we read is that long
+                let _ = self.line.push(b);
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = core::str::from_utf8(&self.line).unwrap_or("");
+        if !self.status_seen {
+            self.status_seen = true;
+            self.http11 = line.starts_with("HTTP/1.1 ");
+            // no body, whatever the headers say
+            let status = line.split(' ').nth(1).unwrap_or("");
+            if status.starts_with('1') || status == "204" || status == "304" {
+                self.content_length = Some(0);
+            }
+            return;
+        }
+        if line.is_empty() {
+            self.done = true;
+            return;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("connection") {
+            self.close = value.eq_ignore_ascii_case("close");
+        } else if name.eq_ignore_ascii_case("content-length") && self.content_length.is_none() {
+            self.content_length = value.parse().ok();
+        }
+    }
+
+    fn complete(&self) -> bool {
+        self.done && self.content_length.is_some_and(|len| self.body >= len)
+    }
+
+    fn keeps_connection(&self) -> bool {
+        self.complete() && self.http11 && !self.close
+    }
+}
+
+pub struct Target<'a> {
+    pub host: &'a str,
+    // skips the DNS lookup when known
+    pub ip: Option<&'a str>,
+    pub port: u16,
+    pub request: &'a [u8],
+}
+
+pub struct Fetch<'a> {
+    modem: &'a dyn CellularModem,
+    target: Target<'a>,
+    phase: Phase,
+    ip: heapless::String<16>,
+    // addresses from our own lookup, for the DNS cache
+    resolved: heapless::Vec<heapless::String<16>, 2>,
+    // address lines still to come
+    awaiting: usize,
+    ttl_s: Option<u32>,
+    received: u32,
+    last_read: usize,
+    mode: ReceiveMode,
+    // a "data arrived" notice came while a read was under way
+    readable: bool,
+    peer_closed: bool,
+    head: ResponseHead,
+    // sending on the connection the previous fetch kept open
+    reused: bool,
+    // the kept connection was dead; closing it before opening a new one
+    reopening: bool,
+    reopened: bool,
+    kept: bool,
+}
+
+impl<'a> Fetch<'a> {
+    pub fn new(modem: &'a dyn CellularModem, target: Target<'a>) -> Self {
+        let mut ip = heapless::String::new();
+        if let Some(known) = target.ip {
+            let _ = ip.push_str(known);
+        }
+        Self {
+            modem,
+            target,
+            phase: Phase::Resolve,
+            ip,
+            resolved: heapless::Vec::new(),
+            awaiting: 1,
+            ttl_s: None,
+            received: 0,
+            last_read: 0,
+            mode: ReceiveMode::Buffered,
+            readable: false,
+            peer_closed: false,
+            head: ResponseHead::new(),
+            reused: false,
+            reopening: false,
+            reopened: false,
+            kept: false,
+        }
+    }
+
+    // Start at the send, on the connection the previous fetch to the same
+    // host and port kept open
+    pub fn reuse_connection(&mut self) {
+        self.reused = true;
+        self.phase = Phase::SendLen;
+    }
+
+    // Before the first command; a reused connection keeps the mode it was
+    // opened with (`Kept::serves`)
+    pub fn set_receive_mode(&mut self, mode: ReceiveMode) {
+        self.mode = mode;
+    }
+
+    pub fn receive_mode(&self) -> ReceiveMode {
+        self.mode
+    }
+
+    pub fn destination(&self) -> (&'a str, u16) {
+        (self.target.host, self.target.port)
+    }
+
+    // The path in the request line, "/" when it cannot be read
+    pub fn path(&self) -> &'a str {
+        let line = self.target.request.split(|&b| b == b'\r').next().unwrap_or(&[]);
+        core::str::from_utf8(line).ok().and_then(|l| l.split(' ').nth(1)).unwrap_or("/")
+    }
+
+    pub fn connection(&self) -> Connection {
+        if self.reused {
+            Connection::Reused
+        } else if self.reopened {
+            Connection::Reopened
+        } else {
+            Connection::New
+        }
+    }
+
+    // The fetch finished and left the socket open for the next one
+    pub fn kept(&self) -> bool {
+        self.kept
+    }
+
+    // The modem reported the peer closing the connection
+    pub fn peer_closed(&self) -> bool {
+        self.peer_closed
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    // Addresses and TTL of the lookup this fetch made, empty when the
+    // address was given or the lookup did not finish
+    pub fn resolved(&self) -> (&[heapless::String<16>], Option<u32>) {
+        (&self.resolved, self.ttl_s)
+    }
+
+    // True once the connect command was issued, i.e. a failure still needs a close
+    pub fn needs_close(&self) -> bool {
+        !matches!(self.phase, Phase::Resolve | Phase::Close)
+    }
+
+    pub fn timeout_ms(&self) -> u32 {
+        self.phase.timeout_ms()
+    }
+
+    pub fn command<const N: usize>(&self, out: &mut heapless::Vec<u8, N>) {
+        out.clear();
+        let modem = self.modem;
+        let line = match self.phase {
+            Phase::Resolve if self.ip.is_empty() => modem.resolve_dns(self.target.host),
+            Phase::Open => match self.mode {
+                ReceiveMode::Buffered => modem.tcp_connect(CONNECT_ID, &self.ip, self.target.port),
+                ReceiveMode::DirectPush => modem.tcp_connect_direct(CONNECT_ID, &self.ip, self.target.port),
+            },
+            Phase::SendLen => modem.tcp_send(CONNECT_ID, self.target.request.len()),
+            Phase::SendBody => {
+                let _ = out.extend_from_slice(self.target.request);
+                return;
+            }
+            Phase::Receive => modem.tcp_recv(CONNECT_ID, READ_CHUNK),
+            Phase::Close => modem.tcp_close(CONNECT_ID),
+            _ => return,
+        };
+        let _ = out.extend_from_slice(line.as_bytes());
+    }
+
+    pub fn sent(&mut self) -> Step {
+        match self.phase {
+            Phase::Resolve if !self.ip.is_empty() => self.enter(Phase::Open),
+            Phase::SendLen => self.enter(Phase::AwaitPrompt),
+            _ => Step::Wait,
+        }
+    }
+
+    // The "> " prompt arrived (at_response::next_awaited)
+    pub fn on_prompt(&mut self) -> Step {
+        match self.phase {
+            Phase::AwaitPrompt => self.enter(Phase::SendBody),
+            _ => Step::Wait,
+        }
+    }
+
+    pub fn on_line(&mut self, line: &str) -> Step {
+        let line = line.trim();
+        if line.is_empty() {
+            return Step::Wait;
+        }
+        if self.modem.parse_closed(line, CONNECT_ID) {
+            self.peer_closed = true;
+            return match self.phase {
+                // the module may still hold data that came before the close
+                Phase::AwaitData if self.mode == ReceiveMode::Buffered => self.enter(Phase::Receive),
+                Phase::AwaitData => self.after_read(),
+                _ => Step::Wait,
+            };
+        }
+        if let Some((CONNECT_ID, notice)) = self.modem.parse_recv_notice(line) {
+            return match notice {
+                RecvNotice::Readable if self.phase == Phase::AwaitData => self.enter(Phase::Receive),
+                RecvNotice::Readable => {
+                    self.readable = true;
+                    Step::Wait
+                }
+                RecvNotice::Pushed(len) => {
+                    self.received += len as u32;
+                    Step::ReadData(len)
+                }
+            };
+        }
+        let kind = at_response::classify(line);
+
+        match self.phase {
+            Phase::Resolve => match self.modem.parse_dns(line) {
+                Some(DnsReply::Address(ip)) => {
+                    if self.ip.is_empty() {
+                        let _ = self.ip.push_str(ip);
+                    }
+                    if let Ok(ip) = heapless::String::try_from(ip) {
+                        let _ = self.resolved.push(ip);
+                    }
+                    // 等到所有地址 (最多两个) 都到了再连接
+                    self.awaiting = self.awaiting.saturating_sub(1);
+                    if self.awaiting == 0 {
+                        self.enter(Phase::Open)
+                    } else {
+                        Step::Wait
+                    }
+                }
+                Some(DnsReply::Failed) => Step::Failed(Error::ResolveFailed),
+                Some(DnsReply::Pending { count, ttl_s }) => {
+                    self.awaiting = count.clamp(1, self.resolved.capacity());
+                    self.ttl_s = ttl_s;
+                    Step::Wait
+                }
+                None if kind == Kind::Failure => Step::Failed(Error::ResolveFailed),
+                None => Step::Wait,
+            },
+            Phase::Open => match kind {
+                Kind::Success => self.enter(Phase::AwaitOpenUrc),
+                Kind::Failure => Step::Failed(Error::OpenRejected),
+                // some modules report the result without a separate OK
+                _ => match self.modem.parse_connect(line, CONNECT_ID) {
+                    Some(0) => self.enter(Phase::SendLen),
+                    Some(code) => Step::Failed(Error::OpenFailed(code)),
+                    None => Step::Wait,
+                },
+            },
+            Phase::AwaitOpenUrc => match self.modem.parse_connect(line, CONNECT_ID) {
+                Some(0) => self.enter(Phase::SendLen),
+                Some(code) => Step::Failed(Error::OpenFailed(code)),
+                None => Step::Wait,
+            },
+            Phase::SendLen => Step::Wait,
+            // the prompt itself comes through on_prompt
+            Phase::AwaitPrompt if kind == Kind::Failure => self.stale_or(Error::SendRejected),
+            Phase::AwaitPrompt => Step::Wait,
+            Phase::SendBody => match self.modem.parse_send(line, CONNECT_ID) {
+                Some(true) => self.await_data(),
+                Some(false) => self.stale_or(Error::SendFailed),
+                None if kind == Kind::Failure => self.stale_or(Error::SendFailed),
+                None => Step::Wait,
+            },
+            Phase::Receive => {
+                if let Some(len) = self.modem.parse_recv(line) {
+                    self.last_read = len;
+                    if len > 0 {
+                        self.received += len as u32;
+                        return Step::ReadData(len);
+                    }
+                    return Step::Wait;
+                }
+                match kind {
+                    Kind::Success => self.after_read(),
+                    Kind::Failure if self.received > 0 => self.enter(Phase::Close),
+                    Kind::Failure => self.stale_or(Error::ClosedEarly),
+                    _ => Step::Wait,
+                }
+            }
+            Phase::AwaitData => Step::Wait,
+            // "OK" or "<id>, CLOSE OK" depending on the module
+            Phase::Close if line.ends_with("CLOSE OK") || kind.is_final() => self.closed(),
+            Phase::Close => Step::Wait,
+        }
+    }
+
+    pub fn on_timeout(&mut self) -> Step {
+        match self.phase {
+            // the socket is gone either way
+            Phase::Close => self.closed(),
+            Phase::AwaitData | Phase::Receive if self.received > 0 => self.enter(Phase::Close),
+            phase @ (Phase::SendLen | Phase::AwaitPrompt | Phase::SendBody) => self.stale_or(Error::Timeout(phase)),
+            phase => Step::Failed(Error::Timeout(phase)),
+        }
+    }
+
+    // Payload of the last `Step::ReadData`
+    pub fn on_data(&mut self, data: &[u8]) -> Step {
+        self.head.feed(data);
+        match self.phase {
+            // pushed data: no OK follows
+            Phase::AwaitData => self.after_read(),
+            _ => Step::Wait,
+        }
+    }
+
+    // One read round trip finished
+    fn after_read(&mut self) -> Step {
+        if self.head.complete() {
+            if self.head.keeps_connection() && !self.peer_closed {
+                self.kept = true;
+                return Step::Done;
+            }
+            return self.enter(Phase::Close);
+        }
+        if self.last_read > 0 {
+            return self.enter(Phase::Receive);
+        }
+        if self.peer_closed {
+            return if self.received > 0 {
+                self.enter(Phase::Close)
+            } else {
+                self.stale_or(Error::ClosedEarly)
+            };
+        }
+        self.await_data()
+    }
+
+    // Read at once when the module already reported data, else wait for it
+    fn await_data(&mut self) -> Step {
+        if self.readable {
+            self.enter(Phase::Receive)
+        } else {
+            self.enter(Phase::AwaitData)
+        }
+    }
+
+    // A kept connection failing before any reply was closed by the server
+    // while idle: close our end and start over on a new one
+    fn stale_or(&mut self, error: Error) -> Step {
+        if !self.reused || self.received > 0 {
+            return Step::Failed(error);
+        }
+        self.reused = false;
+        self.reopening = true;
+        self.reopened = true;
+        self.peer_closed = false;
+        self.readable = false;
+        self.enter(Phase::Close)
+    }
+
+    fn closed(&mut self) -> Step {
+        if self.reopening {
+            self.reopening = false;
+            return self.enter(Phase::Resolve);
+        }
+        Step::Done
+    }
+
+    fn enter(&mut self, phase: Phase) -> Step {
+        self.phase = phase;
+        self.last_read = 0;
+        if phase == Phase::Receive {
+            self.readable = false;
+        }
+        Step::Enter
+    }
+}
+
+// The fetch socket a keep-alive response left open
+pub struct Kept {
+    pub host: heapless::String<64>,
+    pub port: u16,
+    pub mode: ReceiveMode,
+    pub idle_since_ms: u64,
+}
+
+impl Kept {
+    pub fn new(host: &str, port: u16, mode: ReceiveMode, now_ms: u64) -> Option<Self> {
+        Some(Self {
+            host: heapless::String::try_from(host).ok()?,
+            port,
+            mode,
+            idle_since_ms: now_ms,
+        })
+    }
+
+    pub fn serves(&self, host: &str, port: u16, mode: ReceiveMode) -> bool {
+        self.host == host && self.port == port && self.mode == mode
+    }
+
+    pub fn close_at_ms(&self) -> u64 {
+        self.idle_since_ms + KEEP_IDLE_MS
+    }
+}
+
+// 最近几次获取, 工具页显示
+#[derive(Clone, Copy)]
+pub struct Record {
+    pub at_ms: u64,
+    pub origin: Origin,
+    // X-Request-Id of the request that queued it
+    pub request_id: Option<u32>,
+    pub connection: Connection,
+    pub elapsed_ms: u32,
+    pub received: u32,
+    // its body in the response archive, when it was kept
+    pub response: Option<u32>,
+    pub error: Option<Error>,
+}
+
+pub struct History {
+    records: [Option<Record>; HISTORY_LEN],
+    next: usize,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        Self {
+            records: [None; HISTORY_LEN],
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, record: Record) {
+        self.records[self.next] = Some(record);
+        self.next = (self.next + 1) % HISTORY_LEN;
+    }
+
+    // Newest first
+    pub fn iter(&self) -> impl Iterator<Item = &Record> + '_ {
+        (1..=HISTORY_LEN).filter_map(move |back| self.records[(self.next + HISTORY_LEN - back) % HISTORY_LEN].as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modem::{Quectel, Simcom};
+    use crate::urc;
+
+    // What the fake module does, in order
+    #[derive(Clone, Copy)]
+    enum Ev {
+        Line(&'static str),
+        Prompt,
+        // raw payload after a ReadData step
+        Data(&'static [u8]),
+        Timeout,
+    }
+    use Ev::*;
+
+    struct Run {
+        result: Result<(), Error>,
+        // every command written, with the phase it was written in
+        commands: Vec<(Phase, String)>,
+        // every phase entered, in order
+        phases: Vec<Phase>,
+    }
+
+    // Drives `fetch` the way the UART task does (see the module comment),
+    // taking the module's side from `script`
+    fn drive(fetch: &mut Fetch, script: &[Ev]) -> Run {
+        let mut run = Run {
+            result: Ok(()),
+            commands: Vec::new(),
+            phases: vec![fetch.phase()],
+        };
+        let mut events = script.iter();
+        let mut step = Step::Enter;
+        loop {
+            step = match step {
+                Step::Enter => {
+                    if run.phases.last() != Some(&fetch.phase()) {
+                        run.phases.push(fetch.phase());
+                    }
+                    let mut command = heapless::Vec::<u8, 512>::new();
+                    fetch.command(&mut command);
+                    if !command.is_empty() {
+                        run.commands.push((fetch.phase(), String::from_utf8(command.to_vec()).unwrap()));
+                    }
+                    fetch.sent()
+                }
+                Step::ReadData(n) => match events.next() {
+                    Some(Data(data)) if data.len() == n => fetch.on_data(data),
+                    _ => panic!("expected {n} bytes of payload in {:?}", fetch.phase()),
+                },
+                Step::Wait => match events.next() {
+                    Some(Line(line)) => fetch.on_line(line),
+                    Some(Prompt) => fetch.on_prompt(),
+                    Some(Timeout) => fetch.on_timeout(),
+                    Some(Data(_)) => panic!("payload nobody asked for in {:?}", fetch.phase()),
+                    None => panic!("script ran out in {:?}", fetch.phase()),
+                },
+                Step::Done => break,
+                Step::Failed(e) => {
+                    run.result = Err(e);
+                    break;
+                }
+            };
+        }
+        assert!(events.next().is_none(), "script left over after {:?}", run.result);
+        run
+    }
+
+    const REQUEST: &[u8] = b"GET /get HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    const CLOSING: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi";
+    const KEPT: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi";
+
+    fn target(ip: Option<&'static str>) -> Target<'static> {
+        Target {
+            host: "example.com",
+            ip,
+            port: 80,
+            request: REQUEST,
+        }
+    }
+
+    // Connected and sent, waiting for data (Quectel)
+    const SENT: [Ev; 4] = [Line("OK"), Line("+QIOPEN: 0,0"), Prompt, Line("SEND OK")];
+
+    fn script(parts: &[&[Ev]]) -> Vec<Ev> {
+        parts.concat()
+    }
+
+    #[test]
+    fn resolve_to_done() {
+        let mut fetch = Fetch::new(&Quectel, target(None));
+        let run = drive(
+            &mut fetch,
+            &script(&[
+                &[
+                    Line("OK"),
+                    Line("+QIURC: \"dnsgip\",0,2,600"),
+                    Line("+QIURC: \"dnsgip\",\"93.184.216.34\""),
+                    Line("+QIURC: \"dnsgip\",\"93.184.216.35\""),
+                ],
+                &SENT,
+                &[Line("+QIURC: \"recv\",0"), Line("+QIRD: 59"), Data(CLOSING), Line("OK"), Line("OK")],
+            ]),
+        );
+        assert_eq!(run.result, Ok(()));
+        use Phase::*;
+        assert_eq!(
+            run.phases,
+            [Resolve, Open, AwaitOpenUrc, SendLen, AwaitPrompt, SendBody, AwaitData, Receive, Close]
+        );
+        let commands: Vec<_> = run.commands.iter().map(|(_, c)| c.as_str()).collect();
+        assert_eq!(
+            commands,
+            [
+                "AT+QIDNSGIP=1,\"example.com\"\r\n",
+                "AT+QIOPEN=1,0,\"TCP\",\"93.184.216.34\",80,0,0\r\n",
+                "AT+QISEND=0,40\r\n",
+                core::str::from_utf8(REQUEST).unwrap(),
+                "AT+QIRD=0,500\r\n",
+                "AT+QICLOSE=0\r\n",
+            ]
+        );
+        assert_eq!(fetch.received(), 59);
+        assert_eq!(fetch.connection(), Connection::New);
+        assert!(!fetch.kept());
+        let (addresses, ttl) = fetch.resolved();
+        assert_eq!(addresses, ["93.184.216.34", "93.184.216.35"]);
+        assert_eq!(ttl, Some(600));
+    }
+
+    #[test]
+    fn simcom_to_done() {
+        let mut fetch = Fetch::new(&Simcom, target(Some("93.184.216.34")));
+        let run = drive(
+            &mut fetch,
+            &[
+                Line("OK"),
+                Line("0, CONNECT OK"),
+                Prompt,
+                Line("0, SEND OK"),
+                Line("+CIPRXGET: 1,0"),
+                Line("+CIPRXGET: 2,0,59,0"),
+                Data(CLOSING),
+                Line("OK"),
+                Line("0, CLOSE OK"),
+            ],
+        );
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(run.commands.last().unwrap().1, "AT+CIPCLOSE=0\r\n");
+    }
+
+    #[test]
+    fn keep_alive_response_keeps_the_socket() {
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        let run = drive(
+            &mut fetch,
+            &script(&[&SENT, &[Line("+QIURC: \"recv\",0"), Line("+QIRD: 40"), Data(KEPT), Line("OK")]]),
+        );
+        assert_eq!(run.result, Ok(()));
+        assert!(fetch.kept());
+        assert!(run.commands.iter().all(|(phase, _)| *phase != Phase::Close));
+    }
+
+    #[test]
+    fn dead_reused_socket_is_reopened() {
+        // the prompt is refused: the server closed the kept socket while idle
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        fetch.reuse_connection();
+        let run = drive(
+            &mut fetch,
+            &script(&[
+                &[Line("ERROR"), Line("OK")],
+                &SENT,
+                &[Line("+QIURC: \"recv\",0"), Line("+QIRD: 40"), Data(KEPT), Line("OK")],
+            ]),
+        );
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(fetch.connection(), Connection::Reopened);
+        let commands: Vec<_> = run.commands.iter().map(|(phase, _)| *phase).collect();
+        use Phase::*;
+        assert_eq!(commands, [SendLen, Close, Open, SendLen, SendBody, Receive]);
+
+        // the send went out, but the module reports the close before any data
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        fetch.reuse_connection();
+        let run = drive(
+            &mut fetch,
+            &script(&[
+                &[Prompt, Line("SEND OK"), Line("+QIURC: \"closed\",0"), Line("+QIRD: 0"), Line("OK"), Line("OK")],
+                &SENT,
+                &[Line("+QIURC: \"recv\",0"), Line("+QIRD: 40"), Data(KEPT), Line("OK")],
+            ]),
+        );
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(fetch.connection(), Connection::Reopened);
+        // the close notice was for the old socket
+        assert!(!fetch.peer_closed());
+    }
+
+    #[test]
+    fn readable_before_send_ok_skips_await_data() {
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        let run = drive(
+            &mut fetch,
+            &[
+                Line("OK"),
+                Line("+QIOPEN: 0,0"),
+                Prompt,
+                Line("+QIURC: \"recv\",0"),
+                Line("SEND OK"),
+                Line("+QIRD: 59"),
+                Data(CLOSING),
+                Line("OK"),
+                Line("OK"),
+            ],
+        );
+        assert_eq!(run.result, Ok(()));
+        assert!(!run.phases.contains(&Phase::AwaitData));
+        assert!(run.phases.contains(&Phase::Receive));
+
+        // without the early notice the fetch waits for it
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        let run = drive(
+            &mut fetch,
+            &script(&[&SENT, &[Line("+QIURC: \"recv\",0"), Line("+QIRD: 59"), Data(CLOSING), Line("OK"), Line("OK")]]),
+        );
+        assert_eq!(run.result, Ok(()));
+        let at = run.phases.iter().position(|&p| p == Phase::AwaitData).unwrap();
+        assert_eq!(run.phases[at + 1], Phase::Receive);
+    }
+
+    #[test]
+    fn close_before_any_data() {
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        let run = drive(
+            &mut fetch,
+            &script(&[&SENT, &[Line("+QIURC: \"closed\",0"), Line("+QIRD: 0"), Line("OK")]]),
+        );
+        assert_eq!(run.result, Err(Error::ClosedEarly));
+        assert!(fetch.peer_closed());
+        assert!(fetch.needs_close());
+
+        // data that came before the close is still read, then the socket closed
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        let run = drive(
+            &mut fetch,
+            &script(&[
+                &SENT,
+                &[Line("+QIURC: \"closed\",0"), Line("+QIRD: 4"), Data(b"HTTP"), Line("OK")],
+                &[Line("+QIRD: 0"), Line("OK"), Line("OK")],
+            ]),
+        );
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(fetch.received(), 4);
+    }
+
+    #[test]
+    fn timeout_in_each_phase() {
+        const RECV: [Ev; 1] = [Line("+QIURC: \"recv\",0")];
+        let cases: [(Vec<Ev>, Phase); 6] = [
+            (vec![], Phase::Open),
+            (vec![Line("OK")], Phase::AwaitOpenUrc),
+            (vec![Line("OK"), Line("+QIOPEN: 0,0")], Phase::AwaitPrompt),
+            (vec![Line("OK"), Line("+QIOPEN: 0,0"), Prompt], Phase::SendBody),
+            (SENT.to_vec(), Phase::AwaitData),
+            (script(&[&SENT, &RECV]), Phase::Receive),
+        ];
+        for (prefix, phase) in cases {
+            let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+            let run = drive(&mut fetch, &script(&[&prefix, &[Timeout]]));
+            assert_eq!(run.result, Err(Error::Timeout(phase)));
+            assert!(fetch.needs_close());
+        }
+
+        let mut fetch = Fetch::new(&Quectel, target(None));
+        let run = drive(&mut fetch, &[Line("OK"), Timeout]);
+        assert_eq!(run.result, Err(Error::Timeout(Phase::Resolve)));
+        assert!(!fetch.needs_close());
+
+        // with part of the response in, a read timeout closes and keeps it;
+        // a close that is never confirmed still ends the fetch
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        let run = drive(&mut fetch, &script(&[&SENT, &RECV, &[Line("+QIRD: 4"), Data(b"HTTP"), Timeout, Timeout]]));
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(run.phases.last(), Some(&Phase::Close));
+        assert_eq!(fetch.received(), 4);
+
+        // a reused socket that never prompts is reopened instead
+        let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+        fetch.reuse_connection();
+        let run = drive(&mut fetch, &script(&[&[Timeout, Line("OK")], &SENT, &RECV, &[Timeout]]));
+        assert_eq!(run.result, Err(Error::Timeout(Phase::Receive)));
+        assert_eq!(fetch.connection(), Connection::Reopened);
+    }
+
+    // UART captures (host-tests/fixtures/uart) of a fetch of RESPONSE in
+    // two blocks, with URCs between and right after the blocks. The body
+    // itself is full of lines that would end or derail the fetch if it
+    // were ever read as lines.
+    const RESPONSE: &[u8] = include_bytes!("../host-tests/fixtures/uart/response.bin");
+    const FIRST_BLOCK: usize = 70;
+
+    struct Replay {
+        result: Result<(), Error>,
+        payload: Vec<u8>,
+        urcs: Vec<String>,
+    }
+
+    // Replays `reads` the way run_fetch reads the UART: lines (or the
+    // prompt while one is awaited), each URC among them dispatched, until
+    // the fetch asks for payload by length
+    fn replay(fetch: &mut Fetch, reads: &[&[u8]]) -> Replay {
+        let mut reads = reads.iter();
+        let mut pending = Vec::new();
+        let mut out = Replay {
+            result: Ok(()),
+            payload: Vec::new(),
+            urcs: Vec::new(),
+        };
+        let mut step = Step::Enter;
+        loop {
+            step = match step {
+                Step::Enter => {
+                    fetch.command(&mut heapless::Vec::<u8, 512>::new());
+                    fetch.sent()
+                }
+                Step::ReadData(n) => {
+                    while pending.len() < n
+                        && let Some(read) = reads.next()
+                    {
+                        pending.extend_from_slice(read);
+                    }
+                    if pending.len() < n {
+                        fetch.on_timeout()
+                    } else {
+                        let data: Vec<u8> = pending.drain(..n).collect();
+                        out.payload.extend_from_slice(&data);
+                        fetch.on_data(&data)
+                    }
+                }
+                Step::Wait => {
+                    let next = loop {
+                        let got = if fetch.phase() == Phase::AwaitPrompt {
+                            at_response::next_awaited(&pending).map(|(got, used)| match got {
+                                at_response::Awaited::Prompt => (None, used),
+                                at_response::Awaited::Line(line) => (Some(line.to_string()), used),
+                            })
+                        } else {
+                            at_response::next_line(&pending).map(|(line, used)| (Some(line.to_string()), used))
+                        };
+                        if got.is_some() {
+                            break got;
+                        }
+                        match reads.next() {
+                            Some(read) => pending.extend_from_slice(read),
+                            None => break None,
+                        }
+                    };
+                    match next {
+                        Some((None, used)) => {
+                            pending.drain(..used);
+                            fetch.on_prompt()
+                        }
+                        Some((Some(line), used)) => {
+                            pending.drain(..used);
+                            if let Some(urc) = urc::parse(&line) {
+                                out.urcs.push(format!("{urc:?}"));
+                            }
+                            fetch.on_line(&line)
+                        }
+                        None => fetch.on_timeout(),
+                    }
+                }
+                Step::Done => break,
+                Step::Failed(e) => {
+                    out.result = Err(e);
+                    break;
+                }
+            };
+        }
+        assert!(pending.is_empty() && reads.next().is_none(), "capture left over");
+        out
+    }
+
+    // The capture in reads of every size, and cut so that one read holds
+    // the tail of the first block and the start of the line after it
+    fn replay_all(capture: &[u8], new_fetch: impl Fn() -> Fetch<'static>, urcs: &[&str]) {
+        let tail = capture.windows(FIRST_BLOCK).position(|w| w == &RESPONSE[..FIRST_BLOCK]).unwrap() + FIRST_BLOCK;
+        let straddling: [&[u8]; 3] = [&capture[..tail - 5], &capture[tail - 5..tail + 6], &capture[tail + 6..]];
+        let sizes: Vec<Vec<&[u8]>> = (1..=capture.len()).map(|size| capture.chunks(size).collect()).collect();
+        for reads in sizes.iter().map(Vec::as_slice).chain([&straddling[..]]) {
+            let mut fetch = new_fetch();
+            let run = replay(&mut fetch, reads);
+            let first = reads[0].len();
+            assert_eq!(run.result, Ok(()), "reads of {first}");
+            assert!(run.payload == RESPONSE, "reads of {first}: {:?}", String::from_utf8_lossy(&run.payload));
+            assert_eq!(run.urcs, urcs, "reads of {first}");
+            assert_eq!(fetch.received() as usize, RESPONSE.len());
+            // the close notice in the body was payload
+            assert!(!fetch.peer_closed());
+        }
+    }
+
+    #[test]
+    fn captured_buffered_receive_keeps_payload_and_urcs_apart() {
+        replay_all(
+            include_bytes!("../host-tests/fixtures/uart/quectel_buffered.bin"),
+            || Fetch::new(&Quectel, target(Some("93.184.216.34"))),
+            &["Ring", "SmsReceived { storage: \"SM\", index: 3 }", "Ring"],
+        );
+        replay_all(
+            include_bytes!("../host-tests/fixtures/uart/simcom_buffered.bin"),
+            || Fetch::new(&Simcom, target(Some("93.184.216.34"))),
+            &["Ring", "SmsReceived { storage: \"SM\", index: 5 }"],
+        );
+    }
+
+    #[test]
+    fn captured_direct_push_keeps_payload_and_urcs_apart() {
+        replay_all(
+            include_bytes!("../host-tests/fixtures/uart/quectel_direct_push.bin"),
+            || {
+                let mut fetch = Fetch::new(&Quectel, target(Some("93.184.216.34")));
+                fetch.set_receive_mode(ReceiveMode::DirectPush);
+                fetch
+            },
+            &["SmsReceived { storage: \"SM\", index: 4 }", "PdpDeactivated"],
+        );
+    }
+}
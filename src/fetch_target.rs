@@ -0,0 +1,184 @@
+// 获取的目标: /http_get 的 host, port, path 参数
+//
+// The button on the tools page fetches http://httpbin.org/get. /http_get
+// also takes host=, port= and path= to fetch another plain-HTTP target
+// over the same AT sequence; a parameter left out or empty keeps the
+// httpbin value. The values are checked here before any AT command is
+// built from them, since the host goes into a quoted AT+QIOPEN argument
+// and the path into the request line. The target only applies to the
+// fetch started from the page: resumed after a SIM unlock or retried after
+// a recovery step it goes to the same place, while fetches from other
+// origins (an input trigger, say) always go to httpbin.
+
+use core::fmt::Write;
+
+use crate::{at, http};
+
+pub const HOST_MAX: usize = 64;
+pub const PATH_MAX: usize = 96;
+// the request line and headers with the longest host and path
+const REQUEST_MAX: usize = 256;
+
+const DEFAULT_HOST: &str = "httpbin.org";
+const DEFAULT_PATH: &str = "/get";
+// httpbin.org's address, used until a recovery step asks for a fresh lookup
+const DEFAULT_IP: &str = "3.223.36.72";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    // the named parameter has a bad %-escape or does not fit its buffer
+    Encoding(&'static str),
+    Host(at::ArgError),
+    Port,
+    Path,
+}
+
+impl Error {
+    pub fn describe<const N: usize>(&self, out: &mut heapless::String<N>) {
+        match self {
+            Error::Encoding(name) => {
+                let _ = write!(out, "{} is badly %-encoded or too long", name);
+            }
+            Error::Host(e) => {
+                let _ = out.push_str("host: ");
+                e.describe(out);
+            }
+            Error::Port => {
+                let _ = out.push_str("port must be 1-65535");
+            }
+            Error::Path => {
+                let _ = out.push_str("path must start with / and hold no spaces or control characters");
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Target {
+    pub host: heapless::String<HOST_MAX>,
+    pub port: u16,
+    pub path: heapless::String<PATH_MAX>,
+}
+
+impl Target {
+    pub fn httpbin() -> Self {
+        let mut target = Self {
+            host: heapless::String::new(),
+            port: 80,
+            path: heapless::String::new(),
+        };
+        let _ = target.host.push_str(DEFAULT_HOST);
+        let _ = target.path.push_str(DEFAULT_PATH);
+        target
+    }
+
+    // The raw (still %-encoded) query values
+    pub fn from_params(host: Option<&str>, port: Option<&str>, path: Option<&str>) -> Result<Self, Error> {
+        let mut target = Self::httpbin();
+        if let Some(host) = host.filter(|h| !h.is_empty()) {
+            target.host = http::percent_decode(host).ok_or(Error::Encoding("host"))?;
+            at::arg(&target.host, at::Kind::Host).map_err(Error::Host)?;
+        }
+        if let Some(port) = port.filter(|p| !p.is_empty()) {
+            target.port = port.parse().ok().filter(|&p| p > 0).ok_or(Error::Port)?;
+        }
+        if let Some(path) = path.filter(|p| !p.is_empty()) {
+            target.path = http::percent_decode(path).ok_or(Error::Encoding("path"))?;
+            if !target.path.starts_with('/') || target.path.bytes().any(|b| b <= b' ' || b == 0x7f) {
+                return Err(Error::Path);
+            }
+        }
+        Ok(target)
+    }
+
+    // The fixed address when the target is httpbin's HTTP port, so the
+    // fetch can skip DNS; another port there may not be served at all
+    pub fn pinned_ip(&self) -> Option<&'static str> {
+        (self.host == DEFAULT_HOST && self.port == 80).then_some(DEFAULT_IP)
+    }
+
+    pub fn request(&self) -> heapless::String<REQUEST_MAX> {
+        let mut out = heapless::String::new();
+        let _ = write!(out, "GET {} HTTP/1.1\r\nHost: {}", self.path, self.host);
+        if self.port != 80 {
+            let _ = write!(out, ":{}", self.port);
+        }
+        let _ = out.push_str("\r\nUser-Agent: EC800K\r\nAccept: */*\r\n\r\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_and_parameters() {
+        let target = Target::from_params(None, Some(""), None).unwrap();
+        assert_eq!((target.host.as_str(), target.port, target.path.as_str()), ("httpbin.org", 80, "/get"));
+        assert_eq!(target.pinned_ip(), Some("3.223.36.72"));
+
+        assert_eq!(Target::from_params(None, Some("80"), None).unwrap().pinned_ip(), Some("3.223.36.72"));
+        assert_eq!(Target::from_params(None, Some("8080"), None).unwrap().pinned_ip(), None);
+
+        let target = Target::from_params(Some("example.com"), Some("8080"), Some("%2Fa%3Fb%3D1")).unwrap();
+        assert_eq!(target.pinned_ip(), None);
+        assert_eq!(
+            target.request(),
+            "GET /a?b=1 HTTP/1.1\r\nHost: example.com:8080\r\nUser-Agent: EC800K\r\nAccept: */*\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn host_cannot_inject() {
+        for (raw, c) in [("a%0D%0AAT", '\r'), ("a%0Ab", '\n'), ("a%22b", '"'), ("a%3Cb", '<'), ("a+b", ' ')] {
+            let error = Target::from_params(Some(raw), None, None).err();
+            assert_eq!(error, Some(Error::Host(at::ArgError::Forbidden(c))), "{raw}");
+        }
+        assert_eq!(Target::from_params(Some("a%0"), None, None).err(), Some(Error::Encoding("host")));
+    }
+
+    #[test]
+    fn path_cannot_inject() {
+        for raw in ["/a%0D%0AHost:%20x", "/a%0Ab", "/a+b", "/a%20b", "/a b", "/a%09b", "/a%00", "/a%7F", "get"] {
+            assert_eq!(Target::from_params(None, None, Some(raw)).err(), Some(Error::Path), "{raw}");
+        }
+        // no quotes to break out of in the request line
+        let target = Target::from_params(None, None, Some("/%22%3Cb%3E")).unwrap();
+        assert!(target.request().starts_with("GET /\"<b> HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn ports() {
+        for raw in ["0", "65536", "-1", "80a"] {
+            assert_eq!(Target::from_params(None, Some(raw), None).err(), Some(Error::Port), "{raw}");
+        }
+        assert_eq!(Target::from_params(None, Some("65535"), None).unwrap().port, 65535);
+    }
+
+    #[test]
+    fn escapes_and_lengths() {
+        for raw in ["a%", "a%4", "a%G1", "a%ZZ", "%FF", "%C3"] {
+            assert_eq!(Target::from_params(Some(raw), None, None).err(), Some(Error::Encoding("host")), "{raw}");
+            let path = format!("/{raw}");
+            assert_eq!(Target::from_params(None, None, Some(&path)).err(), Some(Error::Encoding("path")), "{raw}");
+        }
+        // %-escapes that decode to UTF-8 are taken
+        let target = Target::from_params(Some("ex%61mple.com"), None, Some("/caf%C3%A9")).unwrap();
+        assert_eq!((target.host.as_str(), target.path.as_str()), ("example.com", "/café"));
+
+        let host = "a".repeat(HOST_MAX);
+        assert_eq!(Target::from_params(Some(&host), None, None).unwrap().host, host.as_str());
+        let host = "a".repeat(HOST_MAX + 1);
+        assert_eq!(Target::from_params(Some(&host), None, None).err(), Some(Error::Encoding("host")));
+        // the escaped form may be longer than the limit, the decoded one not
+        let host = "%61".repeat(HOST_MAX);
+        assert_eq!(Target::from_params(Some(&host), None, None).unwrap().host.len(), HOST_MAX);
+        let path = format!("/{}", "b".repeat(PATH_MAX));
+        assert_eq!(Target::from_params(None, None, Some(&path)).err(), Some(Error::Encoding("path")));
+
+        // the longest target still fits the request buffer
+        let target = Target::from_params(Some(&"a".repeat(HOST_MAX)), Some("65535"), Some(&path[..PATH_MAX])).unwrap();
+        assert!(target.request().ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,287 @@
+// 闪存中的持久记录
+//
+// The firmware is linked into the first 2 MiB of the 4 MiB chip (memory.x),
+// so records live in whole erase sectors counted down from the top. Each
+// record starts with a header (magic, length, checksum); an erased sector or
+// one torn by a reset mid-save reads back as "nothing stored".
+//
+// Below the records, LOG_SECTORS sectors hold modem log checkpoints, used
+// round-robin. Each carries the boot and sequence number it was written
+// in, plus the running count of bytes written and sectors erased, which
+// is how the wear counters survive a reboot. The lifetime statistics take
+// the sector below those.
+
+use embassy_rp::flash::{Blocking, ERASE_SIZE, Error, Flash};
+use embassy_rp::peripherals::FLASH;
+
+pub const FLASH_SIZE: usize = 4 * 1024 * 1024;
+
+// magic[4], length u16, checksum u16
+const HEADER: usize = 8;
+
+// 日志检查点: 从顶部往下第 7..4 个扇区
+pub const LOG_SECTORS: usize = 4;
+const LOG_FIRST_SECTOR: usize = 7;
+const LOG_MAGIC: [u8; 4] = *b"LOG1";
+// magic[4], boot u32, seq u32, length u16, checksum u16, bytes written u64, sectors erased u32
+const LOG_HEADER: usize = 28;
+pub const LOG_CAPACITY: usize = ERASE_SIZE - LOG_HEADER;
+
+#[derive(Clone, Copy)]
+pub struct LogSector {
+    pub boot: u32,
+    pub seq: u32,
+    pub len: usize,
+    // wear counters when the sector was written
+    pub bytes_written: u64,
+    pub sectors_erased: u32,
+}
+
+const fn log_offset(index: usize) -> u32 {
+    (FLASH_SIZE - (LOG_FIRST_SECTOR - index) * ERASE_SIZE) as u32
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Record {
+    Config,
+    Macros,
+    // lifetime statistics, binary
+    Stats,
+}
+
+impl Record {
+    // The text records, erased by a factory reset; the lifetime stats stay
+    pub const ALL: [Record; 2] = [Record::Config, Record::Macros];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Record::Config => "config",
+            Record::Macros => "macros",
+            Record::Stats => "stats",
+        }
+    }
+
+    // (first sector below the top of the chip, sector count)
+    const fn sectors(self) -> (usize, usize) {
+        match self {
+            Record::Config => (3, 1),
+            Record::Macros => (2, 2),
+            // below the log sectors
+            Record::Stats => (8, 1),
+        }
+    }
+
+    // bumped whenever the stored format changes
+    const fn magic(self) -> [u8; 4] {
+        match self {
+            Record::Config => *b"CFG1",
+            Record::Macros => *b"MAC1",
+            Record::Stats => *b"STA1",
+        }
+    }
+
+    const fn offset(self) -> u32 {
+        (FLASH_SIZE - self.sectors().0 * ERASE_SIZE) as u32
+    }
+
+    const fn size(self) -> usize {
+        self.sectors().1 * ERASE_SIZE
+    }
+
+    pub const fn capacity(self) -> usize {
+        self.size() - HEADER
+    }
+}
+
+pub struct Store {
+    flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>,
+    // since the first log checkpoint ever written, see `restore_wear`
+    bytes_written: u64,
+    sectors_erased: u32,
+}
+
+impl Store {
+    pub fn new(flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>) -> Self {
+        Self {
+            flash,
+            bytes_written: 0,
+            sectors_erased: 0,
+        }
+    }
+
+    // (bytes written, sectors erased)
+    pub fn wear(&self) -> (u64, u32) {
+        (self.bytes_written, self.sectors_erased)
+    }
+
+    // Continue the counters from the newest log checkpoint
+    pub fn restore_wear(&mut self, bytes_written: u64, sectors_erased: u32) {
+        self.bytes_written = self.bytes_written.max(bytes_written);
+        self.sectors_erased = self.sectors_erased.max(sectors_erased);
+    }
+
+    fn erase_range(&mut self, start: u32, size: usize) -> Result<(), Error> {
+        self.flash.blocking_erase(start, start + size as u32)?;
+        self.sectors_erased += (size / ERASE_SIZE) as u32;
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        self.flash.blocking_write(offset, data)?;
+        self.bytes_written += data.len() as u64;
+        Ok(())
+    }
+
+    // None when the record was never saved or fails its checksum
+    pub fn load<'b>(&mut self, record: Record, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+        let len = self.stored_len(record)?;
+        let data = buf.get_mut(..len)?;
+        self.read(record, 0, data).ok()?;
+        Some(data)
+    }
+
+    // Length of a valid stored record; the checksum is verified in small
+    // reads so callers can stream records without a full-size buffer
+    pub fn stored_len(&mut self, record: Record) -> Option<usize> {
+        let mut header = [0u8; HEADER];
+        self.flash.blocking_read(record.offset(), &mut header).ok()?;
+        if header[..4] != record.magic() {
+            return None;
+        }
+        let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+        if len > record.capacity() {
+            return None;
+        }
+
+        let mut sum = Fletcher16::new();
+        let mut chunk = [0u8; 256];
+        let mut offset = 0;
+        while offset < len {
+            let n = (len - offset).min(chunk.len());
+            self.read(record, offset, &mut chunk[..n]).ok()?;
+            sum.update(&chunk[..n]);
+            offset += n;
+        }
+        (sum.finish() == u16::from_le_bytes([header[6], header[7]])).then_some(len)
+    }
+
+    // Payload bytes at `offset`, no validation (see `stored_len`)
+    pub fn read(&mut self, record: Record, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        if offset + buf.len() > record.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        self.flash.blocking_read(record.offset() + (HEADER + offset) as u32, buf)
+    }
+
+    pub fn erase(&mut self, record: Record) -> Result<(), Error> {
+        self.erase_range(record.offset(), record.size())
+    }
+
+    pub fn save(&mut self, record: Record, data: &[u8]) -> Result<(), Error> {
+        if data.len() > record.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+        self.erase(record)?;
+        let start = record.offset();
+        // 先写内容后写头部: 中途掉电时头部仍是擦除状态
+        self.write_at(start + HEADER as u32, data)?;
+
+        let mut header = [0u8; HEADER];
+        header[..4].copy_from_slice(&record.magic());
+        header[4..6].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        header[6..].copy_from_slice(&checksum(data).to_le_bytes());
+        self.write_at(start, &header)
+    }
+
+    // Header of a valid checkpoint in log sector `index`
+    pub fn log_sector(&mut self, index: usize) -> Option<LogSector> {
+        let mut header = [0u8; LOG_HEADER];
+        self.flash.blocking_read(log_offset(index), &mut header).ok()?;
+        if header[..4] != LOG_MAGIC {
+            return None;
+        }
+        let u32_at = |at: usize| u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+        let len = u16::from_le_bytes([header[12], header[13]]) as usize;
+        if len > LOG_CAPACITY {
+            return None;
+        }
+
+        let mut sum = Fletcher16::new();
+        let mut chunk = [0u8; 256];
+        let mut offset = 0;
+        while offset < len {
+            let n = (len - offset).min(chunk.len());
+            self.read_log(index, offset, &mut chunk[..n]).ok()?;
+            sum.update(&chunk[..n]);
+            offset += n;
+        }
+        if sum.finish() != u16::from_le_bytes([header[14], header[15]]) {
+            return None;
+        }
+        let mut written = [0u8; 8];
+        written.copy_from_slice(&header[16..24]);
+        Some(LogSector {
+            boot: u32_at(4),
+            seq: u32_at(8),
+            len,
+            bytes_written: u64::from_le_bytes(written),
+            sectors_erased: u32_at(24),
+        })
+    }
+
+    pub fn read_log(&mut self, index: usize, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        if index >= LOG_SECTORS || offset + buf.len() > LOG_CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+        self.flash.blocking_read(log_offset(index) + (LOG_HEADER + offset) as u32, buf)
+    }
+
+    // One erase and one write per checkpoint; the header records the wear
+    // counters including this write
+    pub fn write_log(&mut self, index: usize, boot: u32, seq: u32, data: &[u8]) -> Result<(), Error> {
+        if index >= LOG_SECTORS || data.len() > LOG_CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+        let start = log_offset(index);
+        self.erase_range(start, ERASE_SIZE)?;
+        self.write_at(start + LOG_HEADER as u32, data)?;
+
+        let mut header = [0u8; LOG_HEADER];
+        header[..4].copy_from_slice(&LOG_MAGIC);
+        header[4..8].copy_from_slice(&boot.to_le_bytes());
+        header[8..12].copy_from_slice(&seq.to_le_bytes());
+        header[12..14].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        header[14..16].copy_from_slice(&checksum(data).to_le_bytes());
+        header[16..24].copy_from_slice(&(self.bytes_written + LOG_HEADER as u64).to_le_bytes());
+        header[24..].copy_from_slice(&self.sectors_erased.to_le_bytes());
+        self.write_at(start, &header)
+    }
+}
+
+struct Fletcher16 {
+    a: u16,
+    b: u16,
+}
+
+impl Fletcher16 {
+    const fn new() -> Self {
+        Self { a: 0, b: 0 }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.a = (self.a + byte as u16) % 255;
+            self.b = (self.b + self.a) % 255;
+        }
+    }
+
+    fn finish(self) -> u16 {
+        (self.b << 8) | self.a
+    }
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = Fletcher16::new();
+    sum.update(data);
+    sum.finish()
+}
@@ -0,0 +1,130 @@
+// 端口转发表: AP 侧的 TCP 端口 → 经模块套接字连到远端 host:port
+//
+// Each rule owns one listening port on the AP. A client connecting there
+// gets its own modem connection (id FIRST_CONNECT_ID + rule index; the
+// fetch uses 0) to the rule's destination, and bytes are copied both ways
+// until either side closes. One client per rule: while a link is busy a
+// second client is refused. The AP side and the modem side run in
+// different tasks; `Link` is what they share.
+
+pub const MAX_FORWARDS: usize = 4;
+pub const FIRST_CONNECT_ID: u8 = 1;
+// Config group of each rule, also the owner shown on /net
+pub const NAMES: [&str; MAX_FORWARDS] = ["forward1", "forward2", "forward3", "forward4"];
+// Largest payload moved per modem send or read
+pub const CHUNK: usize = 512;
+
+#[derive(Clone)]
+pub struct Rule {
+    pub enabled: bool,
+    // 0 = not set
+    pub listen_port: u16,
+    pub host: heapless::String<64>,
+    pub port: u16,
+}
+
+impl Rule {
+    pub const DISABLED: Rule = Rule {
+        enabled: false,
+        listen_port: 0,
+        host: heapless::String::new(),
+        port: 0,
+    };
+
+    pub fn active(&self) -> bool {
+        self.enabled && self.listen_port != 0 && self.port != 0 && !self.host.is_empty()
+    }
+
+    pub fn same_as(&self, other: &Rule) -> bool {
+        self.enabled == other.enabled
+            && self.listen_port == other.listen_port
+            && self.host == other.host
+            && self.port == other.port
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Off,
+    Listening,
+    // client accepted, modem connection requested
+    Connecting,
+    Open,
+    // client gone, modem connection still to close
+    Closing,
+    // modem connection closed (by the remote end or after Closing)
+    Closed,
+    Failed,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Off => "off",
+            State::Listening => "listening",
+            State::Connecting => "connecting",
+            State::Open => "open",
+            State::Closing => "closing",
+            State::Closed => "closed",
+            State::Failed => "failed",
+        }
+    }
+
+    // The modem side still has work to do
+    pub fn busy(self) -> bool {
+        matches!(self, State::Connecting | State::Open | State::Closing)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Link {
+    pub state: State,
+    pub since_ms: u64,
+    pub connections: u32,
+    // second clients turned away while the link was busy
+    pub refused: u32,
+    // AP client -> remote
+    pub to_remote: u32,
+    pub from_remote: u32,
+    // the modem reported the remote end closed; the link ends once the
+    // data still buffered in the module has been read
+    pub remote_closed: bool,
+    pub last_error: Option<&'static str>,
+}
+
+impl Link {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Off,
+            since_ms: 0,
+            connections: 0,
+            refused: 0,
+            to_remote: 0,
+            from_remote: 0,
+            remote_closed: false,
+            last_error: None,
+        }
+    }
+
+    pub fn set_state(&mut self, state: State, now_ms: u64) {
+        if self.state != state {
+            self.state = state;
+            self.since_ms = now_ms;
+        }
+    }
+
+    pub fn fail(&mut self, error: &'static str, now_ms: u64) {
+        self.last_error = Some(error);
+        self.set_state(State::Failed, now_ms);
+    }
+}
+
+pub fn connect_id(index: usize) -> u8 {
+    FIRST_CONNECT_ID + index as u8
+}
+
+// "forward2.host" -> (1, "host")
+pub fn parse_path(path: &str) -> Option<(usize, &str)> {
+    let (group, key) = path.split_once('.')?;
+    Some((NAMES.iter().position(|&name| name == group)?, key))
+}
@@ -0,0 +1,259 @@
+// 地理围栏: GNSS 位置离开设定半径时报警
+//
+// The fence is a centre (geofence.lat/lon, decimal degrees) and a radius in
+// metres; radius 0 turns it off and nothing is computed. Each new fix is
+// measured against the centre with the haversine formula. Leaving counts
+// only after OUTSIDE_FIXES fixes in a row outside, so GPS jitter at the
+// edge does not raise alarms; one fix inside is enough to come back. There
+// is no libm here, so the few functions the formula needs are below.
+
+use core::f64::consts::{FRAC_PI_2, PI};
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+pub const OUTSIDE_FIXES: u8 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    Inside,
+    Outside,
+}
+
+impl Side {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Side::Inside => "inside",
+            Side::Outside => "outside",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fence {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_m: u32,
+}
+
+// Degrees as typed in the config: "-33.8568"
+pub fn parse_degrees(text: &str, limit: f64) -> Option<f64> {
+    let value = text.trim().parse::<f64>().ok()?;
+    (-limit..=limit).contains(&value).then_some(value)
+}
+
+pub struct Tracker {
+    // the fence the state below belongs to
+    fence: Option<Fence>,
+    // None until the first fix after boot or a fence change
+    pub side: Option<Side>,
+    outside_streak: u8,
+    pub distance_m: Option<u32>,
+    pub transitions: u32,
+}
+
+impl Tracker {
+    pub const fn new() -> Self {
+        Self {
+            fence: None,
+            side: None,
+            outside_streak: 0,
+            distance_m: None,
+            transitions: 0,
+        }
+    }
+
+    // The side the fix moved the tracker to, when that is a change worth
+    // reporting: leaving (also right after boot), or coming back
+    pub fn update(&mut self, fence: &Fence, latitude: f64, longitude: f64) -> Option<Side> {
+        if self.fence != Some(*fence) {
+            self.fence = Some(*fence);
+            self.side = None;
+            self.outside_streak = 0;
+        }
+        let distance = distance_m(fence.latitude, fence.longitude, latitude, longitude);
+        self.distance_m = Some(distance.min(u32::MAX as f64) as u32);
+        let side = if distance <= fence.radius_m as f64 {
+            self.outside_streak = 0;
+            Side::Inside
+        } else {
+            self.outside_streak = self.outside_streak.saturating_add(1);
+            if self.outside_streak < OUTSIDE_FIXES {
+                return None;
+            }
+            Side::Outside
+        };
+        let before = self.side.replace(side);
+        let report = match (before, side) {
+            (Some(before), side) if before == side => false,
+            (None, Side::Inside) => false,
+            _ => true,
+        };
+        if report {
+            self.transitions += 1;
+        }
+        report.then_some(side)
+    }
+}
+
+// Great-circle distance between two points in degrees
+pub fn distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let half_dphi = (phi2 - phi1) / 2.0;
+    let half_dlambda = wrap_pi((lon2 - lon1).to_radians()) / 2.0;
+    let a = sin(half_dphi) * sin(half_dphi) + cos(phi1) * cos(phi2) * sin(half_dlambda) * sin(half_dlambda);
+    2.0 * EARTH_RADIUS_M * asin(sqrt(a.clamp(0.0, 1.0)))
+}
+
+fn wrap_pi(x: f64) -> f64 {
+    let mut x = x % (2.0 * PI);
+    if x > PI {
+        x -= 2.0 * PI;
+    } else if x < -PI {
+        x += 2.0 * PI;
+    }
+    x
+}
+
+// Taylor series; every argument here is within [-pi/2, pi/2]
+fn sin(x: f64) -> f64 {
+    let x2 = x * x;
+    let mut term = x;
+    let mut sum = x;
+    for n in 1..10 {
+        term *= -x2 / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+    }
+    sum
+}
+
+fn cos(x: f64) -> f64 {
+    sin(FRAC_PI_2 - x.abs())
+}
+
+fn sqrt(x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let mut y = if x > 1.0 { x } else { 1.0 };
+    for _ in 0..60 {
+        let next = (y + x / y) / 2.0;
+        if next >= y {
+            break;
+        }
+        y = next;
+    }
+    y
+}
+
+// x in [0, 1]: sin is increasing on [0, pi/2], so bisect
+fn asin(x: f64) -> f64 {
+    let (mut low, mut high) = (0.0, FRAC_PI_2);
+    for _ in 0..52 {
+        let mid = (low + high) / 2.0;
+        if sin(mid) < x {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 半径 1 km, 中心在悉尼歌剧院
+    const FENCE: Fence = Fence { latitude: -33.8568, longitude: 151.2153, radius_m: 1_000 };
+    // about 200 m and 5.5 km north of the centre
+    const NEAR: (f64, f64) = (-33.855, 151.2153);
+    const FAR: (f64, f64) = (-33.807, 151.2153);
+
+    fn close(actual: f64, expected: f64, tolerance: f64) -> bool {
+        (actual - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn series_match_std() {
+        for i in -100..=100 {
+            let x = i as f64 * FRAC_PI_2 / 100.0;
+            assert!(close(sin(x), x.sin(), 1e-12), "sin({x})");
+            assert!(close(cos(x), x.cos(), 1e-12), "cos({x})");
+        }
+        for i in 0..=100 {
+            let x = i as f64 / 100.0;
+            assert!(close(sqrt(x), x.sqrt(), 1e-12), "sqrt({x})");
+            assert!(close(asin(x), x.asin(), 1e-9), "asin({x})");
+        }
+        assert_eq!(sqrt(-1.0), 0.0);
+    }
+
+    #[test]
+    fn distances() {
+        assert!(close(distance_m(10.0, 20.0, 10.0, 20.0), 0.0, 1e-6));
+        // one degree along a meridian
+        assert!(close(distance_m(0.0, 0.0, 1.0, 0.0), 111_195.0, 1.0));
+        // across the antimeridian, not the long way round
+        assert!(close(distance_m(0.0, 179.5, 0.0, -179.5), 111_195.0, 1.0));
+        // Paris to London
+        assert!(close(distance_m(48.8566, 2.3522, 51.5074, -0.1278), 343_500.0, 1_000.0));
+        // antipodes
+        assert!(close(distance_m(0.0, 0.0, 0.0, 180.0), PI * EARTH_RADIUS_M, 1.0));
+    }
+
+    #[test]
+    fn degrees_from_config() {
+        assert_eq!(parse_degrees(" -33.8568 ", 90.0), Some(-33.8568));
+        assert_eq!(parse_degrees("180", 180.0), Some(180.0));
+        assert_eq!(parse_degrees("90.5", 90.0), None);
+        assert_eq!(parse_degrees("north", 90.0), None);
+        assert_eq!(parse_degrees("", 90.0), None);
+    }
+
+    #[test]
+    fn inside_after_boot_is_quiet() {
+        let mut tracker = Tracker::new();
+        assert_eq!(tracker.update(&FENCE, NEAR.0, NEAR.1), None);
+        assert_eq!(tracker.side, Some(Side::Inside));
+        assert!(close(tracker.distance_m.unwrap() as f64, 200.0, 5.0));
+        assert_eq!(tracker.transitions, 0);
+    }
+
+    #[test]
+    fn leaving_takes_two_fixes_and_one_brings_it_back() {
+        let mut tracker = Tracker::new();
+        tracker.update(&FENCE, NEAR.0, NEAR.1);
+        // one fix outside is jitter
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), None);
+        assert_eq!(tracker.update(&FENCE, NEAR.0, NEAR.1), None);
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), None);
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), Some(Side::Outside));
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), None);
+        assert_eq!(tracker.update(&FENCE, NEAR.0, NEAR.1), Some(Side::Inside));
+        assert_eq!(tracker.transitions, 2);
+        assert_eq!(Side::Outside.as_str(), "outside");
+    }
+
+    #[test]
+    fn outside_after_boot_is_reported() {
+        let mut tracker = Tracker::new();
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), None);
+        assert!(tracker.side.is_none());
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), Some(Side::Outside));
+        assert_eq!(tracker.transitions, 1);
+    }
+
+    #[test]
+    fn fence_change_starts_over() {
+        let mut tracker = Tracker::new();
+        tracker.update(&FENCE, FAR.0, FAR.1);
+        tracker.update(&FENCE, FAR.0, FAR.1);
+        // 把半径放大到 10 km: 同一点现在在里面, 不报
+        let wide = Fence { radius_m: 10_000, ..FENCE };
+        assert_eq!(tracker.update(&wide, FAR.0, FAR.1), None);
+        assert_eq!(tracker.side, Some(Side::Inside));
+        // back to 1 km: the streak starts from zero, so one fix is not enough
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), None);
+        assert!(tracker.side.is_none());
+        assert_eq!(tracker.update(&FENCE, FAR.0, FAR.1), Some(Side::Outside));
+    }
+}
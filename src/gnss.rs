@@ -0,0 +1,216 @@
+// GNSS 定位 (带 GPS 的 EC800K 型号)
+//
+// With gnss.enabled set, the engine is powered with AT+QGPS=1 and polled
+// with AT+QGPSLOC=2 (degrees as decimals) every gnss.interval_s. Until the
+// receiver has a fix the module answers +CME ERROR: 516; that is shown as
+// acquiring, with the time since power on, not as a failure. The last fix
+// is kept when later polls lose it. Turning the setting off powers the
+// engine down (AT+QGPSEND). Polls run at background priority, so they
+// never hold up a fetch.
+
+use core::fmt::Write as _;
+
+use crate::json;
+
+// +CME ERROR codes of the Quectel GNSS commands
+const NO_FIX: &str = "516";
+const SESSION_ONGOING: &str = "504";
+const SESSION_NOT_ACTIVE: &str = "505";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FixType {
+    TwoD,
+    ThreeD,
+}
+
+impl FixType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FixType::TwoD => "2D",
+            FixType::ThreeD => "3D",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Fix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub hdop: f32,
+    pub altitude_m: f32,
+    pub fix_type: FixType,
+    pub speed_kmh: f32,
+    // "2024-05-13T06:19:51Z"
+    pub utc: heapless::String<20>,
+    pub at_ms: u64,
+}
+
+impl Fix {
+    pub fn write_osm_url<W: core::fmt::Write>(&self, out: &mut W) {
+        let _ = core::write!(
+            out,
+            "https://www.openstreetmap.org/?mlat={0:.5}&mlon={1:.5}#map=16/{0:.5}/{1:.5}",
+            self.latitude,
+            self.longitude
+        );
+    }
+}
+
+// One line of the AT+QGPSLOC=2 reply
+pub enum Reply {
+    Fix(Fix),
+    NoFix,
+    // the engine is off (power cycle, or never started)
+    NotActive,
+}
+
+// +QGPSLOC: <utc hhmmss.sss>,<lat>,<lon>,<hdop>,<altitude>,<fix>,<cog>,
+// <spkm>,<spkn>,<date ddmmyy>,<nsat>
+pub fn parse_location(line: &str, now_ms: u64) -> Option<Reply> {
+    if let Some(code) = line.strip_prefix("+CME ERROR:") {
+        return match code.trim() {
+            NO_FIX => Some(Reply::NoFix),
+            SESSION_NOT_ACTIVE => Some(Reply::NotActive),
+            _ => None,
+        };
+    }
+    let mut fields = line.strip_prefix("+QGPSLOC:")?.split(',').map(str::trim);
+    let time = fields.next()?;
+    let latitude = fields.next()?.parse::<f64>().ok()?;
+    let longitude = fields.next()?.parse::<f64>().ok()?;
+    let hdop = fields.next()?.parse().ok()?;
+    let altitude_m = fields.next()?.parse().ok()?;
+    let fix_type = match fields.next()? {
+        "2" => FixType::TwoD,
+        "3" => FixType::ThreeD,
+        _ => return None,
+    };
+    let speed_kmh = fields.nth(1)?.parse().ok()?;
+    let date = fields.nth(1)?;
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return None;
+    }
+
+    let digits = |text: &str, len: usize| text.len() >= len && text.bytes().take(len).all(|b| b.is_ascii_digit());
+    if !digits(time, 6) || !digits(date, 6) {
+        return None;
+    }
+    let mut utc = heapless::String::new();
+    let _ = core::write!(
+        utc,
+        "20{}-{}-{}T{}:{}:{}Z",
+        &date[4..6],
+        &date[2..4],
+        &date[0..2],
+        &time[0..2],
+        &time[2..4],
+        &time[4..6]
+    );
+    Some(Reply::Fix(Fix {
+        latitude,
+        longitude,
+        hdop,
+        altitude_m,
+        fix_type,
+        speed_kmh,
+        utc,
+        at_ms: now_ms,
+    }))
+}
+
+// AT+QGPS=1 answers OK, or 504 when the engine already runs
+pub fn powered_on(line: &str) -> bool {
+    line == "OK"
+        || line
+            .strip_prefix("+CME ERROR:")
+            .is_some_and(|code| code.trim() == SESSION_ONGOING)
+}
+
+pub struct State {
+    // when the engine was powered, None = off
+    pub powered_at_ms: Option<u64>,
+    // the last poll answered 516
+    pub acquiring: bool,
+    pub last_fix: Option<Fix>,
+    pub polls: u32,
+    // replies that were neither a fix nor "no fix"
+    pub errors: u32,
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            powered_at_ms: None,
+            acquiring: false,
+            last_fix: None,
+            polls: 0,
+            errors: 0,
+        }
+    }
+
+    pub fn record(&mut self, reply: Option<Reply>) {
+        self.polls += 1;
+        match reply {
+            Some(Reply::Fix(fix)) => {
+                self.acquiring = false;
+                self.last_fix = Some(fix);
+            }
+            Some(Reply::NoFix) => self.acquiring = true,
+            Some(Reply::NotActive) => {
+                self.powered_at_ms = None;
+                self.acquiring = false;
+            }
+            None => self.errors += 1,
+        }
+    }
+
+    pub fn powered_off(&mut self) {
+        self.powered_at_ms = None;
+        self.acquiring = false;
+    }
+
+    pub fn write_json<const N: usize>(&self, out: &mut heapless::String<N>, enabled: bool, now_ms: u64) {
+        let state = match (self.powered_at_ms, self.acquiring) {
+            (None, _) => "off",
+            (Some(_), true) => "acquiring",
+            (Some(_), false) if self.last_fix.is_some() => "fix",
+            (Some(_), false) => "starting",
+        };
+        let mut fix = heapless::String::<320>::new();
+        match &self.last_fix {
+            Some(last) => {
+                let mut url = heapless::String::<128>::new();
+                last.write_osm_url(&mut url);
+                let mut obj = json::Object::new(&mut fix);
+                obj.raw("lat", &number(format_args!("{:.6}", last.latitude)))
+                    .raw("lon", &number(format_args!("{:.6}", last.longitude)))
+                    .raw("hdop", &number(format_args!("{:.1}", last.hdop)))
+                    .raw("altitude_m", &number(format_args!("{:.1}", last.altitude_m)))
+                    .raw("speed_kmh", &number(format_args!("{:.1}", last.speed_kmh)))
+                    .str("type", last.fix_type.as_str())
+                    .str("utc", &last.utc)
+                    .u32("age_s", (now_ms.saturating_sub(last.at_ms) / 1000) as u32)
+                    .str("map", &url);
+                obj.finish();
+            }
+            None => {
+                let _ = fix.push_str("null");
+            }
+        }
+
+        let mut obj = json::Object::new(out);
+        obj.bool("enabled", enabled).str("state", state);
+        if let Some(at) = self.powered_at_ms {
+            obj.u32("powered_s", (now_ms.saturating_sub(at) / 1000) as u32);
+        }
+        obj.u32("polls", self.polls).u32("errors", self.errors).raw("fix", &fix);
+        obj.finish();
+    }
+}
+
+// A float for `json::Object::raw`
+pub fn number(args: core::fmt::Arguments<'_>) -> heapless::String<16> {
+    let mut out = heapless::String::new();
+    let _ = out.write_fmt(args);
+    out
+}
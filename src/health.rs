@@ -0,0 +1,104 @@
+// 健康检查 (GET /healthz), 给外部监控用
+//
+// health.checks picks which checks count (bits of Check). Every input is
+// state other tasks already keep: the boot log and its task list, the last
+// registration and data-context answers, the last good cellular exchange
+// and the socket budget. The handler never talks to the modem, so it
+// answers at once even while the UART task is stuck. The device is healthy
+// when every selected check passes.
+
+use crate::json;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    // the AP has its IP configuration
+    Network,
+    // home or roaming
+    Registered,
+    // the data context was active the last time anything looked
+    DataContext,
+    // a fetch or keep-warm lookup succeeded within health.max_idle_min
+    RecentActivity,
+    // at least health.min_free_sockets stack sockets unused
+    FreeSockets,
+    // the modem, radio and network tasks all started (boot::Tasks)
+    Tasks,
+}
+
+impl Check {
+    pub const ALL: [Check; 6] = [
+        Check::Network,
+        Check::Registered,
+        Check::DataContext,
+        Check::RecentActivity,
+        Check::FreeSockets,
+        Check::Tasks,
+    ];
+
+    // config health.checks bit
+    pub const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Check::Network => "network",
+            Check::Registered => "registered",
+            Check::DataContext => "data_context",
+            Check::RecentActivity => "recent_activity",
+            Check::FreeSockets => "free_sockets",
+            Check::Tasks => "tasks",
+        }
+    }
+}
+
+pub const ALL_CHECKS: u32 = (1 << Check::ALL.len()) - 1;
+pub const DEFAULT_CHECKS: u32 =
+    Check::Network.bit() | Check::Registered.bit() | Check::FreeSockets.bit() | Check::Tasks.bit();
+
+// What the checks look at, gathered by the caller
+pub struct Inputs {
+    pub network_up: bool,
+    pub registered: bool,
+    // None = not queried since boot
+    pub data_context: Option<bool>,
+    pub last_ok_ms: Option<u64>,
+    pub now_ms: u64,
+    pub max_idle_min: u32,
+    pub free_sockets: usize,
+    pub min_free_sockets: usize,
+    pub critical_tasks_started: bool,
+}
+
+impl Inputs {
+    pub fn passes(&self, check: Check) -> bool {
+        match check {
+            Check::Network => self.network_up,
+            Check::Registered => self.registered,
+            Check::DataContext => self.data_context == Some(true),
+            Check::RecentActivity => self
+                .last_ok_ms
+                .is_some_and(|at| self.now_ms.saturating_sub(at) <= self.max_idle_min as u64 * 60_000),
+            Check::FreeSockets => self.free_sockets >= self.min_free_sockets,
+            Check::Tasks => self.critical_tasks_started,
+        }
+    }
+
+    pub fn healthy(&self, checks: u32) -> bool {
+        Check::ALL.iter().filter(|c| checks & c.bit() != 0).all(|&c| self.passes(c))
+    }
+
+    // {"healthy":true,"checks":{"network":true,...}}, selected checks only
+    pub fn write_json<const N: usize>(&self, out: &mut heapless::String<N>, checks: u32) {
+        let mut results = heapless::String::<128>::new();
+        let mut obj = json::Object::new(&mut results);
+        for check in Check::ALL.into_iter().filter(|c| checks & c.bit() != 0) {
+            obj.bool(check.as_str(), self.passes(check));
+        }
+        obj.finish();
+
+        let mut obj = json::Object::new(out);
+        obj.bool("healthy", self.healthy(checks)).raw("checks", &results);
+        obj.finish();
+    }
+}
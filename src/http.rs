@@ -0,0 +1,1292 @@
+// HTTP 请求解析与内容协商
+
+use core::fmt::Write as _;
+use embassy_time::{Duration, Instant, with_deadline, with_timeout};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+use crate::conn_close::Close;
+use crate::limits;
+
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    // text after '?' in the target, empty when there is none
+    pub query: &'a str,
+    // "HTTP/1.1", "HTTP/1.0", or empty when the request line has none
+    pub version: &'a str,
+    headers: &'a str,
+    // whatever part of the body was read along with the headers, as sent
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    // Chunked transfer coding is HTTP/1.1; older clients need the body
+    // ended by closing the connection
+    pub fn accepts_chunked(&self) -> bool {
+        self.version == "HTTP/1.1"
+    }
+
+    // Where form fields come from: the body of a POST, the query otherwise
+    pub fn form(&self) -> &'a [u8] {
+        if self.method == "POST" { self.body } else { self.query.as_bytes() }
+    }
+
+    // Header lookup is case-insensitive on the name, value is trimmed
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        for line in self.headers.split("\r\n") {
+            if let Some((key, value)) = line.split_once(':')
+                && key.trim().eq_ignore_ascii_case(name)
+            {
+                return Some(value.trim());
+            }
+        }
+        None
+    }
+}
+
+// RFC 9110 methods: others get 501, these 405 where a route does not take them
+pub const KNOWN_METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+pub fn parse_request(raw: &str) -> Option<Request<'_>> {
+    let (request_line, rest) = match raw.split_once("\r\n") {
+        Some(parts) => parts,
+        None => (raw.trim_end_matches('\n'), ""),
+    };
+
+    let mut parts = request_line.split(' ');
+    let method = parts.next()?;
+    let target = parts.next()?;
+    let version = parts.next().unwrap_or("");
+    if method.is_empty() || !target.starts_with('/') {
+        return None;
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let (headers, body) = match rest.find("\r\n\r\n") {
+        Some(end) => (&rest[..end], &rest[end + 4..]),
+        None => (rest, ""),
+    };
+
+    Some(Request {
+        method,
+        path,
+        query,
+        version,
+        headers,
+        body: body.as_bytes(),
+    })
+}
+
+// The request as read off the socket, up to the first byte that is not
+// UTF-8: a full buffer can end in the middle of a character
+pub fn request_text(data: &[u8]) -> &str {
+    match core::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&data[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+// parse_request over the bytes read so far. Only the head is text; the
+// body is passed on as the bytes that arrived
+pub fn parse_head(data: &[u8]) -> Option<Request<'_>> {
+    let Some(end) = find_header_end(data) else {
+        return parse_request(request_text(data));
+    };
+    let mut request = parse_request(request_text(&data[..end]))?;
+    request.body = &data[end..];
+    Some(request)
+}
+
+// Raw (still percent-encoded) value of `key` in a query string or
+// application/x-www-form-urlencoded body. A body is bytes: a value that is
+// not UTF-8 is None, never cut short
+pub fn form_value<'a, F: AsRef<[u8]> + ?Sized>(form: &'a F, key: &str) -> Option<&'a str> {
+    form.as_ref().split(|&b| b == b'&').find_map(|pair| {
+        let (k, v) = match pair.iter().position(|&b| b == b'=') {
+            Some(eq) => (&pair[..eq], &pair[eq + 1..]),
+            None => (pair, &pair[pair.len()..]),
+        };
+        if k == key.as_bytes() { core::str::from_utf8(v).ok() } else { None }
+    })
+}
+
+// '+' is a space, %XX a byte; None on bad escapes, invalid UTF-8 or overflow
+pub fn percent_decode<const N: usize>(raw: &str) -> Option<heapless::String<N>> {
+    let mut bytes = heapless::Vec::<u8, N>::new();
+    let mut input = raw.bytes();
+    while let Some(b) = input.next() {
+        let byte = match b {
+            b'+' => b' ',
+            b'%' => {
+                let hi = (input.next()? as char).to_digit(16)?;
+                let lo = (input.next()? as char).to_digit(16)?;
+                (hi << 4 | lo) as u8
+            }
+            b => b,
+        };
+        bytes.push(byte).ok()?;
+    }
+    heapless::String::from_utf8(bytes).ok()
+}
+
+// Pick one of `offered` according to an Accept header value.
+// The first offered type is the default: it is returned when the header is
+// absent, malformed, or rejects every offered type. Ties between offered
+// types keep the server's order.
+pub fn negotiate<'a>(accept: Option<&str>, offered: &[&'a str]) -> &'a str {
+    let default = offered[0];
+    let accept = match accept {
+        Some(a) if !a.trim().is_empty() => a,
+        _ => return default,
+    };
+
+    let mut best = default;
+    let mut best_q = 0u16;
+
+    for &candidate in offered {
+        let q = match quality_for(accept, candidate) {
+            Some(q) => q,
+            None => return default,
+        };
+        if q > best_q {
+            best = candidate;
+            best_q = q;
+        }
+    }
+
+    best
+}
+
+// q-value (in thousandths) the Accept header assigns to `media_type`,
+// taken from the most specific matching range. None if the header is malformed.
+fn quality_for(accept: &str, media_type: &str) -> Option<u16> {
+    let (want_type, _) = media_type.split_once('/')?;
+
+    let mut q = 0u16;
+    let mut specificity = -1i8;
+
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let range = params.next()?.trim();
+        if range.is_empty() {
+            continue;
+        }
+
+        let (range_type, range_subtype) = range.split_once('/')?;
+        if range_type.is_empty() || range_subtype.is_empty() {
+            return None;
+        }
+
+        let mut range_q = 1000u16;
+        for param in params {
+            if let Some((key, value)) = param.split_once('=')
+                && key.trim().eq_ignore_ascii_case("q")
+            {
+                range_q = parse_qvalue(value.trim())?;
+            }
+        }
+
+        let this_specificity = if range == "*/*" {
+            0
+        } else if range_subtype == "*" && range_type.eq_ignore_ascii_case(want_type) {
+            1
+        } else if range.eq_ignore_ascii_case(media_type) {
+            2
+        } else {
+            continue;
+        };
+
+        if this_specificity > specificity {
+            specificity = this_specificity;
+            q = range_q;
+        }
+    }
+
+    Some(q)
+}
+
+// True if an Accept-Encoding header allows `coding` (explicitly or via `*`)
+pub fn accepts_encoding(accept_encoding: Option<&str>, coding: &str) -> bool {
+    let Some(header) = accept_encoding else {
+        return false;
+    };
+
+    for item in header.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        if !name.eq_ignore_ascii_case(coding) && name != "*" {
+            continue;
+        }
+
+        let mut q = 1000u16;
+        for param in params {
+            if let Some((key, value)) = param.split_once('=')
+                && key.trim().eq_ignore_ascii_case("q")
+            {
+                q = parse_qvalue(value.trim()).unwrap_or(0);
+            }
+        }
+        return q > 0;
+    }
+    false
+}
+
+// If-None-Match: list of entity tags or `*`, compared weakly
+pub fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let Some(header) = if_none_match else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    header.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteRange {
+    From(u32),
+    FromTo(u32, u32),
+    Suffix(u32),
+}
+
+pub enum RangeCheck {
+    Full,
+    // inclusive, like Content-Range
+    Partial { first: u32, last: u32 },
+    Unsatisfiable,
+}
+
+// Single `bytes=` range only. Multi-range and malformed headers give None,
+// which means "ignore the header and send the full body".
+pub fn parse_range(range: Option<&str>) -> Option<ByteRange> {
+    let spec = range?.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    match (first.is_empty(), last.is_empty()) {
+        (true, false) => Some(ByteRange::Suffix(last.parse().ok()?)),
+        (false, true) => Some(ByteRange::From(first.parse().ok()?)),
+        (false, false) => {
+            let (first, last): (u32, u32) = (first.parse().ok()?, last.parse().ok()?);
+            if first > last {
+                return None;
+            }
+            Some(ByteRange::FromTo(first, last))
+        }
+        (true, true) => None,
+    }
+}
+
+// Resolve a range against a body covering offsets [earliest, end).
+// For a plain body `earliest` is 0; for the log ring it is the oldest byte
+// still held, and ranges starting below it cannot be served.
+pub fn check_range(range: Option<ByteRange>, earliest: u32, end: u32) -> RangeCheck {
+    let Some(range) = range else {
+        return RangeCheck::Full;
+    };
+    if end == 0 || end <= earliest {
+        return RangeCheck::Unsatisfiable;
+    }
+
+    let (first, last) = match range {
+        ByteRange::From(first) => (first, end - 1),
+        ByteRange::FromTo(first, last) => (first, last.min(end - 1)),
+        ByteRange::Suffix(0) => return RangeCheck::Unsatisfiable,
+        ByteRange::Suffix(n) => (end.saturating_sub(n).max(earliest), end - 1),
+    };
+
+    if first < earliest || first >= end {
+        return RangeCheck::Unsatisfiable;
+    }
+    RangeCheck::Partial { first, last }
+}
+
+// "0", "0.5", "1", "1.000" -> thousandths
+fn parse_qvalue(s: &str) -> Option<u16> {
+    let (int_part, frac_part) = match s.split_once('.') {
+        Some(parts) => parts,
+        None => (s, ""),
+    };
+    if frac_part.len() > 3 || !frac_part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let whole = match int_part {
+        "0" => 0u16,
+        "1" => 1000u16,
+        _ => return None,
+    };
+
+    let mut frac = 0u16;
+    let mut scale = 100u16;
+    for b in frac_part.bytes() {
+        frac += (b - b'0') as u16 * scale;
+        scale /= 10;
+    }
+
+    if whole == 1000 && frac != 0 {
+        return None;
+    }
+    Some(whole + frac)
+}
+
+// Transfer-Encoding: chunked body writer, used when the length is not known
+// before the body is produced. Without `chunked` (an HTTP/1.0 client) the
+// data goes out as it is and closing the connection ends the body; the
+// headers then leave out Transfer-Encoding (`push_unknown_length`).
+pub struct ChunkedWriter<'a, W: Write> {
+    inner: &'a mut W,
+    chunked: bool,
+}
+
+impl<'a, W: Write> ChunkedWriter<'a, W> {
+    pub fn new(inner: &'a mut W, chunked: bool) -> Self {
+        Self { inner, chunked }
+    }
+
+    pub async fn write_chunk(&mut self, data: &[u8]) -> Result<(), W::Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if !self.chunked {
+            return self.inner.write_all(data).await;
+        }
+        let mut size = heapless::String::<12>::new();
+        let _ = core::write!(size, "{:x}\r\n", data.len());
+        self.inner.write_all(size.as_bytes()).await?;
+        self.inner.write_all(data).await?;
+        self.inner.write_all(b"\r\n").await
+    }
+
+    pub async fn finish(self) -> Result<(), W::Error> {
+        if self.chunked {
+            self.inner.write_all(b"0\r\n\r\n").await?;
+        }
+        self.inner.flush().await
+    }
+}
+
+// The framing header of a body written through ChunkedWriter; nothing for
+// an HTTP/1.0 client, whose body ends with Connection: close
+pub fn push_unknown_length<const N: usize>(header: &mut heapless::String<N>, chunked: bool) {
+    if chunked {
+        let _ = header.push_str("Transfer-Encoding: chunked\r\n");
+    }
+}
+
+// 慢速客户端 (slowloris) 的各阶段时限
+#[derive(Clone, Copy)]
+pub struct Deadlines {
+    // headers complete, measured from accept
+    pub header_ms: u32,
+    // headers plus body, measured from accept
+    pub request_ms: u32,
+    // longest a single write may wait for the socket to take any bytes
+    pub write_progress_ms: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadError {
+    HeaderTimeout,
+    RequestTimeout,
+    // request line or header block over the limits (431)
+    HeadersTooLarge,
+    // Content-Length over what the route takes (413)
+    BodyTooLarge,
+    // the peer closed before the Content-Length bytes were in (400)
+    BodyIncomplete,
+    Closed,
+    Io,
+}
+
+// Read one request into `buf`, returns its length. Stops once the headers
+// and Content-Length bytes of body are in, or the buffer is full (a body
+// the route streams is then read on from the socket). `buf` has to be
+// larger than limits::MAX_HEADER_BLOCK.
+pub async fn read_request<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    accepted: Instant,
+    deadlines: &Deadlines,
+) -> Result<usize, ReadError> {
+    let header_deadline = accepted + Duration::from_millis(deadlines.header_ms as u64);
+    let request_deadline = accepted + Duration::from_millis(deadlines.request_ms as u64);
+
+    let mut len = 0;
+    let mut wanted: Option<usize> = None;
+    while len < buf.len() {
+        if let Some(wanted) = wanted
+            && len >= wanted
+        {
+            break;
+        }
+
+        let deadline = match wanted {
+            Some(_) => request_deadline,
+            None => header_deadline.min(request_deadline),
+        };
+        let n = match with_deadline(deadline, reader.read(&mut buf[len..])).await {
+            Ok(Ok(n)) => n,
+            Ok(Err(_)) => return Err(ReadError::Io),
+            Err(_) if wanted.is_some() => return Err(ReadError::RequestTimeout),
+            Err(_) => return Err(ReadError::HeaderTimeout),
+        };
+        if n == 0 {
+            // peer closed: serve whatever arrived, but not half a body
+            return match wanted {
+                _ if len == 0 => Err(ReadError::Closed),
+                Some(wanted) if len < wanted => Err(ReadError::BodyIncomplete),
+                _ => Ok(len),
+            };
+        }
+        len += n;
+
+        if wanted.is_none() {
+            if request_line_len(&buf[..len]) > limits::MAX_REQUEST_LINE {
+                return Err(ReadError::HeadersTooLarge);
+            }
+            let Some(header_end) = find_header_end(&buf[..len]) else {
+                if len >= limits::MAX_HEADER_BLOCK {
+                    return Err(ReadError::HeadersTooLarge);
+                }
+                continue;
+            };
+            if header_end > limits::MAX_HEADER_BLOCK {
+                return Err(ReadError::HeadersTooLarge);
+            }
+            let request = parse_head(&buf[..header_end]);
+            let body_len = request
+                .as_ref()
+                .and_then(|r| r.header("Content-Length"))
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(0);
+            let (method, path) = request.as_ref().map_or(("GET", "/"), |r| (r.method, r.path));
+            if body_len > limits::max_body(method, path) {
+                return Err(ReadError::BodyTooLarge);
+            }
+            wanted = Some(header_end + body_len);
+        }
+    }
+    Ok(len)
+}
+
+// Length of the first line without its line ending; all of `data` while
+// the line is still coming in
+fn request_line_len(data: &[u8]) -> usize {
+    let line = data.split(|&b| b == b'\n').next().unwrap_or(data);
+    line.strip_suffix(b"\r").unwrap_or(line).len()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BodyError {
+    Timeout,
+    // peer closed before Content-Length bytes arrived
+    Closed,
+    Io,
+}
+
+// Request body of a known length that may not fit the request buffer:
+// first the bytes that arrived with the headers, then the rest straight
+// from the socket, all before `deadline`.
+pub struct BodyReader<'b> {
+    buffered: &'b [u8],
+    // still to deliver, including `buffered`
+    remaining: usize,
+    deadline: Instant,
+}
+
+impl<'b> BodyReader<'b> {
+    // `buffered`: what `read_request` already read past the headers
+    pub fn new(buffered: &'b [u8], content_length: usize, deadline: Instant) -> Self {
+        Self {
+            buffered: &buffered[..buffered.len().min(content_length)],
+            remaining: content_length,
+            deadline,
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    // Next bytes of the body into `out`; Ok(0) once all of it was delivered
+    pub async fn read_body_chunk<R: Read>(&mut self, reader: &mut R, out: &mut [u8]) -> Result<usize, BodyError> {
+        if self.remaining == 0 || out.is_empty() {
+            return Ok(0);
+        }
+        if !self.buffered.is_empty() {
+            let n = self.buffered.len().min(out.len());
+            out[..n].copy_from_slice(&self.buffered[..n]);
+            self.buffered = &self.buffered[n..];
+            self.remaining -= n;
+            return Ok(n);
+        }
+        let want = self.remaining.min(out.len());
+        match with_deadline(self.deadline, reader.read(&mut out[..want])).await {
+            Ok(Ok(0)) => Err(BodyError::Closed),
+            Ok(Ok(n)) => {
+                self.remaining -= n;
+                Ok(n)
+            }
+            Ok(Err(_)) => Err(BodyError::Io),
+            Err(_) => Err(BodyError::Timeout),
+        }
+    }
+
+    // Fill `out` (or what is left of the body), returns the length filled
+    pub async fn read_full<R: Read>(&mut self, reader: &mut R, out: &mut [u8]) -> Result<usize, BodyError> {
+        let mut filled = 0;
+        while filled < out.len() {
+            match self.read_body_chunk(reader, &mut out[filled..]).await? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(filled)
+    }
+
+    // Discard whatever the handler did not read. False if the body did not
+    // arrive in time; the caller should then reset the connection instead
+    // of closing it normally.
+    pub async fn drain<R: Read>(&mut self, reader: &mut R) -> bool {
+        let mut scratch = [0u8; 128];
+        loop {
+            match self.read_body_chunk(reader, &mut scratch).await {
+                Ok(0) => return true,
+                Ok(_) => {}
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+// Insert a Content-Length header for the body of a response built in one
+// buffer; false (response unchanged) if it already has one or is full
+pub fn set_content_length<const N: usize>(response: &mut heapless::String<N>) -> bool {
+    let Some(body_start) = find_header_end(response.as_bytes()) else {
+        return false;
+    };
+    let headers = &response[..body_start];
+    let has_length = |line: &str| line.get(..15).is_some_and(|name| name.eq_ignore_ascii_case("content-length:"));
+    if headers.lines().any(has_length) {
+        return false;
+    }
+    let mut header = heapless::String::<32>::new();
+    let _ = core::write!(header, "Content-Length: {}\r\n", response.len() - body_start);
+    let len = response.len();
+    if len + header.len() > N {
+        return false;
+    }
+
+    // 插在结束头部的空行之前
+    let at = body_start - if response.as_bytes()[..body_start].ends_with(b"\r\n\r\n") { 2 } else { 1 };
+    debug_assert!(response.is_char_boundary(at));
+    let mut bytes = core::mem::take(response).into_bytes();
+    let _ = bytes.resize(len + header.len(), 0);
+    bytes.copy_within(at..len, at + header.len());
+    bytes[at..at + header.len()].copy_from_slice(header.as_bytes());
+    *response = heapless::String::from_utf8(bytes).unwrap_or_default();
+    true
+}
+
+// The Content-Length a response built in one buffer declares, if any, is
+// the byte length of its body (not its length in characters)
+pub fn length_consistent(response: &str) -> bool {
+    let Some(body_start) = find_header_end(response.as_bytes()) else {
+        return true;
+    };
+    let declared = response[..body_start].lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("content-length").then(|| value.trim().parse::<usize>().ok())?
+    });
+    declared.is_none_or(|len| len == response.len() - body_start)
+}
+
+// A page did not fit the buffer it was built in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BufferFull;
+
+// Pages are built with pushes that drop whatever does not fit. Their
+// variable parts (results, log text) are written a few bytes at a time, and
+// nothing written after them is longer than this, so a page that ends
+// closer than this to its capacity may have lost a piece.
+pub const PAGE_HEADROOM: usize = 1024;
+
+// set_content_length for a page with a variable part, refusing one that may
+// have been cut short
+pub fn finish_page<const N: usize>(page: &mut heapless::String<N>) -> Result<(), BufferFull> {
+    if page.len() + PAGE_HEADROOM > N || !set_content_length(page) {
+        return Err(BufferFull);
+    }
+    Ok(())
+}
+
+// Sent in place of a page that did not fit
+pub const PAGE_TOO_LARGE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+Retry-After: 5\r\n\
+Content-Type: text/plain\r\n\
+Content-Length: 15\r\n\
+Connection: close\r\n\r\n\
+Page too large\n";
+
+// Sent when the peer closed before the whole body was in (ReadError::BodyIncomplete)
+pub const BODY_INCOMPLETE: &[u8] = b"HTTP/1.1 400 Bad Request\r\n\
+Content-Type: text/plain\r\n\
+Content-Length: 24\r\n\
+Connection: close\r\n\r\n\
+Request body incomplete\n";
+
+// Sent when the headers miss deadlines.header_ms (ReadError::HeaderTimeout)
+pub const HEADER_TIMEOUT: &[u8] = b"HTTP/1.1 408 Request Timeout\r\n\
+Content-Type: text/plain\r\n\
+Content-Length: 30\r\n\
+Connection: close\r\n\r\n\
+Request headers took too long\n";
+
+// Offset just past the blank line ending the header block
+pub fn find_header_end(data: &[u8]) -> Option<usize> {
+    if let Some(i) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+        return Some(i + 4);
+    }
+    data.windows(2).position(|w| w == b"\n\n").map(|i| i + 2)
+}
+
+// One byte of a "\r\n\r\n" search that may span several writes
+fn advance_header_end(matched: u8, byte: u8) -> u8 {
+    const END: &[u8; 4] = b"\r\n\r\n";
+    match matched {
+        4 => 4,
+        _ if byte == END[matched as usize] => matched + 1,
+        _ if byte == b'\r' => 1,
+        _ => 0,
+    }
+}
+
+#[derive(Debug)]
+pub enum WriteError<E> {
+    Stalled,
+    Io(E),
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error for WriteError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            WriteError::Stalled => ErrorKind::TimedOut,
+            WriteError::Io(e) => e.kind(),
+        }
+    }
+}
+
+// Fails any write or flush that makes no progress within `timeout`, so a
+// client that stops reading cannot hold the connection open. Once stalled
+// every later write fails immediately.
+//
+// For HEAD requests (`suppress_body`) everything after the blank line
+// ending the response headers is swallowed, so handlers can build the same
+// response as for GET and the headers stay accurate.
+//
+// With `set_request_id` an `X-Request-Id` header goes out right after the
+// status line of whatever response the handler writes.
+pub struct ProgressWriter<'a, W: Write> {
+    inner: &'a mut W,
+    timeout: Duration,
+    stalled: bool,
+    written: u32,
+    suppress_body: bool,
+    // bytes of "\r\n\r\n" matched so far; 4 once the body has started
+    header_end: u8,
+    request_id: Option<u32>,
+    // the status line has not ended yet, the request ID header follows it
+    in_status_line: bool,
+    // set by a handler that cut the connection short
+    aborted: Option<Close>,
+    // set by a handler that answered an error and closes normally
+    answered_as: Option<Close>,
+    // false for HTTP/1.0 clients
+    chunked: bool,
+}
+
+impl<'a, W: Write> ProgressWriter<'a, W> {
+    pub fn new(inner: &'a mut W, timeout_ms: u32) -> Self {
+        Self {
+            inner,
+            timeout: Duration::from_millis(timeout_ms as u64),
+            stalled: false,
+            written: 0,
+            suppress_body: false,
+            header_end: 0,
+            request_id: None,
+            in_status_line: false,
+            aborted: None,
+            answered_as: None,
+            chunked: true,
+        }
+    }
+
+    pub fn suppress_body(&mut self) {
+        self.suppress_body = true;
+    }
+
+    // The client cannot decode Transfer-Encoding: chunked
+    pub fn refuse_chunked(&mut self) {
+        self.chunked = false;
+    }
+
+    pub fn chunked(&self) -> bool {
+        self.chunked
+    }
+
+    // Before anything is written
+    pub fn set_request_id(&mut self, id: u32) {
+        self.request_id = Some(id);
+        self.in_status_line = true;
+    }
+
+    async fn write_request_id(&mut self) -> Result<(), WriteError<W::Error>> {
+        let Some(id) = self.request_id else {
+            return Ok(());
+        };
+        let mut header = heapless::String::<32>::new();
+        let _ = core::write!(header, "X-Request-Id: {}\r\n", id);
+        let mut rest = header.as_bytes();
+        while !rest.is_empty() {
+            match with_timeout(self.timeout, self.inner.write(rest)).await {
+                Ok(Ok(0)) => return Err(WriteError::Stalled),
+                Ok(Ok(n)) => {
+                    self.written = self.written.wrapping_add(n as u32);
+                    rest = &rest[n..];
+                }
+                Ok(Err(e)) => return Err(WriteError::Io(e)),
+                Err(_) => {
+                    self.stalled = true;
+                    return Err(WriteError::Stalled);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Length of the part of `buf` that still belongs to the headers
+    fn header_part(&self, buf: &[u8]) -> usize {
+        let mut matched = self.header_end;
+        for (i, &b) in buf.iter().enumerate() {
+            matched = advance_header_end(matched, b);
+            if matched == 4 {
+                return i + 1;
+            }
+        }
+        buf.len()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner
+    }
+
+    pub fn stalled(&self) -> bool {
+        self.stalled
+    }
+
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+
+    pub fn request_id(&self) -> Option<u32> {
+        self.request_id
+    }
+
+    // The handler aborted the socket for `reason`; the server task counts it
+    pub fn set_aborted(&mut self, reason: Close) {
+        self.aborted = Some(reason);
+    }
+
+    pub fn aborted(&self) -> Option<Close> {
+        self.aborted
+    }
+
+    // The response went out in full, but a graceful close counts as `kind`
+    pub fn set_answered_as(&mut self, kind: Close) {
+        self.answered_as = Some(kind);
+    }
+
+    pub fn answered_as(&self) -> Option<Close> {
+        self.answered_as
+    }
+}
+
+impl<W: Write> ErrorType for ProgressWriter<'_, W> {
+    type Error = WriteError<W::Error>;
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if self.stalled {
+            return Err(WriteError::Stalled);
+        }
+        let mut buf = buf;
+        if self.suppress_body {
+            if self.header_end == 4 {
+                return Ok(buf.len());
+            }
+            buf = &buf[..self.header_part(buf)];
+        }
+        // up to the end of the status line, then the request ID
+        let status_line_end = match self.in_status_line {
+            true => buf.iter().position(|&b| b == b'\n').map(|i| i + 1),
+            false => None,
+        };
+        if let Some(end) = status_line_end {
+            buf = &buf[..end];
+        }
+        match with_timeout(self.timeout, self.inner.write(buf)).await {
+            Ok(Ok(n)) => {
+                self.written = self.written.wrapping_add(n as u32);
+                for &b in &buf[..n] {
+                    self.header_end = advance_header_end(self.header_end, b);
+                }
+                if status_line_end == Some(n) {
+                    self.in_status_line = false;
+                    self.write_request_id().await?;
+                }
+                Ok(n)
+            }
+            Ok(Err(e)) => Err(WriteError::Io(e)),
+            Err(_) => {
+                self.stalled = true;
+                Err(WriteError::Stalled)
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.stalled {
+            return Err(WriteError::Stalled);
+        }
+        match with_timeout(self.timeout, self.inner.flush()).await {
+            Ok(result) => result.map_err(WriteError::Io),
+            Err(_) => {
+                self.stalled = true;
+                Err(WriteError::Stalled)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embassy_time::Timer;
+
+    const OFFERED: [&str; 2] = ["text/html", "application/json"];
+
+    // A client that sends each chunk at its time (ms after accept), then
+    // closes; a chunk at u64::MAX never comes. A chunk larger than the
+    // read buffer is handed out over several reads.
+    struct Script<'s> {
+        accepted: Instant,
+        chunks: &'s [(u64, &'s [u8])],
+        next: usize,
+        sent: usize,
+    }
+
+    impl ErrorType for Script<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Script<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            let Some(&(at, data)) = self.chunks.get(self.next) else {
+                return Ok(0);
+            };
+            if at == u64::MAX {
+                core::future::pending::<()>().await;
+            }
+            Timer::at(self.accepted + Duration::from_millis(at)).await;
+            let data = &data[self.sent..];
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            self.sent += n;
+            if n == data.len() {
+                self.next += 1;
+                self.sent = 0;
+            }
+            Ok(n)
+        }
+    }
+
+    const DEADLINES: Deadlines = Deadlines {
+        header_ms: 300,
+        request_ms: 600,
+        write_progress_ms: 1000,
+    };
+
+    fn read(chunks: &[(u64, &[u8])]) -> Result<Vec<u8>, ReadError> {
+        read_with(DEADLINES, chunks)
+    }
+
+    fn read_with(deadlines: Deadlines, chunks: &[(u64, &[u8])]) -> Result<Vec<u8>, ReadError> {
+        let accepted = Instant::now();
+        let mut socket = Script {
+            accepted,
+            chunks,
+            next: 0,
+            sent: 0,
+        };
+        let mut buf = [0; limits::REQUEST_BUFFER];
+        let len = embassy_futures::block_on(read_request(&mut socket, &mut buf, accepted, &deadlines))?;
+        Ok(buf[..len].to_vec())
+    }
+
+    #[test]
+    fn absent_or_blank_accept_gets_the_default() {
+        assert_eq!(negotiate(None, &OFFERED), "text/html");
+        assert_eq!(negotiate(Some("  "), &OFFERED), "text/html");
+    }
+
+    #[test]
+    fn q_values_pick_the_type() {
+        assert_eq!(negotiate(Some("application/json"), &OFFERED), "application/json");
+        assert_eq!(negotiate(Some("text/html;q=0.5, application/json;q=0.9"), &OFFERED), "application/json");
+        assert_eq!(negotiate(Some("text/html;q=0.9, application/json;q=0.5"), &OFFERED), "text/html");
+        assert_eq!(negotiate(Some("application/json; Q=1.000"), &OFFERED), "application/json");
+        // equal q keeps the server's order
+        assert_eq!(negotiate(Some("application/json, text/html"), &OFFERED), "text/html");
+    }
+
+    #[test]
+    fn wildcards_and_specificity() {
+        assert_eq!(negotiate(Some("*/*"), &OFFERED), "text/html");
+        assert_eq!(negotiate(Some("application/*, text/html;q=0.3"), &OFFERED), "application/json");
+        assert_eq!(quality_for("text/*;q=0.4, text/html;q=0.7, */*;q=0.1", "text/html"), Some(700));
+        assert_eq!(quality_for("text/*;q=0.4, text/html;q=0.7, */*;q=0.1", "text/plain"), Some(400));
+        assert_eq!(quality_for("text/*;q=0.4, text/html;q=0.7, */*;q=0.1", "image/png"), Some(100));
+        assert_eq!(quality_for("text/html", "image/png"), Some(0));
+        assert_eq!(quality_for("TEXT/HTML", "text/html"), Some(1000));
+    }
+
+    #[test]
+    fn q_zero_excludes() {
+        assert_eq!(quality_for("text/html;q=0, */*", "text/html"), Some(0));
+        assert_eq!(negotiate(Some("text/html;q=0, */*"), &OFFERED), "application/json");
+        assert_eq!(negotiate(Some("application/json;q=0.000"), &OFFERED), "text/html");
+        // rejecting everything offered still gets the default
+        assert_eq!(negotiate(Some("text/html;q=0, application/json;q=0"), &OFFERED), "text/html");
+    }
+
+    #[test]
+    fn malformed_parameters() {
+        for accept in [
+            "application/json;q=abc",
+            "application/json;q=1.5",
+            "application/json;q=2",
+            "application/json;q=0.1234",
+            "application/json;q=",
+            "applicationjson",
+            "/json",
+            "application/",
+        ] {
+            assert_eq!(quality_for(accept, "application/json"), None, "{accept}");
+            assert_eq!(negotiate(Some(accept), &OFFERED), "text/html", "{accept}");
+        }
+        // parameters other than q, and empty list items, are ignored
+        assert_eq!(quality_for("application/json;charset=utf-8;level", "application/json"), Some(1000));
+        assert_eq!(quality_for(",application/json;q=0.25,", "application/json"), Some(250));
+    }
+
+    #[test]
+    fn slow_headers_time_out() {
+        let got = read(&[(0, b"GET / HTTP/1.1\r\n"), (100, b"Host: a\r\n"), (400, b"\r\n")]);
+        assert_eq!(got, Err(ReadError::HeaderTimeout));
+        // a client that never finishes the request line
+        assert_eq!(read(&[(0, b"GE"), (u64::MAX, b"")]), Err(ReadError::HeaderTimeout));
+    }
+
+    // 请求行分几段到达, 最后一段晚于 header_ms: 408
+    #[test]
+    fn split_headers_past_the_deadline() {
+        let got = read(&[(0, b"GET /status HT"), (100, b"TP/1.1\r\nHost: a"), (350, b"\r\n\r\n")]);
+        assert_eq!(got, Err(ReadError::HeaderTimeout));
+        let got = read(&[(0, b"GET /status HT"), (100, b"TP/1.1\r\nHost: a"), (250, b"\r\n\r\n")]);
+        let request = got.unwrap();
+        assert_eq!(parse_head(&request).map(|r| r.path), Some("/status"));
+
+        // the request deadline bounds the headers too, and is still a 408
+        let deadlines = Deadlines { header_ms: 900, ..DEADLINES };
+        let got = read_with(deadlines, &[(0, b"GET / HTTP/1.1\r\n"), (700, b"\r\n")]);
+        assert_eq!(got, Err(ReadError::HeaderTimeout));
+        assert!(read_with(deadlines, &[(0, b"GET / HTTP/1.1\r\n"), (500, b"\r\n")]).is_ok());
+    }
+
+    #[test]
+    fn slow_body_times_out() {
+        let head: &[u8] = b"POST /at HTTP/1.1\r\nContent-Length: 10\r\n\r\n";
+        let got = read(&[(0, head), (200, b"cmd=A"), (700, b"T+CSQ")]);
+        assert_eq!(got, Err(ReadError::RequestTimeout));
+        // the header deadline no longer applies once the headers are in
+        let got = read(&[(0, head), (400, b"cmd=A"), (u64::MAX, b"")]);
+        assert_eq!(got, Err(ReadError::RequestTimeout));
+    }
+
+    #[test]
+    fn request_just_inside_the_deadlines() {
+        let got = read(&[(0, b"POST /at HTTP/1.1\r\n"), (200, b"Content-Length: 10\r\n\r\ncmd=A"), (450, b"T+CSQ")]);
+        assert_eq!(got.as_deref(), Ok(&b"POST /at HTTP/1.1\r\nContent-Length: 10\r\n\r\ncmd=AT+CSQ"[..]));
+        let got = read(&[(0, b"GET / HTTP/1.1\r\n"), (200, b"\r\n")]);
+        assert_eq!(got.as_deref(), Ok(&b"GET / HTTP/1.1\r\n\r\n"[..]));
+    }
+
+    #[test]
+    fn peer_closing_early() {
+        assert_eq!(read(&[]), Err(ReadError::Closed));
+        let got = read(&[(0, b"POST /at HTTP/1.1\r\nContent-Length: 10\r\n\r\ncmd")]);
+        assert_eq!(got, Err(ReadError::BodyIncomplete));
+    }
+
+    // 400: 正文比 Content-Length 短, 无论字节是不是 UTF-8
+    #[test]
+    fn body_incomplete_is_a_400() {
+        let head: &[u8] = b"POST /at HTTP/1.1\r\nContent-Length: 8\r\n\r\n";
+        assert_eq!(read(&[(0, head), (50, b"cmd=\xff\xfe")]), Err(ReadError::BodyIncomplete));
+        let got = read(&[(0, head), (50, b"cmd=\xff\xfeAT")]).unwrap();
+        assert_eq!(parse_head(&got).unwrap().body, b"cmd=\xff\xfeAT");
+
+        let answer = core::str::from_utf8(BODY_INCOMPLETE).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(length_consistent(answer));
+        assert_eq!(content_length(BODY_INCOMPLETE), "Request body incomplete\n".len());
+    }
+
+    // A request whose request line, header block and body are exactly the
+    // given lengths
+    fn sized(method: &str, line: usize, block: usize, body: usize) -> Vec<u8> {
+        let mut head = format!("{method} /");
+        head += &"a".repeat(line - head.len() - " HTTP/1.1".len());
+        head += " HTTP/1.1\r\n";
+        let fixed = format!("Content-Length: {body}\r\n\r\n");
+        let filler = block - head.len() - fixed.len();
+        if filler > 0 {
+            head += &format!("X: {}\r\n", "b".repeat(filler - "X: \r\n".len()));
+        }
+        head += &fixed;
+        assert_eq!(head.len(), block);
+        let mut request = head.into_bytes();
+        request.resize(block + body, b'c');
+        request
+    }
+
+    fn read_all(request: &[u8]) -> Result<usize, ReadError> {
+        read(&[(0, request)]).map(|r| r.len())
+    }
+
+    #[test]
+    fn request_line_limit() {
+        let max = limits::MAX_REQUEST_LINE;
+        assert_eq!(read_all(&sized("GET", max - 1, 1024, 0)), Ok(1024));
+        assert_eq!(read_all(&sized("GET", max, 1024, 0)), Ok(1024));
+        assert_eq!(read_all(&sized("GET", max + 1, 1024, 0)), Err(ReadError::HeadersTooLarge));
+        // still too long while the rest of the line is on its way
+        let line = vec![b'a'; max + 1];
+        assert_eq!(read(&[(0, b"GET /"), (0, &line), (u64::MAX, b"")]), Err(ReadError::HeadersTooLarge));
+    }
+
+    #[test]
+    fn header_block_limit() {
+        let max = limits::MAX_HEADER_BLOCK;
+        assert_eq!(read_all(&sized("GET", 64, max - 1, 0)), Ok(max - 1));
+        assert_eq!(read_all(&sized("GET", 64, max, 0)), Ok(max));
+        assert_eq!(read_all(&sized("GET", 64, max + 1, 0)), Err(ReadError::HeadersTooLarge));
+        // a block that never ends is refused once it reaches the limit
+        let unended = &sized("GET", 64, max + 64, 0)[..max];
+        assert_eq!(read(&[(0, unended), (u64::MAX, b"")]), Err(ReadError::HeadersTooLarge));
+    }
+
+    #[test]
+    fn body_limits_per_route() {
+        let form = limits::FORM_BODY_MAX;
+        assert_eq!(read_all(&sized("POST", 64, 256, form - 1)), Ok(256 + form - 1));
+        assert_eq!(read_all(&sized("POST", 64, 256, form)), Ok(256 + form));
+        assert_eq!(read_all(&sized("POST", 64, 256, form + 1)), Err(ReadError::BodyTooLarge));
+
+        // GET takes no body at all
+        assert_eq!(read_all(&sized("GET", 64, 256, 0)), Ok(256));
+        assert_eq!(read_all(&sized("GET", 64, 256, 1)), Err(ReadError::BodyTooLarge));
+    }
+
+    // Routes whose bodies may outgrow the buffer: read_request stops once it is full
+    #[test]
+    fn streamed_body_limits() {
+        let with_path = |path: &str, body: usize| {
+            let mut request = format!("POST {path} HTTP/1.1\r\nContent-Length: {body}\r\n\r\n").into_bytes();
+            request.resize(request.len() + body, b'c');
+            (read_all(&request), request.len().min(limits::REQUEST_BUFFER))
+        };
+        for (path, max) in [("/macros", limits::MACRO_FORM_MAX), ("/api/config/import", limits::CONFIG_DOC_MAX)] {
+            let (got, read) = with_path(path, max - 1);
+            assert_eq!(got, Ok(read), "{path}");
+            let (got, read) = with_path(path, max);
+            assert_eq!(got, Ok(read), "{path}");
+            assert_eq!(with_path(path, max + 1).0, Err(ReadError::BodyTooLarge), "{path}");
+        }
+    }
+
+    #[test]
+    fn parse_head_fields() {
+        let raw = b"POST /at?x=1&y HTTP/1.1\r\nHost: 192.168.4.1\r\ncontent-length:  7 \r\n\r\ncmd=AT";
+        let request = parse_head(raw).unwrap();
+        assert_eq!((request.method, request.path, request.query), ("POST", "/at", "x=1&y"));
+        assert_eq!(request.version, "HTTP/1.1");
+        assert!(request.accepts_chunked());
+        assert_eq!(request.header("Content-Length"), Some("7"));
+        assert_eq!(request.header("HOST"), Some("192.168.4.1"));
+        assert_eq!(request.header("Accept"), None);
+        assert_eq!(request.body, b"cmd=AT");
+    }
+
+    #[test]
+    fn parse_head_older_and_partial_requests() {
+        let request = parse_head(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(!request.accepts_chunked());
+        // HTTP/0.9 style: no version, no headers
+        let request = parse_head(b"GET /status\n").unwrap();
+        assert_eq!((request.path, request.version, request.body), ("/status", "", &b""[..]));
+        // headers still arriving
+        let request = parse_head(b"GET /a HTTP/1.1\r\nHost: x\r\nAcc").unwrap();
+        assert_eq!(request.header("Host"), Some("x"));
+        assert_eq!(request.body, b"");
+    }
+
+    #[test]
+    fn parse_head_rejects() {
+        assert!(parse_head(b"").is_none());
+        assert!(parse_head(b"GET\r\n\r\n").is_none());
+        assert!(parse_head(b" / HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_head(b"GET http://x/ HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn request_text_stops_at_invalid_utf8() {
+        assert_eq!(request_text(b"GET /caf\xc3\xa9 HTTP/1.1"), "GET /café HTTP/1.1");
+        // half a character at the end of a read, and a stray byte
+        assert_eq!(request_text(b"GET /caf\xc3"), "GET /caf");
+        assert_eq!(request_text(b"GET /\xff HTTP/1.1\r\n\r\n"), "GET /");
+        assert_eq!(request_text(b"\x80"), "");
+
+        // the body is not text: it is passed on whole
+        let raw = b"POST /at HTTP/1.1\r\nContent-Length: 4\r\n\r\nab\xffd";
+        assert_eq!(parse_head(raw).unwrap().body, b"ab\xffd");
+    }
+
+    #[test]
+    fn post_at_takes_cmd_from_the_body() {
+        let request = read(&[(0, b"POST /at HTTP/1.1\r\nContent-Length: 21\r\n\r\nx=%FF&cmd=AT%2BCSQ&y=")]).unwrap();
+        let request = parse_head(&request).unwrap();
+        assert_eq!(form_value(request.form(), "cmd"), Some("AT%2BCSQ"));
+        assert_eq!(form_value(request.form(), "y"), Some(""));
+        // GET takes it from the query, and ignores a body
+        let request = parse_head(b"GET /at?cmd=ATI HTTP/1.1\r\n\r\ncmd=AT").unwrap();
+        assert_eq!(form_value(request.form(), "cmd"), Some("ATI"));
+        // a stray byte spoils only the value it is in
+        let request = parse_head(b"POST /at HTTP/1.1\r\nContent-Length: 17\r\n\r\nnote=\xff&cmd=ATI").unwrap();
+        assert_eq!(form_value(request.form(), "cmd"), Some("ATI"));
+        assert_eq!(form_value(request.form(), "note"), None);
+        assert_eq!(form_value("flag&cmd", "flag"), Some(""));
+    }
+
+    // The client end of the socket, taking at most `max` bytes per write
+    struct Recorder {
+        sent: Vec<u8>,
+        max: usize,
+    }
+
+    impl ErrorType for Recorder {
+        type Error = ErrorKind;
+    }
+
+    impl Write for Recorder {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            let n = buf.len().min(self.max);
+            self.sent.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    // What the client receives when the handler writes `response` in
+    // pieces of `piece` bytes
+    fn serve(response: &[u8], piece: usize, max: usize, head: bool, request_id: Option<u32>) -> (Vec<u8>, u32) {
+        let mut client = Recorder { sent: Vec::new(), max };
+        let mut socket = ProgressWriter::new(&mut client, DEADLINES.write_progress_ms);
+        if head {
+            socket.suppress_body();
+        }
+        if let Some(id) = request_id {
+            socket.set_request_id(id);
+        }
+        for part in response.chunks(piece) {
+            embassy_futures::block_on(socket.write_all(part)).unwrap();
+        }
+        let written = socket.written();
+        (client.sent, written)
+    }
+
+    fn content_length(response: &[u8]) -> usize {
+        let head = core::str::from_utf8(&response[..find_header_end(response).unwrap()]).unwrap();
+        let line = head.lines().find_map(|l| l.strip_prefix("Content-Length: ")).unwrap();
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn head_sends_the_get_headers_without_the_body() {
+        let mut page = heapless::String::<2048>::new();
+        page.push_str("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n").unwrap();
+        // a body with a blank line of its own
+        page.push_str("<pre>\r\n\r\n</pre><p>status</p>").unwrap();
+        finish_page(&mut page).unwrap();
+        let page = page.as_bytes();
+
+        let (get, _) = serve(page, page.len(), usize::MAX, false, Some(42));
+        let header_end = find_header_end(&get).unwrap();
+        assert_eq!(content_length(&get), get.len() - header_end);
+        assert!(get.starts_with(b"HTTP/1.1 200 OK\r\nX-Request-Id: 42\r\n"));
+
+        // however the handler and the socket split the writes
+        for piece in [1, 2, 3, 5, 64, page.len()] {
+            for max in [1, 4, 7, usize::MAX] {
+                let (head, written) = serve(page, piece, max, true, Some(42));
+                assert_eq!(head, get[..header_end], "pieces of {piece}, writes of {max}");
+                assert_eq!(written as usize, header_end);
+                assert_eq!(content_length(&head), get.len() - header_end);
+            }
+        }
+    }
+
+    #[test]
+    fn head_of_an_error_response() {
+        let (head, _) = serve(PAGE_TOO_LARGE, 10, usize::MAX, true, None);
+        assert!(head.starts_with(b"HTTP/1.1 503 ") && head.ends_with(b"\r\n\r\n"));
+        assert_eq!(content_length(&head), "Page too large\n".len());
+        let (get, _) = serve(PAGE_TOO_LARGE, 10, usize::MAX, false, None);
+        assert_eq!(get, PAGE_TOO_LARGE);
+    }
+
+    #[test]
+    fn header_timeout_answer() {
+        let answer = core::str::from_utf8(HEADER_TIMEOUT).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(length_consistent(answer));
+        assert_eq!(content_length(HEADER_TIMEOUT), "Request headers took too long\n".len());
+    }
+
+    // The 408 closes with a FIN but is counted as a header timeout
+    #[test]
+    fn answered_close_kind() {
+        let mut client = Recorder { sent: Vec::new(), max: usize::MAX };
+        let mut socket = ProgressWriter::new(&mut client, DEADLINES.write_progress_ms);
+        assert_eq!(socket.answered_as(), None);
+        socket.set_answered_as(Close::HeaderTimeout);
+        embassy_futures::block_on(socket.write_all(HEADER_TIMEOUT)).unwrap();
+        assert_eq!((socket.answered_as(), socket.aborted()), (Some(Close::HeaderTimeout), None));
+        assert_eq!(client.sent, HEADER_TIMEOUT);
+    }
+}
@@ -0,0 +1,45 @@
+// 设备名和由它得到的主机名
+//
+// device.name is how the box introduces itself: page titles, webhook
+// payloads, SMS alerts, the config export and /api/status. Empty means
+// the default, "pico-gw-" and the last four hex digits of the WiFi MAC,
+// so boxes flashed with the same image still differ. The hostname is the
+// name cut down to what a DNS label allows: lower-case letters, digits and
+// '-', with any other run of characters turned into one '-'. Both are read
+// each time they are used, so a new name applies without a reboot.
+
+use core::fmt::Write as _;
+
+pub const MAX_NAME: usize = 32;
+
+pub type Name = heapless::String<MAX_NAME>;
+
+pub fn default_name(mac: [u8; 6]) -> Name {
+    let mut name = Name::new();
+    let _ = core::write!(name, "pico-gw-{:02x}{:02x}", mac[4], mac[5]);
+    name
+}
+
+// 1-32 printable characters, at least one of them usable in a hostname
+pub fn valid_name(name: &str) -> bool {
+    (1..=MAX_NAME).contains(&name.len()) && !name.chars().any(char::is_control) && !hostname(name).is_empty()
+}
+
+pub fn hostname(name: &str) -> Name {
+    let mut host = Name::new();
+    let mut gap = false;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            if gap && !host.is_empty() && host.push('-').is_err() {
+                break;
+            }
+            gap = false;
+            if host.push(c.to_ascii_lowercase()).is_err() {
+                break;
+            }
+        } else {
+            gap = true;
+        }
+    }
+    host
+}
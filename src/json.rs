@@ -0,0 +1,317 @@
+// 手写的 JSON 输出辅助函数 (no_std, heapless)
+
+use core::fmt::Write;
+
+pub fn push_str_value<const N: usize>(out: &mut heapless::String<N>, value: &str) {
+    let _ = out.push('"');
+    push_escaped(out, value);
+    let _ = out.push('"');
+}
+
+// String contents without the quotes, for values written in pieces
+pub fn push_escaped<const N: usize>(out: &mut heapless::String<N>, value: &str) {
+    for c in value.chars() {
+        let _ = match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).map_err(|_| ()),
+            c => out.push(c),
+        };
+    }
+}
+
+// Writes `{"key":value,...}` into a heapless string, handling the commas
+pub struct Object<'a, const N: usize> {
+    out: &'a mut heapless::String<N>,
+    first: bool,
+}
+
+impl<'a, const N: usize> Object<'a, N> {
+    pub fn new(out: &'a mut heapless::String<N>) -> Self {
+        let _ = out.push('{');
+        Self { out, first: true }
+    }
+
+    fn key(&mut self, key: &str) {
+        if !self.first {
+            let _ = self.out.push(',');
+        }
+        self.first = false;
+        push_str_value(self.out, key);
+        let _ = self.out.push(':');
+    }
+
+    pub fn str(&mut self, key: &str, value: &str) -> &mut Self {
+        self.key(key);
+        push_str_value(self.out, value);
+        self
+    }
+
+    pub fn u32(&mut self, key: &str, value: u32) -> &mut Self {
+        self.key(key);
+        let _ = write!(self.out, "{}", value);
+        self
+    }
+
+    pub fn bool(&mut self, key: &str, value: bool) -> &mut Self {
+        self.key(key);
+        let _ = self.out.push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    // Value that is already valid JSON (array, nested object)
+    pub fn raw(&mut self, key: &str, value: &str) -> &mut Self {
+        self.key(key);
+        let _ = self.out.push_str(value);
+        self
+    }
+
+    pub fn finish(self) {
+        let _ = self.out.push('}');
+    }
+}
+
+// 读取: 只支持对象, 字符串, 非负整数, 布尔和 null, 够读配置文档
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Value<'a> {
+    Number(u64),
+    // still escaped, see `unescape`
+    Str(&'a str),
+    Bool(bool),
+    Null,
+}
+
+// Byte offset where the document stopped making sense
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SyntaxError(pub usize);
+
+// Calls `f` with the dotted path of every scalar in a (nested) object,
+// e.g. ("tcp.timeout_ms", Number(10000))
+pub fn walk<'a>(text: &'a str, mut f: impl FnMut(&str, Value<'a>)) -> Result<(), SyntaxError> {
+    let mut reader = Reader { text, pos: 0 };
+    let mut path = heapless::String::<64>::new();
+    reader.object(&mut path, &mut f)?;
+    reader.skip_ws();
+    if reader.pos != text.len() {
+        return Err(SyntaxError(reader.pos));
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r' | b'\n')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> Result<(), SyntaxError> {
+        self.skip_ws();
+        if self.peek() != Some(byte) {
+            return Err(SyntaxError(self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str) -> Result<(), SyntaxError> {
+        if !self.text[self.pos..].starts_with(word) {
+            return Err(SyntaxError(self.pos));
+        }
+        self.pos += word.len();
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<&'a str, SyntaxError> {
+        self.eat(b'"')?;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => self.pos += 2,
+                Some(_) => self.pos += 1,
+                None => return Err(SyntaxError(self.pos)),
+            }
+        }
+        let raw = self.text.get(start..self.pos).ok_or(SyntaxError(start))?;
+        self.pos += 1;
+        Ok(raw)
+    }
+
+    fn number(&mut self) -> Result<u64, SyntaxError> {
+        let start = self.pos;
+        let mut value: u64 = 0;
+        while let Some(digit @ b'0'..=b'9') = self.peek() {
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add((digit - b'0') as u64))
+                .ok_or(SyntaxError(start))?;
+            self.pos += 1;
+        }
+        // 不支持小数和指数
+        if matches!(self.peek(), Some(b'.' | b'e' | b'E')) {
+            return Err(SyntaxError(start));
+        }
+        Ok(value)
+    }
+
+    fn object(&mut self, path: &mut heapless::String<64>, f: &mut impl FnMut(&str, Value<'a>)) -> Result<(), SyntaxError> {
+        self.eat(b'{')?;
+        self.skip_ws();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            let key = self.string()?;
+            self.eat(b':')?;
+            let base = path.len();
+            if base > 0 {
+                path.push('.').map_err(|_| SyntaxError(self.pos))?;
+            }
+            path.push_str(key).map_err(|_| SyntaxError(self.pos))?;
+            self.value(path, f)?;
+            path.truncate(base);
+
+            self.skip_ws();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(SyntaxError(self.pos)),
+            }
+        }
+    }
+
+    fn value(&mut self, path: &mut heapless::String<64>, f: &mut impl FnMut(&str, Value<'a>)) -> Result<(), SyntaxError> {
+        self.skip_ws();
+        let value = match self.peek() {
+            Some(b'{') => return self.object(path, f),
+            Some(b'"') => Value::Str(self.string()?),
+            Some(b'0'..=b'9') => Value::Number(self.number()?),
+            Some(b't') => self.literal("true").map(|_| Value::Bool(true))?,
+            Some(b'f') => self.literal("false").map(|_| Value::Bool(false))?,
+            Some(b'n') => self.literal("null").map(|_| Value::Null)?,
+            _ => return Err(SyntaxError(self.pos)),
+        };
+        f(path, value);
+        Ok(())
+    }
+}
+
+// None on a bad escape or when the result does not fit
+pub fn unescape<const N: usize>(raw: &str) -> Option<heapless::String<N>> {
+    let mut out = heapless::String::new();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'u' => {
+                    let hex = chars.as_str().get(..4)?;
+                    let code = u32::from_str_radix(hex, 16).ok()?;
+                    chars = chars.as_str()[4..].chars();
+                    char::from_u32(code)?
+                }
+                c @ ('"' | '\\' | '/') => c,
+                _ => return None,
+            },
+            c => c,
+        };
+        out.push(c).ok()?;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(value: &str) -> heapless::String<128> {
+        let mut out = heapless::String::new();
+        push_escaped(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn quotes_and_backslashes() {
+        assert_eq!(escaped(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(escaped(r"C:\path\"), r"C:\\path\\");
+        assert_eq!(escaped(r#"\""#), r#"\\\""#);
+    }
+
+    #[test]
+    fn control_bytes() {
+        assert_eq!(escaped("a\nb\rc\td"), r"a\nb\rc\td");
+        assert_eq!(escaped("\u{0}\u{1}\u{8}\u{c}\u{1b}\u{1f}"), r"\u0000\u0001\u0008\u000c\u001b\u001f");
+        // DEL and everything above it is legal inside a JSON string
+        assert_eq!(escaped("\u{7f} "), "\u{7f} ");
+    }
+
+    #[test]
+    fn non_ascii_passes_through() {
+        assert_eq!(escaped("Café 咖啡"), "Café 咖啡");
+        assert_eq!(escaped("中国移动 \"4G\""), r#"中国移动 \"4G\""#);
+    }
+
+    // The status document is built with Object; every string must read back
+    #[test]
+    fn status_fields_read_back() {
+        let ssid = "Café \"网\"\\\u{1}";
+        let operator = "中国移动\r\nCMCC";
+        let mut out = heapless::String::<256>::new();
+        let mut status = Object::new(&mut out);
+        status.str("ssid", ssid).str("operator", operator).u32("generation", 7).bool("recovery", false);
+        status.finish();
+        assert_eq!(
+            out,
+            r#"{"ssid":"Café \"网\"\\\u0001","operator":"中国移动\r\nCMCC","generation":7,"recovery":false}"#
+        );
+
+        let mut seen = 0;
+        walk(&out, |path, value| match (path, value) {
+            ("ssid", Value::Str(raw)) => {
+                assert_eq!(unescape::<64>(raw).unwrap(), ssid);
+                seen += 1;
+            }
+            ("operator", Value::Str(raw)) => {
+                assert_eq!(unescape::<64>(raw).unwrap(), operator);
+                seen += 1;
+            }
+            ("generation", Value::Number(7)) | ("recovery", Value::Bool(false)) => seen += 1,
+            _ => panic!("unexpected {path}"),
+        })
+        .unwrap_or_else(|e| panic!("syntax error at {}", e.0));
+        assert_eq!(seen, 4);
+    }
+
+    #[test]
+    fn escaping_stops_at_capacity_without_splitting_an_escape() {
+        let mut out = heapless::String::<5>::new();
+        push_escaped(&mut out, "ab\"cd");
+        assert_eq!(out, "ab\\\"c");
+        // no lone backslash when only half of the escape fits
+        let mut out = heapless::String::<3>::new();
+        push_escaped(&mut out, "ab\"");
+        assert_eq!(out, "ab");
+    }
+}
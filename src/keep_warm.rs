@@ -0,0 +1,51 @@
+// 保活: 定期在蜂窝网络上发一次 DNS 查询
+//
+// Carriers tear down idle PDP contexts and NAT bindings, so the first fetch
+// after hours of idling fails or crawls. Every keep_warm.interval_min the
+// modem checks the data context, reactivates it if it went down, and
+// resolves keep_warm.host. The module does not count bytes per flow, so
+// the cost shown is an estimate from the packet sizes.
+
+// keep_warm.host 为空时查询的名字
+pub const DEFAULT_HOST: &str = "example.com";
+
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub sent: u32,
+    // no answer, or the lookup failed
+    pub failed: u32,
+    // the context was down and had to be activated again
+    pub reactivations: u32,
+    pub last_ms: Option<u64>,
+    // estimated cellular bytes, both directions
+    pub bytes: u32,
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            sent: 0,
+            failed: 0,
+            reactivations: 0,
+            last_ms: None,
+            bytes: 0,
+        }
+    }
+
+    pub fn record(&mut self, host: &str, answered: bool, now_ms: u64) {
+        self.sent += 1;
+        if !answered {
+            self.failed += 1;
+        }
+        self.last_ms = Some(now_ms);
+        self.bytes = self.bytes.saturating_add(query_cost(host));
+    }
+}
+
+// IPv4 and UDP headers plus the DNS message: one question going out, the
+// same question and one A record coming back
+pub fn query_cost(host: &str) -> u32 {
+    let question = host.len() as u32 + 2 + 4;
+    let query = 20 + 8 + 12 + question;
+    query + query + 16
+}
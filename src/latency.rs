@@ -0,0 +1,83 @@
+// HTTP 获取耗时统计
+//
+// Every completed fetch lands in a coarse histogram (exported to
+// Prometheus, never reset) and in a ring of the last N durations, which
+// the p50/p95 summary is computed from exactly.
+
+// 桶的上界 (ms), 最后还有一个 +Inf 桶
+pub const BUCKETS_MS: [u32; 4] = [1_000, 2_000, 5_000, 10_000];
+
+pub struct Summary {
+    pub count: u32,
+    pub p50_ms: u32,
+    pub p95_ms: u32,
+    pub max_ms: u32,
+}
+
+pub struct Histogram<const N: usize> {
+    // per bucket, not cumulative; the last one is +Inf
+    buckets: [u32; BUCKETS_MS.len() + 1],
+    count: u32,
+    sum_ms: u64,
+    max_ms: u32,
+    recent: [u32; N],
+    next: usize,
+}
+
+impl<const N: usize> Histogram<N> {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [0; BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0,
+            max_ms: 0,
+            recent: [0; N],
+            next: 0,
+        }
+    }
+
+    pub fn record(&mut self, duration_ms: u32) {
+        let bucket = BUCKETS_MS.iter().position(|&le| duration_ms <= le).unwrap_or(BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += duration_ms as u64;
+        self.max_ms = self.max_ms.max(duration_ms);
+        self.recent[self.next] = duration_ms;
+        self.next = (self.next + 1) % N;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn sum_ms(&self) -> u64 {
+        self.sum_ms
+    }
+
+    // (upper bound, fetches at or below it); None is +Inf
+    pub fn cumulative(&self) -> impl Iterator<Item = (Option<u32>, u32)> + '_ {
+        let bounds = BUCKETS_MS.iter().map(|&le| Some(le)).chain(core::iter::once(None));
+        bounds.zip(self.buckets.iter().scan(0, |total, &n| {
+            *total += n;
+            Some(*total)
+        }))
+    }
+
+    // Percentiles over the last N fetches, max over all of them
+    pub fn summary(&self) -> Option<Summary> {
+        if self.count == 0 {
+            return None;
+        }
+        let n = (self.count as usize).min(N);
+        let mut sorted = self.recent;
+        sorted[..n].sort_unstable();
+        // nearest-rank
+        let rank = |p: usize| sorted[(p * n).div_ceil(100).max(1) - 1];
+        Some(Summary {
+            count: self.count,
+            p50_ms: rank(50),
+            p95_ms: rank(95),
+            max_ms: self.max_ms,
+        })
+    }
+}
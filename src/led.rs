@@ -0,0 +1,88 @@
+// 板载 LED 的覆盖和 "找到我" 闪烁
+//
+// The LED normally shows the run state: steady on, the triple blink in
+// recovery mode, or a short flash every two seconds while the AP sleeps.
+// POST /api/led sets an override that holds until reboot, and POST
+// /api/identify blinks it fast for IDENTIFY_MS so one box can be told
+// apart from identical ones on a shelf. Identify wins over the
+// override, and the override wins over the run state. The LED task asks
+// `source()` what to show.
+
+pub const IDENTIFY_MS: u64 = 30_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    // the run state decides
+    Auto,
+    On,
+    Off,
+}
+
+impl Mode {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "auto" => Some(Mode::Auto),
+            "on" => Some(Mode::On),
+            "off" => Some(Mode::Off),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Auto => "auto",
+            Mode::On => "on",
+            Mode::Off => "off",
+        }
+    }
+}
+
+// What the LED shows now
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    Identify,
+    Fixed(bool),
+    RunState,
+}
+
+pub struct Led {
+    mode: Mode,
+    identify_until_ms: Option<u64>,
+}
+
+impl Led {
+    pub const fn new() -> Self {
+        Self {
+            mode: Mode::Auto,
+            identify_until_ms: None,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    // Starts over when already blinking
+    pub fn identify(&mut self, now_ms: u64) {
+        self.identify_until_ms = Some(now_ms + IDENTIFY_MS);
+    }
+
+    pub fn source(&self, now_ms: u64) -> Source {
+        if self.identify_until_ms.is_some_and(|until| now_ms < until) {
+            return Source::Identify;
+        }
+        match self.mode {
+            Mode::Auto => Source::RunState,
+            Mode::On => Source::Fixed(true),
+            Mode::Off => Source::Fixed(false),
+        }
+    }
+
+    // For /api/status: "identify" while blinking, else the mode
+    pub fn as_str(&self, now_ms: u64) -> &'static str {
+        match self.source(now_ms) {
+            Source::Identify => "identify",
+            _ => self.mode.as_str(),
+        }
+    }
+}
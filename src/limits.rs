@@ -0,0 +1,30 @@
+// 请求大小上限
+//
+// A request line over MAX_REQUEST_LINE or a header block (request line to
+// the blank line, inclusive) over MAX_HEADER_BLOCK is answered 431, a
+// Content-Length over what the route takes 413. Both are decided before any
+// of the body is read, and the connection is closed afterwards. Only POST
+// carries a body; every other method is limited to none.
+
+pub const MAX_REQUEST_LINE: usize = 512;
+pub const MAX_HEADER_BLOCK: usize = 2048;
+
+// Form posts handled from the request buffer
+pub const FORM_BODY_MAX: usize = 2048;
+// The request buffer: the largest header block and form after it
+pub const REQUEST_BUFFER: usize = MAX_HEADER_BLOCK + FORM_BODY_MAX;
+
+// Larger POST bodies (config import, macro form) are collected here
+pub const POST_BODY_MAX: usize = 12 * 1024;
+// 1 KiB of steps percent-encoded, plus the name
+pub const MACRO_FORM_MAX: usize = 3 * 1024 + 64;
+pub const CONFIG_DOC_MAX: usize = POST_BODY_MAX;
+
+pub fn max_body(method: &str, path: &str) -> usize {
+    match (method, path) {
+        ("POST", "/api/config/import") => CONFIG_DOC_MAX,
+        ("POST", "/macros") => MACRO_FORM_MAX,
+        ("POST", _) => FORM_BODY_MAX,
+        _ => 0,
+    }
+}
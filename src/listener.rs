@@ -0,0 +1,78 @@
+// HTTP 监听端口和它们的角色
+//
+// http.port is where the pages are served, 80 unless configured. When
+// http.port2 is not 0, a second listener opens there, and http.role2 says
+// what it does:
+//   mirror       serves the same pages as http.port
+//   admin        serves the pages, and http.port only redirects to it
+//   portal-only  only redirects to http.port
+// A redirecting listener answers every request with a 302 to / on the
+// port that serves the pages, which is all a captive-portal probe needs.
+// Each listener runs its own set of workers sharing the socket pool. The
+// ports are read once at boot, so a change applies after a reboot.
+
+pub const MAX_LISTENERS: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Mirror,
+    Admin,
+    PortalOnly,
+}
+
+impl Role {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "mirror" => Some(Role::Mirror),
+            "admin" => Some(Role::Admin),
+            "portal-only" => Some(Role::PortalOnly),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Mirror => "mirror",
+            Role::Admin => "admin",
+            Role::PortalOnly => "portal-only",
+        }
+    }
+}
+
+// What a listener answers with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Serves {
+    Pages,
+    // a 302 to this port
+    RedirectTo(u16),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Listener {
+    pub port: u16,
+    pub serves: Serves,
+    // netstat owner
+    pub name: &'static str,
+}
+
+// The listeners to start, http.port first; port2 = 0 means only one
+pub fn plan(port: u16, port2: u16, role2: Role) -> [Option<Listener>; MAX_LISTENERS] {
+    let (first, second) = match role2 {
+        _ if port2 == 0 => (Serves::Pages, None),
+        Role::Mirror => (Serves::Pages, Some(Serves::Pages)),
+        Role::Admin => (Serves::RedirectTo(port2), Some(Serves::Pages)),
+        Role::PortalOnly => (Serves::Pages, Some(Serves::RedirectTo(port))),
+    };
+    [
+        Some(Listener {
+            port,
+            serves: first,
+            name: "http",
+        }),
+        second.map(|serves| Listener {
+            port: port2,
+            serves,
+            name: "http2",
+        }),
+    ]
+}
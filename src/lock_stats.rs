@@ -0,0 +1,168 @@
+// 异步锁的持有时间, 每个取锁的地方一行
+//
+// The async mutexes (the result area, the modem and UART1 logs, the POST
+// body and checkpoint buffers, the log compressor and the UART1 sender) can
+// be held across an await, so a slow holder stalls every task waiting on the same lock.
+// Each place that takes one names its Site, and the hold is measured when
+// the guard drops. The table keeps the count, the longest hold, an
+// average that weighs the newest hold by 1/8, and the holds over
+// http.lock_budget_ms. /api/lockstats shows the table. A hold over the
+// budget is also logged at warn level. The compressor and the two buffers
+// are held across network or flash I/O by design and only ever wanted by
+// the next request or checkpoint, so they are measured but have no budget.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Site {
+    // status and fetch code writing the result area
+    ResultWrite,
+    // copying the result area for /tools and /api/status
+    ResultPage,
+    ModemLogRecord,
+    ModemLogRead,
+    // the checkpoint's copy out of the modem log
+    LogCheckpoint,
+    LogCompress,
+    CheckpointBuffer,
+    Uart1Log,
+    Uart1Write,
+    PostBody,
+}
+
+pub const SITES: usize = 10;
+
+impl Site {
+    pub const ALL: [Site; SITES] = [
+        Site::ResultWrite,
+        Site::ResultPage,
+        Site::ModemLogRecord,
+        Site::ModemLogRead,
+        Site::LogCheckpoint,
+        Site::LogCompress,
+        Site::CheckpointBuffer,
+        Site::Uart1Log,
+        Site::Uart1Write,
+        Site::PostBody,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Site::ResultWrite => "result_write",
+            Site::ResultPage => "result_page",
+            Site::ModemLogRecord => "modem_log_record",
+            Site::ModemLogRead => "modem_log_read",
+            Site::LogCheckpoint => "log_checkpoint",
+            Site::LogCompress => "log_compress",
+            Site::CheckpointBuffer => "checkpoint_buffer",
+            Site::Uart1Log => "uart1_log",
+            Site::Uart1Write => "uart1_write",
+            Site::PostBody => "post_body",
+        }
+    }
+
+    pub fn budgeted(self) -> bool {
+        !matches!(self, Site::LogCompress | Site::CheckpointBuffer | Site::PostBody)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub count: u32,
+    pub max_us: u32,
+    pub ewma_us: u32,
+    pub over_budget: u32,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        count: 0,
+        max_us: 0,
+        ewma_us: 0,
+        over_budget: 0,
+    };
+}
+
+pub struct Table {
+    entries: [Entry; SITES],
+}
+
+impl Table {
+    pub const fn new() -> Self {
+        Self {
+            entries: [Entry::EMPTY; SITES],
+        }
+    }
+
+    // True when a budgeted site went over the budget
+    pub fn record(&mut self, site: Site, hold_us: u32, budget_us: u32) -> bool {
+        let entry = &mut self.entries[site as usize];
+        entry.ewma_us = if entry.count == 0 {
+            hold_us
+        } else {
+            ((entry.ewma_us as u64 * 7 + hold_us as u64) / 8) as u32
+        };
+        entry.count = entry.count.saturating_add(1);
+        entry.max_us = entry.max_us.max(hold_us);
+        let over = site.budgeted() && hold_us > budget_us;
+        if over {
+            entry.over_budget = entry.over_budget.saturating_add(1);
+        }
+        over
+    }
+
+    // In Site::ALL order
+    pub fn entries(&self) -> [Entry; SITES] {
+        self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ewma(table: &Table, site: Site) -> u32 {
+        table.entries()[site as usize].ewma_us
+    }
+
+    #[test]
+    fn first_hold_is_the_average() {
+        let mut table = Table::new();
+        table.record(Site::ResultWrite, 5000, 1000);
+        assert_eq!(ewma(&table, Site::ResultWrite), 5000);
+        // the other sites are untouched
+        assert_eq!(ewma(&table, Site::ResultPage), 0);
+        table.record(Site::ResultWrite, 1000, 1000);
+        assert_eq!(ewma(&table, Site::ResultWrite), 4500);
+    }
+
+    // The division truncates: a rising input stops up to 7 us short of
+    // itself, a falling one reaches it
+    #[test]
+    fn steady_holds_converge() {
+        let mut table = Table::new();
+        table.record(Site::Uart1Log, 0, 1000);
+        for _ in 0..200 {
+            table.record(Site::Uart1Log, 100, 1000);
+        }
+        assert_eq!(ewma(&table, Site::Uart1Log), 93);
+        for _ in 0..200 {
+            table.record(Site::Uart1Log, 0, 1000);
+        }
+        assert_eq!(ewma(&table, Site::Uart1Log), 0);
+
+        table.record(Site::PostBody, u32::MAX, 0);
+        table.record(Site::PostBody, u32::MAX, 0);
+        assert_eq!(ewma(&table, Site::PostBody), u32::MAX);
+    }
+
+    #[test]
+    fn only_budgeted_sites_go_over() {
+        let mut table = Table::new();
+        assert!(!table.record(Site::ResultWrite, 1000, 1000));
+        assert!(table.record(Site::ResultWrite, 1001, 1000));
+        assert!(!table.record(Site::PostBody, 1_000_000, 1000));
+        let entries = table.entries();
+        assert_eq!(entries[Site::ResultWrite as usize].over_budget, 1);
+        assert_eq!(entries[Site::ResultWrite as usize].max_us, 1001);
+        assert_eq!(entries[Site::PostBody as usize].over_budget, 0);
+    }
+}
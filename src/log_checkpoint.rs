@@ -0,0 +1,84 @@
+// 日志检查点的调度 (写入闪存的部分见 flash_store)
+//
+// Every few minutes, and before a requested reboot, the part of the modem
+// log written since the last checkpoint goes into the next log sector,
+// round-robin. Only the newest sector's worth fits, and checkpoints are
+// spaced at least MIN_INTERVAL_MS apart to bound flash wear. At boot the
+// sectors left by the previous boot are found again for /log/previous.txt.
+
+pub const MIN_INTERVAL_MS: u64 = 60_000;
+// 重启前的最后一次检查点只要求和上一次隔开这么久
+pub const REBOOT_MIN_INTERVAL_MS: u64 = 5_000;
+
+pub struct Checkpoints<const N: usize> {
+    pub boot: u32,
+    next_seq: u32,
+    next_index: usize,
+    // log offset up to which everything was checkpointed (or lost)
+    saved_upto: u32,
+    last_write_ms: Option<u64>,
+    previous_boot: Option<u32>,
+    // sectors of the previous boot, oldest first
+    previous: heapless::Vec<usize, N>,
+    pub written: u32,
+}
+
+impl<const N: usize> Checkpoints<N> {
+    pub const fn new() -> Self {
+        Self {
+            boot: 0,
+            next_seq: 0,
+            next_index: 0,
+            saved_upto: 0,
+            last_write_ms: None,
+            previous_boot: None,
+            previous: heapless::Vec::new(),
+            written: 0,
+        }
+    }
+
+    // (sector index, boot, seq) of every valid sector found at boot
+    pub fn restore(&mut self, found: &[(usize, u32, u32)]) {
+        let newest = found.iter().max_by_key(|&&(_, boot, seq)| (boot, seq));
+        let Some(&(newest_index, previous_boot, _)) = newest else {
+            return;
+        };
+        // 按顺序轮流写, 最新扇区的下一个就是最旧的
+        self.next_index = (newest_index + 1) % N;
+        self.boot = previous_boot.wrapping_add(1);
+        self.previous_boot = Some(previous_boot);
+
+        let mut previous: heapless::Vec<(u32, usize), N> =
+            found.iter().filter(|f| f.1 == previous_boot).map(|&(index, _, seq)| (seq, index)).collect();
+        previous.sort_unstable();
+        self.previous = previous.iter().map(|&(_, index)| index).collect();
+    }
+
+    // Log range [first, end) to write now; None when nothing is new or the
+    // last checkpoint is too recent
+    pub fn due(&self, start: u32, end: u32, capacity: usize, now_ms: u64, reboot: bool) -> Option<(u32, u32)> {
+        let spacing = if reboot { REBOOT_MIN_INTERVAL_MS } else { MIN_INTERVAL_MS };
+        if self.last_write_ms.is_some_and(|at| now_ms.saturating_sub(at) < spacing) {
+            return None;
+        }
+        let first = self.saved_upto.max(start).max(end.saturating_sub(capacity as u32));
+        (first < end).then_some((first, end))
+    }
+
+    // (sector index, seq) for the next checkpoint
+    pub fn slot(&self) -> (usize, u32) {
+        (self.next_index, self.next_seq)
+    }
+
+    pub fn saved(&mut self, end: u32, now_ms: u64) {
+        self.saved_upto = end;
+        self.last_write_ms = Some(now_ms);
+        self.next_index = (self.next_index + 1) % N;
+        self.next_seq += 1;
+        self.written += 1;
+    }
+
+    pub fn previous(&self) -> (Option<u32>, heapless::Vec<usize, N>) {
+        (self.previous_boot, self.previous.clone())
+    }
+}
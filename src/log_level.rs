@@ -0,0 +1,88 @@
+// 运行时可调的日志级别
+//
+// defmt filters at compile time only (DEFMT_LOG lets everything through),
+// so these wrappers around its macros drop messages below the level picked
+// at runtime. Raw UART traffic is logged at trace, chatty progress at debug.
+
+use portable_atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Level> {
+        Level::ALL.into_iter().find(|level| level.as_str().eq_ignore_ascii_case(name))
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn get() -> Level {
+    Level::ALL[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Error) {
+            defmt::error!($($arg)*)
+        }
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Warn) {
+            defmt::warn!($($arg)*)
+        }
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Info) {
+            defmt::info!($($arg)*)
+        }
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Debug) {
+            defmt::debug!($($arg)*)
+        }
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Trace) {
+            defmt::trace!($($arg)*)
+        }
+    };
+}
@@ -0,0 +1,146 @@
+// 日志末尾放进页面
+//
+// /log and /log/uart1 show the newest TAIL_MAX bytes of a log ring. The
+// tail may start or end inside a character and may hold bytes that are not
+// UTF-8 at all (binary data from the modem); those show as U+FFFD. Either
+// can make the text longer than the tail: three bytes per stray byte, and
+// up to five per character once escaped for HTML. The /log page therefore
+// gives the tail a page_budget section, and any page that still does not
+// fit is refused whole by http::finish_page (a 503), never sent cut short.
+
+use crate::{http, template, utf8};
+
+// Bytes of the log the pages show
+pub const TAIL_MAX: usize = 2048;
+
+// The log bytes as text, escaped for HTML when asked
+pub fn push<const N: usize>(out: &mut heapless::String<N>, log: &[u8], escape_html: bool) {
+    // 尾部截取的两端可能落在字符中间
+    let log = &log[..utf8::complete_len(log)];
+    let log = &log[utf8::partial_start(log)..];
+    for chunk in log.utf8_chunks() {
+        if escape_html {
+            template::push_html_escaped(out, chunk.valid());
+        } else {
+            let _ = out.push_str(chunk.valid());
+        }
+        if !chunk.invalid().is_empty() {
+            let _ = out.push('\u{fffd}');
+        }
+    }
+}
+
+// /log.txt and /log/uart1.txt
+pub fn format_plain(log: &[u8]) -> Result<heapless::String<4096>, http::BufferFull> {
+    let mut response = heapless::String::new();
+
+    let _ = response.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = response.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    let _ = response.push_str("Connection: close\r\n\r\n");
+    push(&mut response, log, false);
+
+    http::finish_page(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_budget;
+
+    // Full tails: plain, all markup, all stray bytes, Chinese cut at both ends
+    fn tails() -> Vec<Vec<u8>> {
+        let mut chinese = "信号".repeat(TAIL_MAX / 6 + 1).into_bytes();
+        chinese.drain(..1);
+        chinese.truncate(TAIL_MAX);
+        vec![
+            b"AT+CSQ\r\n+CSQ: 23,99\r\nOK\r\n".repeat(TAIL_MAX / 25 + 1)[..TAIL_MAX].to_vec(),
+            vec![b'&'; TAIL_MAX],
+            b"<b>".repeat(TAIL_MAX / 3 + 1)[..TAIL_MAX].to_vec(),
+            vec![0xff; TAIL_MAX],
+            chinese,
+        ]
+    }
+
+    fn lossy(log: &[u8], escape_html: bool) -> String {
+        let mut text = String::new();
+        for chunk in log[..utf8::complete_len(log)][utf8::partial_start(log)..].utf8_chunks() {
+            if escape_html {
+                text += &chunk.valid().replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+            } else {
+                text += chunk.valid();
+            }
+            if !chunk.invalid().is_empty() {
+                text.push('\u{fffd}');
+            }
+        }
+        text
+    }
+
+    // A page that went out is whole and says so in its length; one that
+    // did not is answered with the 503
+    fn complete_or_refused<const N: usize>(page: Result<heapless::String<N>, http::BufferFull>, text: &str, end: &str) {
+        match page {
+            Ok(page) => {
+                assert!(http::length_consistent(&page));
+                assert!(page.contains("Content-Length: "));
+                assert!(page.contains(text), "text cut short");
+                assert!(page.ends_with(end));
+            }
+            Err(http::BufferFull) => {
+                let refused = core::str::from_utf8(http::PAGE_TOO_LARGE).unwrap();
+                assert!(refused.starts_with("HTTP/1.1 503 "));
+                assert!(http::length_consistent(refused));
+            }
+        }
+    }
+
+    #[test]
+    fn plain_log_page_is_complete_or_503() {
+        let mut refused = 0;
+        for tail in tails() {
+            let page = format_plain(&tail);
+            refused += page.is_err() as usize;
+            complete_or_refused(page, &lossy(&tail, false), "");
+        }
+        // three bytes of U+FFFD per stray byte cannot fit
+        assert_eq!(refused, 1);
+    }
+
+    // The /log page as main.rs builds it, with the header and controls
+    // before the tail stood in by filler
+    fn html_page(log: &[u8], budgeted: bool) -> Result<heapless::String<6144>, http::BufferFull> {
+        let mut html = heapless::String::<6144>::new();
+        let _ = html.push_str("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nConnection: close\r\n\r\n");
+        let _ = html.push_str(&"<p>navigation</p>".repeat(80));
+        let _ = html.push_str("<pre id='log'>");
+        let sections = [page_budget::LOG_TAIL];
+        let mut budget = page_budget::Budget::new(&sections, html.capacity() - http::PAGE_HEADROOM);
+        if budgeted {
+            let _ = budget.push(&mut html, 0, |html| push(html, log, true));
+        } else {
+            push(&mut html, log, true);
+        }
+        let _ = html.push_str("</pre></div></body></html>");
+        http::finish_page(&mut html)?;
+        Ok(html)
+    }
+
+    #[test]
+    fn html_log_page_is_complete_or_503() {
+        for tail in tails() {
+            let text = lossy(&tail, true);
+            let page = html_page(&tail, true);
+            // the budgeted tail is sent in full or swapped for the notice
+            let page = page.expect("a budgeted log page always fits");
+            let shown = if page.contains(&text) { text.as_str() } else { "Section log tail omitted" };
+            complete_or_refused(Ok(page), shown, "</html>");
+
+            // without a budget (the UART1 page) a tail that does not fit
+            // refuses the page instead of cutting it
+            complete_or_refused(html_page(&tail, false), &text, "</html>");
+        }
+        assert!(html_page(&[b'&'; TAIL_MAX], true).unwrap().contains("omitted"));
+        assert!(html_page(&[b'&'; TAIL_MAX], false).is_err());
+    }
+}
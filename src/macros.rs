@@ -0,0 +1,323 @@
+// AT 指令宏
+//
+// A macro is a named list of AT commands run one after another. A step may
+// name a substring its reply must contain; without one the step needs a
+// final OK. The text form is what the /macros page edits and what is saved
+// to flash, one step per line with optional expected text and timeout:
+//
+//   [diag]
+//   AT+CSQ | +CSQ: | 2000
+//   AT+CREG?
+//
+// A run stops at the first step that does not pass.
+
+use core::fmt::Write as _;
+
+pub const MAX_MACROS: usize = 8;
+pub const MAX_STEPS: usize = 8;
+pub const DEFAULT_TIMEOUT_MS: u32 = 2_000;
+const MAX_TIMEOUT_MS: u32 = 60_000;
+
+pub type Name = heapless::String<16>;
+
+#[derive(Clone)]
+pub struct Step {
+    pub command: heapless::String<64>,
+    pub expect: heapless::String<32>,
+    pub timeout_ms: u32,
+}
+
+#[derive(Clone)]
+pub struct Macro {
+    pub name: Name,
+    pub steps: heapless::Vec<Step, MAX_STEPS>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    BadName,
+    TooManyMacros,
+    // line number (1-based) within the parsed text
+    TooManySteps(usize),
+    TooLong(usize),
+    BadTimeout(usize),
+    StepOutsideMacro(usize),
+}
+
+impl ParseError {
+    pub fn describe<const N: usize>(&self, out: &mut heapless::String<N>) {
+        let _ = match self {
+            ParseError::BadName => out.push_str("name must be 1-16 letters, digits, '-' or '_'"),
+            ParseError::TooManyMacros => core::write!(out, "at most {} macros", MAX_MACROS).map_err(|_| ()),
+            ParseError::TooManySteps(line) => {
+                core::write!(out, "line {}: at most {} steps per macro", line, MAX_STEPS).map_err(|_| ())
+            }
+            ParseError::TooLong(line) => core::write!(out, "line {}: command or expected text too long", line).map_err(|_| ()),
+            ParseError::BadTimeout(line) => {
+                core::write!(out, "line {}: timeout must be 1-{} ms", line, MAX_TIMEOUT_MS).map_err(|_| ())
+            }
+            ParseError::StepOutsideMacro(line) => core::write!(out, "line {}: step before any [name]", line).map_err(|_| ()),
+        };
+    }
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 16 && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+// "command | expect | timeout"
+fn parse_step(text: &str, line: usize) -> Result<Step, ParseError> {
+    let mut fields = text.split('|').map(str::trim);
+    let mut step = Step {
+        command: heapless::String::new(),
+        expect: heapless::String::new(),
+        timeout_ms: DEFAULT_TIMEOUT_MS,
+    };
+    step.command
+        .push_str(fields.next().unwrap_or(""))
+        .map_err(|_| ParseError::TooLong(line))?;
+    step.expect
+        .push_str(fields.next().unwrap_or(""))
+        .map_err(|_| ParseError::TooLong(line))?;
+    if let Some(timeout) = fields.next().filter(|t| !t.is_empty()) {
+        step.timeout_ms = match timeout.parse() {
+            Ok(ms) if (1..=MAX_TIMEOUT_MS).contains(&ms) => ms,
+            _ => return Err(ParseError::BadTimeout(line)),
+        };
+    }
+    Ok(step)
+}
+
+// Steps of one macro, one per line; blank lines are skipped
+pub fn parse_steps(text: &str) -> Result<heapless::Vec<Step, MAX_STEPS>, ParseError> {
+    let mut steps = heapless::Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let step = parse_step(line, index + 1)?;
+        steps.push(step).map_err(|_| ParseError::TooManySteps(index + 1))?;
+    }
+    Ok(steps)
+}
+
+fn write_step<const N: usize>(out: &mut heapless::String<N>, step: &Step) {
+    let _ = out.push_str(&step.command);
+    if !step.expect.is_empty() || step.timeout_ms != DEFAULT_TIMEOUT_MS {
+        let _ = out.push_str(" |");
+    }
+    if !step.expect.is_empty() {
+        let _ = core::write!(out, " {}", step.expect);
+    }
+    if step.timeout_ms != DEFAULT_TIMEOUT_MS {
+        let _ = core::write!(out, " | {}", step.timeout_ms);
+    }
+    let _ = out.push('\n');
+}
+
+// 带密码的 AT 指令: (指令, 从第几个参数起隐藏)
+const SECRET_ARGS: &[(&str, usize)] = &[
+    ("+CPIN=", 0),
+    ("+CPWD=", 1),
+    ("+CLCK=", 2),
+    ("+CSTT=", 2),
+    ("+QICSGP=", 4),
+];
+
+// Copies a step line with PINs and passwords replaced by '*', for pages
+// that show stored macros to whoever reaches the device
+pub fn push_masked<const N: usize>(out: &mut heapless::String<N>, line: &str) {
+    let bytes = line.as_bytes();
+    let secret = SECRET_ARGS.iter().find_map(|&(command, first)| {
+        bytes
+            .windows(command.len())
+            .position(|w| w.eq_ignore_ascii_case(command.as_bytes()))
+            .map(|at| (at + command.len(), first))
+    });
+    let Some((args_start, first)) = secret else {
+        let _ = out.push_str(line);
+        return;
+    };
+
+    let _ = out.push_str(&line[..args_start]);
+    let mut arg = 0;
+    let mut in_args = true;
+    for c in line[args_start..].chars() {
+        // 期望文本和超时不是参数
+        if c == '|' {
+            in_args = false;
+        }
+        let masked = in_args && arg >= first && !matches!(c, ',' | '"' | ' ');
+        if in_args && c == ',' {
+            arg += 1;
+        }
+        let _ = out.push(if masked { '*' } else { c });
+    }
+}
+
+impl Macro {
+    pub fn write_steps<const N: usize>(&self, out: &mut heapless::String<N>) {
+        for step in &self.steps {
+            write_step(out, step);
+        }
+    }
+}
+
+pub struct Library {
+    macros: heapless::Vec<Macro, MAX_MACROS>,
+}
+
+impl Library {
+    pub const fn new() -> Self {
+        Self {
+            macros: heapless::Vec::new(),
+        }
+    }
+
+    // 出厂自带: 现场最常用的诊断指令
+    pub fn defaults() -> Self {
+        let mut library = Self::new();
+        if let Ok(steps) = parse_steps("ATI\nAT+CPIN? | READY\nAT+CSQ | +CSQ:\nAT+CREG? | +CREG:\nAT+CGATT? | +CGATT:") {
+            let _ = library.set("diag", steps);
+        }
+        library
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut library = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if !valid_name(name) {
+                    return Err(ParseError::BadName);
+                }
+                let mut mac = Macro {
+                    name: Name::new(),
+                    steps: heapless::Vec::new(),
+                };
+                let _ = mac.name.push_str(name);
+                library.macros.push(mac).map_err(|_| ParseError::TooManyMacros)?;
+                continue;
+            }
+            let step = parse_step(line, index + 1)?;
+            let mac = library.macros.last_mut().ok_or(ParseError::StepOutsideMacro(index + 1))?;
+            mac.steps.push(step).map_err(|_| ParseError::TooManySteps(index + 1))?;
+        }
+        Ok(library)
+    }
+
+    pub fn write_text<const N: usize>(&self, out: &mut heapless::String<N>) {
+        for mac in &self.macros {
+            let _ = core::writeln!(out, "[{}]", mac.name);
+            mac.write_steps(out);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Macro> {
+        self.macros.iter().find(|m| m.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Macro> {
+        self.macros.iter()
+    }
+
+    // Replaces a macro of the same name, empty steps delete it
+    pub fn set(&mut self, name: &str, steps: heapless::Vec<Step, MAX_STEPS>) -> Result<(), ParseError> {
+        if !valid_name(name) {
+            return Err(ParseError::BadName);
+        }
+        let existing = self.macros.iter().position(|m| m.name == name);
+        match (existing, steps.is_empty()) {
+            (Some(index), true) => {
+                self.macros.remove(index);
+            }
+            (Some(index), false) => self.macros[index].steps = steps,
+            (None, true) => {}
+            (None, false) => {
+                let mut mac = Macro {
+                    name: Name::new(),
+                    steps,
+                };
+                let _ = mac.name.push_str(name);
+                self.macros.push(mac).map_err(|_| ParseError::TooManyMacros)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    // final result arrived but the expected text was not in the reply
+    Unexpected,
+    Error,
+    Timeout,
+    Uart,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Unexpected => "unexpected",
+            Outcome::Error => "error",
+            Outcome::Timeout => "timeout",
+            Outcome::Uart => "uart",
+        }
+    }
+}
+
+// `matched`: some reply line contained the expected text;
+// `last`: the final result line, None when the step timed out
+pub fn check(step: &Step, matched: bool, last: Option<&str>) -> Outcome {
+    if !step.expect.is_empty() {
+        return match (matched, last) {
+            (true, _) => Outcome::Passed,
+            (false, Some(_)) => Outcome::Unexpected,
+            (false, None) => Outcome::Timeout,
+        };
+    }
+    match last {
+        Some("OK") => Outcome::Passed,
+        Some(_) => Outcome::Error,
+        None => Outcome::Timeout,
+    }
+}
+
+pub struct StepReport {
+    pub command: heapless::String<64>,
+    pub outcome: Outcome,
+    pub reply: heapless::String<128>,
+}
+
+// 一次运行的结果, /macros 页面和 JSON 接口读取
+pub struct Report {
+    pub name: Name,
+    pub started_ms: u64,
+    pub total_steps: usize,
+    pub steps: heapless::Vec<StepReport, MAX_STEPS>,
+    pub running: bool,
+}
+
+impl Report {
+    pub fn new(mac: &Macro, now_ms: u64) -> Self {
+        Self {
+            name: mac.name.clone(),
+            started_ms: now_ms,
+            total_steps: mac.steps.len(),
+            steps: heapless::Vec::new(),
+            running: true,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        !self.running
+            && self.steps.len() == self.total_steps
+            && self.steps.iter().all(|s| s.outcome == Outcome::Passed)
+    }
+}
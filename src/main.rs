@@ -1,24 +1,111 @@
 #![no_std]
 #![no_main]
 
+use core::fmt::Write as _;
 use cyw43_pio::{PioSpi, RM2_CLOCK_DIVIDER};
-use defmt::*;
-use embassy_executor::Spawner;
+use embassy_executor::{SpawnError, SpawnToken, Spawner};
 use embassy_net::tcp::TcpSocket;
 use embassy_net::{Config, Stack, StackResources};
+use embassy_rp::Peri;
 use embassy_rp::bind_interrupts;
-use embassy_rp::gpio::{Level, Output};
-use embassy_rp::peripherals::{DMA_CH0, PIO0, UART0};
+use embassy_rp::gpio::{AnyPin, Input, Level, Output, Pull};
+use embassy_rp::peripherals::{DMA_CH0, PIO0, UART0, UART1};
 use embassy_rp::pio::{InterruptHandler as PioInterruptHandler, Pio};
 use embassy_rp::uart::{
     BufferedInterruptHandler, BufferedUart, BufferedUartRx, BufferedUartTx, Config as UartConfig,
 };
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
 use embedded_io_async::Read;
 use embedded_io_async::Write;
+use portable_atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
+mod actions;
+mod ap_sleep;
+mod archive;
+mod arp;
+mod at;
+mod at_help;
+mod at_rtt;
+mod at_response;
+mod blob_check;
+#[macro_use]
+mod board;
+mod boot;
+mod buffer_pool;
+mod capture;
+mod config;
+mod conn_close;
+mod deflate;
+mod dns_cache;
+mod escalation;
+mod fetch;
+mod fetch_target;
+mod flash_store;
+mod health;
+#[cfg(feature = "proxy")]
+mod forward;
+#[cfg(feature = "gnss")]
+mod geofence;
+#[cfg(feature = "gnss")]
+mod gnss;
+mod http;
+mod identity;
+mod json;
+mod keep_warm;
+mod latency;
+mod led;
+mod limits;
+mod listener;
+mod lock_stats;
+mod log_checkpoint;
+#[macro_use]
+mod log_level;
+mod log_text;
+mod macros;
+mod modem;
+mod modem_log;
+mod modem_queue;
+#[cfg(feature = "sim-modem")]
+mod modem_sim;
+mod modem_sockets;
+mod netstat;
+mod page_budget;
+mod page_cache;
+mod probe_retry;
+#[cfg(feature = "proxy")]
+mod pac;
+mod rate_limit;
+mod registration;
+mod relay;
+mod response;
+mod rings;
+mod routes;
+mod rx_audit;
+mod schedule;
+#[cfg(feature = "proxy")]
+mod shaper;
+mod shutdown;
+mod sim;
+mod socket_budget;
+mod sparkline;
+mod stats;
+mod status_json;
+mod template;
+mod test_services;
+mod transcript;
+mod trigger;
+mod uart_errors;
+mod urc;
+mod utf8;
+mod version;
+mod webhook;
+
+use http::RangeCheck;
+use modem_log::Direction;
+use template::push_html_escaped;
+
 // Program metadata
 #[unsafe(link_section = ".bi_entries")]
 #[used]
@@ -34,10 +121,15 @@ pub static PICOTOOL_ENTRIES: [embassy_rp::binary_info::EntryAddr; 4] = [
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => PioInterruptHandler<PIO0>;
     UART0_IRQ => BufferedInterruptHandler<UART0>;
+    UART1_IRQ => BufferedInterruptHandler<UART1>;
+    ADC_IRQ_FIFO => embassy_rp::adc::InterruptHandler;
 });
 
 const WIFI_SSID: &str = "Pico2W_HTTP";
 const WIFI_PASSWORD: &str = "12345678";
+// 恢复模式下的开放热点
+const RECOVERY_SSID: &str = "Pico2W-Recovery";
+const UART_BAUDRATE: u32 = 921600;
 
 #[embassy_executor::task]
 async fn cyw43_task(
@@ -47,7 +139,7 @@ async fn cyw43_task(
 }
 
 #[embassy_executor::task]
-async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
+async fn net_task(mut runner: embassy_net::Runner<'static, arp::Tap<cyw43::NetDriver<'static>>>) -> ! {
     runner.run().await
 }
 
@@ -57,788 +149,9447 @@ static AT_RESULT: embassy_sync::mutex::Mutex<
     heapless::String<2048>,
 > = embassy_sync::mutex::Mutex::new(heapless::String::new());
 
-static AT_COMMAND_SIGNAL: embassy_sync::signal::Signal<
+// 串口任务执行的操作, 经 MODEM_OPS 排队
+enum ModemOp {
+    AtCommand(heapless::String<64>),
+    Fetch(fetch::Origin),
+    Macro(macros::Name),
+    SimUnlock(sim::Unlock),
+    Ping,
+    KeepWarm,
+    Recover(escalation::Step),
+    Webhooks,
+    #[cfg(feature = "proxy")]
+    Forwards,
+    // close the fetch connection kept open too long without use
+    ReleaseConnection,
+    // read the network time for the schedule windows
+    ClockSync,
+    // power the GNSS engine on or off, poll the position
+    #[cfg(feature = "gnss")]
+    Gnss,
+    // text message (geofence alerts, input triggers)
+    Sms {
+        number: heapless::String<20>,
+        text: heapless::String<96>,
+    },
+    // AT again after bring-up found no modem (probe_retry)
+    Probe,
+    // GET /relay: fetch the URL in RELAY and stream the body to the client
+    Relay,
+    // close everything and power the module off before a reboot
+    Shutdown,
+}
+
+impl ModemOp {
+    // (name, priority, longest time it may wait in the queue)
+    fn class(&self) -> (&'static str, modem_queue::Priority, Duration) {
+        use modem_queue::Priority::*;
+        match self {
+            ModemOp::AtCommand(_) => ("at_command", Interactive, Duration::from_secs(30)),
+            ModemOp::SimUnlock(_) => ("sim_unlock", Interactive, Duration::from_secs(30)),
+            ModemOp::Fetch(_) => ("fetch", User, Duration::from_secs(120)),
+            ModemOp::Macro(_) => ("macro", User, Duration::from_secs(120)),
+            ModemOp::Ping => ("ping", Background, Duration::from_secs(60)),
+            ModemOp::KeepWarm => ("keep_warm", Background, Duration::from_secs(300)),
+            ModemOp::Recover(_) => ("recovery", User, Duration::from_secs(120)),
+            ModemOp::Webhooks => ("webhooks", Background, Duration::from_secs(300)),
+            #[cfg(feature = "proxy")]
+            ModemOp::Forwards => ("forwards", User, Duration::from_secs(30)),
+            ModemOp::ReleaseConnection => ("release_connection", Background, Duration::from_secs(60)),
+            ModemOp::ClockSync => ("clock_sync", Background, Duration::from_secs(60)),
+            #[cfg(feature = "gnss")]
+            ModemOp::Gnss => ("gnss", Background, Duration::from_secs(60)),
+            ModemOp::Sms { .. } => ("sms", Background, Duration::from_secs(300)),
+            ModemOp::Probe => ("modem_probe", Background, Duration::from_secs(60)),
+            ModemOp::Relay => ("relay", User, Duration::from_secs(120)),
+            ModemOp::Shutdown => ("shutdown", Interactive, shutdown::TOTAL_TIMEOUT),
+        }
+    }
+}
+
+static MODEM_OPS: embassy_sync::blocking_mutex::Mutex<
     embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-    heapless::String<64>,
-> = embassy_sync::signal::Signal::new();
+    core::cell::RefCell<modem_queue::Queue<ModemOp, 8>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(modem_queue::Queue::new()));
 
-static HTTP_GET_SIGNAL: embassy_sync::signal::Signal<
+static MODEM_OPS_SIGNAL: embassy_sync::signal::Signal<
     embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
     (),
 > = embassy_sync::signal::Signal::new();
 
-#[embassy_executor::task]
-async fn http_server_task(stack: &'static Stack<'static>) {
-    info!("HTTP server task started");
-    
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
+// 正在执行的操作 (None = 空闲)
+static MODEM_CURRENT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<&'static str>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
 
-    loop {
-        let mut socket = TcpSocket::new(*stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(Duration::from_secs(10)));
+// 正在执行的操作是哪个 HTTP 请求排的队
+static MODEM_REQUEST: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<u32>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
 
-        if let Err(e) = socket.accept(80).await {
-            warn!("Accept error: {:?}", e);
-            Timer::after(Duration::from_millis(100)).await;
-            continue;
-        }
+// " (HTTP #<id>)" for log lines, empty for background work
+fn request_tag(request_id: Option<u32>) -> heapless::String<20> {
+    let mut tag = heapless::String::new();
+    if let Some(id) = request_id {
+        let _ = core::write!(tag, " (HTTP #{})", id);
+    }
+    tag
+}
 
-        // 读取请求
-        let mut buf = [0; 512];
-        let n = match socket.read(&mut buf).await {
-            Ok(n) => n,
-            Err(_) => continue,
-        };
+// False when the queue is full (modem busy). Fetches and polls already
+// waiting are not queued twice.
+fn submit_modem_op(op: ModemOp) -> bool {
+    submit_modem_op_for(op, None)
+}
 
-        if n == 0 {
-            continue;
-        }
+// `request_id`: the HTTP request asking for it, carried into the logs and
+// the fetch history
+fn submit_modem_op_for(op: ModemOp, request_id: Option<u32>) -> bool {
+    let caller = modem_queue::Caller { request_id, action: None };
+    submit_modem_op_as(op, caller) != Submitted::Refused
+}
 
-        let request = core::str::from_utf8(&buf[..n]).unwrap_or("");
-        
-        // 解析请求路径
-        let mut cmd_to_send = heapless::String::<64>::new();
-        let mut trigger_http_get = false;
-        let mut immediate_refresh = false;
-        
-        if request.starts_with("GET /at?cmd=") {
-            immediate_refresh = true;
-            if let Some(start) = request.find("cmd=") {
-                let query = &request[start+4..];
-                if let Some(end) = query.find(' ') {
-                    let cmd = &query[..end];
-                    let decoded = decode_url(cmd);
-                    cmd_to_send = decoded;
-                } else if let Some(end) = query.find('\n') {
-                    let cmd = &query[..end];
-                    let decoded = decode_url(cmd);
-                    cmd_to_send = decoded;
-                } else if !query.is_empty() {
-                    let decoded = decode_url(query);
-                    cmd_to_send = decoded;
-                }
-            }
-        } else if request.contains("/http_get") {
-            immediate_refresh = true;
-            trigger_http_get = true;
-        }
-
-        // 获取当前结果
-        let result = AT_RESULT.lock().await;
-        
-        // 构建响应
-        let html = format_response(result.as_str(), immediate_refresh);
-        
-        // 发送响应
-        let _ = socket.write_all(html.as_bytes()).await;
-        let _ = socket.flush().await;
-        
-        // 如果有命令要发送，在响应后发送信号
-        if !cmd_to_send.is_empty() {
-            info!("Sending AT command signal: {}", cmd_to_send);
-            AT_COMMAND_SIGNAL.signal(cmd_to_send);
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Submitted {
+    Queued,
+    // the same fetch or poll was already waiting
+    Joined,
+    // queue full, or shutting down
+    Refused,
+}
+
+fn submit_modem_op_as(op: ModemOp, caller: modem_queue::Caller) -> Submitted {
+    let (name, priority, max_wait) = op.class();
+    // 关机流程开始后不再接新的操作
+    if SHUTTING_DOWN.load(Ordering::Relaxed) && !matches!(op, ModemOp::Shutdown) {
+        return Submitted::Refused;
+    }
+    let queued = MODEM_OPS.lock(|q| {
+        let mut queue = q.borrow_mut();
+        let single = match op {
+            ModemOp::Fetch(_)
+            | ModemOp::Ping
+            | ModemOp::KeepWarm
+            | ModemOp::Webhooks
+            | ModemOp::ReleaseConnection
+            | ModemOp::ClockSync
+            | ModemOp::Probe
+            | ModemOp::Shutdown => true,
+            #[cfg(feature = "proxy")]
+            ModemOp::Forwards => true,
+            #[cfg(feature = "gnss")]
+            ModemOp::Gnss => true,
+            _ => false,
+        };
+        if single && queue.contains(name) {
+            return Submitted::Joined;
         }
-        
-        if trigger_http_get {
-            info!("Triggering HTTP GET request");
-            HTTP_GET_SIGNAL.signal(());
+        match queue.push(op, name, priority, max_wait.as_millis(), Instant::now().as_millis(), caller) {
+            Ok(()) => Submitted::Queued,
+            Err(_) => Submitted::Refused,
         }
+    });
+    match queued {
+        Submitted::Queued => MODEM_OPS_SIGNAL.signal(()),
+        Submitted::Joined => {}
+        Submitted::Refused => warn!("Modem busy: {} not queued", name),
     }
+    queued
 }
 
-fn format_response(result: &str, immediate_refresh: bool) -> heapless::String<4096> {
-    let mut html = heapless::String::new();
-    
-    let _ = html.push_str("HTTP/1.1 200 OK\r\n");
-    let _ = html.push_str("Content-Type: text/html; charset=utf-8\r\n");
-    let _ = html.push_str("Connection: close\r\n\r\n");
-    
-    let _ = html.push_str("<!DOCTYPE html><html><head>");
-    let _ = html.push_str("<title>EC800K HTTP Tester</title>");
-    let _ = html.push_str("<meta name='viewport' content='width=device-width, initial-scale=1'>");
-    
-    if !immediate_refresh {
-        let _ = html.push_str("<meta http-equiv='refresh' content='5'>");
-    }
-    
-    let _ = html.push_str("<style>");
-    let _ = html.push_str("body { font-family: Arial, sans-serif; margin: 20px; background: #f0f2f5; }");
-    let _ = html.push_str(".container { max-width: 1000px; margin: auto; background: white; padding: 25px; border-radius: 10px; box-shadow: 0 2px 15px rgba(0,0,0,0.1); }");
-    let _ = html.push_str("h1 { color: #2c3e50; border-bottom: 3px solid #3498db; padding-bottom: 15px; }");
-    let _ = html.push_str("input[type='text'] { width: 350px; padding: 12px; font-size: 16px; border: 2px solid #ddd; border-radius: 6px; margin-right: 10px; }");
-    let _ = html.push_str("button { padding: 12px 25px; font-size: 16px; border: none; border-radius: 6px; cursor: pointer; font-weight: bold; margin: 5px; }");
-    let _ = html.push_str(".btn-at { background: linear-gradient(135deg, #3498db, #2980b9); color: white; }");
-    let _ = html.push_str(".btn-http { background: linear-gradient(135deg, #2ecc71, #27ae60); color: white; }");
-    let _ = html.push_str("button:hover { transform: translateY(-2px); box-shadow: 0 4px 8px rgba(0,0,0,0.1); }");
-    let _ = html.push_str(".btn-at:hover { background: linear-gradient(135deg, #2980b9, #1c5a7d); }");
-    let _ = html.push_str(".btn-http:hover { background: linear-gradient(135deg, #27ae60, #1e8449); }");
-    let _ = html.push_str("pre { background: #2c3e50; color: #ecf0f1; padding: 20px; border-radius: 8px; overflow: auto; white-space: pre-wrap; font-family: 'Courier New', monospace; font-size: 14px; line-height: 1.4; border-left: 5px solid #3498db; max-height: 600px; }");
-    let _ = html.push_str(".info-box { background: #e8f4fd; border-left: 5px solid #3498db; padding: 15px; margin: 20px 0; border-radius: 5px; }");
-    let _ = html.push_str(".success { color: #2ecc71; font-weight: bold; }");
-    let _ = html.push_str(".error { color: #e74c3c; font-weight: bold; }");
-    let _ = html.push_str(".step { background: #f8f9fa; padding: 10px; border-radius: 5px; margin: 10px 0; font-family: monospace; border-left: 3px solid #3498db; }");
-    let _ = html.push_str(".warning { background: #fff3cd; border: 1px solid #ffeaa7; padding: 10px; border-radius: 5px; margin: 15px 0; }");
-    let _ = html.push_str("</style>");
-    
-    if immediate_refresh {
-        let _ = html.push_str("<script>");
-        let _ = html.push_str("window.onload = function() {");
-        let _ = html.push_str("  setTimeout(function() { location.reload(); }, 1500);");
-        let _ = html.push_str("};");
-        let _ = html.push_str("</script>");
-    }
-    
-    let _ = html.push_str("</head><body>");
-    
-    let _ = html.push_str("<div class='container'>");
-    let _ = html.push_str("<h1>🌐 EC800K HTTP Tester</h1>");
-    
-    let _ = html.push_str("<div class='info-box'>");
-    let _ = html.push_str("<strong>ℹ️ Connection Info:</strong><br>");
-    let _ = html.push_str("WiFi: <strong>");
-    let _ = html.push_str(WIFI_SSID);
-    let _ = html.push_str("</strong> | Password: <strong>");
-    let _ = html.push_str(WIFI_PASSWORD);
-    let _ = html.push_str("</strong> | IP: <strong>192.168.4.1</strong><br>");
-    let _ = html.push_str("UART: Pico GP12(TX) → EC800K RX | Pico GP13(RX) ← EC800K TX | Baudrate: <strong>921600</strong>");
-    let _ = html.push_str("</div>");
-    
-    let _ = html.push_str("<h3>🚀 Quick Actions</h3>");
-    let _ = html.push_str("<div>");
-    let _ = html.push_str("<a href='/http_get'><button class='btn-http'>🌐 Get httpbin.org/get</button></a>");
-    let _ = html.push_str("<a href='/at?cmd=AT'><button class='btn-at'>📡 Test AT</button></a>");
-    let _ = html.push_str("<a href='/at?cmd=AT+CSQ'><button class='btn-at'>📶 Signal (CSQ)</button></a>");
-    let _ = html.push_str("<a href='/at?cmd=AT+CREG%3F'><button class='btn-at'>📡 Network (CREG)</button></a>");
-    let _ = html.push_str("</div>");
-    
-    let _ = html.push_str("<h3>📝 Custom AT Command</h3>");
-    let _ = html.push_str("<form action='/at' method='get'>");
-    let _ = html.push_str("<input type='text' name='cmd' value='AT' placeholder='Enter AT command'>");
-    let _ = html.push_str("<button type='submit' class='btn-at'>📤 Send AT Command</button>");
-    let _ = html.push_str("</form>");
-    
-    let _ = html.push_str("<div class='warning'>");
-    let _ = html.push_str("<strong>⚠️ Note:</strong> HTTP GET process takes about 30-60 seconds. ");
-    let _ = html.push_str("Click the green button above to start.");
-    let _ = html.push_str("</div>");
-    
-    let _ = html.push_str("<h3>🔧 HTTP GET Process (from CircuitPython)</h3>");
-    let _ = html.push_str("<div class='step'>1. AT+CPIN?</div>");
-    let _ = html.push_str("<div class='step'>2. AT+CREG?</div>");
-    let _ = html.push_str("<div class='step'>3. AT+CGATT=1</div>");
-    let _ = html.push_str("<div class='step'>4. AT+QICSGP=1,1,\"CMNET\"</div>");
-    let _ = html.push_str("<div class='step'>5. AT+QIACT=1 (激活PDP)</div>");
-    let _ = html.push_str("<div class='step'>6. AT+QIOPEN=1,0,\"TCP\",\"3.223.36.72\",80,0,0</div>");
-    let _ = html.push_str("<div class='step'>7. AT+QISEND=0</div>");
-    let _ = html.push_str("<div class='step'>8. Send HTTP request (GET /get HTTP/1.1...)</div>");
-    let _ = html.push_str("<div class='step'>9. AT+QIRD=0 读取数据</div>");
-    
-    let _ = html.push_str("<h3>📊 Results:</h3>");
-    let _ = html.push_str("<pre>");
-    let _ = html.push_str(result);
-    let _ = html.push_str("</pre>");
-    
-    if immediate_refresh {
-        let _ = html.push_str("<p class='success'>🔄 Page will refresh in 1.5 seconds to show results...</p>");
-    } else {
-        let _ = html.push_str("<p><em>Page auto-refreshes every 5 seconds</em></p>");
-    }
-    
-    let _ = html.push_str("</div></body></html>");
-    
-    html
+// 动作记录 (/api/actions)
+static ACTIONS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<actions::Log>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(actions::Log::new()));
+
+// An action with what its subsystem needs to carry it out
+enum ActionRequest {
+    Fetch(fetch::Origin),
+    Sms {
+        number: heapless::String<20>,
+        text: heapless::String<96>,
+    },
+    Webhook {
+        event: webhook::Event,
+        text: heapless::String<64>,
+    },
+    Identify,
 }
 
-fn decode_url(input: &str) -> heapless::String<64> {
-    let mut output = heapless::String::new();
-    let mut chars = input.chars();
-    
-    while let Some(c) = chars.next() {
-        if c == '%' {
-            let hex1 = chars.next().unwrap_or('0');
-            let hex2 = chars.next().unwrap_or('0');
-            if let (Some(h1), Some(h2)) = (hex1.to_digit(16), hex2.to_digit(16)) {
-                let byte = ((h1 << 4) | h2) as u8;
-                let _ = output.push(byte as char);
-            }
-        } else if c == '+' {
-            let _ = output.push(' ');
-        } else {
-            let _ = output.push(c);
+// The one way in for actions (see actions): record, check the source may
+// run it, hand it to its subsystem. Err when it was refused or could not
+// be queued, with the reason also written to the record.
+fn dispatch_action(
+    source: actions::Source,
+    request: ActionRequest,
+    request_id: Option<u32>,
+) -> Result<u32, &'static str> {
+    use actions::{Action, Status};
+    let (action, digest) = match &request {
+        ActionRequest::Fetch(origin) => (Action::Fetch, actions::digest(&[origin.as_str().as_bytes()])),
+        ActionRequest::Sms { number, text } => (Action::Sms, actions::digest(&[number.as_bytes(), text.as_bytes()])),
+        ActionRequest::Webhook { event, text } => {
+            (Action::Webhook, actions::digest(&[event.as_str().as_bytes(), text.as_bytes()]))
         }
-    }
-    
-    if !output.ends_with("\r\n") {
-        let _ = output.push_str("\r\n");
-    }
-    
-    output
-}
+        ActionRequest::Identify => (Action::Identify, actions::digest(&[])),
+    };
+    let now = Instant::now().as_millis();
+    let id = ACTIONS.lock(|a| a.borrow_mut().open(source, action, digest, request_id, now));
+    let allowed = CONFIG.lock(|c| match source {
+        actions::Source::Web => c.borrow().actions.web,
+        actions::Source::Input => c.borrow().actions.input,
+    }) & action.bit()
+        != 0;
 
-#[embassy_executor::task]
-async fn uart_task(mut tx: BufferedUartTx, mut rx: BufferedUartRx) {
-    info!("UART task started (921600 baud)");
-    
-    // 初始测试
-    {
-        info!("Sending initial AT command...");
-        let test_cmd = b"AT\r\n";
-        if let Err(e) = tx.write_all(test_cmd).await {
-            error!("Failed to send initial AT command: {:?}", e);
-        } else {
-            info!("Initial AT command sent");
-            tx.flush().await.ok();
-            
-            Timer::after(Duration::from_millis(200)).await;
-            
-            let mut buf = [0u8; 256];
-            let mut response_received = false;
-            
-            for _ in 0..5 {
-                match rx.read(&mut buf).await {
-                    Ok(n) if n > 0 => {
-                        if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-                            info!("Initial response: {}", s);
-                            response_received = true;
-                            
-                            let mut result = AT_RESULT.lock().await;
-                            result.clear();
-                            let _ = result.push_str("✅ EC800K is responding!\n\n");
-                            let _ = result.push_str("Click the green button to fetch httpbin.org/get\n\n");
-                            let _ = result.push_str("Initial response:\n");
-                            let _ = result.push_str(s);
-                            break;
-                        }
-                    }
-                    _ => {}
+    let (status, detail) = if !allowed {
+        warn!("Action #{}: {} is not allowed in actions.{}", id, action.as_str(), source.as_str());
+        (Status::Failed, actions::NOT_ALLOWED)
+    } else {
+        let caller = modem_queue::Caller {
+            request_id,
+            action: Some(id),
+        };
+        let submitted = match request {
+            ActionRequest::Fetch(origin) => Some(submit_modem_op_as(ModemOp::Fetch(origin), caller)),
+            ActionRequest::Sms { number, text } => Some(submit_modem_op_as(ModemOp::Sms { number, text }, caller)),
+            ActionRequest::Webhook { event, text } => {
+                if notify(event, format_args!("{}", text.as_str())) {
+                    None
+                } else {
+                    Some(Submitted::Refused)
                 }
-                Timer::after(Duration::from_millis(100)).await;
             }
-            
-            if !response_received {
-                let mut result = AT_RESULT.lock().await;
-                result.clear();
-                let _ = result.push_str("⚠️ No response from EC800K on startup\n");
-                let _ = result.push_str("Check wiring and power\n");
+            ActionRequest::Identify => {
+                LED.lock(|l| l.borrow_mut().identify(now));
+                LED_CHANGED.signal(());
+                info!("Identify: blinking the LED for {} s", led::IDENTIFY_MS / 1000);
+                None
             }
+        };
+        match (submitted, action) {
+            (Some(Submitted::Queued), _) => (Status::Pending, ""),
+            (Some(Submitted::Joined), _) => (Status::Done, "joined the one already queued"),
+            (Some(Submitted::Refused), Action::Webhook) => (Status::Failed, "webhook off, event off or throttled"),
+            (Some(Submitted::Refused), _) => (Status::Failed, "modem busy"),
+            (None, Action::Webhook) => (Status::Done, "queued for delivery"),
+            (None, _) => (Status::Done, ""),
         }
+    };
+    if status != Status::Pending {
+        ACTIONS.lock(|a| a.borrow_mut().set(id, status, detail));
     }
-    
-    // 主循环
-    loop {
-        // 等待信号
-        use embassy_futures::select::{select, Either};
-        
-        match select(AT_COMMAND_SIGNAL.wait(), HTTP_GET_SIGNAL.wait()).await {
-            Either::First(cmd) => {
-                handle_at_command(&mut tx, &mut rx, cmd.as_str()).await;
-            }
-            Either::Second(_) => {
-                perform_http_get(&mut tx, &mut rx).await;
-            }
-        }
+    bump_state_generation();
+    if status == Status::Failed { Err(detail) } else { Ok(id) }
+}
+
+fn update_action(id: Option<u32>, status: actions::Status, detail: &str) {
+    let Some(id) = id else {
+        return;
+    };
+    if ACTIONS.lock(|a| a.borrow_mut().set(id, status, detail)) {
+        bump_state_generation();
     }
 }
 
-async fn handle_at_command(tx: &mut BufferedUartTx, rx: &mut BufferedUartRx, command: &str) {
-    info!("Processing AT command: {:?}", command);
-    
-    // 更新状态为发送中
-    {
-        let mut result = AT_RESULT.lock().await;
-        result.clear();
-        let _ = result.push_str("🔄 Sending command:\n");
-        let _ = result.push_str(command.trim());
-        let _ = result.push_str("\n\n⏳ Waiting for response...\n");
+// 串口收发日志, /log 和 /log.txt 读取
+static MODEM_LOG: embassy_sync::mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    modem_log::ModemLog<8192>,
+> = embassy_sync::mutex::Mutex::new(modem_log::ModemLog::new());
+
+// 串口任务只往这里推送 (短临界区, 不等待), 由 modem_log_task 写入 MODEM_LOG
+static MODEM_LOG_QUEUE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<modem_log::PendingQueue<2048>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(modem_log::PendingQueue::new()));
+
+static MODEM_LOG_PENDING: embassy_sync::signal::Signal<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    (),
+> = embassy_sync::signal::Signal::new();
+
+// /events 的流: 每条占一个套接字槽位, 至少留一个给页面
+const EVENT_STREAMS: usize = SOCKET_POOL_SLOTS - 1;
+const EVENTS_HEARTBEAT: Duration = Duration::from_secs(15);
+
+// modem_log_task 写完一批后发布下一行的行号, 每条 /events 流一个接收者
+static MODEM_LOG_LINES: embassy_sync::watch::Watch<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    u32,
+    EVENT_STREAMS,
+> = embassy_sync::watch::Watch::new();
+
+// 启动时实际使用的串口参数 (配置里的修改重启后才生效)
+static UART_ACTIVE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<config::UartSettings>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(config::UartSettings::DEFAULT));
+
+// 调试串口 (UART1) 的收发日志, /log/uart1 读取
+static UART1_LOG: embassy_sync::mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    modem_log::ModemLog<4096>,
+> = embassy_sync::mutex::Mutex::new(modem_log::ModemLog::new());
+
+// 只有 uart.debug_writes 开启时启动才有发送端
+static UART1_TX: embassy_sync::mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    Option<BufferedUartTx>,
+> = embassy_sync::mutex::Mutex::new(None);
+
+static UART1_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+// 二进制抓包, 只在 /api/capture/start 之后记录
+static CAPTURE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<capture::Capture<16384>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(capture::Capture::new()));
+
+// 串口字节计数只在 uart_write_all / uart_read 中累加
+static UART_TX_BYTES: AtomicU32 = AtomicU32::new(0);
+static UART_RX_BYTES: AtomicU32 = AtomicU32::new(0);
+// 最近一个采样窗口的吞吐量 (字节/秒)
+static UART_TX_RATE: AtomicU32 = AtomicU32::new(0);
+static UART_RX_RATE: AtomicU32 = AtomicU32::new(0);
+
+static REQUEST_COUNT: AtomicU32 = AtomicU32::new(0);
+// X-Request-Id, also in the log lines and fetch history it leads to
+static NEXT_REQUEST_ID: AtomicU32 = AtomicU32::new(1);
+static THROTTLED_REQUESTS: AtomicU32 = AtomicU32::new(0);
+static THROTTLED_CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+static HEADER_TIMEOUTS: AtomicU32 = AtomicU32::new(0);
+static REQUEST_TIMEOUTS: AtomicU32 = AtomicU32::new(0);
+static OVERSIZED_HEADERS: AtomicU32 = AtomicU32::new(0);
+static OVERSIZED_BODIES: AtomicU32 = AtomicU32::new(0);
+static WRITE_STALLS: AtomicU32 = AtomicU32::new(0);
+static BUSY_RESPONSES: AtomicU32 = AtomicU32::new(0);
+// 页面超出缓冲区, 以 503 代替
+static PAGES_TOO_LARGE: AtomicU32 = AtomicU32::new(0);
+// 因为页面放不下而省略的段落 (page_budget)
+static SECTIONS_OMITTED: AtomicU32 = AtomicU32::new(0);
+static REAPED_CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+// 每个连接的结束方式 (conn_close)
+static CLOSES: conn_close::Counters = conn_close::Counters::new();
+
+static CONFIG: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<config::Config>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(config::Config::DEFAULT));
+
+// 限流表只在临界区内短暂访问, 不涉及任何调制解调器相关的锁
+static RATE_LIMITER: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<rate_limit::RateLimiter<8>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(rate_limit::RateLimiter::new()));
+
+static NETSTAT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<netstat::Registry<16>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(netstat::Registry::new()));
+
+// 模块侧连接 (获取和转发) 的事件和统计, 按连接 ID
+static MODEM_SOCKETS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<modem_sockets::Tracker>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(modem_sockets::Tracker::new()));
+
+fn track_modem_socket(f: impl FnOnce(&mut modem_sockets::Tracker, u64)) {
+    let now = Instant::now().as_millis();
+    MODEM_SOCKETS.lock(|m| f(&mut m.borrow_mut(), now));
+}
+
+// 套接字登记, 离开作用域 (任何退出路径) 时自动注销
+struct SocketRegistration {
+    index: Option<usize>,
+}
+
+impl SocketRegistration {
+    fn new(owner: &'static str, kind: netstat::Kind, local_port: u16, state: netstat::State) -> Self {
+        let entry = netstat::Entry {
+            owner,
+            kind,
+            local_port,
+            remote: None,
+            state,
+            state_since_ms: Instant::now().as_millis(),
+            rx_bytes: 0,
+            tx_bytes: 0,
+        };
+        Self {
+            index: NETSTAT.lock(|n| n.borrow_mut().register(entry)),
+        }
     }
-    
-    // 发送AT命令
-    let cmd_bytes = command.as_bytes();
-    match tx.write_all(cmd_bytes).await {
-        Ok(_) => {
-            info!("AT command sent successfully");
-            tx.flush().await.ok();
-            
-            // 等待响应
-            Timer::after(Duration::from_millis(200)).await;
-            
-            // 读取响应
-            let mut response = heapless::String::<1024>::new();
-            let mut received = false;
-            let mut total_bytes = 0;
-            
-            for attempt in 0..10 {
-                let mut buf = [0u8; 256];
-                match rx.read(&mut buf).await {
-                    Ok(n) if n > 0 => {
-                        received = true;
-                        total_bytes += n;
-                        if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-                            info!("Response chunk {}: {}", attempt + 1, s);
-                            let _ = response.push_str(s);
-                            
-                            if s.contains("OK") || s.contains("ERROR") {
-                                break;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-                
-                Timer::after(Duration::from_millis(50)).await;
-            }
-            
-            // 更新结果
-            {
-                let mut result = AT_RESULT.lock().await;
-                result.clear();
-                
-                if received {
-                    let _ = result.push_str("📤 Command:\n");
-                    let _ = result.push_str(command.trim());
-                    let _ = result.push_str("\n\n📥 Response (");
-                    let mut bytes_str = heapless::String::<10>::new();
-                    let _ = write_u32(&mut bytes_str, total_bytes as u32);
-                    let _ = result.push_str(bytes_str.as_str());
-                    let _ = result.push_str(" bytes):\n");
-                    let _ = result.push_str(&response);
-                    
-                    if response.contains("OK") {
-                        let _ = result.push_str("\n\n✅ Command successful!");
-                    } else if response.contains("ERROR") {
-                        let _ = result.push_str("\n\n❌ Command failed");
-                    } else if response.trim().is_empty() {
-                        let _ = result.push_str("\n\n⚠️ Empty response");
-                    }
-                } else {
-                    let _ = result.push_str("📤 Command:\n");
-                    let _ = result.push_str(command.trim());
-                    let _ = result.push_str("\n\n❌ No response received\n");
-                    let _ = result.push_str("Possible issues:\n");
-                    let _ = result.push_str("1. Check UART wiring (GP12→RX, GP13←TX)\n");
-                    let _ = result.push_str("2. EC800K might be busy or not powered\n");
-                    let _ = result.push_str("3. Try resetting the EC800K module\n");
+
+    fn update(&self, f: impl FnOnce(&mut netstat::Entry)) {
+        if let Some(index) = self.index {
+            NETSTAT.lock(|n| {
+                if let Some(entry) = n.borrow_mut().get_mut(index) {
+                    f(entry);
                 }
-            }
-        }
-        Err(e) => {
-            error!("Failed to send AT command: {:?}", e);
-            let mut result = AT_RESULT.lock().await;
-            result.clear();
-            let _ = result.push_str("❌ Failed to send AT command\n");
-            let _ = result.push_str("Error: ");
-            let _ = result.push_str("UART write error");
+            });
         }
     }
-    
-    info!("AT command processing complete");
-}
 
-// 辅助函数：将u32写入字符串
-fn write_u32(s: &mut heapless::String<10>, n: u32) -> Result<(), ()> {
-    let mut buffer = heapless::Vec::<u8, 10>::new();
-    let mut n = n;
-    
-    if n == 0 {
-        let _ = s.push_str("0");
-        return Ok(());
+    fn set_state(&self, state: netstat::State) {
+        let now = Instant::now().as_millis();
+        self.update(|e| {
+            e.state = state;
+            e.state_since_ms = now;
+        });
     }
-    
-    while n > 0 {
-        let digit = (n % 10) as u8 + b'0';
-        let _ = buffer.push(digit);
-        n /= 10;
-    }
-    
-    for &digit in buffer.iter().rev() {
-        let _ = s.push(digit as char);
+
+    fn connected(&self, socket: &TcpSocket<'_>) {
+        let remote = socket.remote_endpoint().map(|ep| {
+            let embassy_net::IpAddress::Ipv4(addr) = ep.addr;
+            (addr.octets(), ep.port)
+        });
+        self.update(|e| e.remote = remote);
+        self.set_state(netstat::State::Established);
+        note_client_activity();
     }
-    
-    Ok(())
 }
 
-async fn perform_http_get(tx: &mut BufferedUartTx, rx: &mut BufferedUartRx) {
-    info!("Starting HTTP GET process for httpbin.org/get");
-    
-    // 更新状态 - 快速完成
-    {
-        let mut result = AT_RESULT.lock().await;
-        result.clear();
-        let _ = result.push_str("🚀 Starting HTTP GET process...\n");
-        let _ = result.push_str("Using TCP/IP to 3.223.36.72:80\n\n");
-    }
-    
-    // 步骤1-5: 基础检查
-    let basic_steps = [
-        ("AT+CPIN?\r\n", "Checking SIM status", 1),
-        ("AT+CREG?\r\n", "Checking network registration", 2),
-        ("AT+CGATT=1\r\n", "Attaching to network", 3),
-        ("AT+QICSGP=1,1,\"CMNET\"\r\n", "Setting APN", 4),
-        ("AT+QIACT=1\r\n", "Activating PDP context", 5),
-    ];
-    
-    for (cmd, desc, step) in basic_steps.iter() {
-        if !send_at_command_safe(tx, rx, cmd, desc, *step, 9).await {
-            return;
+impl Drop for SocketRegistration {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            NETSTAT.lock(|n| n.borrow_mut().remove(index));
         }
     }
-    
-    // 步骤6: 打开TCP连接
-    {
-        let mut result = AT_RESULT.lock().await;
-        let _ = result.push_str("\nStep 6/9: Opening TCP connection to 3.223.36.72:80...\n");
-    }
-    
-    let open_result = open_tcp_safe(tx, rx, "3.223.36.72", 80).await;
-    if !open_result {
-        return;
-    }
-    
-    // 步骤7: 准备发送
-    {
-        let mut result = AT_RESULT.lock().await;
-        let _ = result.push_str("\nStep 7/9: Preparing to send...\n");
+}
+
+// 连接结束时 (任何退出路径) 归还该 IP 的并发连接名额
+struct ClientSlot {
+    ip: [u8; 4],
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        RATE_LIMITER.lock(|l| l.borrow_mut().release(self.ip));
     }
-    
-    let send_result = prepare_send_safe(tx, rx).await;
-    if !send_result {
-        return;
+}
+
+fn admit_client(socket: &TcpSocket<'_>) -> Result<Option<ClientSlot>, rate_limit::Decision> {
+    let Some(endpoint) = socket.remote_endpoint() else {
+        return Ok(None);
+    };
+    let embassy_net::IpAddress::Ipv4(addr) = endpoint.addr;
+    let ip = addr.octets();
+
+    let limits = CONFIG.lock(|c| c.borrow().rate_limit);
+    let now = Instant::now().as_millis();
+    match RATE_LIMITER.lock(|l| l.borrow_mut().check(ip, now, &limits)) {
+        rate_limit::Decision::Allow => Ok(Some(ClientSlot { ip })),
+        decision => Err(decision),
     }
-    
-    // 步骤8: 发送HTTP请求
-    {
-        let mut result = AT_RESULT.lock().await;
-        let _ = result.push_str("\nStep 8/9: Sending HTTP request...\n");
+}
+
+// 状态代数: 状态页/JSON 内容每次变化时递增, 用作弱 ETag
+static STATE_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+fn bump_state_generation() {
+    STATE_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+// "/" 和 /api/status 的合并渲染 (page_cache)
+static STATUS_PAGE_CACHE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<page_cache::PageCache<8192>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(page_cache::PageCache::new()));
+static STATUS_JSON_CACHE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<page_cache::PageCache<6144>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(page_cache::PageCache::new()));
+
+// AT_RESULT 的写入者都通过这个 guard, 在释放锁之前递增状态代数,
+// 这样持有锁读到的代数总是和内容一致
+struct ResultGuard {
+    inner: Held<
+        embassy_sync::mutex::MutexGuard<
+            'static,
+            embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+            heapless::String<2048>,
+        >,
+    >,
+}
+
+impl core::ops::Deref for ResultGuard {
+    type Target = heapless::String<2048>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl core::ops::DerefMut for ResultGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl Drop for ResultGuard {
+    fn drop(&mut self) {
+        bump_state_generation();
+    }
+}
+
+async fn modem_result() -> ResultGuard {
+    ResultGuard {
+        inner: held(lock_stats::Site::ResultWrite, AT_RESULT.lock().await),
+    }
+}
+
+// 异步锁的持有时间 (lock_stats)
+static LOCK_STATS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<lock_stats::Table>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(lock_stats::Table::new()));
+
+// An async mutex guard that records how long it was held when it drops
+struct Held<G> {
+    guard: G,
+    site: lock_stats::Site,
+    since: Instant,
+}
+
+fn held<G>(site: lock_stats::Site, guard: G) -> Held<G> {
+    Held {
+        guard,
+        site,
+        since: Instant::now(),
+    }
+}
+
+impl<G: core::ops::Deref> core::ops::Deref for Held<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: core::ops::DerefMut> core::ops::DerefMut for Held<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+// 先记时间, 锁在这之后随 guard 字段一起释放
+impl<G> Drop for Held<G> {
+    fn drop(&mut self) {
+        let hold_us = self.since.elapsed().as_micros().min(u32::MAX as u64) as u32;
+        let budget_ms = CONFIG.lock(|c| c.borrow().http.lock_budget_ms);
+        if LOCK_STATS.lock(|s| s.borrow_mut().record(self.site, hold_us, budget_ms.saturating_mul(1000))) {
+            warn!("Lock {} held for {} us (budget {} ms)", self.site.as_str(), hold_us, budget_ms);
+        }
+    }
+}
+
+// 日志下载的压缩器工作内存 (固定大小); 一次只给一个下载用, 见 serve_log_download
+static LOG_DEFLATER: embassy_sync::mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    deflate::Deflater,
+> = embassy_sync::mutex::Mutex::new(deflate::Deflater::new());
+
+// 静态资源 (build.rs 预先生成 gzip 版本)
+static STYLE_CSS: &[u8] = include_bytes!("../static/style.css");
+// 页面模板, 占位符见 push_page_header / format_overview / format_tools
+static HEADER_TEMPLATE: &str = include_str!("../static/header.html");
+static STATUS_TEMPLATE: &str = include_str!("../static/status.html");
+static TOOLS_TEMPLATE: &str = include_str!("../static/tools.html");
+static STYLE_CSS_GZ: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/style.css.gz"));
+static LIVE_JS: &[u8] = include_bytes!("../static/live.js");
+static LIVE_JS_GZ: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/live.js.gz"));
+static LOGVIEW_HTML: &[u8] = include_bytes!("../static/logview.html");
+static LOGVIEW_HTML_GZ: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/logview.html.gz"));
+
+// 网络收发缓冲区: 槽位数和大小只在这里设置
+const SOCKET_POOL_SLOTS: usize = 2;
+static SOCKET_POOL: buffer_pool::BufferPool<SOCKET_POOL_SLOTS, 4096, 4096> = buffer_pool::BufferPool::new();
+
+// 比缓冲区槽位多一个监听循环, 池满时由它回复 503; 每个监听端口一组
+const HTTP_WORKERS: usize = SOCKET_POOL_SLOTS + 1;
+
+// 启动时打开的 HTTP 监听端口 (http.port, http.port2)
+static HTTP_LISTENERS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<[Option<listener::Listener>; listener::MAX_LISTENERS]>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new([None; listener::MAX_LISTENERS]));
+
+// http://192.168.4.1 with the port when it is not 80
+fn http_url(port: u16) -> heapless::String<32> {
+    let mut url = heapless::String::new();
+    let _ = url.push_str("http://192.168.4.1");
+    if port != 80 {
+        let _ = core::write!(url, ":{}", port);
+    }
+    url
+}
+
+// AP 休眠策略 (power.ap_idle_min); AP_AWAKE 告诉 HTTP 监听循环 AP 是否开着
+static AP_SLEEP: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<ap_sleep::ApSleep>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(ap_sleep::ApSleep::new()));
+
+static AP_AWAKE: embassy_sync::watch::Watch<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    bool,
+    { listener::MAX_LISTENERS * HTTP_WORKERS },
+> = embassy_sync::watch::Watch::new_with(true);
+
+// 唤醒按钮按下, 由主循环处理
+static AP_WAKE_BUTTON: embassy_sync::signal::Signal<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, ()> =
+    embassy_sync::signal::Signal::new();
+
+fn note_client_activity() {
+    let now = Instant::now().as_millis();
+    AP_SLEEP.lock(|a| a.borrow_mut().activity(now));
+}
+
+fn ap_sleeping() -> bool {
+    AP_SLEEP.lock(|a| a.borrow().sleeping_since_ms().is_some())
+}
+
+// AP 客户端的 IP 和 MAC (/api/arp), 由 WiFi 驱动外面的 arp::Tap 更新
+static NEIGHBORS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<arp::Neighbors>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(arp::Neighbors::new()));
+
+// 与启动时的静态地址一致
+const AP_ADDRESS: [u8; 4] = [192, 168, 4, 1];
+
+const ARP_HOOKS: arp::Hooks = arp::Hooks {
+    received: arp_frame_received,
+    answer: arp_pinned_answer,
+};
+
+fn arp_frame_received(frame: &[u8]) {
+    let Some((ip, mac)) = arp::sender(frame) else {
+        return;
+    };
+    if ip[..3] != AP_ADDRESS[..3] || ip == AP_ADDRESS {
+        return;
+    }
+    let event = NEIGHBORS.lock(|n| n.borrow_mut().observe(ip, mac, Instant::now().as_millis()));
+    match event {
+        Some(arp::Event::Added(neighbor)) => log_neighbor("added", &neighbor, None),
+        Some(arp::Event::Moved { neighbor, old_mac }) => log_neighbor("moved", &neighbor, Some(old_mac)),
+        None => {}
+    }
+}
+
+fn arp_pinned_answer(frame: &[u8]) -> Option<[u8; arp::ARP_FRAME_LEN]> {
+    let pins = CONFIG.lock(|c| arp::parse_pins(&c.borrow().arp.pins)).unwrap_or_default();
+    arp::pinned_reply(frame, &pins)
+}
+
+// arp.events: 客户端出现, 换 MAC 和被遗忘时记一条调试日志
+fn log_neighbor(what: &str, neighbor: &arp::Neighbor, old_mac: Option<arp::Mac>) {
+    if !CONFIG.lock(|c| c.borrow().arp.events) {
+        return;
+    }
+    let mut text = heapless::String::<64>::new();
+    arp::write_ip(&mut text, neighbor.ip);
+    let _ = text.push_str(" at ");
+    arp::write_mac(&mut text, neighbor.mac);
+    if let Some(old_mac) = old_mac {
+        let _ = text.push_str(", was ");
+        arp::write_mac(&mut text, old_mac);
+    }
+    debug!("ARP: {} {}", what, text.as_str());
+}
+
+fn expire_neighbors() {
+    let now = Instant::now().as_millis();
+    let mut forgotten = heapless::Vec::<arp::Neighbor, { arp::CAPACITY }>::new();
+    NEIGHBORS.lock(|n| {
+        n.borrow_mut().expire(now, |neighbor| {
+            let _ = forgotten.push(*neighbor);
+        })
+    });
+    for neighbor in &forgotten {
+        log_neighbor("forgotten", neighbor, None);
+    }
+}
+
+// 协议栈套接字表的分配和使用 (/net)
+static SOCKET_BUDGET: socket_budget::Budget = socket_budget::Budget::new();
+
+// At boot, before the subsystem's tasks are spawned; false leaves it off
+fn claim_sockets(subsystem: socket_budget::Subsystem, count: usize) -> bool {
+    match SOCKET_BUDGET.claim(subsystem, count) {
+        Ok(()) => true,
+        Err(e) => {
+            error!(
+                "Socket budget: {} needs {} sockets, {} of {} left; not started",
+                subsystem.as_str(),
+                e.wanted,
+                e.free,
+                socket_budget::STACK_SOCKETS
+            );
+            false
+        }
+    }
+}
+
+// Count a failed accept and return how long to wait before the next one
+fn accept_failed(backoff: &mut socket_budget::Backoff) -> Duration {
+    SOCKET_BUDGET.accept_failed();
+    backoff.failed()
+}
+
+#[embassy_executor::task(pool_size = listener::MAX_LISTENERS * HTTP_WORKERS)]
+async fn http_server_task(stack: &'static Stack<'static>, listener: listener::Listener, worker: usize) {
+    debug!("HTTP server worker {} started on port {}", worker, listener.port);
+
+    let mut busy_rx = [0; 256];
+    let mut busy_tx = [0; 256];
+    let mut backoff = socket_budget::Backoff::new();
+    let mut ap_awake = AP_AWAKE.receiver().expect("one AP receiver per HTTP worker");
+
+    loop {
+        use embassy_futures::select::{Either, select};
+
+        // AP 休眠期间不占缓冲区也不监听
+        ap_awake.get_and(|&awake| awake).await;
+        let Some(lease) = SOCKET_POOL.take() else {
+            let busy = reply_busy(*stack, listener.port, &mut busy_rx, &mut busy_tx);
+            match select(busy, ap_awake.get_and(|&awake| !awake)).await {
+                Either::First(true) => backoff.reset(),
+                Either::First(false) => Timer::after(accept_failed(&mut backoff)).await,
+                Either::Second(_) => {}
+            }
+            continue;
+        };
+        let tcp = CONFIG.lock(|c| c.borrow().tcp);
+        let _open = SOCKET_BUDGET.open(socket_budget::Subsystem::Http);
+        let mut socket = TcpSocket::new(*stack, &mut lease.rx[..], &mut lease.tx[..]);
+        socket.set_timeout(Some(Duration::from_millis(tcp.timeout_ms as u64)));
+        let registration =
+            SocketRegistration::new(listener.name, netstat::Kind::Tcp, listener.port, netstat::State::Listen);
+
+        let accept = socket.accept(listener.port);
+        match select(accept, ap_awake.get_and(|&awake| !awake)).await {
+            Either::First(Err(e)) => {
+                let delay = accept_failed(&mut backoff);
+                warn!("Accept error: {:?}, retrying in {} ms", e, delay.as_millis());
+                Timer::after(delay).await;
+                continue;
+            }
+            Either::First(Ok(())) => {}
+            // AP 关了: 放下套接字和缓冲区, 等它回来
+            Either::Second(_) => {
+                debug!("HTTP worker {} on port {} suspended", worker, listener.port);
+                continue;
+            }
+        }
+        backoff.reset();
+        let accepted = Instant::now();
+        socket.set_keep_alive(Some(Duration::from_millis(tcp.keepalive_ms as u64)));
+        registration.connected(&socket);
+
+        // 按 IP 限流: 超过并发上限直接复位, 超过请求速率回复 429
+        let _slot = match admit_client(&socket) {
+            Ok(slot) => slot,
+            Err(rate_limit::Decision::RateLimited { retry_after_secs }) => {
+                THROTTLED_REQUESTS.fetch_add(1, Ordering::Relaxed);
+                let mut response = heapless::String::<160>::new();
+                let _ = response.push_str("HTTP/1.1 429 Too Many Requests\r\n");
+                let _ = core::write!(response, "Retry-After: {}\r\n", retry_after_secs);
+                let _ = response.push_str("Content-Type: text/plain\r\nContent-Length: 18\r\n");
+                let _ = response.push_str("Connection: close\r\n\r\nToo many requests\n");
+                let _ = socket.write_all(response.as_bytes()).await;
+                let close = close_gracefully(&mut socket, tcp.idle_close_ms).await;
+                record_close(close, None);
+                let _ = socket.flush().await;
+                continue;
+            }
+            Err(_) => {
+                THROTTLED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+                socket.abort();
+                record_close(conn_close::Close::Throttled, None);
+                let _ = socket.flush().await;
+                continue;
+            }
+        };
+
+        let deadlines = CONFIG.lock(|c| c.borrow().deadlines);
+        let mut conn = http::ProgressWriter::new(&mut socket, deadlines.write_progress_ms);
+        handle_client(&mut conn, accepted, deadlines, &registration, listener.serves).await;
+        let written = conn.written();
+        let request_id = conn.request_id();
+        let aborted = conn.aborted();
+        let answered_as = conn.answered_as();
+        let stalled = conn.stalled();
+        registration.update(|e| e.tx_bytes = written);
+        registration.set_state(netstat::State::Closing);
+
+        let close = match aborted {
+            // 处理过程中已经复位
+            Some(reason) => reason,
+            // 响应写入停滞: 直接复位, 不等待 FIN 握手
+            None if stalled => {
+                WRITE_STALLS.fetch_add(1, Ordering::Relaxed);
+                socket.abort();
+                conn_close::Close::WriteStall
+            }
+            None => match close_gracefully(&mut socket, tcp.idle_close_ms).await {
+                conn_close::Close::Graceful => answered_as.unwrap_or(conn_close::Close::Graceful),
+                close => close,
+            },
+        };
+        record_close(close, request_id);
+        let _ = socket.flush().await;
+    }
+}
+
+// 发送 FIN 并等待对方关闭; 空闲窗口内没有关闭的半开连接直接回收
+async fn close_gracefully(socket: &mut TcpSocket<'_>, idle_close_ms: u32) -> conn_close::Close {
+    let close = conn_close::close_gracefully(socket, idle_close_ms).await;
+    if close == conn_close::Close::Reaped {
+        REAPED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+    }
+    close
+}
+
+impl conn_close::Socket for TcpSocket<'_> {
+    fn close(&mut self) {
+        TcpSocket::close(self)
+    }
+
+    fn abort(&mut self) {
+        TcpSocket::abort(self)
+    }
+}
+
+fn record_close(close: conn_close::Close, request_id: Option<u32>) {
+    CLOSES.record(close);
+    match request_id {
+        Some(id) => debug!("Request {} closed: {}", id, close.as_str()),
+        None => debug!("Connection closed: {}", close.as_str()),
+    }
+}
+
+// 测试服务各用一对小缓冲区, 不占 HTTP 的槽位
+static SERVICE_POOL: buffer_pool::BufferPool<2, 1024, 1024> = buffer_pool::BufferPool::new();
+static SERVICE_COUNTERS: test_services::Counters = test_services::Counters::new();
+
+#[embassy_executor::task(pool_size = 2)]
+async fn test_service_task(stack: &'static Stack<'static>, service: test_services::Service) {
+    let Some(lease) = SERVICE_POOL.take() else {
+        warn!("No buffers for the {} service", service.as_str());
+        return;
+    };
+    let enabled = || CONFIG.lock(|c| c.borrow().services.enabled);
+    let mut backoff = socket_budget::Backoff::new();
+
+    loop {
+        // 关闭时不监听, 连接会被协议栈直接拒绝
+        if !enabled() {
+            Timer::after(Duration::from_secs(5)).await;
+            continue;
+        }
+        let _open = SOCKET_BUDGET.open(socket_budget::Subsystem::TestServices);
+        let mut socket = TcpSocket::new(*stack, &mut lease.rx[..], &mut lease.tx[..]);
+        let registration = SocketRegistration::new(service.as_str(), netstat::Kind::Tcp, service.port(), netstat::State::Listen);
+
+        if let Err(e) = socket.accept(service.port()).await {
+            let delay = accept_failed(&mut backoff);
+            warn!("{} accept error: {:?}, retrying in {} ms", service.as_str(), e, delay.as_millis());
+            Timer::after(delay).await;
+            continue;
+        }
+        backoff.reset();
+        // 监听期间被关掉
+        if !enabled() {
+            socket.abort();
+            let _ = socket.flush().await;
+            continue;
+        }
+        registration.connected(&socket);
+        SERVICE_COUNTERS.connected(service);
+
+        serve_test_connection(&mut socket, service, &registration).await;
+        registration.set_state(netstat::State::Closing);
+        socket.close();
+        let idle = Duration::from_millis(CONFIG.lock(|c| c.borrow().tcp.idle_close_ms) as u64);
+        if with_timeout(idle, conn_close::wait_peer_close(&mut socket)).await.is_err() {
+            socket.abort();
+        }
+        let _ = socket.flush().await;
+    }
+}
+
+// Until the peer closes, goes quiet for IDLE_MS, or echo reaches its limit
+async fn serve_test_connection(socket: &mut TcpSocket<'_>, service: test_services::Service, registration: &SocketRegistration) {
+    let idle = Duration::from_millis(test_services::IDLE_MS);
+    let mut buf = [0u8; 256];
+    let mut rx_bytes = 0u32;
+    let mut tx_bytes = 0u32;
+
+    loop {
+        let n = match with_timeout(idle, socket.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => n,
+            _ => break,
+        };
+        rx_bytes += n as u32;
+        SERVICE_COUNTERS.received(service, n);
+
+        if service == test_services::Service::Echo {
+            let n = n.min(test_services::ECHO_LIMIT.saturating_sub(tx_bytes) as usize);
+            if n == 0 || !matches!(with_timeout(idle, socket.write_all(&buf[..n])).await, Ok(Ok(()))) {
+                break;
+            }
+            tx_bytes += n as u32;
+            SERVICE_COUNTERS.sent(service, n);
+        }
+        registration.update(|e| {
+            e.rx_bytes = rx_bytes;
+            e.tx_bytes = tx_bytes;
+        });
+    }
+}
+
+// 端口转发: AP 侧任务和串口任务共享的状态, 以及两个方向的数据管道
+#[cfg(feature = "proxy")]
+static FORWARD_LINKS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<[forward::Link; forward::MAX_FORWARDS]>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new([forward::Link::new(); forward::MAX_FORWARDS]));
+
+#[cfg(feature = "proxy")]
+type ForwardPipe = embassy_sync::pipe::Pipe<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, 1024>;
+// 客户端 → 远端
+#[cfg(feature = "proxy")]
+static FORWARD_UP: [ForwardPipe; forward::MAX_FORWARDS] = [const { ForwardPipe::new() }; forward::MAX_FORWARDS];
+// 远端 → 客户端
+#[cfg(feature = "proxy")]
+static FORWARD_DOWN: [ForwardPipe; forward::MAX_FORWARDS] = [const { ForwardPipe::new() }; forward::MAX_FORWARDS];
+
+// 模块报告过有数据 / 对方已关闭的连接, 每个连接号一位; 没有数据的连接不发 AT+QIRD.
+// 通知可能夹在任何指令的回复里, 由 LineReader 记下
+#[cfg(feature = "proxy")]
+static FORWARD_READABLE: AtomicU32 = AtomicU32::new(0);
+#[cfg(feature = "proxy")]
+static FORWARD_PEER_CLOSED: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(feature = "proxy")]
+fn note_forward_notice(line: &str) {
+    let modem = current_modem();
+    if let Some((id, _)) = modem.parse_recv_notice(line)
+        && id < 32
+    {
+        FORWARD_READABLE.fetch_or(1 << id, Ordering::Relaxed);
+    }
+    for index in 0..forward::MAX_FORWARDS {
+        let id = forward::connect_id(index);
+        if modem.parse_closed(line, id) {
+            // 关闭前到的数据还要读完
+            FORWARD_PEER_CLOSED.fetch_or(1 << id, Ordering::Relaxed);
+            FORWARD_READABLE.fetch_or(1 << id, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "proxy")]
+static FORWARD_POOL: buffer_pool::BufferPool<{ forward::MAX_FORWARDS }, 1024, 1024> = buffer_pool::BufferPool::new();
+
+// 转发负载的令牌桶, 按 shaper::Direction 索引; AT 指令不经过这里
+#[cfg(feature = "proxy")]
+static SHAPER: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<[shaper::Bucket; 2]>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new([shaper::Bucket::new(); 2]));
+
+// Bytes per second each way: the configured rate, or a share of the UART
+#[cfg(feature = "proxy")]
+fn shaper_rate() -> u32 {
+    match CONFIG.lock(|c| c.borrow().shaper.rate) {
+        0 => {
+            let uart = UART_ACTIVE.lock(|a| a.get());
+            shaper::auto_rate(UART_BAUDRATE, uart.data_bits, uart.parity != config::Parity::None, uart.stop_bits)
+        }
+        rate => rate,
+    }
+}
+
+#[cfg(feature = "proxy")]
+fn shaper_allowance(direction: shaper::Direction, want: usize) -> usize {
+    let rate = shaper_rate();
+    let now = Instant::now().as_millis();
+    SHAPER.lock(|s| s.borrow_mut()[direction as usize].allowance(rate, want, now))
+}
+
+#[cfg(feature = "proxy")]
+fn shaper_spend(direction: shaper::Direction, bytes: usize) {
+    SHAPER.lock(|s| s.borrow_mut()[direction as usize].spend(bytes));
+}
+
+#[cfg(feature = "proxy")]
+fn shaper_utilisation(direction: shaper::Direction) -> u32 {
+    let rate = shaper_rate();
+    let now = Instant::now().as_millis();
+    SHAPER.lock(|s| s.borrow_mut()[direction as usize].utilisation(rate, now))
+}
+
+// 有连接时串口任务多久查一次远端数据
+#[cfg(feature = "proxy")]
+const FORWARD_POLL: Duration = Duration::from_millis(300);
+// 空闲的监听多久重新读一次规则 (端口可能改了)
+#[cfg(feature = "proxy")]
+const FORWARD_RULE_CHECK: Duration = Duration::from_secs(5);
+
+#[cfg(feature = "proxy")]
+fn update_forward(index: usize, f: impl FnOnce(&mut forward::Link)) {
+    FORWARD_LINKS.lock(|l| f(&mut l.borrow_mut()[index]));
+}
+
+#[cfg(feature = "proxy")]
+fn forward_state(index: usize) -> forward::State {
+    FORWARD_LINKS.lock(|l| l.borrow()[index].state)
+}
+
+#[cfg(feature = "proxy")]
+#[embassy_executor::task(pool_size = forward::MAX_FORWARDS)]
+async fn forward_task(stack: &'static Stack<'static>, index: usize) {
+    let name = forward::NAMES[index];
+    let Some(lease) = FORWARD_POOL.take() else {
+        warn!("No buffers for {}", name);
+        return;
+    };
+    let mut refuse_rx = [0u8; 64];
+    let mut refuse_tx = [0u8; 64];
+    let mut backoff = socket_budget::Backoff::new();
+
+    loop {
+        let rule = CONFIG.lock(|c| c.borrow().forwards[index].clone());
+        if !rule.active() {
+            update_forward(index, |l| l.set_state(forward::State::Off, Instant::now().as_millis()));
+            Timer::after(FORWARD_RULE_CHECK).await;
+            continue;
+        }
+        update_forward(index, |l| l.set_state(forward::State::Listening, Instant::now().as_millis()));
+        let _open = SOCKET_BUDGET.open(socket_budget::Subsystem::Forwards);
+        let mut socket = TcpSocket::new(*stack, &mut lease.rx[..], &mut lease.tx[..]);
+        let registration = SocketRegistration::new(name, netstat::Kind::Tcp, rule.listen_port, netstat::State::Listen);
+
+        match with_timeout(FORWARD_RULE_CHECK, socket.accept(rule.listen_port)).await {
+            Ok(Ok(())) => backoff.reset(),
+            Ok(Err(e)) => {
+                let delay = accept_failed(&mut backoff);
+                warn!("{} accept error: {:?}, retrying in {} ms", name, e, delay.as_millis());
+                Timer::after(delay).await;
+                continue;
+            }
+            Err(_) => continue,
+        }
+        // 监听期间规则被改掉了
+        if !CONFIG.lock(|c| c.borrow().forwards[index].same_as(&rule)) {
+            socket.abort();
+            let _ = socket.flush().await;
+            continue;
+        }
+        registration.connected(&socket);
+        info!("{}: client connected, opening {}:{}", name, rule.host.as_str(), rule.port);
+
+        FORWARD_UP[index].clear();
+        FORWARD_DOWN[index].clear();
+        update_forward(index, |l| {
+            l.connections += 1;
+            l.last_error = None;
+            l.remote_closed = false;
+            l.set_state(forward::State::Connecting, Instant::now().as_millis());
+        });
+        submit_modem_op(ModemOp::Forwards);
+
+        let refuse = refuse_forward_clients(*stack, index, rule.listen_port, &mut refuse_rx, &mut refuse_tx);
+        embassy_futures::select::select(pump_forward(&mut socket, index, &registration), refuse).await;
+        registration.set_state(netstat::State::Closing);
+
+        // 客户端先走了: 让串口任务关掉模块那头, 关完再接下一个客户端
+        let closing = FORWARD_LINKS.lock(|l| {
+            let link = &mut l.borrow_mut()[index];
+            let busy = link.state.busy();
+            if busy {
+                link.set_state(forward::State::Closing, Instant::now().as_millis());
+            }
+            busy
+        });
+        if closing {
+            submit_modem_op(ModemOp::Forwards);
+            let closed = async {
+                while forward_state(index).busy() {
+                    Timer::after(Duration::from_millis(100)).await;
+                }
+            };
+            if with_timeout(Duration::from_secs(30), closed).await.is_err() {
+                update_forward(index, |l| l.fail("modem did not close the connection", Instant::now().as_millis()));
+            }
+        }
+
+        socket.close();
+        let idle = Duration::from_millis(CONFIG.lock(|c| c.borrow().tcp.idle_close_ms) as u64);
+        if with_timeout(idle, conn_close::wait_peer_close(&mut socket)).await.is_err() {
+            socket.abort();
+        }
+        let _ = socket.flush().await;
+        info!("{}: connection ended", name);
+    }
+}
+
+// 在客户端和两个管道之间搬数据, 直到客户端关闭, 或者模块那头结束且数据都交给了客户端
+#[cfg(feature = "proxy")]
+async fn pump_forward(socket: &mut TcpSocket<'_>, index: usize, registration: &SocketRegistration) {
+    let (mut reader, mut writer) = socket.split();
+    let uplink = async {
+        let mut buf = [0u8; 256];
+        while let Ok(n) = reader.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+            FORWARD_UP[index].write_all(&buf[..n]).await;
+            registration.update(|e| e.rx_bytes += n as u32);
+            submit_modem_op(ModemOp::Forwards);
+        }
+    };
+    let downlink = async {
+        let mut buf = [0u8; 256];
+        while forward_state(index).busy() || !FORWARD_DOWN[index].is_empty() {
+            let Ok(n) = with_timeout(Duration::from_millis(100), FORWARD_DOWN[index].read(&mut buf)).await else {
+                continue;
+            };
+            if writer.write_all(&buf[..n]).await.is_err() {
+                return;
+            }
+            registration.update(|e| e.tx_bytes += n as u32);
+        }
+        let _ = writer.flush().await;
+    };
+    embassy_futures::select::select(uplink, downlink).await;
+}
+
+// 转发忙时再来的客户端: 接受后立即复位并记一笔
+#[cfg(feature = "proxy")]
+async fn refuse_forward_clients(stack: Stack<'static>, index: usize, port: u16, rx: &mut [u8], tx: &mut [u8]) {
+    let mut backoff = socket_budget::Backoff::new();
+    loop {
+        let _open = SOCKET_BUDGET.open(socket_budget::Subsystem::Forwards);
+        let mut socket = TcpSocket::new(stack, &mut *rx, &mut *tx);
+        if socket.accept(port).await.is_err() {
+            Timer::after(accept_failed(&mut backoff)).await;
+            continue;
+        }
+        backoff.reset();
+        warn!("{}: refused a second client, the forward is busy", forward::NAMES[index]);
+        update_forward(index, |l| l.refused += 1);
+        socket.abort();
+        let _ = socket.flush().await;
+    }
+}
+
+// 缓冲区池已满: 用小缓冲区接受连接并回复 503; accept 失败时返回 false
+async fn reply_busy(stack: Stack<'static>, port: u16, rx: &mut [u8], tx: &mut [u8]) -> bool {
+    let _open = SOCKET_BUDGET.open(socket_budget::Subsystem::Http);
+    let mut socket = TcpSocket::new(stack, rx, tx);
+    socket.set_timeout(Some(Duration::from_secs(2)));
+    let registration = SocketRegistration::new("http-busy", netstat::Kind::Tcp, port, netstat::State::Listen);
+
+    if socket.accept(port).await.is_err() {
+        return false;
+    }
+    registration.connected(&socket);
+
+    BUSY_RESPONSES.fetch_add(1, Ordering::Relaxed);
+    let response: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+        Retry-After: 1\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Length: 5\r\n\
+        Connection: close\r\n\r\n\
+        Busy\n";
+    let _ = socket.write_all(response).await;
+    let _ = socket.flush().await;
+    true
+}
+
+// 比请求缓冲区大的 POST 正文 (配置导入, 宏表单) 收集到这里, 一次一个
+static POST_BODY: embassy_sync::mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    [u8; limits::POST_BODY_MAX],
+> = embassy_sync::mutex::Mutex::new([0; limits::POST_BODY_MAX]);
+
+// 响应写入经过停滞检测
+type Conn<'a, 'b> = http::ProgressWriter<'a, TcpSocket<'b>>;
+
+// 单个连接: 读请求, 分发, 写响应
+async fn handle_client(
+    socket: &mut Conn<'_, '_>,
+    accepted: Instant,
+    deadlines: http::Deadlines,
+    registration: &SocketRegistration,
+    serves: listener::Serves,
+) {
+    let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    socket.set_request_id(request_id);
+    // 读取请求 (请求头和整个请求各有时限, 大小见 limits), 表单正文也要放得下
+    let mut buf = [0; limits::REQUEST_BUFFER];
+    let n = match http::read_request(socket.get_mut(), &mut buf, accepted, &deadlines).await {
+        Ok(n) => n,
+        Err(http::ReadError::HeadersTooLarge) => {
+            OVERSIZED_HEADERS.fetch_add(1, Ordering::Relaxed);
+            let response =
+                format_short("431 Request Header Fields Too Large", "text/plain", "Request headers too large\n");
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        Err(http::ReadError::BodyTooLarge) => {
+            OVERSIZED_BODIES.fetch_add(1, Ordering::Relaxed);
+            let response = format_short("413 Payload Too Large", "text/plain", "Request body too large\n");
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        // 对方只关了发送方向时还能收到
+        Err(http::ReadError::BodyIncomplete) => {
+            let _ = socket.write_all(http::BODY_INCOMPLETE).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        // 请求头没有在时限内到齐 (例如分成几段还没发完): 回复 408, 正常关闭
+        Err(http::ReadError::HeaderTimeout) => {
+            HEADER_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+            socket.set_answered_as(conn_close::Close::HeaderTimeout);
+            let _ = socket.write_all(http::HEADER_TIMEOUT).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        Err(http::ReadError::RequestTimeout) => {
+            REQUEST_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+            abort_conn(socket, conn_close::Close::RequestTimeout);
+            return;
+        }
+        Err(_) => return,
+    };
+
+    registration.update(|e| e.rx_bytes = n as u32);
+
+    // 只做重定向的监听端口: 什么请求都指向提供页面的端口
+    if let listener::Serves::RedirectTo(port) = serves {
+        let mut location = http_url(port);
+        let _ = location.push('/');
+        let mut response = heapless::String::<160>::new();
+        let _ = response.push_str("HTTP/1.1 302 Found\r\n");
+        let _ = core::write!(response, "Location: {}\r\n", location);
+        let _ = response.push_str("Content-Length: 0\r\nConnection: close\r\n\r\n");
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+
+    REQUEST_COUNT.fetch_add(1, Ordering::Relaxed);
+    STATS.add(stats::Stat::HttpRequests, 1);
+
+    let parsed = http::parse_head(&buf[..n]);
+    let method = parsed.as_ref().map_or("GET", |r| r.method);
+    let path = match parsed.as_ref().map_or("/", |r| r.path) {
+        // 旧书签
+        "/status" => "/",
+        path => path,
+    };
+    debug!("HTTP #{} {} {}", request_id, method, path);
+
+    // HEAD 与 GET 走同一路径, 只是不写正文
+    let head = method == "HEAD";
+    if head {
+        socket.suppress_body();
+    }
+    // HTTP/1.0 不认识 chunked, 长度未知的正文靠关闭连接结束
+    if parsed.as_ref().is_some_and(|r| !r.accepts_chunked()) {
+        socket.refuse_chunked();
+    }
+    let response = if !http::KNOWN_METHODS.contains(&method) {
+        Some(format_short("501 Not Implemented", "text/plain", "Method not implemented\n"))
+    } else if !routes::allows(path, method) {
+        Some(format_method_not_allowed(routes::allowed_methods(path)))
+    } else {
+        None
+    };
+    if let Some(response) = response {
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+    let method = if head { "GET" } else { method };
+    let accept = parsed.as_ref().and_then(|r| r.header("Accept"));
+    let gzip = http::accepts_encoding(parsed.as_ref().and_then(|r| r.header("Accept-Encoding")), "gzip");
+    let range = http::parse_range(parsed.as_ref().and_then(|r| r.header("Range")));
+    let query = parsed.as_ref().map_or("", |r| r.query);
+    let body = parsed.as_ref().map_or(&[][..], |r| r.body);
+    let content_length = parsed
+        .as_ref()
+        .and_then(|r| r.header("Content-Length"))
+        .and_then(|v| v.parse::<usize>().ok());
+    // 正文按原始字节交给处理函数, 不受上面 UTF-8 截断的影响; 超出请求缓冲区的部分再从 socket 读
+    let received = http::find_header_end(&buf[..n]).map_or(&[][..], |start| &buf[start..n]);
+    let body_deadline = accepted + Duration::from_millis(deadlines.request_ms as u64);
+    let mut body_reader = content_length.map(|len| http::BodyReader::new(received, len, body_deadline));
+
+    // 恢复模式: 主页换成恢复页面
+    if recovery_mode() {
+        let response = match (path, method) {
+            ("/" | "/recovery", "GET") => {
+                serve_recovery_page(socket).await;
+                return;
+            }
+            ("/api/recovery/factory-reset", "POST") => {
+                factory_reset();
+                Some(format_see_other("/"))
+            }
+            ("/api/recovery/reboot", "POST") => Some(format_short("202 Accepted", "text/plain", "Rebooting\n")),
+            _ => None,
+        };
+        if let Some(response) = response {
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            if path == "/api/recovery/reboot" {
+                reboot().await;
+            }
+            return;
+        }
+    }
+
+    // 静态资源和日志直接写入 socket
+    match path {
+        "/style.css" => {
+            serve_static(socket, "text/css", STYLE_CSS, STYLE_CSS_GZ, gzip, range).await;
+            return;
+        }
+        "/live.js" => {
+            serve_static(socket, "text/javascript", LIVE_JS, LIVE_JS_GZ, gzip, range).await;
+            return;
+        }
+        "/log" => {
+            let plain = http::negotiate(accept, &["text/html", "text/plain"]) == "text/plain";
+            serve_log_view(socket, plain).await;
+            return;
+        }
+        "/log.txt" => {
+            serve_log_download(socket, gzip, range).await;
+            return;
+        }
+        "/api/log" => {
+            let since = http::form_value(query, "since").and_then(|v| v.parse().ok());
+            let records = http::negotiate(accept, &["text/plain", "application/json"]) == "application/json";
+            match (http::form_value(query, "log") == Some("uart1"), records) {
+                (true, false) => serve_log_since(socket, &UART1_LOG, lock_stats::Site::Uart1Log, since, false).await,
+                (true, true) => serve_log_records(socket, &UART1_LOG, lock_stats::Site::Uart1Log, since).await,
+                // 没有 since 是人在 curl, 不是 live.js 在追日志: 末尾附上计数
+                (false, false) => {
+                    let site = lock_stats::Site::ModemLogRead;
+                    serve_log_since(socket, &MODEM_LOG, site, since, since.is_none()).await
+                }
+                (false, true) => serve_log_records(socket, &MODEM_LOG, lock_stats::Site::ModemLogRead, since).await,
+            }
+            return;
+        }
+        "/events" => {
+            let last_event_id = parsed.as_ref().and_then(|r| r.header("Last-Event-ID"));
+            let last_event_id = last_event_id.and_then(|v| v.trim().parse().ok());
+            serve_events(socket, last_event_id, head).await;
+            return;
+        }
+        "/logview" => {
+            serve_static(socket, "text/html; charset=utf-8", LOGVIEW_HTML, LOGVIEW_HTML_GZ, gzip, range).await;
+            return;
+        }
+        "/log/previous.txt" => {
+            serve_previous_log(socket).await;
+            return;
+        }
+        "/log/uart1" => {
+            let plain = http::negotiate(accept, &["text/html", "text/plain"]) == "text/plain";
+            serve_uart1_log(socket, plain).await;
+            return;
+        }
+        "/net" => {
+            let page = format_net_html();
+            let _ = socket.write_all(page.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/fetch/history" => {
+            let request_id = http::form_value(query, "request").and_then(|v| v.parse().ok());
+            let body = format_fetch_history_json(request_id);
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/stats" => {
+            let page = format_stats_html();
+            let _ = socket.write_all(page.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/stats" => {
+            let body = format_stats_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/actions" => {
+            let body = format_actions_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/boot" => {
+            let body = format_boot_report_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/lockstats" => {
+            let body = format_lock_stats_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/debug/buffers" => {
+            let page = format_buffers_html().await;
+            let _ = socket.write_all(page.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/buffers" => {
+            let body = format_buffers_json().await;
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/relay" => {
+            serve_relay(socket, query, request_id).await;
+            return;
+        }
+        "/api/buffers/reset" if method == "POST" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = match http::form_value(query, "name").and_then(rings::Buffer::parse) {
+                Some(buffer) => {
+                    reset_ring(buffer).await;
+                    if html {
+                        format_see_other("/debug/buffers")
+                    } else {
+                        format_short("200 OK", "application/json", "{\"reset\":true}")
+                    }
+                }
+                None => format_short("404 Not Found", "text/plain", "no such buffer\n"),
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/arp" => {
+            let body = format_arp_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/net" => {
+            let body = format_net_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        #[cfg(feature = "proxy")]
+        "/proxy.pac" => {
+            let script = format_pac();
+            let _ = socket.write_all(script.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/healthz" => {
+            let response = format_healthz();
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/metrics" => {
+            let metrics = format_metrics();
+            let _ = socket.write_all(metrics.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/macros" if method == "POST" => {
+            match body_reader.as_mut() {
+                Some(reader) => serve_macro_post(socket, reader).await,
+                None => serve_macro_save(socket, body).await,
+            }
+            finish_body(socket, body_reader.as_mut()).await;
+            return;
+        }
+        "/macros" => {
+            serve_macros_page(socket, "200 OK", None).await;
+            return;
+        }
+        "/api/loglevel" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = if method == "POST" { set_log_level(body, html) } else { format_log_level_json() };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/dnscache" => {
+            let body = format_dns_cache_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/dnscache/flush" => {
+            let flushed = DNS_CACHE.lock(|c| c.borrow_mut().flush());
+            info!("DNS cache flushed ({} entries)", flushed);
+            let mut body = heapless::String::<32>::new();
+            let _ = core::write!(body, "{{\"flushed\":{}}}", flushed);
+            let response = format_short("200 OK", "application/json", &body);
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/version" => {
+            let body = format_version_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/spec" => {
+            write_page(socket, path, routes::format_spec()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/macros" => {
+            let body = format_macros_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/capture.bin" => {
+            serve_capture(socket).await;
+            return;
+        }
+        "/api/response" => {
+            serve_last_response(socket).await;
+            return;
+        }
+        "/api/response/meta" => {
+            let body = format_response_meta_json();
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/transcripts" => {
+            serve_transcripts(socket, None).await;
+            return;
+        }
+        _ if path.starts_with("/api/transcripts/") => {
+            let number = path["/api/transcripts/".len()..].trim_end_matches(".json").parse().ok();
+            serve_transcripts(socket, Some(number)).await;
+            return;
+        }
+        _ if path.starts_with("/api/response/") => {
+            serve_archived_response(socket, &path["/api/response/".len()..]).await;
+            return;
+        }
+        "/api/capture" | "/api/capture/start" | "/api/capture/stop" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = match path {
+                "/api/capture/start" => control_capture(true, html),
+                "/api/capture/stop" => control_capture(false, html),
+                _ => format_capture_json(),
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/config" => {
+            let mut errors = FieldErrors::new();
+            let post = method == "POST";
+            let _ = if post && apply_config_form(body, &mut errors) {
+                socket.write_all(format_see_other("/config").as_bytes()).await
+            } else {
+                socket.write_all(format_config_html(post.then_some(&errors)).as_bytes()).await
+            };
+            let _ = socket.flush().await;
+            return;
+        }
+        #[cfg(feature = "proxy")]
+        "/config/forwards" => {
+            let mut errors = FieldErrors::new();
+            let post = method == "POST";
+            let _ = if post && apply_config_form(body, &mut errors) {
+                socket.write_all(format_see_other("/config/forwards").as_bytes()).await
+            } else {
+                socket.write_all(format_forwards_html(post.then_some(&errors)).as_bytes()).await
+            };
+            let _ = socket.flush().await;
+            return;
+        }
+        "/config/triggers" => {
+            let mut errors = FieldErrors::new();
+            let post = method == "POST";
+            let _ = if post && apply_config_form(body, &mut errors) {
+                socket.write_all(format_see_other("/config/triggers").as_bytes()).await
+            } else {
+                socket.write_all(format_triggers_html(post.then_some(&errors)).as_bytes()).await
+            };
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/config/export" => {
+            serve_config_export(socket, http::form_value(query, "redact") == Some("1")).await;
+            return;
+        }
+        "/api/config/import" if method == "POST" => {
+            serve_config_import(socket, body_reader.as_mut()).await;
+            finish_body(socket, body_reader.as_mut()).await;
+            return;
+        }
+        "/api/reboot" if method == "POST" => {
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\n\r\n";
+            let _ = socket.write_all(head.as_bytes()).await;
+            let _ = socket.write_all(b"Shutting the modem down\n").await;
+            let _ = socket.flush().await;
+            quiesce_modem(Some(&mut *socket)).await;
+            let _ = socket.write_all(b"Rebooting\n").await;
+            let _ = socket.flush().await;
+            reboot().await;
+        }
+        "/api/config/factory-reset" if method == "POST" => {
+            factory_reset();
+            let response = format_short("202 Accepted", "text/plain", "Factory reset, rebooting\n");
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            reboot().await;
+        }
+        "/api/sim/pin" if method == "POST" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = queue_sim_unlock(body, html, request_id);
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/sim/forget" if method == "POST" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = if !store_sim_pin("") {
+                format_short("500 Internal Server Error", "text/plain", "flash write failed\n")
+            } else if html {
+                format_see_other("/")
+            } else {
+                format_short("200 OK", "application/json", "{\"stored\":false}")
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/fetch/cancel" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let cancelled = cancel_fetch().await;
+            let response = if html {
+                format_see_other("/tools")
+            } else {
+                let mut body = heapless::String::<32>::new();
+                let _ = core::write!(body, "{{\"cancelled\":\"{}\"}}", cancelled);
+                format_short("200 OK", "application/json", &body)
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/modem/allow-roaming" if method == "POST" => {
+            ROAMING_ALLOWED.store(true, Ordering::Relaxed);
+            warn!("Roaming data allowed until reboot");
+            bump_state_generation();
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = if html {
+                format_see_other("/")
+            } else {
+                format_short("200 OK", "application/json", "{\"roaming_allowed\":true}")
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/identify" if method == "POST" => {
+            let dispatched = dispatch_action(actions::Source::Web, ActionRequest::Identify, Some(request_id));
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = if let Err(reason) = dispatched {
+                let mut body = heapless::String::<96>::new();
+                let mut obj = json::Object::new(&mut body);
+                obj.str("error", reason);
+                obj.finish();
+                format_short("403 Forbidden", "application/json", &body)
+            } else if html {
+                format_see_other("/")
+            } else {
+                let mut body = heapless::String::<48>::new();
+                let _ = core::write!(body, "{{\"led\":\"{}\",\"seconds\":{}}}", led_state(), led::IDENTIFY_MS / 1000);
+                format_short("200 OK", "application/json", &body)
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/led" if method == "POST" => {
+            let mode = http::form_value(query, "mode")
+                .or_else(|| http::form_value(body, "mode"))
+                .and_then(led::Mode::parse);
+            let response = match mode {
+                Some(mode) => {
+                    LED.lock(|l| l.borrow_mut().set_mode(mode));
+                    LED_CHANGED.signal(());
+                    info!("LED override: {}", mode.as_str());
+                    let mut body = heapless::String::<32>::new();
+                    let _ = core::write!(body, "{{\"led\":\"{}\"}}", led_state());
+                    format_short("200 OK", "application/json", &body)
+                }
+                None => format_short("400 Bad Request", "text/plain", "mode must be auto, on or off\n"),
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        // 不在 /api/spec 里: 只有 sim-modem 固件才有
+        #[cfg(feature = "sim-modem")]
+        "/api/sim" => {
+            let fault = http::form_value(query, "fault").or_else(|| http::form_value(body, "fault"));
+            let response = match fault.map(modem_sim::Fault::parse) {
+                Some(Some(fault)) if method == "POST" => {
+                    SIM_FAULT.lock(|f| f.set(fault));
+                    warn!("sim-modem: fault {}", fault.as_str());
+                    format_sim_json()
+                }
+                Some(None) if method == "POST" => format_short(
+                    "400 Bad Request",
+                    "text/plain",
+                    "fault must be none, no_response, qiact_error or close_mid_receive\n",
+                ),
+                _ => format_sim_json(),
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/uart1/write" if method == "POST" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = write_uart1_form(body, html).await;
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        "/api/macros/run" if method == "POST" => {
+            let html = http::negotiate(accept, &["application/json", "text/html"]) == "text/html";
+            let response = queue_macro_run(query, html, request_id);
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+        // 下面的页面
+        "/" | "/tools" | "/at" | "/http_get" | "/api/status" => {}
+        _ => {
+            let response = format_short("404 Not Found", "text/html; charset=utf-8", NOT_FOUND_HTML);
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+    }
+
+    // /at 和 /http_get 按路径精确匹配; HEAD 不触发
+    let mut cmd_to_send = heapless::String::<64>::new();
+    let mut trigger_http_get = false;
+    let mut immediate_refresh = false;
+
+    // POST 时命令在表单正文里
+    let form = parsed.as_ref().map_or(&[][..], |r| r.form());
+    if !head
+        && path == "/at"
+        && let Some(cmd) = http::form_value(form, "cmd")
+        && !cmd.is_empty()
+    {
+        immediate_refresh = true;
+        cmd_to_send = decode_url(cmd);
+    } else if !head && path == "/http_get" {
+        immediate_refresh = true;
+        let param = |name| http::form_value(query, name);
+        match fetch_target::Target::from_params(param("host"), param("port"), param("path")) {
+            Ok(target) => {
+                FETCH_TARGET.lock(|t| *t.borrow_mut() = Some(target));
+                trigger_http_get = true;
+            }
+            // 参数不对就不发任何 AT 指令, 原因显示在页面上
+            Err(e) => {
+                let mut result = modem_result().await;
+                result.clear();
+                let _ = result.push_str("⚠️ HTTP GET not run: ");
+                e.describe(&mut *result);
+                let _ = result.push('\n');
+            }
+        }
+    }
+
+    // 有人在看结果, 交互式获取不会被当作无人等待而取消; HEAD 只看头, 不算
+    if !head {
+        RESULT_VIEWED_MS.store(Instant::now().as_millis(), Ordering::Relaxed);
+    }
+
+    // 构建响应 (根据 Accept 头选择 HTML / JSON)
+    let want_json = path == "/api/status"
+        || (path == "/" && method == "GET" && http::negotiate(accept, &["text/html", "application/json"]) == "application/json");
+    let tools = matches!(path, "/tools" | "/at" | "/http_get");
+    let if_none_match = parsed.as_ref().and_then(|r| r.header("If-None-Match"));
+    let answered = if method == "POST" {
+        // 表单提交的命令 (只有 /at 接受 POST): 跳回工具页, 刷新时不会重复发送
+        let _ = socket.write_all(format_see_other("/tools").as_bytes()).await;
+        let _ = socket.flush().await;
+        true
+    } else if want_json {
+        serve_cached_status(socket, &STATUS_JSON_CACHE, path, true, if_none_match).await
+    } else if path == "/" && !tools {
+        serve_cached_status(socket, &STATUS_PAGE_CACHE, path, false, if_none_match).await
+    } else {
+        false
+    };
+
+    if !answered {
+        // 只有结果区需要锁, 其他路径都是概览页. 复制出来就放锁, 格式化和发送时不占着它;
+        // 状态代数在锁内读取, 与复制的内容一致
+        let (result, generation) = if want_json || tools {
+            let result = held(lock_stats::Site::ResultPage, AT_RESULT.lock().await);
+            (result.clone(), STATE_GENERATION.load(Ordering::Relaxed))
+        } else {
+            (heapless::String::new(), STATE_GENERATION.load(Ordering::Relaxed))
+        };
+        let now = Instant::now().as_millis();
+        let etag = match path {
+            "/" | "/tools" | "/api/status" => Some(page_cache::etag(generation, want_json)),
+            _ => None,
+        };
+
+        // 发送响应 (页面比其他响应大, 各自构建)
+        match etag {
+            Some(ref etag) if http::etag_matches(if_none_match, etag) => {
+                let _ = socket.write_all(format_not_modified(etag).as_bytes()).await;
+            }
+            _ if want_json => {
+                let page = format_status_json(&result, generation);
+                if let Ok(page) = &page {
+                    STATUS_JSON_CACHE.lock(|c| c.borrow_mut().store(page, generation, now));
+                }
+                write_page(socket, path, page).await;
+            }
+            _ if tools => {
+                let page = format_tools(immediate_refresh, etag.as_deref());
+                write_streamed_page(socket, path, page, &result).await;
+            }
+            _ => {
+                let page = format_overview(etag.as_deref());
+                if let Ok(page) = &page
+                    && path == "/"
+                {
+                    STATUS_PAGE_CACHE.lock(|c| c.borrow_mut().store(page, generation, now));
+                }
+                write_page(socket, path, page).await;
+            }
+        }
+        let _ = socket.flush().await;
+    }
+    
+    // 如果有命令要发送，在响应后发送信号
+    let mut submitted = true;
+    if !cmd_to_send.is_empty() {
+        info!("Queueing AT command: {}", cmd_to_send);
+        submitted = submit_modem_op_for(ModemOp::AtCommand(cmd_to_send), Some(request_id));
+    }
+    
+    if trigger_http_get {
+        info!("Queueing HTTP GET request");
+        let request = ActionRequest::Fetch(fetch::Origin::Web);
+        if let Err(reason) = dispatch_action(actions::Source::Web, request, Some(request_id)) {
+            let mut result = modem_result().await;
+            result.clear();
+            let _ = core::writeln!(result, "⚠️ HTTP GET not run: {}", reason);
+            return;
+        }
+    }
+    if !submitted {
+        let mut result = modem_result().await;
+        result.clear();
+        let _ = result.push_str("⚠️ Modem busy: too many operations queued, try again shortly\n");
+    }
+}
+
+// A copy of "/" or /api/status built in this state generation moments ago;
+// false when it has to be built. A matching If-None-Match is left to the
+// usual path, which answers 304 without building anything.
+async fn serve_cached_status<const N: usize>(
+    socket: &mut Conn<'_, '_>,
+    cache: &embassy_sync::blocking_mutex::Mutex<
+        embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+        core::cell::RefCell<page_cache::PageCache<N>>,
+    >,
+    path: &str,
+    json: bool,
+    if_none_match: Option<&str>,
+) -> bool {
+    let generation = STATE_GENERATION.load(Ordering::Relaxed);
+    if http::etag_matches(if_none_match, &page_cache::etag(generation, json)) {
+        return false;
+    }
+    let now = Instant::now().as_millis();
+    let Some(page) = cache.lock(|c| c.borrow_mut().get(generation, now)) else {
+        return false;
+    };
+    write_page(socket, path, Ok(page)).await;
+    let _ = socket.flush().await;
+    true
+}
+
+fn format_method_not_allowed(allow: &str) -> heapless::String<512> {
+    let body = "Method not allowed\n";
+    let mut response = heapless::String::new();
+    let _ = response.push_str("HTTP/1.1 405 Method Not Allowed\r\n");
+    let _ = core::write!(response, "Allow: {}\r\n", allow);
+    let _ = response.push_str("Content-Type: text/plain\r\n");
+    let _ = core::write!(response, "Content-Length: {}\r\n", body.len());
+    let _ = response.push_str("Connection: close\r\n\r\n");
+    let _ = response.push_str(body);
+    response
+}
+
+// A page that did not fit its buffer goes out as a short 503, not cut short
+async fn write_page<const N: usize>(
+    socket: &mut Conn<'_, '_>,
+    path: &str,
+    page: Result<heapless::String<N>, http::BufferFull>,
+) {
+    let _ = match page {
+        Ok(page) => {
+            debug_assert!(core::str::from_utf8(page.as_bytes()).is_ok() && http::length_consistent(&page));
+            socket.write_all(page.as_bytes()).await
+        }
+        Err(http::BufferFull) => {
+            PAGES_TOO_LARGE.fetch_add(1, Ordering::Relaxed);
+            warn!("Page {} did not fit its {} byte buffer, answered 503", path, N);
+            socket.write_all(http::PAGE_TOO_LARGE).await
+        }
+    };
+}
+
+// 处理函数没读完 (提前返回) 的正文丢掉; 正文超时未到齐则直接复位
+async fn finish_body(socket: &mut Conn<'_, '_>, body: Option<&mut http::BodyReader<'_>>) {
+    if let Some(body) = body
+        && !body.drain(socket.get_mut()).await
+    {
+        abort_conn(socket, conn_close::Close::BodyNotDrained);
+    }
+}
+
+// 处理途中放弃连接: 复位, 原因留给 http_server_task 计数
+fn abort_conn(socket: &mut Conn<'_, '_>, reason: conn_close::Close) {
+    socket.set_aborted(reason);
+    socket.get_mut().abort();
+}
+
+fn format_log_level_json() -> heapless::String<512> {
+    let mut body = heapless::String::<32>::new();
+    let mut obj = json::Object::new(&mut body);
+    obj.str("level", log_level::get().as_str());
+    obj.finish();
+    format_short("200 OK", "application/json", &body)
+}
+
+// POST /api/loglevel (表单: level=error|warn|info|debug|trace), 写入配置
+fn set_log_level(form: &[u8], html: bool) -> heapless::String<512> {
+    let level = http::form_value(form, "level").and_then(log_level::Level::parse);
+    let Some(level) = level else {
+        return format_short("400 Bad Request", "text/plain", "level must be error, warn, info, debug or trace\n");
+    };
+    if !store_text_setting("log.level", level.as_str()) {
+        return format_short("500 Internal Server Error", "text/plain", "flash write failed\n");
+    }
+    // 不经过级别过滤, 调低级别时也留下记录
+    defmt::info!("Log level set to {}", level.as_str());
+    if html {
+        return format_see_other("/");
+    }
+    format_log_level_json()
+}
+
+fn push_log_level_html<const N: usize>(html: &mut heapless::String<N>) {
+    let current = log_level::get();
+    let _ = html.push_str("<form method='post' action='/api/loglevel'>Log level: <select name='level'>");
+    for level in log_level::Level::ALL {
+        let selected = if level == current { " selected" } else { "" };
+        let _ = core::write!(html, "<option{}>{}</option>", selected, level.as_str());
+    }
+    let _ = html.push_str("</select> <button type='submit' class='btn-at'>Set</button></form>");
+}
+
+fn push_etag_headers<const N: usize>(out: &mut heapless::String<N>, etag: &str) {
+    let _ = out.push_str("ETag: ");
+    let _ = out.push_str(etag);
+    let _ = out.push_str("\r\nCache-Control: no-cache\r\nVary: Accept\r\n");
+}
+
+fn format_not_modified(etag: &str) -> heapless::String<4096> {
+    let mut response = heapless::String::new();
+
+    let _ = response.push_str("HTTP/1.1 304 Not Modified\r\n");
+    push_etag_headers(&mut response, etag);
+    let _ = response.push_str("Connection: close\r\n\r\n");
+
+    response
+}
+
+// 短响应 (错误, 确认), 正文不超过几百字节
+// GET /api/sim: the simulator's fault mode and the ones it knows
+#[cfg(feature = "sim-modem")]
+fn format_sim_json() -> heapless::String<512> {
+    let mut faults = heapless::String::<96>::new();
+    for (i, fault) in modem_sim::Fault::ALL.into_iter().enumerate() {
+        let _ = faults.push_str(if i > 0 { ",\"" } else { "[\"" });
+        let _ = faults.push_str(fault.as_str());
+        let _ = faults.push('"');
+    }
+    let _ = faults.push(']');
+    let mut body = heapless::String::<160>::new();
+    let mut obj = json::Object::new(&mut body);
+    obj.str("fault", SIM_FAULT.lock(|f| f.get()).as_str());
+    obj.raw("faults", &faults);
+    obj.finish();
+    format_short("200 OK", "application/json", &body)
+}
+
+fn format_short(status: &str, content_type: &str, body: &str) -> heapless::String<512> {
+    let mut response = heapless::String::new();
+    let _ = core::write!(response, "HTTP/1.1 {}\r\n", status);
+    let _ = core::write!(response, "Content-Type: {}\r\n", content_type);
+    let _ = core::write!(response, "Content-Length: {}\r\n", body.len());
+    let _ = response.push_str("Connection: close\r\n\r\n");
+    let _ = response.push_str(body);
+    response
+}
+
+const NOT_FOUND_HTML: &str = "<!DOCTYPE html><html><head><title>Not found</title></head><body>\
+<h1>404 Not Found</h1><p>Nothing here. <a href='/'>Overview</a> | <a href='/api/spec'>API</a></p></body></html>\n";
+
+// 表单提交后跳回页面, 刷新时不会重复提交
+fn format_see_other(location: &str) -> heapless::String<512> {
+    let mut response = heapless::String::new();
+    let _ = response.push_str("HTTP/1.1 303 See Other\r\n");
+    let _ = core::write!(response, "Location: {}\r\n", location);
+    let _ = response.push_str("Content-Length: 0\r\n");
+    let _ = response.push_str("Connection: close\r\n\r\n");
+    response
+}
+
+// 导航栏里的页面
+const PAGES: [(&str, &str); 4] = [
+    ("/", "📊 Overview"),
+    ("/tools", "🛠️ Tools"),
+    ("/log", "📜 Log"),
+    ("/config", "⚙️ Config"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Refresh {
+    Off,
+    // polled in place by /live.js
+    Live,
+    // once, 1.5 s after an action, back to the page without the action
+    Soon,
+}
+
+// Shared <head> and navigation of the UI pages, `current` highlighted
+fn push_page_header<const N: usize>(html: &mut heapless::String<N>, current: &str, title: &str, refresh: Refresh) {
+    let show = |section: &str| match section {
+        "live" => refresh == Refresh::Live,
+        "reload" => refresh == Refresh::Soon,
+        _ => false,
+    };
+    template::render(HEADER_TEMPLATE, html, show, |name, html| match name {
+        "title" => {
+            let _ = html.push_str(title);
+        }
+        "device" => push_html_escaped(html, &device_name()),
+        "path" => {
+            let _ = html.push_str(current);
+        }
+        // /live.js 据此判断页面是否需要重新取
+        "generation" => {
+            let _ = core::write!(html, "{}", STATE_GENERATION.load(Ordering::Relaxed));
+        }
+        "nav" => {
+            for (path, label) in PAGES {
+                let class = if path == current { " class='current'" } else { "" };
+                let _ = core::write!(html, "<a href='{}'{}>{}</a>", path, class, label);
+            }
+        }
+        // 连接指示: SIM 状态和串口任务当前的操作
+        "connection" => {
+            let operation = MODEM_CURRENT.lock(|c| c.get()).unwrap_or("idle");
+            let _ = core::write!(html, "SIM {} · modem {}", sim_status().state.as_str(), operation);
+        }
+        _ => {}
+    });
+}
+
+fn push_html_head<const N: usize>(html: &mut heapless::String<N>, status: &str, etag: Option<&str>) {
+    let _ = core::write!(html, "HTTP/1.1 {}\r\n", status);
+    let _ = html.push_str("Content-Type: text/html; charset=utf-8\r\n");
+    if let Some(etag) = etag {
+        push_etag_headers(html, etag);
+    }
+    let _ = html.push_str("Connection: close\r\n\r\n");
+}
+
+// 概览页: 只读计数器和状态, 不锁结果区
+fn format_overview(etag: Option<&str>) -> Result<heapless::String<8192>, http::BufferFull> {
+    let mut html = heapless::String::new();
+    push_html_head(&mut html, "200 OK", etag);
+    push_page_header(&mut html, "/", "HTTP Tester", Refresh::Live);
+
+    let latency = FETCH_LATENCY.lock(|l| l.borrow().summary());
+    let has_ping = PINGS.lock(|p| p.borrow().last().is_some());
+    let at_rtt = AT_RTT.lock(|r| {
+        let rtt = r.borrow();
+        rtt.average_ms().map(|average| (average, rtt.max_ms(Instant::now().as_millis())))
+    });
+    let uart = UART_ACTIVE.lock(|a| a.get());
+    let keep_warm = KEEP_WARM.lock(|k| k.get());
+    let keep_warm_min = CONFIG.lock(|c| c.borrow().keep_warm.interval_min);
+    let registration = REGISTRATION.lock(|r| r.get());
+    let blocks = ROAMING_BLOCKS.lock(|b| b.get());
+    let probe = MODEM_PROBE.lock(|p| p.get());
+    let (failures, recovery, backoff) = FETCH_LADDER.lock(|l| {
+        let ladder = l.borrow();
+        (ladder.failures, ladder.last, ladder.in_backoff())
+    });
+    #[cfg(feature = "proxy")]
+    let forwarding = CONFIG.lock(|c| c.borrow().forwards.iter().any(forward::Rule::active));
+    let show = |section: &str| match section {
+        "latency" => latency.is_some(),
+        "ping" => has_ping,
+        "at_rtt" => at_rtt.is_some(),
+        "uart_errors" => UART_ERRORS.total() > 0,
+        "rx_audit" => RX_AUDIT.lock(|a| {
+            let audit = a.borrow();
+            audit.discrepancies() > 0 || rx_audit::Loss::ALL.iter().any(|&l| audit.lost(l) > 0)
+        }),
+        "rx_gaps" => RX_AUDIT.lock(|a| a.borrow().discrepancies() > 0),
+        "tx_failures" => UART_TX_STALLS.load(Ordering::Relaxed) + UART_TX_ERRORS.load(Ordering::Relaxed) > 0,
+        "tx_stalled" => UART_TX_STALLED.load(Ordering::Relaxed),
+        "baud_hint" => UART_ERRORS.boot_framing_burst(),
+        "modem_probe" => probe.is_some(),
+        "actions" => ACTIONS.lock(|a| a.borrow().iter().next().is_some()),
+        "uart_pending" => CONFIG.lock(|c| c.borrow().uart) != uart,
+        "debug_port" => uart.debug_port,
+        "keep_warm" => keep_warm_min > 0 || keep_warm.sent > 0,
+        "roaming" => registration == Some(registration::State::Roaming),
+        "roaming_blocked" => roaming_blocked(registration),
+        "roaming_blocks" => blocks.total() > 0,
+        "fetch_failures" => failures > 0,
+        "recovery" => failures > 0 && recovery.is_some(),
+        "backoff" => backoff,
+        #[cfg(feature = "proxy")]
+        "shaper" => forwarding,
+        #[cfg(feature = "proxy")]
+        "shaper_auto" => CONFIG.lock(|c| c.borrow().shaper.rate) == 0,
+        "schedule" => schedule_in_use(),
+        #[cfg(feature = "gnss")]
+        "gnss" => CONFIG.lock(|c| c.borrow().gnss.enabled) || GNSS.lock(|g| g.borrow().powered_at_ms.is_some()),
+        #[cfg(feature = "gnss")]
+        "geofence" => CONFIG.lock(|c| c.borrow().geofence.radius_m) > 0,
+        _ => false,
+    };
+    // 秒, 保留一位小数
+    let seconds = |html: &mut heapless::String<8192>, ms: u32| {
+        let _ = core::write!(html, "{}.{}", ms / 1000, ms % 1000 / 100);
+    };
+    template::render(STATUS_TEMPLATE, &mut html, show, |name, html| match name {
+        "boot" => push_boot_html(html),
+        "sim" => push_sim_html(html),
+        "ssid" => push_html_escaped(html, WIFI_SSID),
+        "password" => push_html_escaped(html, WIFI_PASSWORD),
+        "baud" => {
+            let _ = core::write!(html, "{}", UART_BAUDRATE);
+        }
+        "tx_rate" => {
+            let _ = core::write!(html, "{}", UART_TX_RATE.load(Ordering::Relaxed));
+        }
+        "tx_bytes" => {
+            let _ = core::write!(html, "{}", UART_TX_BYTES.load(Ordering::Relaxed));
+        }
+        "rx_rate" => {
+            let _ = core::write!(html, "{}", UART_RX_RATE.load(Ordering::Relaxed));
+        }
+        "rx_bytes" => {
+            let _ = core::write!(html, "{}", UART_RX_BYTES.load(Ordering::Relaxed));
+        }
+        "uart_errors" => push_uart_error_counts(html),
+        "rx_audit" => push_rx_audit(html),
+        "rx_gaps" => RX_AUDIT.lock(|a| {
+            let audit = a.borrow();
+            let _ = core::write!(html, "{} (last {:+} bytes)", audit.discrepancies(), audit.last_gap());
+        }),
+        "framing" => uart.write_framing(html),
+        "tx_stalls" => {
+            let _ = core::write!(html, "{}", UART_TX_STALLS.load(Ordering::Relaxed));
+        }
+        "tx_write_errors" => {
+            let _ = core::write!(html, "{}", UART_TX_ERRORS.load(Ordering::Relaxed));
+        }
+        "tx_max_drain" => {
+            let _ = core::write!(html, "{}", UART_TX_MAX_DRAIN_MS.load(Ordering::Relaxed));
+        }
+        "actions" => push_recent_actions(html),
+        "probe_attempts" => {
+            let _ = core::write!(html, "{}", probe.map_or(0, |p| p.attempts()));
+        }
+        "probe_interval" => {
+            let _ = core::write!(html, "{}", probe.map_or(0, |p| p.interval_ms(Instant::now().as_millis()) / 1000));
+        }
+        "modem_tx" => {
+            let _ = core::write!(html, "{}", board::ACTIVE.modem_tx);
+        }
+        "modem_rx" => {
+            let _ = core::write!(html, "{}", board::ACTIVE.modem_rx);
+        }
+        "debug_tx" => {
+            let _ = core::write!(html, "{}", board::ACTIVE.debug_tx);
+        }
+        "debug_rx" => {
+            let _ = core::write!(html, "{}", board::ACTIVE.debug_rx);
+        }
+        "debug_baud" => {
+            let _ = core::write!(html, "{}", uart.debug_baud);
+        }
+        "debug_mode" => {
+            let _ = html.push_str(if uart.debug_writes { "read-write" } else { "read-only" });
+        }
+        "modem" => {
+            let _ = html.push_str(current_modem().name());
+        }
+        "led" => {
+            let _ = html.push_str(led_state());
+        }
+        "operation" => {
+            let _ = html.push_str(MODEM_CURRENT.lock(|c| c.get()).unwrap_or("idle"));
+        }
+        "queued" => {
+            let _ = core::write!(html, "{}", MODEM_OPS.lock(|q| q.borrow().len()));
+        }
+        "fetch_count" => {
+            let _ = core::write!(html, "{}", latency.as_ref().map_or(0, |s| s.count));
+        }
+        "fetch_p50" => seconds(html, latency.as_ref().map_or(0, |s| s.p50_ms)),
+        "fetch_p95" => seconds(html, latency.as_ref().map_or(0, |s| s.p95_ms)),
+        "fetch_max" => seconds(html, latency.as_ref().map_or(0, |s| s.max_ms)),
+        "ping_host" => {
+            let _ = html.push_str(PING_HOST);
+        }
+        "at_rtt" => {
+            let _ = core::write!(html, "{}", at_rtt.map_or(0, |(average, _)| average));
+        }
+        "at_rtt_max" => {
+            let _ = core::write!(html, "{}", at_rtt.map_or(0, |(_, max)| max));
+        }
+        "ping" => PINGS.lock(|p| {
+            let pings = p.borrow();
+            let _ = match pings.last() {
+                Some(sparkline::LOST) => html.push_str("<span class='error'>lost</span>"),
+                Some(ms) => core::write!(html, "<strong>{} ms</strong>", ms).map_err(|_| ()),
+                None => Ok(()),
+            };
+            sparkline::push_svg(html, &pings);
+        }),
+        "registration" => {
+            let _ = html.push_str(registration.map_or("not checked yet", registration::State::as_str));
+        }
+        "roaming_blocks" => {
+            let mut first = true;
+            for feature in registration::Feature::ALL {
+                let count = blocks.get(feature);
+                if count > 0 {
+                    let _ = core::write!(html, "{}{} ×{}", if first { "" } else { ", " }, feature.as_str(), count);
+                    first = false;
+                }
+            }
+        }
+        "fetch_failures" => {
+            let _ = core::write!(html, "{}", failures);
+        }
+        #[cfg(feature = "proxy")]
+        "shaper_up" => {
+            let _ = core::write!(html, "{}", shaper_utilisation(shaper::Direction::Up));
+        }
+        #[cfg(feature = "proxy")]
+        "shaper_down" => {
+            let _ = core::write!(html, "{}", shaper_utilisation(shaper::Direction::Down));
+        }
+        #[cfg(feature = "proxy")]
+        "shaper_rate" => {
+            let _ = core::write!(html, "{}", shaper_rate());
+        }
+        #[cfg(feature = "proxy")]
+        "shaper_percent" => {
+            let _ = core::write!(html, "{}", shaper::AUTO_PERCENT);
+        }
+        "recovery" => {
+            if let Some(attempt) = recovery {
+                let outcome = match attempt.ok {
+                    None => "running",
+                    Some(true) => "done",
+                    Some(false) => "failed",
+                };
+                let ago = Instant::now().as_millis().saturating_sub(attempt.at_ms) / 1000;
+                let _ = core::write!(html, "{} ({}, {} s ago)", attempt.step.as_str(), outcome, ago);
+            }
+        }
+        "keep_warm_min" => {
+            let _ = core::write!(html, "{}", keep_warm_min);
+        }
+        "keep_warm_host" => push_html_escaped(html, &keep_warm_host()),
+        "keep_warm_sent" => {
+            let _ = core::write!(html, "{}", keep_warm.sent);
+        }
+        "keep_warm_failed" => {
+            let _ = core::write!(html, "{}", keep_warm.failed);
+        }
+        "keep_warm_reactivations" => {
+            let _ = core::write!(html, "{}", keep_warm.reactivations);
+        }
+        "keep_warm_bytes" => {
+            let _ = core::write!(html, "{}", keep_warm.bytes);
+        }
+        "schedule" => push_schedule_html(html),
+        #[cfg(feature = "gnss")]
+        "gnss" => push_gnss_html(html),
+        #[cfg(feature = "gnss")]
+        "geofence" => push_geofence_html(html),
+        #[cfg(feature = "gnss")]
+        "geofence_radius" => {
+            let _ = core::write!(html, "{}", CONFIG.lock(|c| c.borrow().geofence.radius_m));
+        }
+        "log_level" => push_log_level_html(html),
+        "version" => version::write_footer(html),
+        _ => {}
+    });
+
+    http::finish_page(&mut html)?;
+    Ok(html)
+}
+
+// 工具页: 获取和 AT 指令, 以及它们的结果
+// Everything but the result area, which write_streamed_page sends at the
+// returned offset
+fn format_tools(
+    immediate_refresh: bool,
+    etag: Option<&str>,
+) -> Result<(heapless::String<8192>, usize), http::BufferFull> {
+    let mut html = heapless::String::new();
+    push_html_head(&mut html, "200 OK", etag);
+    let refresh = if immediate_refresh { Refresh::Soon } else { Refresh::Live };
+    push_page_header(&mut html, "/tools", "Tools", refresh);
+
+    let fetch_pending = fetch_pending();
+    let sections = [page_budget::FETCH_SUMMARY];
+    let mut budget = page_budget::Budget::new(&sections, html.capacity() - http::PAGE_HEADROOM);
+    let mut result_at = 0;
+    let show = |section: &str| match section {
+        "live" => !immediate_refresh,
+        "reload" => immediate_refresh,
+        "fetch_pending" => fetch_pending,
+        "history" => FETCH_HISTORY.lock(|h| h.borrow().iter().next().is_some()),
+        _ => false,
+    };
+    template::render(TOOLS_TEMPLATE, &mut html, show, |name, html| match name {
+        "at_actions" => {
+            let modem = current_modem();
+            push_at_action(html, &modem.signal_quality(), "📶 Signal");
+            push_at_action(html, &modem.register(), "📡 Network");
+            push_at_action(html, &modem.ping(PING_HOST, 4), "📈 Ping");
+        }
+        "result" => result_at = html.len(),
+        "history" => push_budgeted(html, &mut budget, 0, push_fetch_history),
+        _ => {}
+    });
+
+    if html.len() + http::PAGE_HEADROOM > html.capacity() {
+        return Err(http::BufferFull);
+    }
+    Ok((html, result_at))
+}
+
+// A page built in one buffer except for one text of any length, inserted
+// at `at` HTML-escaped a piece at a time. The body goes out chunked (or
+// unframed to HTTP/1.0), so the text is never cut or left out for space.
+async fn write_streamed_page<const N: usize>(
+    socket: &mut Conn<'_, '_>,
+    path: &str,
+    page: Result<(heapless::String<N>, usize), http::BufferFull>,
+    text: &str,
+) {
+    let (page, at) = match page {
+        Ok(page) => page,
+        Err(full) => return write_page::<N>(socket, path, Err(full)).await,
+    };
+    // 头部以空行结束, 分块的头插在空行之前
+    let Some(body_start) = http::find_header_end(page.as_bytes()) else {
+        return;
+    };
+    // 模板里没有 {result} 槽 (at 为 0): 整页照发, 不带结果
+    let (at, text) = if (body_start..=page.len()).contains(&at) {
+        (at, text)
+    } else {
+        warn!("Page {} has no result slot, sent without the result", path);
+        (page.len(), "")
+    };
+    let chunked = socket.chunked();
+    let mut framing = heapless::String::<32>::new();
+    http::push_unknown_length(&mut framing, chunked);
+    for part in [&page[..body_start - 2], &framing, "\r\n"] {
+        if socket.write_all(part.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+
+    let mut writer = http::ChunkedWriter::new(socket, chunked);
+    if writer.write_chunk(page[body_start..at].as_bytes()).await.is_err() {
+        return;
+    }
+    // 每次转义最多 64 字节 (转义后至多 5 倍), 攒够一块再发
+    let mut piece = heapless::String::<512>::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut take = rest.len().min(64);
+        while !rest.is_char_boundary(take) {
+            take -= 1;
+        }
+        push_html_escaped(&mut piece, &rest[..take]);
+        rest = &rest[take..];
+        if piece.len() + 64 * 5 > piece.capacity() || rest.is_empty() {
+            if writer.write_chunk(piece.as_bytes()).await.is_err() {
+                return;
+            }
+            piece.clear();
+        }
+    }
+    if writer.write_chunk(page[at..].as_bytes()).await.is_ok() {
+        let _ = writer.finish().await;
+    }
+}
+
+// 写入一段, 超出份额时计数并记日志 (page_budget::Budget::push)
+fn push_budgeted<const N: usize>(
+    html: &mut heapless::String<N>,
+    budget: &mut page_budget::Budget,
+    index: usize,
+    fill: impl FnOnce(&mut heapless::String<N>),
+) {
+    if let Err(omitted) = budget.push(html, index, fill) {
+        SECTIONS_OMITTED.fetch_add(1, Ordering::Relaxed);
+        let name = budget.section(index).name;
+        warn!("Page section {} omitted: {} bytes, room for {}", name, omitted.len, omitted.room);
+    }
+}
+
+fn push_fetch_history<const N: usize>(html: &mut heapless::String<N>) {
+    let now = Instant::now().as_millis();
+    let _ = html.push_str("<table><tr><th>When</th><th>Origin</th><th>Request</th><th>Connection</th><th>Time</th>");
+    let _ = html.push_str("<th>Bytes</th><th>Result</th><th>Response</th></tr>");
+    FETCH_HISTORY.lock(|h| {
+        for record in h.borrow().iter() {
+            let _ = core::write!(
+                html,
+                "<tr><td>{} s ago</td><td>{}</td><td>",
+                now.saturating_sub(record.at_ms) / 1000,
+                record.origin.as_str()
+            );
+            if let Some(id) = record.request_id {
+                let _ = core::write!(html, "#{}", id);
+            }
+            let _ = core::write!(
+                html,
+                "</td><td>{}</td><td>{} ms</td><td>{}</td><td>",
+                record.connection.as_str(),
+                record.elapsed_ms,
+                record.received
+            );
+            match record.error {
+                Some(e) => e.describe(html),
+                None => {
+                    let _ = html.push_str("ok");
+                }
+            }
+            let _ = html.push_str("</td><td>");
+            // 已经被挤出存档的不再给链接
+            if let Some(n) = record.response
+                && RESPONSE_ARCHIVE.lock(|a| a.borrow().get(n).is_some())
+            {
+                let _ = core::write!(html, "<a href=\"/api/response/{}\">#{}</a>", n, n);
+            }
+            let _ = html.push_str("</td></tr>");
+        }
+    });
+    let _ = html.push_str("</table>");
+}
+
+// GET /api/fetch/history, newest first; ?request=<id> keeps the fetch that
+// request queued
+fn format_fetch_history_json(request_id: Option<u32>) -> heapless::String<2048> {
+    let mut out = heapless::String::new();
+    let now = Instant::now().as_millis();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = out.push('[');
+    FETCH_HISTORY.lock(|h| {
+        let history = h.borrow();
+        let records = history.iter().filter(|r| request_id.is_none() || r.request_id == request_id);
+        for (i, record) in records.enumerate() {
+            if i > 0 {
+                let _ = out.push(',');
+            }
+            let mut error = heapless::String::<64>::new();
+            if let Some(e) = record.error {
+                e.describe(&mut error);
+            }
+            let mut obj = json::Object::new(&mut out);
+            obj.u32("age_secs", (now.saturating_sub(record.at_ms) / 1000) as u32)
+                .str("origin", record.origin.as_str());
+            if let Some(id) = record.request_id {
+                obj.u32("request_id", id);
+            }
+            obj.str("connection", record.connection.as_str())
+                .u32("elapsed_ms", record.elapsed_ms)
+                .u32("received", record.received)
+                .bool("ok", record.error.is_none());
+            if let Some(n) = record.response {
+                obj.u32("response", n);
+            }
+            if record.error.is_some() {
+                obj.str("error", &error);
+            }
+            obj.finish();
+        }
+    });
+    let _ = out.push(']');
+
+    http::set_content_length(&mut out);
+    out
+}
+
+fn format_uart_errors_json() -> heapless::String<96> {
+    let mut out = heapless::String::new();
+    let mut obj = json::Object::new(&mut out);
+    for kind in uart_errors::Kind::ALL {
+        obj.u32(kind.as_str(), UART_ERRORS.get(kind));
+    }
+    obj.bool("baud_mismatch_suspected", UART_ERRORS.boot_framing_burst());
+    obj.finish();
+    out
+}
+
+fn format_status_json(result: &str, generation: u32) -> Result<heapless::String<6144>, http::BufferFull> {
+    let mut response = heapless::String::new();
+
+    let _ = response.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = response.push_str("Content-Type: application/json\r\n");
+    push_etag_headers(&mut response, &page_cache::etag(generation, true));
+    let _ = response.push_str("Connection: close\r\n\r\n");
+
+    let uart = UART_ACTIVE.lock(|a| a.get());
+    let mut framing = heapless::String::<4>::new();
+    uart.write_framing(&mut framing);
+    let receive_mode = fetch::ReceiveMode::select(current_modem(), CONFIG.lock(|c| c.borrow().fetch.direct_push));
+    let name = device_name();
+
+    let mut status = json::Object::new(&mut response);
+    status
+        .str("device_name", &name)
+        .str("hostname", &identity::hostname(&name))
+        .str("ssid", wifi_ssid())
+        .bool("recovery", recovery_mode())
+        .str("ip", "192.168.4.1")
+        .str("uart_framing", &framing)
+        .bool("uart1", uart.debug_port)
+        .u32("uart_tx_bytes_per_sec", UART_TX_RATE.load(Ordering::Relaxed))
+        .u32("uart_rx_bytes_per_sec", UART_RX_RATE.load(Ordering::Relaxed))
+        .raw("uart_errors", &format_uart_errors_json())
+        .bool("uart_tx_stalled", UART_TX_STALLED.load(Ordering::Relaxed))
+        .u32("uart_tx_stalls", UART_TX_STALLS.load(Ordering::Relaxed))
+        .u32("uart_tx_write_errors", UART_TX_ERRORS.load(Ordering::Relaxed))
+        .u32("uart_tx_max_drain_ms", UART_TX_MAX_DRAIN_MS.load(Ordering::Relaxed))
+        .u32("socket_pool_in_use", SOCKET_POOL.in_use())
+        .u32("socket_pool_slots", SOCKET_POOL.capacity())
+        .bool("sockets_degraded", SOCKET_BUDGET.degraded())
+        .str("modem", current_modem().name())
+        .bool("roaming_data_blocked", roaming_blocked(REGISTRATION.lock(|r| r.get())))
+        .u32("roaming_blocks", ROAMING_BLOCKS.lock(|b| b.get().total()))
+        .u32("modem_queue_depth", MODEM_OPS.lock(|q| q.borrow().len()) as u32)
+        .str("log_level", log_level::get().as_str())
+        .str("fetch_phase", FETCH_PHASE.lock(|p| p.get()).map_or("idle", fetch::Phase::as_str))
+        .str("fetch_origin", FETCH_ORIGIN.lock(|o| o.get()).map_or("none", fetch::Origin::as_str))
+        .str("receive_mode", receive_mode.as_str())
+        .str("led", led_state())
+        .raw("fetch_latency", &format_latency_json())
+        .raw("keep_warm", &format_keep_warm_json());
+    #[cfg(feature = "proxy")]
+    status.raw("shaper", &format_shaper_json());
+    #[cfg(feature = "gnss")]
+    status.raw("gnss", &format_gnss_json()).raw("geofence", &format_geofence_json());
+    status
+        .u32("fetch_failures_in_a_row", FETCH_LADDER.lock(|l| l.borrow().failures))
+        .str(
+            "recovery_step",
+            FETCH_LADDER.lock(|l| l.borrow().last).map_or("none", |attempt| attempt.step.as_str()),
+        )
+        .raw("boot", &format_boot_json())
+        .u32("tasks_failed", TASKS.lock(|t| t.borrow().failed()) as u32)
+        .u32("generation", generation);
+    let core = status_json::Core {
+        uart_baud: UART_BAUDRATE,
+        uart_tx_bytes: UART_TX_BYTES.load(Ordering::Relaxed),
+        uart_rx_bytes: UART_RX_BYTES.load(Ordering::Relaxed),
+        requests: REQUEST_COUNT.load(Ordering::Relaxed),
+        uptime_secs: Instant::now().as_secs() as u32,
+        sim: sim_status().state.as_str(),
+        registration: REGISTRATION.lock(|r| r.get()).map_or("unknown", registration::State::as_str),
+        modem_operation: MODEM_CURRENT.lock(|c| c.get()).unwrap_or("idle"),
+        result,
+    };
+    core.write(&mut status);
+    status.finish();
+
+    http::finish_page(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(feature = "gnss")]
+fn format_gnss_json() -> heapless::String<512> {
+    let mut out = heapless::String::new();
+    let enabled = CONFIG.lock(|c| c.borrow().gnss.enabled);
+    GNSS.lock(|g| g.borrow().write_json(&mut out, enabled, Instant::now().as_millis()));
+    out
+}
+
+// 只读各任务已缓存的状态, 不发 AT 指令, 模块卡住时也立刻回答
+fn format_healthz() -> heapless::String<384> {
+    let settings = CONFIG.lock(|c| c.borrow().health);
+    let last_ok_ms = LAST_CELLULAR_OK_MS.load(Ordering::Relaxed);
+    let inputs = health::Inputs {
+        network_up: BOOT.lock(|b| {
+            let boot = b.borrow();
+            boot.stages().iter().any(|s| s.name == "network" && s.outcome == boot::Outcome::Done)
+        }),
+        registered: matches!(
+            REGISTRATION.lock(|r| r.get()),
+            Some(registration::State::Home | registration::State::Roaming)
+        ),
+        data_context: PDP_ACTIVE.lock(|p| p.get()),
+        last_ok_ms: (last_ok_ms > 0).then_some(last_ok_ms),
+        now_ms: Instant::now().as_millis(),
+        max_idle_min: settings.max_idle_min,
+        free_sockets: socket_budget::STACK_SOCKETS.saturating_sub(SOCKET_BUDGET.total_in_use()),
+        min_free_sockets: settings.min_free_sockets as usize,
+        critical_tasks_started: TASKS.lock(|t| t.borrow().critical_failed().is_none()),
+    };
+    let mut body = heapless::String::<192>::new();
+    inputs.write_json(&mut body, settings.checks);
+    let status = if inputs.healthy(settings.checks) { "200 OK" } else { "503 Service Unavailable" };
+
+    let mut response = heapless::String::new();
+    let _ = core::write!(response, "HTTP/1.1 {}\r\n", status);
+    let _ = response.push_str("Content-Type: application/json\r\n");
+    let _ = response.push_str("Cache-Control: no-store\r\n");
+    let _ = core::write!(response, "Content-Length: {}\r\n", body.len());
+    let _ = response.push_str("Connection: close\r\n\r\n");
+    let _ = response.push_str(&body);
+    response
+}
+
+// 每次按当前配置生成, 改了监听端口马上生效
+#[cfg(feature = "proxy")]
+fn format_pac() -> heapless::String<512> {
+    let proxy_port = CONFIG.lock(|c| {
+        let config = c.borrow();
+        let index = (config.pac.forward as usize).checked_sub(1)?;
+        let rule = &config.forwards[index];
+        rule.active().then_some(rule.listen_port)
+    });
+    let mut response = heapless::String::new();
+    let _ = response.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = core::write!(response, "Content-Type: {}\r\n", pac::CONTENT_TYPE);
+    let _ = response.push_str("Cache-Control: no-cache\r\n");
+    let _ = response.push_str("Connection: close\r\n\r\n");
+    pac::write_script(&mut response, proxy_port);
+    http::set_content_length(&mut response);
+    response
+}
+
+#[cfg(feature = "proxy")]
+fn format_shaper_json() -> heapless::String<96> {
+    let mut out = heapless::String::new();
+    let mut obj = json::Object::new(&mut out);
+    obj.u32("rate", shaper_rate())
+        .bool("auto", CONFIG.lock(|c| c.borrow().shaper.rate) == 0)
+        .u32("up_pct", shaper_utilisation(shaper::Direction::Up))
+        .u32("down_pct", shaper_utilisation(shaper::Direction::Down));
+    obj.finish();
+    out
+}
+
+fn format_keep_warm_json() -> heapless::String<192> {
+    let stats = KEEP_WARM.lock(|k| k.get());
+    let now = Instant::now().as_millis();
+    let mut out = heapless::String::new();
+    let mut obj = json::Object::new(&mut out);
+    obj.u32("interval_min", CONFIG.lock(|c| c.borrow().keep_warm.interval_min))
+        .str("host", &keep_warm_host())
+        .u32("sent", stats.sent)
+        .u32("failed", stats.failed)
+        .u32("reactivations", stats.reactivations)
+        .u32("estimated_bytes", stats.bytes);
+    match stats.last_ms {
+        Some(at) => obj.u32("last_s_ago", (now.saturating_sub(at) / 1000) as u32),
+        None => obj.raw("last_s_ago", "null"),
+    };
+    obj.finish();
+    out
+}
+
+fn net_snapshot() -> heapless::Vec<netstat::Entry, 16> {
+    NETSTAT.lock(|n| n.borrow().iter().copied().collect())
+}
+
+fn push_endpoint<const N: usize>(out: &mut heapless::String<N>, ip: [u8; 4], port: u16) {
+    let _ = core::write!(out, "{}.{}.{}.{}:{}", ip[0], ip[1], ip[2], ip[3], port);
+}
+
+// /net: 类似 netstat 的套接字列表
+fn format_net_html() -> heapless::String<12288> {
+    let mut html = heapless::String::new();
+    let now = Instant::now().as_millis();
+
+    let _ = html.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = html.push_str("Content-Type: text/html; charset=utf-8\r\n");
+    let _ = html.push_str("Connection: close\r\n\r\n");
+
+    let _ = html.push_str("<!DOCTYPE html><html><head>");
+    let _ = html.push_str("<title>Sockets</title>");
+    let _ = html.push_str("<meta name='viewport' content='width=device-width, initial-scale=1'>");
+    let _ = html.push_str("<link rel='stylesheet' href='/style.css'>");
+    let _ = html.push_str("</head><body>");
+    let _ = html.push_str("<p><a href='/'>← Back</a> | <a href='/api/net'>JSON</a></p>");
+    let _ = html.push_str("<p>HTTP listeners:");
+    for listener in HTTP_LISTENERS.lock(|l| l.get()).into_iter().flatten() {
+        let _ = match listener.serves {
+            listener::Serves::Pages => core::write!(html, " tcp {} pages;", listener.port),
+            listener::Serves::RedirectTo(to) => core::write!(html, " tcp {} redirects to {};", listener.port, to),
+        };
+    }
+    html.pop();
+    let http = CONFIG.lock(|c| c.borrow().http);
+    let planned = listener::plan(http.port as u16, http.port2 as u16, http.role2);
+    if HTTP_LISTENERS.lock(|l| l.get()) != planned {
+        let _ = html.push_str(" (saved ports apply after reboot)");
+    }
+    let _ = html.push_str("</p>");
+    if CONFIG.lock(|c| c.borrow().services.enabled) {
+        let _ = html.push_str("<p>Test services:");
+        for service in test_services::Service::ALL {
+            let stats = SERVICE_COUNTERS.get(service);
+            let _ = core::write!(
+                html,
+                " {} (tcp {}) {} connections, {} bytes in, {} out;",
+                service.as_str(),
+                service.port(),
+                stats.connections,
+                stats.rx_bytes,
+                stats.tx_bytes
+            );
+        }
+        html.pop();
+        let _ = html.push_str("</p>");
+    } else {
+        let _ = html.push_str("<p>Test services (echo, discard) are off in the settings.</p>");
+    }
+    let _ = html.push_str("<table><tr><th>Owner</th><th>Proto</th><th>Local</th><th>Remote</th>");
+    let _ = html.push_str("<th>State</th><th>For</th><th>Rx</th><th>Tx</th></tr>");
+
+    for entry in net_snapshot() {
+        let _ = html.push_str(if entry.suspicious(now) { "<tr class='suspicious'>" } else { "<tr>" });
+        let _ = core::write!(html, "<td>{}</td><td>{}</td>", entry.owner, entry.kind.as_str());
+        let _ = core::write!(html, "<td>*:{}</td><td>", entry.local_port);
+        match entry.remote {
+            Some((ip, port)) => push_endpoint(&mut html, ip, port),
+            None => {
+                let _ = html.push_str("*");
+            }
+        }
+        let _ = core::write!(
+            html,
+            "</td><td>{}</td><td>{}s</td><td>{}</td><td>{}</td></tr>",
+            entry.state.as_str(),
+            now.saturating_sub(entry.state_since_ms) / 1000,
+            entry.rx_bytes,
+            entry.tx_bytes
+        );
+    }
+
+    let _ = html.push_str("</table>");
+
+    push_socket_budget(&mut html);
+    push_arp_table(&mut html, now);
+    push_modem_sockets(&mut html, now);
+    #[cfg(feature = "proxy")]
+    push_forward_table(&mut html, now);
+    let _ = html.push_str("</body></html>");
+
+    http::set_content_length(&mut html);
+    html
+}
+
+fn arp_rows(now: u64) -> heapless::Vec<arp::Row, { arp::CAPACITY + arp::MAX_PINS }> {
+    let pins = CONFIG.lock(|c| arp::parse_pins(&c.borrow().arp.pins)).unwrap_or_default();
+    NEIGHBORS.lock(|n| n.borrow().rows(&pins, now))
+}
+
+// WiFi 侧: 客户端的 IP 和 MAC
+fn push_arp_table<const N: usize>(html: &mut heapless::String<N>, now: u64) {
+    let _ = html.push_str("<h2>WiFi clients (ARP)</h2>");
+    let rows = arp_rows(now);
+    if rows.is_empty() {
+        let _ = html.push_str("<p>No client heard from in the last 10 minutes.</p>");
+        return;
+    }
+    let _ = html.push_str("<table><tr><th>IP</th><th>MAC</th><th>State</th><th>Last heard</th><th>Known for</th></tr>");
+    for row in &rows {
+        let _ = html.push_str("<tr><td>");
+        arp::write_ip(html, row.ip);
+        let _ = html.push_str("</td><td>");
+        arp::write_mac(html, row.mac);
+        let _ = core::write!(html, "</td><td>{}</td>", row.state.as_str());
+        match row.heard {
+            Some(heard) => {
+                let _ = core::write!(
+                    html,
+                    "<td>{} s ago</td><td>{} s</td></tr>",
+                    now.saturating_sub(heard.last_seen_ms) / 1000,
+                    now.saturating_sub(heard.first_seen_ms) / 1000
+                );
+            }
+            None => {
+                let _ = html.push_str("<td>never</td><td></td></tr>");
+            }
+        }
+    }
+    let _ = html.push_str("</table>");
+}
+
+// The newest few actions: "fetch (web) done; sms (input) failed: modem busy"
+fn push_recent_actions<const N: usize>(html: &mut heapless::String<N>) {
+    ACTIONS.lock(|a| {
+        for (i, record) in a.borrow().iter().take(3).enumerate() {
+            let separator = if i > 0 { "; " } else { "" };
+            let _ = core::write!(
+                html,
+                "{}{} ({}) <strong>{}</strong>",
+                separator,
+                record.action.as_str(),
+                record.source.as_str(),
+                record.status.as_str()
+            );
+            if !record.detail.is_empty() {
+                let _ = html.push_str(": ");
+                push_html_escaped(html, &record.detail);
+            }
+        }
+    });
+}
+
+// /api/actions: the action log, newest first
+//   [{"id","age_secs","source","action","digest","request_id"?,"status","detail"},...]
+fn format_actions_json() -> heapless::String<10240> {
+    let mut out = heapless::String::new();
+    let now = Instant::now().as_millis();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = out.push('[');
+    ACTIONS.lock(|a| {
+        for (i, record) in a.borrow().iter().enumerate() {
+            if i > 0 {
+                let _ = out.push(',');
+            }
+            let mut digest = heapless::String::<8>::new();
+            let _ = core::write!(digest, "{:08x}", record.digest);
+            let mut obj = json::Object::new(&mut out);
+            obj.u32("id", record.id)
+                .u32("age_secs", (now.saturating_sub(record.at_ms) / 1000) as u32)
+                .str("source", record.source.as_str())
+                .str("action", record.action.as_str())
+                .str("digest", &digest);
+            if let Some(id) = record.request_id {
+                obj.u32("request_id", id);
+            }
+            obj.str("status", record.status.as_str()).str("detail", &record.detail);
+            obj.finish();
+        }
+    });
+    let _ = out.push(']');
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// /api/lockstats: {"budget_ms","sites":[{"site","count","max_us","ewma_us",
+// "over_budget","budgeted"},...]}
+fn format_lock_stats_json() -> heapless::String<2048> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let budget_ms = CONFIG.lock(|c| c.borrow().http.lock_budget_ms);
+    let _ = core::write!(out, "{{\"budget_ms\":{},\"sites\":[", budget_ms);
+    let table = LOCK_STATS.lock(|s| s.borrow().entries());
+    for (i, (site, entry)) in lock_stats::Site::ALL.into_iter().zip(table).enumerate() {
+        if i > 0 {
+            let _ = out.push(',');
+        }
+        let mut obj = json::Object::new(&mut out);
+        obj.str("site", site.as_str())
+            .u32("count", entry.count)
+            .u32("max_us", entry.max_us)
+            .u32("ewma_us", entry.ewma_us)
+            .u32("over_budget", entry.over_budget)
+            .bool("budgeted", site.budgeted());
+        obj.finish();
+    }
+    let _ = out.push_str("]}");
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// 登记的环形缓冲区: 新的缓冲区在 rings::Buffer 里加一项, 再在这两个函数里接上
+async fn ring_usage(buffer: rings::Buffer) -> rings::Usage {
+    use rings::Ring as _;
+    match buffer {
+        rings::Buffer::ModemLog => held(lock_stats::Site::ModemLogRead, MODEM_LOG.lock().await).usage(),
+        rings::Buffer::ModemLogQueue => MODEM_LOG_QUEUE.lock(|q| q.borrow().usage()),
+        rings::Buffer::Uart1Log => held(lock_stats::Site::Uart1Log, UART1_LOG.lock().await).usage(),
+        rings::Buffer::Capture => CAPTURE.lock(|c| c.borrow().usage()),
+        rings::Buffer::ResponseArchive => RESPONSE_ARCHIVE.lock(|a| a.borrow().usage()),
+    }
+}
+
+async fn reset_ring(buffer: rings::Buffer) {
+    use rings::Ring as _;
+    match buffer {
+        rings::Buffer::ModemLog => held(lock_stats::Site::ModemLogRecord, MODEM_LOG.lock().await).reset(),
+        rings::Buffer::ModemLogQueue => MODEM_LOG_QUEUE.lock(|q| q.borrow_mut().reset()),
+        rings::Buffer::Uart1Log => held(lock_stats::Site::Uart1Log, UART1_LOG.lock().await).reset(),
+        rings::Buffer::Capture => CAPTURE.lock(|c| c.borrow_mut().reset()),
+        rings::Buffer::ResponseArchive => RESPONSE_ARCHIVE.lock(|a| a.borrow_mut().reset()),
+    }
+    info!("Buffer {} reset", buffer.as_str());
+    bump_state_generation();
+}
+
+async fn format_buffers_html() -> heapless::String<4096> {
+    let mut html = heapless::String::new();
+    push_html_head(&mut html, "200 OK", None);
+    push_page_header(&mut html, "/debug/buffers", "Buffers", Refresh::Off);
+    let _ = html.push_str("<h1>🧺 Buffers</h1>");
+    let _ = html.push_str("<p><a href='/api/buffers'>JSON</a></p>");
+    let _ = html.push_str("<table><tr><th>Buffer</th><th>Used</th><th>Written</th><th>Dropped</th>");
+    let _ = html.push_str("<th>Wraps</th><th></th></tr>");
+    for buffer in rings::Buffer::ALL {
+        let usage = ring_usage(buffer).await;
+        let _ = core::write!(
+            html,
+            "<tr><td>{}</td><td>{}/{}</td><td>{}</td><td>{}</td><td>{}</td>",
+            buffer.as_str(),
+            usage.used,
+            usage.capacity,
+            usage.written,
+            usage.dropped,
+            usage.wraps
+        );
+        let _ = core::write!(
+            html,
+            "<td><form method='post' action='/api/buffers/reset?name={}'>\
+             <button type='submit' class='btn-at'>Reset</button></form></td></tr>",
+            buffer.as_str()
+        );
+    }
+    let _ = html.push_str("</table>");
+    let _ = html.push_str("<p>Bytes, counted since boot or the last reset. Wraps are turns of a ring, ");
+    let _ = html.push_str("or entries evicted from the response archive. A reset empties the buffer.</p>");
+    let _ = html.push_str("</div></body></html>");
+
+    http::set_content_length(&mut html);
+    html
+}
+
+// /api/buffers: [{"name","capacity","used","written","dropped","wraps"},...]
+async fn format_buffers_json() -> heapless::String<1024> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Cache-Control: no-store\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = out.push('[');
+    for (i, buffer) in rings::Buffer::ALL.into_iter().enumerate() {
+        let usage = ring_usage(buffer).await;
+        if i > 0 {
+            let _ = out.push(',');
+        }
+        let mut obj = json::Object::new(&mut out);
+        obj.str("name", buffer.as_str())
+            .u32("capacity", usage.capacity as u32)
+            .u32("used", usage.used as u32)
+            .u32("written", usage.written)
+            .u32("dropped", usage.dropped)
+            .u32("wraps", usage.wraps);
+        obj.finish();
+    }
+    let _ = out.push(']');
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// /api/arp: [{"ip","mac","state","last_heard_secs","known_secs"},...]; the
+// two ages are null for a pin not heard from since boot
+fn format_arp_json() -> heapless::String<2048> {
+    let mut out = heapless::String::new();
+    let now = Instant::now().as_millis();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Cache-Control: no-store\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = out.push('[');
+    for (i, row) in arp_rows(now).iter().enumerate() {
+        if i > 0 {
+            let _ = out.push(',');
+        }
+        let mut ip = heapless::String::<16>::new();
+        arp::write_ip(&mut ip, row.ip);
+        let mut mac = heapless::String::<17>::new();
+        arp::write_mac(&mut mac, row.mac);
+
+        let mut obj = json::Object::new(&mut out);
+        obj.str("ip", &ip).str("mac", &mac).str("state", row.state.as_str());
+        match row.heard {
+            Some(heard) => {
+                obj.u32("last_heard_secs", (now.saturating_sub(heard.last_seen_ms) / 1000) as u32)
+                    .u32("known_secs", (now.saturating_sub(heard.first_seen_ms) / 1000) as u32);
+            }
+            None => {
+                obj.raw("last_heard_secs", "null").raw("known_secs", "null");
+            }
+        }
+        obj.finish();
+    }
+    let _ = out.push(']');
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// What uses a modem connect ID
+fn modem_socket_owner(id: u8) -> &'static str {
+    #[cfg(feature = "proxy")]
+    if let Some(&name) = id.checked_sub(forward::FIRST_CONNECT_ID).and_then(|i| forward::NAMES.get(i as usize)) {
+        return name;
+    }
+    if id == fetch::CONNECT_ID { "fetch" } else { "" }
+}
+
+// 蜂窝侧: 每个连接 ID 的当前连接和累计统计, 以及最近的事件
+fn push_modem_sockets<const N: usize>(html: &mut heapless::String<N>, now: u64) {
+    let _ = html.push_str("<h2>Modem connections</h2>");
+    MODEM_SOCKETS.lock(|m| {
+        let tracker = m.borrow();
+        let used = (0..modem_sockets::MAX_IDS as u8).filter(|&id| tracker.totals(id).opened > 0);
+        if used.clone().next().is_none() {
+            let _ = html.push_str("<p>No modem connections since boot.</p>");
+            return;
+        }
+        let _ = html.push_str("<table><tr><th>ID</th><th>Used by</th><th>Now</th><th>Sent</th><th>Received</th>");
+        let _ = html.push_str("<th>Connections</th><th>Closed by us / peer / error</th><th>Avg connect</th>");
+        let _ = html.push_str("<th>Avg lifetime</th><th>Longest</th></tr>");
+        for id in used {
+            let totals = tracker.totals(id);
+            let _ = core::write!(html, "<tr><td>{}</td><td>{}</td><td>", id, modem_socket_owner(id));
+            match tracker.open(id) {
+                Some(open) => {
+                    push_html_escaped(html, &open.host);
+                    let _ = core::write!(html, ":{}, open {} s", open.port, now.saturating_sub(open.since_ms) / 1000);
+                }
+                None => {
+                    let _ = html.push_str("closed");
+                }
+            }
+            let _ = core::write!(
+                html,
+                "</td><td>{}</td><td>{}</td><td>{}</td><td>{} / {} / {}</td><td>{} ms</td><td>{} s</td><td>{} s</td></tr>",
+                totals.sent,
+                totals.received,
+                totals.opened,
+                totals.closed[modem_sockets::CloseReason::Us as usize],
+                totals.closed[modem_sockets::CloseReason::Peer as usize],
+                totals.closed[modem_sockets::CloseReason::Error as usize],
+                totals.average_connect_ms(),
+                totals.average_lifetime_ms() / 1000,
+                totals.longest_ms / 1000
+            );
+        }
+        let _ = html.push_str("</table><h3>Recent events</h3><pre>");
+        for record in tracker.log() {
+            let _ = core::write!(html, "{:>6} s ago  #{} ", now.saturating_sub(record.at_ms) / 1000, record.id);
+            match &record.event {
+                modem_sockets::Event::Opened { host, port, connect_ms } => {
+                    let _ = html.push_str("opened ");
+                    push_html_escaped(html, host);
+                    let _ = core::write!(html, ":{} in {} ms", port, connect_ms);
+                }
+                modem_sockets::Event::Sent { bytes } => {
+                    let _ = core::write!(html, "sent {} bytes", bytes);
+                }
+                modem_sockets::Event::Received { bytes } => {
+                    let _ = core::write!(html, "received {} bytes", bytes);
+                }
+                modem_sockets::Event::Closed {
+                    reason,
+                    sent,
+                    received,
+                    lifetime_ms,
+                } => {
+                    let _ = core::write!(
+                        html,
+                        "closed {} after {} s, {} bytes sent, {} received",
+                        reason.as_str(),
+                        lifetime_ms / 1000,
+                        sent,
+                        received
+                    );
+                }
+            }
+            let _ = html.push('\n');
+        }
+        let _ = html.push_str("</pre>");
+    });
+}
+
+// 套接字预算: 各子系统申请的数量和正在使用的数量
+fn push_socket_budget<const N: usize>(html: &mut heapless::String<N>) {
+    let _ = core::write!(
+        html,
+        "<h2>Socket budget</h2><p>{} of {} stack sockets claimed, {} in use, {} failed accepts.</p>",
+        SOCKET_BUDGET.total_claimed(),
+        socket_budget::STACK_SOCKETS,
+        SOCKET_BUDGET.total_in_use(),
+        SOCKET_BUDGET.accept_failures()
+    );
+    if SOCKET_BUDGET.degraded() {
+        let _ = html.push_str("<div class='warning'>Degraded: a subsystem did not fit and is not running.</div>");
+    }
+    let _ = html.push_str("<table><tr><th>Subsystem</th><th>Claimed</th><th>In use</th></tr>");
+    for &subsystem in socket_budget::Subsystem::ALL {
+        let _ = core::write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            subsystem.as_str(),
+            SOCKET_BUDGET.claimed(subsystem),
+            SOCKET_BUDGET.in_use(subsystem)
+        );
+    }
+    let _ = html.push_str("</table>");
+}
+
+// 转发规则和各自连接的实时状态
+#[cfg(feature = "proxy")]
+fn push_forward_table<const N: usize>(html: &mut heapless::String<N>, now: u64) {
+    let rules = CONFIG.lock(|c| c.borrow().forwards.clone());
+    let links = FORWARD_LINKS.lock(|l| *l.borrow());
+    let _ = html.push_str("<h2>Forwards</h2><p><a href='/config/forwards'>Edit</a></p>");
+    if rules.iter().all(|rule| rule.listen_port == 0) {
+        let _ = html.push_str("<p>No forwards configured.</p>");
+        return;
+    }
+    let _ = html.push_str("<table><tr><th>Rule</th><th>Listen</th><th>Destination</th><th>State</th><th>For</th>");
+    let _ = html.push_str("<th>Connections</th><th>Refused</th><th>Up</th><th>Down</th><th>Last error</th></tr>");
+    for (index, (rule, link)) in rules.iter().zip(links.iter()).enumerate() {
+        if rule.listen_port == 0 {
+            continue;
+        }
+        let _ = core::write!(html, "<tr><td>{}</td><td>*:{}</td><td>", forward::NAMES[index], rule.listen_port);
+        push_html_escaped(html, &rule.host);
+        let _ = core::write!(
+            html,
+            ":{}</td><td>{}</td><td>{}s</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            rule.port,
+            link.state.as_str(),
+            now.saturating_sub(link.since_ms) / 1000,
+            link.connections,
+            link.refused,
+            link.to_remote,
+            link.from_remote,
+            link.last_error.unwrap_or("")
+        );
+    }
+    let _ = html.push_str("</table>");
+}
+
+fn format_net_json() -> heapless::String<2048> {
+    let mut out = heapless::String::new();
+    let now = Instant::now().as_millis();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = out.push('[');
+    for (i, entry) in net_snapshot().iter().enumerate() {
+        if i > 0 {
+            let _ = out.push(',');
+        }
+        let mut remote = heapless::String::<24>::new();
+        if let Some((ip, port)) = entry.remote {
+            push_endpoint(&mut remote, ip, port);
+        }
+
+        let mut obj = json::Object::new(&mut out);
+        obj.str("owner", entry.owner)
+            .str("kind", entry.kind.as_str())
+            .u32("local_port", entry.local_port as u32)
+            .str("remote", &remote)
+            .str("state", entry.state.as_str())
+            .u32("state_secs", (now.saturating_sub(entry.state_since_ms) / 1000) as u32)
+            .u32("rx_bytes", entry.rx_bytes)
+            .u32("tx_bytes", entry.tx_bytes)
+            .bool("suspicious", entry.suspicious(now));
+        obj.finish();
+    }
+    let _ = out.push(']');
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// 启动进度: 仍在启动或启动失败时在页面顶部提示, 并列出各阶段耗时
+fn push_boot_html<const N: usize>(html: &mut heapless::String<N>) {
+    BOOT.lock(|b| {
+        let boot = b.borrow();
+        let critical = TASKS.lock(|t| t.borrow().critical_failed().copied());
+        if let Some(stage) = boot.failed()
+            && let boot::Outcome::Failed(reason) = stage.outcome
+        {
+            let _ = core::write!(html, "<div class='warning error'>❌ Boot stage {} failed: {}</div>", stage.name, reason);
+        } else if let Some(task) = critical {
+            let error = task.error.unwrap_or("");
+            let _ = core::write!(html, "<div class='warning error'>❌ Task {} not started: {}</div>", task.name, error);
+        } else if let Some(stage) = boot.running() {
+            let _ = core::write!(html, "<div class='warning'>⏳ Starting… ({})</div>", stage.name);
+        }
+
+        let _ = html.push_str("<details><summary>⏱️ Boot</summary>");
+        for stage in boot.stages() {
+            let mark = match stage.outcome {
+                boot::Outcome::Running => "⏳",
+                boot::Outcome::Done => "✅",
+                boot::Outcome::Failed(_) => "❌",
+            };
+            let _ = core::write!(html, "<div class='step'>{} {} {} ms</div>", mark, stage.name, stage.duration_ms);
+        }
+        // 页面上只列出没有启动的任务, 完整列表见 /api/boot
+        TASKS.lock(|t| {
+            let tasks = t.borrow();
+            let started: u32 = tasks.tasks().iter().map(|t| t.started as u32).sum();
+            let _ = core::write!(html, "<div class='step'>✅ {} tasks started</div>", started);
+            for task in tasks.tasks().iter().filter(|t| t.failed > 0) {
+                let error = task.error.unwrap_or("");
+                let _ = core::write!(
+                    html,
+                    "<div class='step'>❌ {} ×{} not started: {}</div>",
+                    task.name,
+                    task.failed,
+                    error
+                );
+            }
+        });
+        let _ = html.push_str("</details>");
+    });
+}
+
+fn format_boot_json() -> heapless::String<1024> {
+    let mut out = heapless::String::new();
+    let _ = out.push('[');
+    BOOT.lock(|b| {
+        for (i, stage) in b.borrow().stages().iter().enumerate() {
+            if i > 0 {
+                let _ = out.push(',');
+            }
+            let (outcome, reason) = match stage.outcome {
+                boot::Outcome::Running => ("running", ""),
+                boot::Outcome::Done => ("done", ""),
+                boot::Outcome::Failed(reason) => ("failed", reason),
+            };
+            let mut obj = json::Object::new(&mut out);
+            obj.str("stage", stage.name)
+                .str("outcome", outcome)
+                .u32("started_ms", stage.started_ms)
+                .u32("duration_ms", stage.duration_ms);
+            if !reason.is_empty() {
+                obj.str("error", reason);
+            }
+            obj.finish();
+        }
+    });
+    let _ = out.push(']');
+    out
+}
+
+// 启动报告: 各阶段, 以及每个任务启动了几个, 几个没有启动
+fn format_boot_report_json() -> heapless::String<2048> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Cache-Control: no-store\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = core::write!(out, "{{\"stages\":{},\"tasks\":[", format_boot_json());
+    TASKS.lock(|t| {
+        for (i, task) in t.borrow().tasks().iter().enumerate() {
+            if i > 0 {
+                let _ = out.push(',');
+            }
+            let mut obj = json::Object::new(&mut out);
+            obj.str("task", task.name)
+                .bool("critical", task.critical)
+                .u32("started", task.started as u32)
+                .u32("failed", task.failed as u32);
+            if let Some(error) = task.error {
+                obj.str("error", error);
+            }
+            obj.finish();
+        }
+    });
+    let _ = out.push_str("]}");
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// Prometheus 文本格式
+fn format_metrics() -> heapless::String<7168> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: text/plain; version=0.0.4\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = out.push_str("# TYPE uptime_seconds gauge\n");
+    let _ = core::writeln!(out, "uptime_seconds {}", Instant::now().as_secs());
+    let _ = out.push_str("# TYPE uart_bytes_total counter\n");
+    let _ = core::writeln!(out, "uart_bytes_total{{direction=\"tx\"}} {}", UART_TX_BYTES.load(Ordering::Relaxed));
+    let _ = core::writeln!(out, "uart_bytes_total{{direction=\"rx\"}} {}", UART_RX_BYTES.load(Ordering::Relaxed));
+    RX_AUDIT.lock(|a| {
+        let audit = a.borrow();
+        let _ = out.push_str("# TYPE uart_rx_delivered_bytes_total counter\n");
+        for sink in rx_audit::Sink::ALL {
+            let bytes = audit.delivered(sink);
+            let _ = core::writeln!(out, "uart_rx_delivered_bytes_total{{sink=\"{}\"}} {}", sink.as_str(), bytes);
+        }
+        let _ = out.push_str("# TYPE uart_rx_lost_bytes_total counter\n");
+        for loss in rx_audit::Loss::ALL {
+            let bytes = audit.lost(loss);
+            let _ = core::writeln!(out, "uart_rx_lost_bytes_total{{reason=\"{}\"}} {}", loss.as_str(), bytes);
+        }
+        let _ = out.push_str("# TYPE uart_rx_accounting_discrepancies_total counter\n");
+        let _ = core::writeln!(out, "uart_rx_accounting_discrepancies_total {}", audit.discrepancies());
+    });
+    let _ = out.push_str("# TYPE http_requests_total counter\n");
+    let _ = core::writeln!(out, "http_requests_total {}", REQUEST_COUNT.load(Ordering::Relaxed));
+    let _ = out.push_str("# TYPE http_throttled_total counter\n");
+    let _ = core::writeln!(out, "http_throttled_total{{reason=\"rate\"}} {}", THROTTLED_REQUESTS.load(Ordering::Relaxed));
+    let _ = core::writeln!(
+        out,
+        "http_throttled_total{{reason=\"connections\"}} {}",
+        THROTTLED_CONNECTIONS.load(Ordering::Relaxed)
+    );
+    let _ = out.push_str("# TYPE http_reaped_total counter\n");
+    let _ = core::writeln!(out, "http_reaped_total {}", REAPED_CONNECTIONS.load(Ordering::Relaxed));
+    let _ = out.push_str("# TYPE http_closes_total counter\n");
+    for close in conn_close::Close::ALL {
+        let _ = core::writeln!(out, "http_closes_total{{how=\"{}\"}} {}", close.as_str(), CLOSES.get(close));
+    }
+    let (dropped, depth, max_depth, capacity) = MODEM_LOG_QUEUE.lock(|q| {
+        let q = q.borrow();
+        (q.dropped_total, q.depth(), q.max_depth, q.capacity())
+    });
+    let _ = out.push_str("# TYPE modem_log_dropped_bytes_total counter\n");
+    let _ = core::writeln!(out, "modem_log_dropped_bytes_total {}", dropped);
+    let _ = out.push_str("# TYPE modem_log_queue_depth_bytes gauge\n");
+    let _ = core::writeln!(out, "modem_log_queue_depth_bytes {}", depth);
+    let _ = out.push_str("# TYPE modem_log_queue_max_depth_bytes gauge\n");
+    let _ = core::writeln!(out, "modem_log_queue_max_depth_bytes {}", max_depth);
+    let _ = out.push_str("# TYPE modem_log_queue_capacity_bytes gauge\n");
+    let _ = core::writeln!(out, "modem_log_queue_capacity_bytes {}", capacity);
+    let _ = out.push_str("# TYPE net_sockets gauge\n");
+    let _ = core::writeln!(out, "net_sockets {}", net_snapshot().len());
+    let _ = out.push_str("# TYPE socket_pool_in_use gauge\n");
+    let _ = core::writeln!(out, "socket_pool_in_use {}", SOCKET_POOL.in_use());
+    let _ = out.push_str("# TYPE http_busy_total counter\n");
+    let _ = core::writeln!(out, "http_busy_total {}", BUSY_RESPONSES.load(Ordering::Relaxed));
+    let _ = out.push_str("# TYPE http_page_too_large_total counter\n");
+    let _ = core::writeln!(out, "http_page_too_large_total {}", PAGES_TOO_LARGE.load(Ordering::Relaxed));
+    let _ = out.push_str("# TYPE http_page_sections_omitted_total counter\n");
+    let _ = core::writeln!(out, "http_page_sections_omitted_total {}", SECTIONS_OMITTED.load(Ordering::Relaxed));
+    let _ = out.push_str("# TYPE http_timeouts_total counter\n");
+    let _ = core::writeln!(out, "http_timeouts_total{{phase=\"headers\"}} {}", HEADER_TIMEOUTS.load(Ordering::Relaxed));
+    let _ = core::writeln!(out, "http_timeouts_total{{phase=\"request\"}} {}", REQUEST_TIMEOUTS.load(Ordering::Relaxed));
+    let _ = core::writeln!(out, "http_timeouts_total{{phase=\"write\"}} {}", WRITE_STALLS.load(Ordering::Relaxed));
+    let _ = out.push_str("# TYPE http_too_large_total counter\n");
+    let oversized = [("headers", &OVERSIZED_HEADERS), ("body", &OVERSIZED_BODIES)];
+    for (part, count) in oversized {
+        let _ = core::writeln!(out, "http_too_large_total{{part=\"{}\"}} {}", part, count.load(Ordering::Relaxed));
+    }
+    FETCH_LATENCY.lock(|l| {
+        let latency = l.borrow();
+        let _ = out.push_str("# TYPE fetch_duration_seconds histogram\n");
+        for (le, count) in latency.cumulative() {
+            let _ = match le {
+                Some(ms) => core::writeln!(out, "fetch_duration_seconds_bucket{{le=\"{}\"}} {}", ms / 1000, count),
+                None => core::writeln!(out, "fetch_duration_seconds_bucket{{le=\"+Inf\"}} {}", count),
+            };
+        }
+        let sum_ms = latency.sum_ms();
+        let _ = core::writeln!(out, "fetch_duration_seconds_sum {}.{:03}", sum_ms / 1000, sum_ms % 1000);
+        let _ = core::writeln!(out, "fetch_duration_seconds_count {}", latency.count());
+    });
+    AT_RTT.lock(|r| {
+        let rtt = r.borrow();
+        let now = Instant::now().as_millis();
+        if let Some(average) = rtt.average_ms() {
+            let _ = out.push_str("# TYPE modem_at_rtt_ms gauge\n");
+            let _ = core::writeln!(out, "modem_at_rtt_ms{{stat=\"ewma\"}} {}", average);
+            let _ = core::writeln!(out, "modem_at_rtt_ms{{stat=\"last\"}} {}", rtt.last_ms());
+            let _ = core::writeln!(out, "modem_at_rtt_ms{{stat=\"max_1h\"}} {}", rtt.max_ms(now));
+        }
+        let _ = out.push_str("# TYPE modem_at_rtt_samples_total counter\n");
+        let _ = core::writeln!(out, "modem_at_rtt_samples_total {}", rtt.count());
+    });
+    let _ = out.push_str("# TYPE http_status_cache_total counter\n");
+    for (page, cache) in [
+        ("overview", STATUS_PAGE_CACHE.lock(|c| (c.borrow().hits(), c.borrow().misses()))),
+        ("status_json", STATUS_JSON_CACHE.lock(|c| (c.borrow().hits(), c.borrow().misses()))),
+    ] {
+        let _ = core::writeln!(out, "http_status_cache_total{{page=\"{}\",result=\"hit\"}} {}", page, cache.0);
+        let _ = core::writeln!(out, "http_status_cache_total{{page=\"{}\",result=\"miss\"}} {}", page, cache.1);
+    }
+    let _ = out.push_str("# TYPE wifi_ap_sleeps_total counter\n");
+    let _ = core::writeln!(out, "wifi_ap_sleeps_total {}", AP_SLEEP.lock(|a| a.borrow().sleeps()));
+    WEBHOOKS.lock(|w| {
+        let queue = w.borrow();
+        let _ = out.push_str("# TYPE webhook_notifications_total counter\n");
+        for (result, count) in [
+            ("sent", queue.sent),
+            ("failed", queue.failed),
+            ("dropped", queue.dropped),
+            ("suppressed", queue.suppressed),
+        ] {
+            let _ = core::writeln!(out, "webhook_notifications_total{{result=\"{}\"}} {}", result, count);
+        }
+        let _ = out.push_str("# TYPE webhook_queue_depth gauge\n");
+        let _ = core::writeln!(out, "webhook_queue_depth {}", queue.len());
+    });
+    let (bytes, erases) = flash_wear();
+    let _ = out.push_str("# TYPE flash_bytes_written_total counter\n");
+    let _ = core::writeln!(out, "flash_bytes_written_total {}", bytes);
+    let _ = out.push_str("# TYPE flash_sectors_erased_total counter\n");
+    let _ = core::writeln!(out, "flash_sectors_erased_total {}", erases);
+    MODEM_OPS.lock(|q| {
+        let queue = q.borrow();
+        let _ = out.push_str("# TYPE modem_queue_depth gauge\n");
+        let _ = core::writeln!(out, "modem_queue_depth {}", queue.len());
+        let _ = out.push_str("# TYPE modem_ops_busy_total counter\n");
+        let _ = core::writeln!(out, "modem_ops_busy_total{{reason=\"expired\"}} {}", queue.expired);
+        let _ = core::writeln!(out, "modem_ops_busy_total{{reason=\"queue_full\"}} {}", queue.rejected);
+    });
+    let _ = out.push_str("# TYPE uart_errors_total counter\n");
+    for kind in uart_errors::Kind::ALL {
+        let _ = core::writeln!(out, "uart_errors_total{{kind=\"{}\"}} {}", kind.as_str(), UART_ERRORS.get(kind));
+    }
+    let _ = out.push_str("# TYPE uart_tx_failures_total counter\n");
+    let _ = core::writeln!(out, "uart_tx_failures_total{{reason=\"stalled\"}} {}", UART_TX_STALLS.load(Ordering::Relaxed));
+    let _ = core::writeln!(out, "uart_tx_failures_total{{reason=\"error\"}} {}", UART_TX_ERRORS.load(Ordering::Relaxed));
+    FETCH_LADDER.lock(|l| {
+        let ladder = l.borrow();
+        let _ = out.push_str("# TYPE fetch_failures_consecutive gauge\n");
+        let _ = core::writeln!(out, "fetch_failures_consecutive {}", ladder.failures);
+        let _ = out.push_str("# TYPE fetch_recovery_steps_total counter\n");
+        for step in escalation::Step::ALL {
+            let _ = core::writeln!(out, "fetch_recovery_steps_total{{step=\"{}\"}} {}", step.as_str(), ladder.counts[step as usize]);
+        }
+    });
+    let keep_warm = KEEP_WARM.lock(|k| k.get());
+    let _ = out.push_str("# TYPE keep_warm_lookups_total counter\n");
+    let _ = core::writeln!(out, "keep_warm_lookups_total{{result=\"ok\"}} {}", keep_warm.sent - keep_warm.failed);
+    let _ = core::writeln!(out, "keep_warm_lookups_total{{result=\"failed\"}} {}", keep_warm.failed);
+    let _ = out.push_str("# TYPE keep_warm_reactivations_total counter\n");
+    let _ = core::writeln!(out, "keep_warm_reactivations_total {}", keep_warm.reactivations);
+    let _ = out.push_str("# TYPE keep_warm_estimated_bytes_total counter\n");
+    let _ = core::writeln!(out, "keep_warm_estimated_bytes_total {}", keep_warm.bytes);
+    let _ = out.push_str("# TYPE uart_tx_max_drain_ms gauge\n");
+    let _ = core::writeln!(out, "uart_tx_max_drain_ms {}", UART_TX_MAX_DRAIN_MS.load(Ordering::Relaxed));
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// 最近几次获取的耗时摘要, 还没有完成过获取时为 null
+fn format_latency_json() -> heapless::String<128> {
+    let mut out = heapless::String::new();
+    match FETCH_LATENCY.lock(|l| l.borrow().summary()) {
+        Some(summary) => {
+            let mut obj = json::Object::new(&mut out);
+            obj.u32("count", summary.count)
+                .u32("p50_ms", summary.p50_ms)
+                .u32("p95_ms", summary.p95_ms)
+                .u32("max_ms", summary.max_ms);
+            obj.finish();
+        }
+        None => {
+            let _ = out.push_str("null");
+        }
+    }
+    out
+}
+
+// `end`: log offset just past the tail shown, /live.js polls /api/log from there
+fn format_log_html(log: &[u8], end: u32) -> Result<heapless::String<6144>, http::BufferFull> {
+    let mut html = heapless::String::new();
+    push_html_head(&mut html, "200 OK", None);
+    push_page_header(&mut html, "/log", "Log", Refresh::Live);
+
+    let _ = html.push_str("<p><a href='/log.txt'>⬇️ Download log.txt</a> | 🔎 <a href='/logview'>Log viewer</a></p>");
+    push_capture_controls(&mut html);
+    push_checkpoint_html(&mut html);
+    let _ = core::write!(html, "<pre id='log' data-log='/api/log' data-end='{}'>", end);
+    let sections = [page_budget::LOG_TAIL];
+    let mut budget = page_budget::Budget::new(&sections, html.capacity() - http::PAGE_HEADROOM);
+    push_budgeted(&mut html, &mut budget, 0, |html| log_text::push(html, log, true));
+    let _ = html.push_str("</pre>");
+    let _ = html.push_str("</div></body></html>");
+
+    http::finish_page(&mut html)?;
+    Ok(html)
+}
+
+async fn serve_static(
+    socket: &mut Conn<'_, '_>,
+    content_type: &str,
+    raw: &[u8],
+    gzipped: &[u8],
+    gzip: bool,
+    range: Option<http::ByteRange>,
+) {
+    // Range 请求总是针对未压缩的内容
+    let gzip = gzip && range.is_none();
+    let body = if gzip { gzipped } else { raw };
+
+    let mut header = heapless::String::<320>::new();
+    let body = match http::check_range(range, 0, body.len() as u32) {
+        RangeCheck::Full => {
+            let _ = header.push_str("HTTP/1.1 200 OK\r\n");
+            body
+        }
+        RangeCheck::Partial { first, last } => {
+            let _ = header.push_str("HTTP/1.1 206 Partial Content\r\n");
+            let _ = core::write!(header, "Content-Range: bytes {}-{}/{}\r\n", first, last, body.len());
+            &body[first as usize..=last as usize]
+        }
+        RangeCheck::Unsatisfiable => {
+            let _ = header.push_str("HTTP/1.1 416 Range Not Satisfiable\r\n");
+            let _ = core::write!(header, "Content-Range: bytes */{}\r\n", body.len());
+            &body[..0]
+        }
+    };
+
+    let _ = header.push_str("Content-Type: ");
+    let _ = header.push_str(content_type);
+    let _ = core::write!(header, "\r\nContent-Length: {}\r\n", body.len());
+    if gzip {
+        let _ = header.push_str("Content-Encoding: gzip\r\n");
+    }
+    let _ = header.push_str("Accept-Ranges: bytes\r\n");
+    let _ = header.push_str("Vary: Accept-Encoding\r\n");
+    let _ = header.push_str("Cache-Control: max-age=3600\r\n");
+    let _ = header.push_str("Connection: close\r\n\r\n");
+
+    let _ = socket.write_all(header.as_bytes()).await;
+    let _ = socket.write_all(body).await;
+    let _ = socket.flush().await;
+}
+
+// /log 页面: 只显示日志末尾
+async fn serve_log_view(socket: &mut Conn<'_, '_>, plain: bool) {
+    let mut tail = [0u8; log_text::TAIL_MAX];
+    let (len, end) = read_log_tail(&MODEM_LOG, lock_stats::Site::ModemLogRead, &mut tail).await;
+
+    if plain {
+        write_page(socket, "/log", log_text::format_plain(&tail[..len])).await;
+    } else {
+        write_page(socket, "/log", format_log_html(&tail[..len], end)).await;
+    }
+    let _ = socket.flush().await;
+}
+
+// Newest bytes of a log ring: (length copied, offset just past them)
+async fn read_log_tail<const N: usize>(
+    log: &embassy_sync::mutex::Mutex<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, modem_log::ModemLog<N>>,
+    site: lock_stats::Site,
+    tail: &mut [u8],
+) -> (usize, u32) {
+    let log = held(site, log.lock().await);
+    let end = log.ring.end_offset();
+    let start = end.saturating_sub(tail.len() as u32).max(log.ring.start_offset());
+    let len = log.ring.read_at(start, tail);
+    (len, start + len as u32)
+}
+
+// GET /api/log?since=<offset>: 从该偏移起的日志文本 (一次最多 2 KB), 没有 since 时给末尾。
+// 已被覆盖的部分从最早还在的位置开始; X-Log-End 是下一次的 since。
+// `counters`: 日志后面加一段串口收发字节数, 一次 curl 就够写问题报告
+async fn serve_log_since<const N: usize>(
+    socket: &mut Conn<'_, '_>,
+    log: &embassy_sync::mutex::Mutex<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, modem_log::ModemLog<N>>,
+    site: lock_stats::Site,
+    since: Option<u32>,
+    counters: bool,
+) {
+    let mut chunk = [0u8; 2048];
+    let (start, len, earliest) = {
+        let log = held(site, log.lock().await);
+        let (earliest, end) = (log.ring.start_offset(), log.ring.end_offset());
+        let start = since.unwrap_or(end.saturating_sub(chunk.len() as u32)).clamp(earliest, end);
+        (start, log.ring.read_at(start, &mut chunk), earliest)
+    };
+    // 多字节字符被截在末尾时留到下一次
+    let len = utf8::complete_len(&chunk[..len]);
+    write_page(socket, "/api/log", format_log_since(&chunk[..len], start, earliest, counters)).await;
+    let _ = socket.flush().await;
+}
+
+fn format_log_since(
+    log: &[u8],
+    start: u32,
+    earliest: u32,
+    counters: bool,
+) -> Result<heapless::String<4096>, http::BufferFull> {
+    let mut response = heapless::String::new();
+
+    let _ = response.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = response.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    let _ = response.push_str("Cache-Control: no-store\r\n");
+    let _ = core::write!(response, "X-Log-Start: {}\r\n", start);
+    let _ = core::write!(response, "X-Log-End: {}\r\n", start + log.len() as u32);
+    let _ = core::write!(response, "X-Log-Earliest-Offset: {}\r\n", earliest);
+    let _ = response.push_str("Connection: close\r\n\r\n");
+    log_text::push(&mut response, log, false);
+    if counters {
+        if !response.ends_with('\n') {
+            let _ = response.push('\n');
+        }
+        let _ = core::writeln!(
+            response,
+            "--- uart tx {} bytes, rx {} bytes, uptime {} s ---",
+            UART_TX_BYTES.load(Ordering::Relaxed),
+            UART_RX_BYTES.load(Ordering::Relaxed),
+            Instant::now().as_secs()
+        );
+    }
+
+    http::finish_page(&mut response)?;
+    Ok(response)
+}
+
+// GET /api/log?since=<seq> with Accept: application/json: the complete
+// lines from number `seq` on as records, as many as fit in one response.
+// Without since, or with one the log has not reached (after a reboot),
+// from the oldest line still kept. "next" is the since for the following
+// request and "more" says whether to ask again right away.
+//
+//   {"earliest":seq,"records":[{"seq":n,"dir":"tx"|"rx"|"note","t":ms since boot,
+//     "text":"...","cut":true (only when cut)},...],"next":seq,"more":bool}
+async fn serve_log_records<const N: usize>(
+    socket: &mut Conn<'_, '_>,
+    log: &embassy_sync::mutex::Mutex<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, modem_log::ModemLog<N>>,
+    site: lock_stats::Site,
+    since: Option<u32>,
+) {
+    let mut response = heapless::String::<4096>::new();
+    let _ = response.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = response.push_str("Content-Type: application/json\r\n");
+    let _ = response.push_str("Cache-Control: no-store\r\n");
+    let _ = response.push_str("Connection: close\r\n\r\n");
+
+    {
+        let log = held(site, log.lock().await);
+        let next_seq = log.next_seq();
+        let since = since.filter(|&seq| seq <= next_seq).unwrap_or(0);
+        let earliest = log.lines_since(0).next().map_or(next_seq, |line| line.seq);
+        let _ = core::write!(response, "{{\"earliest\":{},\"records\":[", earliest);
+
+        let mut next = next_seq;
+        let mut first = true;
+        let mut text = [0u8; modem_log::TEXT_MAX];
+        let mut record = heapless::String::<1664>::new();
+        for line in log.lines_since(since) {
+            let (bytes, cut) = log.line_text(&line, &mut text);
+            // 空行 (换向前的 "\r\n" 之后) 不算记录
+            if bytes.is_empty() {
+                continue;
+            }
+
+            record.clear();
+            if !first {
+                let _ = record.push(',');
+            }
+            let mut obj = json::Object::new(&mut record);
+            obj.u32("seq", line.seq)
+                .str("dir", line.kind.as_str())
+                .u32("t", line.at_ms.min(u32::MAX as u64) as u32);
+            if cut {
+                obj.bool("cut", true);
+            }
+            obj.raw("text", "");
+            let _ = record.push('"');
+            for chunk in bytes.utf8_chunks() {
+                json::push_escaped(&mut record, chunk.valid());
+                if !chunk.invalid().is_empty() {
+                    let _ = record.push('\u{fffd}');
+                }
+            }
+            let _ = record.push_str("\"}");
+
+            // 结尾和 Content-Length 头要留位置
+            if response.len() + record.len() + 64 > response.capacity() {
+                next = line.seq;
+                break;
+            }
+            let _ = response.push_str(&record);
+            first = false;
+        }
+        let _ = core::write!(response, "],\"next\":{},\"more\":{}}}", next, next < next_seq);
+    }
+
+    http::set_content_length(&mut response);
+    let _ = socket.write_all(response.as_bytes()).await;
+    let _ = socket.flush().await;
+}
+
+// GET /events: 串口日志的新行以 Server-Sent Events 推送, 连接一直开着。
+// 每条流有自己的行号游标, 多条流互不影响; 行号就是事件 id, 断线重连时
+// 浏览器带上 Last-Event-ID, 从下一行接着给。流占着一个套接字槽位, 所以
+// 同时最多 EVENT_STREAMS 条, 其余的回 503。
+//
+//   id: <seq>
+//   event: tx | rx | note
+//   data: <line>
+async fn serve_events(socket: &mut Conn<'_, '_>, last_event_id: Option<u32>, head: bool) {
+    let Some(mut published) = MODEM_LOG_LINES.receiver() else {
+        let response = format_short("503 Service Unavailable", "text/plain", "another event stream is open\n");
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    };
+    let header = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                  Cache-Control: no-store\r\nConnection: close\r\n\r\n";
+    if socket.write_all(header.as_bytes()).await.is_err() || socket.flush().await.is_err() || head {
+        return;
+    }
+    let mut next = match last_event_id {
+        Some(seq) => seq.wrapping_add(1),
+        None => held(lock_stats::Site::ModemLogRead, MODEM_LOG.lock().await).next_seq(),
+    };
+    let mut more = true;
+    loop {
+        if !more {
+            // 对方不读也不关时, 心跳的写入会在 write_progress_ms 后失败
+            if with_timeout(EVENTS_HEARTBEAT, published.changed()).await.is_err() {
+                if socket.write_all(b": heartbeat\n\n").await.is_err() || socket.flush().await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        }
+        // 对方已经关闭 (FIN)
+        if !socket.get_mut().may_recv() {
+            return;
+        }
+        // 锁内只复制, 写套接字之前放开
+        let mut frames = heapless::String::<2048>::new();
+        (next, more) = {
+            let log = held(lock_stats::Site::ModemLogRead, MODEM_LOG.lock().await);
+            log.push_event_frames(next, &mut frames)
+        };
+        if frames.is_empty() {
+            continue;
+        }
+        if socket.write_all(frames.as_bytes()).await.is_err() || socket.flush().await.is_err() {
+            return;
+        }
+    }
+}
+
+// /log/uart1: 调试串口日志末尾, 与 /log 相同的文本处理
+async fn serve_uart1_log(socket: &mut Conn<'_, '_>, plain: bool) {
+    let mut tail = [0u8; log_text::TAIL_MAX];
+    let (len, end) = read_log_tail(&UART1_LOG, lock_stats::Site::Uart1Log, &mut tail).await;
+    if plain {
+        write_page(socket, "/log/uart1", log_text::format_plain(&tail[..len])).await;
+    } else {
+        write_page(socket, "/log/uart1", format_uart1_html(&tail[..len], end)).await;
+    }
+    let _ = socket.flush().await;
+}
+
+fn format_uart1_html(log: &[u8], end: u32) -> Result<heapless::String<6144>, http::BufferFull> {
+    let mut html = heapless::String::new();
+    push_html_head(&mut html, "200 OK", None);
+    push_page_header(&mut html, "/log", "UART1", Refresh::Live);
+
+    let _ = html.push_str("<h1>🔌 UART1 debug port</h1>");
+    let active = UART_ACTIVE.lock(|a| a.get());
+    if !active.debug_port {
+        let _ = html.push_str("<div class='warning'>UART1 is off. Set uart.debug_port=1 on the <a href='/config'>config page</a> and reboot.</div>");
+    } else {
+        let _ = core::write!(
+            html,
+            "<div class='step'>GP{}(TX) GP{}(RX) | {} baud 8N1 | {} | read errors: {}</div>",
+            board::ACTIVE.debug_tx,
+            board::ACTIVE.debug_rx,
+            active.debug_baud,
+            if active.debug_writes { "read-write" } else { "read-only" },
+            UART1_ERRORS.load(Ordering::Relaxed)
+        );
+    }
+    let _ = core::write!(html, "<pre id='log' data-log='/api/log?log=uart1' data-end='{}'>", end);
+    log_text::push(&mut html, log, true);
+    let _ = html.push_str("</pre>");
+    if active.debug_writes {
+        let _ = html.push_str("<form method='post' action='/api/uart1/write'>");
+        let _ = html.push_str("<input type='text' name='data' maxlength='128' placeholder='text to send'>");
+        let _ = html.push_str("<label><input type='checkbox' name='crlf' value='1' checked> CR LF</label>");
+        let _ = html.push_str("<button type='submit' class='btn-at'>📤 Send</button></form>");
+    }
+    let _ = html.push_str("</div></body></html>");
+
+    http::finish_page(&mut html)?;
+    Ok(html)
+}
+
+// POST /api/uart1/write (表单: data=..., crlf=1)
+async fn write_uart1_form(form: &[u8], html: bool) -> heapless::String<512> {
+    let Some(text) = http::form_value(form, "data").and_then(http::percent_decode::<128>) else {
+        return format_short("400 Bad Request", "text/plain", "data missing or longer than 128 bytes\n");
+    };
+    let mut data = heapless::Vec::<u8, 130>::new();
+    let _ = data.extend_from_slice(text.as_bytes());
+    if http::form_value(form, "crlf") == Some("1") {
+        let _ = data.extend_from_slice(b"\r\n");
+    }
+    match uart1_write(&data).await {
+        None => format_short("403 Forbidden", "text/plain", "UART1 is read-only (set uart.debug_writes=1 and reboot)\n"),
+        Some(_) if html => format_see_other("/log/uart1"),
+        Some(written) => {
+            let mut body = heapless::String::<32>::new();
+            let _ = core::write!(body, "{{\"written\":{}}}", written);
+            format_short("200 OK", "application/json", &body)
+        }
+    }
+}
+
+// /log.txt: 日志偏移量从开机起计算, 支持单个 Range 续传;
+// 客户端支持 gzip, 不是 Range 请求且压缩器空闲时边压缩边以 chunked 方式发送
+async fn serve_log_download(socket: &mut Conn<'_, '_>, gzip: bool, range: Option<http::ByteRange>) {
+    let (earliest, end) = {
+        let log = held(lock_stats::Site::ModemLogRead, MODEM_LOG.lock().await);
+        (log.ring.start_offset(), log.ring.end_offset())
+    };
+
+    let mut header = heapless::String::<384>::new();
+    let (first, end, gzip) = match http::check_range(range, earliest, end) {
+        RangeCheck::Full => {
+            let _ = header.push_str("HTTP/1.1 200 OK\r\n");
+            let _ = core::write!(header, "X-Log-Start-Offset: {}\r\n", earliest);
+            (earliest, end, gzip)
+        }
+        RangeCheck::Partial { first, last } => {
+            let _ = header.push_str("HTTP/1.1 206 Partial Content\r\n");
+            let _ = core::write!(header, "Content-Range: bytes {}-{}/{}\r\n", first, last, end);
+            (first, last + 1, false)
+        }
+        RangeCheck::Unsatisfiable => {
+            let _ = header.push_str("HTTP/1.1 416 Range Not Satisfiable\r\n");
+            let _ = core::write!(header, "Content-Range: bytes */{}\r\n", end);
+            let _ = core::write!(header, "X-Log-Earliest-Offset: {}\r\n", earliest);
+            let _ = header.push_str("Content-Length: 0\r\n");
+            let _ = header.push_str("Connection: close\r\n\r\n");
+            let _ = socket.write_all(header.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+    };
+
+    // 压缩器同时只给一个下载用: 它正被另一个 (可能很慢的) 客户端占着时,
+    // 这次下载不压缩, 而不是排队等它下完
+    let deflater = if gzip { LOG_DEFLATER.try_lock().ok() } else { None };
+    let gzip = deflater.is_some();
+
+    let _ = header.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    let _ = header.push_str("Content-Disposition: attachment; filename=\"ec800k-log.txt\"\r\n");
+    let _ = header.push_str("Accept-Ranges: bytes\r\n");
+    let _ = header.push_str("Vary: Accept-Encoding\r\n");
+    if gzip {
+        let _ = header.push_str("Content-Encoding: gzip\r\n");
+        http::push_unknown_length(&mut header, socket.chunked());
+    } else {
+        let _ = core::write!(header, "Content-Length: {}\r\n", end - first);
+    }
+    let _ = header.push_str("Connection: close\r\n\r\n");
+
+    if socket.write_all(header.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let complete = if let Some(deflater) = deflater {
+        stream_log_gzip(socket, &mut held(lock_stats::Site::LogCompress, deflater), first, end).await
+    } else {
+        stream_log_plain(socket, first, end).await
+    };
+
+    // 下载过程中日志被覆盖或写失败: 直接断开, 不发送不完整的正文
+    if complete {
+        let _ = socket.flush().await;
+    } else {
+        abort_conn(socket, conn_close::Close::Truncated);
+    }
+}
+
+// 每次只在锁内复制一小段, 慢速客户端不会阻塞串口任务
+async fn read_log_piece(offset: u32, end: u32, piece: &mut [u8]) -> usize {
+    let want = ((end - offset) as usize).min(piece.len());
+    held(lock_stats::Site::ModemLogRead, MODEM_LOG.lock().await).ring.read_at(offset, &mut piece[..want])
+}
+
+async fn stream_log_plain(socket: &mut Conn<'_, '_>, first: u32, end: u32) -> bool {
+    let mut piece = [0u8; 512];
+    let mut offset = first;
+    while offset < end {
+        let n = read_log_piece(offset, end, &mut piece).await;
+        if n == 0 || socket.write_all(&piece[..n]).await.is_err() {
+            return false;
+        }
+        offset += n as u32;
+    }
+    true
+}
+
+// 日志下载压缩时每次读取的字节数
+const LOG_GZIP_PIECE: usize = 512;
+const _: () = assert!(deflate::FINISH_MAX <= deflate::max_compressed(LOG_GZIP_PIECE));
+
+async fn stream_log_gzip(socket: &mut Conn<'_, '_>, deflater: &mut deflate::Deflater, first: u32, end: u32) -> bool {
+    deflater.reset();
+
+    let chunked = socket.chunked();
+    let mut writer = http::ChunkedWriter::new(socket, chunked);
+    if writer.write_chunk(&deflate::GZIP_HEADER).await.is_err() {
+        return false;
+    }
+
+    let mut piece = [0u8; LOG_GZIP_PIECE];
+    let mut out = heapless::Vec::<u8, { deflate::max_compressed(LOG_GZIP_PIECE) }>::new();
+    // out 按压缩器的上限分配, 仍然装不下时断开而不是发出损坏的 gzip
+    let mut overflow = false;
+    let mut offset = first;
+    while offset < end {
+        let n = read_log_piece(offset, end, &mut piece).await;
+        if n == 0 {
+            return false;
+        }
+        offset += n as u32;
+
+        out.clear();
+        deflater.compress(&piece[..n], |b| overflow |= out.push(b).is_err());
+        if overflow || writer.write_chunk(&out).await.is_err() {
+            return false;
+        }
+    }
+
+    out.clear();
+    deflater.finish(|b| overflow |= out.push(b).is_err());
+    !overflow && writer.write_chunk(&out).await.is_ok() && writer.finish().await.is_ok()
+}
+
+// PWRKEY 按下的时长 (EC800K 要求至少 500 ms) 和按下后等 RDY 的上限
+const PWRKEY_PULSE: Duration = Duration::from_millis(600);
+const RDY_TIMEOUT: Duration = Duration::from_secs(15);
+
+enum Probe {
+    Answered,
+    Silent,
+    WriteFailed(&'static str),
+}
+
+// A bare AT and up to about 3 s for anything to come back; the first
+// bytes of an answer go to `response`
+async fn probe_at(tx: &mut ModemTx, rx: &mut ModemRx, response: &mut heapless::String<256>) -> Probe {
+    info!("Sending initial AT command...");
+    if let Err(e) = uart_write_all(tx, b"AT\r\n").await {
+        error!("Failed to send initial AT command: {}", e.as_str());
+        return Probe::WriteFailed(e.as_str());
+    }
+    Timer::after(Duration::from_millis(200)).await;
+
+    let mut buf = [0u8; 256];
+    // 调制解调器没有上电时读操作不能无限等待
+    for _ in 0..5 {
+        if let Ok(Ok(n)) = with_timeout(Duration::from_millis(500), uart_read(rx, &mut buf)).await
+            && n > 0
+        {
+            let Ok(s) = core::str::from_utf8(&buf[..n]) else {
+                rx_lost(rx_audit::Loss::InvalidUtf8, n);
+                Timer::after(Duration::from_millis(100)).await;
+                continue;
+            };
+            trace!("Initial response: {}", s);
+            let before = response.len();
+            utf8::push_truncated(response, s);
+            let kept = response.len() - before;
+            rx_delivered(rx_audit::Sink::Boot, kept);
+            rx_lost(rx_audit::Loss::BufferFull, n - kept);
+            return Probe::Answered;
+        }
+        Timer::after(Duration::from_millis(100)).await;
+    }
+    Probe::Silent
+}
+
+enum Rdy {
+    Seen,
+    // bytes received while waiting, none of them a RDY line
+    TimedOut(u32),
+}
+
+async fn wait_for_rdy(rx: &mut ModemRx) -> Rdy {
+    let deadline = Instant::now() + RDY_TIMEOUT;
+    let mut line = heapless::Vec::<u8, 32>::new();
+    let mut received = 0u32;
+    let mut buf = [0u8; 64];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let Ok(read) = with_timeout(left, uart_read(rx, &mut buf)).await else {
+            break;
+        };
+        let Ok(n) = read else {
+            continue;
+        };
+        received += n as u32;
+        rx_delivered(rx_audit::Sink::Boot, n);
+        for &b in &buf[..n] {
+            if b != b'\n' {
+                // 过长的行不会是 RDY, 截断无妨
+                let _ = line.push(b);
+                continue;
+            }
+            let text = core::str::from_utf8(&line).unwrap_or("").trim();
+            if matches!(urc::parse(text), Some(urc::Urc::Restarted)) {
+                return Rdy::Seen;
+            }
+            line.clear();
+        }
+    }
+    Rdy::TimedOut(received)
+}
+
+// 上电流程: 先探测已在运行的模块; 没有应答就按 PWRKEY (modem.pwrkey),
+// 等 RDY, 再探测一次. 每一步都是启动页上的一个阶段
+async fn bring_up_modem(
+    tx: &mut ModemTx,
+    rx: &mut ModemRx,
+    mut pwrkey: Option<Output<'static>>,
+) -> Option<heapless::String<256>> {
+    let mut response = heapless::String::new();
+    let stage = boot_begin("modem probe");
+    match probe_at(tx, rx, &mut response).await {
+        Probe::Answered => {
+            boot_end(stage, boot::Outcome::Done);
+            return Some(response);
+        }
+        Probe::WriteFailed(e) => {
+            finish_transcript(e);
+            boot_end(stage, boot::Outcome::Failed("uart write"));
+            return None;
+        }
+        // 没有应答不算失败: 模块可能还没开机
+        Probe::Silent => boot_end(stage, boot::Outcome::Done),
+    }
+
+    if let Some(key) = pwrkey.as_mut() {
+        let stage = boot_begin("modem power key");
+        set_modem_status("⏳ No answer from the modem: pressing its power key\n").await;
+        key.set_high();
+        Timer::after(PWRKEY_PULSE).await;
+        key.set_low();
+        boot_end(stage, boot::Outcome::Done);
+    }
+
+    let stage = boot_begin("modem RDY");
+    set_modem_status("⏳ Waiting for the modem to start (RDY)\n").await;
+    match wait_for_rdy(rx).await {
+        Rdy::Seen => boot_end(stage, boot::Outcome::Done),
+        Rdy::TimedOut(received) => {
+            let reason = if received == 0 {
+                "no RDY and no bytes at all: check wiring and power"
+            } else {
+                "no RDY but bytes arrived: check the baud rate"
+            };
+            {
+                let mut result = modem_result().await;
+                result.clear();
+                let _ = result.push_str("⚠️ No response from EC800K on startup\n");
+                let seconds = RDY_TIMEOUT.as_secs();
+                let _ = core::writeln!(result, "No RDY within {} s, {} bytes received", seconds, received);
+                push_wiring_hint(&mut *result);
+                if pwrkey.is_none() {
+                    let _ = result.push_str("Check power, or set modem.pwrkey if the power key is wired\n");
+                } else {
+                    let _ = result.push_str("Check power\n");
+                }
+                push_uart_error_hint(&mut *result);
+            }
+            finish_transcript("no response");
+            boot_end(stage, boot::Outcome::Failed(reason));
+            notify(webhook::Event::ModemError, format_args!("no response from the modem at boot"));
+            return None;
+        }
+    }
+
+    let stage = boot_begin("modem AT after RDY");
+    match probe_at(tx, rx, &mut response).await {
+        Probe::Answered => {
+            boot_end(stage, boot::Outcome::Done);
+            Some(response)
+        }
+        Probe::WriteFailed(e) => {
+            finish_transcript(e);
+            boot_end(stage, boot::Outcome::Failed("uart write"));
+            None
+        }
+        Probe::Silent => {
+            set_modem_status("⚠️ The modem sent RDY but does not answer AT\n").await;
+            finish_transcript("no response after RDY");
+            boot_end(stage, boot::Outcome::Failed("no response after RDY"));
+            notify(webhook::Event::ModemError, format_args!("no response from the modem after RDY"));
+            None
+        }
+    }
+}
+
+async fn set_modem_status(text: &str) {
+    let mut result = modem_result().await;
+    result.clear();
+    let _ = result.push_str(text);
+}
+
+// 模块应答之后: 识别型号, 检查 SIM, 试存储的 PIN
+async fn init_modem(tx: &mut ModemTx, rx: &mut ModemRx, response: &str) {
+    let stage = boot_begin("modem init");
+    {
+        let mut result = modem_result().await;
+        result.clear();
+        let _ = result.push_str("✅ EC800K is responding!\n\n");
+        let _ = result.push_str("Click the green button to fetch httpbin.org/get\n\n");
+        let _ = result.push_str("Initial response:\n");
+        utf8::push_truncated(&mut result, response);
+    }
+    detect_modem(tx, rx).await;
+    let state = check_sim(tx, rx).await;
+    if try_stored_pin(tx, rx, state).await.locked() {
+        let mut result = modem_result().await;
+        let _ = result.push_str("\n\n🔒 The SIM is locked: enter the PIN on this page\n");
+    }
+    finish_transcript("ok");
+    boot_end(stage, boot::Outcome::Done);
+}
+
+// 启动时没有找到模块, 之后定期重新探测; None = 模块已应答 (或在恢复模式)
+static MODEM_PROBE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<probe_retry::Retry>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+async fn run_modem_probe(tx: &mut ModemTx, rx: &mut ModemRx) {
+    let Some(mut retry) = MODEM_PROBE.lock(|p| p.get()) else {
+        return;
+    };
+    let mut response = heapless::String::new();
+    if !matches!(probe_at(tx, rx, &mut response).await, Probe::Answered) {
+        let now = Instant::now().as_millis();
+        retry.failed(now);
+        debug!("Modem probe {} unanswered, next in {} s", retry.attempts(), retry.interval_ms(now) / 1000);
+        MODEM_PROBE.lock(|p| p.set(Some(retry)));
+        bump_state_generation();
+        return;
+    }
+
+    MODEM_PROBE.lock(|p| p.set(None));
+    let took_s = Instant::now().as_millis().saturating_sub(retry.since_ms()) / 1000;
+    info!("Modem answered after {} probes ({} s)", retry.attempts() + 1, took_s);
+    begin_transcript(transcript::Kind::Init);
+    init_modem(tx, rx, &response).await;
+    notify(
+        webhook::Event::ModemRecovered,
+        format_args!("modem {} answered after {} s ({} probes)", current_modem().name(), took_s, retry.attempts() + 1),
+    );
+}
+
+fn decode_url(input: &str) -> heapless::String<64> {
+    let mut output = heapless::String::new();
+    let mut chars = input.chars();
+    
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            let hex1 = chars.next().unwrap_or('0');
+            let hex2 = chars.next().unwrap_or('0');
+            if let (Some(h1), Some(h2)) = (hex1.to_digit(16), hex2.to_digit(16)) {
+                let byte = ((h1 << 4) | h2) as u8;
+                let _ = output.push(byte as char);
+            }
+        } else if c == '+' {
+            let _ = output.push(' ');
+        } else {
+            let _ = output.push(c);
+        }
+    }
+    
+    if !output.ends_with("\r\n") {
+        let _ = output.push_str("\r\n");
+    }
+    
+    output
+}
+
+#[embassy_executor::task]
+async fn uart_task(mut tx: ModemTx, mut rx: ModemRx, pwrkey: Option<Output<'static>>) {
+    info!("UART task started (921600 baud)");
+    
+    // 初始测试 (恢复模式下不碰调制解调器, 只处理手动 AT 指令)
+    if recovery_mode() {
+        let mut result = modem_result().await;
+        result.clear();
+        let _ = result.push_str("🛟 Recovery mode: modem init skipped\n");
+        let _ = result.push_str("Manual AT commands still work\n");
+    } else {
+        begin_transcript(transcript::Kind::Init);
+        if let Some(response) = bring_up_modem(&mut tx, &mut rx, pwrkey).await {
+            init_modem(&mut tx, &mut rx, &response).await;
+            notify(webhook::Event::Boot, format_args!("modem {} responding", current_modem().name()));
+        } else {
+            MODEM_PROBE.lock(|p| p.set(Some(probe_retry::Retry::new(Instant::now().as_millis()))));
+            let mut result = modem_result().await;
+            let _ = core::writeln!(
+                result,
+                "🔁 Probing again every {} s, every {} s after {} minutes",
+                probe_retry::FAST_MS / 1000,
+                probe_retry::SLOW_MS / 1000,
+                probe_retry::SLOW_AFTER_MS / 60_000
+            );
+        }
+    }
+    
+    // 主循环: 到期的后台操作进队, 然后按优先级逐个执行
+    let mut next_ping = Instant::now() + PING_INTERVAL;
+    let mut last_keep_warm = Instant::now();
+    #[cfg(feature = "proxy")]
+    let mut last_forward_poll = Instant::now();
+    let mut last_clock_sync: Option<Instant> = None;
+    #[cfg(feature = "gnss")]
+    let mut last_gnss_poll: Option<Instant> = None;
+    loop {
+        use embassy_futures::select::select3;
+
+        // 定期 ping, 保活查询和待发的通知; 恢复模式下都不做 (调制解调器没有初始化)
+        // 时段窗口外的 ping, 保活和通知推迟到窗口打开
+        let now = Instant::now();
+        let ping_due = scheduled(schedule::Feature::Ping, next_ping);
+        let webhooks_due = WEBHOOKS
+            .lock(|w| w.borrow().next_due_ms())
+            .map(|due| scheduled(schedule::Feature::Webhooks, Instant::from_millis(due)));
+        let retry_due = FETCH_LADDER.lock(|l| l.borrow().retry_due_ms()).map(Instant::from_millis);
+        let keep_warm_due = match CONFIG.lock(|c| c.borrow().keep_warm.interval_min) {
+            0 => None,
+            minutes => Some(scheduled(
+                schedule::Feature::KeepWarm,
+                last_keep_warm + Duration::from_secs(minutes as u64 * 60),
+            )),
+        };
+        let clock_due = schedule_in_use().then(|| match (last_clock_sync, CLOCK.lock(|c| c.get())) {
+            (None, _) => now,
+            (Some(last), None) => last + CLOCK_RETRY,
+            (Some(last), Some(_)) => last + CLOCK_RESYNC,
+        });
+        // 关闭 GNSS 后尽快给引擎断电
+        #[cfg(feature = "gnss")]
+        let gnss = CONFIG.lock(|c| c.borrow().gnss);
+        #[cfg(feature = "gnss")]
+        let gnss_due = match (gnss.enabled, GNSS.lock(|g| g.borrow().powered_at_ms.is_some())) {
+            (false, false) => None,
+            (false, true) => Some(now),
+            (true, _) => Some(last_gnss_poll.map_or(now, |last| last + Duration::from_secs(gnss.interval_s as u64))),
+        };
+        #[cfg(not(feature = "gnss"))]
+        let gnss_due = None;
+        #[cfg(feature = "proxy")]
+        let forwards_due = FORWARD_LINKS
+            .lock(|l| l.borrow().iter().any(|link| link.state.busy()))
+            .then(|| last_forward_poll + FORWARD_POLL);
+        #[cfg(not(feature = "proxy"))]
+        let forwards_due = None;
+        let release_due = FETCH_KEPT.lock(|k| k.borrow().as_ref().map(|kept| Instant::from_millis(kept.close_at_ms())));
+        let probe_due = MODEM_PROBE.lock(|p| p.get()).map(|retry| Instant::from_millis(retry.due_ms()));
+        if !recovery_mode() {
+            if probe_due.is_some_and(|due| now >= due) {
+                submit_modem_op(ModemOp::Probe);
+            }
+            if now >= ping_due {
+                submit_modem_op(ModemOp::Ping);
+                next_ping = now + PING_INTERVAL;
+            }
+            if keep_warm_due.is_some_and(|due| now >= due) {
+                submit_modem_op(ModemOp::KeepWarm);
+                last_keep_warm = now;
+            }
+            if retry_due.is_some_and(|due| now >= due) {
+                info!("Hourly fetch retry");
+                FETCH_LADDER.lock(|l| l.borrow_mut().retry_started());
+                submit_modem_op(ModemOp::Fetch(fetch::Origin::Recovery));
+            }
+            if webhooks_due.is_some_and(|due| now >= due) {
+                submit_modem_op(ModemOp::Webhooks);
+            }
+            #[cfg(feature = "proxy")]
+            if forwards_due.is_some_and(|due| now >= due) {
+                submit_modem_op(ModemOp::Forwards);
+                last_forward_poll = now;
+            }
+            if release_due.is_some_and(|due| now >= due) {
+                submit_modem_op(ModemOp::ReleaseConnection);
+            }
+            if clock_due.is_some_and(|due| now >= due) {
+                submit_modem_op(ModemOp::ClockSync);
+                last_clock_sync = Some(now);
+            }
+            #[cfg(feature = "gnss")]
+            if gnss_due.is_some_and(|due| now >= due) {
+                submit_modem_op(ModemOp::Gnss);
+                last_gnss_poll = Some(now);
+            }
+        }
+
+        while let Some(entry) = MODEM_OPS.lock(|q| q.borrow_mut().take_expired(now.as_millis())) {
+            report_modem_busy(entry).await;
+        }
+        if let Some(ms) = MODEM_OPS.lock(|q| q.borrow_mut().starvation(now.as_millis())) {
+            warn!("Background modem polls starved for {} s", ms / 1000);
+        }
+
+        let Some(entry) = MODEM_OPS.lock(|q| q.borrow_mut().pop()) else {
+            let wake = async {
+                if recovery_mode() {
+                    core::future::pending::<()>().await;
+                }
+                let due = [
+                    webhooks_due,
+                    keep_warm_due,
+                    retry_due,
+                    forwards_due,
+                    release_due,
+                    clock_due,
+                    gnss_due,
+                    probe_due,
+                ]
+                    .into_iter()
+                    .flatten()
+                    .fold(ping_due, Instant::min);
+                Timer::at(due).await;
+            };
+            select3(MODEM_OPS_SIGNAL.wait(), WEBHOOK_SIGNAL.wait(), wake).await;
+            continue;
+        };
+
+        MODEM_CURRENT.lock(|c| c.set(Some(entry.name)));
+        MODEM_REQUEST.lock(|r| r.set(entry.request_id));
+        update_action(entry.action, actions::Status::Running, "");
+        bump_state_generation();
+        let waited_ms = now.as_millis().saturating_sub(entry.enqueued_ms);
+        match entry.request_id {
+            Some(id) => info!("Modem op {} for HTTP #{} after {} ms in the queue", entry.name, id, waited_ms),
+            None => debug!("Modem op {} after {} ms in the queue", entry.name, waited_ms),
+        }
+        // 动作失败的原因, 空 = 成功
+        let mut failure = heapless::String::<48>::new();
+        match entry.op {
+            ModemOp::AtCommand(cmd) => handle_at_command(&mut tx, &mut rx, cmd.as_str()).await,
+            ModemOp::Fetch(_) if recovery_mode() => {
+                let mut result = modem_result().await;
+                result.clear();
+                let _ = result.push_str("🛟 HTTP GET is disabled in recovery mode (it runs the modem init sequence)\n");
+                let _ = failure.push_str("recovery mode");
+            }
+            ModemOp::Fetch(origin) => {
+                let started = Instant::now().as_millis();
+                begin_transcript(transcript::Kind::Fetch);
+                perform_http_get(&mut tx, &mut rx, origin).await;
+                // 没有经过成功, 失败或取消的出口 (例如漫游时不允许数据)
+                finish_transcript("stopped");
+                // 这次获取写下的记录; 没有就是在连接之前停下了
+                let recorded = FETCH_HISTORY.lock(|h| {
+                    let history = h.borrow();
+                    history.iter().next().filter(|r| r.at_ms >= started).map(|r| r.error)
+                });
+                match recorded {
+                    Some(Some(error)) => error.describe(&mut failure),
+                    Some(None) => {}
+                    None => {
+                        let _ = failure.push_str("stopped before connecting");
+                    }
+                }
+            }
+            ModemOp::Macro(name) => run_macro(&mut tx, &mut rx, &name).await,
+            ModemOp::SimUnlock(unlock) => unlock_sim(&mut tx, &mut rx, &unlock).await,
+            ModemOp::Ping => run_ping(&mut tx, &mut rx).await,
+            ModemOp::KeepWarm => run_keep_warm(&mut tx, &mut rx).await,
+            ModemOp::Recover(step) => run_recovery_step(&mut tx, &mut rx, step).await,
+            ModemOp::Webhooks => send_due_webhooks(&mut tx, &mut rx).await,
+            #[cfg(feature = "proxy")]
+            ModemOp::Forwards => run_forwards(&mut tx, &mut rx).await,
+            ModemOp::ReleaseConnection => release_kept_connection(&mut tx, &mut rx).await,
+            ModemOp::ClockSync => sync_clock(&mut tx, &mut rx).await,
+            ModemOp::Probe => run_modem_probe(&mut tx, &mut rx).await,
+            ModemOp::Relay => {
+                if let Err(reason) = run_relay(&mut tx, &mut rx).await {
+                    let _ = failure.push_str(reason);
+                }
+            }
+            #[cfg(feature = "gnss")]
+            ModemOp::Gnss => run_gnss(&mut tx, &mut rx).await,
+            ModemOp::Sms { number, text } => {
+                if !send_sms(&mut tx, &mut rx, &number, &text).await {
+                    warn!("SMS to {} not sent", number.as_str());
+                    let _ = failure.push_str("not sent");
+                }
+            }
+            ModemOp::Shutdown => {
+                power_down_modem(&mut tx, &mut rx).await;
+                // 模块已关机, 等待复位
+                core::future::pending::<()>().await;
+            }
+        }
+        if failure.is_empty() {
+            update_action(entry.action, actions::Status::Done, "");
+        } else {
+            update_action(entry.action, actions::Status::Failed, &failure);
+        }
+        check_rx_audit();
+        MODEM_CURRENT.lock(|c| c.set(None));
+        MODEM_REQUEST.lock(|r| r.set(None));
+        bump_state_generation();
+    }
+}
+
+// 排队太久被丢弃的操作: 告诉发起者调制解调器忙
+async fn report_modem_busy(entry: modem_queue::Entry<ModemOp>) {
+    warn!("Modem busy: {}{} dropped after waiting in the queue", entry.name, request_tag(entry.request_id));
+    update_action(entry.action, actions::Status::Failed, "waited too long in the modem queue");
+    match entry.op {
+        // 转发还有连接时下一轮轮询会再排队
+        #[cfg(feature = "proxy")]
+        ModemOp::Forwards => return,
+        #[cfg(feature = "gnss")]
+        ModemOp::Gnss => return,
+        ModemOp::Ping
+        | ModemOp::KeepWarm
+        | ModemOp::Webhooks
+        | ModemOp::ReleaseConnection
+        | ModemOp::ClockSync
+        | ModemOp::Sms { .. }
+        | ModemOp::Probe
+        | ModemOp::Shutdown => return,
+        ModemOp::Relay => {
+            RELAY.lock(|r| r.borrow_mut().finish(Some("modem busy")));
+            RELAY_EVENT.signal(());
+            return;
+        }
+        ModemOp::Recover(step) => FETCH_LADDER.lock(|l| l.borrow_mut().finished(step, false)),
+        ModemOp::Macro(_) => MACRO_REPORT.lock(|r| *r.borrow_mut() = None),
+        _ => {}
+    }
+    let mut result = modem_result().await;
+    result.clear();
+    let _ = core::writeln!(result, "⚠️ Modem busy: {} waited too long in the queue and was not run", entry.name);
+}
+
+// 网络时间 (AT+CCLK?), 时段窗口用; 未同步前为 None
+static CLOCK: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<schedule::Clock>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+// Until the network time is known, ask every few minutes; after that the
+// monotonic clock only needs correcting now and then
+const CLOCK_RETRY: Duration = Duration::from_secs(5 * 60);
+const CLOCK_RESYNC: Duration = Duration::from_secs(6 * 60 * 60);
+
+// Some window needs the network time: a schedule window or the AP wake window
+fn schedule_in_use() -> bool {
+    CONFIG.lock(|c| {
+        let c = c.borrow();
+        c.schedule.windows.iter().any(schedule::Window::complete)
+            || (c.power.ap_idle_min > 0 && c.power.wake_window().is_some())
+    })
+}
+
+fn schedule_gate(feature: schedule::Feature) -> schedule::Gate {
+    let clock = CLOCK.lock(|c| c.get());
+    CONFIG.lock(|c| {
+        let schedule = &c.borrow().schedule;
+        schedule::gate(&schedule.windows, feature, clock, schedule.unsynced_open, Instant::now().as_millis())
+    })
+}
+
+// When a background action due at `due` may run: then, or once its window opens
+fn scheduled(feature: schedule::Feature, due: Instant) -> Instant {
+    match schedule_gate(feature) {
+        schedule::Gate::Open => due,
+        schedule::Gate::Closed { opens_in_ms } => due.max(Instant::now() + Duration::from_millis(opens_in_ms)),
+        // 时钟同步后再看
+        schedule::Gate::NoClock => due.max(Instant::now() + CLOCK_RETRY),
+    }
+}
+
+async fn sync_clock(tx: &mut ModemTx, rx: &mut ModemRx) {
+    let mut clock = None;
+    quiet_query(tx, rx, "AT+CCLK?\r\n", Duration::from_secs(2), |line| {
+        if clock.is_none() {
+            clock = schedule::parse_cclk(line, Instant::now().as_millis());
+        }
+    })
+    .await;
+    let Some(clock) = clock else {
+        debug!("Network time not known yet");
+        return;
+    };
+    if CLOCK.lock(|c| c.replace(Some(clock))).is_none() {
+        let mut time = heapless::String::<8>::new();
+        schedule::write_hhmm(&mut time, (clock.day_ms(Instant::now().as_millis()) / 60_000) as u32);
+        info!("Clock set from the network time: {}", time.as_str());
+    }
+    bump_state_generation();
+}
+
+// Status line: the clock, then each restricted feature and when it runs next
+fn push_schedule_html<const N: usize>(html: &mut heapless::String<N>) {
+    let now_ms = Instant::now().as_millis();
+    let clock = CLOCK.lock(|c| c.get());
+    match clock {
+        Some(clock) => {
+            let _ = html.push_str("clock <strong>");
+            schedule::write_hhmm(html, (clock.day_ms(now_ms) / 60_000) as u32);
+            let _ = html.push_str("</strong>");
+        }
+        None => {
+            let _ = html.push_str("clock <strong>not set</strong>");
+        }
+    }
+    let windows = CONFIG.lock(|c| c.borrow().schedule.windows.clone());
+    for feature in schedule::Feature::ALL {
+        if !schedule::restricts(&windows, feature) {
+            continue;
+        }
+        let _ = core::write!(html, " | {}: ", feature.as_str());
+        match schedule_gate(feature) {
+            schedule::Gate::Open => {
+                let _ = html.push_str("<strong>in window</strong>");
+            }
+            schedule::Gate::Closed { opens_in_ms } => {
+                let _ = html.push_str("next at <strong>");
+                if let Some(clock) = clock {
+                    schedule::write_hhmm(html, ((clock.day_ms(now_ms) + opens_in_ms) / 60_000 % (24 * 60)) as u32);
+                }
+                let minutes = opens_in_ms.div_ceil(60_000);
+                let _ = core::write!(html, "</strong> (in {} h {} min)", minutes / 60, minutes % 60);
+            }
+            schedule::Gate::NoClock => {
+                let _ = html.push_str("waiting for the clock");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "gnss")]
+static GNSS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<gnss::State>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(gnss::State::new()));
+
+// Power the engine down when gnss.enabled was turned off; otherwise power
+// it up if needed and read the position once
+#[cfg(feature = "gnss")]
+async fn run_gnss(tx: &mut ModemTx, rx: &mut ModemRx) {
+    let modem = current_modem();
+    let powered = GNSS.lock(|g| g.borrow().powered_at_ms.is_some());
+    if !CONFIG.lock(|c| c.borrow().gnss.enabled) {
+        if powered {
+            if let Some(command) = modem.gnss_power(false) {
+                quiet_command(tx, rx, &command, Duration::from_secs(2)).await;
+            }
+            info!("GNSS engine powered off");
+            GNSS.lock(|g| g.borrow_mut().powered_off());
+            bump_state_generation();
+        }
+        return;
+    }
+    let (Some(power_on), Some(query)) = (modem.gnss_power(true), modem.gnss_location()) else {
+        return;
+    };
+
+    if !powered {
+        let mut on = false;
+        quiet_query(tx, rx, &power_on, Duration::from_secs(2), |line| on |= gnss::powered_on(line)).await;
+        if !on {
+            warn!("GNSS engine did not start (no GPS on this module?)");
+            GNSS.lock(|g| g.borrow_mut().record(None));
+            return;
+        }
+        info!("GNSS engine powered on");
+        GNSS.lock(|g| g.borrow_mut().powered_at_ms = Some(Instant::now().as_millis()));
+    }
+
+    let mut reply = None;
+    quiet_query(tx, rx, &query, Duration::from_secs(2), |line| {
+        if reply.is_none() {
+            reply = gnss::parse_location(line, Instant::now().as_millis());
+        }
+    })
+    .await;
+    let position = match &reply {
+        Some(gnss::Reply::Fix(fix)) => Some((fix.latitude, fix.longitude)),
+        _ => None,
+    };
+    let (was_acquiring, powered_at) = GNSS.lock(|g| {
+        let mut state = g.borrow_mut();
+        let before = state.acquiring || state.last_fix.is_none();
+        state.record(reply);
+        (before, state.powered_at_ms.unwrap_or(0))
+    });
+    if position.is_some() && was_acquiring {
+        info!("GNSS fix {} s after power on", Instant::now().as_millis().saturating_sub(powered_at) / 1000);
+    }
+    if let Some((latitude, longitude)) = position {
+        check_geofence(latitude, longitude);
+    }
+    bump_state_generation();
+}
+
+#[cfg(feature = "gnss")]
+static GEOFENCE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<geofence::Tracker>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(geofence::Tracker::new()));
+
+// 每个新定位对照围栏, 出入时才报 (日志, webhook, 可选短信)
+#[cfg(feature = "gnss")]
+fn check_geofence(latitude: f64, longitude: f64) {
+    let Some(fence) = CONFIG.lock(|c| c.borrow().geofence.fence()) else {
+        return;
+    };
+    let Some((side, distance)) = GEOFENCE.lock(|g| {
+        let mut tracker = g.borrow_mut();
+        let side = tracker.update(&fence, latitude, longitude)?;
+        Some((side, tracker.distance_m.unwrap_or(0)))
+    }) else {
+        return;
+    };
+    let what = match side {
+        geofence::Side::Outside => "left the geofence",
+        geofence::Side::Inside => "back inside the geofence",
+    };
+    warn!("GNSS: {}, {} m from the centre (radius {} m)", what, distance, fence.radius_m);
+    notify(webhook::Event::Geofence, format_args!("{}, {} m from the centre", what, distance));
+
+    let number = CONFIG.lock(|c| c.borrow().geofence.sms.clone());
+    if !number.is_empty() {
+        let mut text = heapless::String::<96>::new();
+        let _ = core::write!(
+            text,
+            "{}: {}, {} m from the centre, at {:.5},{:.5}",
+            device_name(),
+            what,
+            distance,
+            latitude,
+            longitude
+        );
+        submit_modem_op(ModemOp::Sms { number, text });
+    }
+}
+
+#[cfg(feature = "gnss")]
+fn push_geofence_html<const N: usize>(html: &mut heapless::String<N>) {
+    let (side, distance) = GEOFENCE.lock(|g| {
+        let tracker = g.borrow();
+        (tracker.side, tracker.distance_m)
+    });
+    match side {
+        Some(side) => {
+            let _ = core::write!(html, "<strong>{}</strong>", side.as_str());
+        }
+        None if distance.is_some() => {
+            let _ = html.push_str("<strong>checking</strong>");
+        }
+        None => {
+            let _ = html.push_str("waiting for a GNSS fix");
+        }
+    }
+    if let Some(distance) = distance {
+        let _ = core::write!(html, ", {} m from the centre", distance);
+    }
+}
+
+#[cfg(feature = "gnss")]
+fn format_geofence_json() -> heapless::String<128> {
+    let mut out = heapless::String::new();
+    let radius_m = CONFIG.lock(|c| c.borrow().geofence.fence()).map_or(0, |fence| fence.radius_m);
+    GEOFENCE.lock(|g| {
+        let tracker = g.borrow();
+        let mut obj = json::Object::new(&mut out);
+        obj.u32("radius_m", radius_m)
+            .str("side", tracker.side.map_or("unknown", geofence::Side::as_str));
+        match tracker.distance_m {
+            Some(distance) => obj.u32("distance_m", distance),
+            None => obj.raw("distance_m", "null"),
+        };
+        obj.u32("transitions", tracker.transitions);
+        obj.finish();
+    });
+    out
+}
+
+// 短信 (文本模式): 发送命令给出 '>' 提示后写正文, Ctrl-Z 结束
+async fn send_sms(tx: &mut ModemTx, rx: &mut ModemRx, number: &str, text: &str) -> bool {
+    let modem = current_modem();
+    if !quiet_command(tx, rx, &modem.sms_text_mode(), Duration::from_secs(2)).await {
+        return false;
+    }
+    if uart_write_all(tx, modem.sms_send(number).as_bytes()).await.is_err() {
+        return false;
+    }
+    let mut reader = LineReader::new();
+    if !reader.expect_prompt(rx, Instant::now() + Duration::from_secs(5)).await {
+        return false;
+    }
+    if uart_write_all(tx, text.as_bytes()).await.is_err() || uart_write_all(tx, &[0x1A]).await.is_err() {
+        return false;
+    }
+    // 网络确认可能要几十秒
+    let mut reference = false;
+    let ok = await_final(rx, Duration::from_secs(60), |line| reference |= line.starts_with("+CMGS:")).await;
+    if ok && reference {
+        info!("SMS sent to {}", number);
+    }
+    ok && reference
+}
+
+#[cfg(feature = "gnss")]
+fn push_gnss_html<const N: usize>(html: &mut heapless::String<N>) {
+    let now_ms = Instant::now().as_millis();
+    let state = GNSS.lock(|g| {
+        let state = g.borrow();
+        (state.powered_at_ms, state.acquiring, state.last_fix.clone())
+    });
+    match &state {
+        (None, _, _) if current_modem().gnss_location().is_none() => {
+            let _ = html.push_str("not supported by this module");
+            return;
+        }
+        (None, _, _) => {
+            let _ = html.push_str("<strong>off</strong>");
+        }
+        (Some(at), true, _) => {
+            let _ = core::write!(html, "<strong>acquiring</strong> for {} s", now_ms.saturating_sub(*at) / 1000);
+        }
+        (Some(_), false, None) => {
+            let _ = html.push_str("<strong>starting</strong>");
+        }
+        (Some(_), false, Some(fix)) => {
+            let _ = core::write!(html, "<strong>{} fix</strong>", fix.fix_type.as_str());
+        }
+    }
+    let (_, acquiring, Some(fix)) = state else {
+        return;
+    };
+    if acquiring {
+        let _ = html.push_str(" | last fix");
+    }
+    let _ = core::write!(
+        html,
+        " <strong>{:.5}, {:.5}</strong> | HDOP {:.1} | {:.1} m | {:.1} km/h | {} ({} s ago) | <a href='",
+        fix.latitude,
+        fix.longitude,
+        fix.hdop,
+        fix.altitude_m,
+        fix.speed_kmh,
+        fix.utc.as_str(),
+        now_ms.saturating_sub(fix.at_ms) / 1000
+    );
+    fix.write_osm_url(html);
+    let _ = html.push_str("'>map</a>");
+}
+
+// 链路延迟趋势: 每分钟 ping 一次, 保留最近 60 次 (约一小时)
+const PING_HOST: &str = "8.8.8.8";
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+const PING_TIMEOUT_S: u8 = 5;
+
+const PING_LOSS_ALARM: u32 = 3;
+static PINGS_LOST_IN_A_ROW: AtomicU32 = AtomicU32::new(0);
+
+static PINGS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<sparkline::Ring<60>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(sparkline::Ring::new()));
+
+// 每次 ping 前裸 AT 的往返时间
+static AT_RTT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<at_rtt::Rtt>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(at_rtt::Rtt::new()));
+
+// 漫游被禁止时不 ping, 也不算丢包
+async fn run_ping(tx: &mut ModemTx, rx: &mut ModemRx) {
+    measure_at_rtt(tx, rx).await;
+    if !cellular_data_allowed(tx, rx, registration::Feature::Ping).await {
+        return;
+    }
+    let modem = current_modem();
+    let command = modem.ping(PING_HOST, PING_TIMEOUT_S);
+    let mut rtt = None;
+    if uart_write_all(tx, command.as_bytes()).await.is_ok() {
+        let mut reader = LineReader::new();
+        let mut line = heapless::String::<128>::new();
+        let mut deadline = Instant::now() + Duration::from_secs(PING_TIMEOUT_S as u64 + 2);
+        let mut answered = false;
+        while reader.next_line(rx, deadline, &mut line).await {
+            let line = line.trim();
+            if answered {
+                continue;
+            }
+            if line == "ERROR" || line.starts_with("+CME ERROR") {
+                break;
+            }
+            if let Some(reply) = modem.parse_ping(line) {
+                rtt = reply;
+                answered = true;
+                // 统计行和 OK 可能还在后面, 读掉免得混进下一条指令的回复
+                deadline = Instant::now() + Duration::from_millis(300);
+            }
+        }
+    }
+    match rtt {
+        Some(ms) => debug!("Ping {}: {} ms", PING_HOST, ms),
+        None => warn!("Ping {}: lost", PING_HOST),
+    }
+    PINGS.lock(|p| p.borrow_mut().push(rtt));
+    bump_state_generation();
+
+    // 连续丢包视为链路故障, 之后第一次成功视为恢复
+    let lost = match rtt {
+        Some(_) => PINGS_LOST_IN_A_ROW.swap(0, Ordering::Relaxed),
+        None => PINGS_LOST_IN_A_ROW.fetch_add(1, Ordering::Relaxed) + 1,
+    };
+    match rtt {
+        None if lost == PING_LOSS_ALARM => {
+            notify(webhook::Event::ModemError, format_args!("{} pings to {} lost", lost, PING_HOST));
+        }
+        Some(ms) if lost >= PING_LOSS_ALARM => {
+            notify(webhook::Event::ModemRecovered, format_args!("ping {} ms after {} lost", ms, lost));
+        }
+        _ => {}
+    }
+}
+
+// /at 控制台等待最终结果行的时间
+const AT_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn handle_at_command(tx: &mut ModemTx, rx: &mut ModemRx, command: &str) {
+    info!("Processing AT command: {:?}", command);
+    
+    // 更新状态为发送中
+    {
+        let mut result = modem_result().await;
+        result.clear();
+        let _ = result.push_str("🔄 Sending command:\n");
+        let _ = result.push_str(command.trim());
+        let _ = result.push_str("\n\n⏳ Waiting for response...\n");
+    }
+    
+    // 发送AT命令
+    let cmd_bytes = command.as_bytes();
+    match uart_write_all(tx, cmd_bytes).await {
+        Ok(_) => {
+            info!("AT command sent successfully");
+            
+            // 读取响应, 直到出现最终结果行或超时
+            let mut raw = heapless::Vec::<u8, 1024>::new();
+            let mut parsed = 0;
+            let mut outcome = None;
+            let deadline = Instant::now() + AT_REPLY_TIMEOUT;
+            while outcome.is_none() && !raw.is_full() {
+                let mut buf = [0u8; 256];
+                let room = (raw.capacity() - raw.len()).min(buf.len());
+                match embassy_time::with_deadline(deadline, uart_read(rx, &mut buf[..room])).await {
+                    Ok(Ok(n)) => {
+                        trace!("Response chunk: {=[u8]:a}", &buf[..n]);
+                        let _ = raw.extend_from_slice(&buf[..n]);
+                        parsed += at_response::split(&raw[parsed..], |line, kind| {
+                            dispatch_urc(line);
+                            if kind.is_final() && outcome.is_none() {
+                                outcome = Some(kind);
+                            }
+                        });
+                    }
+                    Ok(Err(_)) => {}
+                    Err(_) => break,
+                }
+            }
+            let received = !raw.is_empty();
+            let total_bytes = raw.len();
+            let response = match core::str::from_utf8(&raw) {
+                Ok(text) => text,
+                Err(e) => core::str::from_utf8(&raw[..e.valid_up_to()]).unwrap_or(""),
+            };
+            rx_delivered(rx_audit::Sink::Console, response.len());
+            rx_lost(rx_audit::Loss::InvalidUtf8, total_bytes - response.len());
+            
+            // 更新结果
+            {
+                let mut result = modem_result().await;
+                result.clear();
+                
+                if received {
+                    let _ = result.push_str("📤 Command:\n");
+                    let _ = result.push_str(command.trim());
+                    push_at_help(&mut result, command);
+                    let _ = result.push_str("\n\n📥 Response (");
+                    let mut bytes_str = heapless::String::<10>::new();
+                    let _ = write_u32(&mut bytes_str, total_bytes as u32);
+                    let _ = result.push_str(bytes_str.as_str());
+                    let _ = result.push_str(" bytes):\n");
+                    utf8::push_truncated(&mut result, response);
+                    
+                    match outcome {
+                        Some(at_response::Kind::Success) => {
+                            let _ = result.push_str("\n\n✅ Command successful!");
+                        }
+                        Some(_) => {
+                            let _ = result.push_str("\n\n❌ Command failed");
+                        }
+                        None if response.trim().is_empty() => {
+                            let _ = result.push_str("\n\n⚠️ Empty response");
+                        }
+                        None => {}
+                    }
+                    push_at_decoded(&mut result, response);
+                } else {
+                    let _ = result.push_str("📤 Command:\n");
+                    let _ = result.push_str(command.trim());
+                    push_at_help(&mut result, command);
+                    let _ = result.push_str("\n\n❌ No response received\n");
+                    let _ = result.push_str("Possible issues:\n");
+                    let _ = result.push_str("1. ");
+                    push_wiring_hint(&mut *result);
+                    let _ = result.push_str("2. EC800K might be busy or not powered\n");
+                    let _ = result.push_str("3. Try resetting the EC800K module\n");
+                    push_uart_error_hint(&mut *result);
+                }
+            }
+        }
+        Err(e) => {
+            error!("Failed to send AT command: {}", e.as_str());
+            let mut result = modem_result().await;
+            result.clear();
+            let _ = result.push_str("❌ Failed to send AT command\n");
+            let _ = result.push_str("Error: ");
+            let _ = result.push_str(e.as_str());
+        }
+    }
+    
+    info!("AT command processing complete");
+}
+
+// 命令下面一行说明 (at_help), 不认得的命令什么都不加
+fn push_at_help<const N: usize>(out: &mut heapless::String<N>, command: &str) {
+    if let Some(help) = at_help::command(command) {
+        let _ = core::write!(out, "\nℹ️ {} (expect {})", help.about, help.answer);
+    }
+}
+
+// 认得的应答行逐行解读
+fn push_at_decoded<const N: usize>(out: &mut heapless::String<N>, response: &str) {
+    let mut first = true;
+    for line in response.lines() {
+        let mut note = heapless::String::<64>::new();
+        if !at_help::decode(line, &mut note) {
+            continue;
+        }
+        if first {
+            let _ = out.push_str("\n\n🔎 Decoded:");
+            first = false;
+        }
+        let _ = core::write!(out, "\n{} → {}", line.trim(), note);
+    }
+}
+
+// 调制解调器的串口; sim-modem 特性下换成通往模拟器 (modem_sim) 的管道, 其余代码不知道区别
+#[cfg(not(feature = "sim-modem"))]
+type ModemTx = BufferedUartTx;
+#[cfg(not(feature = "sim-modem"))]
+type ModemRx = BufferedUartRx;
+#[cfg(feature = "sim-modem")]
+type ModemTx = SimTx;
+#[cfg(feature = "sim-modem")]
+type ModemRx = SimRx;
+
+#[cfg(feature = "sim-modem")]
+type SimPipe = embassy_sync::pipe::Pipe<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, 2048>;
+// 固件 → 模拟器
+#[cfg(feature = "sim-modem")]
+static SIM_TO_MODEM: SimPipe = SimPipe::new();
+// 模拟器 → 固件
+#[cfg(feature = "sim-modem")]
+static SIM_FROM_MODEM: SimPipe = SimPipe::new();
+// POST /api/sim 设置, 从下一个收到的字节起生效
+#[cfg(feature = "sim-modem")]
+static SIM_FAULT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<modem_sim::Fault>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(modem_sim::Fault::None));
+
+// The UART halves' error type, so uart_read and uart_send need no change
+#[cfg(feature = "sim-modem")]
+struct SimTx;
+#[cfg(feature = "sim-modem")]
+struct SimRx;
+
+#[cfg(feature = "sim-modem")]
+impl embedded_io_async::ErrorType for SimTx {
+    type Error = embassy_rp::uart::Error;
+}
+
+#[cfg(feature = "sim-modem")]
+impl embedded_io_async::ErrorType for SimRx {
+    type Error = embassy_rp::uart::Error;
+}
+
+#[cfg(feature = "sim-modem")]
+impl Write for SimTx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(SIM_TO_MODEM.write(buf).await)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sim-modem")]
+impl Read for SimRx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(SIM_FROM_MODEM.read(buf).await)
+    }
+}
+
+// 模拟器: 收下固件发的字节, 回复到时间了再写回去
+#[cfg(feature = "sim-modem")]
+#[embassy_executor::task]
+async fn modem_sim_task() {
+    use embassy_futures::select::{Either, select};
+
+    let mut engine = modem_sim::Engine::new();
+    let mut input = [0u8; 64];
+    let mut output = [0u8; 256];
+    loop {
+        let due = engine.next_due_ms().map_or(Instant::MAX, Instant::from_millis);
+        if let Either::First(n) = select(SIM_TO_MODEM.read(&mut input), Timer::at(due)).await {
+            engine.set_fault(SIM_FAULT.lock(|f| f.get()));
+            let now = Instant::now().as_millis();
+            for &byte in &input[..n] {
+                engine.feed(byte, now);
+            }
+        }
+        loop {
+            let n = engine.take_due(Instant::now().as_millis(), &mut output);
+            if n == 0 {
+                break;
+            }
+            SIM_FROM_MODEM.write_all(&output[..n]).await;
+        }
+    }
+}
+
+// 所有串口读写都经过这里, 同时记录到 MODEM_LOG
+async fn uart_write_all(tx: &mut ModemTx, data: &[u8]) -> Result<(), TxError> {
+    queue_modem_log(Direction::Tx, data);
+    uart_send(tx, data).await
+}
+
+// 发送缓冲区这么久还没排空就算卡住 (模块流控, 接线错误)
+const UART_TX_STALL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy)]
+enum TxError {
+    Uart,
+    Stalled,
+}
+
+impl TxError {
+    fn as_str(self) -> &'static str {
+        match self {
+            TxError::Uart => "UART write error",
+            TxError::Stalled => "UART TX stalled (buffer not draining)",
+        }
+    }
+}
+
+static UART_TX_ERRORS: AtomicU32 = AtomicU32::new(0);
+static UART_TX_STALLS: AtomicU32 = AtomicU32::new(0);
+// 最近一次发送卡住, 下一次成功发送时清除
+static UART_TX_STALLED: AtomicBool = AtomicBool::new(false);
+// The buffered driver does not expose its fill level, so the backlog is
+// measured as the longest time a write took to drain
+static UART_TX_MAX_DRAIN_MS: AtomicU32 = AtomicU32::new(0);
+
+// Queue and drain; returns once the bytes are on the wire
+async fn uart_send(tx: &mut ModemTx, data: &[u8]) -> Result<(), TxError> {
+    let started = Instant::now();
+    let sent = with_timeout(UART_TX_STALL, async {
+        tx.write_all(data).await?;
+        tx.flush().await
+    })
+    .await;
+    match sent {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => {
+            UART_TX_ERRORS.fetch_add(1, Ordering::Relaxed);
+            return Err(TxError::Uart);
+        }
+        Err(_) => {
+            UART_TX_STALLS.fetch_add(1, Ordering::Relaxed);
+            if !UART_TX_STALLED.swap(true, Ordering::Relaxed) {
+                error!("UART TX stalled: {} bytes not sent within {} ms", data.len(), UART_TX_STALL.as_millis());
+                bump_state_generation();
+            }
+            return Err(TxError::Stalled);
+        }
+    }
+    if UART_TX_STALLED.swap(false, Ordering::Relaxed) {
+        info!("UART TX draining again");
+        bump_state_generation();
+    }
+    UART_TX_MAX_DRAIN_MS.fetch_max(started.elapsed().as_millis() as u32, Ordering::Relaxed);
+    UART_TX_BYTES.fetch_add(data.len() as u32, Ordering::Relaxed);
+    Ok(())
+}
+
+async fn uart_read(rx: &mut ModemRx, buf: &mut [u8]) -> Result<usize, embassy_rp::uart::Error> {
+    let n = rx.read(buf).await.inspect_err(|&e| note_uart_error(e))?;
+    UART_RX_BYTES.fetch_add(n as u32, Ordering::Relaxed);
+    RX_AUDIT.lock(|a| a.borrow_mut().read(n));
+    queue_modem_log(Direction::Rx, &buf[..n]);
+    Ok(n)
+}
+
+// 读到的字节交给了哪里, 丢在了哪里 (rx_audit); 每个调制解调器操作结束时核对
+static RX_AUDIT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<rx_audit::Audit>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(rx_audit::Audit::new()));
+
+fn rx_delivered(sink: rx_audit::Sink, bytes: usize) {
+    RX_AUDIT.lock(|a| a.borrow_mut().deliver(sink, bytes));
+}
+
+fn rx_lost(loss: rx_audit::Loss, bytes: usize) {
+    RX_AUDIT.lock(|a| a.borrow_mut().lose(loss, bytes));
+}
+
+fn check_rx_audit() {
+    if let Some(gap) = RX_AUDIT.lock(|a| a.borrow_mut().check()) {
+        warn!("UART RX accounting off by {} bytes", gap);
+    }
+}
+
+// 串口接收错误 (驱动已丢弃出错的字节), 概览页和 /metrics 显示计数
+static UART_ERRORS: uart_errors::Counters = uart_errors::Counters::new();
+
+fn note_uart_error(error: embassy_rp::uart::Error) {
+    let Some(kind) = uart_errors::Kind::from_uart(error) else {
+        return;
+    };
+    if UART_ERRORS.record(kind, Instant::now().as_millis()) {
+        warn!("UART {} error", kind.as_str());
+    }
+}
+
+// "overrun 0 | break 0 | parity 0 | framing 12"
+fn push_uart_error_counts<const N: usize>(out: &mut heapless::String<N>) {
+    for (i, kind) in uart_errors::Kind::ALL.into_iter().enumerate() {
+        let separator = if i > 0 { " | " } else { "" };
+        let _ = core::write!(out, "{}{} {}", separator, kind.as_str(), UART_ERRORS.get(kind));
+    }
+}
+
+// "lines 1520 | payload 0 | console 96 | boot 12 | lost: invalid_utf8 3 | unread 40"
+fn push_rx_audit<const N: usize>(out: &mut heapless::String<N>) {
+    RX_AUDIT.lock(|a| {
+        let audit = a.borrow();
+        for (i, sink) in rx_audit::Sink::ALL.into_iter().enumerate() {
+            let separator = if i > 0 { " | " } else { "" };
+            let _ = core::write!(out, "{}{} {}", separator, sink.as_str(), audit.delivered(sink));
+        }
+        let _ = out.push_str(" | lost:");
+        for loss in rx_audit::Loss::ALL.into_iter().filter(|&l| audit.lost(l) > 0) {
+            let _ = core::write!(out, " {} {}", loss.as_str(), audit.lost(loss));
+        }
+    });
+}
+
+// 没有回复时的排查提示里加上串口错误
+// "Check UART wiring (GP12→EC800K RX, GP13←EC800K TX)" for the active board
+fn push_wiring_hint<const N: usize>(out: &mut heapless::String<N>) {
+    let board = &board::ACTIVE;
+    let _ = core::write!(out, "Check UART wiring (GP{}→EC800K RX, GP{}←EC800K TX", board.modem_tx, board.modem_rx);
+    if let (Some(rts), Some(cts)) = (board.modem_rts, board.modem_cts) {
+        let _ = core::write!(out, ", RTS GP{}, CTS GP{}", rts, cts);
+    }
+    let _ = out.push_str(")\n");
+}
+
+fn push_uart_error_hint<const N: usize>(out: &mut heapless::String<N>) {
+    if UART_ERRORS.total() == 0 {
+        return;
+    }
+    let _ = out.push_str("UART errors so far: ");
+    push_uart_error_counts(out);
+    let _ = out.push('\n');
+    if UART_ERRORS.boot_framing_burst() {
+        let _ = core::writeln!(out, "Framing errors right after boot: the module is probably not at {} baud (check AT+IPR)", UART_BAUDRATE);
+    }
+}
+
+fn queue_modem_log(direction: Direction, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    MODEM_LOG_QUEUE.lock(|q| q.borrow_mut().push(direction, data));
+    MODEM_LOG_PENDING.signal(());
+
+    let now = Instant::now().as_millis() as u32;
+    CAPTURE.lock(|c| c.borrow_mut().record(direction, data, now));
+    let dir = match direction {
+        Direction::Tx => transcript::Dir::Tx,
+        Direction::Rx => transcript::Dir::Rx,
+    };
+    TRANSCRIPTS.lock(|t| t.borrow_mut().feed(dir, data, now as u64));
+}
+
+// 启动探测和每次获取的结构化记录 (/api/transcripts)
+static TRANSCRIPTS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<transcript::Transcripts>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(transcript::Transcripts::new()));
+
+fn begin_transcript(kind: transcript::Kind) {
+    TRANSCRIPTS.lock(|t| t.borrow_mut().begin(kind, Instant::now().as_millis()));
+}
+
+// No-op when nothing is being recorded
+fn finish_transcript(outcome: &str) {
+    TRANSCRIPTS.lock(|t| t.borrow_mut().finish(outcome, Instant::now().as_millis()));
+}
+
+const UART_RATE_WINDOW_SECS: u32 = 5;
+
+// 每个窗口采样一次串口计数, 计算两个方向的吞吐量
+#[embassy_executor::task]
+async fn uart_rate_task() {
+    let mut last_tx = UART_TX_BYTES.load(Ordering::Relaxed);
+    let mut last_rx = UART_RX_BYTES.load(Ordering::Relaxed);
+    loop {
+        Timer::after(Duration::from_secs(UART_RATE_WINDOW_SECS as u64)).await;
+
+        let tx = UART_TX_BYTES.load(Ordering::Relaxed);
+        let rx = UART_RX_BYTES.load(Ordering::Relaxed);
+        let tx_rate = tx.wrapping_sub(last_tx) / UART_RATE_WINDOW_SECS;
+        let rx_rate = rx.wrapping_sub(last_rx) / UART_RATE_WINDOW_SECS;
+        last_tx = tx;
+        last_rx = rx;
+
+        let old_tx = UART_TX_RATE.swap(tx_rate, Ordering::Relaxed);
+        let old_rx = UART_RX_RATE.swap(rx_rate, Ordering::Relaxed);
+        if old_tx != tx_rate || old_rx != rx_rate {
+            bump_state_generation();
+        }
+    }
+}
+
+// 把待写队列搬进日志环形缓冲区; 稍等片刻再搬, 一次处理多块
+#[embassy_executor::task]
+async fn modem_log_task() {
+    let mut chunk = [0u8; modem_log::MAX_RECORD];
+    loop {
+        MODEM_LOG_PENDING.wait().await;
+        Timer::after(Duration::from_millis(20)).await;
+
+        let mut log = held(lock_stats::Site::ModemLogRecord, MODEM_LOG.lock().await);
+        // 记录的时间是写入日志的时间, 比收发晚最多几十毫秒
+        let now = Instant::now().as_millis();
+        while let Some(record) = MODEM_LOG_QUEUE.lock(|q| q.borrow_mut().pop(&mut chunk)) {
+            match record {
+                modem_log::Record::Data(direction, len) => log.record(direction, &chunk[..len], now),
+                modem_log::Record::Dropped(count) => log.record_dropped(count, now),
+            }
+        }
+        MODEM_LOG_LINES.sender().send(log.next_seq());
+    }
+}
+
+fn make_uart_config(baudrate: u32, settings: &config::UartSettings) -> UartConfig {
+    let mut uart_config = UartConfig::default();
+    uart_config.baudrate = baudrate;
+    uart_config.data_bits = match settings.data_bits {
+        5 => embassy_rp::uart::DataBits::DataBits5,
+        6 => embassy_rp::uart::DataBits::DataBits6,
+        7 => embassy_rp::uart::DataBits::DataBits7,
+        _ => embassy_rp::uart::DataBits::DataBits8,
+    };
+    uart_config.stop_bits = match settings.stop_bits {
+        2 => embassy_rp::uart::StopBits::STOP2,
+        _ => embassy_rp::uart::StopBits::STOP1,
+    };
+    uart_config.parity = match settings.parity {
+        config::Parity::None => embassy_rp::uart::Parity::ParityNone,
+        config::Parity::Even => embassy_rp::uart::Parity::ParityEven,
+        config::Parity::Odd => embassy_rp::uart::Parity::ParityOdd,
+    };
+    uart_config
+}
+
+// 调试串口: 收到的内容写入 UART1_LOG, 读错误只计数
+#[embassy_executor::task]
+async fn uart1_task(mut rx: BufferedUartRx) {
+    let mut buf = [0u8; 256];
+    loop {
+        match rx.read(&mut buf).await {
+            Ok(n) => {
+                let mut log = held(lock_stats::Site::Uart1Log, UART1_LOG.lock().await);
+                log.record(Direction::Rx, &buf[..n], Instant::now().as_millis());
+            }
+            Err(_) => {
+                UART1_ERRORS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// Write to the debug port; None when it is read-only or not running
+async fn uart1_write(data: &[u8]) -> Option<usize> {
+    if !CONFIG.lock(|c| c.borrow().uart.debug_writes) {
+        return None;
+    }
+    let mut tx = held(lock_stats::Site::Uart1Write, UART1_TX.lock().await);
+    let tx = tx.as_mut()?;
+    tx.write_all(data).await.ok()?;
+    held(lock_stats::Site::Uart1Log, UART1_LOG.lock().await).record(Direction::Tx, data, Instant::now().as_millis());
+    Some(data.len())
+}
+
+// 日志检查点 (log.persist_min 分钟一次, 0 = 关闭)
+static LOG_CHECKPOINTS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<log_checkpoint::Checkpoints<{ flash_store::LOG_SECTORS }>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(log_checkpoint::Checkpoints::new()));
+
+static CHECKPOINT_BUF: embassy_sync::mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    [u8; flash_store::LOG_CAPACITY],
+> = embassy_sync::mutex::Mutex::new([0; flash_store::LOG_CAPACITY]);
+
+// 上次启动留下的日志扇区, 以及闪存磨损计数
+fn restore_log_checkpoints(store: &mut flash_store::Store) {
+    let mut found = heapless::Vec::<(usize, u32, u32), { flash_store::LOG_SECTORS }>::new();
+    for index in 0..flash_store::LOG_SECTORS {
+        if let Some(sector) = store.log_sector(index) {
+            store.restore_wear(sector.bytes_written, sector.sectors_erased);
+            let _ = found.push((index, sector.boot, sector.seq));
+        }
+    }
+    LOG_CHECKPOINTS.lock(|c| c.borrow_mut().restore(&found));
+    info!("Log checkpoints: {} sectors from earlier boots", found.len());
+}
+
+// Write what the log gained since the last checkpoint; false if there was
+// nothing to write, persistence is off, or the flash write failed
+async fn checkpoint_log(reboot: bool) -> bool {
+    if CONFIG.lock(|c| c.borrow().log.persist_min) == 0 {
+        return false;
+    }
+    let now = Instant::now().as_millis();
+    let mut data = held(lock_stats::Site::CheckpointBuffer, CHECKPOINT_BUF.lock().await);
+    let (len, end) = {
+        let log = held(lock_stats::Site::LogCheckpoint, MODEM_LOG.lock().await);
+        let (start, end) = (log.ring.start_offset(), log.ring.end_offset());
+        let due = LOG_CHECKPOINTS.lock(|c| c.borrow().due(start, end, data.len(), now, reboot));
+        let Some((first, end)) = due else {
+            return false;
+        };
+        (log.ring.read_at(first, &mut data[..(end - first) as usize]), end)
+    };
+
+    let (boot, (index, seq)) = LOG_CHECKPOINTS.lock(|c| {
+        let checkpoints = c.borrow();
+        (checkpoints.boot, checkpoints.slot())
+    });
+    let written = FLASH_STORE.lock(|s| match s.borrow_mut().as_mut() {
+        Some(store) => store.write_log(index, boot, seq, &data[..len]).is_ok(),
+        None => false,
+    });
+    if !written {
+        error!("Log checkpoint to flash failed");
+        return false;
+    }
+    LOG_CHECKPOINTS.lock(|c| c.borrow_mut().saved(end, now));
+    debug!("Log checkpoint {}: {} bytes to sector {}", seq, len, index);
+    true
+}
+
+// 运行统计: 本次启动的计数在原子量里, 累计值是启动时闪存里的加上本次的
+static STATS: stats::Counters = stats::Counters::new();
+
+static STATS_STORED: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<stats::Snapshot>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(stats::Snapshot::ZERO));
+
+fn lifetime_stats() -> stats::Snapshot {
+    STATS_STORED.lock(|s| s.get()).plus(&STATS.snapshot())
+}
+
+// Count this boot into the lifetime totals and write them back at once,
+// so a unit that keeps losing power within the hour still counts its boots
+fn load_stats(store: &mut flash_store::Store, watchdog_reset: bool) {
+    // 新版本写的记录更长, 多出的统计忽略
+    let mut buf = [0u8; 256];
+    let stored = store
+        .load(flash_store::Record::Stats, &mut buf)
+        .and_then(stats::Snapshot::decode)
+        .unwrap_or(stats::Snapshot::ZERO);
+    STATS_STORED.lock(|s| s.set(stored));
+    STATS.add(stats::Stat::Boots, 1);
+    if watchdog_reset {
+        warn!("The last reset was the watchdog");
+        STATS.add(stats::Stat::WatchdogResets, 1);
+    }
+    let lifetime = stored.plus(&STATS.snapshot());
+    if store.save(flash_store::Record::Stats, &lifetime.encode()).is_err() {
+        error!("Saving the lifetime stats failed");
+    }
+    info!("Boot {} of this unit", lifetime.get(stats::Stat::Boots));
+}
+
+fn save_stats() -> bool {
+    let saved = write_record(flash_store::Record::Stats, &lifetime_stats().encode());
+    if !saved {
+        error!("Saving the lifetime stats failed");
+    }
+    saved
+}
+
+// GET /stats: 本次启动和累计并排
+fn format_stats_html() -> heapless::String<4096> {
+    let mut html = heapless::String::new();
+    push_html_head(&mut html, "200 OK", None);
+    push_page_header(&mut html, "/stats", "Stats", Refresh::Off);
+    let _ = html.push_str("<h1>📊 Stats</h1>");
+    let _ = html.push_str("<p><a href='/api/stats'>JSON</a></p>");
+
+    let (boot, lifetime) = (STATS.snapshot(), lifetime_stats());
+    let _ = html.push_str("<table><tr><th></th><th>This boot</th><th>Lifetime</th></tr>");
+    for stat in stats::Stat::ALL {
+        let _ = core::write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            stat.label(),
+            boot.get(stat),
+            lifetime.get(stat)
+        );
+    }
+    let _ = html.push_str("<tr><td>Max temperature (chip)</td>");
+    for snapshot in [boot, lifetime] {
+        let _ = html.push_str("<td>");
+        match snapshot.max_temperature() {
+            Some(centi_c) => {
+                stats::write_temperature(&mut html, centi_c);
+                let _ = html.push_str(" °C");
+            }
+            None => {
+                let _ = html.push_str("-");
+            }
+        }
+        let _ = html.push_str("</td>");
+    }
+    let _ = html.push_str("</tr></table>");
+    let _ = core::write!(
+        html,
+        "<p>Lifetime totals are saved to flash at boot, every {} minutes and before a reboot; ",
+        stats::SAVE_EVERY_MS / 60_000
+    );
+    let _ = html.push_str("a power cut loses what was added since the last save. A factory reset keeps them.</p>");
+    let _ = html.push_str("</div></body></html>");
+
+    http::set_content_length(&mut html);
+    html
+}
+
+fn format_stats_json() -> heapless::String<1024> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let _ = core::write!(out, "{{\"uptime_s\":{},\"since_boot\":", Instant::now().as_secs());
+    stats::write_json(&mut out, &STATS.snapshot());
+    let _ = out.push_str(",\"lifetime\":");
+    stats::write_json(&mut out, &lifetime_stats());
+    let _ = core::write!(out, ",\"save_every_s\":{}}}", stats::SAVE_EVERY_MS / 1000);
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// 每分钟读一次片上温度, 每小时把累计统计写回闪存
+#[embassy_executor::task]
+async fn stats_task(
+    mut adc: embassy_rp::adc::Adc<'static, embassy_rp::adc::Async>,
+    mut sensor: embassy_rp::adc::Channel<'static>,
+) {
+    let mut next_save = Instant::now() + Duration::from_millis(stats::SAVE_EVERY_MS);
+    loop {
+        if let Ok(raw) = adc.read(&mut sensor).await {
+            STATS.temperature(stats::temperature_from_adc(raw));
+        }
+        if Instant::now() >= next_save {
+            save_stats();
+            next_save += Duration::from_millis(stats::SAVE_EVERY_MS);
+        }
+        Timer::after(Duration::from_secs(60)).await;
+    }
+}
+
+// 输入触发的记录, 以及每个输入上次动作的时间 (限速)
+static TRIGGER_LOG: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<trigger::Log>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(trigger::Log::new()));
+
+// One input: wait for a change, let it settle for debounce_ms, then act if
+// the level really changed
+#[embassy_executor::task(pool_size = trigger::MAX_TRIGGERS)]
+async fn trigger_task(index: usize, mut input: Input<'static>) {
+    let mut high = input.is_high();
+    loop {
+        input.wait_for_any_edge().await;
+        let debounce_ms = CONFIG.lock(|c| c.borrow().triggers[index].debounce_ms);
+        Timer::after(Duration::from_millis(debounce_ms as u64)).await;
+        if input.is_high() == high {
+            continue;
+        }
+        high = !high;
+        fire_trigger(index, high);
+    }
+}
+
+// 唤醒按钮: 接地为按下, 和触发输入一样消抖
+#[embassy_executor::task]
+async fn ap_wake_task(mut input: Input<'static>) {
+    loop {
+        input.wait_for_falling_edge().await;
+        Timer::after(Duration::from_millis(50)).await;
+        if input.is_low() {
+            AP_WAKE_BUTTON.signal(());
+            LED_CHANGED.signal(());
+            input.wait_for_high().await;
+        }
+    }
+}
+
+fn fire_trigger(index: usize, high: bool) {
+    let binding = CONFIG.lock(|c| c.borrow().triggers[index].clone());
+    if !binding.active() || !binding.edge.counts(high) {
+        return;
+    }
+    let name = trigger::NAMES[index];
+    let level = if high { "high" } else { "low" };
+    let now = Instant::now().as_millis();
+    let outcome = if !TRIGGER_LOG.lock(|l| l.borrow_mut().allow(index, binding.min_interval_s, now)) {
+        trigger::Outcome::RateLimited
+    } else {
+        let request = match binding.action {
+            trigger::Action::Off => None,
+            trigger::Action::Fetch => Some(ActionRequest::Fetch(fetch::Origin::Input)),
+            trigger::Action::Webhook => {
+                let mut text = heapless::String::<64>::new();
+                let _ = core::write!(text, "{} GP{} went {}", name, binding.gpio, level);
+                Some(ActionRequest::Webhook {
+                    event: webhook::Event::Input,
+                    text,
+                })
+            }
+            trigger::Action::Sms => {
+                let mut text = heapless::String::<96>::new();
+                let _ = core::write!(text, "{}: {} GP{} went {}", device_name(), name, binding.gpio, level);
+                Some(ActionRequest::Sms {
+                    number: binding.sms.clone(),
+                    text,
+                })
+            }
+        };
+        let dispatched = request.map(|request| dispatch_action(actions::Source::Input, request, None));
+        match (dispatched, binding.action) {
+            (Some(Ok(_)), _) => trigger::Outcome::Queued,
+            (Some(Err(actions::NOT_ALLOWED)), _) => trigger::Outcome::NotAllowed,
+            (_, trigger::Action::Webhook) => trigger::Outcome::NotSent,
+            (_, _) => trigger::Outcome::Busy,
+        }
+    };
+    info!(
+        "{}: GP{} went {}, {} {}",
+        name,
+        binding.gpio,
+        level,
+        binding.action.as_str(),
+        outcome.as_str()
+    );
+    TRIGGER_LOG.lock(|l| {
+        l.borrow_mut().record(trigger::Occurrence {
+            index,
+            gpio: binding.gpio,
+            high,
+            action: binding.action,
+            outcome,
+            at_ms: now,
+        })
+    });
+    bump_state_generation();
+}
+
+#[embassy_executor::task]
+async fn log_checkpoint_task() {
+    loop {
+        let minutes = CONFIG.lock(|c| c.borrow().log.persist_min);
+        // 关闭时每分钟看一次配置
+        Timer::after(Duration::from_secs(60 * minutes.max(1) as u64)).await;
+        checkpoint_log(false).await;
+    }
+}
+
+// /log/previous.txt: 上次启动最后存下的日志, 本次启动已覆盖的扇区不算
+async fn serve_previous_log(socket: &mut Conn<'_, '_>) {
+    let (boot, sectors) = LOG_CHECKPOINTS.lock(|c| c.borrow().previous());
+    let mut parts = heapless::Vec::<(usize, usize), { flash_store::LOG_SECTORS }>::new();
+    FLASH_STORE.lock(|s| {
+        if let Some(store) = s.borrow_mut().as_mut() {
+            for index in sectors {
+                if let Some(sector) = store.log_sector(index).filter(|sector| Some(sector.boot) == boot) {
+                    let _ = parts.push((index, sector.len));
+                }
+            }
+        }
+    });
+    if parts.is_empty() {
+        let response = format_short("404 Not Found", "text/plain", "No log saved from the previous boot\n");
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+
+    let mut header = heapless::String::<256>::new();
+    let _ = header.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = header.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    let _ = header.push_str("Content-Disposition: attachment; filename=\"ec800k-log-previous.txt\"\r\n");
+    let _ = core::write!(header, "Content-Length: {}\r\n", parts.iter().map(|p| p.1).sum::<usize>());
+    let _ = header.push_str("Connection: close\r\n\r\n");
+    if socket.write_all(header.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let mut chunk = [0u8; 256];
+    for (index, len) in parts {
+        let mut offset = 0;
+        while offset < len {
+            let n = (len - offset).min(chunk.len());
+            let read = FLASH_STORE.lock(|s| match s.borrow_mut().as_mut() {
+                Some(store) => store.read_log(index, offset, &mut chunk[..n]).is_ok(),
+                None => false,
+            });
+            // 长度已经发出, 读不到就只能断开
+            if !read || socket.write_all(&chunk[..n]).await.is_err() {
+                abort_conn(socket, conn_close::Close::Truncated);
+                return;
+            }
+            offset += n;
+        }
+    }
+    let _ = socket.flush().await;
+}
+
+fn flash_wear() -> (u64, u32) {
+    FLASH_STORE.lock(|s| s.borrow().as_ref().map_or((0, 0), |store| store.wear()))
+}
+
+fn push_checkpoint_html<const N: usize>(html: &mut heapless::String<N>) {
+    let minutes = CONFIG.lock(|c| c.borrow().log.persist_min);
+    let written = LOG_CHECKPOINTS.lock(|c| c.borrow().written);
+    let (bytes, erases) = flash_wear();
+    let _ = html.push_str("<p>💾 Log checkpoints: ");
+    if minutes == 0 {
+        let _ = html.push_str("off (config log.persist_min)");
+    } else {
+        let _ = core::write!(html, "every {} min, {} this boot", minutes, written);
+    }
+    let _ = core::write!(html, " | Flash wear: {} KiB written, {} sector erases", bytes / 1024, erases);
+    let _ = html.push_str(" | <a href='/log/previous.txt'>⬇️ Log from the previous boot</a></p>");
+}
+
+// 辅助函数：将u32写入字符串
+fn write_u32<const N: usize>(s: &mut heapless::String<N>, n: u32) -> Result<(), ()> {
+    let mut buffer = heapless::Vec::<u8, 10>::new();
+    let mut n = n;
+    
+    if n == 0 {
+        let _ = s.push_str("0");
+        return Ok(());
+    }
+    
+    while n > 0 {
+        let digit = (n % 10) as u8 + b'0';
+        let _ = buffer.push(digit);
+        n /= 10;
+    }
+    
+    for &digit in buffer.iter().rev() {
+        let _ = s.push(digit as char);
+    }
+    
+    Ok(())
+}
+
+// 事件通知队列; 串口任务空闲时发送
+static WEBHOOKS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<webhook::Queue>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(webhook::Queue::new()));
+
+static WEBHOOK_SIGNAL: embassy_sync::signal::Signal<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    (),
+> = embassy_sync::signal::Signal::new();
+
+const FETCH_FAILURE_ALARM: u32 = 3;
+
+// 连续失败计数和恢复步骤, 成功一次就清零
+static FETCH_LADDER: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<escalation::Ladder>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(escalation::Ladder::new()));
+
+// 下一次获取不用固定的 IP, 重新解析主机名 (恢复步骤 re_resolve)
+static FETCH_RESOLVE_HOST: AtomicBool = AtomicBool::new(false);
+
+// /http_get 选的目标 (None = httpbin.org/get), 留给这次获取的 SIM 解锁续传和恢复重试;
+// 成功或别的来源开始获取时清掉
+static FETCH_TARGET: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<Option<fetch_target::Target>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(None));
+
+// Queues a notification when a URL is set and the event class is enabled;
+// true when it was queued
+fn notify(event: webhook::Event, detail: core::fmt::Arguments) -> bool {
+    let enabled = CONFIG.lock(|c| {
+        let webhook = &c.borrow().webhook;
+        !webhook.url.is_empty() && webhook.events & event.bit() != 0
+    });
+    if !enabled {
+        return false;
+    }
+    let mut text = heapless::String::<64>::new();
+    let _ = core::write!(text, "{}", detail);
+    let now = Instant::now().as_millis();
+    if !WEBHOOKS.lock(|w| w.borrow_mut().fire(event, &text, now)) {
+        return false;
+    }
+    info!("Webhook queued: {} ({})", event.as_str(), text.as_str());
+    WEBHOOK_SIGNAL.signal(());
+    true
+}
+
+fn note_fetch_failure(reason: &str) {
+    finish_transcript(reason);
+    STATS.add(stats::Stat::Fetches, 1);
+    STATS.add(stats::Stat::FetchFailures, 1);
+    let (failures, step) = FETCH_LADDER.lock(|l| {
+        let mut ladder = l.borrow_mut();
+        let step = ladder.failed(Instant::now().as_millis());
+        (ladder.failures, step)
+    });
+    if failures == FETCH_FAILURE_ALARM {
+        notify(
+            webhook::Event::FetchFailures,
+            format_args!("{} fetches failed, last: {}", FETCH_FAILURE_ALARM, reason),
+        );
+    }
+    match step {
+        Some(escalation::Step::Backoff) => {
+            error!("{} fetches failed in a row after a modem restart, retrying hourly", failures);
+            FETCH_LADDER.lock(|l| l.borrow_mut().finished(escalation::Step::Backoff, true));
+            notify(
+                webhook::Event::FetchFailures,
+                format_args!("still failing after a modem restart, retrying hourly; last: {}", reason),
+            );
+        }
+        Some(step) => {
+            warn!("{} fetches failed in a row, recovery step: {}", failures, step.as_str());
+            submit_modem_op(ModemOp::Recover(step));
+        }
+        None => {}
+    }
+    bump_state_generation();
+}
+
+fn note_fetch_success() {
+    finish_transcript("ok");
+    STATS.add(stats::Stat::Fetches, 1);
+    if FETCH_LADDER.lock(|l| l.borrow_mut().succeeded()) {
+        info!("Fetch succeeded, recovery ladder reset");
+        bump_state_generation();
+    }
+}
+
+// One step of the escalation ladder, then a single retry fetch
+async fn run_recovery_step(tx: &mut ModemTx, rx: &mut ModemRx, step: escalation::Step) {
+    // the retry starts from a fresh connection
+    release_kept_connection(tx, rx).await;
+    let modem = current_modem();
+    let ok = match step {
+        escalation::Step::ReResolve => {
+            let flushed = DNS_CACHE.lock(|c| c.borrow_mut().flush());
+            FETCH_RESOLVE_HOST.store(true, Ordering::Relaxed);
+            info!("Recovery: DNS cache flushed ({} entries), next fetch resolves afresh", flushed);
+            true
+        }
+        escalation::Step::ResetPdp => {
+            quiet_command(tx, rx, &modem.deactivate_pdp(), Duration::from_secs(40)).await;
+            for command in modem.activate_pdp("CMNET") {
+                quiet_command(tx, rx, &command, Duration::from_secs(10)).await;
+            }
+            let mut active = false;
+            if quiet_query(tx, rx, &modem.pdp_state(), Duration::from_secs(5), |line| {
+                active |= modem.parse_pdp_state(line) == Some(true);
+            })
+            .await
+            {
+                PDP_ACTIVE.lock(|p| p.set(Some(active)));
+            }
+            active
+        }
+        escalation::Step::RestartModem => restart_modem(tx, rx).await,
+        escalation::Step::Backoff => true,
+    };
+    if ok {
+        info!("Recovery step {} done, retrying the fetch", step.as_str());
+    } else {
+        warn!("Recovery step {} failed, retrying the fetch anyway", step.as_str());
+    }
+    FETCH_LADDER.lock(|l| l.borrow_mut().finished(step, ok));
+    bump_state_generation();
+    submit_modem_op(ModemOp::Fetch(fetch::Origin::Recovery));
+}
+
+// AT+CFUN=1,1, then wait until the module answers AT again (it may reset
+// before replying OK)
+async fn restart_modem(tx: &mut ModemTx, rx: &mut ModemRx) -> bool {
+    warn!("Recovery: restarting the modem");
+    quiet_command(tx, rx, &current_modem().restart(), Duration::from_secs(5)).await;
+    PDP_ACTIVE.lock(|p| p.set(None));
+    Timer::after(Duration::from_secs(5)).await;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline {
+        if quiet_command(tx, rx, "AT\r\n", Duration::from_secs(1)).await {
+            return true;
+        }
+        Timer::after(Duration::from_secs(1)).await;
+    }
+    false
+}
+
+// 最近一次查询到的注册状态 (None = 还没查过)
+static REGISTRATION: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<registration::State>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+static ROAMING_BLOCKS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<registration::Blocks>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(registration::Blocks::new()));
+
+// 数据链路最近一次查询的结果 (None = 还没查过), /healthz 用
+static PDP_ACTIVE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<bool>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+// 最近一次成功的蜂窝交互 (获取或保活查询), 毫秒; 0 = 还没有
+static LAST_CELLULAR_OK_MS: AtomicU64 = AtomicU64::new(0);
+
+// POST /api/modem/allow-roaming, 重启后失效
+static ROAMING_ALLOWED: AtomicBool = AtomicBool::new(false);
+
+// Query CREG and CEREG; None when the module answered neither
+async fn refresh_registration(tx: &mut ModemTx, rx: &mut ModemRx) -> Option<registration::State> {
+    let modem = current_modem();
+    let mut state = None::<registration::State>;
+    for command in [modem.register(), modem.register_eps()] {
+        quiet_query(tx, rx, &command, Duration::from_secs(2), |line| {
+            if let Some(answer) = registration::parse(line) {
+                state = Some(state.map_or(answer, |s| s.merge(answer)));
+            }
+        })
+        .await;
+    }
+    let state = state?;
+    let previous = REGISTRATION.lock(|r| r.replace(Some(state)));
+    if previous != Some(state) {
+        info!("Network registration: {}", state.as_str());
+        bump_state_generation();
+    }
+    Some(state)
+}
+
+fn roaming_blocked(state: Option<registration::State>) -> bool {
+    state == Some(registration::State::Roaming)
+        && CONFIG.lock(|c| c.borrow().roaming.block_data)
+        && !ROAMING_ALLOWED.load(Ordering::Relaxed)
+}
+
+// Every feature that uses cellular data asks here first; a refusal is
+// counted against the feature
+async fn cellular_data_allowed(tx: &mut ModemTx, rx: &mut ModemRx, feature: registration::Feature) -> bool {
+    if !roaming_blocked(refresh_registration(tx, rx).await) {
+        return true;
+    }
+    let first = ROAMING_BLOCKS.lock(|b| {
+        let mut blocks = b.get();
+        let first = blocks.record(feature);
+        b.set(blocks);
+        first
+    });
+    if first {
+        warn!("Roaming: cellular data blocked for {}", feature.as_str());
+    } else {
+        debug!("Roaming: cellular data blocked for {}", feature.as_str());
+    }
+    bump_state_generation();
+    false
+}
+
+// 保活计数, 概览页和 /api/status 显示
+static KEEP_WARM: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<keep_warm::Stats>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(keep_warm::Stats::new()));
+
+fn keep_warm_host() -> heapless::String<64> {
+    let host = CONFIG.lock(|c| c.borrow().keep_warm.host.clone());
+    if host.is_empty() {
+        heapless::String::try_from(keep_warm::DEFAULT_HOST).unwrap_or_default()
+    } else {
+        host
+    }
+}
+
+// Check the data context (reactivating it if the carrier dropped it), then
+// resolve the keep-warm name; the answer itself is not used
+async fn run_keep_warm(tx: &mut ModemTx, rx: &mut ModemRx) {
+    if !cellular_data_allowed(tx, rx, registration::Feature::KeepWarm).await {
+        return;
+    }
+    let modem = current_modem();
+    let host = keep_warm_host();
+
+    let mut active = false;
+    let answered = quiet_query(tx, rx, &modem.pdp_state(), Duration::from_secs(5), |line| {
+        active |= modem.parse_pdp_state(line) == Some(true);
+    })
+    .await;
+    if answered {
+        PDP_ACTIVE.lock(|p| p.set(Some(active)));
+    }
+    let reactivated = answered && !active;
+    if reactivated {
+        warn!("Keep-warm: data context was down, reactivating");
+        for command in modem.activate_pdp("CMNET") {
+            quiet_command(tx, rx, &command, Duration::from_secs(10)).await;
+        }
+    }
+
+    // 模块先回 OK, 解析结果随后作为 URC 到达
+    let mut resolved = false;
+    if uart_write_all(tx, modem.resolve_dns(&host).as_bytes()).await.is_ok() {
+        let mut reader = LineReader::new();
+        let mut line = heapless::String::<128>::new();
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while reader.next_line(rx, deadline, &mut line).await {
+            let line = line.trim();
+            if line == "ERROR" || line.starts_with("+CME ERROR") {
+                break;
+            }
+            match modem.parse_dns(line) {
+                Some(modem::DnsReply::Address(_)) => {
+                    resolved = true;
+                    break;
+                }
+                Some(modem::DnsReply::Failed) => break,
+                _ => {}
+            }
+        }
+    }
+    if resolved {
+        debug!("Keep-warm lookup of {} answered", host.as_str());
+        PDP_ACTIVE.lock(|p| p.set(Some(true)));
+        LAST_CELLULAR_OK_MS.store(Instant::now().as_millis(), Ordering::Relaxed);
+    } else {
+        warn!("Keep-warm lookup of {} failed", host.as_str());
+    }
+
+    KEEP_WARM.lock(|k| {
+        let mut stats = k.get();
+        stats.record(&host, resolved, Instant::now().as_millis());
+        stats.reactivations += reactivated as u32;
+        k.set(stats);
+    });
+    bump_state_generation();
+}
+
+// 串口任务这一侧: 打开新的转发连接, 双向搬一轮数据, 关掉结束了的连接
+#[cfg(feature = "proxy")]
+async fn run_forwards(tx: &mut ModemTx, rx: &mut ModemRx) {
+    for index in 0..forward::MAX_FORWARDS {
+        match forward_state(index) {
+            forward::State::Connecting => open_forward(tx, rx, index).await,
+            state @ (forward::State::Open | forward::State::Closing) => exchange_forward(tx, rx, index, state).await,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(feature = "proxy")]
+async fn open_forward(tx: &mut ModemTx, rx: &mut ModemRx, index: usize) {
+    let name = forward::NAMES[index];
+    let rule = CONFIG.lock(|c| c.borrow().forwards[index].clone());
+    let id = forward::connect_id(index);
+    let started = Instant::now();
+    let outcome = connect_forward(tx, rx, id, &rule).await;
+    match outcome {
+        Ok(()) => {
+            info!("{}: connected to {}:{}", name, rule.host.as_str(), rule.port);
+            let connect_ms = started.elapsed().as_millis() as u32;
+            track_modem_socket(|m, now| m.opened(id, &rule.host, rule.port, connect_ms, now));
+        }
+        Err(e) => warn!("{}: {}", name, e),
+    }
+    let now = Instant::now().as_millis();
+    update_forward(index, |link| match outcome {
+        Ok(()) if link.state == forward::State::Connecting => link.set_state(forward::State::Open, now),
+        // 客户端在连接期间走了, 留在 Closing 等下一轮关掉
+        Ok(()) => {}
+        Err(e) => link.fail(e, now),
+    });
+    bump_state_generation();
+}
+
+#[cfg(feature = "proxy")]
+async fn connect_forward(
+    tx: &mut ModemTx,
+    rx: &mut ModemRx,
+    id: u8,
+    rule: &forward::Rule,
+) -> Result<(), &'static str> {
+    if !cellular_data_allowed(tx, rx, registration::Feature::Forward).await {
+        return Err("cellular data is blocked while roaming");
+    }
+    // 数据连接可能还没建立 (已激活时模块回 ERROR, 忽略)
+    let modem = current_modem();
+    for command in modem.activate_pdp("CMNET") {
+        quiet_command(tx, rx, &command, Duration::from_secs(10)).await;
+    }
+    let Some(ip) = resolve_forward_host(tx, rx, &rule.host).await else {
+        return Err("DNS lookup failed");
+    };
+
+    // 第一次读不等通知
+    FORWARD_PEER_CLOSED.fetch_and(!(1 << id), Ordering::Relaxed);
+    FORWARD_READABLE.fetch_or(1 << id, Ordering::Relaxed);
+    if uart_write_all(tx, modem.tcp_connect(id, &ip, rule.port).as_bytes()).await.is_err() {
+        return Err("UART write error");
+    }
+    let mut reader = LineReader::new();
+    let mut line = heapless::String::<128>::new();
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while reader.next_line(rx, deadline, &mut line).await {
+        let line = line.trim();
+        match modem.parse_connect(line, id) {
+            Some(0) => return Ok(()),
+            Some(_) => break,
+            None if line == "ERROR" => break,
+            None => {}
+        }
+    }
+    // 失败的连接可能半开着
+    quiet_command(tx, rx, &modem.tcp_close(id), Duration::from_secs(5)).await;
+    Err("TCP connection failed")
+}
+
+// 字面 IP, 缓存里的地址, 或者让模块解析 (结果写进缓存)
+#[cfg(feature = "proxy")]
+async fn resolve_forward_host(tx: &mut ModemTx, rx: &mut ModemRx, host: &str) -> Option<dns_cache::Address> {
+    if host.parse::<core::net::Ipv4Addr>().is_ok() {
+        return dns_cache::Address::try_from(host).ok();
+    }
+    match cached_address(host) {
+        dns_cache::Lookup::Hit(ip) => return Some(ip),
+        dns_cache::Lookup::Failed => return None,
+        dns_cache::Lookup::Miss => {}
+    }
+
+    let modem = current_modem();
+    let mut addresses = heapless::Vec::<dns_cache::Address, { dns_cache::MAX_ADDRESSES }>::new();
+    let mut ttl_s = None;
+    let mut awaiting = 1;
+    let mut failed = false;
+    if uart_write_all(tx, modem.resolve_dns(host).as_bytes()).await.is_ok() {
+        let mut reader = LineReader::new();
+        let mut line = heapless::String::<128>::new();
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while awaiting > 0 && reader.next_line(rx, deadline, &mut line).await {
+            let line = line.trim();
+            match modem.parse_dns(line) {
+                Some(modem::DnsReply::Address(ip)) => {
+                    if let Ok(ip) = dns_cache::Address::try_from(ip) {
+                        let _ = addresses.push(ip);
+                    }
+                    awaiting -= 1;
+                }
+                Some(modem::DnsReply::Pending { count, ttl_s: ttl }) => {
+                    awaiting = count.clamp(1, dns_cache::MAX_ADDRESSES);
+                    ttl_s = ttl;
+                }
+                Some(modem::DnsReply::Failed) => failed = true,
+                None if line == "ERROR" => failed = true,
+                None => {}
+            }
+            if failed {
+                break;
+            }
+        }
+    }
+    // 超时不缓存, 模块可能只是慢
+    if failed || !addresses.is_empty() {
+        DNS_CACHE.lock(|c| c.borrow_mut().insert(host, &addresses, ttl_s, Instant::now().as_millis()));
+    }
+    addresses.first().cloned()
+}
+
+// 每轮每个方向最多搬这么多块, 其余留到下一轮, 别的操作不会等太久
+#[cfg(feature = "proxy")]
+const FORWARD_CHUNKS_PER_POLL: usize = 4;
+
+#[cfg(feature = "proxy")]
+async fn exchange_forward(tx: &mut ModemTx, rx: &mut ModemRx, index: usize, state: forward::State) {
+    let id = forward::connect_id(index);
+    let mut chunk = heapless::Vec::<u8, { forward::CHUNK }>::new();
+    let mut error = None;
+    let mut remote_done = false;
+
+    // 客户端 → 远端, Closing 时也把剩下的发完; 令牌不够的留到下一轮
+    for _ in 0..FORWARD_CHUNKS_PER_POLL {
+        let allowed = shaper_allowance(shaper::Direction::Up, forward::CHUNK);
+        if allowed == 0 {
+            break;
+        }
+        let _ = chunk.resize(allowed, 0);
+        let n = FORWARD_UP[index].try_read(&mut chunk).unwrap_or(0);
+        chunk.truncate(n);
+        if n == 0 {
+            break;
+        }
+        if !send_forward_chunk(tx, rx, id, &chunk).await {
+            error = Some("send failed");
+            break;
+        }
+        shaper_spend(shaper::Direction::Up, n);
+        update_forward(index, |l| l.to_remote += n as u32);
+        track_modem_socket(|m, now| m.sent(id, n, now));
+        STATS.add(stats::Stat::CellularBytes, n as u64);
+    }
+
+    // 远端 → 客户端, 只读模块报告过有数据的连接; 对方关闭后先把模块里剩下的数据读完
+    if state == forward::State::Open && error.is_none() {
+        for _ in 0..FORWARD_CHUNKS_PER_POLL {
+            let room = shaper_allowance(shaper::Direction::Down, FORWARD_DOWN[index].free_capacity().min(forward::CHUNK));
+            if room == 0 {
+                break;
+            }
+            // 先清掉再读: 读的时候到的通知不会丢
+            if FORWARD_READABLE.fetch_and(!(1 << id), Ordering::Relaxed) & (1 << id) == 0 {
+                break;
+            }
+            let Ok(closed) = read_forward_chunk(tx, rx, id, room, &mut chunk).await else {
+                error = Some("read failed");
+                break;
+            };
+            if closed {
+                update_forward(index, |l| l.remote_closed = true);
+            }
+            if chunk.is_empty() {
+                remote_done = FORWARD_LINKS.lock(|l| l.borrow()[index].remote_closed);
+                break;
+            }
+            // 可能还有
+            FORWARD_READABLE.fetch_or(1 << id, Ordering::Relaxed);
+            let _ = FORWARD_DOWN[index].try_write(&chunk);
+            shaper_spend(shaper::Direction::Down, chunk.len());
+            update_forward(index, |l| l.from_remote += chunk.len() as u32);
+            track_modem_socket(|m, now| m.received(id, chunk.len(), now));
+            STATS.add(stats::Stat::CellularBytes, chunk.len() as u64);
+        }
+    }
+
+    if state == forward::State::Closing || remote_done || error.is_some() {
+        let modem = current_modem();
+        quiet_command(tx, rx, &modem.tcp_close(id), Duration::from_secs(5)).await;
+        let reason = match error {
+            Some(_) => modem_sockets::CloseReason::Error,
+            None if remote_done => modem_sockets::CloseReason::Peer,
+            None => modem_sockets::CloseReason::Us,
+        };
+        track_modem_socket(|m, now| m.closed(id, reason, now));
+        let now = Instant::now().as_millis();
+        update_forward(index, |link| match error {
+            Some(error) => link.fail(error, now),
+            None => link.set_state(forward::State::Closed, now),
+        });
+        info!("{}: modem connection closed", forward::NAMES[index]);
+        bump_state_generation();
+    }
+}
+
+// 发送一块数据; false 表示模块拒绝或者 SEND FAIL
+#[cfg(feature = "proxy")]
+async fn send_forward_chunk(tx: &mut ModemTx, rx: &mut ModemRx, id: u8, data: &[u8]) -> bool {
+    let modem = current_modem();
+    if uart_write_all(tx, modem.tcp_send(id, data.len()).as_bytes()).await.is_err() {
+        return false;
+    }
+    let mut reader = LineReader::new();
+    if !reader.expect_prompt(rx, Instant::now() + Duration::from_secs(5)).await {
+        return false;
+    }
+    if uart_write_all(tx, data).await.is_err() {
+        return false;
+    }
+    let mut line = heapless::String::<128>::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while reader.next_line(rx, deadline, &mut line).await {
+        let line = line.trim();
+        match modem.parse_send(line, id) {
+            Some(sent) => return sent,
+            None if line == "ERROR" => return false,
+            None => {}
+        }
+    }
+    false
+}
+
+// 读取最多 `max` 字节到 `out`; Ok(true) 表示看到了对方关闭的通知, Err 表示连接已经没了
+#[cfg(feature = "proxy")]
+async fn read_forward_chunk(
+    tx: &mut ModemTx,
+    rx: &mut ModemRx,
+    id: u8,
+    max: usize,
+    out: &mut heapless::Vec<u8, { forward::CHUNK }>,
+) -> Result<bool, ()> {
+    out.clear();
+    let modem = current_modem();
+    if uart_write_all(tx, modem.tcp_recv(id, max).as_bytes()).await.is_err() {
+        return Err(());
+    }
+    let mut reader = LineReader::new();
+    let mut line = heapless::String::<128>::new();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while reader.next_line(rx, deadline, &mut line).await {
+        let line = line.trim();
+        if let Some(len) = modem.parse_recv(line) {
+            if !reader.read_bytes(rx, len, deadline, out).await {
+                return Err(());
+            }
+        } else if line == "OK" {
+            // 关闭通知由 LineReader 记下, 可能在之前任何一条指令的回复里
+            return Ok(FORWARD_PEER_CLOSED.fetch_and(!(1 << id), Ordering::Relaxed) & (1 << id) != 0);
+        } else if line == "ERROR" {
+            return Err(());
+        }
+    }
+    Err(())
+}
+
+// Times a bare AT to its OK; no sample when it does not answer
+async fn measure_at_rtt(tx: &mut ModemTx, rx: &mut ModemRx) {
+    let started = Instant::now();
+    if !quiet_command(tx, rx, "AT\r\n", Duration::from_secs(2)).await {
+        return;
+    }
+    let ms = started.elapsed().as_millis() as u32;
+    AT_RTT.lock(|r| r.borrow_mut().record(ms, Instant::now().as_millis()));
+    let warn_ms = CONFIG.lock(|c| c.borrow().health.at_rtt_warn_ms);
+    if warn_ms > 0 && ms > warn_ms {
+        warn!("AT round trip took {} ms (health.at_rtt_warn_ms = {})", ms, warn_ms);
+    }
+}
+
+// 不显示在结果区的指令, 等到最终结果行或超时
+async fn quiet_command(tx: &mut ModemTx, rx: &mut ModemRx, command: &str, timeout: Duration) -> bool {
+    quiet_query(tx, rx, command, timeout, |_| {}).await
+}
+
+// Reads reply lines until one satisfies `wanted`; false on timeout
+async fn await_line(rx: &mut ModemRx, timeout: Duration, wanted: impl Fn(&str) -> bool) -> bool {
+    let mut reader = LineReader::new();
+    let mut line = heapless::String::<128>::new();
+    let deadline = Instant::now() + timeout;
+    while reader.next_line(rx, deadline, &mut line).await {
+        if wanted(line.trim()) {
+            return true;
+        }
+    }
+    false
+}
+
+// Like `quiet_command`, every reply line (final one included) goes to `on_line`
+async fn quiet_query(
+    tx: &mut ModemTx,
+    rx: &mut ModemRx,
+    command: &str,
+    timeout: Duration,
+    on_line: impl FnMut(&str),
+) -> bool {
+    if uart_write_all(tx, command.as_bytes()).await.is_err() {
+        return false;
+    }
+    await_final(rx, timeout, on_line).await
+}
+
+// True when the reply ends in OK
+async fn await_final(rx: &mut ModemRx, timeout: Duration, mut on_line: impl FnMut(&str)) -> bool {
+    let mut reader = LineReader::new();
+    let mut line = heapless::String::<128>::new();
+    let deadline = Instant::now() + timeout;
+    while reader.next_line(rx, deadline, &mut line).await {
+        let line = line.trim();
+        on_line(line);
+        let kind = at_response::classify(line);
+        if kind.is_final() {
+            return kind == at_response::Kind::Success;
+        }
+    }
+    false
+}
+
+// 主机名解析缓存, 获取前先查
+static DNS_CACHE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<dns_cache::Cache>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(dns_cache::Cache::new()));
+
+fn cached_address(host: &str) -> dns_cache::Lookup {
+    DNS_CACHE.lock(|c| c.borrow_mut().lookup(host, Instant::now().as_millis()))
+}
+
+// Cache what a fetch without a known address resolved; a timeout is not
+// cached, the module may just have been slow
+fn remember_lookup(host: &str, fetch: &fetch::Fetch<'_>, outcome: Result<(), fetch::Error>) {
+    let (addresses, ttl_s) = fetch.resolved();
+    if addresses.is_empty() && outcome != Err(fetch::Error::ResolveFailed) {
+        return;
+    }
+    DNS_CACHE.lock(|c| c.borrow_mut().insert(host, addresses, ttl_s, Instant::now().as_millis()));
+}
+
+// GET /api/dnscache
+fn format_dns_cache_json() -> heapless::String<2048> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    DNS_CACHE.lock(|c| dns_cache::write_json(&mut out, &c.borrow(), Instant::now().as_millis()));
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// 一次 POST; 2xx 算成功
+async fn post_webhook(tx: &mut ModemTx, rx: &mut ModemRx, notification: &webhook::Notification) -> bool {
+    let url = CONFIG.lock(|c| c.borrow().webhook.url.clone());
+    let Some(target) = webhook::parse_url(&url) else {
+        return false;
+    };
+    let mut body = heapless::String::<256>::new();
+    #[cfg(feature = "gnss")]
+    notification.write_body(&mut body, &device_name(), GNSS.lock(|g| g.borrow().last_fix.clone()).as_ref());
+    #[cfg(not(feature = "gnss"))]
+    notification.write_body(&mut body, &device_name());
+    let mut request = heapless::String::<512>::new();
+    if !webhook::write_request(&mut request, &target, &body) {
+        return false;
+    }
+    if !cellular_data_allowed(tx, rx, registration::Feature::Webhook).await {
+        return false;
+    }
+
+    // 数据连接可能还没建立 (已激活时模块回 ERROR, 忽略)
+    let modem = current_modem();
+    for command in modem.activate_pdp("CMNET") {
+        quiet_command(tx, rx, &command, Duration::from_secs(10)).await;
+    }
+
+    let started = Instant::now();
+    let literal = target.host.parse::<core::net::Ipv4Addr>().is_ok();
+    let cached = if literal { dns_cache::Lookup::Miss } else { cached_address(target.host) };
+    let ip = match &cached {
+        _ if literal => Some(target.host),
+        dns_cache::Lookup::Hit(ip) => Some(ip.as_str()),
+        dns_cache::Lookup::Failed => {
+            warn!("Webhook {}: DNS lookup failed recently, not retrying yet", notification.event.as_str());
+            return false;
+        }
+        dns_cache::Lookup::Miss => None,
+    };
+    let mut fetch = fetch::Fetch::new(modem, fetch::Target {
+        host: target.host,
+        ip,
+        port: target.port,
+        request: request.as_bytes(),
+    });
+    let mut reply = heapless::String::<1024>::new();
+    let outcome = run_fetch(tx, rx, &mut fetch, &mut reply, fetch::Origin::Webhook, started, false).await;
+    if ip.is_none() {
+        remember_lookup(target.host, &fetch, outcome);
+    }
+    let status = reply.split(' ').nth(1).unwrap_or("");
+    match outcome {
+        Ok(()) if status.starts_with('2') => true,
+        Ok(()) => {
+            warn!("Webhook {}: HTTP status {}", notification.event.as_str(), status);
+            false
+        }
+        Err(e) => {
+            let mut reason = heapless::String::<48>::new();
+            e.describe(&mut reason);
+            warn!("Webhook {}: {}", notification.event.as_str(), reason.as_str());
+            false
+        }
+    }
+}
+
+async fn send_due_webhooks(tx: &mut ModemTx, rx: &mut ModemRx) {
+    while let Some(notification) = WEBHOOKS.lock(|w| w.borrow_mut().take_due(Instant::now().as_millis())) {
+        let sent = post_webhook(tx, rx, &notification).await;
+        if sent {
+            info!("Webhook sent: {}", notification.event.as_str());
+        }
+        WEBHOOKS.lock(|w| {
+            let mut queue = w.borrow_mut();
+            if sent {
+                queue.sent += 1;
+            } else {
+                queue.retry(notification, Instant::now().as_millis());
+            }
+        });
+    }
+}
+
+// 中继下载 (relay): 状态, 模块到客户端的正文管道, 以及状态变化的通知
+static RELAY: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<relay::Relay>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(relay::Relay::new()));
+
+static RELAY_PIPE: embassy_sync::pipe::Pipe<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, 1024> =
+    embassy_sync::pipe::Pipe::new();
+
+static RELAY_EVENT: embassy_sync::signal::Signal<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, ()> =
+    embassy_sync::signal::Signal::new();
+
+// ModemOp::Relay; Err is the reason for the action log
+async fn run_relay(tx: &mut ModemTx, rx: &mut ModemRx) -> Result<(), &'static str> {
+    let Some(url) = RELAY.lock(|r| r.borrow().start()) else {
+        RELAY.lock(|r| r.borrow_mut().finish(Some("client gone")));
+        return Ok(());
+    };
+    let outcome = relay_fetch(tx, rx, &url).await;
+    let error = outcome.err();
+    RELAY.lock(|r| r.borrow_mut().finish(error));
+    RELAY_EVENT.signal(());
+    match error {
+        Some(reason) => warn!("Relay of {} failed: {}", url.as_str(), reason),
+        None => info!("Relayed {}", url.as_str()),
+    }
+    outcome
+}
+
+async fn relay_fetch(tx: &mut ModemTx, rx: &mut ModemRx, url: &str) -> Result<(), &'static str> {
+    let target = webhook::parse_url(url).ok_or("bad URL")?;
+    let mut request = heapless::String::<384>::new();
+    core::write!(request, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", target.path, target.host)
+        .map_err(|_| "URL too long")?;
+    if !cellular_data_allowed(tx, rx, registration::Feature::Fetch).await {
+        return Err("cellular data blocked while roaming");
+    }
+
+    // 数据连接可能还没建立 (已激活时模块回 ERROR, 忽略)
+    let modem = current_modem();
+    for command in modem.activate_pdp("CMNET") {
+        quiet_command(tx, rx, &command, Duration::from_secs(10)).await;
+    }
+
+    let started = Instant::now();
+    let literal = target.host.parse::<core::net::Ipv4Addr>().is_ok();
+    let cached = if literal { dns_cache::Lookup::Miss } else { cached_address(target.host) };
+    let ip = match &cached {
+        _ if literal => Some(target.host),
+        dns_cache::Lookup::Hit(ip) => Some(ip.as_str()),
+        dns_cache::Lookup::Failed => return Err("DNS lookup failed recently"),
+        dns_cache::Lookup::Miss => None,
+    };
+    let mut fetch = fetch::Fetch::new(modem, fetch::Target {
+        host: target.host,
+        ip,
+        port: target.port,
+        request: request.as_bytes(),
+    });
+    let mut preview = heapless::String::<1024>::new();
+    let outcome = run_fetch(tx, rx, &mut fetch, &mut preview, fetch::Origin::Relay, started, false).await;
+    if ip.is_none() {
+        remember_lookup(target.host, &fetch, outcome);
+    }
+    match outcome {
+        Ok(()) => Ok(()),
+        Err(fetch::Error::Cancelled(_)) => Err("client gone"),
+        Err(_) => Err("upstream fetch failed"),
+    }
+}
+
+// 一块响应交给 /relay 的客户端: 头部在这里解析, 正文进管道. 管道满了就在这里等,
+// 下一次 AT+QIRD 也跟着等. false 表示客户端已经走了
+async fn relay_forward(data: &[u8]) -> bool {
+    let Some((body_at, head_ready)) = RELAY.lock(|r| {
+        let mut relay = r.borrow_mut();
+        if relay.abandoned {
+            return None;
+        }
+        let before = relay.head.done();
+        let used = relay.head.feed(data);
+        let ready = !before && relay.head.done();
+        if ready {
+            relay.state = relay::State::Streaming;
+        }
+        Some((used, ready))
+    }) else {
+        return false;
+    };
+    if head_ready {
+        RELAY_EVENT.signal(());
+    }
+    let body = &data[body_at..];
+    STATS.add(stats::Stat::RelayBytes, body.len() as u64);
+    if with_timeout(Duration::from_millis(relay::STALL_MS), RELAY_PIPE.write_all(body)).await.is_err() {
+        warn!("Relay client took nothing for {} s, giving up", relay::STALL_MS / 1000);
+        RELAY.lock(|r| r.borrow_mut().abandoned = true);
+        return false;
+    }
+    true
+}
+
+// The client side is done; frees the relay unless the modem task still
+// has to notice
+fn release_relay() {
+    let withdrawn = MODEM_OPS.lock(|q| q.borrow_mut().remove("relay")).is_some();
+    let running = !withdrawn && MODEM_CURRENT.lock(|c| c.get()) == Some("relay");
+    RELAY.lock(|r| r.borrow_mut().release(running));
+}
+
+// GET /relay?url=<encoded>: the upstream status and Content-Type, then the
+// body in chunks as the modem reads it
+async fn serve_relay(socket: &mut Conn<'_, '_>, query: &str, request_id: u32) {
+    let url = http::form_value(query, "url").and_then(http::percent_decode::<{ relay::URL_MAX }>);
+    let Some(url) = url.filter(|url| webhook::parse_url(url).is_some()) else {
+        let response = format_short("400 Bad Request", "text/plain", "url must be http://host[:port][/path]\n");
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    };
+    if !RELAY.lock(|r| r.borrow_mut().begin(&url)) {
+        let response = format_short("503 Service Unavailable", "text/plain", "another relay is running\n");
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+    RELAY_PIPE.clear();
+    RELAY_EVENT.reset();
+    if !submit_modem_op_for(ModemOp::Relay, Some(request_id)) {
+        release_relay();
+        let response = format_short("503 Service Unavailable", "text/plain", "modem busy, try again shortly\n");
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+    info!("Relay of {} queued{}", url.as_str(), request_tag(Some(request_id)));
+
+    let deadline = Instant::now() + Duration::from_millis(relay::HEAD_WAIT_MS);
+    let state = loop {
+        let state = RELAY.lock(|r| r.borrow().state);
+        if state != relay::State::Queued || embassy_time::with_deadline(deadline, RELAY_EVENT.wait()).await.is_err() {
+            break state;
+        }
+    };
+    let chunked = socket.chunked();
+    let head = RELAY.lock(|r| {
+        let relay = r.borrow();
+        if !relay.head.done() || relay.head.status == 0 {
+            let reason = match state {
+                relay::State::Queued => "timed out",
+                relay::State::Failed => relay.error.as_str(),
+                _ => "upstream did not answer with HTTP",
+            };
+            let mut body = heapless::String::<64>::new();
+            let _ = core::writeln!(body, "relay failed: {}", reason);
+            return Err(body);
+        }
+        let mut header = heapless::String::<512>::new();
+        let head = &relay.head;
+        let _ = core::write!(header, "HTTP/1.1 {} {}\r\n", head.status, head.reason);
+        let content_type = if head.content_type.is_empty() { "application/octet-stream" } else { &head.content_type };
+        let _ = core::write!(header, "Content-Type: {}\r\n", content_type);
+        http::push_unknown_length(&mut header, chunked);
+        let _ = header.push_str("Cache-Control: no-store\r\n");
+        let _ = header.push_str("Connection: close\r\n\r\n");
+        Ok(header)
+    });
+    let header = match head {
+        Ok(header) => header,
+        Err(body) => {
+            release_relay();
+            let response = format_short("502 Bad Gateway", "text/plain", &body);
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+            return;
+        }
+    };
+    if socket.write_all(header.as_bytes()).await.is_err() {
+        release_relay();
+        return;
+    }
+
+    let mut writer = http::ChunkedWriter::new(socket, chunked);
+    let mut buf = [0u8; 512];
+    let complete = loop {
+        match with_timeout(Duration::from_millis(500), RELAY_PIPE.read(&mut buf)).await {
+            Ok(n) => {
+                if writer.write_chunk(&buf[..n]).await.is_err() {
+                    break false;
+                }
+            }
+            // 管道空了: 看模块那边是不是已经结束
+            Err(_) => match RELAY.lock(|r| r.borrow().state) {
+                relay::State::Done if RELAY_PIPE.is_empty() => break true,
+                relay::State::Failed | relay::State::Idle if RELAY_PIPE.is_empty() => break false,
+                _ => {}
+            },
+        }
+    };
+    release_relay();
+    if complete {
+        let _ = writer.finish().await;
+    } else {
+        // 没有结束块: 客户端知道传输不完整
+        let _ = socket.flush().await;
+    }
+}
+
+// SIM 状态; 锁定时获取流程停下, 等状态页输入 PIN/PUK
+static SIM: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<sim::SimStatus>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(sim::SimStatus::new()));
+
+// 因 SIM 锁定而中断的获取, 解锁后自动重新开始
+static FETCH_WAITING_FOR_SIM: AtomicBool = AtomicBool::new(false);
+
+// 存储的 PIN 每次启动最多自动提交一次
+static STORED_PIN_TRIED: AtomicBool = AtomicBool::new(false);
+
+fn sim_status() -> sim::SimStatus {
+    SIM.lock(|s| s.get())
+}
+
+// AT+CPIN?, plus the attempt counters while the SIM is locked
+async fn check_sim(tx: &mut ModemTx, rx: &mut ModemRx) -> sim::SimState {
+    let mut state = sim::SimState::Unknown;
+    quiet_query(tx, rx, "AT+CPIN?\r\n", Duration::from_secs(5), |line| {
+        if let Some(parsed) = sim::parse_cpin(line) {
+            state = parsed;
+        }
+    })
+    .await;
+
+    let mut counters = None;
+    if state.locked() {
+        let modem = current_modem();
+        quiet_query(tx, rx, &modem.pin_counter(), Duration::from_secs(2), |line| {
+            counters = counters.or(modem.parse_pin_counter(line));
+        })
+        .await;
+    }
+
+    SIM.lock(|s| {
+        let mut status = s.get();
+        status.state = state;
+        status.pin_left = counters.map(|c| c.0);
+        status.puk_left = counters.map(|c| c.1);
+        s.set(status);
+    });
+    bump_state_generation();
+    info!("SIM: {}", state.as_str());
+    state
+}
+
+// 带 PIN 的指令: 日志和抓包里只留打码的副本
+async fn uart_write_secret(tx: &mut ModemTx, command: &str) -> Result<(), TxError> {
+    let mut masked = heapless::String::<96>::new();
+    macros::push_masked(&mut masked, command.trim_end());
+    let _ = masked.push_str("\r\n");
+    queue_modem_log(Direction::Tx, masked.as_bytes());
+    uart_send(tx, command.as_bytes()).await
+}
+
+// AT+CPIN with echo off; true when the SIM accepted the code
+async fn submit_sim_code(tx: &mut ModemTx, rx: &mut ModemRx, unlock: &sim::Unlock) -> bool {
+    let Some(command) = unlock.command() else {
+        return false;
+    };
+
+    // 关闭回显, 否则模块会把 PIN 原样回显进日志
+    quiet_command(tx, rx, "ATE0\r\n", Duration::from_secs(1)).await;
+    let accepted = uart_write_secret(tx, &command).await.is_ok() && await_final(rx, Duration::from_secs(10), |_| {}).await;
+    quiet_command(tx, rx, "ATE1\r\n", Duration::from_secs(1)).await;
+
+    SIM.lock(|s| {
+        let mut status = s.get();
+        status.last_unlock_failed = !accepted;
+        s.set(status);
+    });
+    if accepted {
+        // 解锁后 SIM 需要一点时间初始化
+        Timer::after(Duration::from_secs(2)).await;
+    }
+    accepted
+}
+
+async fn unlock_sim(tx: &mut ModemTx, rx: &mut ModemRx, unlock: &sim::Unlock) {
+    let accepted = submit_sim_code(tx, rx, unlock).await;
+    let state = check_sim(tx, rx).await;
+
+    let mut result = modem_result().await;
+    result.clear();
+    if !accepted {
+        warn!("SIM unlock rejected");
+        let _ = result.push_str("❌ The SIM rejected the code. Check it before trying again.\n");
+        return;
+    }
+    info!("SIM unlocked");
+    let _ = core::writeln!(result, "🔓 SIM unlocked (state: {})", state.as_str());
+
+    // 存储的 PIN 与刚被接受的不一致 (例如 PUK 重设过) 就不再保留
+    let stored = CONFIG.lock(|c| c.borrow().sim.pin.clone());
+    let store = if unlock.remember {
+        Some(unlock.pin.as_str())
+    } else {
+        (!stored.is_empty() && stored != unlock.pin).then_some("")
+    };
+    if let Some(pin) = store {
+        if store_sim_pin(pin) {
+            let _ = result.push_str(if pin.is_empty() { "Stored PIN no longer matches: forgotten\n" } else { "PIN stored on this device\n" });
+        } else {
+            let _ = result.push_str("⚠️ Could not save the PIN setting to flash\n");
+        }
+    }
+
+    if state == sim::SimState::Ready && FETCH_WAITING_FOR_SIM.swap(false, Ordering::Relaxed) {
+        let _ = result.push_str("Resuming the HTTP GET...\n");
+        submit_modem_op(ModemOp::Fetch(fetch::Origin::SimUnlock));
+    }
+}
+
+// Submits the PIN from the config once per boot, and only while the SIM
+// still has all 3 attempts: a wrong stored PIN costs at most the first
+// attempt, the rest stay for whoever enters it on the status page.
+async fn try_stored_pin(tx: &mut ModemTx, rx: &mut ModemRx, state: sim::SimState) -> sim::SimState {
+    let pin = CONFIG.lock(|c| c.borrow().sim.pin.clone());
+    if state != sim::SimState::PinRequired || pin.is_empty() || STORED_PIN_TRIED.load(Ordering::Relaxed) {
+        return state;
+    }
+    let status = sim_status();
+    if status.last_unlock_failed || status.pin_left.is_none_or(|left| left < 3) {
+        warn!("Stored SIM PIN not submitted: first attempt already used or unknown");
+        let mut result = modem_result().await;
+        let _ = result.push_str("🔑 Stored PIN not submitted: the SIM has already seen a wrong PIN\n");
+        return state;
+    }
+
+    STORED_PIN_TRIED.store(true, Ordering::Relaxed);
+    info!("Submitting the stored SIM PIN");
+    let unlock = sim::Unlock {
+        puk: sim::Code::new(),
+        pin,
+        remember: false,
+    };
+    let accepted = submit_sim_code(tx, rx, &unlock).await;
+    let state = check_sim(tx, rx).await;
+
+    let mut result = modem_result().await;
+    if accepted {
+        info!("Stored SIM PIN accepted");
+        let _ = core::writeln!(result, "🔑 Stored PIN submitted automatically: accepted (SIM {})", state.as_str());
+    } else {
+        warn!("Stored SIM PIN rejected");
+        let _ = result.push_str("🔑 Stored PIN submitted automatically: rejected. It will not be sent again; enter the PIN on the status page.\n");
+    }
+    state
+}
+
+// config sim.pin 写入闪存; 空字符串表示忘记
+fn store_sim_pin(pin: &str) -> bool {
+    if !store_text_setting("sim.pin", pin) {
+        return false;
+    }
+    info!("SIM PIN {}", if pin.is_empty() { "forgotten" } else { "stored" });
+    true
+}
+
+// POST /api/sim/pin (表单: pin, 以及 PUK 状态下的 puk)
+fn queue_sim_unlock(form: &[u8], html: bool, request_id: u32) -> heapless::String<512> {
+    let field = |key| http::form_value(form, key).and_then(http::percent_decode::<8>).unwrap_or_default();
+    let unlock = sim::Unlock {
+        puk: field("puk"),
+        pin: field("pin"),
+        remember: http::form_value(form, "remember").is_some(),
+    };
+    let state = sim_status().state;
+    if !state.locked() {
+        return format_short("409 Conflict", "text/plain", "the SIM is not waiting for a PIN\n");
+    }
+    if !sim::valid_pin(&unlock.pin) {
+        return format_short("400 Bad Request", "text/plain", "PIN must be 4-8 digits\n");
+    }
+    if state == sim::SimState::PukRequired && !sim::valid_puk(&unlock.puk) {
+        return format_short("400 Bad Request", "text/plain", "PUK must be 8 digits\n");
+    }
+    if state == sim::SimState::PinRequired && !unlock.puk.is_empty() {
+        return format_short("400 Bad Request", "text/plain", "the SIM wants a PIN, not a PUK\n");
+    }
+
+    info!("Queueing SIM unlock");
+    if !submit_modem_op_for(ModemOp::SimUnlock(unlock), Some(request_id)) {
+        return format_short("503 Service Unavailable", "text/plain", "modem busy, try again shortly\n");
+    }
+    if html {
+        return format_see_other("/");
+    }
+    format_short("202 Accepted", "application/json", "{\"queued\":true}")
+}
+
+// 状态页上的 PIN/PUK 输入框
+fn push_sim_html<const N: usize>(html: &mut heapless::String<N>) {
+    let status = sim_status();
+    let left = |html: &mut heapless::String<N>, count: Option<u8>, what: &str| {
+        if let Some(count) = count {
+            let _ = core::write!(html, " {} {} attempts left.", count, what);
+        }
+    };
+    let stored = CONFIG.lock(|c| !c.borrow().sim.pin.is_empty());
+    if stored && !status.state.locked() {
+        let _ = html.push_str("<div class='step'>🔑 A SIM PIN is stored on this device for unattended unlocking.");
+        let _ = html.push_str("<form method='post' action='/api/sim/forget'><button type='submit' class='btn-at'>Forget the PIN</button></form></div>");
+    }
+    match status.state {
+        sim::SimState::PinRequired => {
+            let _ = html.push_str("<div class='warning'><strong>🔒 SIM PIN required.</strong>");
+            left(html, status.pin_left, "PIN");
+            if status.pin_left == Some(1) {
+                let _ = html.push_str(" <span class='error'>One more wrong PIN locks the SIM (PUK needed).</span>");
+            }
+        }
+        sim::SimState::PukRequired => {
+            let _ = html.push_str("<div class='warning error'><strong>⛔ SIM blocked: PUK required.</strong> ");
+            let _ = html.push_str("Too many wrong PINs were entered. Enter the 8-digit PUK printed on the SIM card holder ");
+            let _ = html.push_str("(or ask the operator for it) and choose a new PIN.");
+            left(html, status.puk_left, "PUK");
+            let _ = html.push_str(" If the PUK attempts run out the SIM is permanently blocked.");
+        }
+        sim::SimState::NotInserted => {
+            let _ = html.push_str("<div class='warning error'>❌ No SIM card detected.</div>");
+            return;
+        }
+        _ => return,
+    }
+    if status.last_unlock_failed {
+        let _ = html.push_str("<br><span class='error'>The last code was rejected.</span>");
+    }
+    let _ = html.push_str("<form method='post' action='/api/sim/pin'>");
+    if status.state == sim::SimState::PukRequired {
+        let _ = html.push_str("<input type='password' name='puk' placeholder='PUK' inputmode='numeric' pattern='[0-9]{8}' required>");
+        let _ = html.push_str("<input type='password' name='pin' placeholder='New PIN' inputmode='numeric' pattern='[0-9]{4,8}' required>");
+    } else {
+        let _ = html.push_str("<input type='password' name='pin' placeholder='PIN' inputmode='numeric' pattern='[0-9]{4,8}' required>");
+    }
+    let _ = html.push_str("<button type='submit' class='btn-at'>🔓 Unlock</button>");
+    let _ = core::write!(html, "<br><label><input type='checkbox' name='remember'{}> Remember the PIN on this device</label>", if stored { " checked" } else { "" });
+    let _ = html.push_str("<br><small>Storing the PIN trades security for availability: the device can unlock the SIM by itself after a reboot, ");
+    let _ = html.push_str("but anyone who gets hold of the device or its flash can recover the PIN. It is tried automatically once per boot, ");
+    let _ = html.push_str("and never once the SIM has seen a wrong PIN.</small></form></div>");
+}
+
+async fn perform_http_get(tx: &mut ModemTx, rx: &mut ModemRx, origin: fetch::Origin) {
+    let request_id = MODEM_REQUEST.lock(|r| r.get());
+    // 别的来源 (GPIO 输入等) 总是去 httpbin, 它们之后的恢复重试也一样
+    let target = match origin {
+        fetch::Origin::Web | fetch::Origin::SimUnlock | fetch::Origin::Recovery => {
+            FETCH_TARGET.lock(|t| t.borrow().clone())
+        }
+        _ => {
+            FETCH_TARGET.lock(|t| *t.borrow_mut() = None);
+            None
+        }
+    }
+    .unwrap_or_else(fetch_target::Target::httpbin);
+    info!(
+        "Starting HTTP GET process for {}:{}{} ({}){}",
+        target.host.as_str(),
+        target.port,
+        target.path.as_str(),
+        origin.as_str(),
+        request_tag(request_id)
+    );
+    let triggered = Instant::now();
+    // 排队期间的取消请求已经从队列里撤下了这次获取
+    FETCH_CANCEL.store(false, Ordering::Relaxed);
+    let resolve_host = FETCH_RESOLVE_HOST.swap(false, Ordering::Relaxed);
+    
+    // 更新状态 - 快速完成
+    {
+        let mut result = modem_result().await;
+        result.clear();
+        let _ = result.push_str("🚀 Starting HTTP GET process...\n");
+        let _ = match target.pinned_ip() {
+            Some(ip) if !resolve_host => core::write!(result, "Using TCP/IP to {}:{}\n\n", ip, target.port),
+            Some(_) => {
+                let (host, port) = (&target.host, target.port);
+                core::write!(result, "Using TCP/IP to {}:{} (resolving the address afresh)\n\n", host, port)
+            }
+            None => core::write!(result, "Using TCP/IP to {}:{}{}\n\n", target.host, target.port, target.path),
+        };
+    }
+    
+    // SIM 锁定时停在这里, 状态页输入 PIN/PUK 后自动重新开始
+    {
+        let mut result = modem_result().await;
+        let _ = result.push_str("Checking SIM status...\n");
+    }
+    let state = check_sim(tx, rx).await;
+    let state = try_stored_pin(tx, rx, state).await;
+    {
+        let mut result = modem_result().await;
+        let _ = core::writeln!(result, "  -> SIM {}", state.as_str());
+        let problem = match state {
+            _ if state.locked() => Some("🔒 The SIM is locked. Enter the PIN (or PUK) on the status page; the fetch resumes after unlocking."),
+            sim::SimState::NotInserted => Some("❌ No SIM card inserted"),
+            sim::SimState::Other => Some("❌ The SIM is locked in a way this firmware cannot unlock"),
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            FETCH_WAITING_FOR_SIM.store(state.locked(), Ordering::Relaxed);
+            let _ = core::writeln!(result, "\n{}", problem);
+            drop(result);
+            note_fetch_failure("SIM not ready");
+            return;
+        }
+    }
+
+    if !cellular_data_allowed(tx, rx, registration::Feature::Fetch).await {
+        let mut result = modem_result().await;
+        let _ = result.push_str("\n🚫 Registered as ROAMING: cellular data is blocked (roaming.block_data).\n");
+        let _ = result.push_str("Allow roaming on the overview page to fetch anyway (until reboot).\n");
+        return;
+    }
+
+    // 基础检查: 模块初始化, 网络注册, PDP 激活 (指令取决于模块型号)
+    let modem = current_modem();
+    let mut basic_steps = heapless::Vec::<(modem::Command, &str), 12>::new();
+    for cmd in modem.init() {
+        let _ = basic_steps.push((cmd, "Initialising modem"));
+    }
+    let _ = basic_steps.push((modem.register(), "Checking network registration"));
+    for cmd in modem.activate_pdp("CMNET") {
+        let _ = basic_steps.push((cmd, "Activating PDP context"));
+    }
+
+    let total = basic_steps.len() as u8 + 1;
+    for (step, (cmd, desc)) in basic_steps.iter().enumerate() {
+        if let Some(cancel) = fetch_cancel_requested(origin, triggered) {
+            report_fetch_cancelled(cancel).await;
+            return;
+        }
+        if !send_at_command_safe(tx, rx, cmd, desc, step as u8 + 1, total).await {
+            note_fetch_failure(desc);
+            return;
+        }
+    }
+
+    // 最后一步: TCP 连接, 发送请求, 读取响应, 关闭
+    {
+        let mut result = modem_result().await;
+        let _ = core::write!(result, "\nStep {}/{}: HTTP GET via {}\n", total, total, modem.name());
+    }
+    let request = target.request();
+    let mut fetch = fetch::Fetch::new(modem, fetch::Target {
+        host: &target.host,
+        ip: target.pinned_ip().filter(|_| !resolve_host),
+        port: target.port,
+        request: request.as_bytes(),
+    });
+    let mut body = heapless::String::<1024>::new();
+    let outcome = run_fetch(tx, rx, &mut fetch, &mut body, origin, triggered, true).await;
+    if let Err(fetch::Error::Cancelled(cancel)) = outcome {
+        report_fetch_cancelled(cancel).await;
+        return;
+    }
+
+    match outcome {
+        Ok(()) => {
+            FETCH_TARGET.lock(|t| *t.borrow_mut() = None);
+            note_fetch_success();
+        }
+        Err(e) => {
+            let mut reason = heapless::String::<48>::new();
+            e.describe(&mut reason);
+            note_fetch_failure(&reason);
+        }
+    }
+
+    // 最终状态
+    {
+        let mut result = modem_result().await;
+        if let Err(e) = outcome {
+            let _ = result.push_str("\n❌ ");
+            e.describe(&mut *result);
+            let _ = result.push('\n');
+        }
+        if fetch.received() == 0 {
+            let _ = result.push_str("\n⚠️ No data received\n");
+        } else {
+            let _ = core::writeln!(result, "\n--- HTTP Response ({} bytes) ---", fetch.received());
+            utf8::push_truncated(&mut result, &body);
+            let _ = result.push_str("\n--- End ---\n");
+        }
+        let _ = result.push_str("\n\n🔚 Process completed.\n");
+    }
+}
+
+// 模块型号在启动探测 (ATI) 时确定, 默认 Quectel
+static MODEM_BACKEND: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<modem::Backend>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(modem::Backend::Quectel));
+
+fn current_modem() -> &'static dyn modem::CellularModem {
+    MODEM_BACKEND.lock(|b| b.get()).modem()
+}
+
+// 指令按钮, 查询参数里的 '?' 和 '"' 需要转义
+fn push_at_action<const N: usize>(html: &mut heapless::String<N>, command: &str, label: &str) {
+    let _ = html.push_str("<a href='/at?cmd=");
+    for c in command.trim().chars() {
+        let _ = match c {
+            '?' => html.push_str("%3F"),
+            '"' => html.push_str("%22"),
+            c => html.push(c),
+        };
+    }
+    let _ = core::write!(html, "'><button class='btn-at'>{} ({})</button></a>", label, command.trim());
+}
+
+// ATI 回复里的固件版本, 启动探测前为空
+static MODEM_REVISION: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<heapless::String<48>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(heapless::String::new()));
+
+// cyw43 固件 blob 内嵌的版本字符串, 初始化时读出
+static CYW43_FIRMWARE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<&'static str>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+// 启动时对两个 cyw43 blob 的校验结果 (固件, CLM)
+static CYW43_BLOBS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<[blob_check::Blob; 2]>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+// GET /api/version
+fn format_version_json() -> heapless::String<1024> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let revision = MODEM_REVISION.lock(|r| r.borrow().clone());
+    let blobs = CYW43_BLOBS.lock(|b| b.get());
+    let runtime = version::Runtime {
+        cyw43_firmware: CYW43_FIRMWARE.lock(|f| f.get()),
+        cyw43_blobs: blobs.as_ref().map_or(&[], |b| b),
+        modem: current_modem().name(),
+        modem_revision: (!revision.is_empty()).then_some(revision.as_str()),
+    };
+    version::write_json(&mut out, &runtime);
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// 根据 ATI 的回复选择指令方言
+async fn detect_modem(tx: &mut ModemTx, rx: &mut ModemRx) {
+    if uart_write_all(tx, b"ATI\r\n").await.is_err() {
+        return;
+    }
+
+    let mut reader = LineReader::new();
+    let mut line = heapless::String::<64>::new();
+    let mut reply = heapless::String::<192>::new();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while reader.next_line(rx, deadline, &mut line).await {
+        let line = line.trim();
+        if at_response::classify(line).is_final() {
+            break;
+        }
+        let _ = reply.push_str(line);
+        let _ = reply.push(' ');
+    }
+
+    let backend = modem::Backend::detect(&reply);
+    MODEM_BACKEND.lock(|b| b.set(backend));
+    if let Some(revision) = version::modem_revision(&reply) {
+        MODEM_REVISION.lock(|r| {
+            let mut stored = r.borrow_mut();
+            stored.clear();
+            for c in revision.chars() {
+                if stored.push(c).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    info!("Modem backend: {} ({})", backend.modem().name(), reply.as_str());
+}
+
+// 当前 HTTP 获取所处阶段 (None = 空闲)
+static FETCH_PHASE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<fetch::Phase>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+// 获取耗时 (触发到最后一个字节); 放在全局, 串口任务重启后仍然保留
+static FETCH_LATENCY: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<latency::Histogram<32>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(latency::Histogram::new()));
+
+// 正在进行的获取是谁发起的
+static FETCH_ORIGIN: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<Option<fetch::Origin>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new(None));
+
+// POST /api/fetch/cancel 置位, 交互式获取在下一个安全点停下
+static FETCH_CANCEL: AtomicBool = AtomicBool::new(false);
+// 首页或 /api/status 最后一次被请求的时间
+static RESULT_VIEWED_MS: AtomicU64 = AtomicU64::new(0);
+
+// 上一次获取留着没关的连接 (fetch::CONNECT_ID)
+static FETCH_KEPT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<Option<fetch::Kept>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(None));
+
+// 最近一次完成的获取的原始响应 (/api/response)
+static LAST_RESPONSE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<Option<response::Response>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(None));
+
+// 最近几次的正文 (/api/response/<n>)
+static RESPONSE_ARCHIVE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<archive::Archive>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(archive::Archive::new()));
+
+static FETCH_HISTORY: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<fetch::History>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(fetch::History::new()));
+
+// True when the kept connection goes to host:port and receives the same
+// way; any other is closed so the fetch can open its own on the same id
+async fn take_kept_connection(
+    tx: &mut ModemTx,
+    rx: &mut ModemRx,
+    host: &str,
+    port: u16,
+    mode: fetch::ReceiveMode,
+) -> bool {
+    let Some(kept) = FETCH_KEPT.lock(|k| k.borrow_mut().take()) else {
+        return false;
+    };
+    if kept.serves(host, port, mode) {
+        info!("Reusing the connection to {}:{}", host, port);
+        return true;
+    }
+    quiet_command(tx, rx, &current_modem().tcp_close(fetch::CONNECT_ID), Duration::from_secs(5)).await;
+    track_modem_socket(|m, now| m.closed(fetch::CONNECT_ID, modem_sockets::CloseReason::Us, now));
+    false
+}
+
+async fn release_kept_connection(tx: &mut ModemTx, rx: &mut ModemRx) {
+    let Some(kept) = FETCH_KEPT.lock(|k| k.borrow_mut().take()) else {
+        return;
+    };
+    info!("Closing the idle connection to {}:{}", kept.host.as_str(), kept.port);
+    quiet_command(tx, rx, &current_modem().tcp_close(fetch::CONNECT_ID), Duration::from_secs(5)).await;
+    track_modem_socket(|m, now| m.closed(fetch::CONNECT_ID, modem_sockets::CloseReason::Us, now));
+}
+
+fn fetch_pending() -> bool {
+    MODEM_CURRENT.lock(|c| c.get()) == Some("fetch") || MODEM_OPS.lock(|q| q.borrow().contains("fetch"))
+}
+
+// Why an interactive fetch should stop now, if it should
+fn fetch_cancel_requested(origin: fetch::Origin, triggered: Instant) -> Option<fetch::Cancel> {
+    if origin == fetch::Origin::Relay {
+        return RELAY.lock(|r| r.borrow().abandoned).then_some(fetch::Cancel::Abandoned);
+    }
+    if !origin.interactive() {
+        return None;
+    }
+    if FETCH_CANCEL.swap(false, Ordering::Relaxed) {
+        return Some(fetch::Cancel::Requested);
+    }
+    let abandon_ms = CONFIG.lock(|c| c.borrow().fetch.abandon_s) as u64 * 1000;
+    let viewed = RESULT_VIEWED_MS.load(Ordering::Relaxed).max(triggered.as_millis());
+    (abandon_ms > 0 && Instant::now().as_millis().saturating_sub(viewed) > abandon_ms).then_some(fetch::Cancel::Abandoned)
+}
+
+// "queued": withdrawn before it ran, "running": stops at the next safe
+// point, "idle": nothing to cancel
+async fn cancel_fetch() -> &'static str {
+    if MODEM_OPS.lock(|q| q.borrow_mut().remove("fetch")).is_some() {
+        info!("Queued fetch cancelled");
+        report_fetch_cancelled(fetch::Cancel::Requested).await;
+        return "queued";
+    }
+    if MODEM_CURRENT.lock(|c| c.get()) == Some("fetch") {
+        FETCH_CANCEL.store(true, Ordering::Relaxed);
+        return "running";
+    }
+    "idle"
+}
+
+// 取消不算失败: 不计入连续失败次数
+async fn report_fetch_cancelled(cancel: fetch::Cancel) {
+    let mut reason = heapless::String::<48>::new();
+    fetch::Error::Cancelled(cancel).describe(&mut reason);
+    info!("Fetch {}", reason.as_str());
+    finish_transcript(&reason);
+    let mut result = modem_result().await;
+    let _ = core::writeln!(result, "\n🚫 Fetch {}\n\n🔚 Process completed.", reason);
+}
+
+fn set_fetch_phase(phase: Option<fetch::Phase>) {
+    FETCH_PHASE.lock(|p| p.set(phase));
+    if let Some(phase) = phase {
+        TRANSCRIPTS.lock(|t| t.borrow_mut().event(phase.as_str(), Instant::now().as_millis()));
+    }
+    bump_state_generation();
+}
+
+fn fetch_phase_label(phase: fetch::Phase) -> &'static str {
+    match phase {
+        fetch::Phase::Resolve => "Resolving host",
+        fetch::Phase::Open => "Opening TCP connection",
+        fetch::Phase::AwaitOpenUrc => "Waiting for connection",
+        fetch::Phase::SendLen => "Preparing to send",
+        fetch::Phase::AwaitPrompt => "Waiting for '>' prompt",
+        fetch::Phase::SendBody => "Sending HTTP request",
+        fetch::Phase::AwaitData => "Waiting for the response",
+        fetch::Phase::Receive => "Reading response",
+        fetch::Phase::Close => "Closing connection",
+    }
+}
+
+// 每一行串口回复都经过这里: 主动上报在哪条指令的回复里出现都照样处理, 读到它的一方忽略它
+fn dispatch_urc(line: &str) {
+    #[cfg(feature = "proxy")]
+    note_forward_notice(line);
+    match urc::parse(line) {
+        Some(urc::Urc::SmsReceived { storage, index }) => info!("SMS received, stored in {} at {}", storage, index),
+        Some(urc::Urc::Ring) => info!("Incoming call (not answered)"),
+        Some(urc::Urc::PdpDeactivated) => {
+            warn!("The network deactivated the data context");
+            PDP_ACTIVE.lock(|p| p.set(Some(false)));
+            bump_state_generation();
+        }
+        Some(urc::Urc::Restarted) => {
+            info!("Modem reported RDY (started)");
+            STATS.add(stats::Stat::ModemRestarts, 1);
+            PDP_ACTIVE.lock(|p| p.set(None));
+        }
+        None => {}
+    }
+}
+
+// 串口按行读取; 没有换行的 '>' 提示符单独算一行
+struct LineReader {
+    pending: heapless::Vec<u8, 512>,
+}
+
+impl LineReader {
+    fn new() -> Self {
+        Self {
+            pending: heapless::Vec::new(),
+        }
+    }
+
+    fn consume(&mut self, count: usize) {
+        let len = self.pending.len();
+        self.pending.copy_within(count..len, 0);
+        self.pending.truncate(len - count);
+    }
+
+    async fn fill(&mut self, rx: &mut ModemRx, deadline: Instant) -> bool {
+        let mut buf = [0u8; 128];
+        let room = (self.pending.capacity() - self.pending.len()).min(buf.len());
+        match embassy_time::with_deadline(deadline, uart_read(rx, &mut buf[..room])).await {
+            Ok(Ok(n)) => {
+                let _ = self.pending.extend_from_slice(&buf[..n]);
+                true
+            }
+            // 出错的字节已丢弃 (计入 UART_ERRORS), 继续等到截止时间
+            Ok(Err(_)) => true,
+            Err(_) => false,
+        }
+    }
+
+    async fn next_line<const N: usize>(
+        &mut self,
+        rx: &mut ModemRx,
+        deadline: Instant,
+        line: &mut heapless::String<N>,
+    ) -> bool {
+        loop {
+            // 缓冲区满了还没有换行: 整块当作一行
+            let end = match at_response::next_line(&self.pending) {
+                None if self.pending.is_full() => {
+                    let len = match utf8::complete_len(&self.pending) {
+                        0 => self.pending.len(),
+                        len => len,
+                    };
+                    Some((core::str::from_utf8(&self.pending[..len]).unwrap_or(""), len))
+                }
+                end => end,
+            };
+            if let Some((text, consumed)) = end {
+                line.clear();
+                for c in text.chars() {
+                    if line.push(c).is_err() {
+                        break;
+                    }
+                }
+                let raw = rx_audit::text_len(&self.pending[..consumed]);
+                RX_AUDIT.lock(|a| a.borrow_mut().line(consumed, raw, text.len(), line.len()));
+                self.consume(consumed);
+                dispatch_urc(line.trim());
+                return true;
+            }
+            if !self.fill(rx, deadline).await {
+                return false;
+            }
+        }
+    }
+
+    // 等待数据提示 "> ": Some(true) 是提示, Some(false) 是提示之前的一行 (在 `line` 里),
+    // None 是超时. 主动上报照常分发, 提示被拆成几次读到也没关系
+    async fn next_awaited<const N: usize>(
+        &mut self,
+        rx: &mut ModemRx,
+        deadline: Instant,
+        line: &mut heapless::String<N>,
+    ) -> Option<bool> {
+        loop {
+            match at_response::next_awaited(&self.pending) {
+                Some((at_response::Awaited::Prompt, consumed)) => {
+                    rx_delivered(rx_audit::Sink::Lines, consumed);
+                    self.consume(consumed);
+                    return Some(true);
+                }
+                Some((at_response::Awaited::Line(text), consumed)) => {
+                    line.clear();
+                    utf8::push_truncated(line, text);
+                    let raw = rx_audit::text_len(&self.pending[..consumed]);
+                    RX_AUDIT.lock(|a| a.borrow_mut().line(consumed, raw, text.len(), line.len()));
+                    self.consume(consumed);
+                    dispatch_urc(line.trim());
+                    return Some(false);
+                }
+                // 缓冲区满了还没有提示也没有换行: 丢掉重新等
+                None if self.pending.is_full() => {
+                    rx_lost(rx_audit::Loss::BufferFull, self.pending.len());
+                    self.pending.clear();
+                }
+                None => {}
+            }
+            if !self.fill(rx, deadline).await {
+                return None;
+            }
+        }
+    }
+
+    // 发送命令之后等 "> " 提示; false 表示模块先给了最终结果或者超时
+    async fn expect_prompt(&mut self, rx: &mut ModemRx, deadline: Instant) -> bool {
+        let mut line = heapless::String::<128>::new();
+        loop {
+            match self.next_awaited(rx, deadline, &mut line).await {
+                Some(true) => return true,
+                Some(false) if at_response::classify(line.trim()).is_final() => return false,
+                Some(false) => {}
+                None => return false,
+            }
+        }
+    }
+
+    // 读取 n 字节二进制数据 (转发的负载), 放不下的部分丢弃
+    async fn read_bytes<const N: usize>(
+        &mut self,
+        rx: &mut ModemRx,
+        mut count: usize,
+        deadline: Instant,
+        out: &mut heapless::Vec<u8, N>,
+    ) -> bool {
+        while count > 0 {
+            if self.pending.is_empty() && !self.fill(rx, deadline).await {
+                return false;
+            }
+            let take = count.min(self.pending.len());
+            let room = take.min(out.capacity() - out.len());
+            let _ = out.extend_from_slice(&self.pending[..room]);
+            rx_delivered(rx_audit::Sink::Payload, room);
+            rx_lost(rx_audit::Loss::BufferFull, take - room);
+            self.consume(take);
+            count -= take;
+        }
+        true
+    }
+}
+
+// 操作结束时还没读的字节不会再有人看
+impl Drop for LineReader {
+    fn drop(&mut self) {
+        rx_lost(rx_audit::Loss::Unread, self.pending.len());
+    }
+}
+
+// 驱动 fetch 状态机: 写各阶段的命令, 按行喂给状态机, 超时交给状态机决定
+// `triggered`: when the fetch was requested, the start of its latency sample;
+// `show`: report progress in the results area. An interactive fetch stops
+// before its next command once cancelled; the close still runs.
+async fn run_fetch(
+    tx: &mut ModemTx,
+    rx: &mut ModemRx,
+    fetch: &mut fetch::Fetch<'_>,
+    body: &mut heapless::String<1024>,
+    origin: fetch::Origin,
+    triggered: Instant,
+    show: bool,
+) -> Result<(), fetch::Error> {
+    FETCH_ORIGIN.lock(|o| o.set(Some(origin)));
+    // 中继靠模块缓存数据来等慢的一方
+    let direct_push = origin != fetch::Origin::Relay && CONFIG.lock(|c| c.borrow().fetch.direct_push);
+    fetch.set_receive_mode(fetch::ReceiveMode::select(current_modem(), direct_push));
+    let (host, port) = fetch.destination();
+    if take_kept_connection(tx, rx, host, port, fetch.receive_mode()).await {
+        fetch.reuse_connection();
+        if show {
+            let mut result = modem_result().await;
+            let _ = core::writeln!(result, "\nReusing the open connection to {}:{}", host, port);
+        }
+    }
+    let mut reader = LineReader::new();
+    let mut last_byte = None;
+    let mut chunk = heapless::Vec::<u8, { fetch::READ_CHUNK }>::new();
+    let mut body_carry = utf8::Carry::new();
+    // 显示在结果区的获取才替换 /api/response
+    let mut response = show.then(|| response::Response::new(origin, triggered.as_millis()));
+    let mut command = heapless::Vec::<u8, 256>::new();
+    let mut line = heapless::String::<256>::new();
+    let mut shown_phase = None;
+    let mut open_started = None;
+    let mut deadline = Instant::now();
+    let mut step = fetch::Step::Enter;
+
+    let outcome = loop {
+        step = match step {
+            fetch::Step::Enter => {
+                if fetch.phase() != fetch::Phase::Close
+                    && let Some(cancel) = fetch_cancel_requested(origin, triggered)
+                {
+                    break Err(fetch::Error::Cancelled(cancel));
+                }
+                let phase = fetch.phase();
+                if shown_phase != Some(phase) {
+                    shown_phase = Some(phase);
+                    set_fetch_phase(Some(phase));
+                    match phase {
+                        // 复用的连接已经被对方关掉, 正在重新连接
+                        fetch::Phase::Open => {
+                            open_started = Some(Instant::now());
+                            track_modem_socket(|m, now| {
+                                m.closed(fetch::CONNECT_ID, modem_sockets::CloseReason::Peer, now)
+                            });
+                        }
+                        fetch::Phase::SendLen => {
+                            if let Some(started) = open_started.take() {
+                                let connect_ms = started.elapsed().as_millis() as u32;
+                                track_modem_socket(|m, now| m.opened(fetch::CONNECT_ID, host, port, connect_ms, now));
+                            }
+                        }
+                        _ => {}
+                    }
+                    if show {
+                        let mut result = modem_result().await;
+                        let _ = core::writeln!(result, "\n{}...", fetch_phase_label(phase));
+                    }
+                }
+
+                fetch.command(&mut command);
+                if !command.is_empty() {
+                    match uart_write_all(tx, &command).await {
+                        Ok(()) if phase == fetch::Phase::SendBody => {
+                            track_modem_socket(|m, now| m.sent(fetch::CONNECT_ID, command.len(), now));
+                            STATS.add(stats::Stat::CellularBytes, command.len() as u64);
+                        }
+                        Ok(()) => {}
+                        Err(TxError::Uart) => break Err(fetch::Error::Uart),
+                        Err(TxError::Stalled) => break Err(fetch::Error::UartStalled),
+                    }
+                }
+                deadline = Instant::now() + Duration::from_millis(fetch.timeout_ms() as u64);
+                fetch.sent()
+            }
+            // 提示可能被拆开, 也可能紧跟在没有换行的回显后面
+            fetch::Step::Wait if fetch.phase() == fetch::Phase::AwaitPrompt => {
+                match reader.next_awaited(rx, deadline, &mut line).await {
+                    Some(true) => fetch.on_prompt(),
+                    Some(false) => {
+                        if show && urc::parse(&line).is_none() {
+                            let mut result = modem_result().await;
+                            let _ = core::writeln!(result, "  -> {}", line.trim());
+                        }
+                        fetch.on_line(&line)
+                    }
+                    None => fetch.on_timeout(),
+                }
+            }
+            fetch::Step::Wait => {
+                if reader.next_line(rx, deadline, &mut line).await {
+                    // 无关的主动上报已经处理过了, 不混进获取的过程
+                    if show && !line.trim().is_empty() && urc::parse(&line).is_none() {
+                        let mut result = modem_result().await;
+                        let _ = core::writeln!(result, "  -> {}", line.trim());
+                    }
+                    fetch.on_line(&line)
+                } else {
+                    fetch.on_timeout()
+                }
+            }
+            fetch::Step::ReadData(n) => {
+                chunk.clear();
+                if reader.read_bytes(rx, n, deadline, &mut chunk).await {
+                    track_modem_socket(|m, now| m.received(fetch::CONNECT_ID, chunk.len(), now));
+                    STATS.add(stats::Stat::CellularBytes, chunk.len() as u64);
+                    let next = if origin == fetch::Origin::Relay && !relay_forward(&chunk).await {
+                        fetch::Step::Failed(fetch::Error::Cancelled(fetch::Cancel::Abandoned))
+                    } else {
+                        fetch.on_data(&chunk)
+                    };
+                    if let Some(response) = response.as_mut() {
+                        response.feed(&chunk);
+                    }
+                    // 字符可能跨两次读取; 不是 UTF-8 的字节不显示
+                    body_carry.feed(&chunk, |piece| {
+                        for part in piece.utf8_chunks() {
+                            utf8::push_truncated(body, part.valid());
+                        }
+                    });
+                    last_byte = Some(Instant::now());
+                    next
+                } else {
+                    fetch.on_timeout()
+                }
+            }
+            fetch::Step::Done => break Ok(()),
+            fetch::Step::Failed(e) => break Err(e),
+        };
+    };
+
+    // 失败时连接可能还开着
+    if outcome.is_err() && fetch.needs_close() {
+        let close = current_modem().tcp_close(fetch::CONNECT_ID);
+        let _ = uart_write_all(tx, close.as_bytes()).await;
+        Timer::after(Duration::from_millis(500)).await;
+    }
+    if !fetch.kept() {
+        let reason = match outcome {
+            Ok(()) if fetch.peer_closed() => modem_sockets::CloseReason::Peer,
+            Ok(()) | Err(fetch::Error::Cancelled(_)) => modem_sockets::CloseReason::Us,
+            Err(_) => modem_sockets::CloseReason::Error,
+        };
+        track_modem_socket(|m, now| m.closed(fetch::CONNECT_ID, reason, now));
+    }
+
+    if outcome.is_ok()
+        && let Some(at) = last_byte
+    {
+        let ms = (at - triggered).as_millis() as u32;
+        FETCH_LATENCY.lock(|l| l.borrow_mut().record(ms));
+        info!("Fetch took {} ms{}", ms, request_tag(MODEM_REQUEST.lock(|r| r.get())));
+    }
+    let mut archived = None;
+    if outcome.is_ok()
+        && let Some(response) = response
+    {
+        let mut url = heapless::String::<128>::new();
+        let _ = core::write!(url, "http://{}:{}{}", host, port, fetch.path());
+        archived = Some(RESPONSE_ARCHIVE.lock(|a| a.borrow_mut().push(&url, &response, triggered.as_millis())));
+        LAST_RESPONSE.lock(|r| *r.borrow_mut() = Some(response));
+    }
+    if outcome.is_ok() {
+        PDP_ACTIVE.lock(|p| p.set(Some(true)));
+        LAST_CELLULAR_OK_MS.store(Instant::now().as_millis(), Ordering::Relaxed);
+    }
+    if outcome.is_ok() && fetch.kept() {
+        debug!("Keeping the connection to {}:{} open", host, port);
+        let kept = fetch::Kept::new(host, port, fetch.receive_mode(), Instant::now().as_millis());
+        FETCH_KEPT.lock(|k| *k.borrow_mut() = kept);
+    }
+    FETCH_HISTORY.lock(|h| {
+        h.borrow_mut().push(fetch::Record {
+            at_ms: triggered.as_millis(),
+            origin,
+            request_id: MODEM_REQUEST.lock(|r| r.get()),
+            connection: fetch.connection(),
+            elapsed_ms: (Instant::now() - triggered).as_millis() as u32,
+            received: fetch.received(),
+            response: archived,
+            error: outcome.err(),
+        })
+    });
+    FETCH_ORIGIN.lock(|o| o.set(None));
+    set_fetch_phase(None);
+    outcome
+}
+
+// 安全的AT命令发送
+async fn send_at_command_safe(tx: &mut ModemTx, rx: &mut ModemRx, 
+                             cmd: &str, desc: &str, step: u8, total: u8) -> bool {
+    {
+        let mut result = modem_result().await;
+        let _ = result.push_str("\nStep ");
+        let mut step_str = heapless::String::<3>::new();
+        let _ = write_u32(&mut step_str, step as u32);
+        let _ = result.push_str(&step_str);
+        let _ = result.push_str("/");
+        let mut total_str = heapless::String::<3>::new();
+        let _ = write_u32(&mut total_str, total as u32);
+        let _ = result.push_str(&total_str);
+        let _ = result.push_str(": ");
+        let _ = result.push_str(desc);
+        let _ = result.push_str("...\n");
+    }
+    
+    match uart_write_all(tx, cmd.as_bytes()).await {
+        Ok(_) => {
+            let mut reader = LineReader::new();
+            let mut line = heapless::String::<128>::new();
+            let deadline = Instant::now() + Duration::from_millis(1500);
+            let mut got_error = false;
+            while reader.next_line(rx, deadline, &mut line).await {
+                let text = line.trim();
+                if text.is_empty() {
+                    continue;
+                }
+                {
+                    let mut result = modem_result().await;
+                    let _ = result.push_str("  -> ");
+                    let _ = result.push_str(text);
+                    let _ = result.push_str("\n");
+                }
+                match at_response::classify(text) {
+                    at_response::Kind::Success => break,
+                    at_response::Kind::Failure => {
+                        got_error = true;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            
+            if got_error {
+                {
+                    let mut result = modem_result().await;
+                    let _ = result.push_str("\n❌ ");
+                    let _ = result.push_str(desc);
+                    let _ = result.push_str(" failed\n");
+                }
+                return false;
+            }
+            
+            true
+        }
+        Err(_) => {
+            {
+                let mut result = modem_result().await;
+                let _ = result.push_str("\n❌ Failed to send ");
+                let _ = result.push_str(desc);
+                let _ = result.push_str(" command\n");
+            }
+            false
+        }
+    }
+}
+
+fn format_capture_json() -> heapless::String<512> {
+    let mut body = heapless::String::<160>::new();
+    CAPTURE.lock(|c| {
+        let capture = c.borrow();
+        let mut obj = json::Object::new(&mut body);
+        obj.bool("active", capture.active())
+            .u32("bytes", capture.recorded() as u32)
+            .u32("capacity", capture.capacity() as u32)
+            .u32("dropped_chunks", capture.dropped_chunks());
+        obj.finish();
+    });
+    format_short("200 OK", "application/json", &body)
+}
+
+// POST /api/capture/start|stop; 开始时清掉上一次的抓包
+fn control_capture(start: bool, html: bool) -> heapless::String<512> {
+    let now = Instant::now().as_millis() as u32;
+    CAPTURE.lock(|c| {
+        let mut capture = c.borrow_mut();
+        if start {
+            capture.start(now);
+        } else {
+            capture.stop();
+        }
+    });
+    info!("UART capture {}", if start { "started" } else { "stopped" });
+    if html {
+        format_see_other("/log")
+    } else {
+        format_capture_json()
+    }
+}
+
+fn push_capture_controls<const N: usize>(html: &mut heapless::String<N>) {
+    let (active, recorded, capacity, dropped) = CAPTURE.lock(|c| {
+        let capture = c.borrow();
+        (capture.active(), capture.recorded(), capture.capacity(), capture.dropped_chunks())
+    });
+    let (action, label) = if active {
+        ("/api/capture/stop", "⏹ Stop capture")
+    } else {
+        ("/api/capture/start", "⏺ Start capture")
+    };
+    let _ = core::write!(
+        html,
+        "<form method='post' action='{}'><button type='submit' class='btn-at'>{}</button> ",
+        action,
+        label
+    );
+    let _ = core::write!(html, "{}/{} bytes", recorded, capacity);
+    if dropped > 0 {
+        let _ = core::write!(html, ", {} chunks dropped", dropped);
+    }
+    if recorded > 0 {
+        let _ = html.push_str(" | <a href='/capture.bin'>⬇️ capture.bin</a>");
+    }
+    let _ = html.push_str("</form>");
+}
+
+// /capture.bin: 文件头加上请求时已有的记录, 分块从锁内复制
+async fn serve_capture(socket: &mut Conn<'_, '_>) {
+    let (file_header, end) = CAPTURE.lock(|c| {
+        let capture = c.borrow();
+        (capture.header(), capture.recorded())
+    });
+
+    let mut header = heapless::String::<256>::new();
+    let _ = header.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = header.push_str("Content-Type: application/octet-stream\r\n");
+    let _ = header.push_str("Content-Disposition: attachment; filename=\"capture.bin\"\r\n");
+    let _ = core::write!(header, "Content-Length: {}\r\n", capture::HEADER_LEN + end);
+    let _ = header.push_str("Cache-Control: no-store\r\n");
+    let _ = header.push_str("Connection: close\r\n\r\n");
+    if socket.write_all(header.as_bytes()).await.is_err() || socket.write_all(&file_header).await.is_err() {
+        return;
+    }
+
+    let mut piece = [0u8; 512];
+    let mut offset = 0;
+    while offset < end {
+        let want = (end - offset).min(piece.len());
+        let n = CAPTURE.lock(|c| c.borrow().read_at(offset, &mut piece[..want]));
+        // 下载途中重新开始抓包: 剩下的补零, 长度仍与 Content-Length 一致
+        if n < want {
+            piece[n..want].fill(0);
+        }
+        if socket.write_all(&piece[..want]).await.is_err() {
+            return;
+        }
+        offset += want;
+    }
+    let _ = socket.flush().await;
+}
+
+fn format_no_response() -> heapless::String<512> {
+    format_short("404 Not Found", "application/json", "{\"error\":\"no fetch has completed yet\"}")
+}
+
+// GET /api/response: 原样返回正文, Content-Type 用服务器给的
+async fn serve_last_response(socket: &mut Conn<'_, '_>) {
+    let stored = LAST_RESPONSE.lock(|r| {
+        r.borrow().as_ref().map(|response| {
+            let mut content_type = heapless::String::<96>::new();
+            let _ = content_type.push_str(response.content_type());
+            (content_type, response.body().len(), response.truncated())
+        })
+    });
+    let Some((content_type, end, truncated)) = stored else {
+        let _ = socket.write_all(format_no_response().as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    };
+
+    let mut header = heapless::String::<256>::new();
+    let _ = header.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = core::write!(header, "Content-Type: {}\r\n", content_type);
+    let _ = core::write!(header, "Content-Length: {}\r\n", end);
+    if truncated {
+        let _ = header.push_str("X-Truncated: true\r\n");
+    }
+    let _ = header.push_str("Cache-Control: no-store\r\n");
+    let _ = header.push_str("Connection: close\r\n\r\n");
+    if socket.write_all(header.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let mut piece = [0u8; 512];
+    let mut offset = 0;
+    while offset < end {
+        let want = (end - offset).min(piece.len());
+        let n = LAST_RESPONSE.lock(|r| r.borrow().as_ref().map_or(0, |response| response.read_at(offset, &mut piece[..want])));
+        // 发送途中换成了更短的响应: 剩下的补零, 长度仍与 Content-Length 一致
+        if n < want {
+            piece[n..want].fill(0);
+        }
+        if socket.write_all(&piece[..want]).await.is_err() {
+            return;
+        }
+        offset += want;
+    }
+    let _ = socket.flush().await;
+}
+
+// GET /api/response/<n>: 存档里的一次正文, 元数据放在响应头里
+async fn serve_archived_response(socket: &mut Conn<'_, '_>, number: &str) {
+    let now = Instant::now().as_millis();
+    let number = number.parse::<u32>().ok();
+    let mut header = heapless::String::<512>::new();
+    let found = RESPONSE_ARCHIVE.lock(|a| {
+        let archive = a.borrow();
+        let entry = number.and_then(|n| archive.get(n))?;
+        let _ = header.push_str("HTTP/1.1 200 OK\r\n");
+        let _ = core::write!(header, "Content-Type: {}\r\n", entry.content_type);
+        let _ = core::write!(header, "Content-Length: {}\r\n", entry.stored());
+        let _ = core::write!(header, "X-Fetch-Url: {}\r\n", entry.url);
+        if let Some(status) = entry.status {
+            let _ = core::write!(header, "X-Fetch-Status: {}\r\n", status);
+        }
+        let _ = core::write!(header, "X-Body-Bytes: {}\r\n", entry.body_bytes);
+        if entry.truncated {
+            let _ = header.push_str("X-Truncated: true\r\n");
+        }
+        let _ = core::write!(header, "Age: {}\r\n", now.saturating_sub(entry.at_ms) / 1000);
+        let _ = header.push_str("Cache-Control: no-store\r\n");
+        let _ = header.push_str("Connection: close\r\n\r\n");
+        Some((entry.number, entry.stored()))
+    });
+    let Some((number, end)) = found else {
+        let body = "{\"error\":\"no such response in the archive\"}";
+        let _ = socket.write_all(format_short("404 Not Found", "application/json", body).as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    };
+    if socket.write_all(header.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let mut piece = [0u8; 512];
+    let mut offset = 0;
+    while offset < end {
+        let want = (end - offset).min(piece.len());
+        let n = RESPONSE_ARCHIVE.lock(|a| a.borrow().read_at(number, offset, &mut piece[..want]));
+        // 发送途中被挤出存档: 剩下的补零, 长度仍与 Content-Length 一致
+        if n < want {
+            piece[n..want].fill(0);
+        }
+        if socket.write_all(&piece[..want]).await.is_err() {
+            return;
+        }
+        offset += want;
+    }
+    let _ = socket.flush().await;
+}
+
+// GET /api/transcripts: 保留的记录, 最新的在前; /api/transcripts/<n>: 下载其中一份
+// `one`: Some(None) is a number that did not parse
+async fn serve_transcripts(socket: &mut Conn<'_, '_>, one: Option<Option<u32>>) {
+    let numbers = match one {
+        None => TRANSCRIPTS.lock(|t| t.borrow().numbers()),
+        Some(number) => {
+            let number = number.filter(|&n| TRANSCRIPTS.lock(|t| t.borrow().get(n).is_some()));
+            number.into_iter().collect()
+        }
+    };
+    let mut header = heapless::String::<256>::new();
+    if one.is_some() && numbers.is_empty() {
+        let body = "{\"error\":\"no such transcript\"}";
+        let _ = socket.write_all(format_short("404 Not Found", "application/json", body).as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+    let _ = header.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = header.push_str("Content-Type: application/json\r\n");
+    if let Some(&number) = numbers.first()
+        && one.is_some()
+    {
+        let _ = core::write!(
+            header,
+            "Content-Disposition: attachment; filename=\"{}-transcript-{}.json\"\r\n",
+            identity::hostname(&device_name()),
+            number
+        );
+    }
+    let _ = header.push_str("Cache-Control: no-store\r\n");
+    http::push_unknown_length(&mut header, socket.chunked());
+    let _ = header.push_str("Connection: close\r\n\r\n");
+    if socket.write_all(header.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let chunked = socket.chunked();
+    let mut writer = http::ChunkedWriter::new(socket, chunked);
+    let mut piece = heapless::String::<1536>::new();
+    if one.is_none() {
+        let _ = piece.push('[');
+    }
+    for (i, &number) in numbers.iter().enumerate() {
+        if i > 0 {
+            let _ = piece.push(',');
+        }
+        let mut offset = Some(0);
+        let mut head = true;
+        while let Some(at) = offset {
+            // 每次在锁内写一条, 写出去之前不持有锁
+            let written = TRANSCRIPTS.lock(|t| {
+                let transcripts = t.borrow();
+                let transcript = transcripts.get(number)?;
+                if head {
+                    transcript.write_head_json(&mut piece);
+                }
+                Some(transcript.write_entry_json(at, &mut piece))
+            });
+            // 发送途中被新的记录挤掉: 断开, 不发送不完整的 JSON
+            let Some(next) = written else {
+                abort_conn(socket, conn_close::Close::Truncated);
+                return;
+            };
+            head = false;
+            offset = next;
+            if next.is_none() {
+                let _ = piece.push_str("]}");
+            }
+            if writer.write_chunk(piece.as_bytes()).await.is_err() {
+                return;
+            }
+            piece.clear();
+        }
     }
-    
-    let _http_result = send_http_safe(tx, rx).await;
-    
-    // 步骤9: 读取响应
-    {
-        let mut result = AT_RESULT.lock().await;
-        let _ = result.push_str("\nStep 9/9: Reading response...\n");
+    if one.is_none() {
+        let _ = piece.push(']');
     }
-    
-    read_response_safe(tx, rx).await;
-    
-    // 清理连接
-    let _ = tx.write_all(b"AT+QICLOSE=0\r\n").await;
-    tx.flush().await.ok();
-    Timer::after(Duration::from_millis(500)).await;
-    
-    // 最终状态
+    if writer.write_chunk(piece.as_bytes()).await.is_ok() {
+        let _ = writer.finish().await;
+    }
+}
+
+// GET /api/response/meta
+fn format_response_meta_json() -> heapless::String<3072> {
+    let mut out = heapless::String::new();
+    let found = LAST_RESPONSE.lock(|r| {
+        let stored = r.borrow();
+        let Some(response) = stored.as_ref() else {
+            return false;
+        };
+        let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+        let _ = out.push_str("Content-Type: application/json\r\n");
+        let _ = out.push_str("Connection: close\r\n\r\n");
+        response.write_meta_json(&mut out, Instant::now().as_millis());
+        true
+    });
+    if !found {
+        let _ = out.push_str(&format_no_response());
+        return out;
+    }
+    http::set_content_length(&mut out);
+    out
+}
+
+// 宏库 (启动时从闪存加载) 和最近一次运行的结果
+static MACROS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<macros::Library>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(macros::Library::new()));
+
+static MACRO_REPORT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<Option<macros::Report>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(None));
+
+static FLASH_STORE: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<Option<flash_store::Store>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(None));
+
+const MACRO_TEXT_MAX: usize = flash_store::Record::Macros.capacity();
+
+fn load_macros(store: &mut flash_store::Store) {
+    let mut buf = [0u8; MACRO_TEXT_MAX];
+    let library = match store.load(flash_store::Record::Macros, &mut buf).map(core::str::from_utf8) {
+        Some(Ok(text)) => match macros::Library::parse(text) {
+            Ok(library) => library,
+            Err(e) => {
+                let mut reason = heapless::String::<96>::new();
+                e.describe(&mut reason);
+                warn!("Stored macros unreadable ({}), using defaults", reason.as_str());
+                macros::Library::defaults()
+            }
+        },
+        _ => macros::Library::defaults(),
+    };
+    info!("Macros loaded: {}", library.iter().count());
+    MACROS.lock(|m| *m.borrow_mut() = library);
+}
+
+// 整个宏库写回闪存
+fn save_macros() -> bool {
+    let mut text = heapless::String::<MACRO_TEXT_MAX>::new();
+    MACROS.lock(|m| m.borrow().write_text(&mut text));
+    write_record(flash_store::Record::Macros, text.as_bytes())
+}
+
+// POST /api/macros/run?name=...: 在这里登记运行, 串口任务空闲时执行
+fn queue_macro_run(query: &str, html: bool, request_id: u32) -> heapless::String<512> {
+    let Some(name) = http::form_value(query, "name").and_then(http::percent_decode::<16>) else {
+        return format_short("400 Bad Request", "text/plain", "name required\n");
+    };
+    let Some(mac) = MACROS.lock(|m| m.borrow().get(&name).cloned()) else {
+        return format_short("404 Not Found", "text/plain", "no such macro\n");
+    };
+    let queued = MACRO_REPORT.lock(|r| {
+        let mut report = r.borrow_mut();
+        if report.as_ref().is_some_and(|r| r.running) {
+            return false;
+        }
+        *report = Some(macros::Report::new(&mac, Instant::now().as_millis()));
+        true
+    });
+    if !queued {
+        return format_short("409 Conflict", "text/plain", "a macro is already running\n");
+    }
+
+    info!("Queueing macro {}", name.as_str());
+    if !submit_modem_op_for(ModemOp::Macro(name.clone()), Some(request_id)) {
+        MACRO_REPORT.lock(|r| *r.borrow_mut() = None);
+        return format_short("503 Service Unavailable", "text/plain", "modem busy, try again shortly\n");
+    }
+    if html {
+        return format_see_other("/macros");
+    }
+    let mut body = heapless::String::<48>::new();
+    let mut obj = json::Object::new(&mut body);
+    obj.str("queued", &name);
+    obj.finish();
+    format_short("202 Accepted", "application/json", &body)
+}
+
+// 逐步执行, 每步结果立即写入 MACRO_REPORT; 第一个没通过的步骤之后停止
+async fn run_macro(tx: &mut ModemTx, rx: &mut ModemRx, name: &str) {
+    let Some(mac) = MACROS.lock(|m| m.borrow().get(name).cloned()) else {
+        MACRO_REPORT.lock(|r| *r.borrow_mut() = None);
+        return;
+    };
+    MACRO_REPORT.lock(|r| *r.borrow_mut() = Some(macros::Report::new(&mac, Instant::now().as_millis())));
+    info!("Running macro {} ({} steps)", name, mac.steps.len());
+
+    let mut reader = LineReader::new();
+    let mut line = heapless::String::<128>::new();
+    for step in &mac.steps {
+        let mut reply = heapless::String::<128>::new();
+        let written = uart_write_all(tx, step.command.as_bytes()).await.is_ok() && uart_write_all(tx, b"\r\n").await.is_ok();
+        let outcome = if written {
+            let deadline = Instant::now() + Duration::from_millis(step.timeout_ms as u64);
+            let mut matched = false;
+            let mut last = None::<heapless::String<32>>;
+            while reader.next_line(rx, deadline, &mut line).await {
+                let text = line.trim();
+                // 回显和空行不算回复
+                if text.is_empty() || text == step.command.trim() {
+                    continue;
+                }
+                matched |= !step.expect.is_empty() && text.contains(step.expect.as_str());
+                if !reply.is_empty() {
+                    let _ = reply.push('\n');
+                }
+                for c in text.chars() {
+                    if reply.push(c).is_err() {
+                        break;
+                    }
+                }
+                if at_response::classify(text).is_final() {
+                    last = heapless::String::try_from(text).ok();
+                    break;
+                }
+            }
+            macros::check(step, matched, last.as_deref())
+        } else {
+            macros::Outcome::Uart
+        };
+
+        info!("Macro {}: {} -> {}", name, step.command.as_str(), outcome.as_str());
+        MACRO_REPORT.lock(|r| {
+            if let Some(report) = r.borrow_mut().as_mut() {
+                let _ = report.steps.push(macros::StepReport {
+                    command: step.command.clone(),
+                    outcome,
+                    reply,
+                });
+            }
+        });
+        if outcome != macros::Outcome::Passed {
+            break;
+        }
+    }
+
+    MACRO_REPORT.lock(|r| {
+        if let Some(report) = r.borrow_mut().as_mut() {
+            report.running = false;
+        }
+    });
+    show_macro_result().await;
+}
+
+// 运行摘要也写到主页的结果区
+async fn show_macro_result() {
+    let mut result = modem_result().await;
+    result.clear();
+    MACRO_REPORT.lock(|r| {
+        let report = r.borrow();
+        let Some(report) = report.as_ref() else {
+            return;
+        };
+        let _ = core::write!(result, "🧩 Macro: {}\n\n", report.name);
+        for step in &report.steps {
+            let icon = if step.outcome == macros::Outcome::Passed { "✅" } else { "❌" };
+            let _ = core::writeln!(result, "{} {} ({})", icon, step.command, step.outcome.as_str());
+            for line in step.reply.lines() {
+                let _ = core::writeln!(result, "   {}", line);
+            }
+        }
+        if report.passed() {
+            let _ = core::write!(result, "\n✅ All {} steps passed", report.total_steps);
+        } else {
+            let _ = core::write!(result, "\n❌ Stopped at step {}/{}", report.steps.len(), report.total_steps);
+        }
+    });
+}
+
+// POST /macros: name + steps 表单, 保存单个宏 (没有步骤即删除)
+// POST /macros 的表单可能比请求缓冲区大 (步骤经过百分号编码)
+async fn serve_macro_post(socket: &mut Conn<'_, '_>, body: &mut http::BodyReader<'_>) {
+    let len = body.remaining();
+    if len > limits::MACRO_FORM_MAX {
+        let response = format_short("413 Payload Too Large", "text/plain", "Form too large\n");
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+    let mut form = held(lock_stats::Site::PostBody, POST_BODY.lock().await);
+    match body.read_full(socket.get_mut(), &mut form[..len]).await {
+        Ok(filled) if filled == len => {
+            serve_macro_save(socket, &form[..len]).await;
+        }
+        _ => {
+            let response = format_short("408 Request Timeout", "text/plain", "Form incomplete\n");
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.flush().await;
+        }
+    }
+}
+
+async fn serve_macro_save(socket: &mut Conn<'_, '_>, form: &[u8]) {
+    let name = http::form_value(form, "name").and_then(http::percent_decode::<16>);
+    let steps = http::form_value(form, "steps").and_then(http::percent_decode::<1024>);
+    let (Some(name), Some(steps)) = (name, steps) else {
+        serve_macros_page(socket, "400 Bad Request", Some("name and steps are required")).await;
+        return;
+    };
+
+    let updated = macros::parse_steps(&steps).and_then(|steps| MACROS.lock(|m| m.borrow_mut().set(&name, steps)));
+    if let Err(e) = updated {
+        let mut message = heapless::String::<96>::new();
+        e.describe(&mut message);
+        serve_macros_page(socket, "400 Bad Request", Some(&message)).await;
+        return;
+    }
+
+    if !save_macros() {
+        error!("Saving macros to flash failed");
+        serve_macros_page(socket, "500 Internal Server Error", Some("flash write failed, change kept until reboot")).await;
+        return;
+    }
+    let response = format_see_other("/macros");
+    let _ = socket.write_all(response.as_bytes()).await;
+    let _ = socket.flush().await;
+}
+
+fn push_macro_report_html<const N: usize>(html: &mut heapless::String<N>) {
+    MACRO_REPORT.lock(|r| {
+        let report = r.borrow();
+        let Some(report) = report.as_ref() else {
+            return;
+        };
+        let state = if report.running {
+            "⏳ running"
+        } else if report.passed() {
+            "✅ passed"
+        } else {
+            "❌ failed"
+        };
+        let _ = core::write!(html, "<h3>Last run: {} ({})</h3>", report.name, state);
+        let _ = html.push_str("<table><tr><th>Command</th><th>Result</th><th>Reply</th></tr>");
+        for step in &report.steps {
+            let _ = html.push_str("<tr><td>");
+            push_html_escaped(html, &step.command);
+            let _ = core::write!(html, "</td><td>{}</td><td><pre>", step.outcome.as_str());
+            push_html_escaped(html, &step.reply);
+            let _ = html.push_str("</pre></td></tr>");
+        }
+        let _ = html.push_str("</table>");
+    });
+}
+
+fn push_macro_form<const N: usize>(html: &mut heapless::String<N>, mac: &macros::Macro) {
+    let mut steps = heapless::String::<1024>::new();
+    mac.write_steps(&mut steps);
+
+    // 名字只含字母数字 '-' '_', 可以直接放进属性和 URL
+    let _ = core::write!(html, "<h3>{}</h3>", mac.name);
+    let _ = core::write!(
+        html,
+        "<form method='post' action='/api/macros/run?name={}'><button type='submit' class='btn-http'>▶ Run</button></form>",
+        mac.name
+    );
+    let _ = html.push_str("<form method='post' action='/macros'>");
+    let _ = core::write!(html, "<input type='hidden' name='name' value='{}'>", mac.name);
+    let _ = html.push_str("<textarea name='steps' rows='8' cols='60'>");
+    push_html_escaped(html, &steps);
+    let _ = html.push_str("</textarea><br><button type='submit' class='btn-at'>💾 Save</button></form>");
+}
+
+// /macros: 最近一次运行的结果, 每个宏一个运行按钮和编辑表单
+async fn serve_macros_page(socket: &mut Conn<'_, '_>, status: &str, error: Option<&str>) {
+    let running = MACRO_REPORT.lock(|r| r.borrow().as_ref().is_some_and(|r| r.running));
+    let mut html = heapless::String::<2048>::new();
+
+    let _ = core::write!(html, "HTTP/1.1 {}\r\n", status);
+    let _ = html.push_str("Content-Type: text/html; charset=utf-8\r\n");
+    let _ = html.push_str("Connection: close\r\n\r\n");
+
+    let _ = html.push_str("<!DOCTYPE html><html><head>");
+    let _ = html.push_str("<title>AT Macros</title>");
+    let _ = html.push_str("<meta name='viewport' content='width=device-width, initial-scale=1'>");
+    if running {
+        let _ = html.push_str("<meta http-equiv='refresh' content='2'>");
+    }
+    let _ = html.push_str("<link rel='stylesheet' href='/style.css'>");
+    let _ = html.push_str("</head><body>");
+    let _ = html.push_str("<p><a href='/'>← Back</a> | <a href='/api/macros'>JSON</a></p>");
+    if let Some(error) = error {
+        let _ = html.push_str("<div class='warning'>❌ ");
+        push_html_escaped(&mut html, error);
+        let _ = html.push_str("</div>");
+    }
+    let _ = socket.write_all(html.as_bytes()).await;
+
+    html.clear();
+    push_macro_report_html(&mut html);
+    let _ = socket.write_all(html.as_bytes()).await;
+
+    for index in 0..macros::MAX_MACROS {
+        let Some(mac) = MACROS.lock(|m| m.borrow().iter().nth(index).cloned()) else {
+            break;
+        };
+        html.clear();
+        push_macro_form(&mut html, &mac);
+        let _ = socket.write_all(html.as_bytes()).await;
+    }
+
+    html.clear();
+    let _ = html.push_str("<h3>New macro</h3><form method='post' action='/macros'>");
+    let _ = html.push_str("<input type='text' name='name' placeholder='name'><br>");
+    let _ = html.push_str("<textarea name='steps' rows='8' cols='60'></textarea><br>");
+    let _ = html.push_str("<button type='submit' class='btn-at'>💾 Save</button></form>");
+    let _ = html.push_str("<p>One command per line: <code>AT+CSQ | +CSQ: | 2000</code>. ");
+    let _ = html.push_str("Expected text and timeout (ms) are optional; without expected text a step needs OK. ");
+    let _ = html.push_str("Saving with no steps deletes the macro.</p>");
+    let _ = html.push_str("</body></html>");
+    let _ = socket.write_all(html.as_bytes()).await;
+    let _ = socket.flush().await;
+}
+
+fn format_macros_json() -> heapless::String<2048> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let mut names = heapless::String::<160>::new();
+    let _ = names.push('[');
+    MACROS.lock(|m| {
+        for (i, mac) in m.borrow().iter().enumerate() {
+            if i > 0 {
+                let _ = names.push(',');
+            }
+            json::push_str_value(&mut names, &mac.name);
+        }
+    });
+    let _ = names.push(']');
+
+    let mut last = heapless::String::<1536>::new();
+    MACRO_REPORT.lock(|r| match r.borrow().as_ref() {
+        Some(report) => {
+            let mut steps = heapless::String::<1280>::new();
+            let _ = steps.push('[');
+            for (i, step) in report.steps.iter().enumerate() {
+                if i > 0 {
+                    let _ = steps.push(',');
+                }
+                let mut obj = json::Object::new(&mut steps);
+                obj.str("command", &step.command)
+                    .str("outcome", step.outcome.as_str())
+                    .str("reply", &step.reply);
+                obj.finish();
+            }
+            let _ = steps.push(']');
+
+            let mut obj = json::Object::new(&mut last);
+            obj.str("name", &report.name)
+                .u32("started_ms", report.started_ms as u32)
+                .bool("running", report.running)
+                .bool("passed", report.passed())
+                .u32("total_steps", report.total_steps as u32)
+                .raw("steps", &steps);
+            obj.finish();
+        }
+        None => {
+            let _ = last.push_str("null");
+        }
+    });
+
+    let mut obj = json::Object::new(&mut out);
+    obj.raw("macros", &names).raw("last", &last);
+    obj.finish();
+
+    http::set_content_length(&mut out);
+    out
+}
+
+// 单个启动步骤的最长等待时间
+const BOOT_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+static BOOT: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<boot::BootLog<16>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(boot::BootLog::new()));
+
+fn boot_begin(name: &'static str) -> Option<usize> {
+    info!("Boot: {}...", name);
+    let now = Instant::now().as_millis() as u32;
+    let index = BOOT.lock(|b| b.borrow_mut().begin(name, now));
+    bump_state_generation();
+    index
+}
+
+fn boot_end(index: Option<usize>, outcome: boot::Outcome) {
+    let Some(index) = index else {
+        return;
+    };
+    let now = Instant::now().as_millis() as u32;
+    if let Some(stage) = BOOT.lock(|b| b.borrow_mut().end(index, outcome, now)) {
+        match outcome {
+            boot::Outcome::Failed(reason) => error!("Boot: {} failed after {} ms: {}", stage.name, stage.duration_ms, reason),
+            _ => info!("Boot: {} done in {} ms", stage.name, stage.duration_ms),
+        }
+    }
+    bump_state_generation();
+}
+
+static TASKS: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<boot::Tasks<16>>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(boot::Tasks::new()));
+
+// 工作任务池满时再试的次数和间隔
+const SPAWN_ATTEMPTS: u32 = 3;
+const SPAWN_RETRY: Duration = Duration::from_millis(50);
+
+// 启动任务并记入启动报告; 启动不了就不带它继续运行
+fn spawn_task<S>(
+    spawner: Spawner,
+    name: &'static str,
+    critical: bool,
+    token: Result<SpawnToken<S>, SpawnError>,
+) -> bool {
+    let result = match token {
+        Ok(token) => {
+            spawner.spawn(token);
+            Ok(())
+        }
+        Err(_) => {
+            error!("Task {} not started: {}", name, boot::POOL_FULL);
+            Err(boot::POOL_FULL)
+        }
+    };
+    TASKS.lock(|t| t.borrow_mut().record(name, critical, result));
+    bump_state_generation();
+    result.is_ok()
+}
+
+// 同一任务的多个实例: 池满时稍等再试, 退出中的实例会让出位置
+async fn spawn_worker<S>(
+    spawner: Spawner,
+    name: &'static str,
+    mut task: impl FnMut() -> Result<SpawnToken<S>, SpawnError>,
+) -> bool {
+    for _ in 1..SPAWN_ATTEMPTS {
+        if let Ok(token) = task() {
+            return spawn_task(spawner, name, false, Ok(token));
+        }
+        Timer::after(SPAWN_RETRY).await;
+    }
+    spawn_task(spawner, name, false, task())
+}
+
+// 启动失败后停在这里, 其他任务 (串口, 已启动的 HTTP 服务) 继续运行
+async fn park() -> ! {
+    loop {
+        Timer::after(Duration::from_secs(60)).await;
+    }
+}
+
+// 配置文档: 导出, 导入和闪存里的配置记录使用同一种 JSON 格式
+//
+//   {"version":1,"redacted":false,"rate_limit":{...},"deadlines":{...},
+//    "tcp":{...},"macros":"[diag]\nATI\n..."}
+//
+// The flash record holds the same document without "macros" (those have
+// their own record). Missing fields keep their current value.
+const CONFIG_VERSION: u64 = 1;
+const CONFIG_TEXT_MAX: usize = flash_store::Record::Config.capacity();
+
+type FieldErrors = heapless::Vec<(heapless::String<48>, heapless::String<96>), 16>;
+
+struct ConfigDoc {
+    config: config::Config,
+    macros: Option<macros::Library>,
+}
+
+// Only the first 16 problems are kept
+fn push_field_error(errors: &mut FieldErrors, field: &str, problem: core::fmt::Arguments) {
+    let mut entry = (heapless::String::new(), heapless::String::new());
+    let _ = entry.0.push_str(field);
+    let _ = core::write!(entry.1, "{}", problem);
+    let _ = errors.push(entry);
+}
+
+fn push_arg_error(errors: &mut FieldErrors, field: &str, error: at::ArgError) {
+    let mut problem = heapless::String::<48>::new();
+    error.describe(&mut problem);
+    push_field_error(errors, field, format_args!("{}", problem));
+}
+
+// 先校验每个字段, 全部通过才返回; 不修改任何全局状态.
+// A JSON syntax error is reported with an empty field name.
+fn parse_config_doc(text: &str, base: &config::Config, errors: &mut FieldErrors) -> Option<ConfigDoc> {
+    let mut config = base.clone();
+    let mut library = None;
+    let mut version = None;
+
+    let walked = json::walk(text, |path, value| match (path, value) {
+        ("version", json::Value::Number(v)) => version = Some(v),
+        ("version", _) => push_field_error(errors, path, format_args!("must be {}", CONFIG_VERSION)),
+        ("redacted", json::Value::Bool(false)) => {}
+        // 打码的导出里 PIN 和密码已经变成 '*', 导入会把它们写进闪存
+        ("redacted", _) => push_field_error(errors, path, format_args!("redacted exports cannot be imported")),
+        ("macros", json::Value::Str(raw)) => match json::unescape::<MACRO_TEXT_MAX>(raw) {
+            Some(text) => match macros::Library::parse(&text) {
+                Ok(parsed) => library = Some(parsed),
+                Err(e) => {
+                    let mut reason = heapless::String::<96>::new();
+                    e.describe(&mut reason);
+                    push_field_error(errors, path, format_args!("{}", reason));
+                }
+            },
+            None => push_field_error(errors, path, format_args!("bad escape or longer than {} bytes", MACRO_TEXT_MAX)),
+        },
+        ("macros", _) => push_field_error(errors, path, format_args!("must be a string")),
+        (_, _) if config::omitted(path) => {}
+        (_, json::Value::Number(v)) => match config.set(path, v.min(u32::MAX as u64) as u32) {
+            Ok(()) => {}
+            Err(config::FieldError::Unknown) => push_field_error(errors, path, format_args!("unknown field")),
+            Err(config::FieldError::OutOfRange { min, max }) => {
+                push_field_error(errors, path, format_args!("must be {}-{}", min, max))
+            }
+            Err(_) => push_field_error(errors, path, format_args!("must be a string")),
+        },
+        (_, json::Value::Str(raw)) if config.get_text(path).is_some() => {
+            let result = json::unescape::<128>(raw).map(|text| config.set_text(path, &text));
+            match result {
+                Some(Ok(())) => {}
+                Some(Err(config::FieldError::TooLong { max })) => {
+                    push_field_error(errors, path, format_args!("at most {} bytes", max))
+                }
+                Some(Err(config::FieldError::Arg(e))) => push_arg_error(errors, path, e),
+                Some(Err(_)) => push_field_error(errors, path, format_args!("invalid value")),
+                None => push_field_error(errors, path, format_args!("bad escape or too long")),
+            }
+        }
+        (_, _) if config.get(path).is_some() => push_field_error(errors, path, format_args!("must be a number")),
+        (_, _) if config.get_text(path).is_some() => push_field_error(errors, path, format_args!("must be a string")),
+        (_, _) => push_field_error(errors, path, format_args!("unknown field")),
+    });
+    if let Err(json::SyntaxError(at)) = walked {
+        errors.clear();
+        push_field_error(errors, "", format_args!("invalid JSON at byte {}", at));
+        return None;
+    }
+
+    if version != Some(CONFIG_VERSION) {
+        push_field_error(errors, "version", format_args!("must be {}", CONFIG_VERSION));
+    }
+    if errors.is_empty()
+        && let Some((field, problem)) = config.check()
     {
-        let mut result = AT_RESULT.lock().await;
-        let _ = result.push_str("\n\n🔚 Process completed.\n");
+        push_field_error(errors, field, format_args!("{}", problem));
     }
+    errors.is_empty().then_some(ConfigDoc { config, macros: library })
 }
 
-// 安全的AT命令发送
-async fn send_at_command_safe(tx: &mut BufferedUartTx, rx: &mut BufferedUartRx, 
-                             cmd: &str, desc: &str, step: u8, total: u8) -> bool {
+fn load_config(store: &mut flash_store::Store) {
+    let mut buf = [0u8; CONFIG_TEXT_MAX];
+    let Some(stored) = store.load(flash_store::Record::Config, &mut buf) else {
+        info!("No stored config, using defaults");
+        return;
+    };
+    let doc = core::str::from_utf8(stored)
+        .ok()
+        .and_then(|text| parse_config_doc(text, &config::Config::DEFAULT, &mut FieldErrors::new()));
+    match doc {
+        Some(doc) => {
+            apply_config(doc.config);
+            info!("Config loaded from flash");
+        }
+        None => warn!("Stored config unreadable, using defaults"),
+    }
+}
+
+// 替换运行中的配置; 保存在配置之外的设置 (日志级别) 一起更新
+fn apply_config(config: config::Config) {
+    log_level::set(config.log.level);
+    CONFIG.lock(|c| *c.borrow_mut() = config);
+}
+
+// 修改单个字符串设置并写入闪存
+// POST /config: fields missing from the form keep their value; nothing is
+// saved unless every field given is valid
+fn apply_config_form(form: &[u8], errors: &mut FieldErrors) -> bool {
+    let mut config = CONFIG.lock(|c| c.borrow().clone());
+    for field in config::FIELDS {
+        let Some(raw) = http::form_value(form, field.path) else {
+            continue;
+        };
+        match raw.trim().parse::<u32>().map(|value| config.set(field.path, value)) {
+            Ok(Ok(())) => {}
+            Ok(Err(config::FieldError::OutOfRange { min, max })) => {
+                push_field_error(errors, field.path, format_args!("must be {}-{}", min, max))
+            }
+            _ => push_field_error(errors, field.path, format_args!("must be a number")),
+        }
+    }
+    for &(path, _) in config::TEXT_FIELDS {
+        let Some(raw) = http::form_value(form, path) else {
+            continue;
+        };
+        match http::percent_decode::<128>(raw).map(|value| config.set_text(path, &value)) {
+            Some(Ok(())) => {}
+            Some(Err(config::FieldError::TooLong { max })) => {
+                push_field_error(errors, path, format_args!("at most {} bytes", max))
+            }
+            Some(Err(config::FieldError::Arg(e))) => push_arg_error(errors, path, e),
+            Some(Err(_)) => push_field_error(errors, path, format_args!("invalid value")),
+            None => push_field_error(errors, path, format_args!("bad encoding or too long")),
+        }
+    }
+    if errors.is_empty()
+        && let Some((field, problem)) = config.check()
     {
-        let mut result = AT_RESULT.lock().await;
-        let _ = result.push_str("\nStep ");
-        let mut step_str = heapless::String::<3>::new();
-        let _ = write_u32(&mut step_str, step as u32);
-        let _ = result.push_str(&step_str);
-        let _ = result.push_str("/");
-        let mut total_str = heapless::String::<3>::new();
-        let _ = write_u32(&mut total_str, total as u32);
-        let _ = result.push_str(&total_str);
-        let _ = result.push_str(": ");
-        let _ = result.push_str(desc);
-        let _ = result.push_str("...\n");
+        push_field_error(errors, field, format_args!("{}", problem));
+    }
+    if !errors.is_empty() {
+        return false;
+    }
+    if !save_config(&config) {
+        push_field_error(errors, "", format_args!("flash write failed"));
+        return false;
+    }
+    apply_config(config);
+    info!("Config saved from the web form");
+    true
+}
+
+// 表单属性值 (单引号) 里的转义
+fn push_attr_escaped<const N: usize>(out: &mut heapless::String<N>, text: &str) {
+    for c in text.chars() {
+        let _ = match c {
+            '\'' => out.push_str("&#39;"),
+            '<' => out.push_str("&lt;"),
+            '&' => out.push_str("&amp;"),
+            c => out.push(c),
+        };
+    }
+}
+
+// GET /config, or the form again with the problems of a rejected POST
+fn format_config_html(errors: Option<&FieldErrors>) -> heapless::String<12288> {
+    let mut html = heapless::String::new();
+    let status = if errors.is_some() { "422 Unprocessable Entity" } else { "200 OK" };
+    push_html_head(&mut html, status, None);
+    push_page_header(&mut html, "/config", "Config", Refresh::Off);
+    let _ = html.push_str("<h1>⚙️ Config</h1>");
+
+    if let Some(errors) = errors {
+        let _ = html.push_str("<div class='warning'><strong>Not saved:</strong>");
+        for (field, problem) in errors {
+            let _ = html.push_str("<br>");
+            push_html_escaped(&mut html, field);
+            let _ = html.push_str(": ");
+            push_html_escaped(&mut html, problem);
+        }
+        let _ = html.push_str("</div>");
+    }
+
+    let config = CONFIG.lock(|c| c.borrow().clone());
+    let _ = html.push_str("<form method='post' action='/config'><table><tr><th>Setting</th><th>Value</th><th>Range</th></tr>");
+    // 转发规则有自己的页面
+    let own_page = |path: &str| {
+        #[cfg(feature = "proxy")]
+        if forward::parse_path(path).is_some() {
+            return true;
+        }
+        trigger::parse_path(path).is_some()
+    };
+    for field in config::FIELDS.iter().filter(|f| !own_page(f.path)) {
+        let _ = core::write!(
+            html,
+            "<tr><td>{0}</td><td><input type='number' name='{0}' value='{1}' min='{2}' max='{3}'></td><td>{2}-{3}</td></tr>",
+            field.path,
+            config.get(field.path).unwrap_or(0),
+            field.min,
+            field.max
+        );
+    }
+    for &(path, max) in config::TEXT_FIELDS.iter().filter(|f| !own_page(f.0)) {
+        let value = config.get_text(path).unwrap_or("");
+        let _ = core::write!(html, "<tr><td>{0}</td><td><input type='text' name='{0}' value='", path);
+        // PIN 不回显, 原样提交掩码表示保持不变
+        if path == "sim.pin" && !value.is_empty() {
+            let _ = html.push_str(config::PIN_MASK);
+        } else {
+            push_attr_escaped(&mut html, value);
+        }
+        let _ = core::write!(html, "' maxlength='{}'></td><td>text</td></tr>", max);
+    }
+    let _ = html.push_str("</table><button type='submit' class='btn-http'>💾 Save</button></form>");
+
+    let _ = html.push_str("<p>🌙 scheduleN.features: add up");
+    for feature in schedule::Feature::ALL {
+        let _ = core::write!(html, " {} = {}", feature.bit(), feature.as_str());
+    }
+    let _ = html.push_str(". Those run only between start and end (HH:MM, network time).</p>");
+    let _ = html.push_str("<p>🏷️ device.name: 1-32 bytes, shown in page titles, alerts and exports, used right away. ");
+    let _ = core::write!(
+        html,
+        "Empty = {}. The hostname form is <strong>{}</strong>.</p>",
+        identity::default_name(WIFI_MAC.lock(|m| m.get())),
+        identity::hostname(&device_name())
+    );
+    let _ = html.push_str("<p>🔌 http.port serves the pages. http.port2, when not 0, opens a second port whose http.role2 is ");
+    let _ = html.push_str("mirror (the same pages), admin (the pages; http.port only redirects there) or portal-only ");
+    let _ = html.push_str("(only redirects to http.port). Ports apply after a reboot. ");
+    let _ = html.push_str("An async lock held longer than http.lock_budget_ms is logged; see /api/lockstats.</p>");
+    let _ = html.push_str("<p>🩺 health.checks: add up");
+    for check in health::Check::ALL {
+        let _ = core::write!(html, " {} = {}", check.bit(), check.as_str());
+    }
+    let _ = html.push_str(". <a href='/healthz'>/healthz</a> answers 503 when one of them fails. ");
+    let _ = html.push_str("health.at_rtt_warn_ms: log a warning when an AT probe is slower; 0 = off.</p>");
+    let _ = html.push_str("<p>🔋 power.ap_idle_min: stop the WiFi AP after this many minutes without a client ");
+    let _ = html.push_str("connection; 0 = never. It comes back when the button from power.wake_gpio to ground is ");
+    let _ = html.push_str("pressed (power.wake_button, after a reboot) or between power.wake_start and ");
+    let _ = html.push_str("power.wake_end (HH:MM).</p>");
+    let _ = html.push_str("<p>🎬 actions.web, actions.input: the actions each source may run; add up");
+    for action in actions::Action::ALL {
+        let _ = core::write!(html, " {} = {}", action.bit(), action.as_str());
+    }
+    let _ = html.push_str(". A refused action is still listed on <a href='/api/actions'>/api/actions</a>.</p>");
+    let _ = html.push_str("<p>📶 modem.pwrkey: when the modem does not answer at boot, press its power key ");
+    let _ = html.push_str("for 600 ms from modem.pwrkey_gpio (high = pressed, through an NPN that pulls PWRKEY low), ");
+    let _ = html.push_str("then wait for RDY. Used after a reboot.</p>");
+    let _ = html.push_str("<p>📇 arp.pins: up to 4 ip=mac pairs separated by spaces, e.g. ");
+    let _ = html.push_str("192.168.4.20=aa:bb:cc:dd:ee:ff. The gateway answers its own ARP requests for them ");
+    let _ = html.push_str("instead of asking the client. arp.events: log clients added, moved and forgotten ");
+    let _ = html.push_str("at debug level. ");
+    let _ = html.push_str("See <a href='/net'>/net</a>.</p>");
+    #[cfg(feature = "proxy")]
+    let _ = html.push_str("<p>🔀 <a href='/config/forwards'>Port forwards</a> | ⚡ <a href='/config/triggers'>Input triggers</a></p>");
+    #[cfg(not(feature = "proxy"))]
+    let _ = html.push_str("<p>⚡ <a href='/config/triggers'>Input triggers</a></p>");
+    let _ = html.push_str("<p>⬇️ <a href='/api/config/export'>Export</a> | ⬆️ Import: POST the exported JSON to /api/config/import</p>");
+    let _ = html.push_str("<form method='post' action='/api/config/factory-reset' onsubmit=\"return confirm('Erase all settings and reboot?')\">");
+    let _ = html.push_str("<button type='submit' class='btn-at'>🧹 Factory reset</button></form>");
+    let _ = html.push_str("</div></body></html>");
+
+    http::set_content_length(&mut html);
+    html
+}
+
+// GET /config/forwards, or the form again with the problems of a rejected POST
+#[cfg(feature = "proxy")]
+fn format_forwards_html(errors: Option<&FieldErrors>) -> heapless::String<4096> {
+    let mut html = heapless::String::new();
+    let status = if errors.is_some() { "422 Unprocessable Entity" } else { "200 OK" };
+    push_html_head(&mut html, status, None);
+    push_page_header(&mut html, "/config/forwards", "Forwards", Refresh::Off);
+    let _ = html.push_str("<h1>🔀 Port forwards</h1>");
+    let _ = html.push_str("<p>A client connecting to the listen port on 192.168.4.1 is connected through the modem to the destination. ");
+    let _ = html.push_str("One client per forward; live state is on <a href='/net'>/net</a>.</p>");
+    let _ = html.push_str("<p>A forward to an HTTP proxy can be handed to browsers with <a href='/proxy.pac'>/proxy.pac</a>: ");
+    let _ = html.push_str("set pac.forward on the <a href='/config'>config page</a> to its number.</p>");
+
+    if let Some(errors) = errors {
+        let _ = html.push_str("<div class='warning'><strong>Not saved:</strong>");
+        for (field, problem) in errors {
+            let _ = html.push_str("<br>");
+            push_html_escaped(&mut html, field);
+            let _ = html.push_str(": ");
+            push_html_escaped(&mut html, problem);
+        }
+        let _ = html.push_str("</div>");
+    }
+
+    let rules = CONFIG.lock(|c| c.borrow().forwards.clone());
+    let _ = html.push_str("<form method='post' action='/config/forwards'><table>");
+    let _ = html.push_str("<tr><th>Forward</th><th>Enabled</th><th>Listen port</th><th>Host</th><th>Port</th></tr>");
+    for (name, rule) in forward::NAMES.iter().zip(rules.iter()) {
+        let _ = core::write!(
+            html,
+            "<tr><td>{0}</td><td><select name='{0}.enabled'><option value='0'>off</option><option value='1'{1}>on</option></select></td>",
+            name,
+            if rule.enabled { " selected" } else { "" }
+        );
+        let _ = core::write!(
+            html,
+            "<td><input type='number' name='{}.listen_port' value='{}' min='0' max='65535'></td>",
+            name,
+            rule.listen_port
+        );
+        let _ = core::write!(html, "<td><input type='text' name='{}.host' value='", name);
+        push_attr_escaped(&mut html, &rule.host);
+        let _ = core::write!(
+            html,
+            "' maxlength='64'></td><td><input type='number' name='{}.port' value='{}' min='0' max='65535'></td></tr>",
+            name,
+            rule.port
+        );
+    }
+    let _ = html.push_str("</table><button type='submit' class='btn-http'>💾 Save</button></form>");
+    let _ = html.push_str("</div></body></html>");
+
+    http::set_content_length(&mut html);
+    html
+}
+
+// GET /config/triggers, or the form again with the problems of a rejected POST
+fn format_triggers_html(errors: Option<&FieldErrors>) -> heapless::String<6144> {
+    let mut html = heapless::String::new();
+    let status = if errors.is_some() { "422 Unprocessable Entity" } else { "200 OK" };
+    push_html_head(&mut html, status, None);
+    push_page_header(&mut html, "/config/triggers", "Triggers", Refresh::Off);
+    let _ = html.push_str("<h1>⚡ Input triggers</h1>");
+    let _ = html.push_str("<p>Inputs have the pull-up on: wire the contact to ground. ");
+    let _ = html.push_str("A new GPIO, or an input turned on from off, takes effect after a reboot.</p>");
+
+    if let Some(errors) = errors {
+        let _ = html.push_str("<div class='warning'><strong>Not saved:</strong>");
+        for (field, problem) in errors {
+            let _ = html.push_str("<br>");
+            push_html_escaped(&mut html, field);
+            let _ = html.push_str(": ");
+            push_html_escaped(&mut html, problem);
+        }
+        let _ = html.push_str("</div>");
+    }
+
+    let bindings = CONFIG.lock(|c| c.borrow().triggers.clone());
+    let _ = html.push_str("<form method='post' action='/config/triggers'><table>");
+    let _ = html.push_str("<tr><th>Input</th><th>GPIO</th><th>Edge</th><th>Debounce ms</th><th>Action</th><th>SMS to</th><th>Min s apart</th></tr>");
+    for (name, binding) in trigger::NAMES.iter().zip(bindings.iter()) {
+        let _ = core::write!(
+            html,
+            "<tr><td>{0}</td><td><input type='number' name='{0}.gpio' value='{1}' min='0' max='28'></td><td><select name='{0}.edge'>",
+            name,
+            binding.gpio
+        );
+        for edge in [trigger::Edge::Falling, trigger::Edge::Rising, trigger::Edge::Both] {
+            let selected = if edge == binding.edge { " selected" } else { "" };
+            let _ = core::write!(html, "<option{}>{}</option>", selected, edge.as_str());
+        }
+        let _ = core::write!(
+            html,
+            "</select></td><td><input type='number' name='{}.debounce_ms' value='{}' min='0' max='10000'></td><td><select name='{}.action'>",
+            name,
+            binding.debounce_ms,
+            name
+        );
+        for action in [trigger::Action::Off, trigger::Action::Fetch, trigger::Action::Webhook, trigger::Action::Sms] {
+            let selected = if action == binding.action { " selected" } else { "" };
+            let _ = core::write!(html, "<option{}>{}</option>", selected, action.as_str());
+        }
+        let _ = core::write!(html, "</select></td><td><input type='text' name='{}.sms' value='", name);
+        push_attr_escaped(&mut html, &binding.sms);
+        let _ = core::write!(
+            html,
+            "' maxlength='20'></td><td><input type='number' name='{}.min_interval_s' value='{}' min='0' max='86400'></td></tr>",
+            name,
+            binding.min_interval_s
+        );
+    }
+    let _ = html.push_str("</table><button type='submit' class='btn-http'>💾 Save</button></form>");
+
+    let now = Instant::now().as_millis();
+    TRIGGER_LOG.lock(|l| {
+        let log = l.borrow();
+        let _ = core::write!(html, "<h2>Recent triggers ({} since boot)</h2>", log.total);
+        if log.total == 0 {
+            let _ = html.push_str("<p>None yet.</p>");
+            return;
+        }
+        let _ = html.push_str("<table><tr><th>Input</th><th>GPIO</th><th>Level</th><th>Action</th><th>Result</th><th>Age</th></tr>");
+        for occurrence in log.iter() {
+            let _ = core::write!(
+                html,
+                "<tr><td>{}</td><td>GP{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} s ago</td></tr>",
+                trigger::NAMES[occurrence.index],
+                occurrence.gpio,
+                if occurrence.high { "high" } else { "low" },
+                occurrence.action.as_str(),
+                occurrence.outcome.as_str(),
+                now.saturating_sub(occurrence.at_ms) / 1000
+            );
+        }
+        let _ = html.push_str("</table>");
+    });
+    let _ = html.push_str("</div></body></html>");
+
+    http::set_content_length(&mut html);
+    html
+}
+
+fn store_text_setting(path: &str, value: &str) -> bool {
+    let mut config = CONFIG.lock(|c| c.borrow().clone());
+    if config.set_text(path, value).is_err() || !save_config(&config) {
+        return false;
     }
-    
-    match tx.write_all(cmd.as_bytes()).await {
-        Ok(_) => {
-            tx.flush().await.ok();
-            Timer::after(Duration::from_millis(300)).await;
-            
-            let mut got_ok = false;
-            let mut got_error = false;
-            
-            for _ in 0..6 {
-                let mut buf = [0u8; 128];
-                match rx.read(&mut buf).await {
-                    Ok(n) if n > 0 => {
-                        if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-                            {
-                                let mut result = AT_RESULT.lock().await;
-                                let _ = result.push_str("  -> ");
-                                let _ = result.push_str(s.trim());
-                                let _ = result.push_str("\n");
-                            }
-                            
-                            if s.contains("OK") {
-                                got_ok = true;
-                            }
-                            if s.contains("ERROR") {
-                                got_error = true;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-                Timer::after(Duration::from_millis(200)).await;
-                
-                if got_ok || got_error {
-                    break;
-                }
-            }
-            
-            if got_error {
-                {
-                    let mut result = AT_RESULT.lock().await;
-                    let _ = result.push_str("\n❌ ");
-                    let _ = result.push_str(desc);
-                    let _ = result.push_str(" failed\n");
-                }
-                return false;
+    apply_config(config);
+    true
+}
+
+fn write_record(record: flash_store::Record, data: &[u8]) -> bool {
+    FLASH_STORE.lock(|s| match s.borrow_mut().as_mut() {
+        Some(store) => store.save(record, data).is_ok(),
+        None => false,
+    })
+}
+
+fn save_config(config: &config::Config) -> bool {
+    let mut text = heapless::String::<CONFIG_TEXT_MAX>::new();
+    let _ = core::write!(text, "{{\"version\":{},", CONFIG_VERSION);
+    config.write_json_members(&mut text, config::Secrets::Obfuscate);
+    let _ = text.push('}');
+    write_record(flash_store::Record::Config, text.as_bytes())
+}
+
+// GET /api/config/export[?redact=1]: 宏逐个写出, 不需要整块缓冲区
+async fn serve_config_export(socket: &mut Conn<'_, '_>, redact: bool) {
+    let mut out = heapless::String::<4096>::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = core::write!(
+        out,
+        "Content-Disposition: attachment; filename=\"{}-config.json\"\r\n",
+        identity::hostname(&device_name())
+    );
+    let _ = out.push_str("Cache-Control: no-store\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+
+    let config = CONFIG.lock(|c| c.borrow().clone());
+    let _ = core::write!(out, "{{\"version\":{},\"redacted\":{},", CONFIG_VERSION, redact);
+    config.write_json_members(&mut out, config::Secrets::Mask);
+    let _ = out.push_str(",\"macros\":\"");
+    let _ = socket.write_all(out.as_bytes()).await;
+
+    let count = MACROS.lock(|m| m.borrow().iter().count());
+    for index in 0..count {
+        let mut text = heapless::String::<1024>::new();
+        MACROS.lock(|m| {
+            if let Some(mac) = m.borrow().iter().nth(index) {
+                let _ = core::writeln!(text, "[{}]", mac.name);
+                mac.write_steps(&mut text);
             }
-            
-            true
-        }
-        Err(_) => {
-            {
-                let mut result = AT_RESULT.lock().await;
-                let _ = result.push_str("\n❌ Failed to send ");
-                let _ = result.push_str(desc);
-                let _ = result.push_str(" command\n");
+        });
+        out.clear();
+        for line in text.lines() {
+            if redact {
+                let mut masked = heapless::String::<192>::new();
+                macros::push_masked(&mut masked, line);
+                json::push_escaped(&mut out, &masked);
+            } else {
+                json::push_escaped(&mut out, line);
             }
-            false
+            let _ = out.push_str("\\n");
         }
+        let _ = socket.write_all(out.as_bytes()).await;
     }
+    let _ = socket.write_all(b"\"}").await;
+    let _ = socket.flush().await;
 }
 
-// 安全的TCP连接打开
-async fn open_tcp_safe(tx: &mut BufferedUartTx, rx: &mut BufferedUartRx, 
-                      ip: &str, port: u16) -> bool {
-    // Build command manually without format!
-    let mut cmd = heapless::String::<64>::new();
-    let _ = cmd.push_str("AT+QIOPEN=1,0,\"TCP\",\"");
-    let _ = cmd.push_str(ip);
-    let _ = cmd.push_str("\",");
-    
-    // Convert port number to string
-    let mut port_str = heapless::String::<5>::new();
-    let mut port_temp = port;
-    if port_temp == 0 {
-        let _ = port_str.push_str("0");
-    } else {
-        let mut digits = heapless::Vec::<u8, 5>::new();
-        while port_temp > 0 {
-            let digit = (port_temp % 10) as u8 + b'0';
-            let _ = digits.push(digit);
-            port_temp /= 10;
+// POST /api/config/import: 所有字段通过校验后才写闪存, 两条记录都写成功才生效
+fn import_config(text: &str) -> (&'static str, heapless::String<2048>) {
+    let mut body = heapless::String::new();
+    let base = CONFIG.lock(|c| c.borrow().clone());
+    let mut errors = FieldErrors::new();
+    if let Some(doc) = parse_config_doc(text, &base, &mut errors) {
+        let mut saved = save_config(&doc.config);
+        if saved && let Some(library) = &doc.macros {
+            let mut text = heapless::String::<MACRO_TEXT_MAX>::new();
+            library.write_text(&mut text);
+            saved = write_record(flash_store::Record::Macros, text.as_bytes());
         }
-        for digit in digits.iter().rev() {
-            let _ = port_str.push(*digit as char);
+        if !saved {
+            error!("Config import: flash write failed");
+            let _ = body.push_str("{\"ok\":false,\"error\":\"flash write failed, stored config may be partly updated\"}");
+            return ("500 Internal Server Error", body);
+        }
+
+        apply_config(doc.config);
+        if let Some(library) = doc.macros {
+            MACROS.lock(|m| *m.borrow_mut() = library);
         }
+        info!("Config imported");
+        let _ = body.push_str("{\"ok\":true}");
+        return ("200 OK", body);
     }
-    
-    let _ = cmd.push_str(&port_str);
-    let _ = cmd.push_str(",0,0\r\n");
-    
-    match tx.write_all(cmd.as_bytes()).await {
-        Ok(_) => {
-            tx.flush().await.ok();
-            
-            let mut connected = false;
-            
-            for _ in 0..20 {
-                let mut buf = [0u8; 128];
-                match rx.read(&mut buf).await {
-                    Ok(n) if n > 0 => {
-                        if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-                            {
-                                let mut result = AT_RESULT.lock().await;
-                                let _ = result.push_str("  -> ");
-                                let _ = result.push_str(s.trim());
-                                let _ = result.push_str("\n");
-                            }
-                            
-                            if s.contains("CONNECT") || s.contains("+QIOPEN: 0,0") || s.contains("OK") {
-                                connected = true;
-                                break;
-                            }
-                            if s.contains("ERROR") || s.contains("+QIOPEN: 0,4") {
-                                break;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-                Timer::after(Duration::from_millis(500)).await;
-            }
-            
-            if !connected {
-                {
-                    let mut result = AT_RESULT.lock().await;
-                    let _ = result.push_str("\n❌ TCP connection failed\n");
-                }
-                return false;
-            }
-            
-            true
+
+    warn!("Config import rejected: {} problems", errors.len());
+    let _ = body.push_str("{\"ok\":false,\"errors\":[");
+    for (i, (field, problem)) in errors.iter().enumerate() {
+        if i > 0 {
+            let _ = body.push(',');
         }
-        Err(_) => {
-            {
-                let mut result = AT_RESULT.lock().await;
-                let _ = result.push_str("\n❌ Failed to send TCP command\n");
+        let mut obj = json::Object::new(&mut body);
+        obj.str("field", field).str("error", problem);
+        obj.finish();
+    }
+    let _ = body.push_str("]}");
+    let status = if errors.first().is_some_and(|(field, _)| field.is_empty()) {
+        "400 Bad Request"
+    } else {
+        "422 Unprocessable Entity"
+    };
+    (status, body)
+}
+
+// 请求缓冲区里已有的正文 (`received`) 之外, 其余部分在这里继续读
+async fn serve_config_import(socket: &mut Conn<'_, '_>, body: Option<&mut http::BodyReader<'_>>) {
+    let fail = |status, problem: &str| {
+        let mut body = heapless::String::<2048>::new();
+        let mut obj = json::Object::new(&mut body);
+        obj.bool("ok", false).str("error", problem);
+        obj.finish();
+        (status, body)
+    };
+    let (status, body) = match body {
+        None => fail("411 Length Required", "Content-Length required"),
+        Some(body) if body.remaining() > limits::CONFIG_DOC_MAX => fail("413 Payload Too Large", "document too large"),
+        Some(body) => {
+            let mut doc = held(lock_stats::Site::PostBody, POST_BODY.lock().await);
+            let len = body.remaining();
+            match body.read_full(socket.get_mut(), &mut doc[..len]).await {
+                Ok(filled) if filled == len => match core::str::from_utf8(&doc[..len]) {
+                    Ok(text) => import_config(text),
+                    Err(_) => fail("400 Bad Request", "document is not UTF-8"),
+                },
+                _ => fail("408 Request Timeout", "document incomplete"),
             }
-            false
         }
+    };
+
+    let mut head = heapless::String::<160>::new();
+    let _ = core::write!(head, "HTTP/1.1 {}\r\n", status);
+    let _ = head.push_str("Content-Type: application/json\r\n");
+    let _ = core::write!(head, "Content-Length: {}\r\n", body.len());
+    let _ = head.push_str("Connection: close\r\n\r\n");
+    let _ = socket.write_all(head.as_bytes()).await;
+    let _ = socket.write_all(body.as_bytes()).await;
+    let _ = socket.flush().await;
+}
+
+// 上电时恢复引脚 (board::ACTIVE.recovery) 接地进入的恢复模式
+static RECOVERY: AtomicBool = AtomicBool::new(false);
+
+// LED 覆盖 (/api/led) 和识别闪烁 (/api/identify), 重启后回到 auto
+static LED: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::RefCell<led::Led>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::RefCell::new(led::Led::new()));
+
+// LED 任务不用等到下一个周期
+static LED_CHANGED: embassy_sync::signal::Signal<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, ()> =
+    embassy_sync::signal::Signal::new();
+
+fn led_state() -> &'static str {
+    LED.lock(|l| l.borrow().as_str(Instant::now().as_millis()))
+}
+
+fn recovery_mode() -> bool {
+    RECOVERY.load(Ordering::Relaxed)
+}
+
+fn wifi_ssid() -> &'static str {
+    if recovery_mode() { RECOVERY_SSID } else { WIFI_SSID }
+}
+
+// 读自 cyw43, 默认设备名用它的最后两字节
+static WIFI_MAC: embassy_sync::blocking_mutex::Mutex<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    core::cell::Cell<[u8; 6]>,
+> = embassy_sync::blocking_mutex::Mutex::new(core::cell::Cell::new([0; 6]));
+
+fn device_name() -> identity::Name {
+    let name = CONFIG.lock(|c| c.borrow().device.name.clone());
+    if name.is_empty() { identity::default_name(WIFI_MAC.lock(|m| m.get())) } else { name }
+}
+
+// 三次短闪后熄灭, 共 1 秒; 和正常运行时的常亮区分开
+async fn blink_recovery(control: &mut cyw43::Control<'_>) {
+    for _ in 0..3 {
+        control.gpio_set(0, true).await;
+        Timer::after(Duration::from_millis(100)).await;
+        control.gpio_set(0, false).await;
+        Timer::after(Duration::from_millis(150)).await;
     }
+    Timer::after(Duration::from_millis(250)).await;
 }
 
-// 安全的发送准备
-async fn prepare_send_safe(tx: &mut BufferedUartTx, rx: &mut BufferedUartRx) -> bool {
-    match tx.write_all(b"AT+QISEND=0\r\n").await {
-        Ok(_) => {
-            tx.flush().await.ok();
-            
-            let mut got_prompt = false;
-            
-            for _ in 0..10 {
-                let mut buf = [0u8; 64];
-                match rx.read(&mut buf).await {
-                    Ok(n) if n > 0 => {
-                        if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-                            {
-                                let mut result = AT_RESULT.lock().await;
-                                let _ = result.push_str("  -> ");
-                                let _ = result.push_str(s.trim());
-                                let _ = result.push_str("\n");
-                            }
-                            
-                            if s.contains(">") {
-                                got_prompt = true;
-                                break;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-                Timer::after(Duration::from_millis(500)).await;
-            }
-            
-            if !got_prompt {
-                {
-                    let mut result = AT_RESULT.lock().await;
-                    let _ = result.push_str("\n❌ No '>' prompt received\n");
-                }
-                return false;
-            }
-            
-            true
+// 识别: 快闪 1 秒, 和上面两种都不一样
+async fn blink_identify(control: &mut cyw43::Control<'_>) {
+    for _ in 0..10 {
+        control.gpio_set(0, true).await;
+        Timer::after(Duration::from_millis(50)).await;
+        control.gpio_set(0, false).await;
+        Timer::after(Duration::from_millis(50)).await;
+    }
+}
+
+async fn blink_ap_sleeping(control: &mut cyw43::Control<'_>) {
+    control.gpio_set(0, true).await;
+    Timer::after(Duration::from_millis(50)).await;
+    control.gpio_set(0, false).await;
+}
+
+// 按 power.ap_idle_min 关掉或重开 AP; 只在主循环里调用, 它拥有 control
+async fn apply_ap_sleep(control: &mut cyw43::Control<'_>) {
+    let now = Instant::now().as_millis();
+    // 还开着的连接也算有人在用
+    if NETSTAT.lock(|n| n.borrow().iter().any(|e| e.remote.is_some())) {
+        note_client_activity();
+    }
+    let power = CONFIG.lock(|c| c.borrow().power.clone());
+    let clock = CLOCK.lock(|c| c.get());
+    let in_window = match (power.wake_window(), clock) {
+        (Some((start, end)), Some(clock)) => schedule::inside(start, end, clock.day_ms(now)),
+        _ => false,
+    };
+    let button = AP_WAKE_BUTTON.try_take().is_some();
+    let change = AP_SLEEP.lock(|a| a.borrow_mut().step(power.ap_idle_min, in_window, button, now));
+    match change {
+        None => return,
+        Some(ap_sleep::Change::Sleep) => {
+            let wake = if power.wake_button {
+                "press the wake button to wake"
+            } else {
+                "it wakes when the wake window opens"
+            };
+            warn!("AP sleeping after {} min without clients, {}", power.ap_idle_min, wake);
+            // 先让 HTTP 监听循环放下缓冲区
+            AP_AWAKE.sender().send(false);
+            control.close_ap().await;
         }
-        Err(_) => {
-            {
-                let mut result = AT_RESULT.lock().await;
-                let _ = result.push_str("\n❌ Failed to send QISEND\n");
-            }
-            false
+        Some(ap_sleep::Change::Wake(reason)) => {
+            info!("AP waking: {}", reason.as_str());
+            control.start_ap_wpa2(WIFI_SSID, WIFI_PASSWORD, 5).await;
+            AP_AWAKE.sender().send(true);
         }
     }
+    LED_CHANGED.signal(());
+    bump_state_generation();
 }
 
-// 安全的HTTP发送
-async fn send_http_safe(tx: &mut BufferedUartTx, rx: &mut BufferedUartRx) -> bool {
-    let http_request = "GET /get HTTP/1.1\r\nHost: httpbin.org\r\nUser-Agent: EC800K\r\nAccept: */*\r\nConnection: close\r\n\r\n";
-    
-    match tx.write_all(http_request.as_bytes()).await {
-        Ok(_) => {
-            // 发送Ctrl+Z
-            let ctrl_z = [0x1A];
-            let _ = tx.write_all(&ctrl_z).await;
-            tx.flush().await.ok();
-            
-            {
-                let mut result = AT_RESULT.lock().await;
-                let _ = result.push_str("  -> HTTP request sent\n");
-            }
-            
-            // 等待响应
-            Timer::after(Duration::from_secs(2)).await;
-            
-            // 检查是否有SEND OK
-            let mut send_ok = false;
-            for _ in 0..5 {
-                let mut buf = [0u8; 128];
-                match rx.read(&mut buf).await {
-                    Ok(n) if n > 0 => {
-                        if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-                            if s.contains("SEND OK") {
-                                send_ok = true;
-                                {
-                                    let mut result = AT_RESULT.lock().await;
-                                    let _ = result.push_str("  -> ");
-                                    let _ = result.push_str(s.trim());
-                                    let _ = result.push_str("\n");
-                                }
-                                break;
-                            }
-                        }
-                    }
-                    _ => {}
+// 擦除所有闪存记录, 内存中的配置回到默认值
+fn factory_reset() {
+    FLASH_STORE.lock(|s| {
+        if let Some(store) = s.borrow_mut().as_mut() {
+            for record in flash_store::Record::ALL {
+                if store.erase(record).is_err() {
+                    error!("Factory reset: erasing {} failed", record.as_str());
                 }
-                Timer::after(Duration::from_millis(500)).await;
             }
-            
-            true
         }
-        Err(_) => {
-            {
-                let mut result = AT_RESULT.lock().await;
-                let _ = result.push_str("\n❌ Failed to send HTTP request\n");
+    });
+    apply_config(config::Config::DEFAULT);
+    MACROS.lock(|m| *m.borrow_mut() = macros::Library::defaults());
+    warn!("Factory reset: flash config erased");
+}
+
+// 重启流程开始 (只走一次), 之后 submit_modem_op 拒绝新的操作
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+static SHUTDOWN_REPORTS: embassy_sync::channel::Channel<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    shutdown::Report,
+    { shutdown::Step::ALL.len() },
+> = embassy_sync::channel::Channel::new();
+
+// Take the modem down before a reset, each step logged and, for
+// POST /api/reboot, written to the client as it completes
+async fn quiesce_modem(mut socket: Option<&mut Conn<'_, '_>>) {
+    if recovery_mode() || SHUTTING_DOWN.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let deadline = Instant::now() + shutdown::TOTAL_TIMEOUT;
+    let cancelled = cancel_fetch().await;
+    debug!("Shutdown: fetch {}", cancelled);
+    let _ = SHUTDOWN_REPORTS.try_send(shutdown::Report {
+        step: shutdown::Step::CancelFetch,
+        outcome: shutdown::Outcome::Done,
+    });
+    submit_modem_op(ModemOp::Shutdown);
+
+    for _ in shutdown::Step::ALL {
+        let Ok(report) = embassy_time::with_deadline(deadline, SHUTDOWN_REPORTS.receive()).await else {
+            warn!("Modem shutdown did not finish in time, resetting anyway");
+            if let Some(socket) = socket.as_deref_mut() {
+                let _ = socket.write_all(b"modem shutdown: timed out, resetting anyway\n").await;
             }
-            false
+            break;
+        };
+        info!("Modem shutdown: {} {}", report.step.as_str(), report.outcome.as_str());
+        if let Some(socket) = socket.as_deref_mut() {
+            let mut line = heapless::String::<64>::new();
+            let _ = core::writeln!(line, "{}: {}", report.step.as_str(), report.outcome.as_str());
+            let _ = socket.write_all(line.as_bytes()).await;
+            let _ = socket.flush().await;
         }
     }
 }
 
-// 安全的响应读取
-async fn read_response_safe(tx: &mut BufferedUartTx, rx: &mut BufferedUartRx) {
-    // 先等待一下，让数据到达
-    Timer::after(Duration::from_secs(3)).await;
-    
-    // 发送读取命令
-    let _ = tx.write_all(b"AT+QIRD=0,500\r\n").await;
-    tx.flush().await.ok();
-    
-    // 等待并读取
-    Timer::after(Duration::from_secs(2)).await;
-    
-    let mut response = heapless::String::<1024>::new();
-    let mut got_data = false;
-    
-    for _ in 0..5 {
-        let mut buf = [0u8; 256];
-        match rx.read(&mut buf).await {
-            Ok(n) if n > 0 => {
-                got_data = true;
-                if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-                    let _ = response.push_str(s);
+// The modem side of `quiesce_modem`, run as ModemOp::Shutdown
+async fn power_down_modem(tx: &mut ModemTx, rx: &mut ModemRx) {
+    let modem = current_modem();
+    let report = |step, outcome| {
+        let _ = SHUTDOWN_REPORTS.try_send(shutdown::Report { step, outcome });
+    };
+
+    // 空闲的连接 ID 回复 ERROR, 不算失败
+    FETCH_KEPT.lock(|k| k.borrow_mut().take());
+    #[cfg(feature = "proxy")]
+    let last_id = forward::connect_id(forward::MAX_FORWARDS - 1);
+    #[cfg(not(feature = "proxy"))]
+    let last_id = fetch::CONNECT_ID;
+    for id in fetch::CONNECT_ID..=last_id {
+        quiet_command(tx, rx, &modem.tcp_close(id), shutdown::CLOSE_TIMEOUT).await;
+        track_modem_socket(|m, now| m.closed(id, modem_sockets::CloseReason::Us, now));
+    }
+    report(shutdown::Step::CloseConnections, shutdown::Outcome::Done);
+
+    let deactivated = quiet_command(tx, rx, &modem.deactivate_pdp(), shutdown::DEACTIVATE_TIMEOUT).await;
+    report(
+        shutdown::Step::Deactivate,
+        if deactivated { shutdown::Outcome::Done } else { shutdown::Outcome::Failed },
+    );
+
+    let outcome = if uart_write_all(tx, modem.power_down().as_bytes()).await.is_err() {
+        shutdown::Outcome::Failed
+    } else if await_line(rx, shutdown::POWER_DOWN_TIMEOUT, |line| modem.parse_powered_down(line)).await {
+        shutdown::Outcome::Done
+    } else {
+        shutdown::Outcome::TimedOut
+    };
+    report(shutdown::Step::PowerDown, outcome);
+}
+
+// 留一点时间让响应发出去; 先关模块, 日志存一次检查点
+async fn reboot() -> ! {
+    quiesce_modem(None).await;
+    warn!("Rebooting");
+    checkpoint_log(true).await;
+    save_stats();
+    Timer::after(Duration::from_millis(200)).await;
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+fn push_masked_line<const N: usize>(html: &mut heapless::String<N>, line: &[u8]) {
+    let mut masked = heapless::String::<192>::new();
+    macros::push_masked(&mut masked, core::str::from_utf8(line).unwrap_or("?"));
+    push_html_escaped(html, &masked);
+    let _ = html.push('\n');
+}
+
+// 恢复页面: 闪存里保存的配置 (密码打码), 恢复出厂和重启按钮
+async fn serve_recovery_page(socket: &mut Conn<'_, '_>) {
+    let mut html = heapless::String::<2048>::new();
+
+    let _ = html.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = html.push_str("Content-Type: text/html; charset=utf-8\r\n");
+    let _ = html.push_str("Cache-Control: no-store\r\n");
+    let _ = html.push_str("Connection: close\r\n\r\n");
+
+    let _ = html.push_str("<!DOCTYPE html><html><head>");
+    let _ = html.push_str("<title>🛟 RECOVERY MODE - Pico2W</title>");
+    let _ = html.push_str("<meta name='viewport' content='width=device-width, initial-scale=1'>");
+    let _ = html.push_str("<link rel='stylesheet' href='/style.css'>");
+    let _ = html.push_str("</head><body><div class='container'>");
+    let _ = html.push_str("<h1>🛟 RECOVERY MODE</h1>");
+    let _ = core::write!(html, "<div class='warning error'>GP{} was held low at power-up. ", board::ACTIVE.recovery);
+    let _ = html.push_str("The stored config was not loaded, the modem was not initialised and the access point is open. ");
+    let _ = core::write!(html, "Release GP{} and reboot to return to normal operation.</div>", board::ACTIVE.recovery);
+    push_boot_html(&mut html);
+
+    let _ = html.push_str("<h3>Compiled-in settings</h3><table>");
+    let _ = core::write!(html, "<tr><td>WiFi SSID</td><td>{}</td></tr>", WIFI_SSID);
+    let _ = html.push_str("<tr><td>WiFi password</td><td>********</td></tr>");
+    let _ = html.push_str("<tr><td>IP</td><td>192.168.4.1</td></tr>");
+    let _ = core::write!(html, "<tr><td>UART</td><td>{} baud</td></tr>", UART_BAUDRATE);
+    let _ = core::write!(html, "<tr><td>Board</td><td>{}</td></tr>", board::ACTIVE.name);
+    let _ = html.push_str("</table>");
+    let _ = socket.write_all(html.as_bytes()).await;
+
+    for record in flash_store::Record::ALL {
+        html.clear();
+        let len = FLASH_STORE.lock(|s| s.borrow_mut().as_mut().and_then(|store| store.stored_len(record)));
+        let _ = core::write!(html, "<h3>Stored {}</h3>", record.as_str());
+        let Some(len) = len else {
+            let _ = html.push_str("<p>Nothing stored (or the record is corrupt).</p>");
+            let _ = socket.write_all(html.as_bytes()).await;
+            continue;
+        };
+        let _ = html.push_str("<pre>");
+
+        // 按行读出, 逐行打码后再转义
+        let mut chunk = [0u8; 256];
+        let mut line = heapless::Vec::<u8, 160>::new();
+        let mut offset = 0;
+        while offset < len {
+            let n = (len - offset).min(chunk.len());
+            let read = FLASH_STORE.lock(|s| match s.borrow_mut().as_mut() {
+                Some(store) => store.read(record, offset, &mut chunk[..n]).is_ok(),
+                None => false,
+            });
+            if !read {
+                break;
+            }
+            offset += n;
+            for &b in &chunk[..n] {
+                if b != b'\n' && line.push(b).is_ok() {
+                    continue;
+                }
+                push_masked_line(&mut html, &line);
+                line.clear();
+                if b != b'\n' {
+                    let _ = line.push(b);
                 }
             }
-            _ => {}
+            if html.len() > html.capacity() - 512 {
+                let _ = socket.write_all(html.as_bytes()).await;
+                html.clear();
+            }
         }
-        Timer::after(Duration::from_millis(500)).await;
-    }
-    
-    {
-        let mut result = AT_RESULT.lock().await;
-        if got_data {
-            let _ = result.push_str("\n--- HTTP Response ---\n");
-            let _ = result.push_str(&response);
-            let _ = result.push_str("\n--- End ---\n");
-        } else {
-            let _ = result.push_str("\n⚠️ No data received\n");
+        if !line.is_empty() {
+            push_masked_line(&mut html, &line);
         }
+        let _ = html.push_str("</pre>");
+        let _ = socket.write_all(html.as_bytes()).await;
     }
+
+    html.clear();
+    let _ = html.push_str("<h3>Actions</h3>");
+    let _ = html.push_str("<form method='post' action='/api/recovery/factory-reset' ");
+    let _ = html.push_str("onsubmit=\"return confirm('Erase all stored config?')\">");
+    let _ = html.push_str("<button type='submit' class='btn-http'>🧹 Factory reset config</button></form>");
+    let _ = html.push_str("<form method='post' action='/api/recovery/reboot'>");
+    let _ = html.push_str("<button type='submit' class='btn-at'>🔄 Reboot</button></form>");
+    let _ = html.push_str("<p><a href='/at?cmd=AT'>Manual AT commands</a> and <a href='/log'>the modem log</a> still work.</p>");
+    let _ = html.push_str("</div></body></html>");
+    let _ = socket.write_all(html.as_bytes()).await;
+    let _ = socket.flush().await;
 }
 
 #[embassy_executor::main]
@@ -849,11 +9600,174 @@ async fn main(spawner: Spawner) {
     
     let p = embassy_rp::init(Default::default());
 
+    // 恢复引脚上电时接地: 恢复模式 (不读闪存配置, 不初始化调制解调器, 开放热点)
+    let recovery = {
+        let pin = Input::new(recovery_pin!(p), Pull::Up);
+        Timer::after(Duration::from_millis(1)).await;
+        pin.is_low()
+    };
+    RECOVERY.store(recovery, Ordering::Relaxed);
+    if recovery {
+        warn!("GP{} held low: starting in RECOVERY mode", board::ACTIVE.recovery);
+    }
+
+    // 闪存里保存的配置和宏, 没有或损坏时使用默认值
+    let stage = boot_begin(if recovery { "flash config (skipped)" } else { "flash config" });
+    let mut store = flash_store::Store::new(embassy_rp::flash::Flash::new_blocking(p.FLASH));
+    if recovery {
+        MACROS.lock(|m| *m.borrow_mut() = macros::Library::defaults());
+    } else {
+        load_config(&mut store);
+        load_macros(&mut store);
+    }
+    restore_log_checkpoints(&mut store);
+    let watchdog = embassy_rp::watchdog::Watchdog::new(p.WATCHDOG);
+    load_stats(&mut store, watchdog.reset_reason() == Some(embassy_rp::watchdog::ResetReason::TimedOut));
+    FLASH_STORE.lock(|s| *s.borrow_mut() = Some(store));
+    boot_end(stage, boot::Outcome::Done);
+
+    // 按编号取用的 GPIO (board::ACTIVE), 由调制解调器 PWRKEY, 输入触发和唤醒按钮各取所需
+    let mut gpios: [Option<Peri<'static, AnyPin>>; 29] = gpio_pins!(p);
+
+    // 串口和调制解调器任务不依赖 WiFi, 先启动
+    let stage = boot_begin("uart");
+    let uart_settings = CONFIG.lock(|c| c.borrow().uart);
+    UART_ACTIVE.lock(|a| a.set(uart_settings));
+
+    #[cfg(not(feature = "sim-modem"))]
+    let (uart_tx, uart_rx) = {
+        static UART_TX_BUF: StaticCell<[u8; 2048]> = StaticCell::new();
+        static UART_RX_BUF: StaticCell<[u8; 2048]> = StaticCell::new();
+        let uart_tx_buf = UART_TX_BUF.init([0u8; 2048]);
+        let uart_rx_buf = UART_RX_BUF.init([0u8; 2048]);
+        let uart_config = make_uart_config(UART_BAUDRATE, &uart_settings);
+
+        let mut framing = heapless::String::<4>::new();
+        uart_settings.write_framing(&mut framing);
+        info!("Configuring UART at {} baud, {}...", UART_BAUDRATE, framing.as_str());
+
+        info!(
+            "Board {}: modem TX GP{}, RX GP{}",
+            board::ACTIVE.name,
+            board::ACTIVE.modem_tx,
+            board::ACTIVE.modem_rx
+        );
+        modem_uart!(p, Irqs, uart_tx_buf, uart_rx_buf, uart_config).split()
+    };
+    // UART0 和它的引脚不用
+    #[cfg(feature = "sim-modem")]
+    let (uart_tx, uart_rx) = {
+        warn!("sim-modem: the modem is simulated, UART0 is not used");
+        spawn_task(spawner, "modem_sim", true, modem_sim_task());
+        (SimTx, SimRx)
+    };
+    spawn_task(spawner, "modem_log", false, modem_log_task());
+    spawn_task(spawner, "log_checkpoint", false, log_checkpoint_task());
+    let adc = embassy_rp::adc::Adc::new(p.ADC, Irqs, embassy_rp::adc::Config::default());
+    let sensor = embassy_rp::adc::Channel::new_temp_sensor(p.ADC_TEMP_SENSOR);
+    spawn_task(spawner, "stats", false, stats_task(adc, sensor));
+    spawn_task(spawner, "uart_rate", false, uart_rate_task());
+    let modem = CONFIG.lock(|c| c.borrow().modem);
+    let pwrkey = if recovery || !modem.pwrkey {
+        None
+    } else if let Some(pin) = gpios.get_mut(modem.pwrkey_gpio as usize).and_then(Option::take) {
+        info!("Modem PWRKEY on GP{}", modem.pwrkey_gpio);
+        Some(Output::new(pin, Level::Low))
+    } else {
+        warn!("Modem PWRKEY: GP{} is not available", modem.pwrkey_gpio);
+        None
+    };
+    spawn_task(spawner, "uart", true, uart_task(uart_tx, uart_rx, pwrkey));
+    boot_end(stage, boot::Outcome::Done);
+
+    // 调试串口默认只接 RX, TX 脚保持高阻, 不干扰被监听的线路
+    if uart_settings.debug_port {
+        let stage = boot_begin("uart1");
+        static UART1_TX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+        static UART1_RX_BUF: StaticCell<[u8; 1024]> = StaticCell::new();
+        let rx_buf = UART1_RX_BUF.init([0u8; 1024]);
+        let debug_config = make_uart_config(uart_settings.debug_baud, &config::UartSettings::DEFAULT);
+        let rx = if uart_settings.debug_writes {
+            let tx_buf = UART1_TX_BUF.init([0u8; 256]);
+            let (tx_pin, rx_pin) = debug_uart_pins!(p);
+            let (tx, rx) = BufferedUart::new(p.UART1, tx_pin, rx_pin, Irqs, tx_buf, rx_buf, debug_config).split();
+            *held(lock_stats::Site::Uart1Write, UART1_TX.lock().await) = Some(tx);
+            rx
+        } else {
+            BufferedUartRx::new(p.UART1, Irqs, debug_uart_pins!(p).1, rx_buf, debug_config)
+        };
+        info!(
+            "UART1 debug port at {} baud ({})",
+            uart_settings.debug_baud,
+            if uart_settings.debug_writes { "read-write" } else { "read-only" }
+        );
+        spawn_task(spawner, "uart1", false, uart1_task(rx));
+        boot_end(stage, boot::Outcome::Done);
+    }
+
+    // 输入触发: 开启的输入按配置取引脚 (board::ACTIVE.is_free), 每个一个任务
+    if !recovery {
+        let bindings = CONFIG.lock(|c| c.borrow().triggers.clone());
+        for (index, binding) in bindings.iter().enumerate().filter(|(_, b)| b.active()) {
+            let Some(pin) = gpios.get_mut(binding.gpio as usize).and_then(Option::take) else {
+                warn!("{}: GP{} is not available", trigger::NAMES[index], binding.gpio);
+                continue;
+            };
+            info!(
+                "{}: GP{} {} edge -> {}",
+                trigger::NAMES[index],
+                binding.gpio,
+                binding.edge.as_str(),
+                binding.action.as_str()
+            );
+            let input = Input::new(pin, Pull::Up);
+            spawn_task(spawner, "trigger", false, trigger_task(index, input));
+        }
+
+        let power = CONFIG.lock(|c| c.borrow().power.clone());
+        if power.ap_idle_min > 0 && power.wake_button {
+            match gpios.get_mut(power.wake_gpio as usize).and_then(Option::take) {
+                Some(pin) => {
+                    info!("AP wake button on GP{}", power.wake_gpio);
+                    let input = Input::new(pin, Pull::Up);
+                    spawn_task(spawner, "ap_wake", false, ap_wake_task(input));
+                }
+                None => warn!("AP wake button: GP{} is not available", power.wake_gpio),
+            }
+        }
+    }
+
     let fw = include_bytes!("../cyw43-firmware/43439A0.bin");
     let clm = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
+    CYW43_FIRMWARE.lock(|f| f.set(version::cyw43_firmware_version(fw)));
+
+    // 损坏的 blob 会让 cyw43::new 挂住: 校验不过就不启动 WiFi, 调制解调器和调试串口照常工作
+    let stage = boot_begin("cyw43 blobs");
+    let blobs = [blob_check::Blob::firmware(fw), blob_check::Blob::clm(clm)];
+    for blob in &blobs {
+        if blob.ok() {
+            info!("{}: {} bytes, CRC-32 {:08x}", blob.name, blob.len, blob.crc);
+        } else {
+            error!(
+                "{}: {} bytes, CRC-32 {:08x}; built in as {} bytes, CRC-32 {:08x}",
+                blob.name,
+                blob.len,
+                blob.crc,
+                blob.expected_len,
+                blob.expected_crc
+            );
+        }
+    }
+    CYW43_BLOBS.lock(|b| b.set(Some(blobs)));
+    if blobs.iter().any(|b| !b.ok()) {
+        boot_end(stage, boot::Outcome::Failed("checksum mismatch, WiFi not started"));
+        park().await
+    }
+    boot_end(stage, boot::Outcome::Done);
 
-    let pwr = Output::new(p.PIN_23, Level::Low);
-    let cs = Output::new(p.PIN_25, Level::High);
+    let (radio_pwr, radio_cs, radio_dio, radio_clk) = radio_pins!(p);
+    let pwr = Output::new(radio_pwr, Level::Low);
+    let cs = Output::new(radio_cs, Level::High);
     let mut pio = Pio::new(p.PIO0, Irqs);
     let spi = PioSpi::new(
         &mut pio.common,
@@ -861,46 +9775,25 @@ async fn main(spawner: Spawner) {
         RM2_CLOCK_DIVIDER,
         pio.irq0,
         cs,
-        p.PIN_24,
-        p.PIN_29,
+        radio_dio,
+        radio_clk,
         p.DMA_CH0,
     );
 
+    // cyw43 卡住时只报告错误, 不再无声地挂起
+    let stage = boot_begin("cyw43 firmware");
     static STATE: StaticCell<cyw43::State> = StaticCell::new();
     let state = STATE.init(cyw43::State::new());
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
-    
-    spawner.spawn(cyw43_task(runner).expect("Failed to spawn cyw43 task"));
-
-    control.init(clm).await;
-    control.set_power_management(cyw43::PowerManagementMode::Performance).await;
-
-    static UART_TX_BUF: StaticCell<[u8; 2048]> = StaticCell::new();
-    static UART_RX_BUF: StaticCell<[u8; 2048]> = StaticCell::new();
-    let uart_tx_buf = UART_TX_BUF.init([0u8; 2048]);
-    let uart_rx_buf = UART_RX_BUF.init([0u8; 2048]);
-
-    let mut uart_config = UartConfig::default();
-    uart_config.baudrate = 921600;
-    uart_config.data_bits = embassy_rp::uart::DataBits::DataBits8;
-    uart_config.stop_bits = embassy_rp::uart::StopBits::STOP1;
-    uart_config.parity = embassy_rp::uart::Parity::ParityNone;
-
-    info!("Configuring UART at 921600 baud...");
+    let Ok((net_device, mut control, runner)) = with_timeout(BOOT_STEP_TIMEOUT, cyw43::new(state, pwr, spi, fw)).await else {
+        boot_end(stage, boot::Outcome::Failed("timeout"));
+        park().await
+    };
+    boot_end(stage, boot::Outcome::Done);
     
-    let uart = BufferedUart::new(
-        p.UART0,
-        p.PIN_12,
-        p.PIN_13,
-        Irqs,
-        uart_tx_buf,
-        uart_rx_buf,
-        uart_config,
-    );
-
-    let (uart_tx, uart_rx) = uart.split();
-    spawner.spawn(uart_task(uart_tx, uart_rx).expect("Failed to spawn uart task"));
+    spawn_task(spawner, "cyw43", true, cyw43_task(runner));
 
+    // 网络栈一建立就启动 HTTP 服务, WiFi 仍在启动时页面显示启动进度
+    let stage = boot_begin("http server");
     let config = Config::ipv4_static(embassy_net::StaticConfigV4 {
         address: embassy_net::Ipv4Cidr::new(embassy_net::Ipv4Address::new(192, 168, 4, 1), 24),
         gateway: Some(embassy_net::Ipv4Address::new(192, 168, 4, 1)),
@@ -910,42 +9803,129 @@ async fn main(spawner: Spawner) {
     let seed = 0x0123_4567_89ab_cdef;
 
     static STACK: StaticCell<Stack<'static>> = StaticCell::new();
-    static RESOURCES: StaticCell<StackResources<8>> = StaticCell::new();
+    static RESOURCES: StaticCell<StackResources<{ socket_budget::STACK_SOCKETS }>> = StaticCell::new();
     let (stack, runner) = embassy_net::new(
-        net_device,
+        arp::Tap::new(net_device, ARP_HOOKS),
         config,
-        RESOURCES.init(StackResources::<8>::new()),
+        RESOURCES.init(StackResources::new()),
         seed,
     );
     let stack = STACK.init(stack);
 
-    spawner.spawn(net_task(runner).expect("Failed to spawn net task"));
+    spawn_task(spawner, "net", true, net_task(runner));
+
+    // 各子系统先申请套接字, 超出预算的不启动
+    let http = CONFIG.lock(|c| c.borrow().http);
+    let listeners = listener::plan(http.port as u16, http.port2 as u16, http.role2);
+    let count = listeners.iter().flatten().count();
+    if claim_sockets(socket_budget::Subsystem::Http, count * HTTP_WORKERS) {
+        for listener in listeners.into_iter().flatten() {
+            for worker in 0..HTTP_WORKERS {
+                spawn_worker(spawner, "http_server", || http_server_task(stack, listener, worker)).await;
+            }
+            match listener.serves {
+                listener::Serves::Pages => info!("HTTP server started on port {}", listener.port),
+                listener::Serves::RedirectTo(to) => info!("HTTP redirects on port {} to port {}", listener.port, to),
+            }
+        }
+        HTTP_LISTENERS.lock(|l| l.set(listeners));
+    }
+    if claim_sockets(socket_budget::Subsystem::TestServices, test_services::Service::ALL.len()) {
+        for service in test_services::Service::ALL {
+            spawn_worker(spawner, "test_service", || test_service_task(stack, service)).await;
+        }
+    }
+    #[cfg(feature = "proxy")]
+    if !recovery_mode() && claim_sockets(socket_budget::Subsystem::Forwards, 2 * forward::MAX_FORWARDS) {
+        for index in 0..forward::MAX_FORWARDS {
+            spawn_worker(spawner, "forward", || forward_task(stack, index)).await;
+        }
+    }
+    boot_end(stage, boot::Outcome::Done);
 
-    info!("Starting WiFi AP: {}", WIFI_SSID);
-    control.start_ap_wpa2(WIFI_SSID, WIFI_PASSWORD, 5).await;
-    info!("AP started!");
+    let stage = boot_begin("cyw43 init");
+    let init = async {
+        control.init(clm).await;
+        control.set_power_management(cyw43::PowerManagementMode::Performance).await;
+        let mac = control.address().await;
+        WIFI_MAC.lock(|m| m.set(mac));
+    };
+    if with_timeout(BOOT_STEP_TIMEOUT, init).await.is_err() {
+        boot_end(stage, boot::Outcome::Failed("timeout"));
+        park().await
+    }
+    boot_end(stage, boot::Outcome::Done);
 
-    Timer::after(Duration::from_secs(2)).await;
+    let stage = boot_begin("wifi ap");
+    info!("Starting WiFi AP: {}", wifi_ssid());
+    let start_ap = async {
+        if recovery {
+            control.start_ap_open(RECOVERY_SSID, 5).await;
+        } else {
+            control.start_ap_wpa2(WIFI_SSID, WIFI_PASSWORD, 5).await;
+        }
+    };
+    if with_timeout(BOOT_STEP_TIMEOUT, start_ap).await.is_err() {
+        boot_end(stage, boot::Outcome::Failed("timeout"));
+        park().await
+    }
+    boot_end(stage, boot::Outcome::Done);
 
-    spawner.spawn(http_server_task(stack).expect("Failed to spawn HTTP server"));
-    info!("HTTP server started on port 80");
+    let stage = boot_begin("network");
+    if with_timeout(BOOT_STEP_TIMEOUT, stack.wait_config_up()).await.is_err() {
+        boot_end(stage, boot::Outcome::Failed("no IP config"));
+    } else {
+        boot_end(stage, boot::Outcome::Done);
+    }
 
     info!("=========================================");
     info!("✅ EC800K HTTP Tester Ready!");
-    info!("Connect to WiFi: {}", WIFI_SSID);
-    info!("Password: {}", WIFI_PASSWORD);
-    info!("Visit: http://192.168.4.1");
+    info!("Connect to WiFi: {}", wifi_ssid());
+    if !recovery {
+        info!("Password: {}", WIFI_PASSWORD);
+    }
+    let pages = HTTP_LISTENERS.lock(|l| l.get().into_iter().flatten().find(|l| l.serves == listener::Serves::Pages));
+    info!("Visit: {}", http_url(pages.map_or(80, |l| l.port)));
     info!("Click the green button to fetch httpbin.org/get");
     info!("=========================================");
 
     // 简化的主循环 - 避免阻塞
+    // 板载 LED: 识别闪烁优先, 然后是 /api/led 的覆盖, 否则正常运行常亮, 恢复模式三连闪,
+    // AP 休眠时每 2 秒闪一下
     let mut counter = 0u32;
+    let mut next_tick = Instant::now() + Duration::from_secs(5);
     loop {
-        Timer::after(Duration::from_secs(5)).await;
-        
+        if !recovery {
+            apply_ap_sleep(&mut control).await;
+        }
+        let source = LED.lock(|l| l.borrow().source(Instant::now().as_millis()));
+        match source {
+            led::Source::Identify => blink_identify(&mut control).await,
+            led::Source::RunState if recovery => blink_recovery(&mut control).await,
+            led::Source::RunState if ap_sleeping() => {
+                blink_ap_sleeping(&mut control).await;
+                let next_blink = (Instant::now() + Duration::from_secs(2)).min(next_tick);
+                embassy_futures::select::select(LED_CHANGED.wait(), Timer::at(next_blink)).await;
+            }
+            led::Source::RunState | led::Source::Fixed(_) => {
+                control.gpio_set(0, source != led::Source::Fixed(false)).await;
+                embassy_futures::select::select(LED_CHANGED.wait(), Timer::at(next_tick)).await;
+            }
+        }
+        if Instant::now() < next_tick {
+            continue;
+        }
+        next_tick += Duration::from_secs(5);
+
         counter += 1;
         if counter % 6 == 0 {
-            info!("System alive...");
+            debug!("System alive...");
+        }
+        expire_neighbors();
+
+        // 运行时间按分钟粒度计入状态代数 (弱 ETag 允许秒级差异)
+        if counter % 12 == 0 {
+            bump_state_generation();
         }
     }
 }
@@ -0,0 +1,537 @@
+// 蜂窝模块 AT 指令方言
+//
+// Quectel (EC800K) and SIMCom modules wired the same way differ mostly in
+// the TCP/DNS command families. Callers build commands and recognise
+// replies through `CellularModem` and never spell out module-specific
+// strings. The backend is picked at runtime from the ATI reply.
+
+use core::fmt::Write as _;
+
+use crate::at::{self, Kind};
+
+pub type Command = heapless::String<96>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DnsReply<'a> {
+    Address(&'a str),
+    // header line of a successful lookup, `count` addresses follow
+    Pending { count: usize, ttl_s: Option<u32> },
+    Failed,
+}
+
+// Unsolicited notice that data arrived on a socket
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RecvNotice {
+    // buffered in the module until `tcp_recv`
+    Readable,
+    // direct push: this many raw bytes follow the line
+    Pushed(usize),
+}
+
+pub trait CellularModem {
+    fn name(&self) -> &'static str;
+
+    // One-time setup after power on, each command answers OK
+    fn init(&self) -> heapless::Vec<Command, 4>;
+    fn register(&self) -> Command {
+        command(format_args!("AT+CREG?"))
+    }
+    // LTE registration; modules without it answer ERROR
+    fn register_eps(&self) -> Command {
+        command(format_args!("AT+CEREG?"))
+    }
+    fn activate_pdp(&self, apn: &str) -> heapless::Vec<Command, 4>;
+    fn deactivate_pdp(&self) -> Command;
+    // Query the data context; no state line before OK means it is down
+    fn pdp_state(&self) -> Command;
+    fn resolve_dns(&self, host: &str) -> Command;
+    fn tcp_connect(&self, id: u8, ip: &str, port: u16) -> Command;
+    // Received data pushed to the UART as it arrives instead of buffered
+    // for `tcp_recv`; same as `tcp_connect` where there is no such mode
+    fn tcp_connect_direct(&self, id: u8, ip: &str, port: u16) -> Command {
+        self.tcp_connect(id, ip, port)
+    }
+    fn supports_direct_push(&self) -> bool {
+        false
+    }
+    fn tcp_send(&self, id: u8, len: usize) -> Command;
+    fn tcp_recv(&self, id: u8, max: usize) -> Command;
+    fn tcp_close(&self, id: u8) -> Command;
+    fn signal_quality(&self) -> Command {
+        command(format_args!("AT+CSQ"))
+    }
+    // Full functionality with a reset: the module reboots after OK
+    fn restart(&self) -> Command {
+        command(format_args!("AT+CFUN=1,1"))
+    }
+    // A single echo request
+    fn ping(&self, host: &str, timeout_s: u8) -> Command;
+    // Remaining SIM PIN1/PUK1 attempts
+    fn pin_counter(&self) -> Command;
+    // Orderly power off; the module reports it with a line of its own
+    // after OK (`parse_powered_down`)
+    fn power_down(&self) -> Command;
+    fn parse_powered_down(&self, line: &str) -> bool;
+    // Text-mode SMS: the send command answers a '>' prompt, then takes the
+    // text ended by Ctrl-Z
+    fn sms_text_mode(&self) -> Command {
+        command(format_args!("AT+CMGF=1"))
+    }
+    fn sms_send(&self, number: &str) -> Command {
+        let mut out = command(format_args!("AT+CMGS=\"{}\"", quoted(number, Kind::Phone)));
+        // CR only: a LF after it would start the message text
+        out.pop();
+        out
+    }
+    // GNSS engine on/off and a position query (gnss::parse_location reads
+    // the reply); None when the module has no GNSS commands here
+    #[cfg(feature = "gnss")]
+    fn gnss_power(&self, _on: bool) -> Option<Command> {
+        None
+    }
+    #[cfg(feature = "gnss")]
+    fn gnss_location(&self) -> Option<Command> {
+        None
+    }
+
+    fn parse_dns<'l>(&self, line: &'l str) -> Option<DnsReply<'l>>;
+    // Some(true) context 1 active, Some(false) inactive, None not a state line
+    fn parse_pdp_state(&self, line: &str) -> Option<bool>;
+    // Some(0) connected, Some(code) failed
+    fn parse_connect(&self, line: &str, id: u8) -> Option<u16>;
+    // Some(true) SEND OK, Some(false) SEND FAIL
+    fn parse_send(&self, line: &str, id: u8) -> Option<bool>;
+    // Payload length announced before the raw bytes of a read
+    fn parse_recv(&self, line: &str) -> Option<usize>;
+    // Unsolicited "data arrived" notice and the connect ID it is for
+    fn parse_recv_notice(&self, line: &str) -> Option<(u8, RecvNotice)>;
+    // Unsolicited "peer closed" notice
+    fn parse_closed(&self, line: &str, id: u8) -> bool;
+    // Some(Some(ms)) echo reply, Some(None) lost, None not a ping result
+    fn parse_ping(&self, line: &str) -> Option<Option<u32>>;
+    // (PIN attempts left, PUK attempts left)
+    fn parse_pin_counter(&self, line: &str) -> Option<(u8, u8)>;
+}
+
+// `format_args!` into a Command with the trailing CR LF
+fn command(args: core::fmt::Arguments<'_>) -> Command {
+    let mut out = Command::new();
+    let _ = out.write_fmt(args);
+    let _ = out.push_str("\r\n");
+    out
+}
+
+// A user-supplied argument; empty when it fails the checks in `at`
+fn quoted(value: &str, kind: Kind) -> &str {
+    at::arg(value, kind).unwrap_or("")
+}
+
+fn commands<const N: usize>(list: &[core::fmt::Arguments<'_>]) -> heapless::Vec<Command, N> {
+    list.iter().map(|args| command(*args)).collect()
+}
+
+// "<id>, <text>" replies of the SIMCom multi-connection mode
+fn strip_id(line: &str, id: u8) -> Option<&str> {
+    let (prefix, rest) = line.split_once(',')?;
+    (prefix.trim().parse::<u8>() == Ok(id)).then(|| rest.trim())
+}
+
+pub struct Quectel;
+
+impl CellularModem for Quectel {
+    fn name(&self) -> &'static str {
+        "Quectel"
+    }
+
+    fn init(&self) -> heapless::Vec<Command, 4> {
+        heapless::Vec::new()
+    }
+
+    fn activate_pdp(&self, apn: &str) -> heapless::Vec<Command, 4> {
+        commands(&[
+            format_args!("AT+CGATT=1"),
+            format_args!("AT+QICSGP=1,1,\"{}\"", quoted(apn, Kind::Apn)),
+            format_args!("AT+QIACT=1"),
+        ])
+    }
+
+    fn deactivate_pdp(&self) -> Command {
+        command(format_args!("AT+QIDEACT=1"))
+    }
+
+    fn pdp_state(&self) -> Command {
+        command(format_args!("AT+QIACT?"))
+    }
+
+    fn resolve_dns(&self, host: &str) -> Command {
+        command(format_args!("AT+QIDNSGIP=1,\"{}\"", quoted(host, Kind::Host)))
+    }
+
+    // access mode 0: received data waits in the module for AT+QIRD
+    fn tcp_connect(&self, id: u8, ip: &str, port: u16) -> Command {
+        command(format_args!("AT+QIOPEN=1,{},\"TCP\",\"{}\",{},0,0", id, quoted(ip, Kind::Address), port))
+    }
+
+    // access mode 1
+    fn tcp_connect_direct(&self, id: u8, ip: &str, port: u16) -> Command {
+        command(format_args!("AT+QIOPEN=1,{},\"TCP\",\"{}\",{},0,1", id, quoted(ip, Kind::Address), port))
+    }
+
+    fn supports_direct_push(&self) -> bool {
+        true
+    }
+
+    fn tcp_send(&self, id: u8, len: usize) -> Command {
+        command(format_args!("AT+QISEND={},{}", id, len))
+    }
+
+    fn tcp_recv(&self, id: u8, max: usize) -> Command {
+        command(format_args!("AT+QIRD={},{}", id, max))
+    }
+
+    fn tcp_close(&self, id: u8) -> Command {
+        command(format_args!("AT+QICLOSE={}", id))
+    }
+
+    fn ping(&self, host: &str, timeout_s: u8) -> Command {
+        command(format_args!("AT+QPING=1,\"{}\",{},1", quoted(host, Kind::Host), timeout_s))
+    }
+
+    fn pin_counter(&self) -> Command {
+        command(format_args!("AT+QPINC=\"SC\""))
+    }
+
+    fn power_down(&self) -> Command {
+        command(format_args!("AT+QPOWD=1"))
+    }
+
+    fn parse_powered_down(&self, line: &str) -> bool {
+        line == "POWERED DOWN"
+    }
+
+    #[cfg(feature = "gnss")]
+    fn gnss_power(&self, on: bool) -> Option<Command> {
+        Some(if on {
+            command(format_args!("AT+QGPS=1"))
+        } else {
+            command(format_args!("AT+QGPSEND"))
+        })
+    }
+
+    // decimal degrees
+    #[cfg(feature = "gnss")]
+    fn gnss_location(&self) -> Option<Command> {
+        Some(command(format_args!("AT+QGPSLOC=2")))
+    }
+
+    // +QIURC: "dnsgip",<err>,<count>,<ttl> then +QIURC: "dnsgip","<ip>" per address
+    fn parse_dns<'l>(&self, line: &'l str) -> Option<DnsReply<'l>> {
+        let rest = line.strip_prefix("+QIURC: \"dnsgip\",")?;
+        if let Some(ip) = rest.strip_prefix('"') {
+            return Some(DnsReply::Address(ip.trim_end_matches('"')));
+        }
+        let mut fields = rest.split(',').map(str::trim);
+        if fields.next() != Some("0") {
+            return Some(DnsReply::Failed);
+        }
+        Some(DnsReply::Pending {
+            count: fields.next().and_then(|c| c.parse().ok()).unwrap_or(1),
+            ttl_s: fields.next().and_then(|t| t.parse().ok()),
+        })
+    }
+
+    // +QIACT: <ctx>,<state>,<type>,"<ip>", listed only for active contexts
+    fn parse_pdp_state(&self, line: &str) -> Option<bool> {
+        let mut fields = line.strip_prefix("+QIACT:")?.split(',').map(str::trim);
+        (fields.next()? == "1").then(|| fields.next() == Some("1"))
+    }
+
+    // +QIOPEN: <id>,<err>
+    fn parse_connect(&self, line: &str, id: u8) -> Option<u16> {
+        let rest = line.strip_prefix("+QIOPEN:")?;
+        let code = strip_id(rest, id)?;
+        Some(code.parse().unwrap_or(u16::MAX))
+    }
+
+    fn parse_send(&self, line: &str, _id: u8) -> Option<bool> {
+        match line {
+            "SEND OK" => Some(true),
+            "SEND FAIL" => Some(false),
+            _ => None,
+        }
+    }
+
+    // +QIRD: <len>
+    fn parse_recv(&self, line: &str) -> Option<usize> {
+        line.strip_prefix("+QIRD:")?.trim().parse().ok()
+    }
+
+    // +QIURC: "recv",<id> in buffer access mode, +QIURC: "recv",<id>,<len>
+    // and the payload in direct push mode
+    fn parse_recv_notice(&self, line: &str) -> Option<(u8, RecvNotice)> {
+        let mut fields = line.strip_prefix("+QIURC: \"recv\",")?.split(',').map(str::trim);
+        let id = fields.next()?.parse().ok()?;
+        match fields.next() {
+            None => Some((id, RecvNotice::Readable)),
+            Some(len) => Some((id, RecvNotice::Pushed(len.parse().ok()?))),
+        }
+    }
+
+    // +QIURC: "closed",<id>
+    fn parse_closed(&self, line: &str, id: u8) -> bool {
+        line.strip_prefix("+QIURC: \"closed\",")
+            .is_some_and(|rest| rest.trim().parse::<u8>() == Ok(id))
+    }
+
+    // +QPING: <result>,"<ip>",<bytes>,<time>,<ttl> per echo, +QPING: <err> when
+    // nothing was sent; the closing statistics line has no quoted address
+    fn parse_ping(&self, line: &str) -> Option<Option<u32>> {
+        let rest = line.strip_prefix("+QPING:")?.trim();
+        let mut fields = rest.split(',');
+        let result = fields.next()?;
+        match fields.next() {
+            None => Some(None),
+            Some(ip) if ip.starts_with('"') && result == "0" => Some(fields.nth(1).and_then(|t| t.trim().parse().ok())),
+            Some(ip) if ip.starts_with('"') => Some(None),
+            Some(_) => None,
+        }
+    }
+
+    // +QPINC: "SC",<pin1 left>,<puk1 left>
+    fn parse_pin_counter(&self, line: &str) -> Option<(u8, u8)> {
+        let rest = line.strip_prefix("+QPINC: \"SC\",")?;
+        let (pin, puk) = rest.split_once(',')?;
+        Some((pin.trim().parse().ok()?, puk.trim().parse().ok()?))
+    }
+}
+
+// SIMCom CIPSTART/CIPSEND family, multi-connection mode with manual
+// receive (CIPRXGET) so reads look like Quectel's QIRD
+pub struct Simcom;
+
+impl CellularModem for Simcom {
+    fn name(&self) -> &'static str {
+        "SIMCom"
+    }
+
+    fn init(&self) -> heapless::Vec<Command, 4> {
+        commands(&[format_args!("AT+CIPMUX=1"), format_args!("AT+CIPRXGET=1")])
+    }
+
+    fn activate_pdp(&self, apn: &str) -> heapless::Vec<Command, 4> {
+        commands(&[
+            format_args!("AT+CGATT=1"),
+            format_args!("AT+CSTT=\"{}\"", quoted(apn, Kind::Apn)),
+            format_args!("AT+CIICR"),
+            format_args!("AT+CIFSR"),
+        ])
+    }
+
+    fn deactivate_pdp(&self) -> Command {
+        command(format_args!("AT+CIPSHUT"))
+    }
+
+    fn pdp_state(&self) -> Command {
+        command(format_args!("AT+CGACT?"))
+    }
+
+    fn resolve_dns(&self, host: &str) -> Command {
+        command(format_args!("AT+CDNSGIP=\"{}\"", quoted(host, Kind::Host)))
+    }
+
+    fn tcp_connect(&self, id: u8, ip: &str, port: u16) -> Command {
+        command(format_args!("AT+CIPSTART={},\"TCP\",\"{}\",{}", id, quoted(ip, Kind::Address), port))
+    }
+
+    fn tcp_send(&self, id: u8, len: usize) -> Command {
+        command(format_args!("AT+CIPSEND={},{}", id, len))
+    }
+
+    fn tcp_recv(&self, id: u8, max: usize) -> Command {
+        command(format_args!("AT+CIPRXGET=2,{},{}", id, max))
+    }
+
+    fn tcp_close(&self, id: u8) -> Command {
+        command(format_args!("AT+CIPCLOSE={}", id))
+    }
+
+    // retry count, data length, timeout in 100 ms units
+    fn ping(&self, host: &str, timeout_s: u8) -> Command {
+        command(format_args!("AT+CIPPING=\"{}\",1,32,{}", quoted(host, Kind::Host), timeout_s as u32 * 10))
+    }
+
+    fn pin_counter(&self) -> Command {
+        command(format_args!("AT+SPIC"))
+    }
+
+    fn power_down(&self) -> Command {
+        command(format_args!("AT+CPOWD=1"))
+    }
+
+    fn parse_powered_down(&self, line: &str) -> bool {
+        line == "NORMAL POWER DOWN"
+    }
+
+    // +CDNSGIP: 1,"<host>","<ip>" or +CDNSGIP: 0,<err>
+    fn parse_dns<'l>(&self, line: &'l str) -> Option<DnsReply<'l>> {
+        let rest = line.strip_prefix("+CDNSGIP:")?.trim();
+        let mut fields = rest.split(',');
+        if fields.next() != Some("1") {
+            return Some(DnsReply::Failed);
+        }
+        match fields.nth(1) {
+            Some(ip) => Some(DnsReply::Address(ip.trim_matches('"'))),
+            None => Some(DnsReply::Failed),
+        }
+    }
+
+    // +CGACT: <cid>,<state>
+    fn parse_pdp_state(&self, line: &str) -> Option<bool> {
+        let mut fields = line.strip_prefix("+CGACT:")?.split(',').map(str::trim);
+        (fields.next()? == "1").then(|| fields.next() == Some("1"))
+    }
+
+    // <id>, CONNECT OK / <id>, CONNECT FAIL / <id>, ALREADY CONNECT
+    fn parse_connect(&self, line: &str, id: u8) -> Option<u16> {
+        match strip_id(line, id)? {
+            "CONNECT OK" | "ALREADY CONNECT" => Some(0),
+            "CONNECT FAIL" => Some(1),
+            _ => None,
+        }
+    }
+
+    fn parse_send(&self, line: &str, id: u8) -> Option<bool> {
+        match strip_id(line, id)? {
+            "SEND OK" => Some(true),
+            "SEND FAIL" => Some(false),
+            _ => None,
+        }
+    }
+
+    // +CIPRXGET: 2,<id>,<len>,<remaining>
+    fn parse_recv(&self, line: &str) -> Option<usize> {
+        let rest = line.strip_prefix("+CIPRXGET: 2,")?;
+        rest.split(',').nth(1)?.trim().parse().ok()
+    }
+
+    // +CIPRXGET: 1,<id>
+    fn parse_recv_notice(&self, line: &str) -> Option<(u8, RecvNotice)> {
+        let id = line.strip_prefix("+CIPRXGET: 1,")?.trim().parse().ok()?;
+        Some((id, RecvNotice::Readable))
+    }
+
+    fn parse_closed(&self, line: &str, id: u8) -> bool {
+        strip_id(line, id) == Some("CLOSED")
+    }
+
+    // +CIPPING: <n>,"<ip>",<time in 100 ms>,<ttl>; a timeout reports ttl 255
+    fn parse_ping(&self, line: &str) -> Option<Option<u32>> {
+        let rest = line.strip_prefix("+CIPPING:")?.trim();
+        let mut fields = rest.split(',').skip(2).map(str::trim);
+        let time = fields.next()?.parse::<u32>().ok();
+        let ttl = fields.next();
+        Some(time.filter(|_| ttl != Some("255")).map(|t| t * 100))
+    }
+
+    // +SPIC: <pin1>,<puk1>,<pin2>,<puk2>
+    fn parse_pin_counter(&self, line: &str) -> Option<(u8, u8)> {
+        let mut counts = line.strip_prefix("+SPIC:")?.split(',').map(|c| c.trim().parse().ok());
+        Some((counts.next()??, counts.next()??))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Quectel,
+    Simcom,
+}
+
+impl Backend {
+    // Quectel is the default: the board ships with an EC800K
+    pub fn detect(ati_reply: &str) -> Self {
+        let reply = ati_reply.as_bytes();
+        let has = |needle: &[u8]| reply.windows(needle.len()).any(|w| w.eq_ignore_ascii_case(needle));
+        if has(b"SIMCOM") {
+            Backend::Simcom
+        } else {
+            Backend::Quectel
+        }
+    }
+
+    pub fn modem(self) -> &'static dyn CellularModem {
+        match self {
+            Backend::Quectel => &Quectel,
+            Backend::Simcom => &Simcom,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quectel_tcp_and_dns_commands() {
+        let m = Quectel;
+        assert_eq!(m.resolve_dns("httpbin.org"), "AT+QIDNSGIP=1,\"httpbin.org\"\r\n");
+        assert_eq!(m.tcp_connect(0, "3.223.36.72", 80), "AT+QIOPEN=1,0,\"TCP\",\"3.223.36.72\",80,0,0\r\n");
+        assert_eq!(m.tcp_connect_direct(2, "2001:db8::1", 8080), "AT+QIOPEN=1,2,\"TCP\",\"2001:db8::1\",8080,0,1\r\n");
+        assert_eq!(m.tcp_send(0, 71), "AT+QISEND=0,71\r\n");
+        assert_eq!(m.tcp_recv(0, 500), "AT+QIRD=0,500\r\n");
+        assert_eq!(m.tcp_close(0), "AT+QICLOSE=0\r\n");
+    }
+
+    #[test]
+    fn simcom_tcp_and_dns_commands() {
+        let m = Simcom;
+        assert_eq!(m.resolve_dns("httpbin.org"), "AT+CDNSGIP=\"httpbin.org\"\r\n");
+        assert_eq!(m.tcp_connect(0, "3.223.36.72", 80), "AT+CIPSTART=0,\"TCP\",\"3.223.36.72\",80\r\n");
+        // no direct push mode: the same command
+        assert!(!m.supports_direct_push());
+        assert_eq!(m.tcp_connect_direct(0, "3.223.36.72", 80), m.tcp_connect(0, "3.223.36.72", 80));
+        assert_eq!(m.tcp_send(1, 71), "AT+CIPSEND=1,71\r\n");
+        assert_eq!(m.tcp_recv(0, 500), "AT+CIPRXGET=2,0,500\r\n");
+        assert_eq!(m.tcp_close(0), "AT+CIPCLOSE=0\r\n");
+    }
+
+    #[test]
+    fn bad_arguments_go_out_empty() {
+        for m in [Backend::Quectel.modem(), Backend::Simcom.modem()] {
+            let dns = m.resolve_dns("evil\"\r\nAT+CFUN=0");
+            assert!(dns.ends_with("\"\"\r\n") && dns.matches("\r\n").count() == 1, "{dns}");
+            let open = m.tcp_connect(0, "1.2.3.4\",1\r\nAT", 80);
+            assert!(open.contains(",\"\",80") && open.matches("\r\n").count() == 1, "{open}");
+        }
+    }
+
+    #[test]
+    fn backend_from_ati() {
+        assert!(Backend::detect("Quectel\r\nEC800K\r\nRevision: x") == Backend::Quectel);
+        assert!(Backend::detect("SIMCOM_SIM7600") == Backend::Simcom);
+        assert!(Backend::detect("simcom sim800") == Backend::Simcom);
+        assert!(Backend::detect("") == Backend::Quectel);
+    }
+
+    // Every command built from a user-supplied field is one line, and the
+    // field stays inside its quotes
+    #[test]
+    fn user_fields_cannot_break_out() {
+        const BAD: [&str; 4] = ["x\r\nAT+CFUN=0", "x\nAT", "x\",\"y", "x<script>"];
+        for m in [Backend::Quectel.modem(), Backend::Simcom.modem()] {
+            for bad in BAD {
+                let mut built: Vec<Command> = vec![m.resolve_dns(bad), m.ping(bad, 4), m.sms_send(bad)];
+                built.extend(m.activate_pdp(bad));
+                for command in &built {
+                    let line = command.trim_end_matches(['\r', '\n']);
+                    assert!(!line.contains(['\r', '\n']), "{} {command:?}", m.name());
+                    assert_eq!(line.matches('"').count() % 2, 0, "{} {command:?}", m.name());
+                    assert!(!line.contains('<') || line.contains("\"x<script>\""), "{} {command:?}", m.name());
+                }
+            }
+        }
+        assert_eq!(Quectel.sms_send("+8613800138000"), "AT+CMGS=\"+8613800138000\"\r");
+        assert_eq!(Quectel.sms_send("1\r\n2"), "AT+CMGS=\"\"\r");
+        assert_eq!(Simcom.ping("a\"b", 4), "AT+CIPPING=\"\",1,32,40\r\n");
+        assert_eq!(Quectel.activate_pdp("cmnet\"")[1], "AT+QICSGP=1,1,\"\"\r\n");
+    }
+}
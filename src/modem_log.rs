@@ -0,0 +1,574 @@
+// 调制解调器串口收发日志 (环形缓冲区)
+//
+// Offsets are absolute: the byte count written since boot. The ring keeps
+// the newest N bytes, so everything below `start_offset()` is gone.
+//
+// ModemLog also numbers the lines as they are written, from 0 at boot, and
+// remembers where each starts, when, and which way it went. /api/log hands
+// those out as records: a record keeps its number however far the ring
+// has wrapped, and a client asking from a number it has not seen gets
+// only complete lines, never one cut at the ring's start. /events streams
+// the same lines as Server-Sent Events, numbered the same way.
+
+use core::fmt::Write as _;
+
+use crate::rings::{Ring, Usage};
+use crate::utf8;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Tx,
+    Rx,
+}
+
+pub struct LogRing<const N: usize> {
+    buf: [u8; N],
+    total: u32,
+    // end offset at the last reset; nothing below it is readable
+    floor: u32,
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            total: 0,
+            floor: 0,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        for &b in data {
+            self.buf[self.total as usize % N] = b;
+            self.total = self.total.wrapping_add(1);
+        }
+    }
+
+    pub fn start_offset(&self) -> u32 {
+        self.total.saturating_sub(N as u32).max(self.floor)
+    }
+
+    // Offsets keep counting, so a reader's next offset stays valid
+    pub fn clear(&mut self) {
+        self.floor = self.total;
+    }
+
+    pub fn end_offset(&self) -> u32 {
+        self.total
+    }
+
+    // Copy bytes starting at an absolute offset, returns how many were copied
+    // (0 if the offset was already overwritten or is past the end)
+    pub fn read_at(&self, offset: u32, out: &mut [u8]) -> usize {
+        if offset < self.start_offset() || offset >= self.total {
+            return 0;
+        }
+        let n = out.len().min((self.total - offset) as usize);
+        for (i, slot) in out[..n].iter_mut().enumerate() {
+            *slot = self.buf[(offset as usize + i) % N];
+        }
+        n
+    }
+}
+
+// Lines the index remembers; more short lines than this and the oldest
+// drop out of /api/log records before their bytes leave the ring
+pub const LINE_INDEX: usize = 256;
+
+// Bytes of a line /api/log records and /events frames give at most
+pub const TEXT_MAX: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Tx,
+    Rx,
+    // "[n bytes dropped]"
+    Note,
+}
+
+impl LineKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineKind::Tx => "tx",
+            LineKind::Rx => "rx",
+            LineKind::Note => "note",
+        }
+    }
+}
+
+impl From<Direction> for LineKind {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::Tx => LineKind::Tx,
+            Direction::Rx => LineKind::Rx,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LineStart {
+    offset: u32,
+    at_ms: u64,
+    kind: LineKind,
+}
+
+// A complete line of the log: the bytes at `offset`, without the newline
+#[derive(Clone, Copy)]
+pub struct Line {
+    // lines since boot
+    pub seq: u32,
+    pub kind: LineKind,
+    pub at_ms: u64,
+    pub offset: u32,
+    pub len: usize,
+}
+
+// Where each line starts, so records keep their number and bounds however
+// the ring wraps
+struct LineIndex {
+    starts: heapless::Deque<LineStart, LINE_INDEX>,
+    // seq of starts.front()
+    first_seq: u32,
+}
+
+impl LineIndex {
+    const fn new() -> Self {
+        Self {
+            starts: heapless::Deque::new(),
+            first_seq: 0,
+        }
+    }
+
+    // Push into the ring, noting a line start after each newline
+    fn push<const N: usize>(&mut self, ring: &mut LogRing<N>, data: &[u8], kind: LineKind, now_ms: u64) {
+        for piece in data.split_inclusive(|&b| b == b'\n') {
+            // 行的时间和方向取它的第一个字节, 不是上一行结束的时候
+            if let Some(start) = self.starts.back_mut()
+                && start.offset == ring.end_offset()
+            {
+                start.at_ms = now_ms;
+                start.kind = kind;
+            }
+            ring.push(piece);
+            if piece.ends_with(b"\n") {
+                if self.starts.is_full() {
+                    self.starts.pop_front();
+                    self.first_seq = self.first_seq.wrapping_add(1);
+                }
+                let _ = self.starts.push_back(LineStart {
+                    offset: ring.end_offset(),
+                    at_ms: now_ms,
+                    kind,
+                });
+            }
+        }
+    }
+
+    // Only the start of the line being written stays
+    fn clear(&mut self) {
+        while self.starts.len() > 1 {
+            self.starts.pop_front();
+            self.first_seq = self.first_seq.wrapping_add(1);
+        }
+    }
+}
+
+// Ring plus ">> " / "<< " markers whenever the traffic direction changes.
+// A character split across two chunks is held back until its last byte
+// arrives, so a marker never lands in the middle of it.
+pub struct ModemLog<const N: usize> {
+    pub ring: LogRing<N>,
+    last: Option<Direction>,
+    // indexed by Direction
+    carry: [utf8::Carry; 2],
+    lines: LineIndex,
+    // from record_dropped
+    dropped: u32,
+}
+
+impl<const N: usize> ModemLog<N> {
+    pub const fn new() -> Self {
+        Self {
+            ring: LogRing::new(),
+            last: None,
+            carry: [utf8::Carry::new(), utf8::Carry::new()],
+            lines: LineIndex::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn record(&mut self, direction: Direction, data: &[u8], now_ms: u64) {
+        let (ring, last, lines) = (&mut self.ring, &mut self.last, &mut self.lines);
+        self.carry[direction as usize].feed(data, |piece| {
+            if *last != Some(direction) {
+                let marker: &[u8] = match direction {
+                    Direction::Tx => b"\n>> ",
+                    Direction::Rx => b"\n<< ",
+                };
+                lines.push(ring, marker, direction.into(), now_ms);
+                *last = Some(direction);
+            }
+            lines.push(ring, piece, direction.into(), now_ms);
+        });
+    }
+
+    // Note lost bytes inline; the next chunk gets a fresh direction marker
+    pub fn record_dropped(&mut self, count: u32, now_ms: u64) {
+        let mut note = heapless::String::<32>::new();
+        let _ = core::write!(note, "\n[{} bytes dropped]", count);
+        self.lines.push(&mut self.ring, note.as_bytes(), LineKind::Note, now_ms);
+        self.dropped = self.dropped.saturating_add(count);
+        self.last = None;
+        // the rest of a held character may have been dropped
+        for carry in &mut self.carry {
+            carry.clear();
+        }
+    }
+
+    // Complete lines still whole in the ring, from `seq` on (or the oldest)
+    pub fn lines_since(&self, seq: u32) -> impl Iterator<Item = Line> + '_ {
+        let starts = &self.lines.starts;
+        let first_seq = self.lines.first_seq;
+        let start_offset = self.ring.start_offset();
+        starts
+            .iter()
+            .zip(starts.iter().skip(1))
+            .enumerate()
+            .map(move |(i, (line, next))| Line {
+                seq: first_seq.wrapping_add(i as u32),
+                kind: line.kind,
+                at_ms: line.at_ms,
+                offset: line.offset,
+                len: (next.offset - line.offset - 1) as usize,
+            })
+            .filter(move |line| line.offset >= start_offset && line.seq >= seq)
+    }
+
+    // seq the next complete line will get
+    pub fn next_seq(&self) -> u32 {
+        self.lines.first_seq.wrapping_add(self.lines.starts.len().saturating_sub(1) as u32)
+    }
+
+    // A line as /api/log records and /events give it: without the ">> " /
+    // "<< " marker and the CR, cut to TEXT_MAX on a character boundary.
+    // (text, whether it was cut)
+    pub fn line_text<'a>(&self, line: &Line, text: &'a mut [u8; TEXT_MAX]) -> (&'a [u8], bool) {
+        let len = self.ring.read_at(line.offset, &mut text[..line.len.min(TEXT_MAX)]);
+        let mut bytes = &text[..len];
+        if line.kind != LineKind::Note {
+            bytes = bytes.strip_prefix(b">> ").or(bytes.strip_prefix(b"<< ")).unwrap_or(bytes);
+        }
+        let cut = line.len > TEXT_MAX;
+        if cut {
+            bytes = &bytes[..utf8::complete_len(bytes)];
+        }
+        (bytes.strip_suffix(b"\r").unwrap_or(bytes), cut)
+    }
+
+    // /events frames for the lines from `next` on, as many as fit. (the
+    // seq to go on from, whether lines were left for the next round)
+    pub fn push_event_frames<const M: usize>(&self, next: u32, out: &mut heapless::String<M>) -> (u32, bool) {
+        // Last-Event-ID 是重启之前的 (行号还没到那里): 从最早的行开始
+        let next_seq = self.next_seq();
+        let next = if next > next_seq { 0 } else { next };
+        let mut text = [0u8; TEXT_MAX];
+        let mut frame = heapless::String::<{ TEXT_MAX * 3 + 48 }>::new();
+        for line in self.lines_since(next) {
+            let (bytes, _) = self.line_text(&line, &mut text);
+            if bytes.is_empty() {
+                continue;
+            }
+            frame.clear();
+            let _ = core::write!(frame, "id: {}\nevent: {}\ndata: ", line.seq, line.kind.as_str());
+            for chunk in bytes.utf8_chunks() {
+                // CR 在 SSE 里也是换行
+                for c in chunk.valid().chars() {
+                    let _ = frame.push(if c == '\r' { ' ' } else { c });
+                }
+                if !chunk.invalid().is_empty() {
+                    let _ = frame.push('\u{fffd}');
+                }
+            }
+            let _ = frame.push_str("\n\n");
+            if out.len() + frame.len() > out.capacity() {
+                return (line.seq, true);
+            }
+            let _ = out.push_str(&frame);
+        }
+        (next_seq, false)
+    }
+}
+
+impl<const N: usize> Ring for ModemLog<N> {
+    fn usage(&self) -> Usage {
+        let written = self.ring.end_offset().wrapping_sub(self.ring.floor);
+        Usage {
+            capacity: N,
+            used: (self.ring.end_offset() - self.ring.start_offset()) as usize,
+            written,
+            dropped: self.dropped,
+            wraps: written / N as u32,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ring.clear();
+        self.lines.clear();
+        self.dropped = 0;
+        self.last = None;
+    }
+}
+
+// 串口任务和日志环形缓冲区之间的待写队列
+//
+// The UART side only pushes under a short critical section and never waits;
+// a separate task drains records into the ModemLog. Records are framed as
+// [kind, len lo, len hi, payload]. When a chunk does not fit it is dropped
+// and the count is written as a marker record ahead of the next chunk that
+// does fit.
+
+const KIND_TX: u8 = 0;
+const KIND_RX: u8 = 1;
+const KIND_DROPPED: u8 = 2;
+const HEADER_LEN: usize = 3;
+
+// Larger pushes are split so the consumer can copy out one record at a time
+pub const MAX_RECORD: usize = 256;
+
+pub enum Record {
+    Data(Direction, usize),
+    Dropped(u32),
+}
+
+pub struct PendingQueue<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+    unreported_drops: u32,
+    pub dropped_total: u32,
+    pub max_depth: usize,
+    // payload bytes queued
+    written: u32,
+    // times the write position went past the end of `buf`
+    wraps: u32,
+}
+
+impl<const N: usize> PendingQueue<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+            unreported_drops: 0,
+            dropped_total: 0,
+            max_depth: 0,
+            written: 0,
+            wraps: 0,
+        }
+    }
+
+    pub fn push(&mut self, direction: Direction, data: &[u8]) {
+        let kind = match direction {
+            Direction::Tx => KIND_TX,
+            Direction::Rx => KIND_RX,
+        };
+        for chunk in data.chunks(MAX_RECORD) {
+            let marker_len = if self.unreported_drops > 0 { HEADER_LEN + 4 } else { 0 };
+            if N - self.len < marker_len + HEADER_LEN + chunk.len() {
+                self.unreported_drops = self.unreported_drops.saturating_add(chunk.len() as u32);
+                self.dropped_total = self.dropped_total.saturating_add(chunk.len() as u32);
+                continue;
+            }
+            if marker_len > 0 {
+                let count = self.unreported_drops.to_le_bytes();
+                self.write_record(KIND_DROPPED, &count);
+                self.unreported_drops = 0;
+            }
+            self.write_record(kind, chunk);
+            self.written = self.written.saturating_add(chunk.len() as u32);
+        }
+        self.max_depth = self.max_depth.max(self.len);
+    }
+
+    // Copy the oldest record's payload into `out` (at least MAX_RECORD bytes)
+    pub fn pop(&mut self, out: &mut [u8]) -> Option<Record> {
+        if self.len == 0 {
+            return None;
+        }
+        let kind = self.read_byte();
+        let len = self.read_byte() as usize | (self.read_byte() as usize) << 8;
+        for slot in out[..len].iter_mut() {
+            *slot = self.read_byte();
+        }
+        Some(match kind {
+            KIND_DROPPED => Record::Dropped(u32::from_le_bytes([out[0], out[1], out[2], out[3]])),
+            KIND_TX => Record::Data(Direction::Tx, len),
+            _ => Record::Data(Direction::Rx, len),
+        })
+    }
+
+    pub fn depth(&self) -> usize {
+        self.len
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    fn write_record(&mut self, kind: u8, payload: &[u8]) {
+        let len = payload.len() as u16;
+        for b in [kind, len as u8, (len >> 8) as u8].into_iter().chain(payload.iter().copied()) {
+            let at = (self.head + self.len) % N;
+            self.buf[at] = b;
+            self.len += 1;
+            if at == N - 1 {
+                self.wraps = self.wraps.saturating_add(1);
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let b = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        b
+    }
+}
+
+impl<const N: usize> Ring for PendingQueue<N> {
+    fn usage(&self) -> Usage {
+        Usage {
+            capacity: N,
+            used: self.len,
+            written: self.written,
+            dropped: self.dropped_total,
+            wraps: self.wraps,
+        }
+    }
+
+    // Records not yet in the log are lost without a note
+    fn reset(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.unreported_drops = 0;
+        self.dropped_total = 0;
+        self.max_depth = 0;
+        self.written = 0;
+        self.wraps = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> ModemLog<1024> {
+        let mut log = ModemLog::new();
+        log.record(Direction::Tx, b"AT+CSQ\r\n", 10);
+        log.record(Direction::Rx, b"\r\n+CSQ: 23,99\r\n\r\nOK\r\n", 20);
+        log
+    }
+
+    fn frames<const N: usize>(log: &ModemLog<N>, next: u32) -> (String, u32, bool) {
+        let mut out = heapless::String::<1024>::new();
+        let (next, more) = log.push_event_frames(next, &mut out);
+        (out.to_string(), next, more)
+    }
+
+    const SESSION: &str = "id: 0\nevent: tx\ndata: AT+CSQ\n\n\
+                           id: 3\nevent: rx\ndata: +CSQ: 23,99\n\n\
+                           id: 5\nevent: rx\ndata: OK\n\n";
+
+    #[test]
+    fn one_frame_per_line_without_markers() {
+        let log = session();
+        assert_eq!(frames(&log, 0), (SESSION.to_string(), 6, false));
+        assert_eq!(log.next_seq(), 6);
+    }
+
+    #[test]
+    fn cr_and_invalid_bytes_in_data() {
+        let mut log = ModemLog::<1024>::new();
+        // 行中的 CR 会让浏览器换行, 换成空格
+        log.record(Direction::Rx, b"a\rb\r\n\xffok\xc3\r\n", 0);
+        log.record_dropped(3, 0);
+        log.record(Direction::Rx, b"\r\n", 0);
+        let (out, _, _) = frames(&log, 0);
+        assert_eq!(
+            out,
+            "id: 0\nevent: rx\ndata: a b\n\n\
+             id: 1\nevent: rx\ndata: \u{fffd}ok\u{fffd}\n\n\
+             id: 3\nevent: note\ndata: [3 bytes dropped]\n\n"
+        );
+        assert!(!out.contains('\r'));
+    }
+
+    // Last-Event-ID n: 从 n + 1 接着给
+    #[test]
+    fn resume_after_last_event_id() {
+        let log = session();
+        let (out, next, more) = frames(&log, 3 + 1);
+        assert_eq!(out, "id: 5\nevent: rx\ndata: OK\n\n");
+        assert_eq!((next, more), (6, false));
+        assert_eq!(frames(&log, next), (String::new(), 6, false));
+        // an id from before a reboot, beyond any line yet: from the oldest
+        assert_eq!(frames(&log, 1000), (SESSION.to_string(), 6, false));
+    }
+
+    #[test]
+    fn numbers_go_on_after_a_reset() {
+        let mut log = session();
+        log.reset();
+        assert_eq!(frames(&log, 6), (String::new(), 6, false));
+        log.record(Direction::Tx, b"ATI\r\n", 30);
+        let (out, next, _) = frames(&log, 6);
+        // 6 是换向标记前的空行, 不发
+        assert_eq!(out, "id: 7\nevent: tx\ndata: ATI\n\n");
+        // a stream started before the reset does not see the old lines again
+        assert_eq!(frames(&log, 0).0, out);
+        assert_eq!(next, 8);
+    }
+
+    #[test]
+    fn full_output_leaves_the_rest_for_the_next_round() {
+        let log = session();
+        let first = "id: 0\nevent: tx\ndata: AT+CSQ\n\n";
+        let mut out = heapless::String::<48>::new();
+        assert_eq!(log.push_event_frames(0, &mut out), (3, true));
+        assert_eq!(out, first);
+        // exactly full is not cut
+        let mut out = heapless::String::<{ SESSION.len() }>::new();
+        assert_eq!(log.push_event_frames(0, &mut out), (6, false));
+        // a frame that never fits is not sent in part
+        let mut out = heapless::String::<8>::new();
+        assert_eq!(log.push_event_frames(0, &mut out), (0, true));
+        assert!(out.is_empty());
+
+        let mut all = String::new();
+        let mut next = 0;
+        loop {
+            let mut out = heapless::String::<48>::new();
+            let more;
+            (next, more) = log.push_event_frames(next, &mut out);
+            all += &out;
+            if !more {
+                break;
+            }
+        }
+        assert_eq!(all, SESSION);
+    }
+
+    #[test]
+    fn long_lines_are_cut_on_a_character() {
+        let mut log = ModemLog::<2048>::new();
+        let mut line = "信".repeat(TEXT_MAX / 3 + 10).into_bytes();
+        line.extend_from_slice(b"\r\n");
+        log.record(Direction::Rx, &line, 0);
+        let line = log.lines_since(0).next().unwrap();
+        let mut text = [0; TEXT_MAX];
+        let (bytes, cut) = log.line_text(&line, &mut text);
+        assert!(cut);
+        // TEXT_MAX 包括 "<< " 标记
+        assert_eq!(core::str::from_utf8(bytes).unwrap(), "信".repeat((TEXT_MAX - 3) / 3));
+    }
+}
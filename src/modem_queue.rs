@@ -0,0 +1,138 @@
+// 串口操作队列
+//
+// Every modem operation waits here until the UART task is free. The
+// highest priority runs first (oldest first within a priority), so
+// background polls only run while nothing else is waiting. An operation
+// that waits longer than its own limit is dropped and reported as "modem
+// busy" instead of running long after the caller gave up.
+
+// 后台轮询超过这个时间没有机会运行就记录一次
+pub const STARVATION_MS: u64 = 5 * 60 * 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    // periodic polls: ping, webhook delivery
+    Background,
+    // fetches, macros
+    User,
+    // AT console, SIM unlock
+    Interactive,
+}
+
+pub struct Entry<T> {
+    pub op: T,
+    pub name: &'static str,
+    pub priority: Priority,
+    pub enqueued_ms: u64,
+    // the HTTP request that asked for it, None for background work
+    pub request_id: Option<u32>,
+    // its record in the action log (see actions)
+    pub action: Option<u32>,
+    max_wait_ms: u64,
+}
+
+// Who asked for an operation
+#[derive(Clone, Copy, Default)]
+pub struct Caller {
+    pub request_id: Option<u32>,
+    pub action: Option<u32>,
+}
+
+pub struct Queue<T, const N: usize> {
+    pending: heapless::Vec<Entry<T>, N>,
+    // oldest background operation queued since one last ran
+    background_waiting_since: Option<u64>,
+    starvation_reported: bool,
+    // dropped after waiting too long
+    pub expired: u32,
+    // queue full
+    pub rejected: u32,
+}
+
+impl<T, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            pending: heapless::Vec::new(),
+            background_waiting_since: None,
+            starvation_reported: false,
+            expired: 0,
+            rejected: 0,
+        }
+    }
+
+    // Err gives the operation back when the queue is full
+    pub fn push(
+        &mut self,
+        op: T,
+        name: &'static str,
+        priority: Priority,
+        max_wait_ms: u64,
+        now_ms: u64,
+        caller: Caller,
+    ) -> Result<(), T> {
+        let entry = Entry {
+            op,
+            name,
+            priority,
+            enqueued_ms: now_ms,
+            request_id: caller.request_id,
+            action: caller.action,
+            max_wait_ms,
+        };
+        if let Err(entry) = self.pending.push(entry) {
+            self.rejected += 1;
+            return Err(entry.op);
+        }
+        if priority == Priority::Background {
+            self.background_waiting_since.get_or_insert(now_ms);
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.pending.iter().any(|e| e.name == name)
+    }
+
+    // Withdraw a queued operation before it runs
+    pub fn remove(&mut self, name: &str) -> Option<Entry<T>> {
+        let index = self.pending.iter().position(|e| e.name == name)?;
+        Some(self.pending.remove(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    // One entry that has waited longer than its limit, removed from the queue
+    pub fn take_expired(&mut self, now_ms: u64) -> Option<Entry<T>> {
+        let index = self
+            .pending
+            .iter()
+            .position(|e| now_ms.saturating_sub(e.enqueued_ms) > e.max_wait_ms)?;
+        self.expired += 1;
+        Some(self.pending.remove(index))
+    }
+
+    // Highest priority, oldest first
+    pub fn pop(&mut self) -> Option<Entry<T>> {
+        let top = self.pending.iter().map(|e| e.priority).max()?;
+        let index = self.pending.iter().position(|e| e.priority == top)?;
+        let entry = self.pending.remove(index);
+        if entry.priority == Priority::Background {
+            self.background_waiting_since = None;
+            self.starvation_reported = false;
+        }
+        Some(entry)
+    }
+
+    // How long background work has been kept waiting, once per episode
+    // after STARVATION_MS
+    pub fn starvation(&mut self, now_ms: u64) -> Option<u64> {
+        let waited = now_ms.saturating_sub(self.background_waiting_since?);
+        if waited < STARVATION_MS || self.starvation_reported {
+            return None;
+        }
+        self.starvation_reported = true;
+        Some(waited)
+    }
+}
@@ -0,0 +1,629 @@
+// 模拟的调制解调器: sim-modem 特性下代替 UART0 上的 EC800K
+//
+// The `sim-modem` feature replaces the modem UART with a pair of pipes to
+// a task that runs this engine. The AT paths can then be exercised on a
+// bare Pico 2 W. The engine answers a subset of the Quectel dialect: ATI
+// (so the Quectel backend is picked), ATE, CPIN, CREG/CEREG, CGATT,
+// CGDCONT, CSQ, QICSGP, QIACT/QIDEACT, QIDNSGIP, QIOPEN, QISEND, QIRD,
+// QICLOSE and CFUN=1,1. Anything else answers ERROR. Every reply is queued
+// with a canned delay, so OK and the URCs after it arrive in the order a
+// real module sends them. A connection ignores what is sent on it, answers
+// the first send with RESPONSE and then reports the peer closed. The
+// faults set through /api/sim make the module go silent, refuse QIACT or
+// close half way through the response. The engine only holds state and
+// bytes; the task passes the time in and moves the bytes.
+
+use core::fmt::Write as _;
+
+pub const SOCKETS: usize = 12;
+// what every connection answers
+pub const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 52\r\n\
+Connection: close\r\n\r\n{\"simulated\": true, \"url\": \"http://httpbin.org/get\"}";
+// context 1 address and the answer to every lookup
+pub const LOCAL_IP: &str = "10.0.0.2";
+pub const RESOLVED_IP: &str = "3.223.36.72";
+
+// 固定延迟 (ms)
+const REPLY_MS: u64 = 20;
+const ACTIVATE_MS: u64 = 800;
+const DNS_MS: u64 = 200;
+const OPEN_MS: u64 = 300;
+const DATA_MS: u64 = 400;
+const CLOSE_MS: u64 = 200;
+const RESTART_MS: u64 = 2_000;
+
+const LINE_MAX: usize = 128;
+const OUT_BYTES: usize = 2048;
+const OUT_SEGMENTS: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    None,
+    // reads everything, answers nothing
+    NoResponse,
+    // AT+QIACT=1 answers ERROR
+    QiactError,
+    // connections close after half of RESPONSE
+    CloseMidReceive,
+}
+
+impl Fault {
+    pub const ALL: [Fault; 4] = [Fault::None, Fault::NoResponse, Fault::QiactError, Fault::CloseMidReceive];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Fault::None => "none",
+            Fault::NoResponse => "no_response",
+            Fault::QiactError => "qiact_error",
+            Fault::CloseMidReceive => "close_mid_receive",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.as_str() == name)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Socket {
+    // access mode 1: data pushed with the recv notice
+    direct: bool,
+    // bytes of RESPONSE that arrived, and how many QIRD took
+    arrived: usize,
+    read: usize,
+}
+
+pub struct Engine {
+    fault: Fault,
+    echo: bool,
+    pdp_active: bool,
+    apn: heapless::String<32>,
+    sockets: [Option<Socket>; SOCKETS],
+    line: heapless::Vec<u8, LINE_MAX>,
+    // (connect ID, bytes still to come) after a QISEND prompt
+    sending: Option<(u8, usize)>,
+    // the last byte ended a command: a LF after it is not data
+    after_cr: bool,
+    out: heapless::Deque<u8, OUT_BYTES>,
+    // (due, length) of each queued reply, in order
+    segments: heapless::Deque<(u64, usize), OUT_SEGMENTS>,
+}
+
+impl Engine {
+    pub const fn new() -> Self {
+        Self {
+            fault: Fault::None,
+            echo: true,
+            pdp_active: false,
+            apn: heapless::String::new(),
+            sockets: [None; SOCKETS],
+            line: heapless::Vec::new(),
+            sending: None,
+            after_cr: false,
+            out: heapless::Deque::new(),
+            segments: heapless::Deque::new(),
+        }
+    }
+
+    pub fn set_fault(&mut self, fault: Fault) {
+        self.fault = fault;
+    }
+
+    // One byte from the firmware
+    pub fn feed(&mut self, byte: u8, now_ms: u64) {
+        if self.fault == Fault::NoResponse {
+            self.line.clear();
+            self.sending = None;
+            return;
+        }
+        let after_cr = core::mem::take(&mut self.after_cr);
+        if let Some((id, left)) = self.sending {
+            if byte == b'\n' && after_cr {
+                return;
+            }
+            if left > 1 {
+                self.sending = Some((id, left - 1));
+            } else {
+                self.sending = None;
+                self.sent(id, now_ms);
+            }
+            return;
+        }
+        match byte {
+            b'\r' => {
+                self.after_cr = true;
+                let line = core::mem::take(&mut self.line);
+                self.execute(&line, now_ms);
+            }
+            b'\n' => {}
+            // 太长的指令截断, 之后回 ERROR
+            _ => {
+                let _ = self.line.push(byte);
+            }
+        }
+    }
+
+    // When the next reply is due; None when nothing is queued
+    pub fn next_due_ms(&self) -> Option<u64> {
+        self.segments.front().map(|&(due, _)| due)
+    }
+
+    // Copy out the bytes due by now, up to out.len(); 0 when nothing is due
+    pub fn take_due(&mut self, now_ms: u64, out: &mut [u8]) -> usize {
+        let mut n = 0;
+        while let Some(&(due, len)) = self.segments.front()
+            && due <= now_ms
+            && n < out.len()
+        {
+            let take = len.min(out.len() - n);
+            for slot in &mut out[n..n + take] {
+                *slot = self.out.pop_front().unwrap_or(0);
+            }
+            n += take;
+            if take == len {
+                self.segments.pop_front();
+            } else if let Some(front) = self.segments.front_mut() {
+                front.1 -= take;
+            }
+        }
+        n
+    }
+
+    // Queue `parts` as one reply `delay_ms` from now, never ahead of the
+    // reply before it; dropped when the output is full, like a module
+    // whose buffer overflowed
+    fn queue(&mut self, now_ms: u64, delay_ms: u64, parts: &[&[u8]]) {
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        if len == 0 || self.out.capacity() - self.out.len() < len || self.segments.is_full() {
+            return;
+        }
+        let due = self.segments.back().map_or(0, |&(due, _)| due).max(now_ms + delay_ms);
+        for &b in parts.iter().flat_map(|p| p.iter()) {
+            let _ = self.out.push_back(b);
+        }
+        let _ = self.segments.push_back((due, len));
+    }
+
+    // "\r\n<line>\r\n" for each line
+    fn reply(&mut self, now_ms: u64, delay_ms: u64, lines: &[&str]) {
+        let mut text = heapless::String::<256>::new();
+        for line in lines {
+            let _ = text.push_str("\r\n");
+            let _ = text.push_str(line);
+            let _ = text.push_str("\r\n");
+        }
+        self.queue(now_ms, delay_ms, &[text.as_bytes()]);
+    }
+
+    fn ok(&mut self, now_ms: u64, delay_ms: u64) {
+        self.reply(now_ms, delay_ms, &["OK"]);
+    }
+
+    fn error(&mut self, now_ms: u64) {
+        self.reply(now_ms, REPLY_MS, &["ERROR"]);
+    }
+
+    fn execute(&mut self, raw: &[u8], now_ms: u64) {
+        if self.echo && !raw.is_empty() {
+            self.queue(now_ms, 0, &[raw, b"\r"]);
+        }
+        let Ok(line) = core::str::from_utf8(raw) else {
+            self.error(now_ms);
+            return;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        // 只有指令名不分大小写, 参数 (APN, 主机名) 原样保留
+        let mut upper = heapless::String::<LINE_MAX>::new();
+        for c in line.chars() {
+            let _ = upper.push(c.to_ascii_uppercase());
+        }
+        let args = |prefix: &str| upper.starts_with(prefix).then(|| &line[prefix.len()..]);
+
+        match upper.as_str() {
+            "AT" => self.ok(now_ms, REPLY_MS),
+            "ATE0" | "ATE1" => {
+                self.echo = upper == "ATE1";
+                self.ok(now_ms, REPLY_MS);
+            }
+            "ATI" => self.reply(now_ms, REPLY_MS, &["Quectel", "EC800K", "Revision: EC800KCNLCR06A01M08_SIM", "OK"]),
+            "AT+CPIN?" => self.reply(now_ms, REPLY_MS, &["+CPIN: READY", "OK"]),
+            "AT+CREG?" => self.reply(now_ms, REPLY_MS, &["+CREG: 0,1", "OK"]),
+            "AT+CEREG?" => self.reply(now_ms, REPLY_MS, &["+CEREG: 0,1", "OK"]),
+            "AT+CSQ" => self.reply(now_ms, REPLY_MS, &["+CSQ: 24,99", "OK"]),
+            "AT+CGATT?" => self.reply(now_ms, REPLY_MS, &["+CGATT: 1", "OK"]),
+            "AT+CGATT=1" => self.ok(now_ms, REPLY_MS),
+            "AT+CGDCONT?" => {
+                let mut context = heapless::String::<64>::new();
+                let _ = core::write!(context, "+CGDCONT: 1,\"IP\",\"{}\"", self.apn);
+                self.reply(now_ms, REPLY_MS, &[&context, "OK"]);
+            }
+            "AT+QIACT=1" if self.fault == Fault::QiactError => self.reply(now_ms, ACTIVATE_MS, &["ERROR"]),
+            "AT+QIACT=1" => {
+                self.pdp_active = true;
+                self.ok(now_ms, ACTIVATE_MS);
+            }
+            "AT+QIACT?" if self.pdp_active => {
+                let mut state = heapless::String::<64>::new();
+                let _ = core::write!(state, "+QIACT: 1,1,1,\"{}\"", LOCAL_IP);
+                self.reply(now_ms, REPLY_MS, &[&state, "OK"]);
+            }
+            "AT+QIACT?" => self.ok(now_ms, REPLY_MS),
+            "AT+QIDEACT=1" => {
+                self.pdp_active = false;
+                self.sockets = [None; SOCKETS];
+                self.ok(now_ms, ACTIVATE_MS);
+            }
+            "AT+CFUN=1,1" => {
+                self.ok(now_ms, REPLY_MS);
+                self.restart();
+                self.reply(now_ms, RESTART_MS, &["RDY"]);
+            }
+            _ => {
+                if let Some(args) = args("AT+CGDCONT=").or_else(|| args("AT+QICSGP=")) {
+                    self.set_apn(args);
+                    self.ok(now_ms, REPLY_MS);
+                } else if args("AT+QIDNSGIP=").is_some() {
+                    self.resolve(now_ms);
+                } else if let Some(args) = args("AT+QIOPEN=") {
+                    self.open(args, now_ms);
+                } else if let Some(args) = args("AT+QISEND=") {
+                    self.prompt(args, now_ms);
+                } else if let Some(args) = args("AT+QIRD=") {
+                    self.read(args, now_ms);
+                } else if let Some(id) = args("AT+QICLOSE=").and_then(|a| a.trim().parse::<usize>().ok()) {
+                    if let Some(socket) = self.sockets.get_mut(id) {
+                        *socket = None;
+                    }
+                    self.ok(now_ms, REPLY_MS);
+                } else {
+                    self.error(now_ms);
+                }
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.echo = true;
+        self.pdp_active = false;
+        self.sockets = [None; SOCKETS];
+    }
+
+    // <ctx>,"IP","<apn>" (CGDCONT) or <ctx>,<type>,"<apn>" (QICSGP)
+    fn set_apn(&mut self, args: &str) {
+        let apn = args.split(',').nth(2).unwrap_or("").trim().trim_matches('"');
+        self.apn.clear();
+        let _ = self.apn.push_str(apn);
+    }
+
+    // 先回 OK, 地址随后作为 URC 到达
+    fn resolve(&mut self, now_ms: u64) {
+        if !self.pdp_active {
+            self.error(now_ms);
+            return;
+        }
+        self.ok(now_ms, REPLY_MS);
+        let mut address = heapless::String::<48>::new();
+        let _ = core::write!(address, "+QIURC: \"dnsgip\",\"{}\"", RESOLVED_IP);
+        self.reply(now_ms, DNS_MS, &["+QIURC: \"dnsgip\",0,1,600", &address]);
+    }
+
+    // 1,<id>,"TCP","<ip>",<port>,0,<access mode>
+    fn open(&mut self, args: &str, now_ms: u64) {
+        let mut fields = args.split(',').map(str::trim);
+        let id = fields.nth(1).and_then(|id| id.parse::<u8>().ok());
+        let direct = fields.nth(4) == Some("1");
+        let Some(id) = id.filter(|&id| (id as usize) < SOCKETS && self.sockets[id as usize].is_none()) else {
+            self.error(now_ms);
+            return;
+        };
+        self.ok(now_ms, REPLY_MS);
+        // 561: 数据连接没有激活
+        let code = if self.pdp_active {
+            self.sockets[id as usize] = Some(Socket { direct, arrived: 0, read: 0 });
+            0
+        } else {
+            561
+        };
+        let mut urc = heapless::String::<24>::new();
+        let _ = core::write!(urc, "+QIOPEN: {},{}", id, code);
+        self.reply(now_ms, OPEN_MS, &[&urc]);
+    }
+
+    // <id>,<len>: '>' then exactly len bytes
+    fn prompt(&mut self, args: &str, now_ms: u64) {
+        let (id, len) = args.split_once(',').unwrap_or((args, ""));
+        let id = id.trim().parse::<u8>().ok().filter(|&id| self.socket(id).is_some());
+        match (id, len.trim().parse::<usize>()) {
+            (Some(id), Ok(len)) if len > 0 => {
+                self.queue(now_ms, REPLY_MS, &[b"\r\n> "]);
+                self.sending = Some((id, len));
+            }
+            _ => self.error(now_ms),
+        }
+    }
+
+    // The data of a send is in: SEND OK, then the answer and the close
+    fn sent(&mut self, id: u8, now_ms: u64) {
+        self.reply(now_ms, REPLY_MS, &["SEND OK"]);
+        let end = match self.fault {
+            Fault::CloseMidReceive => RESPONSE.len() / 2,
+            _ => RESPONSE.len(),
+        };
+        let Some(socket) = self.socket(id) else {
+            return;
+        };
+        // 只回答第一次发送
+        if socket.arrived > 0 {
+            return;
+        }
+        socket.arrived = end;
+        let direct = socket.direct;
+        if direct {
+            socket.read = end;
+        }
+
+        let mut urc = heapless::String::<48>::new();
+        if direct {
+            let _ = core::write!(urc, "\r\n+QIURC: \"recv\",{},{}\r\n", id, end);
+            self.queue(now_ms, DATA_MS, &[urc.as_bytes(), &RESPONSE[..end]]);
+        } else {
+            let _ = core::write!(urc, "\r\n+QIURC: \"recv\",{}\r\n", id);
+            self.queue(now_ms, DATA_MS, &[urc.as_bytes()]);
+        }
+        urc.clear();
+        let _ = core::write!(urc, "+QIURC: \"closed\",{}", id);
+        self.reply(now_ms, DATA_MS + CLOSE_MS, &[&urc]);
+    }
+
+    // <id>,<max>: +QIRD: <n>, n bytes, OK
+    fn read(&mut self, args: &str, now_ms: u64) {
+        let (id, max) = args.split_once(',').unwrap_or((args, ""));
+        let max = max.trim().parse::<usize>().unwrap_or(usize::MAX);
+        let Some(socket) = id.trim().parse::<u8>().ok().and_then(|id| self.socket(id)) else {
+            self.error(now_ms);
+            return;
+        };
+        let start = socket.read;
+        let n = max.min(socket.arrived - start);
+        socket.read += n;
+
+        let mut head = heapless::String::<24>::new();
+        let _ = core::write!(head, "\r\n+QIRD: {}\r\n", n);
+        self.queue(now_ms, REPLY_MS, &[head.as_bytes(), &RESPONSE[start..start + n], b"\r\nOK\r\n"]);
+    }
+
+    fn socket(&mut self, id: u8) -> Option<&mut Socket> {
+        self.sockets.get_mut(id as usize)?.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 驱动模拟器的任务: 时间只在等回复时前进
+    struct Bench {
+        engine: Engine,
+        now: u64,
+    }
+
+    impl Bench {
+        fn new() -> Self {
+            Self { engine: Engine::new(), now: 0 }
+        }
+
+        fn send(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.engine.feed(b, self.now);
+            }
+        }
+
+        // One command line and what came back, with each reply's delay
+        fn at(&mut self, line: &str) -> Vec<(u64, String)> {
+            let start = self.now;
+            self.send(line.as_bytes());
+            self.send(b"\r");
+            self.drain(start)
+        }
+
+        // Wait out every queued reply, as modem_sim_task does
+        fn drain(&mut self, start: u64) -> Vec<(u64, String)> {
+            let mut replies = Vec::new();
+            while let Some(due) = self.engine.next_due_ms() {
+                if due > self.now {
+                    assert_eq!(self.engine.take_due(due - 1, &mut [0; 8]), 0, "a reply came early");
+                }
+                self.now = self.now.max(due);
+                let mut out = [0; OUT_BYTES];
+                let n = self.engine.take_due(self.now, &mut out);
+                replies.push((due - start, String::from_utf8_lossy(&out[..n]).into_owned()));
+            }
+            replies
+        }
+
+        // Past the echo and up to an active context
+        fn attached(fault: Fault) -> Self {
+            let mut bench = Self::new();
+            bench.engine.set_fault(fault);
+            bench.at("ATE0");
+            bench.at("AT+QIACT=1");
+            bench
+        }
+    }
+
+    fn lines(lines: &[&str]) -> String {
+        lines.iter().map(|l| format!("\r\n{l}\r\n")).collect()
+    }
+
+    fn response(end: usize) -> &'static str {
+        core::str::from_utf8(&RESPONSE[..end]).unwrap()
+    }
+
+    #[test]
+    fn session_from_at_to_close() {
+        let mut bench = Bench::new();
+        assert_eq!(bench.at("AT"), [(0, "AT\r".into()), (REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("ATE0"), [(0, "ATE0\r".into()), (REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT"), [(REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT+CPIN?"), [(REPLY_MS, lines(&["+CPIN: READY", "OK"]))]);
+        assert_eq!(bench.at("AT+CREG?"), [(REPLY_MS, lines(&["+CREG: 0,1", "OK"]))]);
+        assert_eq!(bench.at("AT+CSQ"), [(REPLY_MS, lines(&["+CSQ: 24,99", "OK"]))]);
+
+        // 指令名不分大小写, APN 原样保留
+        assert_eq!(bench.at("at+cgdcont=1,\"IP\",\"CMNet\""), [(REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT+CGDCONT?"), [(REPLY_MS, lines(&["+CGDCONT: 1,\"IP\",\"CMNet\"", "OK"]))]);
+        assert_eq!(bench.at("AT+QIACT?"), [(REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT+QIACT=1"), [(ACTIVATE_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT+QIACT?"), [(REPLY_MS, lines(&["+QIACT: 1,1,1,\"10.0.0.2\"", "OK"]))]);
+        assert_eq!(
+            bench.at("AT+QIDNSGIP=1,\"httpbin.org\""),
+            [
+                (REPLY_MS, lines(&["OK"])),
+                (DNS_MS, lines(&["+QIURC: \"dnsgip\",0,1,600", "+QIURC: \"dnsgip\",\"3.223.36.72\""]))
+            ]
+        );
+
+        assert_eq!(
+            bench.at("AT+QIOPEN=1,0,\"TCP\",\"3.223.36.72\",80,0,0"),
+            [(REPLY_MS, lines(&["OK"])), (OPEN_MS, lines(&["+QIOPEN: 0,0"]))]
+        );
+        assert_eq!(bench.at("AT+QISEND=0,5"), [(REPLY_MS, "\r\n> ".into())]);
+        let start = bench.now;
+        bench.send(b"hello");
+        assert_eq!(
+            bench.drain(start),
+            [
+                (REPLY_MS, lines(&["SEND OK"])),
+                (DATA_MS, "\r\n+QIURC: \"recv\",0\r\n".into()),
+                (DATA_MS + CLOSE_MS, lines(&["+QIURC: \"closed\",0"]))
+            ]
+        );
+        let all = format!("\r\n+QIRD: {}\r\n{}\r\nOK\r\n", RESPONSE.len(), response(RESPONSE.len()));
+        assert_eq!(bench.at("AT+QIRD=0,1500"), [(REPLY_MS, all)]);
+        assert_eq!(bench.at("AT+QIRD=0,1500"), [(REPLY_MS, "\r\n+QIRD: 0\r\n\r\nOK\r\n".into())]);
+        assert_eq!(bench.at("AT+QICLOSE=0"), [(REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT+QISEND=0,5"), [(REPLY_MS, lines(&["ERROR"]))]);
+    }
+
+    #[test]
+    fn send_data_is_not_taken_for_commands() {
+        let mut bench = Bench::attached(Fault::None);
+        bench.at("AT+QIOPEN=1,3,\"TCP\",\"3.223.36.72\",80,0,0");
+        bench.at("AT+QISEND=3,4");
+        // CR LF 和 "AT" 都只是数据
+        let start = bench.now;
+        bench.send(b"AT\r\n");
+        assert_eq!(bench.drain(start)[0], (REPLY_MS, lines(&["SEND OK"])));
+        // 第二次发送没有新的回答
+        bench.at("AT+QISEND=3,1");
+        let start = bench.now;
+        bench.send(b"x");
+        assert_eq!(bench.drain(start), [(REPLY_MS, lines(&["SEND OK"]))]);
+    }
+
+    #[test]
+    fn direct_push_carries_the_data_with_the_notice() {
+        let mut bench = Bench::attached(Fault::None);
+        bench.at("AT+QIOPEN=1,1,\"TCP\",\"3.223.36.72\",80,0,1");
+        bench.at("AT+QISEND=1,2");
+        let start = bench.now;
+        bench.send(b"hi");
+        let pushed = format!("\r\n+QIURC: \"recv\",1,{}\r\n{}", RESPONSE.len(), response(RESPONSE.len()));
+        assert_eq!(bench.drain(start)[1], (DATA_MS, pushed));
+        assert_eq!(bench.at("AT+QIRD=1,1500"), [(REPLY_MS, "\r\n+QIRD: 0\r\n\r\nOK\r\n".into())]);
+    }
+
+    #[test]
+    fn small_reads_split_a_reply() {
+        let mut bench = Bench::new();
+        bench.send(b"ATI\r");
+        let mut text = Vec::new();
+        let mut out = [0; 3];
+        while let Some(due) = bench.engine.next_due_ms() {
+            let n = bench.engine.take_due(due, &mut out);
+            assert!(n > 0);
+            text.extend_from_slice(&out[..n]);
+        }
+        let info = lines(&["Quectel", "EC800K", "Revision: EC800KCNLCR06A01M08_SIM", "OK"]);
+        assert_eq!(String::from_utf8(text).unwrap(), format!("ATI\r{info}"));
+    }
+
+    #[test]
+    fn unknown_commands_answer_error() {
+        let mut bench = Bench::attached(Fault::None);
+        assert_eq!(bench.at("AT+FOO"), [(REPLY_MS, lines(&["ERROR"]))]);
+        assert_eq!(bench.at(&format!("AT+{}", "X".repeat(LINE_MAX))), [(REPLY_MS, lines(&["ERROR"]))]);
+        assert_eq!(bench.at("AT+QIOPEN=1,12,\"TCP\",\"3.223.36.72\",80,0,0"), [(REPLY_MS, lines(&["ERROR"]))]);
+    }
+
+    #[test]
+    fn restart_brings_the_echo_back() {
+        let mut bench = Bench::attached(Fault::None);
+        assert_eq!(bench.at("AT+CFUN=1,1"), [(REPLY_MS, lines(&["OK"])), (RESTART_MS, lines(&["RDY"]))]);
+        assert_eq!(bench.at("AT"), [(0, "AT\r".into()), (REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT+QIACT?"), [(0, "AT+QIACT?\r".into()), (REPLY_MS, lines(&["OK"]))]);
+    }
+
+    #[test]
+    fn fault_names_round_trip() {
+        for fault in Fault::ALL {
+            assert!(Fault::parse(fault.as_str()) == Some(fault));
+        }
+        assert!(Fault::parse("silent").is_none());
+    }
+
+    #[test]
+    fn no_response_answers_nothing() {
+        let mut bench = Bench::attached(Fault::None);
+        bench.at("AT+QIOPEN=1,0,\"TCP\",\"3.223.36.72\",80,0,0");
+        bench.at("AT+QISEND=0,5");
+        bench.engine.set_fault(Fault::NoResponse);
+        bench.send(b"hel");
+        for line in ["AT", "ATI", "AT+CSQ", "AT+QIRD=0,1500"] {
+            assert_eq!(bench.at(line), []);
+            assert_eq!(bench.engine.next_due_ms(), None);
+        }
+        // 清除故障后是一条新的指令, 发送中的数据已丢弃
+        bench.engine.set_fault(Fault::None);
+        assert_eq!(bench.at("AT"), [(REPLY_MS, lines(&["OK"]))]);
+    }
+
+    #[test]
+    fn qiact_error_leaves_the_context_down() {
+        let mut bench = Bench::new();
+        bench.engine.set_fault(Fault::QiactError);
+        bench.at("ATE0");
+        assert_eq!(bench.at("AT+QIACT=1"), [(ACTIVATE_MS, lines(&["ERROR"]))]);
+        assert_eq!(bench.at("AT+QIACT?"), [(REPLY_MS, lines(&["OK"]))]);
+        assert_eq!(bench.at("AT+QIDNSGIP=1,\"httpbin.org\""), [(REPLY_MS, lines(&["ERROR"]))]);
+        assert_eq!(
+            bench.at("AT+QIOPEN=1,0,\"TCP\",\"3.223.36.72\",80,0,0"),
+            [(REPLY_MS, lines(&["OK"])), (OPEN_MS, lines(&["+QIOPEN: 0,561"]))]
+        );
+    }
+
+    #[test]
+    fn close_mid_receive_stops_half_way() {
+        let half = RESPONSE.len() / 2;
+
+        let mut bench = Bench::attached(Fault::CloseMidReceive);
+        bench.at("AT+QIOPEN=1,0,\"TCP\",\"3.223.36.72\",80,0,0");
+        bench.at("AT+QISEND=0,1");
+        let start = bench.now;
+        bench.send(b"x");
+        assert_eq!(bench.drain(start)[2], (DATA_MS + CLOSE_MS, lines(&["+QIURC: \"closed\",0"])));
+        let read = format!("\r\n+QIRD: {half}\r\n{}\r\nOK\r\n", response(half));
+        assert_eq!(bench.at("AT+QIRD=0,1500"), [(REPLY_MS, read)]);
+
+        let mut bench = Bench::attached(Fault::CloseMidReceive);
+        bench.at("AT+QIOPEN=1,0,\"TCP\",\"3.223.36.72\",80,0,1");
+        bench.at("AT+QISEND=0,1");
+        let start = bench.now;
+        bench.send(b"x");
+        let pushed = format!("\r\n+QIURC: \"recv\",0,{half}\r\n{}", response(half));
+        assert_eq!(bench.drain(start)[1], (DATA_MS, pushed));
+    }
+}
@@ -0,0 +1,248 @@
+// 模块侧连接的生命周期 (/net 页面)
+//
+// The fetch (connect ID 0) and the port forwards (forward::FIRST_CONNECT_ID
+// and up) report every modem connection here. Each connection reports when
+// it opened, with its destination and time to connect, then the bytes sent
+// and received, then how it closed: by us, by the peer or by an error.
+// Events go to a short log, newest last. A send or receive on the same ID
+// within COALESCE_MS of the entry before it is added to that entry, so a
+// busy forward cannot push everything else out of the log. Each ID also
+// keeps totals over all of its connections. Hosts are cut to HOST_MAX bytes.
+
+pub const MAX_IDS: usize = 6;
+pub const HOST_MAX: usize = 32;
+const LOG_LEN: usize = 24;
+const COALESCE_MS: u64 = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    Us,
+    Peer,
+    Error,
+}
+
+impl CloseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Us => "by us",
+            CloseReason::Peer => "by peer",
+            CloseReason::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Event {
+    Opened {
+        host: heapless::String<HOST_MAX>,
+        port: u16,
+        connect_ms: u32,
+    },
+    Sent {
+        bytes: u32,
+    },
+    Received {
+        bytes: u32,
+    },
+    Closed {
+        reason: CloseReason,
+        sent: u32,
+        received: u32,
+        lifetime_ms: u32,
+    },
+}
+
+#[derive(Clone)]
+pub struct Record {
+    pub id: u8,
+    pub at_ms: u64,
+    pub event: Event,
+}
+
+// The connection an ID has right now
+#[derive(Clone)]
+pub struct Open {
+    pub host: heapless::String<HOST_MAX>,
+    pub port: u16,
+    pub since_ms: u64,
+    pub sent: u32,
+    pub received: u32,
+}
+
+// Over every connection an ID has had since boot
+#[derive(Clone, Copy)]
+pub struct Totals {
+    pub opened: u32,
+    // indexed by CloseReason
+    pub closed: [u32; 3],
+    pub sent: u32,
+    pub received: u32,
+    pub connect_ms: u32,
+    pub lifetime_ms: u32,
+    pub longest_ms: u32,
+}
+
+impl Totals {
+    const ZERO: Totals = Totals {
+        opened: 0,
+        closed: [0; 3],
+        sent: 0,
+        received: 0,
+        connect_ms: 0,
+        lifetime_ms: 0,
+        longest_ms: 0,
+    };
+
+    pub fn average_connect_ms(&self) -> u32 {
+        self.connect_ms.checked_div(self.opened).unwrap_or(0)
+    }
+
+    // Of the connections that have closed
+    pub fn average_lifetime_ms(&self) -> u32 {
+        self.lifetime_ms.checked_div(self.closed.iter().sum()).unwrap_or(0)
+    }
+}
+
+pub struct Tracker {
+    open: [Option<Open>; MAX_IDS],
+    totals: [Totals; MAX_IDS],
+    log: heapless::Deque<Record, LOG_LEN>,
+}
+
+impl Tracker {
+    pub const fn new() -> Self {
+        Self {
+            open: [const { None }; MAX_IDS],
+            totals: [Totals::ZERO; MAX_IDS],
+            log: heapless::Deque::new(),
+        }
+    }
+
+    pub fn opened(&mut self, id: u8, host: &str, port: u16, connect_ms: u32, now_ms: u64) {
+        if id as usize >= MAX_IDS {
+            return;
+        }
+        // 上一个连接没有报告关闭: 按出错算
+        self.closed(id, CloseReason::Error, now_ms);
+        let mut short = heapless::String::new();
+        for c in host.chars() {
+            if short.push(c).is_err() {
+                break;
+            }
+        }
+        let totals = &mut self.totals[id as usize];
+        totals.opened += 1;
+        totals.connect_ms = totals.connect_ms.saturating_add(connect_ms);
+        self.open[id as usize] = Some(Open {
+            host: short.clone(),
+            port,
+            since_ms: now_ms,
+            sent: 0,
+            received: 0,
+        });
+        self.push(Record {
+            id,
+            at_ms: now_ms,
+            event: Event::Opened {
+                host: short,
+                port,
+                connect_ms,
+            },
+        });
+    }
+
+    pub fn sent(&mut self, id: u8, bytes: usize, now_ms: u64) {
+        let Some(open) = self.open.get_mut(id as usize).and_then(Option::as_mut) else {
+            return;
+        };
+        let bytes = bytes as u32;
+        open.sent = open.sent.saturating_add(bytes);
+        let totals = &mut self.totals[id as usize];
+        totals.sent = totals.sent.saturating_add(bytes);
+        if let Some(Record {
+            event: Event::Sent { bytes: logged },
+            ..
+        }) = self.recent(id, now_ms)
+        {
+            *logged = logged.saturating_add(bytes);
+            return;
+        }
+        self.push(Record {
+            id,
+            at_ms: now_ms,
+            event: Event::Sent { bytes },
+        });
+    }
+
+    pub fn received(&mut self, id: u8, bytes: usize, now_ms: u64) {
+        let Some(open) = self.open.get_mut(id as usize).and_then(Option::as_mut) else {
+            return;
+        };
+        let bytes = bytes as u32;
+        open.received = open.received.saturating_add(bytes);
+        let totals = &mut self.totals[id as usize];
+        totals.received = totals.received.saturating_add(bytes);
+        if let Some(Record {
+            event: Event::Received { bytes: logged },
+            ..
+        }) = self.recent(id, now_ms)
+        {
+            *logged = logged.saturating_add(bytes);
+            return;
+        }
+        self.push(Record {
+            id,
+            at_ms: now_ms,
+            event: Event::Received { bytes },
+        });
+    }
+
+    // Nothing happens when the ID has no open connection
+    pub fn closed(&mut self, id: u8, reason: CloseReason, now_ms: u64) {
+        let Some(open) = self.open.get_mut(id as usize).and_then(Option::take) else {
+            return;
+        };
+        let lifetime_ms = now_ms.saturating_sub(open.since_ms).min(u32::MAX as u64) as u32;
+        let totals = &mut self.totals[id as usize];
+        totals.closed[reason as usize] += 1;
+        totals.lifetime_ms = totals.lifetime_ms.saturating_add(lifetime_ms);
+        totals.longest_ms = totals.longest_ms.max(lifetime_ms);
+        self.push(Record {
+            id,
+            at_ms: now_ms,
+            event: Event::Closed {
+                reason,
+                sent: open.sent,
+                received: open.received,
+                lifetime_ms,
+            },
+        });
+    }
+
+    pub fn open(&self, id: u8) -> Option<&Open> {
+        self.open.get(id as usize)?.as_ref()
+    }
+
+    pub fn totals(&self, id: u8) -> Totals {
+        self.totals.get(id as usize).copied().unwrap_or(Totals::ZERO)
+    }
+
+    // Oldest first
+    pub fn log(&self) -> impl Iterator<Item = &Record> + '_ {
+        self.log.iter()
+    }
+
+    // The newest entry, when it is this ID's and young enough to add to
+    fn recent(&mut self, id: u8, now_ms: u64) -> Option<&mut Record> {
+        self.log
+            .back_mut()
+            .filter(|record| record.id == id && now_ms.saturating_sub(record.at_ms) < COALESCE_MS)
+    }
+
+    fn push(&mut self, record: Record) {
+        if self.log.is_full() {
+            self.log.pop_front();
+        }
+        let _ = self.log.push_back(record);
+    }
+}
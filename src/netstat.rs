@@ -0,0 +1,87 @@
+// 套接字登记表 (/net 页面)
+//
+// Every task that owns a socket registers it here and reports state changes
+// and byte counts. This is what the tasks believe, not a query of the
+// network stack. Times are milliseconds since boot.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Tcp,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Tcp => "tcp",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Listen,
+    Established,
+    Closing,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Listen => "LISTEN",
+            State::Established => "ESTABLISHED",
+            State::Closing => "CLOSING",
+        }
+    }
+}
+
+// A socket stuck outside its steady state this long is flagged on the page
+pub const SUSPICIOUS_AFTER_MS: u64 = 60_000;
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub owner: &'static str,
+    pub kind: Kind,
+    pub local_port: u16,
+    pub remote: Option<([u8; 4], u16)>,
+    pub state: State,
+    pub state_since_ms: u64,
+    pub rx_bytes: u32,
+    pub tx_bytes: u32,
+}
+
+impl Entry {
+    // Listening sockets wait indefinitely by design
+    pub fn suspicious(&self, now_ms: u64) -> bool {
+        !matches!(self.state, State::Listen | State::Established)
+            && now_ms.saturating_sub(self.state_since_ms) > SUSPICIOUS_AFTER_MS
+    }
+}
+
+pub struct Registry<const N: usize> {
+    entries: [Option<Entry>; N],
+}
+
+impl<const N: usize> Registry<N> {
+    pub const fn new() -> Self {
+        Self { entries: [None; N] }
+    }
+
+    // None when the table is full; the socket then simply isn't listed
+    pub fn register(&mut self, entry: Entry) -> Option<usize> {
+        let index = self.entries.iter().position(Option::is_none)?;
+        self.entries[index] = Some(entry);
+        Some(index)
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.entries[index] = None;
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Entry> {
+        self.entries[index].as_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().flatten()
+    }
+}
@@ -0,0 +1,27 @@
+// 代理自动配置 (GET /proxy.pac)
+//
+// There is no proxy server on the device itself. pac.forward names the
+// port forward (1-4) that leads to an upstream HTTP proxy. Its listen port
+// on 192.168.4.1 is then the proxy the script hands out. The script is
+// built from the live config on every request, so it follows a changed
+// listen port. Names without a dot and addresses on the AP subnet,
+// including the gateway itself, always go DIRECT. With no proxy set, or
+// its rule disabled, everything goes DIRECT.
+
+use core::fmt::Write;
+
+pub const CONTENT_TYPE: &str = "application/x-ns-proxy-autoconfig";
+const GATEWAY: &str = "192.168.4.1";
+
+// `proxy_port`: listen port of the proxy forward, None = no proxy
+pub fn write_script<W: Write>(out: &mut W, proxy_port: Option<u16>) {
+    let _ = out.write_str("function FindProxyForURL(url, host) {\n");
+    let _ = out.write_str("  if (isPlainHostName(host)) return \"DIRECT\";\n");
+    let _ = out.write_str("  if (/^\\d+\\.\\d+\\.\\d+\\.\\d+$/.test(host) && isInNet(host, \"192.168.4.0\", \"255.255.255.0\"))\n");
+    let _ = out.write_str("    return \"DIRECT\";\n");
+    let _ = match proxy_port {
+        Some(port) => writeln!(out, "  return \"PROXY {}:{}; DIRECT\";", GATEWAY, port),
+        None => out.write_str("  return \"DIRECT\";\n"),
+    };
+    let _ = out.write_str("}\n");
+}
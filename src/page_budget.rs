@@ -0,0 +1,192 @@
+// 页面分段预算: 放不下时先省略优先级低的段落
+//
+// A page is still built in one fixed buffer. Its variable sections (the
+// fetch summary, the log tail) each have a priority and a largest share
+// of the space left after http::PAGE_HEADROOM. A section
+// may use its share, less what the higher-priority sections still to come
+// on the page have reserved, so a section early in the page can never
+// crowd out a more important one after it. A section that comes out
+// larger is taken back out and replaced by a one-line notice linking to
+// where it can be read in full. The rest of the page is always sent. The
+// result area on /tools is not budgeted: it is streamed in after the page
+// is built (write_streamed_page).
+
+use core::fmt::Write;
+
+pub struct Section {
+    pub name: &'static str,
+    // 0 is kept first
+    pub priority: u8,
+    pub max_percent: u8,
+    // where the whole section can be read
+    pub link: &'static str,
+}
+
+pub const FETCH_SUMMARY: Section = Section {
+    name: "fetch summary",
+    priority: 1,
+    max_percent: 25,
+    link: "/api/fetch/history",
+};
+
+pub const LOG_TAIL: Section = Section {
+    name: "log tail",
+    priority: 3,
+    max_percent: 100,
+    link: "/log.txt",
+};
+
+// A section that came out larger than its room
+pub struct Omitted {
+    pub len: usize,
+    pub room: usize,
+}
+
+pub struct Budget<'a> {
+    sections: &'a [Section],
+    // page bytes the sections and everything else may fill
+    budget: usize,
+    // bit per section index
+    rendered: u32,
+}
+
+impl<'a> Budget<'a> {
+    pub fn new(sections: &'a [Section], budget: usize) -> Self {
+        Self {
+            sections,
+            budget,
+            rendered: 0,
+        }
+    }
+
+    fn share(&self, section: &Section) -> usize {
+        self.budget * section.max_percent as usize / 100
+    }
+
+    // Bytes section `index` may take when the page already holds `used`
+    pub fn room(&self, index: usize, used: usize) -> usize {
+        let priority = self.sections[index].priority;
+        let reserved: usize = self
+            .sections
+            .iter()
+            .enumerate()
+            .filter(|&(i, s)| self.rendered & (1 << i) == 0 && s.priority < priority)
+            .map(|(_, s)| self.share(s))
+            .sum();
+        self.budget
+            .saturating_sub(used)
+            .saturating_sub(reserved)
+            .min(self.share(&self.sections[index]))
+    }
+
+    pub fn rendered(&mut self, index: usize) {
+        self.rendered |= 1 << index;
+    }
+
+    pub fn section(&self, index: usize) -> &Section {
+        &self.sections[index]
+    }
+
+    // Writes section `index` with `fill`. One larger than its room is taken
+    // back out and replaced by a line naming it and its link.
+    pub fn push<const N: usize>(
+        &mut self,
+        html: &mut heapless::String<N>,
+        index: usize,
+        fill: impl FnOnce(&mut heapless::String<N>),
+    ) -> Result<(), Omitted> {
+        let start = html.len();
+        let room = self.room(index, start);
+        fill(html);
+        self.rendered(index);
+        let len = html.len() - start;
+        if len <= room {
+            return Ok(());
+        }
+        html.truncate(start);
+        let section = &self.sections[index];
+        let _ = write!(
+            html,
+            "<em>Section {} omitted ({} bytes) — view at <a href='{}'>{}</a></em>",
+            section.name, len, section.link, section.link
+        );
+        Err(Omitted { len, room })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+
+    const PAGE: usize = 6144;
+    const STATUS_TABLE: &str = "<table><tr><th>SIM</th><td>ready</td></tr><tr><th>CSQ</th><td>23</td></tr></table>";
+
+    fn page() -> heapless::String<PAGE> {
+        let mut html = heapless::String::new();
+        let _ = html.push_str("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n");
+        let _ = html.push_str("<h1>Status</h1>");
+        html
+    }
+
+    fn log(len: usize) -> String {
+        "+QIURC: \"recv\",0\r\n".repeat(len / 18 + 1)[..len].to_string()
+    }
+
+    #[test]
+    fn huge_log_never_pushes_out_the_status_table() {
+        for len in [0, 100, 4000, PAGE, 10 * PAGE] {
+            let text = log(len);
+            let mut html = page();
+            let sections = [LOG_TAIL, FETCH_SUMMARY];
+            let mut budget = Budget::new(&sections, PAGE - http::PAGE_HEADROOM);
+            // line by line, as the log is written: what does not fit is dropped
+            let _ = budget.push(&mut html, 0, |html| {
+                for line in text.split_inclusive('\n') {
+                    let _ = html.push_str(line);
+                }
+            });
+            let _ = budget.push(&mut html, 1, |html| {
+                let _ = html.push_str("<table><tr><td>fetch</td></tr></table>");
+            });
+            let _ = html.push_str(STATUS_TABLE);
+            let _ = html.push_str("</body></html>");
+
+            assert!(html.contains(STATUS_TABLE), "log of {len} bytes");
+            assert!(html.contains("<td>fetch</td>"), "log of {len} bytes");
+            assert!(html.ends_with("</html>"));
+            assert_eq!(html.contains(&text), !html.contains("omitted"), "log of {len} bytes");
+            assert!(http::finish_page(&mut html).is_ok(), "log of {len} bytes");
+        }
+    }
+
+    #[test]
+    fn omitted_section_leaves_a_link() {
+        let mut html = page();
+        let start = html.len();
+        let sections = [LOG_TAIL];
+        let mut budget = Budget::new(&sections, 2000);
+        let omitted = budget.push(&mut html, 0, |html| {
+            let _ = html.push_str(&log(3000));
+        });
+        let omitted = omitted.expect_err("over its room");
+        assert_eq!(omitted.len, 3000);
+        assert_eq!(omitted.room, 2000 - start);
+        assert_eq!(
+            &html[start..],
+            "<em>Section log tail omitted (3000 bytes) — view at <a href='/log.txt'>/log.txt</a></em>"
+        );
+    }
+
+    #[test]
+    fn room_keeps_the_share_of_more_important_sections_to_come() {
+        let sections = [LOG_TAIL, FETCH_SUMMARY];
+        let mut budget = Budget::new(&sections, 4000);
+        // the fetch summary (priority 1) still to come keeps its 25 %
+        assert_eq!(budget.room(0, 500), 4000 - 500 - 1000);
+        assert_eq!(budget.room(1, 500), 1000);
+        budget.rendered(1);
+        assert_eq!(budget.room(0, 500), 3500);
+        assert_eq!(budget.room(0, 5000), 0);
+    }
+}
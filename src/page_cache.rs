@@ -0,0 +1,156 @@
+// 状态页的合并渲染: 同一时间窗口内的请求共用一份
+//
+// Several phones refreshing the overview each take every state lock and
+// format the same page within the same second, while the UART task waits
+// for those locks. The first request for "/" or /api/status in a window
+// builds the page and stores it with the state generation it was built
+// in. Requests within WINDOW_MS that see the same generation get a copy
+// and take no state locks. A generation bump makes the copy stale at once.
+// Only pages that are the same for every client are cached: /tools carries
+// the requester's own refresh, so it is always built.
+//
+// The weak ETag of these pages names the generation too. The handler reads
+// the generation once, before it renders, and uses it for the ETag, the
+// page and the copy stored here. A change during the render leaves all
+// three one generation behind, so the next poll gets the new state.
+
+use core::fmt::Write;
+
+pub const WINDOW_MS: u64 = 500;
+
+// JSON and HTML get different tags: Vary: Accept
+pub fn etag(generation: u32, json: bool) -> heapless::String<24> {
+    let mut etag = heapless::String::new();
+    let _ = write!(etag, "W/\"{}-{}\"", generation, if json { "j" } else { "h" });
+    etag
+}
+
+pub struct PageCache<const N: usize> {
+    page: heapless::String<N>,
+    // (generation, built at) of `page`; None before the first build
+    key: Option<(u32, u64)>,
+    hits: u32,
+    misses: u32,
+}
+
+impl<const N: usize> PageCache<N> {
+    pub const fn new() -> Self {
+        Self {
+            page: heapless::String::new(),
+            key: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // A copy of the page when it was built in `generation` less than
+    // WINDOW_MS ago; None means build it and store() it
+    pub fn get(&mut self, generation: u32, now_ms: u64) -> Option<heapless::String<N>> {
+        match self.key {
+            Some((built_in, at)) if built_in == generation && now_ms.saturating_sub(at) < WINDOW_MS => {
+                self.hits += 1;
+                Some(self.page.clone())
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn store(&mut self, page: &heapless::String<N>, generation: u32, now_ms: u64) {
+        self.page.clone_from(page);
+        self.key = Some((generation, now_ms));
+    }
+
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u32 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::etag_matches;
+
+    // What "/" answers a poll that sends `if_none_match`, the way the
+    // handler does it; `during` runs between reading the generation and
+    // storing the page, where the render is
+    fn poll(
+        cache: &mut PageCache<64>,
+        generation: &mut u32,
+        now_ms: u64,
+        if_none_match: Option<&str>,
+        during: impl FnOnce(&mut u32),
+    ) -> (u16, heapless::String<24>, heapless::String<64>) {
+        let read = *generation;
+        let tag = etag(read, false);
+        if etag_matches(if_none_match, &tag) {
+            return (304, tag, heapless::String::new());
+        }
+        if let Some(page) = cache.get(read, now_ms) {
+            return (200, tag, page);
+        }
+        during(generation);
+        let mut page = heapless::String::new();
+        let _ = write!(page, "state {}", read);
+        cache.store(&page, read, now_ms);
+        (200, tag, page)
+    }
+
+    #[test]
+    fn change_during_render_is_sent_on_the_next_poll() {
+        let mut cache = PageCache::new();
+        let mut generation = 7;
+
+        // the state changes while the page is rendered: the page and its
+        // tag both name generation 7
+        let (status, tag, page) = poll(&mut cache, &mut generation, 0, None, |g| *g += 1);
+        assert_eq!((status, tag.as_str(), page.as_str()), (200, "W/\"7-h\"", "state 7"));
+        assert_eq!(generation, 8);
+
+        // the client's tag is for 7, so it gets 8 and not a 304; the copy
+        // built in 7 is not served either, though the window is still open
+        let (status, tag, page) = poll(&mut cache, &mut generation, 100, Some(&tag), |_| {});
+        assert_eq!((status, tag.as_str(), page.as_str()), (200, "W/\"8-h\"", "state 8"));
+
+        // nothing changed since: 304
+        let (status, _, _) = poll(&mut cache, &mut generation, 200, Some(&tag), |_| {});
+        assert_eq!(status, 304);
+    }
+
+    // A tag taken after the render would name state the page does not show
+    // and the client would never be told about the change
+    #[test]
+    fn tag_read_after_the_render_would_hide_the_change() {
+        let before = etag(7, false);
+        let after = etag(8, false);
+        assert!(!etag_matches(Some(&before), &after));
+        assert!(etag_matches(Some(&after), &after));
+    }
+
+    #[test]
+    fn tags_differ_by_generation_and_type() {
+        assert_eq!(etag(3, true), "W/\"3-j\"");
+        assert!(!etag_matches(Some(&etag(3, true)), &etag(3, false)));
+        assert!(etag_matches(Some("\"3-j\", W/\"4-j\""), &etag(4, true)));
+        assert!(etag_matches(Some("*"), &etag(u32::MAX, false)));
+    }
+
+    #[test]
+    fn copy_is_served_within_the_window_and_generation() {
+        let mut cache = PageCache::<16>::new();
+        assert_eq!(cache.get(0, 0), None);
+        cache.store(&heapless::String::try_from("page").unwrap(), 1, 1000);
+        assert_eq!(cache.get(1, 1000 + WINDOW_MS - 1).as_deref(), Some("page"));
+        assert_eq!(cache.get(1, 1000 + WINDOW_MS), None);
+        assert_eq!(cache.get(2, 1000), None);
+        // a clock that went back is not "older than the window"
+        assert_eq!(cache.get(1, 0).as_deref(), Some("page"));
+        assert_eq!((cache.hits(), cache.misses()), (2, 3));
+    }
+}
@@ -0,0 +1,56 @@
+// 启动时没有应答的调制解调器: 定期重新探测
+//
+// When bring-up ends without an answer, the modem is probed again with a
+// bare AT every FAST_MS. After SLOW_AFTER_MS without an answer the probes
+// drop to one every SLOW_MS to save power. The probes go through the
+// modem queue like the other background polls, so manual AT commands
+// still get through in between. The first answer runs the usual init and
+// ends the retries.
+
+pub const FAST_MS: u64 = 5_000;
+pub const SLOW_MS: u64 = 60_000;
+pub const SLOW_AFTER_MS: u64 = 10 * 60_000;
+
+#[derive(Clone, Copy)]
+pub struct Retry {
+    since_ms: u64,
+    // probes sent after bring-up gave up
+    attempts: u32,
+    next_ms: u64,
+}
+
+impl Retry {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            since_ms: now_ms,
+            attempts: 0,
+            next_ms: now_ms + FAST_MS,
+        }
+    }
+
+    pub fn due_ms(&self) -> u64 {
+        self.next_ms
+    }
+
+    pub fn since_ms(&self) -> u64 {
+        self.since_ms
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn interval_ms(&self, now_ms: u64) -> u64 {
+        if now_ms.saturating_sub(self.since_ms) < SLOW_AFTER_MS {
+            FAST_MS
+        } else {
+            SLOW_MS
+        }
+    }
+
+    // A probe went unanswered: schedule the next one
+    pub fn failed(&mut self, now_ms: u64) {
+        self.attempts += 1;
+        self.next_ms = now_ms + self.interval_ms(now_ms);
+    }
+}
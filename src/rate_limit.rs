@@ -0,0 +1,260 @@
+// 按客户端 IP 的令牌桶限流和并发连接上限
+//
+// Small fixed table; entries of idle clients age out so a phone that left
+// the AP frees its slot. Times are milliseconds since boot.
+
+#[derive(Clone, Copy)]
+pub struct Limits {
+    // bucket capacity, i.e. requests allowed in a burst
+    pub burst: u32,
+    // one token is added back every `refill_ms`
+    pub refill_ms: u32,
+    pub max_connections: u8,
+    pub idle_expiry_ms: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Decision {
+    Allow,
+    RateLimited { retry_after_secs: u32 },
+    TooManyConnections,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    ip: [u8; 4],
+    tokens: u32,
+    last_refill_ms: u64,
+    last_seen_ms: u64,
+    active: u8,
+}
+
+pub struct RateLimiter<const N: usize> {
+    entries: [Option<Entry>; N],
+}
+
+impl<const N: usize> RateLimiter<N> {
+    pub const fn new() -> Self {
+        Self { entries: [None; N] }
+    }
+
+    // Called for every accepted connection. On Allow the connection counts
+    // as active until `release` is called for the same IP.
+    pub fn check(&mut self, ip: [u8; 4], now_ms: u64, limits: &Limits) -> Decision {
+        self.expire(now_ms, limits);
+
+        let Some(entry) = self.entry_for(ip, now_ms, limits) else {
+            // table full of active clients: fail open
+            return Decision::Allow;
+        };
+        entry.last_seen_ms = now_ms;
+
+        if entry.active >= limits.max_connections {
+            return Decision::TooManyConnections;
+        }
+
+        let refill_ms = limits.refill_ms.max(1) as u64;
+        let elapsed = now_ms.saturating_sub(entry.last_refill_ms);
+        let refilled = (elapsed / refill_ms) as u32;
+        if refilled > 0 {
+            entry.tokens = (entry.tokens + refilled).min(limits.burst);
+            entry.last_refill_ms += refilled as u64 * refill_ms;
+        }
+        if entry.tokens >= limits.burst {
+            entry.last_refill_ms = now_ms;
+        }
+
+        if entry.tokens == 0 {
+            let wait_ms = refill_ms - now_ms.saturating_sub(entry.last_refill_ms).min(refill_ms);
+            return Decision::RateLimited {
+                retry_after_secs: (wait_ms.div_ceil(1000) as u32).max(1),
+            };
+        }
+
+        entry.tokens -= 1;
+        entry.active += 1;
+        Decision::Allow
+    }
+
+    pub fn release(&mut self, ip: [u8; 4]) {
+        for entry in self.entries.iter_mut().flatten() {
+            if entry.ip == ip {
+                entry.active = entry.active.saturating_sub(1);
+                return;
+            }
+        }
+    }
+
+    fn expire(&mut self, now_ms: u64, limits: &Limits) {
+        for slot in self.entries.iter_mut() {
+            if let Some(entry) = slot
+                && entry.active == 0
+                && now_ms.saturating_sub(entry.last_seen_ms) > limits.idle_expiry_ms as u64
+            {
+                *slot = None;
+            }
+        }
+    }
+
+    fn entry_for(&mut self, ip: [u8; 4], now_ms: u64, limits: &Limits) -> Option<&mut Entry> {
+        let index = match self.entries.iter().position(|e| matches!(e, Some(e) if e.ip == ip)) {
+            Some(i) => i,
+            None => {
+                // free slot, otherwise evict the least recently seen idle client
+                let i = match self.entries.iter().position(Option::is_none) {
+                    Some(i) => i,
+                    None => self
+                        .entries
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, e)| e.filter(|e| e.active == 0).map(|e| (i, e.last_seen_ms)))
+                        .min_by_key(|&(_, seen)| seen)
+                        .map(|(i, _)| i)?,
+                };
+                self.entries[i] = Some(Entry {
+                    ip,
+                    tokens: limits.burst,
+                    last_refill_ms: now_ms,
+                    last_seen_ms: now_ms,
+                    active: 0,
+                });
+                i
+            }
+        };
+        self.entries[index].as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [u8; 4] = [192, 168, 4, 2];
+    const B: [u8; 4] = [192, 168, 4, 3];
+    const C: [u8; 4] = [192, 168, 4, 4];
+
+    const LIMITS: Limits = Limits { burst: 3, refill_ms: 1_000, max_connections: 2, idle_expiry_ms: 60_000 };
+    // 补充极慢: 桶空了就一直空着, 除非条目过期
+    const SLOW: Limits = Limits { refill_ms: 1_000_000, ..LIMITS };
+
+    // one request on a connection that closes straight away
+    fn request<const N: usize>(limiter: &mut RateLimiter<N>, ip: [u8; 4], now_ms: u64, limits: &Limits) -> Decision {
+        let decision = limiter.check(ip, now_ms, limits);
+        if decision == Decision::Allow {
+            limiter.release(ip);
+        }
+        decision
+    }
+
+    #[test]
+    fn burst_then_one_per_refill() {
+        let mut limiter = RateLimiter::<4>::new();
+        for _ in 0..3 {
+            assert_eq!(request(&mut limiter, A, 0, &LIMITS), Decision::Allow);
+        }
+        assert_eq!(request(&mut limiter, A, 0, &LIMITS), Decision::RateLimited { retry_after_secs: 1 });
+        assert_eq!(request(&mut limiter, A, 999, &LIMITS), Decision::RateLimited { retry_after_secs: 1 });
+        assert_eq!(request(&mut limiter, A, 1_000, &LIMITS), Decision::Allow);
+        assert_eq!(request(&mut limiter, A, 1_500, &LIMITS), Decision::RateLimited { retry_after_secs: 1 });
+        // two refills' worth gives two requests
+        assert_eq!(request(&mut limiter, A, 3_000, &LIMITS), Decision::Allow);
+        assert_eq!(request(&mut limiter, A, 3_000, &LIMITS), Decision::Allow);
+        assert_eq!(request(&mut limiter, A, 3_000, &LIMITS), Decision::RateLimited { retry_after_secs: 1 });
+    }
+
+    #[test]
+    fn retry_after_rounds_up_the_wait() {
+        let limits = Limits { burst: 1, refill_ms: 5_000, ..LIMITS };
+        let mut limiter = RateLimiter::<4>::new();
+        assert_eq!(request(&mut limiter, A, 0, &limits), Decision::Allow);
+        assert_eq!(request(&mut limiter, A, 1_200, &limits), Decision::RateLimited { retry_after_secs: 4 });
+        assert_eq!(request(&mut limiter, A, 4_999, &limits), Decision::RateLimited { retry_after_secs: 1 });
+        assert_eq!(request(&mut limiter, A, 5_000, &limits), Decision::Allow);
+    }
+
+    #[test]
+    fn a_full_bucket_does_not_bank_time() {
+        let mut limiter = RateLimiter::<4>::new();
+        assert_eq!(request(&mut limiter, A, 0, &LIMITS), Decision::Allow);
+        // 十秒空闲只能把桶补满, 不能多出七个
+        for _ in 0..3 {
+            assert_eq!(request(&mut limiter, A, 10_000, &LIMITS), Decision::Allow);
+        }
+        assert_eq!(request(&mut limiter, A, 10_000, &LIMITS), Decision::RateLimited { retry_after_secs: 1 });
+        assert_eq!(request(&mut limiter, A, 10_999, &LIMITS), Decision::RateLimited { retry_after_secs: 1 });
+        assert_eq!(request(&mut limiter, A, 11_000, &LIMITS), Decision::Allow);
+    }
+
+    #[test]
+    fn clients_have_their_own_buckets() {
+        let mut limiter = RateLimiter::<4>::new();
+        for _ in 0..3 {
+            assert_eq!(request(&mut limiter, A, 0, &LIMITS), Decision::Allow);
+        }
+        assert!(matches!(request(&mut limiter, A, 0, &LIMITS), Decision::RateLimited { .. }));
+        assert_eq!(request(&mut limiter, B, 0, &LIMITS), Decision::Allow);
+    }
+
+    #[test]
+    fn open_connections_are_capped_until_released() {
+        let mut limiter = RateLimiter::<4>::new();
+        assert_eq!(limiter.check(A, 0, &LIMITS), Decision::Allow);
+        assert_eq!(limiter.check(A, 0, &LIMITS), Decision::Allow);
+        assert_eq!(limiter.check(A, 0, &LIMITS), Decision::TooManyConnections);
+        assert_eq!(limiter.check(B, 0, &LIMITS), Decision::Allow);
+        limiter.release(A);
+        assert_eq!(limiter.check(A, 0, &LIMITS), Decision::Allow);
+        // releasing an unknown or already idle client changes nothing
+        limiter.release(C);
+        limiter.release(B);
+        limiter.release(B);
+        assert_eq!(limiter.check(B, 0, &LIMITS), Decision::Allow);
+    }
+
+    #[test]
+    fn idle_clients_age_out() {
+        let mut limiter = RateLimiter::<4>::new();
+        for _ in 0..3 {
+            request(&mut limiter, A, 0, &SLOW);
+        }
+        assert!(matches!(request(&mut limiter, A, 0, &SLOW), Decision::RateLimited { .. }));
+        // a refused request still counts as seen
+        assert!(matches!(request(&mut limiter, A, 60_000, &SLOW), Decision::RateLimited { .. }));
+        assert!(matches!(request(&mut limiter, A, 120_000, &SLOW), Decision::RateLimited { .. }));
+        assert_eq!(request(&mut limiter, A, 180_001, &SLOW), Decision::Allow);
+    }
+
+    #[test]
+    fn open_connections_keep_the_entry() {
+        let mut limiter = RateLimiter::<4>::new();
+        assert_eq!(limiter.check(A, 0, &SLOW), Decision::Allow);
+        assert_eq!(limiter.check(A, 0, &SLOW), Decision::Allow);
+        // 连接还开着: 过了过期时间也不能丢掉计数
+        assert_eq!(limiter.check(A, 100_000, &SLOW), Decision::TooManyConnections);
+    }
+
+    #[test]
+    fn full_table_evicts_the_least_recently_seen_idle_client() {
+        let mut limiter = RateLimiter::<2>::new();
+        for _ in 0..3 {
+            request(&mut limiter, A, 0, &SLOW);
+            request(&mut limiter, B, 10, &SLOW);
+        }
+        // C takes A's slot: A was seen first
+        assert_eq!(request(&mut limiter, C, 20, &SLOW), Decision::Allow);
+        assert!(matches!(request(&mut limiter, B, 30, &SLOW), Decision::RateLimited { .. }));
+        assert_eq!(request(&mut limiter, A, 40, &SLOW), Decision::Allow);
+    }
+
+    #[test]
+    fn full_table_of_open_connections_fails_open() {
+        let mut limiter = RateLimiter::<2>::new();
+        for _ in 0..2 {
+            assert_eq!(limiter.check(A, 0, &LIMITS), Decision::Allow);
+            assert_eq!(limiter.check(B, 0, &LIMITS), Decision::Allow);
+        }
+        for _ in 0..5 {
+            assert_eq!(limiter.check(C, 0, &LIMITS), Decision::Allow);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// 网络注册状态 (CREG / CEREG), 漫游时默认禁止蜂窝数据
+//
+// Roaming data on these SIMs is very expensive. Every feature that uses
+// cellular data asks first; while the module is registered as roaming and
+// roaming.block_data is set, the attempt is refused and counted against
+// the feature that made it.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    NotRegistered,
+    Home,
+    Searching,
+    Denied,
+    Unknown,
+    Roaming,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::NotRegistered => "not registered",
+            State::Home => "home",
+            State::Searching => "searching",
+            State::Denied => "denied",
+            State::Unknown => "unknown",
+            State::Roaming => "roaming",
+        }
+    }
+
+    fn from_stat(stat: &str) -> Option<State> {
+        Some(match stat {
+            "0" => State::NotRegistered,
+            "1" => State::Home,
+            "2" => State::Searching,
+            "3" => State::Denied,
+            "4" => State::Unknown,
+            "5" => State::Roaming,
+            _ => return None,
+        })
+    }
+
+    // Combine the CS and EPS answers: roaming on either counts, since that
+    // is the expensive case; otherwise home on either is enough
+    pub fn merge(self, other: State) -> State {
+        if self == State::Roaming || other == State::Roaming {
+            State::Roaming
+        } else if self == State::Home || other == State::Home {
+            State::Home
+        } else {
+            other
+        }
+    }
+}
+
+// `+CREG: <n>,<stat>[,...]` (query) or `+CREG: <stat>[,"<lac>",...]`
+// (unsolicited), same for +CEREG and +CGREG
+pub fn parse(line: &str) -> Option<State> {
+    let rest = ["+CREG:", "+CEREG:", "+CGREG:"]
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix))?;
+    let mut fields = rest.split(',').map(str::trim);
+    let first = fields.next()?;
+    let stat = match fields.next() {
+        Some(second) if !second.starts_with('"') => second,
+        _ => first,
+    };
+    State::from_stat(stat)
+}
+
+// 使用蜂窝数据的功能
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Fetch,
+    Webhook,
+    KeepWarm,
+    Ping,
+    Forward,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::Fetch,
+        Feature::Webhook,
+        Feature::KeepWarm,
+        Feature::Ping,
+        Feature::Forward,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::Fetch => "fetch",
+            Feature::Webhook => "webhook",
+            Feature::KeepWarm => "keep_warm",
+            Feature::Ping => "ping",
+            Feature::Forward => "forward",
+        }
+    }
+}
+
+// Attempts refused while roaming
+#[derive(Clone, Copy)]
+pub struct Blocks {
+    counts: [u32; 5],
+}
+
+impl Blocks {
+    pub const fn new() -> Self {
+        Self { counts: [0; 5] }
+    }
+
+    // True for the first block of this feature
+    pub fn record(&mut self, feature: Feature) -> bool {
+        self.counts[feature as usize] += 1;
+        self.counts[feature as usize] == 1
+    }
+
+    pub fn get(&self, feature: Feature) -> u32 {
+        self.counts[feature as usize]
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+}
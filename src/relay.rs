@@ -0,0 +1,164 @@
+// 中继下载 (/relay): 经蜂窝连接获取一个 URL, 边收边转给 WiFi 客户端
+//
+// GET /relay?url=http://... queues a fetch of that URL on the modem for a
+// client on the WiFi side that cannot use the trigger-and-read pages. The
+// upstream status and Content-Type go back to the client, and the body
+// follows in chunked encoding as it is read from the module. The modem
+// task passes the body on through a small pipe and waits for room in it
+// before the next AT+QIRD, so the module holds whatever the client has
+// not taken yet and the slower side sets the pace. One relay runs at a
+// time; another request gets 503 meanwhile. A fetch that fails after the
+// head went out ends the response without the closing chunk, so the
+// client sees a broken transfer, not a short file. Relayed body bytes are
+// counted in their own stat, relay_bytes.
+
+use crate::utf8;
+
+pub const URL_MAX: usize = 256;
+// from the request to the upstream head: the modem queue wait plus a fetch's setup
+pub const HEAD_WAIT_MS: u64 = 180_000;
+// the client took nothing from a full pipe for this long
+pub const STALL_MS: u64 = 30_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    // waiting for the modem task or the upstream head
+    Queued,
+    Streaming,
+    Done,
+    Failed,
+}
+
+// Status line and Content-Type of the upstream response
+pub struct Head {
+    line: heapless::Vec<u8, 128>,
+    lines: u32,
+    done: bool,
+    // 0 when the status line was not HTTP
+    pub status: u16,
+    pub reason: heapless::String<32>,
+    pub content_type: heapless::String<96>,
+}
+
+impl Head {
+    pub const fn new() -> Self {
+        Self {
+            line: heapless::Vec::new(),
+            lines: 0,
+            done: false,
+            status: 0,
+            reason: heapless::String::new(),
+            content_type: heapless::String::new(),
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    // Bytes of `data` that belonged to the head; the rest is body
+    pub fn feed(&mut self, data: &[u8]) -> usize {
+        for (i, &b) in data.iter().enumerate() {
+            if self.done {
+                return i;
+            }
+            if b == b'\n' {
+                self.end_line();
+                self.line.clear();
+            } else if b != b'\r' {
+                // 过长的头部行截断, 只用到状态行和 Content-Type
+                let _ = self.line.push(b);
+            }
+        }
+        data.len()
+    }
+
+    fn end_line(&mut self) {
+        let line = core::str::from_utf8(&self.line).unwrap_or("");
+        self.lines += 1;
+        if self.lines == 1 {
+            let mut parts = line.splitn(3, ' ');
+            if parts.next().is_some_and(|v| v.starts_with("HTTP/")) {
+                self.status = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+                utf8::push_truncated(&mut self.reason, parts.next().unwrap_or("").trim());
+            }
+            return;
+        }
+        if line.is_empty() {
+            self.done = true;
+        } else if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-type")
+        {
+            self.content_type.clear();
+            utf8::push_truncated(&mut self.content_type, value.trim());
+        }
+    }
+}
+
+pub struct Relay {
+    pub state: State,
+    pub url: heapless::String<URL_MAX>,
+    pub head: Head,
+    // the client went away; the modem task stops at its next read
+    pub abandoned: bool,
+    // why a failed relay failed
+    pub error: heapless::String<48>,
+}
+
+impl Relay {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Idle,
+            url: heapless::String::new(),
+            head: Head::new(),
+            abandoned: false,
+            error: heapless::String::new(),
+        }
+    }
+
+    // False while another relay is running
+    pub fn begin(&mut self, url: &str) -> bool {
+        if self.state != State::Idle {
+            return false;
+        }
+        *self = Self::new();
+        self.state = State::Queued;
+        utf8::push_truncated(&mut self.url, url);
+        true
+    }
+
+    // The modem task picks it up; None when the client has already gone
+    pub fn start(&self) -> Option<heapless::String<URL_MAX>> {
+        (self.state == State::Queued && !self.abandoned).then(|| self.url.clone())
+    }
+
+    // The modem task is done with it; `error` None when the fetch succeeded
+    pub fn finish(&mut self, error: Option<&str>) {
+        if self.abandoned {
+            self.state = State::Idle;
+            return;
+        }
+        match error {
+            None if self.head.done => self.state = State::Done,
+            None => self.fail("no HTTP response head"),
+            Some(error) => self.fail(error),
+        }
+    }
+
+    fn fail(&mut self, error: &str) {
+        self.state = State::Failed;
+        self.error.clear();
+        utf8::push_truncated(&mut self.error, error);
+    }
+
+    // The client side is done with it. `running`: the modem task is on it
+    // and will call finish; otherwise the relay is free again at once.
+    pub fn release(&mut self, running: bool) {
+        if running && matches!(self.state, State::Queued | State::Streaming) {
+            self.abandoned = true;
+        } else {
+            self.state = State::Idle;
+        }
+    }
+}
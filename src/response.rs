@@ -0,0 +1,161 @@
+// 最近一次获取的原始响应 (/api/response)
+//
+// The fetch passes every payload byte here as it reads it. The head is kept
+// as text: the status line and the first MAX_HEADERS headers. The body is
+// kept as raw bytes up to BODY_MAX; anything beyond is counted, not stored,
+// and the response is marked truncated. The results area only ever shows
+// the body HTML-escaped; this is what lets it be fetched as it came.
+
+use core::fmt::Write as _;
+
+use crate::{fetch, json, utf8};
+
+pub const BODY_MAX: usize = 4096;
+pub const MAX_HEADERS: usize = 12;
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+pub struct Header {
+    pub name: heapless::String<32>,
+    pub value: heapless::String<96>,
+}
+
+pub struct Response {
+    line: heapless::Vec<u8, 128>,
+    head_done: bool,
+    status_line: heapless::String<64>,
+    headers: heapless::Vec<Header, MAX_HEADERS>,
+    // header lines seen, including the ones not kept
+    header_count: u32,
+    head_bytes: u32,
+    body_bytes: u32,
+    body: heapless::Vec<u8, BODY_MAX>,
+    origin: fetch::Origin,
+    at_ms: u64,
+}
+
+impl Response {
+    pub fn new(origin: fetch::Origin, at_ms: u64) -> Self {
+        Self {
+            line: heapless::Vec::new(),
+            head_done: false,
+            status_line: heapless::String::new(),
+            headers: heapless::Vec::new(),
+            header_count: 0,
+            head_bytes: 0,
+            body_bytes: 0,
+            body: heapless::Vec::new(),
+            origin,
+            at_ms,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        for (i, &b) in data.iter().enumerate() {
+            if self.head_done {
+                let rest = &data[i..];
+                self.body_bytes += rest.len() as u32;
+                let room = (self.body.capacity() - self.body.len()).min(rest.len());
+                let _ = self.body.extend_from_slice(&rest[..room]);
+                return;
+            }
+            self.head_bytes += 1;
+            if b == b'\n' {
+                self.end_line();
+                self.line.clear();
+            } else if b != b'\r' {
+                let _ = self.line.push(b);
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = core::str::from_utf8(&self.line).unwrap_or("");
+        if self.status_line.is_empty() && self.header_count == 0 {
+            utf8::push_truncated(&mut self.status_line, line);
+            return;
+        }
+        if line.is_empty() {
+            self.head_done = true;
+            return;
+        }
+        self.header_count += 1;
+        if let Some((name, value)) = line.split_once(':') {
+            let mut header = Header {
+                name: heapless::String::new(),
+                value: heapless::String::new(),
+            };
+            utf8::push_truncated(&mut header.name, name.trim());
+            utf8::push_truncated(&mut header.value, value.trim());
+            let _ = self.headers.push(header);
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn content_type(&self) -> &str {
+        self.header("Content-Type").unwrap_or(DEFAULT_CONTENT_TYPE)
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        self.status_line.split(' ').nth(1)?.parse().ok()
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    // As sent by the server, including what was not stored
+    pub fn body_bytes(&self) -> u32 {
+        self.body_bytes
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.body_bytes as usize > self.body.len()
+    }
+
+    pub fn read_at(&self, offset: usize, out: &mut [u8]) -> usize {
+        let stored = self.body.get(offset..).unwrap_or(&[]);
+        let n = stored.len().min(out.len());
+        out[..n].copy_from_slice(&stored[..n]);
+        n
+    }
+
+    pub fn write_meta_json<const N: usize>(&self, out: &mut heapless::String<N>, now_ms: u64) {
+        let mut headers = heapless::String::<2048>::new();
+        let _ = headers.push('[');
+        for (i, header) in self.headers.iter().enumerate() {
+            if i > 0 {
+                let _ = headers.push(',');
+            }
+            let mut obj = json::Object::new(&mut headers);
+            obj.str("name", &header.name).str("value", &header.value);
+            obj.finish();
+        }
+        let _ = headers.push(']');
+
+        let mut status = heapless::String::<8>::new();
+        let _ = match self.status() {
+            Some(code) => core::write!(status, "{}", code),
+            None => status.push_str("null").map_err(|_| core::fmt::Error),
+        };
+
+        let mut obj = json::Object::new(out);
+        obj.str("status_line", &self.status_line)
+            .raw("status", &status)
+            .raw("headers", &headers)
+            .u32("headers_total", self.header_count)
+            .str("content_type", self.content_type())
+            .u32("head_bytes", self.head_bytes)
+            .u32("body_bytes", self.body_bytes)
+            .u32("stored_bytes", self.body.len() as u32)
+            .bool("truncated", self.truncated())
+            .str("origin", self.origin.as_str())
+            .u32("age_s", (now_ms.saturating_sub(self.at_ms) / 1000) as u32);
+        obj.finish();
+    }
+}
@@ -0,0 +1,57 @@
+// 环形缓冲区一览 (/debug/buffers, /api/buffers)
+//
+// The RAM buffers that fill up on their own each implement Ring, so one
+// page can show how full they are and whether anything has been lost.
+// The counters start at boot or at the buffer's last reset. `written` is
+// what went in, `dropped` what never made it in, and `wraps` how often the
+// oldest data had to make room: a turn of a byte ring or one evicted
+// archive entry. A reset empties the buffer and starts its counters over.
+// Readers holding an offset into a log simply find nothing older.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Buffer {
+    ModemLog,
+    // between the UART task and the modem log
+    ModemLogQueue,
+    Uart1Log,
+    Capture,
+    ResponseArchive,
+}
+
+impl Buffer {
+    pub const ALL: [Buffer; 5] = [
+        Buffer::ModemLog,
+        Buffer::ModemLogQueue,
+        Buffer::Uart1Log,
+        Buffer::Capture,
+        Buffer::ResponseArchive,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Buffer::ModemLog => "modem_log",
+            Buffer::ModemLogQueue => "modem_log_queue",
+            Buffer::Uart1Log => "uart1_log",
+            Buffer::Capture => "capture",
+            Buffer::ResponseArchive => "response_archive",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|b| b.as_str() == name)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Usage {
+    pub capacity: usize,
+    pub used: usize,
+    pub written: u32,
+    pub dropped: u32,
+    pub wraps: u32,
+}
+
+pub trait Ring {
+    fn usage(&self) -> Usage;
+    fn reset(&mut self);
+}
@@ -0,0 +1,280 @@
+// 路由表: 路径, 允许的方法和一句说明
+//
+// handle_client dispatches with a `match` on the path; the 405 check and
+// /api/spec both read this table. A route missing here answers 405 to
+// anything but GET/HEAD and is left out of the spec, so add both together.
+// Unknown paths get a 404. Nothing on this device asks for
+// credentials, so the spec says "auth":"none" once instead of per route.
+
+use crate::{http, json, version};
+
+pub struct Route {
+    pub path: &'static str,
+    pub methods: &'static str,
+    pub description: &'static str,
+}
+
+const fn route(path: &'static str, methods: &'static str, description: &'static str) -> Route {
+    Route {
+        path,
+        methods,
+        description,
+    }
+}
+
+const GET: &str = "GET, HEAD";
+const POST: &str = "POST";
+const FORM: &str = "GET, HEAD, POST";
+// 有副作用的 GET: HEAD 得 405, 不会真的去做
+const GET_ONLY: &str = "GET";
+
+pub const ROUTES: &[Route] = &[
+    route("/", GET, "Overview page; JSON status with Accept: application/json"),
+    route("/status", GET, "Same as /"),
+    route("/tools", GET, "Fetch and AT console page with the last result"),
+    route("/at", FORM, "Queue the AT command in cmd= (query or form body) and show the tools page"),
+    route(
+        "/http_get",
+        GET,
+        "Queue a fetch of httpbin.org/get, or of host=, port=, path=, and show the tools page",
+    ),
+    route("/api/status", GET, "Modem, UART and last result as JSON"),
+    route("/api/spec", GET, "This description of the HTTP API"),
+    route("/api/version", GET, "Firmware, cyw43 and modem versions"),
+    route("/style.css", GET, "Stylesheet; gzip and Range supported"),
+    route("/live.js", GET, "Script that updates the UI pages in place; gzip and Range supported"),
+    route("/log", GET, "Modem UART log; text with Accept: text/plain"),
+    route("/log.txt", GET, "Whole modem UART log; gzip and Range supported"),
+    route(
+        "/api/log",
+        GET,
+        "Log text from offset ?since= (2 KB at most; X-Log-End is the next offset); ?log=uart1. \
+         Without since, the modem log tail ends with a line of UART byte counters. \
+         With Accept: application/json, line records from line number ?since= (\"next\" is the next one)",
+    ),
+    route(
+        "/events",
+        GET,
+        "New modem log lines as Server-Sent Events (id: line number, event: tx, rx or note); \
+         Last-Event-ID resumes, one stream at a time",
+    ),
+    route("/logview", GET, "Log viewer with filters, pause and download; works without internet access"),
+    route("/log/previous.txt", GET, "Log saved before the last reboot"),
+    route("/log/uart1", GET, "UART1 debug port log; text with Accept: text/plain"),
+    route("/net", GET, "Modem socket table"),
+    route("/api/net", GET, "Modem socket table as JSON"),
+    route("/api/actions", GET, "The last 50 actions (fetch, SMS, webhook, identify) with their source and outcome"),
+    route("/api/arp", GET, "WiFi clients by IP and MAC, with pins from arp.pins, as JSON"),
+    route("/metrics", GET, "Prometheus metrics"),
+    route("/stats", GET, "Since-boot and lifetime statistics side by side"),
+    route("/api/stats", GET, "Since-boot and lifetime statistics as JSON"),
+    route("/api/boot", GET, "Boot stages with their times, and which tasks started or failed to start"),
+    route("/api/lockstats", GET, "Hold times of each async lock site and holds over http.lock_budget_ms"),
+    route("/debug/buffers", GET, "Fill level and losses of each RAM ring buffer, with reset buttons"),
+    route("/api/buffers", GET, "Capacity, used, written, dropped and wraps of each ring buffer as JSON"),
+    route("/api/buffers/reset", POST, "Empty the ring buffer in ?name= and start its counters over"),
+    route("/healthz", GET, "200 or 503 from the checks in health.checks, with each result as JSON"),
+    route("/macros", FORM, "Macro library; POST replaces it"),
+    route("/api/macros", GET, "Macro names and steps as JSON"),
+    route("/api/macros/run", POST, "Queue the macro in ?name="),
+    route("/api/loglevel", FORM, "Log level; POST level= changes it until reboot"),
+    route("/api/dnscache", GET, "DNS cache entries and hit counts"),
+    route("/api/dnscache/flush", POST, "Drop every DNS cache entry"),
+    route("/api/response", GET, "Body of the last fetch as received, with its Content-Type"),
+    route("/api/response/meta", GET, "Status line, headers and sizes of the last fetch as JSON"),
+    route("/api/response/<n>", GET, "Archived body <n> from the fetch history; URL, status and size in X- headers"),
+    route("/api/transcripts", GET, "The last 3 boot-probe and fetch transcripts as JSON"),
+    route("/api/transcripts/<n>", GET, "Transcript <n> as a JSON download"),
+    route(
+        "/relay",
+        GET_ONLY,
+        "Fetch the http:// URL in ?url= over cellular and stream the body back with its status and Content-Type",
+    ),
+    route("/capture.bin", GET, "Timestamped UART capture"),
+    route("/api/capture", GET, "Capture state as JSON"),
+    route("/api/capture/start", POST, "Start a UART capture"),
+    route("/api/capture/stop", POST, "Stop the UART capture"),
+    route("/config", FORM, "Settings page; POST saves the form"),
+    #[cfg(feature = "proxy")]
+    route("/config/forwards", FORM, "Port forwarding rules; POST saves them"),
+    #[cfg(feature = "proxy")]
+    route("/proxy.pac", GET, "Proxy auto-config through the forward in pac.forward; DIRECT when unset"),
+    route("/config/triggers", FORM, "GPIO input triggers and recent occurrences; POST saves them"),
+    route("/api/config/export", GET, "Settings as JSON; ?redact=1 hides secrets"),
+    route("/api/config/import", POST, "Replace settings with an exported document"),
+    route("/api/config/factory-reset", POST, "Erase settings and reboot"),
+    route("/api/reboot", POST, "Shut the modem down, streaming each step as text, then reboot"),
+    route("/api/sim/pin", POST, "Unlock the SIM with pin=, optionally storing it"),
+    route("/api/sim/forget", POST, "Erase the stored SIM PIN"),
+    route("/api/fetch/history", GET, "Recent fetches as JSON; ?request=<id> for the one an X-Request-Id queued"),
+    route("/api/fetch/cancel", POST, "Cancel the queued or running fetch"),
+    route("/api/modem/allow-roaming", POST, "Allow cellular data while roaming until reboot"),
+    route("/api/identify", POST, "Blink the LED fast for 30 s to find this box"),
+    route("/api/led", POST, "Override the LED with mode=auto|on|off until reboot"),
+    route("/api/uart1/write", POST, "Write data= to the UART1 debug port"),
+    route("/recovery", GET, "Recovery page (recovery mode only)"),
+    route("/api/recovery/factory-reset", POST, "Erase settings and reboot (recovery mode only)"),
+    route("/api/recovery/reboot", POST, "Reboot (recovery mode only)"),
+];
+
+pub fn find(path: &str) -> Option<&'static Route> {
+    ROUTES.iter().find(|r| r.path == path)
+}
+
+// Methods a path answers; a path not listed takes GET and HEAD, then gets 404
+pub fn allowed_methods(path: &str) -> &'static str {
+    find(path).map_or(GET, |r| r.methods)
+}
+
+// Whether `method` on `path` is served; otherwise it gets 405
+pub fn allows(path: &str, method: &str) -> bool {
+    allowed_methods(path).split(", ").any(|m| m == method)
+}
+
+pub fn write_spec_json<const N: usize>(out: &mut heapless::String<N>) {
+    let _ = out.push_str("{\"version\":");
+    json::push_str_value(out, version::CRATE_VERSION);
+    let _ = out.push_str(",\"auth\":\"none\",\"routes\":[");
+    for (i, route) in ROUTES.iter().enumerate() {
+        if i > 0 {
+            let _ = out.push(',');
+        }
+        let mut methods = heapless::String::<32>::new();
+        let _ = methods.push('[');
+        for (j, method) in route.methods.split(", ").enumerate() {
+            if j > 0 {
+                let _ = methods.push(',');
+            }
+            json::push_str_value(&mut methods, method);
+        }
+        let _ = methods.push(']');
+
+        let mut obj = json::Object::new(out);
+        obj.str("path", route.path)
+            .raw("methods", &methods)
+            .str("description", route.description);
+        obj.finish();
+    }
+    let _ = out.push_str("]}");
+}
+
+// GET /api/spec; the whole table, or a 503 rather than a document cut short
+pub fn format_spec() -> Result<heapless::String<10240>, http::BufferFull> {
+    let mut out = heapless::String::new();
+
+    let _ = out.push_str("HTTP/1.1 200 OK\r\n");
+    let _ = out.push_str("Content-Type: application/json\r\n");
+    let _ = out.push_str("Connection: close\r\n\r\n");
+    write_spec_json(&mut out);
+
+    http::finish_page(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Spec {
+        path: String,
+        methods: Vec<String>,
+        description: String,
+    }
+
+    // The route objects of the spec document, in order
+    fn spec_routes(doc: &str) -> Vec<Spec> {
+        let list = doc.split_once(",\"routes\":[").unwrap().1.strip_suffix("]}").unwrap();
+        let mut routes = Vec::new();
+        let mut rest = list;
+        while !rest.is_empty() {
+            // an object ends at the first '}' outside a string
+            let mut in_string = false;
+            let mut escaped = false;
+            let end = rest
+                .bytes()
+                .position(|b| {
+                    match b {
+                        _ if escaped => escaped = false,
+                        b'\\' => escaped = true,
+                        b'"' => in_string = !in_string,
+                        b'}' if !in_string => return true,
+                        _ => {}
+                    }
+                    false
+                })
+                .unwrap();
+            let object = &rest[..=end];
+            rest = rest[end + 1..].strip_prefix(',').unwrap_or(&rest[end + 1..]);
+
+            // walk reads no arrays: take the methods out first
+            let (before, after) = object.split_once(",\"methods\":[").unwrap();
+            let (methods, after) = after.split_once(']').unwrap();
+            let methods = methods.split(',').map(|m| m.trim_matches('"').to_string()).collect();
+            let (mut path, mut description) = (String::new(), String::new());
+            json::walk(&format!("{before}{after}"), |key, value| {
+                let json::Value::Str(raw) = value else { panic!("{key} is not a string") };
+                let text = json::unescape::<256>(raw).unwrap().to_string();
+                match key {
+                    "path" => path = text,
+                    "description" => description = text,
+                    _ => panic!("unexpected {key}"),
+                }
+            })
+            .unwrap_or_else(|e| panic!("bad route object at {}: {object}", e.0));
+            routes.push(Spec {
+                path,
+                methods,
+                description,
+            });
+        }
+        routes
+    }
+
+    #[test]
+    fn spec_and_table_list_the_same_routes() {
+        let page = format_spec().expect("the spec fits its page");
+        assert!(http::length_consistent(&page));
+        let doc = &page[http::find_header_end(page.as_bytes()).unwrap()..];
+        assert!(doc.starts_with("{\"version\":\"") && doc.ends_with("]}"));
+        assert!(doc.contains(",\"auth\":\"none\","));
+
+        let spec = spec_routes(doc);
+        assert_eq!(spec.len(), ROUTES.len());
+        for (listed, route) in spec.iter().zip(ROUTES) {
+            assert_eq!(listed.path, route.path);
+            assert_eq!(listed.methods.join(", "), route.methods, "{}", route.path);
+            assert_eq!(listed.description, route.description, "{}", route.path);
+        }
+        // and every path in the spec is one the 405 check knows
+        for listed in &spec {
+            assert_eq!(allowed_methods(&listed.path), listed.methods.join(", "));
+        }
+    }
+
+    #[test]
+    fn each_path_once_with_known_methods() {
+        for (i, route) in ROUTES.iter().enumerate() {
+            assert!(route.path.starts_with('/'), "{}", route.path);
+            assert!(!ROUTES[..i].iter().any(|r| r.path == route.path), "{} listed twice", route.path);
+            assert!(!route.description.is_empty());
+            for method in route.methods.split(", ") {
+                assert!(http::KNOWN_METHODS.contains(&method), "{} {method}", route.path);
+            }
+        }
+        assert_eq!(allowed_methods("/no/such/path"), "GET, HEAD");
+        assert_eq!(allowed_methods("/api/reboot"), "POST");
+    }
+
+    #[test]
+    fn relay_is_get_only() {
+        // HEAD 也会真的去蜂窝网取一次, 所以 405
+        assert_eq!(allowed_methods("/relay"), "GET");
+        assert!(allows("/relay", "GET"));
+        assert!(!allows("/relay", "HEAD"));
+        assert!(!allows("/relay", "POST"));
+        // other pages still answer HEAD
+        assert!(allows("/api/response", "HEAD"));
+        assert!(allows("/no/such/path", "HEAD"));
+        assert!(!allows("/api/reboot", "GET"));
+    }
+}
@@ -0,0 +1,261 @@
+// 串口接收字节的去向核对: 读到的每个字节要么交给了某处, 要么记为丢弃
+//
+// UART_RX_BYTES counts what was read from the modem UART, but a byte can
+// still be lost after that: an invalid UTF-8 line comes out empty, a line
+// longer than its buffer is cut, a full line buffer is emptied, and bytes
+// left in a reader when its operation ends are never looked at. The reader
+// pipeline therefore books every byte it takes off the UART once more:
+// either to the sink it went to or to the reason it was lost. Between
+// modem operations no reader holds anything, so the UART task checks
+// there that the books balance. A gap is counted as a discrepancy and the
+// books start level again. The modem log is a copy of everything read,
+// so it is not a sink here. The log has its own drop counters.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    // AT answers, URCs and data the fetch reads as lines, with their CR LF
+    Lines,
+    // binary data read by length (forwarded connections)
+    Payload,
+    // the /at console's answer
+    Console,
+    // the boot probe and the wait for RDY
+    Boot,
+}
+
+pub const SINKS: usize = 4;
+
+impl Sink {
+    pub const ALL: [Sink; SINKS] = [Sink::Lines, Sink::Payload, Sink::Console, Sink::Boot];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sink::Lines => "lines",
+            Sink::Payload => "payload",
+            Sink::Console => "console",
+            Sink::Boot => "boot",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Loss {
+    InvalidUtf8,
+    // the part of a line that did not fit the line buffer
+    LineTooLong,
+    // a reader buffer filled up without a line end, or payload without room
+    BufferFull,
+    // still in a reader when its operation ended
+    Unread,
+}
+
+pub const LOSSES: usize = 4;
+
+impl Loss {
+    pub const ALL: [Loss; LOSSES] = [Loss::InvalidUtf8, Loss::LineTooLong, Loss::BufferFull, Loss::Unread];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Loss::InvalidUtf8 => "invalid_utf8",
+            Loss::LineTooLong => "line_too_long",
+            Loss::BufferFull => "buffer_full",
+            Loss::Unread => "unread",
+        }
+    }
+}
+
+pub struct Audit {
+    read: u32,
+    delivered: [u32; SINKS],
+    lost: [u32; LOSSES],
+    // read minus everything booked, at the last level point
+    booked_gap: u32,
+    discrepancies: u32,
+    // the gap found by the last failed check; negative when more was booked than read
+    last_gap: i32,
+}
+
+impl Audit {
+    pub const fn new() -> Self {
+        Self {
+            read: 0,
+            delivered: [0; SINKS],
+            lost: [0; LOSSES],
+            booked_gap: 0,
+            discrepancies: 0,
+            last_gap: 0,
+        }
+    }
+
+    pub fn read(&mut self, bytes: usize) {
+        self.read = self.read.wrapping_add(bytes as u32);
+    }
+
+    pub fn deliver(&mut self, sink: Sink, bytes: usize) {
+        let count = &mut self.delivered[sink as usize];
+        *count = count.wrapping_add(bytes as u32);
+    }
+
+    pub fn lose(&mut self, loss: Loss, bytes: usize) {
+        if bytes > 0 {
+            let count = &mut self.lost[loss as usize];
+            *count = count.wrapping_add(bytes as u32);
+        }
+    }
+
+    // A line of `consumed` bytes, `text` of them between the line ends,
+    // came out as a string of `parsed` bytes of which `kept` fit the
+    // line buffer. A line with text that parsed to nothing was not UTF-8.
+    pub fn line(&mut self, consumed: usize, text: usize, parsed: usize, kept: usize) {
+        if text > 0 && parsed == 0 {
+            self.lose(Loss::InvalidUtf8, text);
+            self.deliver(Sink::Lines, consumed - text);
+        } else {
+            self.lose(Loss::LineTooLong, parsed - kept);
+            self.deliver(Sink::Lines, consumed - (parsed - kept));
+        }
+    }
+
+    fn booked(&self) -> u32 {
+        self.delivered.iter().chain(&self.lost).fold(0u32, |sum, &n| sum.wrapping_add(n))
+    }
+
+    // Where no reader holds bytes. Some(gap) when the books do not balance;
+    // the gap is then taken as the new level
+    pub fn check(&mut self) -> Option<i32> {
+        let gap = self.read.wrapping_sub(self.booked());
+        if gap == self.booked_gap {
+            return None;
+        }
+        self.last_gap = gap.wrapping_sub(self.booked_gap) as i32;
+        self.booked_gap = gap;
+        self.discrepancies = self.discrepancies.saturating_add(1);
+        Some(self.last_gap)
+    }
+
+    pub fn delivered(&self, sink: Sink) -> u32 {
+        self.delivered[sink as usize]
+    }
+
+    pub fn lost(&self, loss: Loss) -> u32 {
+        self.lost[loss as usize]
+    }
+
+    pub fn discrepancies(&self) -> u32 {
+        self.discrepancies
+    }
+
+    pub fn last_gap(&self) -> i32 {
+        self.last_gap
+    }
+}
+
+// Bytes of a line between its CR/LF ends
+pub fn text_len(raw: &[u8]) -> usize {
+    let is_end = |b: &u8| *b == b'\r' || *b == b'\n';
+    let start = raw.iter().position(|b| !is_end(b)).unwrap_or(raw.len());
+    let end = raw.iter().rposition(|b| !is_end(b)).map_or(start, |i| i + 1);
+    end - start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::at_response;
+
+    // The line reader's bookkeeping: one UART read split into lines that
+    // go to a line buffer of N bytes, then the payload read by length and
+    // whatever the operation left behind
+    fn read_lines<const N: usize>(audit: &mut Audit, read: &[u8], payload: usize) -> Vec<String> {
+        audit.read(read.len());
+        let mut pending = read;
+        let mut lines = Vec::new();
+        while let Some((text, consumed)) = at_response::next_line(pending) {
+            let mut line = heapless::String::<N>::new();
+            for c in text.chars() {
+                if line.push(c).is_err() {
+                    break;
+                }
+            }
+            audit.line(consumed, text_len(&pending[..consumed]), text.len(), line.len());
+            lines.push(line.to_string());
+            pending = &pending[consumed..];
+            if line == "CONNECT" {
+                let take = payload.min(pending.len());
+                audit.deliver(Sink::Payload, take);
+                pending = &pending[take..];
+            }
+        }
+        audit.lose(Loss::Unread, pending.len());
+        lines
+    }
+
+    #[test]
+    fn every_byte_of_one_read_is_booked_once() {
+        let mut audit = Audit::new();
+        let read = b"\r\nOK\r\n+QIURC: \"recv\",0\r\n\xff\xfe\r\n\
+            a line much longer than the buffer\r\nCONNECT\r\nHTTP/1.1 200\r\nOK\r\n+CSQ";
+        let lines = read_lines::<16>(&mut audit, read, 14);
+        assert_eq!(lines, ["", "OK", "+QIURC: \"recv\",0", "", "a line much long", "CONNECT", "OK"]);
+
+        assert_eq!(audit.lost(Loss::InvalidUtf8), 2);
+        assert_eq!(audit.lost(Loss::LineTooLong), 34 - 16);
+        assert_eq!(audit.delivered(Sink::Payload), 14);
+        assert_eq!(audit.lost(Loss::Unread), 4);
+        assert_eq!(audit.lost(Loss::BufferFull), 0);
+        let lines = read.len() - 2 - 18 - 14 - 4;
+        assert_eq!(audit.delivered(Sink::Lines) as usize, lines);
+        assert_eq!(audit.check(), None);
+        assert_eq!(audit.discrepancies(), 0);
+    }
+
+    // Requests one after another keep the books level
+    #[test]
+    fn books_balance_across_reads() {
+        let mut audit = Audit::new();
+        for _ in 0..3 {
+            read_lines::<64>(&mut audit, b"AT+QISEND=0,5\r\r\n> ", 0);
+            read_lines::<64>(&mut audit, b"SEND OK\r\n\r\n+QIURC: \"closed\",0\r\n", 0);
+            assert_eq!(audit.check(), None);
+        }
+        assert_eq!(audit.lost(Loss::Unread), 3 * 2);
+        assert_eq!(audit.delivered(Sink::Lines), 3 * (15 + 32));
+    }
+
+    #[test]
+    fn a_gap_is_counted_once_and_becomes_the_level() {
+        let mut audit = Audit::new();
+        audit.read(100);
+        audit.deliver(Sink::Console, 90);
+        assert_eq!(audit.check(), Some(10));
+        assert_eq!((audit.discrepancies(), audit.last_gap()), (1, 10));
+        // the same 10 bytes are not counted again
+        assert_eq!(audit.check(), None);
+        audit.read(20);
+        audit.deliver(Sink::Boot, 20);
+        assert_eq!(audit.check(), None);
+
+        // more booked than read
+        audit.deliver(Sink::Lines, 5);
+        assert_eq!(audit.check(), Some(-5));
+        assert_eq!((audit.discrepancies(), audit.last_gap()), (2, -5));
+    }
+
+    #[test]
+    fn counters_wrap_without_a_false_gap() {
+        let mut audit = Audit::new();
+        audit.read(u32::MAX as usize);
+        audit.deliver(Sink::Payload, u32::MAX as usize);
+        audit.read(10);
+        audit.deliver(Sink::Payload, 10);
+        assert_eq!(audit.check(), None);
+    }
+
+    #[test]
+    fn text_len_leaves_out_line_ends() {
+        assert_eq!(text_len(b"\r\nOK\r\n"), 2);
+        assert_eq!(text_len(b"\r\n"), 0);
+        assert_eq!(text_len(b"a\rb\n"), 3);
+        assert_eq!(text_len(b""), 0);
+    }
+}
@@ -0,0 +1,298 @@
+// 按时段限制后台蜂窝活动 (夜间免费流量之类)
+//
+// Up to MAX_WINDOWS daily windows, each a start and end time of day (HH:MM,
+// the local time the network reports through AT+CCLK) and the background
+// features it applies to. A feature named by at least one window runs only
+// inside one of its windows; features no window names are not restricted.
+// A window whose end is before its start spans midnight. Until the clock
+// has been set, `unsynced_open` decides whether gated features run anyway
+// or wait. Fetches started from the pages are never gated.
+
+pub const MAX_WINDOWS: usize = 2;
+// Config group of each window
+pub const NAMES: [&str; MAX_WINDOWS] = ["schedule1", "schedule2"];
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Ping,
+    KeepWarm,
+    Webhooks,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 3] = [Feature::Ping, Feature::KeepWarm, Feature::Webhooks];
+
+    // config scheduleN.features bit
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::Ping => "ping",
+            Feature::KeepWarm => "keep_warm",
+            Feature::Webhooks => "webhooks",
+        }
+    }
+}
+
+pub const ALL_FEATURES: u32 = (1 << Feature::ALL.len()) - 1;
+
+#[derive(Clone)]
+pub struct Window {
+    // "HH:MM", empty = not set
+    pub start: heapless::String<5>,
+    pub end: heapless::String<5>,
+    // Feature bits, 0 = window off
+    pub features: u32,
+}
+
+impl Window {
+    pub const OFF: Window = Window {
+        start: heapless::String::new(),
+        end: heapless::String::new(),
+        features: 0,
+    };
+
+    // (start, end) in minutes of the day when the window is in use
+    fn span(&self) -> Option<(u32, u32)> {
+        if self.features == 0 {
+            return None;
+        }
+        Some((parse_hhmm(&self.start)?, parse_hhmm(&self.end)?))
+    }
+
+    pub fn complete(&self) -> bool {
+        self.span().is_some_and(|(start, end)| start != end)
+    }
+}
+
+// "23:30" -> minutes since midnight
+pub fn parse_hhmm(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+pub fn write_hhmm<W: core::fmt::Write>(out: &mut W, minute: u32) {
+    let _ = core::write!(out, "{:02}:{:02}", minute / 60, minute % 60);
+}
+
+// "schedule2.start" -> (1, "start")
+pub fn parse_path(path: &str) -> Option<(usize, &str)> {
+    let (group, key) = path.split_once('.')?;
+    Some((NAMES.iter().position(|&name| name == group)?, key))
+}
+
+// Local time of day, from the network clock at the last sync and the
+// monotonic clock since
+#[derive(Clone, Copy)]
+pub struct Clock {
+    day_ms_at_sync: u64,
+    synced_at_ms: u64,
+}
+
+impl Clock {
+    pub fn day_ms(&self, now_ms: u64) -> u64 {
+        (self.day_ms_at_sync + now_ms.saturating_sub(self.synced_at_ms)) % DAY_MS
+    }
+}
+
+// +CCLK: "yy/MM/dd,hh:mm:ss±zz" (local time, zone in quarter hours). A
+// module that never heard the network time reports its epoch (year 70 or
+// 80), which is not taken as a time.
+pub fn parse_cclk(line: &str, now_ms: u64) -> Option<Clock> {
+    let value = line.strip_prefix("+CCLK:")?.trim().trim_matches('"');
+    let (date, time) = value.split_once(',')?;
+    let year = date.split('/').next()?.parse::<u32>().ok()?;
+    if !(20..70).contains(&year) {
+        return None;
+    }
+    let mut fields = time.get(..8)?.split(':').map(|f| f.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (fields.next()??, fields.next()??, fields.next()??);
+    if hours >= 24 || minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    Some(Clock {
+        day_ms_at_sync: ((hours * 60 + minutes) * 60 + seconds) * 1000,
+        synced_at_ms: now_ms,
+    })
+}
+
+// `day_ms` falls in start..end (minutes of the day; end before start spans midnight)
+pub fn inside(start: u32, end: u32, day_ms: u64) -> bool {
+    let (start, end) = (start as u64 * 60_000, end as u64 * 60_000);
+    if start < end {
+        (start..end).contains(&day_ms)
+    } else {
+        day_ms >= start || day_ms < end
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Gate {
+    Open,
+    // the next window starts this many ms from now
+    Closed { opens_in_ms: u64 },
+    // gated, but the clock is not set and unsynced_open is off
+    NoClock,
+}
+
+// Windows in use for `feature`, as (start, end) minutes of the day
+fn spans(windows: &[Window], feature: Feature) -> impl Iterator<Item = (u32, u32)> + '_ {
+    windows
+        .iter()
+        .filter(move |w| w.features & feature.bit() != 0)
+        .filter_map(Window::span)
+        .filter(|(start, end)| start != end)
+}
+
+// Some window names the feature, so it only runs inside one
+pub fn restricts(windows: &[Window], feature: Feature) -> bool {
+    spans(windows, feature).next().is_some()
+}
+
+pub fn gate(windows: &[Window], feature: Feature, clock: Option<Clock>, unsynced_open: bool, now_ms: u64) -> Gate {
+    if !restricts(windows, feature) {
+        return Gate::Open;
+    }
+    let Some(clock) = clock else {
+        return if unsynced_open { Gate::Open } else { Gate::NoClock };
+    };
+
+    let now = clock.day_ms(now_ms);
+    let mut opens_in_ms = DAY_MS;
+    for (start, end) in spans(windows, feature) {
+        if inside(start, end, now) {
+            return Gate::Open;
+        }
+        let start = start as u64 * 60_000;
+        opens_in_ms = opens_in_ms.min((start + DAY_MS - now) % DAY_MS);
+    }
+    Gate::Closed { opens_in_ms }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60_000;
+    const HOUR: u64 = 60 * MINUTE;
+
+    fn window(start: &str, end: &str, features: u32) -> Window {
+        Window {
+            start: start.try_into().unwrap(),
+            end: end.try_into().unwrap(),
+            features,
+        }
+    }
+
+    // A clock synced at boot to `hour`:`minute`
+    fn clock_at(hour: u64, minute: u64) -> Clock {
+        Clock { day_ms_at_sync: hour * HOUR + minute * MINUTE, synced_at_ms: 0 }
+    }
+
+    #[test]
+    fn times_of_day() {
+        assert_eq!(parse_hhmm("23:30"), Some(1410));
+        assert_eq!(parse_hhmm("00:00"), Some(0));
+        for bad in ["24:00", "12:60", "9:30", "09:3", "0930", "ab:cd", ""] {
+            assert_eq!(parse_hhmm(bad), None, "{bad:?}");
+        }
+        let mut out = heapless::String::<5>::new();
+        write_hhmm(&mut out, 1410);
+        assert_eq!(out, "23:30");
+        out.clear();
+        write_hhmm(&mut out, 5);
+        assert_eq!(out, "00:05");
+    }
+
+    #[test]
+    fn config_paths() {
+        assert_eq!(parse_path("schedule1.features"), Some((0, "features")));
+        assert_eq!(parse_path("schedule2.start"), Some((1, "start")));
+        assert_eq!(parse_path("schedule3.start"), None);
+        assert_eq!(parse_path("schedule1"), None);
+    }
+
+    #[test]
+    fn windows_in_use() {
+        assert!(window("01:00", "05:00", ALL_FEATURES).complete());
+        assert!(window("23:00", "01:00", Feature::Ping.bit()).complete());
+        // 没选功能, 时间相同, 或时间无效: 不生效
+        assert!(!window("01:00", "05:00", 0).complete());
+        assert!(!window("01:00", "01:00", ALL_FEATURES).complete());
+        assert!(!window("01:00", "", ALL_FEATURES).complete());
+        assert!(!Window::OFF.complete());
+    }
+
+    #[test]
+    fn network_clock() {
+        let clock = parse_cclk("+CCLK: \"24/05/01,23:59:30+32\"", 1_000).unwrap();
+        assert_eq!(clock.day_ms(1_000), 23 * HOUR + 59 * MINUTE + 30_000);
+        // wraps at midnight
+        assert_eq!(clock.day_ms(31_000), 0);
+        assert_eq!(clock.day_ms(41_000), 10_000);
+        // the module's epoch is not a time
+        assert!(parse_cclk("+CCLK: \"70/01/01,00:00:05+00\"", 0).is_none());
+        assert!(parse_cclk("+CCLK: \"80/01/06,00:00:05+00\"", 0).is_none());
+        assert!(parse_cclk("+CCLK: \"24/05/01,24:00:00+00\"", 0).is_none());
+        assert!(parse_cclk("+CCLK: \"24/05/01,12:00\"", 0).is_none());
+        assert!(parse_cclk("+CSQ: 20,99", 0).is_none());
+    }
+
+    #[test]
+    fn inside_windows() {
+        // 09:00-17:00, end excluded
+        assert!(!inside(540, 1020, 9 * HOUR - 1));
+        assert!(inside(540, 1020, 9 * HOUR));
+        assert!(!inside(540, 1020, 17 * HOUR));
+        // 22:00-06:00 spans midnight
+        assert!(inside(1320, 360, 23 * HOUR));
+        assert!(inside(1320, 360, 0));
+        assert!(inside(1320, 360, 6 * HOUR - 1));
+        assert!(!inside(1320, 360, 12 * HOUR));
+    }
+
+    #[test]
+    fn unnamed_features_are_not_gated() {
+        let windows = [window("01:00", "05:00", Feature::Ping.bit()), Window::OFF];
+        assert!(restricts(&windows, Feature::Ping));
+        assert!(!restricts(&windows, Feature::Webhooks));
+        assert_eq!(gate(&windows, Feature::Webhooks, None, false, 0), Gate::Open);
+        // a window with equal times names nothing
+        let windows = [window("01:00", "01:00", ALL_FEATURES), Window::OFF];
+        assert_eq!(gate(&windows, Feature::Ping, Some(clock_at(12, 0)), false, 0), Gate::Open);
+    }
+
+    #[test]
+    fn no_clock_yet() {
+        let windows = [window("01:00", "05:00", Feature::Ping.bit()), Window::OFF];
+        assert_eq!(gate(&windows, Feature::Ping, None, false, 0), Gate::NoClock);
+        assert_eq!(gate(&windows, Feature::Ping, None, true, 0), Gate::Open);
+    }
+
+    #[test]
+    fn closed_until_the_nearest_window() {
+        let windows = [
+            window("22:00", "06:00", Feature::KeepWarm.bit()),
+            window("12:00", "13:00", Feature::KeepWarm.bit() | Feature::Ping.bit()),
+        ];
+        let keep_warm = |clock| gate(&windows, Feature::KeepWarm, Some(clock), false, 0);
+        assert_eq!(keep_warm(clock_at(23, 0)), Gate::Open);
+        assert_eq!(keep_warm(clock_at(12, 30)), Gate::Open);
+        assert_eq!(keep_warm(clock_at(10, 0)), Gate::Closed { opens_in_ms: 2 * HOUR });
+        assert_eq!(keep_warm(clock_at(13, 0)), Gate::Closed { opens_in_ms: 9 * HOUR });
+        // Ping only has the noon window: tomorrow's
+        assert_eq!(
+            gate(&windows, Feature::Ping, Some(clock_at(13, 0)), false, 0),
+            Gate::Closed { opens_in_ms: 23 * HOUR }
+        );
+        // the clock keeps running after the sync
+        assert_eq!(gate(&windows, Feature::Ping, Some(clock_at(11, 0)), false, HOUR), Gate::Open);
+    }
+}
@@ -0,0 +1,145 @@
+// 转发流量整形: 每个方向一个令牌桶
+//
+// The UART to the module carries forwarded payload and every AT exchange.
+// Unshaped, one bulk transfer keeps the modem busy and CSQ polls, pings
+// and keep-warm lookups wait behind it. Forwarded bytes take tokens here
+// before they are sent or read; AT commands never do. Rates are bytes per
+// second, by default AUTO_PERCENT of what the UART carries at its baud and
+// framing. A bucket holds at most BURST_MS worth of tokens.
+
+pub const AUTO_PERCENT: u32 = 70;
+const BURST_MS: u64 = 500;
+// utilisation is measured over windows at least this long
+const WINDOW_MS: u64 = 2_000;
+
+// Start bit, data bits, parity bit and stop bits per byte on the wire
+pub fn auto_rate(baud: u32, data_bits: u32, parity: bool, stop_bits: u32) -> u32 {
+    let bits = 1 + data_bits + parity as u32 + stop_bits;
+    baud / bits * AUTO_PERCENT / 100
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    // AP client -> remote
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy)]
+pub struct Bucket {
+    // thousandths of a byte, so slow rates still refill every millisecond
+    tokens: u64,
+    last_ms: u64,
+    window_start_ms: u64,
+    window_bytes: u32,
+    // share of the rate used in the last window
+    utilisation_pct: u32,
+}
+
+impl Bucket {
+    pub const fn new() -> Self {
+        Self {
+            tokens: 0,
+            last_ms: 0,
+            window_start_ms: 0,
+            window_bytes: 0,
+            utilisation_pct: 0,
+        }
+    }
+
+    fn refill(&mut self, rate: u32, now_ms: u64) {
+        let elapsed = now_ms.saturating_sub(self.last_ms);
+        self.last_ms = now_ms;
+        self.tokens = (self.tokens + elapsed * rate as u64).min(rate as u64 * BURST_MS);
+
+        let window = now_ms.saturating_sub(self.window_start_ms);
+        if window >= WINDOW_MS {
+            let capacity = (rate as u64 * window / 1000).max(1);
+            self.utilisation_pct = (self.window_bytes as u64 * 100 / capacity).min(100) as u32;
+            self.window_start_ms = now_ms;
+            self.window_bytes = 0;
+        }
+    }
+
+    // Bytes that may move now, at most `want`
+    pub fn allowance(&mut self, rate: u32, want: usize, now_ms: u64) -> usize {
+        self.refill(rate, now_ms);
+        ((self.tokens / 1000) as usize).min(want)
+    }
+
+    pub fn spend(&mut self, bytes: usize) {
+        self.tokens = self.tokens.saturating_sub(bytes as u64 * 1000);
+        self.window_bytes += bytes as u32;
+    }
+
+    pub fn utilisation(&mut self, rate: u32, now_ms: u64) -> u32 {
+        self.refill(rate, now_ms);
+        self.utilisation_pct
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_rate_counts_framing() {
+        // 8N1: ten bits a byte
+        assert_eq!(auto_rate(115_200, 8, false, 1), 8_064);
+        // 8E2: twelve
+        assert_eq!(auto_rate(115_200, 8, true, 2), 6_720);
+        assert_eq!(auto_rate(921_600, 8, false, 1), 64_512);
+    }
+
+    #[test]
+    fn starts_empty_and_refills_at_the_rate() {
+        let mut bucket = Bucket::new();
+        assert_eq!(bucket.allowance(1_000, 100, 0), 0);
+        assert_eq!(bucket.allowance(1_000, 100, 50), 50);
+        // never more than asked for
+        assert_eq!(bucket.allowance(1_000, 20, 50), 20);
+        bucket.spend(20);
+        assert_eq!(bucket.allowance(1_000, 100, 50), 30);
+        bucket.spend(30);
+        assert_eq!(bucket.allowance(1_000, 100, 50), 0);
+    }
+
+    #[test]
+    fn slow_rates_keep_fractions() {
+        // 10 B/s: 每毫秒 0.01 字节, 攒够 100 ms 才有一个字节
+        let mut bucket = Bucket::new();
+        assert_eq!(bucket.allowance(10, 100, 99), 0);
+        assert_eq!(bucket.allowance(10, 100, 100), 1);
+        // half a second's worth at most
+        assert_eq!(bucket.allowance(10, 100, 1_000), 5);
+    }
+
+    #[test]
+    fn idle_time_is_capped_at_the_burst() {
+        let mut bucket = Bucket::new();
+        assert_eq!(bucket.allowance(1_000, 10_000, 60_000), 500);
+        bucket.spend(500);
+        assert_eq!(bucket.allowance(1_000, 10_000, 60_000), 0);
+        // overspending does not go below empty
+        bucket.spend(100);
+        assert_eq!(bucket.allowance(1_000, 10_000, 60_001), 1);
+    }
+
+    #[test]
+    fn utilisation_over_whole_windows() {
+        let mut bucket = Bucket::new();
+        let granted = bucket.allowance(1_000, 2_000, 1_000);
+        assert_eq!(granted, 500);
+        bucket.spend(granted);
+        let granted = bucket.allowance(1_000, 2_000, 1_500);
+        assert_eq!(granted, 500);
+        bucket.spend(granted);
+        // 1000 bytes in two seconds at 1000 B/s
+        assert_eq!(bucket.utilisation(1_000, 2_000), 50);
+        // the window just started: the last figure stands
+        assert_eq!(bucket.utilisation(1_000, 3_999), 50);
+        assert_eq!(bucket.utilisation(1_000, 4_000), 0);
+        bucket.spend(10_000);
+        assert_eq!(bucket.utilisation(1_000, 6_000), 100);
+    }
+}
@@ -0,0 +1,61 @@
+// 重启前关闭模块
+//
+// Every reboot first takes the module down in order, so it does not come
+// back with a PDP context and sockets it still believes are open: cancel
+// the fetch, close every connect ID the firmware uses, deactivate the
+// context, then power off (AT+QPOWD=1) and wait for "POWERED DOWN". Each
+// step has its own timeout and the whole sequence TOTAL_TIMEOUT; after
+// that the reset happens anyway. Recovery mode never starts the modem and
+// skips all of it.
+
+use embassy_time::Duration;
+
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEACTIVATE_TIMEOUT: Duration = Duration::from_secs(40);
+pub const POWER_DOWN_TIMEOUT: Duration = Duration::from_secs(15);
+// includes waiting for a running modem operation to finish
+pub const TOTAL_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    CancelFetch,
+    CloseConnections,
+    Deactivate,
+    PowerDown,
+}
+
+impl Step {
+    pub const ALL: [Step; 4] = [Step::CancelFetch, Step::CloseConnections, Step::Deactivate, Step::PowerDown];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Step::CancelFetch => "cancel fetch",
+            Step::CloseConnections => "close connections",
+            Step::Deactivate => "deactivate PDP context",
+            Step::PowerDown => "power down",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    Failed,
+    TimedOut,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Done => "done",
+            Outcome::Failed => "failed",
+            Outcome::TimedOut => "timed out",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Report {
+    pub step: Step,
+    pub outcome: Outcome,
+}
@@ -0,0 +1,138 @@
+// SIM 卡状态和 PIN/PUK 解锁
+//
+// AT+CPIN? tells whether the SIM is usable. A locked SIM stops the fetch
+// sequence until the PIN (or PUK and a new PIN) is entered on the status
+// page; the remaining attempts come from the module's PIN counter query.
+// A PIN stored in the config is submitted once per boot, see `try_stored_pin`.
+
+use core::fmt::Write;
+
+use crate::at;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SimState {
+    Unknown,
+    Ready,
+    PinRequired,
+    PukRequired,
+    NotInserted,
+    // PIN2, PH-SIM PIN and other locks this firmware does not handle
+    Other,
+}
+
+impl SimState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SimState::Unknown => "unknown",
+            SimState::Ready => "ready",
+            SimState::PinRequired => "pin_required",
+            SimState::PukRequired => "puk_required",
+            SimState::NotInserted => "not_inserted",
+            SimState::Other => "locked",
+        }
+    }
+
+    pub fn locked(self) -> bool {
+        matches!(self, SimState::PinRequired | SimState::PukRequired)
+    }
+}
+
+// +CPIN: <code>, or the +CME ERROR a missing SIM answers with
+pub fn parse_cpin(line: &str) -> Option<SimState> {
+    if let Some(code) = line.strip_prefix("+CPIN:") {
+        return Some(match code.trim() {
+            "READY" => SimState::Ready,
+            "SIM PIN" => SimState::PinRequired,
+            "SIM PUK" => SimState::PukRequired,
+            _ => SimState::Other,
+        });
+    }
+    match line.strip_prefix("+CME ERROR:")?.trim() {
+        "10" | "SIM not inserted" => Some(SimState::NotInserted),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SimStatus {
+    pub state: SimState,
+    pub pin_left: Option<u8>,
+    pub puk_left: Option<u8>,
+    // the last PIN/PUK entered was rejected; nothing is re-sent
+    // automatically until someone enters one again
+    pub last_unlock_failed: bool,
+}
+
+impl SimStatus {
+    pub const fn new() -> Self {
+        Self {
+            state: SimState::Unknown,
+            pin_left: None,
+            puk_left: None,
+            last_unlock_failed: false,
+        }
+    }
+}
+
+pub type Code = heapless::String<8>;
+
+// 网页提交的解锁请求; puk 为空表示只输入 PIN
+pub struct Unlock {
+    pub puk: Code,
+    pub pin: Code,
+    // keep the PIN in the config once it is accepted
+    pub remember: bool,
+}
+
+impl Unlock {
+    // AT+CPIN with the PIN, or the PUK and the new PIN; None when a code is
+    // not digits only
+    pub fn command(&self) -> Option<heapless::String<48>> {
+        let code = |value: &str| value.is_empty() || at::arg(value, at::Kind::Pin).is_ok();
+        if !code(&self.pin) || !code(&self.puk) {
+            return None;
+        }
+        let mut command = heapless::String::new();
+        let _ = if self.puk.is_empty() {
+            write!(command, "AT+CPIN=\"{}\"\r\n", self.pin)
+        } else {
+            write!(command, "AT+CPIN=\"{}\",\"{}\"\r\n", self.puk, self.pin)
+        };
+        Some(command)
+    }
+}
+
+pub fn valid_pin(pin: &str) -> bool {
+    (4..=8).contains(&pin.len()) && pin.bytes().all(|b| b.is_ascii_digit())
+}
+
+pub fn valid_puk(puk: &str) -> bool {
+    puk.len() == 8 && puk.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unlock(puk: &str, pin: &str) -> Unlock {
+        Unlock {
+            puk: Code::try_from(puk).unwrap(),
+            pin: Code::try_from(pin).unwrap(),
+            remember: false,
+        }
+    }
+
+    #[test]
+    fn unlock_commands() {
+        assert_eq!(unlock("", "1234").command().unwrap(), "AT+CPIN=\"1234\"\r\n");
+        assert_eq!(unlock("12345678", "0000").command().unwrap(), "AT+CPIN=\"12345678\",\"0000\"\r\n");
+    }
+
+    #[test]
+    fn codes_cannot_inject() {
+        for bad in ["1\r\nAT", "12\n34", "12\"34", "12<34", "12,34", " 1234"] {
+            assert!(unlock("", bad).command().is_none(), "{bad:?}");
+            assert!(unlock(bad, "1234").command().is_none(), "{bad:?}");
+        }
+    }
+}
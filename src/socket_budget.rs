@@ -0,0 +1,154 @@
+// 套接字预算 (embassy-net 的套接字表大小固定)
+//
+// The stack has room for STACK_SOCKETS sockets. Before its tasks are
+// spawned each subsystem claims the most sockets it can hold open at once;
+// a claim that does not fit is refused, that subsystem stays off and the
+// device reports itself degraded instead of panicking in TcpSocket::new
+// later. While running, every open socket is counted against its subsystem
+// (`open`), so /net shows claims next to use. Claims are only made from
+// main() during boot.
+
+use embassy_time::Duration;
+use portable_atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+
+// StackResources size: the claims below plus room for later subsystems
+pub const STACK_SOCKETS: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    // workers on each HTTP port, each one socket at a time (also the 503 reply)
+    Http,
+    TestServices,
+    // a listener per rule and one more to refuse clients while it is busy
+    #[cfg(feature = "proxy")]
+    Forwards,
+}
+
+impl Subsystem {
+    pub const ALL: &[Subsystem] = &[
+        Subsystem::Http,
+        Subsystem::TestServices,
+        #[cfg(feature = "proxy")]
+        Subsystem::Forwards,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Subsystem::Http => "http",
+            Subsystem::TestServices => "test services",
+            #[cfg(feature = "proxy")]
+            Subsystem::Forwards => "forwards",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Exhausted {
+    pub wanted: u8,
+    pub free: u8,
+}
+
+pub struct Budget {
+    claimed: [AtomicU8; Subsystem::ALL.len()],
+    in_use: [AtomicU8; Subsystem::ALL.len()],
+    degraded: AtomicBool,
+    accept_failures: AtomicU32,
+}
+
+impl Budget {
+    pub const fn new() -> Self {
+        Self {
+            claimed: [const { AtomicU8::new(0) }; Subsystem::ALL.len()],
+            in_use: [const { AtomicU8::new(0) }; Subsystem::ALL.len()],
+            degraded: AtomicBool::new(false),
+            accept_failures: AtomicU32::new(0),
+        }
+    }
+
+    pub fn claim(&self, subsystem: Subsystem, count: usize) -> Result<(), Exhausted> {
+        let free = STACK_SOCKETS - self.total_claimed();
+        if count > free {
+            self.degraded.store(true, Ordering::Relaxed);
+            return Err(Exhausted {
+                wanted: count.min(u8::MAX as usize) as u8,
+                free: free as u8,
+            });
+        }
+        self.claimed[subsystem as usize].fetch_add(count as u8, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn claimed(&self, subsystem: Subsystem) -> u8 {
+        self.claimed[subsystem as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn in_use(&self, subsystem: Subsystem) -> u8 {
+        self.in_use[subsystem as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total_claimed(&self) -> usize {
+        Subsystem::ALL.iter().map(|&s| self.claimed(s) as usize).sum()
+    }
+
+    pub fn total_in_use(&self) -> usize {
+        Subsystem::ALL.iter().map(|&s| self.in_use(s) as usize).sum()
+    }
+
+    // A claim was refused and some subsystem is not running
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    pub fn accept_failed(&self) {
+        self.accept_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn accept_failures(&self) -> u32 {
+        self.accept_failures.load(Ordering::Relaxed)
+    }
+
+    // Count a socket until the returned guard is dropped
+    pub fn open(&'static self, subsystem: Subsystem) -> Open {
+        self.in_use[subsystem as usize].fetch_add(1, Ordering::Relaxed);
+        Open {
+            counter: &self.in_use[subsystem as usize],
+        }
+    }
+}
+
+pub struct Open {
+    counter: &'static AtomicU8,
+}
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+const BACKOFF_MIN_MS: u32 = 100;
+const BACKOFF_MAX_MS: u32 = 10_000;
+
+// Delay before the next accept after failures: 100 ms, doubling up to 10 s,
+// back to the start after an accept that worked
+pub struct Backoff {
+    delay_ms: u32,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self {
+            delay_ms: BACKOFF_MIN_MS,
+        }
+    }
+
+    pub fn failed(&mut self) -> Duration {
+        let delay = self.delay_ms;
+        self.delay_ms = (delay * 2).min(BACKOFF_MAX_MS);
+        Duration::from_millis(delay as u64)
+    }
+
+    pub fn reset(&mut self) {
+        self.delay_ms = BACKOFF_MIN_MS;
+    }
+}
@@ -0,0 +1,165 @@
+// 周期性 ping 的往返时间和首页上的小趋势图
+//
+// The ring keeps one u16 per ping, oldest first when iterated; a lost ping
+// is stored as `LOST`. `push_svg` draws the ring as an inline SVG: the
+// Y axis is scaled to the largest RTT shown, lost pings break the line
+// and are marked with a red bar.
+
+use core::fmt::Write as _;
+
+pub const LOST: u16 = u16::MAX;
+
+const STEP: usize = 4;
+const HEIGHT: u32 = 32;
+// 上下各留一点空白, 线条不贴边
+const PAD: u32 = 2;
+
+pub struct Ring<const N: usize> {
+    samples: [u16; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [0; N],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    // None: the ping was lost or timed out
+    pub fn push(&mut self, rtt_ms: Option<u32>) {
+        self.samples[self.next] = match rtt_ms {
+            Some(ms) => ms.min(LOST as u32 - 1) as u16,
+            None => LOST,
+        };
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let start = (self.next + N - self.len) % N;
+        (0..self.len).map(move |i| self.samples[(start + i) % N])
+    }
+
+    pub fn last(&self) -> Option<u16> {
+        self.iter().last()
+    }
+
+    // Largest RTT that was not lost
+    pub fn max(&self) -> Option<u16> {
+        self.iter().filter(|&s| s != LOST).max()
+    }
+}
+
+// Always as wide as a full ring so the graph does not stretch while it fills
+pub fn push_svg<const S: usize, const N: usize>(out: &mut heapless::String<S>, ring: &Ring<N>) {
+    let width = (N.max(2) - 1) * STEP;
+    let scale = ring.max().unwrap_or(1).max(1) as u32;
+    let y = |ms: u16| HEIGHT - PAD - ms as u32 * (HEIGHT - 2 * PAD) / scale;
+
+    let _ = core::write!(
+        out,
+        "<svg class='spark' width='{}' height='{}' viewBox='0 0 {} {}'>",
+        width,
+        HEIGHT,
+        width,
+        HEIGHT
+    );
+    let _ = core::write!(out, "<title>last {} pings, max {} ms</title>", ring.len(), scale);
+
+    let mut open = false;
+    for (i, sample) in ring.iter().enumerate() {
+        let x = i * STEP;
+        if sample == LOST {
+            if open {
+                let _ = out.push_str("'/>");
+                open = false;
+            }
+            let _ = core::write!(out, "<rect x='{}' y='0' width='2' height='{}' fill='#e53935'/>", x.saturating_sub(1), HEIGHT);
+            continue;
+        }
+        if open {
+            let _ = core::write!(out, " {},{}", x, y(sample));
+            continue;
+        }
+        // 起点写两次: 前后都丢包的单个点也画成一个圆点
+        let _ = out.push_str("<polyline fill='none' stroke='#1e88e5' stroke-width='1.5' stroke-linecap='round' points='");
+        let _ = core::write!(out, "{},{} {},{}", x, y(sample), x, y(sample));
+        open = true;
+    }
+    if open {
+        let _ = out.push_str("'/>");
+    }
+    let _ = out.push_str("</svg>");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn svg<const N: usize>(ring: &Ring<N>) -> String {
+        let mut out = heapless::String::<2048>::new();
+        push_svg(&mut out, ring);
+        out.to_string()
+    }
+
+    const LINE: &str = "<polyline fill='none' stroke='#1e88e5' stroke-width='1.5' stroke-linecap='round' points='";
+
+    fn gap(x: usize) -> String {
+        format!("<rect x='{x}' y='0' width='2' height='32' fill='#e53935'/>")
+    }
+
+    // 10, 20, lost, 40, lost, lost, 30 on a scale of 40 ms: y = 30 - ms * 28 / 40
+    #[test]
+    fn snapshot_with_gaps() {
+        let mut ring = Ring::<8>::new();
+        for rtt in [Some(10), Some(20), None, Some(40), None, None, Some(30)] {
+            ring.push(rtt);
+        }
+        let expected = [
+            "<svg class='spark' width='28' height='32' viewBox='0 0 28 32'>",
+            "<title>last 7 pings, max 40 ms</title>",
+            &format!("{LINE}0,23 0,23 4,16'/>"),
+            &gap(7),
+            &format!("{LINE}12,2 12,2'/>"),
+            &gap(15),
+            &gap(19),
+            &format!("{LINE}24,9 24,9'/>"),
+            "</svg>",
+        ]
+        .concat();
+        assert_eq!(svg(&ring), expected);
+    }
+
+    // Oldest first after the ring wrapped; an RTT too large for u16 is
+    // clamped and sets the scale
+    #[test]
+    fn snapshot_after_wrap() {
+        let mut ring = Ring::<4>::new();
+        assert_eq!(
+            svg(&ring),
+            "<svg class='spark' width='12' height='32' viewBox='0 0 12 32'><title>last 0 pings, max 1 ms</title></svg>"
+        );
+        for rtt in [Some(500), Some(100_000), Some(0), None, Some(5)] {
+            ring.push(rtt);
+        }
+        let expected = [
+            "<svg class='spark' width='12' height='32' viewBox='0 0 12 32'>",
+            "<title>last 4 pings, max 65534 ms</title>",
+            &format!("{LINE}0,2 0,2 4,30'/>"),
+            &gap(7),
+            &format!("{LINE}12,30 12,30'/>"),
+            "</svg>",
+        ]
+        .concat();
+        assert_eq!(svg(&ring), expected);
+        assert_eq!((ring.len(), ring.last(), ring.max()), (4, Some(5), Some(65534)));
+    }
+}
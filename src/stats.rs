@@ -0,0 +1,195 @@
+// 开机以来和累计的运行统计 (/stats)
+//
+// Two sets of the same numbers: since this boot, and over the unit's
+// lifetime. The hot paths only add to atomics in `Counters`. The
+// lifetime set is what flash held at boot plus what this boot has added.
+// It is written back at boot, every SAVE_EVERY_MS and before a reboot, so
+// a power cut loses at most one interval of increments. The stored
+// record is a fixed list of little-endian numbers. A record from an
+// older build with fewer stats reads the missing ones as 0. Temperatures
+// are kept in hundredths of a degree C.
+
+use core::fmt::Write as _;
+
+use portable_atomic::{AtomicI32, AtomicU64, Ordering};
+
+pub const SAVE_EVERY_MS: u64 = 60 * 60 * 1000;
+// the maximum temperature (i32), then one u64 per stat
+pub const ENCODED_LEN: usize = 4 + 8 * Stat::ALL.len();
+const NO_TEMPERATURE: i32 = i32::MIN;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Stat {
+    Boots,
+    WatchdogResets,
+    // the module announced a start (RDY), whatever caused it
+    ModemRestarts,
+    Fetches,
+    FetchFailures,
+    // payload through the module's sockets, both ways
+    CellularBytes,
+    HttpRequests,
+    // response bodies passed on by /relay, also in CellularBytes
+    RelayBytes,
+}
+
+impl Stat {
+    // The stored order: new stats go at the end
+    pub const ALL: [Stat; 8] = [
+        Stat::Boots,
+        Stat::WatchdogResets,
+        Stat::ModemRestarts,
+        Stat::Fetches,
+        Stat::FetchFailures,
+        Stat::CellularBytes,
+        Stat::HttpRequests,
+        Stat::RelayBytes,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stat::Boots => "boots",
+            Stat::WatchdogResets => "watchdog_resets",
+            Stat::ModemRestarts => "modem_restarts",
+            Stat::Fetches => "fetches",
+            Stat::FetchFailures => "fetch_failures",
+            Stat::CellularBytes => "cellular_bytes",
+            Stat::HttpRequests => "http_requests",
+            Stat::RelayBytes => "relay_bytes",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Stat::Boots => "Boots",
+            Stat::WatchdogResets => "Watchdog resets",
+            Stat::ModemRestarts => "Modem restarts",
+            Stat::Fetches => "Fetches",
+            Stat::FetchFailures => "Failed fetches",
+            Stat::CellularBytes => "Cellular bytes",
+            Stat::HttpRequests => "HTTP requests served",
+            Stat::RelayBytes => "Relayed download bytes",
+        }
+    }
+}
+
+// Since boot; shared by every task
+pub struct Counters {
+    values: [AtomicU64; Stat::ALL.len()],
+    max_temperature: AtomicI32,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            values: [const { AtomicU64::new(0) }; Stat::ALL.len()],
+            max_temperature: AtomicI32::new(NO_TEMPERATURE),
+        }
+    }
+
+    pub fn add(&self, stat: Stat, n: u64) {
+        self.values[stat as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn temperature(&self, centi_c: i32) {
+        self.max_temperature.fetch_max(centi_c, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let mut values = [0; Stat::ALL.len()];
+        for (value, counter) in values.iter_mut().zip(&self.values) {
+            *value = counter.load(Ordering::Relaxed);
+        }
+        let max = self.max_temperature.load(Ordering::Relaxed);
+        Snapshot {
+            values,
+            max_temperature: (max != NO_TEMPERATURE).then_some(max),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    values: [u64; Stat::ALL.len()],
+    // hundredths of a degree C, None until measured
+    max_temperature: Option<i32>,
+}
+
+impl Snapshot {
+    pub const ZERO: Snapshot = Snapshot {
+        values: [0; Stat::ALL.len()],
+        max_temperature: None,
+    };
+
+    pub fn get(&self, stat: Stat) -> u64 {
+        self.values[stat as usize]
+    }
+
+    pub fn max_temperature(&self) -> Option<i32> {
+        self.max_temperature
+    }
+
+    // Lifetime: the stored totals plus this boot
+    pub fn plus(&self, boot: &Snapshot) -> Snapshot {
+        let mut sum = *self;
+        for (value, add) in sum.values.iter_mut().zip(&boot.values) {
+            *value = value.saturating_add(*add);
+        }
+        sum.max_temperature = match (self.max_temperature, boot.max_temperature) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        sum
+    }
+
+    pub fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut out = [0; ENCODED_LEN];
+        out[..4].copy_from_slice(&self.max_temperature.unwrap_or(NO_TEMPERATURE).to_le_bytes());
+        for (chunk, value) in out[4..].chunks_exact_mut(8).zip(&self.values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Snapshot> {
+        let max = i32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        let mut snapshot = Snapshot {
+            values: [0; Stat::ALL.len()],
+            max_temperature: (max != NO_TEMPERATURE).then_some(max),
+        };
+        for (value, chunk) in snapshot.values.iter_mut().zip(data[4..].chunks_exact(8)) {
+            *value = u64::from_le_bytes(chunk.try_into().ok()?);
+        }
+        Some(snapshot)
+    }
+}
+
+// RP2350 on-chip sensor: 12-bit reading of a 3.3 V range, 0.706 V at
+// 27 °C, -1.721 mV per degree
+pub fn temperature_from_adc(raw: u16) -> i32 {
+    let microvolts = raw as i64 * 3_300_000 / 4096;
+    (2700 - (microvolts - 706_000) * 100 / 1721) as i32
+}
+
+// "41.25" from hundredths
+pub fn write_temperature<W: core::fmt::Write>(out: &mut W, centi_c: i32) {
+    let sign = if centi_c < 0 { "-" } else { "" };
+    let abs = centi_c.unsigned_abs();
+    let _ = core::write!(out, "{}{}.{:02}", sign, abs / 100, abs % 100);
+}
+
+// {"boots":n,...,"max_temperature_c":41.25 or null}
+pub fn write_json<const N: usize>(out: &mut heapless::String<N>, snapshot: &Snapshot) {
+    let _ = out.push('{');
+    for stat in Stat::ALL {
+        let _ = core::write!(out, "\"{}\":{},", stat.as_str(), snapshot.get(stat));
+    }
+    let _ = out.push_str("\"max_temperature_c\":");
+    match snapshot.max_temperature {
+        Some(centi_c) => write_temperature(out, centi_c),
+        None => {
+            let _ = out.push_str("null");
+        }
+    }
+    let _ = out.push('}');
+}
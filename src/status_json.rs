@@ -0,0 +1,130 @@
+// /api/status 里脚本依赖的字段
+//
+// The scraper-facing part of the JSON status (GET /api/status, and / with
+// Accept: application/json): the UART baud rate and byte counters, the
+// request count, the uptime, the modem state and the last command or fetch
+// result. main.rs reads them from its statics and writes the rest of the
+// document before them. Counts go out as JSON numbers and strings are
+// escaped, so an AT error with quotes in it still parses.
+
+use crate::json;
+
+pub struct Core<'a> {
+    pub uart_baud: u32,
+    pub uart_tx_bytes: u32,
+    pub uart_rx_bytes: u32,
+    pub requests: u32,
+    pub uptime_secs: u32,
+    pub sim: &'a str,
+    pub registration: &'a str,
+    pub modem_operation: &'a str,
+    pub result: &'a str,
+}
+
+impl Core<'_> {
+    // The result goes last: it is the longest field and the one a
+    // person reading the raw JSON looks for at the end
+    pub fn write<const N: usize>(&self, status: &mut json::Object<'_, N>) {
+        status
+            .u32("uart_baud", self.uart_baud)
+            .u32("uart_tx_bytes", self.uart_tx_bytes)
+            .u32("uart_rx_bytes", self.uart_rx_bytes)
+            .u32("requests", self.requests)
+            .u32("uptime_secs", self.uptime_secs)
+            .str("sim", self.sim)
+            .str("registration", self.registration)
+            .str("modem_operation", self.modem_operation)
+            .str("result", self.result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Value;
+
+    fn document(core: &Core) -> heapless::String<512> {
+        let mut out = heapless::String::new();
+        let mut status = json::Object::new(&mut out);
+        status.str("device_name", "pico2w");
+        core.write(&mut status);
+        status.finish();
+        out
+    }
+
+    #[test]
+    fn fields_by_name_and_type() {
+        let core = Core {
+            uart_baud: 115_200,
+            uart_tx_bytes: 12,
+            uart_rx_bytes: u32::MAX,
+            requests: 3,
+            uptime_secs: 86_400,
+            sim: "ready",
+            registration: "home",
+            modem_operation: "idle",
+            result: "OK",
+        };
+        let out = document(&core);
+        let mut fields = Vec::new();
+        json::walk(&out, |path, value| {
+            let value = match value {
+                Value::Number(n) => n.to_string(),
+                Value::Str(raw) => format!("\"{raw}\""),
+                _ => panic!("{path} is neither a number nor a string"),
+            };
+            fields.push(format!("{path}={value}"));
+        })
+        .unwrap_or_else(|e| panic!("syntax error at {}", e.0));
+        assert_eq!(
+            fields,
+            [
+                "device_name=\"pico2w\"",
+                "uart_baud=115200",
+                "uart_tx_bytes=12",
+                "uart_rx_bytes=4294967295",
+                "requests=3",
+                "uptime_secs=86400",
+                "sim=\"ready\"",
+                "registration=\"home\"",
+                "modem_operation=\"idle\"",
+                "result=\"OK\"",
+            ]
+        );
+    }
+
+    // 模块的错误回复里有引号, 换行和控制字符
+    #[test]
+    fn strings_are_escaped() {
+        let result = "AT+QIOPEN=1,0,\"TCP\",\"httpbin.org\",80\r\n+CME ERROR: \"busy\"\\\u{1a}";
+        let operation = "fetch \"httpbin.org\"";
+        let core = Core {
+            uart_baud: 115_200,
+            uart_tx_bytes: 0,
+            uart_rx_bytes: 0,
+            requests: 0,
+            uptime_secs: 0,
+            sim: "not_inserted",
+            registration: "unknown",
+            modem_operation: operation,
+            result,
+        };
+        let out = document(&core);
+        assert!(out.ends_with(r#","result":"AT+QIOPEN=1,0,\"TCP\",\"httpbin.org\",80\r\n+CME ERROR: \"busy\"\\\u001a"}"#));
+
+        let mut read = 0;
+        json::walk(&out, |path, value| match (path, value) {
+            ("result", Value::Str(raw)) => {
+                assert_eq!(json::unescape::<128>(raw).unwrap(), result);
+                read += 1;
+            }
+            ("modem_operation", Value::Str(raw)) => {
+                assert_eq!(json::unescape::<64>(raw).unwrap(), operation);
+                read += 1;
+            }
+            _ => {}
+        })
+        .unwrap_or_else(|e| panic!("syntax error at {}", e.0));
+        assert_eq!(read, 2);
+    }
+}
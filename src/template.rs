@@ -0,0 +1,146 @@
+// 极简页面模板
+//
+// A template is plain text with `{name}` placeholders and `{?name}...{/name}`
+// sections. `render` copies the text to any `core::fmt::Write` output and
+// asks `fill` to write each placeholder (escaping is up to it); a section is
+// kept only when `show` says so, and sections may nest. A brace not followed
+// by a name and `}` is copied as is, so inline scripts need no escaping.
+// `push_html_escaped` is the escaping the pages use for text.
+
+use core::fmt::Write;
+
+enum Tag<'a> {
+    Value(&'a str),
+    Open(&'a str),
+    Close,
+}
+
+// `{name}`, `{?name}` or `{/name}` at the start of `text`, and its length
+fn tag(text: &str) -> Option<(Tag<'_>, usize)> {
+    let inner = &text[1..];
+    let end = inner.find('}')?;
+    let body = &inner[..end];
+    let (kind, name) = match body.as_bytes().first()? {
+        b'?' => (1, &body[1..]),
+        b'/' => (2, &body[1..]),
+        _ => (0, body),
+    };
+    let valid = !name.is_empty() && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+    if !valid {
+        return None;
+    }
+    let tag = match kind {
+        1 => Tag::Open(name),
+        2 => Tag::Close,
+        _ => Tag::Value(name),
+    };
+    Some((tag, end + 2))
+}
+
+pub fn render<W: Write>(template: &str, out: &mut W, show: impl Fn(&str) -> bool, mut fill: impl FnMut(&str, &mut W)) {
+    let mut rest = template;
+    // depth inside hidden sections, 0 = output visible
+    let mut hidden = 0usize;
+    while let Some(at) = rest.find('{') {
+        if hidden == 0 {
+            let _ = out.write_str(&rest[..at]);
+        }
+        rest = &rest[at..];
+        let Some((tag, len)) = tag(rest) else {
+            if hidden == 0 {
+                let _ = out.write_char('{');
+            }
+            rest = &rest[1..];
+            continue;
+        };
+        match tag {
+            Tag::Value(name) if hidden == 0 => fill(name, out),
+            Tag::Value(_) => {}
+            Tag::Open(name) if hidden == 0 && show(name) => {}
+            Tag::Open(_) => hidden += 1,
+            Tag::Close => hidden = hidden.saturating_sub(1),
+        }
+        rest = &rest[len..];
+    }
+    if hidden == 0 {
+        let _ = out.write_str(rest);
+    }
+}
+
+// Text as HTML element content
+pub fn push_html_escaped<const N: usize>(out: &mut heapless::String<N>, text: &str) {
+    for c in text.chars() {
+        let _ = match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            c => out.push(c),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = include_str!("../static/status.html");
+    // What format_overview fills in main.rs
+    const STATUS_SLOTS: &[&str] = &[
+        "actions", "at_rtt", "at_rtt_max", "baud", "boot", "debug_baud", "debug_mode", "debug_rx", "debug_tx",
+        "fetch_count", "fetch_failures", "fetch_max", "fetch_p50", "fetch_p95", "framing", "geofence",
+        "geofence_radius", "gnss", "keep_warm_bytes", "keep_warm_failed", "keep_warm_host", "keep_warm_min",
+        "keep_warm_reactivations", "keep_warm_sent", "led", "log_level", "modem", "modem_rx", "modem_tx",
+        "operation", "password", "ping", "ping_host", "probe_attempts", "probe_interval", "queued", "recovery",
+        "registration", "roaming_blocks", "rx_audit", "rx_bytes", "rx_gaps", "rx_rate", "schedule",
+        "shaper_down", "shaper_percent", "shaper_rate", "shaper_up", "sim", "ssid", "tx_bytes", "tx_max_drain",
+        "tx_rate", "tx_stalls", "tx_write_errors", "uart_errors", "version",
+    ];
+
+    // The page with each slot filled by `[name]`, and the slots asked for
+    fn render_status(show: impl Fn(&str) -> bool) -> (String, Vec<String>) {
+        let mut page = String::new();
+        let mut filled = Vec::new();
+        render(STATUS, &mut page, show, |name, page| {
+            filled.push(name.to_string());
+            let _ = write!(page, "[{name}]");
+        });
+        (page, filled)
+    }
+
+    fn leftover_tags(page: &str) -> Vec<&str> {
+        page.match_indices('{').filter(|&(at, _)| tag(&page[at..]).is_some()).map(|(at, _)| &page[at..]).collect()
+    }
+
+    #[test]
+    fn status_page_fills_every_slot() {
+        let (page, filled) = render_status(|_| true);
+        for name in &filled {
+            assert!(STATUS_SLOTS.contains(&name.as_str()), "slot {name} is not filled by the handler");
+        }
+        for slot in STATUS_SLOTS {
+            assert!(filled.iter().any(|f| f == slot), "{slot} is never asked for");
+            assert!(page.contains(&format!("[{slot}]")));
+        }
+        assert!(leftover_tags(&page).is_empty(), "{:?}", leftover_tags(&page));
+    }
+
+    #[test]
+    fn hidden_sections_take_their_slots_with_them() {
+        let (all, _) = render_status(|_| true);
+        let (page, filled) = render_status(|_| false);
+        assert!(leftover_tags(&page).is_empty(), "{:?}", leftover_tags(&page));
+        assert!(page.len() < all.len());
+        // the always-shown slots are still there
+        for slot in ["ssid", "baud", "modem", "version"] {
+            assert!(filled.iter().any(|f| f == slot), "{slot}");
+        }
+        assert!(!filled.iter().any(|f| f == "rx_gaps" || f == "shaper_rate"));
+    }
+
+    #[test]
+    fn braces_that_are_not_tags_are_copied() {
+        let mut out = String::new();
+        render("a{b}{ c}{X}{?s}d{/s}{}{", &mut out, |_| false, |name, out| out.push_str(&name.to_uppercase()));
+        assert_eq!(out, "aB{ c}{X}{}{");
+    }
+}
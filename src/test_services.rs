@@ -0,0 +1,80 @@
+// TCP echo (端口 7) 和 discard (端口 9) 测试服务
+//
+// Something to point netcat at when a client is on the AP but "nothing
+// works": `nc 192.168.4.1 7` answers every line, port 9 swallows it. Each
+// service runs one listening socket. A connection that sends nothing for
+// IDLE_MS is closed, and echo closes the connection once it has written
+// ECHO_LIMIT bytes back.
+
+use portable_atomic::{AtomicU32, Ordering};
+
+pub const IDLE_MS: u64 = 30_000;
+pub const ECHO_LIMIT: u32 = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    Echo,
+    Discard,
+}
+
+impl Service {
+    pub const ALL: [Service; 2] = [Service::Echo, Service::Discard];
+
+    pub fn port(self) -> u16 {
+        match self {
+            Service::Echo => 7,
+            Service::Discard => 9,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Service::Echo => "echo",
+            Service::Discard => "discard",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub connections: u32,
+    pub rx_bytes: u32,
+    pub tx_bytes: u32,
+}
+
+pub struct Counters {
+    connections: [AtomicU32; 2],
+    rx_bytes: [AtomicU32; 2],
+    tx_bytes: [AtomicU32; 2],
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            connections: [AtomicU32::new(0), AtomicU32::new(0)],
+            rx_bytes: [AtomicU32::new(0), AtomicU32::new(0)],
+            tx_bytes: [AtomicU32::new(0), AtomicU32::new(0)],
+        }
+    }
+
+    pub fn connected(&self, service: Service) {
+        self.connections[service as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, service: Service, bytes: usize) {
+        self.rx_bytes[service as usize].fetch_add(bytes as u32, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, service: Service, bytes: usize) {
+        self.tx_bytes[service as usize].fetch_add(bytes as u32, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, service: Service) -> Stats {
+        let i = service as usize;
+        Stats {
+            connections: self.connections[i].load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes[i].load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes[i].load(Ordering::Relaxed),
+        }
+    }
+}
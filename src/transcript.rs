@@ -0,0 +1,304 @@
+// 模块初始化和获取的结构化记录 (/api/transcripts)
+//
+// While an operation runs, every UART byte in either direction is cut
+// into lines and kept with its time offset and a classification. The
+// operations are the modem probe at boot and each fetch, and the fetch
+// adds its phase changes. The result is something to attach to a support
+// ticket instead of the interleaved log. Each transcript holds at most
+// CAPACITY bytes of entries. When it fills up, the entries in the middle
+// go: the first half is kept as it was, the newest entries are kept, and
+// `elided` counts what was dropped at entry `elided_after`. Lines longer
+// than TEXT_MAX are cut and flagged. The last KEEP finished transcripts
+// are kept, numbered from 1 at boot.
+//
+// JSON, format 1 (new keys may be added, none will change meaning):
+//
+//   {"format":1,"number":n,"kind":"init"|"fetch","started_ms":since boot,
+//    "duration_ms":n,"outcome":"...","elided":n,"elided_after":n,
+//    "entries":[{"t":ms from start,"dir":"tx"|"rx"|"event",
+//                "type":"command"|"data"|"ok"|"error"|"prompt"|"urc"|"phase",
+//                "cut":true (only when cut),"text":"..."},...]}
+
+use crate::{at_response, json, utf8};
+
+pub const CAPACITY: usize = 4096;
+pub const KEEP: usize = 3;
+pub const TEXT_MAX: usize = 160;
+// dir, type, offset u32, length
+const ENTRY_HEADER: usize = 7;
+const CUT: u8 = 0x80;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    // the modem probe at boot
+    Init,
+    Fetch,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Init => "init",
+            Kind::Fetch => "fetch",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Dir {
+    Tx = 0,
+    Rx = 1,
+    Event = 2,
+}
+
+const DIRS: [&str; 3] = ["tx", "rx", "event"];
+const TYPES: [&str; 7] = ["command", "data", "ok", "error", "prompt", "urc", "phase"];
+
+fn classify(dir: Dir, text: &[u8]) -> u8 {
+    let text = core::str::from_utf8(text).unwrap_or("");
+    match dir {
+        Dir::Tx if text.starts_with("AT") || text.starts_with("at") => 0,
+        Dir::Tx => 1,
+        Dir::Rx => match at_response::classify(text) {
+            at_response::Kind::Data => 1,
+            at_response::Kind::Success => 2,
+            at_response::Kind::Failure => 3,
+            at_response::Kind::Intermediate => 4,
+            at_response::Kind::Urc => 5,
+        },
+        Dir::Event => 6,
+    }
+}
+
+pub struct Transcript {
+    // 0 = empty slot
+    number: u32,
+    kind: Kind,
+    started_ms: u64,
+    duration_ms: u32,
+    outcome: heapless::String<48>,
+    buf: [u8; CAPACITY],
+    used: usize,
+    // entries before the gap: they are never dropped
+    head_end: usize,
+    head_count: u32,
+    elided: u32,
+}
+
+impl Transcript {
+    const fn new() -> Self {
+        Self {
+            number: 0,
+            kind: Kind::Init,
+            started_ms: 0,
+            duration_ms: 0,
+            outcome: heapless::String::new(),
+            buf: [0; CAPACITY],
+            used: 0,
+            head_end: 0,
+            head_count: 0,
+            elided: 0,
+        }
+    }
+
+    fn push(&mut self, dir: Dir, text: &[u8], now_ms: u64) {
+        let text = trim_line(text);
+        if text.is_empty() {
+            return;
+        }
+        let cut = text.len() > TEXT_MAX;
+        let text = &text[..text.len().min(TEXT_MAX)];
+        let need = ENTRY_HEADER + text.len();
+        // 放不下: 丢掉前半段之后最旧的条目
+        while self.used + need > CAPACITY && self.used > self.head_end {
+            let len = ENTRY_HEADER + self.buf[self.head_end + 6] as usize;
+            self.buf.copy_within(self.head_end + len..self.used, self.head_end);
+            self.used -= len;
+            self.elided += 1;
+        }
+        if self.used + need > CAPACITY {
+            return;
+        }
+
+        let offset = now_ms.saturating_sub(self.started_ms).min(u32::MAX as u64) as u32;
+        let entry = &mut self.buf[self.used..self.used + need];
+        entry[0] = dir as u8;
+        entry[1] = classify(dir, text) | if cut { CUT } else { 0 };
+        entry[2..6].copy_from_slice(&offset.to_le_bytes());
+        entry[6] = text.len() as u8;
+        entry[ENTRY_HEADER..].copy_from_slice(text);
+        self.used += need;
+        if self.elided == 0 && self.used <= CAPACITY / 2 {
+            self.head_end = self.used;
+            self.head_count += 1;
+        }
+    }
+
+    // `{"format":1,...,"entries":[`; the entries follow, then "]}"
+    pub fn write_head_json<const N: usize>(&self, out: &mut heapless::String<N>) {
+        let mut obj = json::Object::new(out);
+        obj.u32("format", 1)
+            .u32("number", self.number)
+            .str("kind", self.kind.as_str())
+            .u32("started_ms", self.started_ms.min(u32::MAX as u64) as u32)
+            .u32("duration_ms", self.duration_ms)
+            .str("outcome", &self.outcome)
+            .u32("elided", self.elided)
+            .u32("elided_after", self.head_count)
+            .raw("entries", "[");
+        // 不 finish(): 条目和结尾的 "]}" 由调用方接着写
+    }
+
+    // Writes the entry at byte `offset` (0 for the first) with a leading
+    // comma unless it is the first; returns the next offset, None past the end
+    pub fn write_entry_json<const N: usize>(&self, offset: usize, out: &mut heapless::String<N>) -> Option<usize> {
+        if offset >= self.used {
+            return None;
+        }
+        let entry = &self.buf[offset..];
+        let len = entry[6] as usize;
+        let text = &entry[ENTRY_HEADER..ENTRY_HEADER + len];
+        if offset > 0 {
+            let _ = out.push(',');
+        }
+        let mut obj = json::Object::new(out);
+        obj.u32("t", u32::from_le_bytes([entry[2], entry[3], entry[4], entry[5]]))
+            .str("dir", DIRS[entry[0] as usize])
+            .str("type", TYPES[(entry[1] & !CUT) as usize]);
+        if entry[1] & CUT != 0 {
+            obj.bool("cut", true);
+        }
+        obj.raw("text", "");
+        // 原始字节: 无效的 UTF-8 换成 U+FFFD
+        let _ = out.push('"');
+        for chunk in text.utf8_chunks() {
+            json::push_escaped(out, chunk.valid());
+            if !chunk.invalid().is_empty() {
+                let _ = out.push('\u{fffd}');
+            }
+        }
+        let _ = out.push_str("\"}");
+        Some(offset + ENTRY_HEADER + len)
+    }
+}
+
+// Without the line ending
+fn trim_line(text: &[u8]) -> &[u8] {
+    let end = text.iter().rposition(|&b| b != b'\r' && b != b'\n').map_or(0, |i| i + 1);
+    &text[..end]
+}
+
+pub struct Transcripts {
+    slots: [Transcript; KEEP + 1],
+    active: Option<usize>,
+    last_number: u32,
+    // the unfinished line of each direction
+    partial: [heapless::Vec<u8, TEXT_MAX>; 2],
+    partial_cut: [bool; 2],
+}
+
+impl Transcripts {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { Transcript::new() }; KEEP + 1],
+            active: None,
+            last_number: 0,
+            partial: [heapless::Vec::new(), heapless::Vec::new()],
+            partial_cut: [false; 2],
+        }
+    }
+
+    // Starts recording; one still running is finished as "interrupted"
+    pub fn begin(&mut self, kind: Kind, now_ms: u64) {
+        if self.active.is_some() {
+            self.finish("interrupted", now_ms);
+        }
+        // 空槽位或最旧的一条
+        let Some((index, _)) = self.slots.iter().enumerate().min_by_key(|(_, t)| t.number) else {
+            return;
+        };
+        self.last_number += 1;
+        let slot = &mut self.slots[index];
+        slot.number = self.last_number;
+        slot.kind = kind;
+        slot.started_ms = now_ms;
+        slot.duration_ms = 0;
+        slot.outcome.clear();
+        slot.used = 0;
+        slot.head_end = 0;
+        slot.head_count = 0;
+        slot.elided = 0;
+        self.active = Some(index);
+        for partial in self.partial.iter_mut() {
+            partial.clear();
+        }
+        self.partial_cut = [false; 2];
+    }
+
+    // UART bytes as they pass; kept as lines
+    pub fn feed(&mut self, dir: Dir, data: &[u8], now_ms: u64) {
+        let Some(index) = self.active else {
+            return;
+        };
+        let side = dir as usize;
+        for &b in data {
+            if b == b'\n' {
+                if !self.partial_cut[side] {
+                    self.slots[index].push(dir, &self.partial[side], now_ms);
+                }
+                self.partial[side].clear();
+                self.partial_cut[side] = false;
+            } else if self.partial[side].push(b).is_err() && !self.partial_cut[side] {
+                // 超长的行: 记下开头, 其余到换行为止都丢掉
+                let mut line = [0u8; TEXT_MAX + 1];
+                line[..TEXT_MAX].copy_from_slice(&self.partial[side]);
+                line[TEXT_MAX] = b;
+                self.slots[index].push(dir, &line, now_ms);
+                self.partial_cut[side] = true;
+            }
+        }
+    }
+
+    pub fn event(&mut self, text: &str, now_ms: u64) {
+        if let Some(index) = self.active {
+            self.slots[index].push(Dir::Event, text.as_bytes(), now_ms);
+        }
+    }
+
+    pub fn finish(&mut self, outcome: &str, now_ms: u64) {
+        let Some(index) = self.active.take() else {
+            return;
+        };
+        let slot = &mut self.slots[index];
+        // 没有换行的最后一段 (例如 '>' 提示符)
+        for (side, dir) in [(0, Dir::Tx), (1, Dir::Rx)] {
+            if !self.partial_cut[side] {
+                slot.push(dir, &self.partial[side], now_ms);
+            }
+        }
+        slot.duration_ms = now_ms.saturating_sub(slot.started_ms).min(u32::MAX as u64) as u32;
+        utf8::push_truncated(&mut slot.outcome, outcome);
+    }
+
+    // Finished transcripts, newest first
+    pub fn numbers(&self) -> heapless::Vec<u32, KEEP> {
+        let mut numbers = heapless::Vec::<u32, { KEEP + 1 }>::new();
+        for (index, slot) in self.slots.iter().enumerate() {
+            if slot.number != 0 && self.active != Some(index) {
+                let _ = numbers.push(slot.number);
+            }
+        }
+        numbers.sort_unstable_by(|a, b| b.cmp(a));
+        numbers.truncate(KEEP);
+        numbers.iter().copied().collect()
+    }
+
+    // A finished transcript
+    pub fn get(&self, number: u32) -> Option<&Transcript> {
+        self.slots
+            .iter()
+            .enumerate()
+            .find(|(index, t)| number != 0 && t.number == number && self.active != Some(*index))
+            .map(|(_, t)| t)
+    }
+}
@@ -0,0 +1,187 @@
+// 输入触发: GPIO 电平变化时执行一个动作 (门磁, 人体感应之类)
+//
+// Up to MAX_TRIGGERS inputs, each a GPIO with the internal pull-up (a
+// contact to ground reads low), the edge that counts, a debounce time and
+// an action: a fetch, a webhook, or an SMS to the trigger's own number.
+// After an edge the level has to hold for debounce_ms before it counts.
+// A trigger within min_interval_s of the last one on the same input is
+// logged as rate limited and does nothing, so a chattering contact cannot
+// eat the data budget. Pins are claimed at boot; a new triggerN.gpio takes
+// effect after a reboot.
+
+pub const MAX_TRIGGERS: usize = 4;
+// Config group of each input
+pub const NAMES: [&str; MAX_TRIGGERS] = ["trigger1", "trigger2", "trigger3", "trigger4"];
+const LOG_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+impl Edge {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "rising" => Some(Edge::Rising),
+            "falling" => Some(Edge::Falling),
+            "both" => Some(Edge::Both),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Edge::Rising => "rising",
+            Edge::Falling => "falling",
+            Edge::Both => "both",
+        }
+    }
+
+    // Whether a change that left the input at `high` counts
+    pub fn counts(self, high: bool) -> bool {
+        match self {
+            Edge::Rising => high,
+            Edge::Falling => !high,
+            Edge::Both => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Off,
+    Fetch,
+    Webhook,
+    Sms,
+}
+
+impl Action {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "off" => Some(Action::Off),
+            "fetch" => Some(Action::Fetch),
+            "webhook" => Some(Action::Webhook),
+            "sms" => Some(Action::Sms),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Off => "off",
+            Action::Fetch => "fetch",
+            Action::Webhook => "webhook",
+            Action::Sms => "sms",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Binding {
+    pub gpio: u8,
+    pub edge: Edge,
+    pub debounce_ms: u32,
+    pub action: Action,
+    pub min_interval_s: u32,
+    // recipient of Action::Sms
+    pub sms: heapless::String<20>,
+}
+
+impl Binding {
+    pub const fn off(gpio: u8) -> Self {
+        Self {
+            gpio,
+            edge: Edge::Falling,
+            debounce_ms: 50,
+            action: Action::Off,
+            min_interval_s: 60,
+            sms: heapless::String::new(),
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.action != Action::Off
+    }
+}
+
+// "trigger3.edge" -> (2, "edge")
+pub fn parse_path(path: &str) -> Option<(usize, &str)> {
+    let (group, key) = path.split_once('.')?;
+    Some((NAMES.iter().position(|&name| name == group)?, key))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Queued,
+    RateLimited,
+    // the modem queue was full
+    Busy,
+    // webhook action without a webhook URL, or the event suppressed
+    NotSent,
+    // actions.input does not allow the action
+    NotAllowed,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Queued => "queued",
+            Outcome::RateLimited => "rate limited",
+            Outcome::Busy => "modem busy",
+            Outcome::NotSent => "not sent",
+            Outcome::NotAllowed => "not allowed",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Occurrence {
+    pub index: usize,
+    pub gpio: u8,
+    pub high: bool,
+    pub action: Action,
+    pub outcome: Outcome,
+    pub at_ms: u64,
+}
+
+// Every occurrence, newest kept, plus when each input last acted
+pub struct Log {
+    entries: heapless::Deque<Occurrence, LOG_LEN>,
+    last_action_ms: [Option<u64>; MAX_TRIGGERS],
+    pub total: u32,
+}
+
+impl Log {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Deque::new(),
+            last_action_ms: [None; MAX_TRIGGERS],
+            total: 0,
+        }
+    }
+
+    // False within min_interval_s of the last action on this input
+    pub fn allow(&mut self, index: usize, min_interval_s: u32, now_ms: u64) -> bool {
+        let last = &mut self.last_action_ms[index];
+        if last.is_some_and(|at| now_ms.saturating_sub(at) < min_interval_s as u64 * 1000) {
+            return false;
+        }
+        *last = Some(now_ms);
+        true
+    }
+
+    pub fn record(&mut self, occurrence: Occurrence) {
+        if self.entries.is_full() {
+            self.entries.pop_back();
+        }
+        let _ = self.entries.push_front(occurrence);
+        self.total += 1;
+    }
+
+    // Newest first
+    pub fn iter(&self) -> impl Iterator<Item = &Occurrence> {
+        self.entries.iter()
+    }
+}
@@ -0,0 +1,83 @@
+// 串口硬件错误计数 (溢出, 断开, 奇偶校验, 帧错误)
+//
+// The buffered UART reports receive errors as read errors and drops the
+// byte. Each kind is counted here; only the first few of each are logged so
+// a wrong baud rate does not flood the defmt output. A burst of framing
+// errors shortly after boot almost always means the module runs at another
+// baud rate.
+
+use portable_atomic::{AtomicU32, Ordering};
+
+// 每种错误只记录前几次
+const LOG_FIRST: u32 = 3;
+// 启动后这段时间内出现这么多帧错误就提示波特率
+const BOOT_WINDOW_MS: u64 = 30_000;
+const BOOT_FRAMING_BURST: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Overrun,
+    Break,
+    Parity,
+    Framing,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [Kind::Overrun, Kind::Break, Kind::Parity, Kind::Framing];
+
+    pub fn from_uart(error: embassy_rp::uart::Error) -> Option<Kind> {
+        match error {
+            embassy_rp::uart::Error::Overrun => Some(Kind::Overrun),
+            embassy_rp::uart::Error::Break => Some(Kind::Break),
+            embassy_rp::uart::Error::Parity => Some(Kind::Parity),
+            embassy_rp::uart::Error::Framing => Some(Kind::Framing),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Overrun => "overrun",
+            Kind::Break => "break",
+            Kind::Parity => "parity",
+            Kind::Framing => "framing",
+        }
+    }
+}
+
+pub struct Counters {
+    counts: [AtomicU32; 4],
+    // framing errors in the first BOOT_WINDOW_MS
+    boot_framing: AtomicU32,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            counts: [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)],
+            boot_framing: AtomicU32::new(0),
+        }
+    }
+
+    // True when this occurrence should be logged
+    pub fn record(&self, kind: Kind, uptime_ms: u64) -> bool {
+        let count = self.counts[kind as usize].fetch_add(1, Ordering::Relaxed) + 1;
+        if kind == Kind::Framing && uptime_ms < BOOT_WINDOW_MS {
+            self.boot_framing.fetch_add(1, Ordering::Relaxed);
+        }
+        count <= LOG_FIRST
+    }
+
+    pub fn get(&self, kind: Kind) -> u32 {
+        self.counts[kind as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u32 {
+        Kind::ALL.iter().map(|&kind| self.get(kind)).sum()
+    }
+
+    // Framing errors right after boot: the baud rate is probably wrong
+    pub fn boot_framing_burst(&self) -> bool {
+        self.boot_framing.load(Ordering::Relaxed) >= BOOT_FRAMING_BURST
+    }
+}
@@ -0,0 +1,43 @@
+// 主动上报 (URC) 的识别
+//
+// The module sends URCs whenever it likes: between the lines of a reply,
+// during a fetch, right after a block of payload. Every line the UART
+// readers take apart goes through `parse`, and the URCs found are handled
+// wherever they turn up. The reader that met them carries on as if they
+// were not there. Payload never comes through here. It is read by the
+// length its header announced, so a URC that starts in the same read as
+// the tail of a block stays in the buffer and becomes the next line.
+// Socket notices ("recv", "closed") depend on the backend and are
+// recognised by `CellularModem` instead.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Urc<'a> {
+    // +CMTI: "<storage>",<index>
+    SmsReceived { storage: &'a str, index: u16 },
+    // RING or +CRING: <type>
+    Ring,
+    // the network dropped the data context
+    PdpDeactivated,
+    // RDY: the module started again on its own
+    Restarted,
+}
+
+pub fn parse(line: &str) -> Option<Urc<'_>> {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix("+CMTI:") {
+        let (storage, index) = rest.split_once(',')?;
+        return Some(Urc::SmsReceived {
+            storage: storage.trim().trim_matches('"'),
+            index: index.trim().parse().ok()?,
+        });
+    }
+    match line {
+        "RING" => Some(Urc::Ring),
+        "RDY" => Some(Urc::Restarted),
+        // Quectel +QIURC: "pdpdeact",<ctx>, SIMCom +PDP: DEACT
+        "+PDP: DEACT" => Some(Urc::PdpDeactivated),
+        _ if line.starts_with("+CRING:") => Some(Urc::Ring),
+        _ if line.starts_with("+QIURC: \"pdpdeact\",") => Some(Urc::PdpDeactivated),
+        _ => None,
+    }
+}
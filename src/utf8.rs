@@ -0,0 +1,216 @@
+// UTF-8 文本的安全截断
+//
+// The logs, the modem's replies and fetched bodies arrive in pieces of any
+// size, and fixed buffers end at any byte. Text that goes into a response
+// goes through here so every cut falls between characters. Operator names
+// from AT+COPS, for example, are often Chinese. A page sent as utf-8 then
+// never shows replacement characters just because a buffer or read ended
+// in the middle of a character. Bytes that are not UTF-8 at all are left
+// as they are.
+
+// Bytes in the character this byte starts; 1 for ASCII and stray bytes
+fn sequence_len(first: u8) -> usize {
+    match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    }
+}
+
+fn is_continuation(b: u8) -> bool {
+    b & 0xc0 == 0x80
+}
+
+// Length of `bytes` without a character cut off at the end
+pub fn complete_len(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let b = bytes[bytes.len() - back];
+        if is_continuation(b) {
+            continue;
+        }
+        return if sequence_len(b) > back { bytes.len() - back } else { bytes.len() };
+    }
+    bytes.len()
+}
+
+// How many bytes at the start belong to a character that began earlier
+pub fn partial_start(bytes: &[u8]) -> usize {
+    bytes.iter().take(3).take_while(|&&b| is_continuation(b)).count()
+}
+
+// The longest start of `text` that is at most `max` bytes
+pub fn floor(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+// As much of `text` as fits, cut between characters; false when cut
+pub fn push_truncated<const N: usize>(out: &mut heapless::String<N>, text: &str) -> bool {
+    let fitting = floor(text, N - out.len());
+    let _ = out.push_str(fitting);
+    fitting.len() == text.len()
+}
+
+// Keeps the start of a character that is split across two pieces, so the
+// pieces can be passed on one at a time with whole characters only
+pub struct Carry {
+    held: heapless::Vec<u8, 3>,
+}
+
+impl Carry {
+    pub const fn new() -> Self {
+        Self { held: heapless::Vec::new() }
+    }
+
+    // `each` gets the bytes of `data` that end on a character boundary,
+    // with any held bytes completed first; a new cut-off tail is held back
+    pub fn feed(&mut self, mut data: &[u8], mut each: impl FnMut(&[u8])) {
+        if let Some(&first) = self.held.first() {
+            let missing = sequence_len(first) - self.held.len();
+            let taken = data.iter().take(missing).take_while(|&&b| is_continuation(b)).count();
+            if taken == missing {
+                let mut whole = [0u8; 4];
+                let len = self.held.len();
+                whole[..len].copy_from_slice(&self.held);
+                whole[len..len + taken].copy_from_slice(&data[..taken]);
+                each(&whole[..len + taken]);
+                self.held.clear();
+                data = &data[taken..];
+            } else if taken == data.len() {
+                // still not complete
+                let _ = self.held.extend_from_slice(data);
+                return;
+            } else {
+                // not a character after all: pass the bytes on as they are
+                each(&self.held);
+                self.held.clear();
+            }
+        }
+        let end = complete_len(data);
+        if end > 0 {
+            each(&data[..end]);
+        }
+        let _ = self.held.extend_from_slice(&data[end..]);
+    }
+
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One of each length: 1, 2, 3 and 4 bytes
+    const MIXED: &str = "aé信😀";
+
+    #[test]
+    fn complete_len_at_every_cut() {
+        let bytes = MIXED.as_bytes();
+        let boundaries = [0, 1, 3, 6, 10];
+        for cut in 0..=bytes.len() {
+            let expected = boundaries.iter().copied().filter(|&b| b <= cut).max().unwrap();
+            assert_eq!(complete_len(&bytes[..cut]), expected, "cut at {cut}");
+        }
+    }
+
+    #[test]
+    fn partial_start_at_every_cut() {
+        let bytes = MIXED.as_bytes();
+        let boundaries = [0, 1, 3, 6, 10];
+        for cut in 0..=bytes.len() {
+            let next = boundaries.iter().copied().find(|&b| b >= cut).unwrap();
+            assert_eq!(partial_start(&bytes[cut..]), next - cut, "cut at {cut}");
+        }
+    }
+
+    #[test]
+    fn invalid_bytes_are_left_alone() {
+        // stray continuation bytes at the end, a lead byte with nothing after
+        // it in the middle, bytes that never start a character
+        assert_eq!(complete_len(b"ab\x80\x80\x80"), 5);
+        assert_eq!(complete_len(b"ab\xe4\xbf"), 2);
+        assert_eq!(complete_len(b"\xe4x"), 2);
+        assert_eq!(complete_len(b"\xff\xfe"), 2);
+        assert_eq!(complete_len(b"\xf8"), 1);
+        // more than three continuation bytes in a row cannot belong to one
+        // character; only three are skipped
+        assert_eq!(partial_start(b"\x80\x80\x80\x80a"), 3);
+        assert_eq!(partial_start(b"\xffa"), 0);
+        assert_eq!(complete_len(b""), 0);
+        assert_eq!(partial_start(b""), 0);
+    }
+
+    #[test]
+    fn truncation_falls_between_characters() {
+        for max in 0..=MIXED.len() + 1 {
+            let cut = floor(MIXED, max);
+            assert!(cut.len() <= max && MIXED.starts_with(cut));
+            // the next character would not have fitted
+            if let Some(next) = MIXED[cut.len()..].chars().next() {
+                assert!(cut.len() + next.len_utf8() > max);
+            }
+        }
+
+        let mut out = heapless::String::<7>::new();
+        let _ = out.push_str("ab");
+        assert!(!push_truncated(&mut out, "信号"));
+        assert_eq!(out, "ab信");
+        assert!(!push_truncated(&mut out, "😀"));
+        assert_eq!(out, "ab信");
+        assert!(push_truncated(&mut out, "xy"));
+        assert_eq!(out, "ab信xy");
+        assert!(push_truncated(&mut out, ""));
+    }
+
+    // Every way of cutting MIXED into two pieces comes out whole
+    #[test]
+    fn carry_rejoins_characters_split_across_pieces() {
+        let bytes = MIXED.as_bytes();
+        for cut in 0..=bytes.len() {
+            let mut carry = Carry::new();
+            let mut out = Vec::new();
+            for piece in [&bytes[..cut], &bytes[cut..]] {
+                carry.feed(piece, |whole| {
+                    assert!(core::str::from_utf8(whole).is_ok(), "cut at {cut}: {whole:x?}");
+                    out.extend_from_slice(whole);
+                });
+            }
+            assert_eq!(out, bytes, "cut at {cut}");
+        }
+
+        // a byte at a time
+        let mut carry = Carry::new();
+        let mut out = Vec::new();
+        for b in bytes {
+            carry.feed(core::slice::from_ref(b), |whole| out.extend_from_slice(whole));
+        }
+        assert_eq!(out, bytes);
+    }
+
+    #[test]
+    fn carry_passes_invalid_bytes_on() {
+        // a lead byte followed by something that does not continue it
+        let mut carry = Carry::new();
+        let mut out = Vec::new();
+        carry.feed(b"a\xe4", |b| out.extend_from_slice(b));
+        assert_eq!(out, b"a");
+        carry.feed(b"xy", |b| out.extend_from_slice(b));
+        assert_eq!(out, b"a\xe4xy");
+
+        // a held start dropped by clear
+        let mut out = Vec::new();
+        carry.feed(b"\xe4\xbf", |b| out.extend_from_slice(b));
+        carry.clear();
+        carry.feed(b"z", |b| out.extend_from_slice(b));
+        assert_eq!(out, b"z");
+    }
+}
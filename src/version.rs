@@ -0,0 +1,82 @@
+// 固件版本信息 (编译时写入, 加上启动时读到的 cyw43 / 模块固件版本)
+//
+// The compile-time part comes from Cargo and build.rs and matches the
+// picotool binary_info entries; the WiFi chip and modem firmware versions
+// are only known at runtime.
+
+use core::fmt::Write as _;
+
+use crate::blob_check::Blob;
+use crate::json;
+
+pub const PROGRAM: &str = "EC800K HTTP Tester";
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("GIT_HASH");
+pub const PROFILE: &str = env!("BUILD_PROFILE");
+pub const EMBASSY_RP: &str = env!("VERSION_EMBASSY_RP");
+pub const EMBASSY_NET: &str = env!("VERSION_EMBASSY_NET");
+pub const CYW43: &str = env!("VERSION_CYW43");
+
+// The blob carries "... Version: 7.95.61 (abcd531 CY) CRC: ..." near its end
+pub fn cyw43_firmware_version(blob: &[u8]) -> Option<&str> {
+    const TAG: &[u8] = b"Version: ";
+    let start = blob.windows(TAG.len()).rposition(|w| w == TAG)? + TAG.len();
+    let rest = &blob[start..];
+    let end = rest.iter().position(|&b| b == 0 || b == b'\n').unwrap_or(rest.len());
+    let text = core::str::from_utf8(&rest[..end.min(64)]).ok()?;
+    let text = text.split(" CRC:").next().unwrap_or(text).trim();
+    (!text.is_empty()).then_some(text)
+}
+
+// ATI reply: "Quectel EC800K Revision: EC800KCNLCR06A03M08"
+pub fn modem_revision(ati_reply: &str) -> Option<&str> {
+    let (_, rest) = ati_reply.split_once("Revision:")?;
+    rest.split_whitespace().next()
+}
+
+pub struct Runtime<'a> {
+    pub cyw43_firmware: Option<&'a str>,
+    // empty before the boot check
+    pub cyw43_blobs: &'a [Blob],
+    pub modem: &'a str,
+    pub modem_revision: Option<&'a str>,
+}
+
+pub fn write_json<const N: usize>(out: &mut heapless::String<N>, runtime: &Runtime<'_>) {
+    // [{"name":"43439A0.bin","len":231077,"crc":"1a2b3c4d","ok":true},...]
+    let mut blobs = heapless::String::<256>::new();
+    let _ = blobs.push('[');
+    for (i, blob) in runtime.cyw43_blobs.iter().enumerate() {
+        if i > 0 {
+            let _ = blobs.push(',');
+        }
+        let mut crc = heapless::String::<8>::new();
+        let _ = core::write!(crc, "{:08x}", blob.crc);
+        let mut item = json::Object::new(&mut blobs);
+        item.str("name", blob.name)
+            .u32("len", blob.len as u32)
+            .str("crc", &crc)
+            .bool("ok", blob.ok());
+        item.finish();
+    }
+    let _ = blobs.push(']');
+
+    let mut obj = json::Object::new(out);
+    obj.str("program", PROGRAM)
+        .str("version", CRATE_VERSION)
+        .str("git", GIT_HASH)
+        .str("profile", PROFILE)
+        .str("embassy_rp", EMBASSY_RP)
+        .str("embassy_net", EMBASSY_NET)
+        .str("cyw43", CYW43)
+        .str("cyw43_firmware", runtime.cyw43_firmware.unwrap_or("unknown"))
+        .raw("cyw43_blobs", &blobs)
+        .str("modem", runtime.modem)
+        .str("modem_revision", runtime.modem_revision.unwrap_or("unknown"));
+    obj.finish();
+}
+
+// 页脚一行
+pub fn write_footer<const N: usize>(out: &mut heapless::String<N>) {
+    let _ = core::write!(out, "{} v{} ({}, {})", PROGRAM, CRATE_VERSION, GIT_HASH, PROFILE);
+}
@@ -0,0 +1,206 @@
+// 事件通知 (webhook)
+//
+// Selected events are POSTed as a small JSON document to a configured
+// http:// URL over the cellular link. At most 4 notifications wait at a
+// time; a failed POST is retried with growing delays and then dropped.
+// The same event class firing again within 5 minutes is suppressed so a
+// flapping link does not burn data.
+
+use core::fmt::Write as _;
+
+#[cfg(feature = "gnss")]
+use crate::gnss;
+use crate::{at, json};
+
+pub const QUEUE_LEN: usize = 4;
+const SUPPRESS_MS: u64 = 5 * 60 * 1000;
+const MAX_ATTEMPTS: u8 = 4;
+// 第 n 次重试前等待 30 s * 2^(n-1)
+const RETRY_BASE_MS: u64 = 30_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    ModemError,
+    ModemRecovered,
+    DataBudget,
+    FetchFailures,
+    Boot,
+    Geofence,
+    // a GPIO input trigger with the webhook action
+    Input,
+}
+
+impl Event {
+    pub const ALL: [Event; 7] = [
+        Event::ModemError,
+        Event::ModemRecovered,
+        Event::DataBudget,
+        Event::FetchFailures,
+        Event::Boot,
+        Event::Geofence,
+        Event::Input,
+    ];
+
+    // config webhook.events bit
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::ModemError => "modem_error",
+            Event::ModemRecovered => "modem_recovered",
+            Event::DataBudget => "data_budget",
+            Event::FetchFailures => "fetch_failures",
+            Event::Boot => "boot",
+            Event::Geofence => "geofence",
+            Event::Input => "input",
+        }
+    }
+}
+
+pub const ALL_EVENTS: u32 = (1 << Event::ALL.len()) - 1;
+
+#[derive(Clone)]
+pub struct Notification {
+    pub event: Event,
+    pub at_ms: u64,
+    pub detail: heapless::String<64>,
+    attempts: u8,
+    next_try_ms: u64,
+}
+
+impl Notification {
+    // `position`: the last GNSS fix, when there is one
+    pub fn write_body<const N: usize>(
+        &self,
+        out: &mut heapless::String<N>,
+        device: &str,
+        #[cfg(feature = "gnss")] position: Option<&gnss::Fix>,
+    ) {
+        let mut obj = json::Object::new(out);
+        obj.str("event", self.event.as_str())
+            .u32("uptime_s", (self.at_ms / 1000) as u32)
+            .str("device", device)
+            .str("detail", &self.detail);
+        #[cfg(feature = "gnss")]
+        if let Some(fix) = position {
+            obj.raw("lat", &gnss::number(format_args!("{:.6}", fix.latitude)))
+                .raw("lon", &gnss::number(format_args!("{:.6}", fix.longitude)));
+        }
+        obj.finish();
+    }
+}
+
+pub struct Queue {
+    pending: heapless::Vec<Notification, QUEUE_LEN>,
+    last_fired_ms: [Option<u64>; Event::ALL.len()],
+    pub sent: u32,
+    // gave up after MAX_ATTEMPTS
+    pub failed: u32,
+    // queue was full
+    pub dropped: u32,
+    pub suppressed: u32,
+}
+
+impl Queue {
+    pub const fn new() -> Self {
+        Self {
+            pending: heapless::Vec::new(),
+            last_fired_ms: [None; Event::ALL.len()],
+            sent: 0,
+            failed: 0,
+            dropped: 0,
+            suppressed: 0,
+        }
+    }
+
+    // False when suppressed as a duplicate or the queue is full
+    pub fn fire(&mut self, event: Event, detail: &str, now_ms: u64) -> bool {
+        let last = &mut self.last_fired_ms[event as usize];
+        if last.is_some_and(|at| now_ms.saturating_sub(at) < SUPPRESS_MS) {
+            self.suppressed += 1;
+            return false;
+        }
+        *last = Some(now_ms);
+
+        let mut notification = Notification {
+            event,
+            at_ms: now_ms,
+            detail: heapless::String::new(),
+            attempts: 0,
+            next_try_ms: now_ms,
+        };
+        for c in detail.chars() {
+            if notification.detail.push(c).is_err() {
+                break;
+            }
+        }
+        if self.pending.push(notification).is_err() {
+            self.dropped += 1;
+            return false;
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn next_due_ms(&self) -> Option<u64> {
+        self.pending.iter().map(|n| n.next_try_ms).min()
+    }
+
+    // Oldest notification whose time has come, removed from the queue
+    pub fn take_due(&mut self, now_ms: u64) -> Option<Notification> {
+        let index = self.pending.iter().position(|n| n.next_try_ms <= now_ms)?;
+        Some(self.pending.remove(index))
+    }
+
+    pub fn retry(&mut self, mut notification: Notification, now_ms: u64) {
+        notification.attempts += 1;
+        if notification.attempts >= MAX_ATTEMPTS {
+            self.failed += 1;
+            return;
+        }
+        notification.next_try_ms = now_ms + (RETRY_BASE_MS << (notification.attempts - 1));
+        if self.pending.push(notification).is_err() {
+            self.dropped += 1;
+        }
+    }
+}
+
+pub struct Url<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub path: &'a str,
+}
+
+// http://host[:port][/path]; the modem has no TLS here
+pub fn parse_url(url: &str) -> Option<Url<'_>> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(at) => rest.split_at(at),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    if at::arg(host, at::Kind::Host).is_err() || path.bytes().any(|b| b <= b' ') {
+        return None;
+    }
+    Some(Url { host, port, path })
+}
+
+pub fn write_request<const N: usize>(out: &mut heapless::String<N>, url: &Url<'_>, body: &str) -> bool {
+    core::write!(
+        out,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        url.path,
+        url.host,
+        body.len(),
+        body
+    )
+    .is_ok()
+}
@@ -0,0 +1,101 @@
+// Live updates for the UI pages, instead of reloading the whole page.
+// Every 2 s while the tab is visible (15 s while hidden) it polls
+// /api/status and fills in elements marked data-status='<key>', the
+// connection indicator and the tools result. When the state generation
+// changes it fetches the page again and swaps the elements marked
+// data-swap. A <pre id='log' data-log='<url>' data-end='<offset>'> gets
+// new log text from <url>&since=<offset>. The log only follows new text
+// while it is scrolled to the bottom. After 3 failed polls in a row a
+// "connection lost" banner is shown until a poll succeeds again.
+(function () {
+  var VISIBLE_MS = 2000, HIDDEN_MS = 15000, LOST_AFTER = 3, LOG_MAX = 65536;
+  var generation = document.body.getAttribute('data-generation');
+  var log = document.getElementById('log');
+  var failures = 0, timer = null;
+
+  function byId(id) { return document.getElementById(id); }
+
+  function atBottom(el) { return el.scrollTop + el.clientHeight >= el.scrollHeight - 4; }
+
+  function setText(el, text) {
+    if (el.textContent === text) return;
+    var follow = atBottom(el), top = el.scrollTop;
+    el.textContent = text;
+    el.scrollTop = follow ? el.scrollHeight : top;
+  }
+
+  function check(r) {
+    if (!r.ok) throw new Error(r.status);
+    return r;
+  }
+
+  function swap() {
+    return fetch(location.pathname).then(check).then(function (r) { return r.text(); }).then(function (text) {
+      var page = new DOMParser().parseFromString(text, 'text/html');
+      var parts = document.querySelectorAll('[data-swap]');
+      for (var i = 0; i < parts.length; i++) {
+        var fresh = page.getElementById(parts[i].id);
+        if (fresh) parts[i].innerHTML = fresh.innerHTML;
+      }
+    });
+  }
+
+  function status() {
+    return fetch('/api/status', { headers: { Accept: 'application/json' } }).then(check).then(function (r) {
+      return r.json();
+    }).then(function (s) {
+      var fields = document.querySelectorAll('[data-status]');
+      for (var i = 0; i < fields.length; i++) {
+        var value = s[fields[i].getAttribute('data-status')];
+        if (value !== undefined) fields[i].textContent = value;
+      }
+      var conn = byId('conn');
+      if (conn) conn.textContent = 'SIM ' + s.sim + ' · modem ' + s.modem_operation;
+      var result = byId('result');
+      if (result) setText(result, s.result);
+      var changed = generation !== null && String(s.generation) !== generation;
+      generation = String(s.generation);
+      if (changed) return swap();
+    });
+  }
+
+  function logTail() {
+    if (!log || !log.hasAttribute('data-log')) return;
+    var url = log.getAttribute('data-log');
+    url += (url.indexOf('?') < 0 ? '?' : '&') + 'since=' + log.getAttribute('data-end');
+    return fetch(url).then(check).then(function (r) {
+      var end = r.headers.get('X-Log-End');
+      return r.text().then(function (text) {
+        if (end !== null) log.setAttribute('data-end', end);
+        if (!text) return;
+        var follow = atBottom(log), top = log.scrollTop;
+        var all = log.textContent + text;
+        log.textContent = all.length > LOG_MAX ? all.slice(all.length - LOG_MAX) : all;
+        log.scrollTop = follow ? log.scrollHeight : top;
+      });
+    });
+  }
+
+  function schedule(ms) {
+    clearTimeout(timer);
+    timer = setTimeout(poll, ms);
+  }
+
+  function poll() {
+    timer = null;
+    status().then(logTail).then(function () {
+      failures = 0;
+    }, function () {
+      failures++;
+    }).then(function () {
+      byId('lost').hidden = failures < LOST_AFTER;
+      schedule(document.hidden ? HIDDEN_MS : VISIBLE_MS);
+    });
+  }
+
+  document.addEventListener('visibilitychange', function () {
+    if (!document.hidden && timer !== null) schedule(0);
+  });
+  if (log) log.scrollTop = log.scrollHeight;
+  schedule(VISIBLE_MS);
+})();
@@ -0,0 +1,243 @@
+// Decoder for the firmware's /capture.bin UART trace (format in src/capture.rs).
+//
+// Plain std Rust, built on the host rather than for the firmware target:
+//
+//   rustc -O tools/capture_decode.rs -o capture_decode
+//   curl -o capture.bin http://192.168.4.1/capture.bin
+//   ./capture_decode capture.bin          # escaped text per chunk
+//   ./capture_decode --hex capture.bin    # plus a hex dump of every payload
+//
+// host-tests/ builds it as a module too, so the firmware's encoder
+// (src/capture.rs) is tested against this decoder.
+
+use std::fmt::Write as _;
+use std::process::ExitCode;
+
+const MAGIC: &[u8; 4] = b"MCAP";
+const HEADER_LEN: usize = 20;
+const RECORD_HEADER_LEN: usize = 7;
+
+pub struct Header {
+    pub version: u16,
+    pub started_ms: u32,
+    pub dropped_chunks: u32,
+    pub dropped_bytes: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Tx,
+    Rx,
+    Dropped,
+    Unknown(u8),
+}
+
+pub struct Record<'a> {
+    pub kind: Kind,
+    pub time_ms: u32,
+    pub payload: &'a [u8],
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+pub fn parse_header(data: &[u8]) -> Result<Header, String> {
+    if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+        return Err("not a capture file (bad magic)".into());
+    }
+    let header = Header {
+        version: u16_at(data, 4),
+        started_ms: u32_at(data, 8),
+        dropped_chunks: u32_at(data, 12),
+        dropped_bytes: u32_at(data, 16),
+    };
+    if header.version != 1 {
+        return Err(format!("unsupported capture version {}", header.version));
+    }
+    Ok(header)
+}
+
+// Splits the bytes after the file header into records; a truncated last
+// record is reported as an error after the complete ones
+pub fn parse_records(mut data: &[u8]) -> (Vec<Record<'_>>, Option<String>) {
+    let mut records = Vec::new();
+    while !data.is_empty() {
+        if data.len() < RECORD_HEADER_LEN {
+            return (records, Some(format!("{} trailing bytes", data.len())));
+        }
+        let len = u16_at(data, 1) as usize;
+        let Some(payload) = data.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len) else {
+            let error = format!("record {} truncated", records.len());
+            return (records, Some(error));
+        };
+        let kind = match data[0] {
+            0 => Kind::Tx,
+            1 => Kind::Rx,
+            2 => Kind::Dropped,
+            other => Kind::Unknown(other),
+        };
+        records.push(Record {
+            kind,
+            time_ms: u32_at(data, 3),
+            payload,
+        });
+        data = &data[RECORD_HEADER_LEN + len..];
+    }
+    (records, None)
+}
+
+fn escape(payload: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in payload {
+        match b {
+            b'\r' => out.push_str("\\r"),
+            b'\n' => out.push_str("\\n"),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\x{:02x}", b);
+            }
+        }
+    }
+    out
+}
+
+fn hex_dump(payload: &[u8]) {
+    for (row, chunk) in payload.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("            {:04x}  {}", row * 16, hex.join(" "));
+    }
+}
+
+fn main() -> ExitCode {
+    let mut hex = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--hex" => hex = true,
+            _ => path = Some(arg),
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("usage: capture_decode [--hex] capture.bin");
+        return ExitCode::FAILURE;
+    };
+
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let header = match parse_header(&data) {
+        Ok(header) => header,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("capture started {} ms after boot", header.started_ms);
+    let (records, error) = parse_records(&data[HEADER_LEN..]);
+    for record in &records {
+        let time = format!("{:>5}.{:03}", record.time_ms / 1000, record.time_ms % 1000);
+        let dir = match record.kind {
+            Kind::Tx => "TX".to_string(),
+            Kind::Rx => "RX".to_string(),
+            Kind::Unknown(kind) => format!("?{}", kind),
+            Kind::Dropped => {
+                println!("{} -- capture buffer full, later chunks dropped --", time);
+                continue;
+            }
+        };
+        println!("{} {} {:>4}  {}", time, dir, record.payload.len(), escape(record.payload));
+        if hex {
+            hex_dump(record.payload);
+        }
+    }
+    if header.dropped_chunks > 0 {
+        println!(
+            "dropped {} chunks ({} bytes) after the buffer filled",
+            header.dropped_chunks, header.dropped_bytes
+        );
+    }
+    if let Some(error) = error {
+        eprintln!("{}: {}", path, error);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(kind: u8, time_ms: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![kind];
+        out.extend((payload.len() as u16).to_le_bytes());
+        out.extend(time_ms.to_le_bytes());
+        out.extend(payload);
+        out
+    }
+
+    #[test]
+    fn header_needs_magic_and_version() {
+        let mut header = b"MCAP\x01\x00\x00\x00".to_vec();
+        header.extend(7u32.to_le_bytes());
+        header.extend(2u32.to_le_bytes());
+        header.extend(9u32.to_le_bytes());
+        let parsed = parse_header(&header).unwrap();
+        assert_eq!((parsed.started_ms, parsed.dropped_chunks, parsed.dropped_bytes), (7, 2, 9));
+
+        assert!(parse_header(&header[..HEADER_LEN - 1]).is_err());
+        let mut bad = header.clone();
+        bad[0] = b'X';
+        assert_eq!(parse_header(&bad).err().unwrap(), "not a capture file (bad magic)");
+        let mut bad = header;
+        bad[4] = 2;
+        assert_eq!(parse_header(&bad).err().unwrap(), "unsupported capture version 2");
+    }
+
+    // Every cut inside the last record keeps the records before it
+    #[test]
+    fn truncated_record_keeps_the_complete_ones() {
+        let first = record(0, 1, b"AT\r\n");
+        let data = [first.clone(), record(1, 2, b"\r\nOK\r\n")].concat();
+        for cut in first.len() + 1..data.len() {
+            let (records, error) = parse_records(&data[..cut]);
+            assert_eq!(records.len(), 1, "cut at {cut}");
+            assert_eq!(records[0].payload, b"AT\r\n");
+            let error = error.unwrap();
+            if cut - first.len() < RECORD_HEADER_LEN {
+                assert_eq!(error, format!("{} trailing bytes", cut - first.len()));
+            } else {
+                assert_eq!(error, "record 1 truncated");
+            }
+        }
+        assert!(parse_records(&data).1.is_none());
+        assert!(parse_records(&[]).0.is_empty());
+    }
+
+    #[test]
+    fn corrupt_records_do_not_panic() {
+        // a length running past the end
+        let mut data = record(1, 0, b"abc");
+        data[1..3].copy_from_slice(&u16::MAX.to_le_bytes());
+        let (records, error) = parse_records(&data);
+        assert!(records.is_empty());
+        assert_eq!(error.as_deref(), Some("record 0 truncated"));
+
+        // an unknown kind is passed on for the listing to flag
+        let data = record(9, 5, b"?");
+        let (records, error) = parse_records(&data);
+        assert!(error.is_none());
+        assert_eq!(records[0].kind, Kind::Unknown(9));
+        assert_eq!(escape(b"a\r\n\\\x00\xff"), "a\\r\\n\\\\\\x00\\xff");
+    }
+}